@@ -0,0 +1,30 @@
+// The Cook-Torrance GGX specular BRDF shared by the PBR effect and the environment filtering
+
+#define PI 3.14159265359
+
+// The GGX normal distribution function
+float distributionGGX(float NdotH, float roughness) {
+  float a = roughness * roughness;
+  float a2 = a * a;
+  float denominator = NdotH * NdotH * (a2 - 1.0) + 1.0;
+
+  return a2 / (PI * denominator * denominator);
+}
+
+// The Smith geometry function with the Schlick-GGX approximation. `k` depends on whether the
+// light is direct or image based.
+float geometrySmith(float NdotV, float NdotL, float k) {
+  float ggxV = NdotV / (NdotV * (1.0 - k) + k);
+  float ggxL = NdotL / (NdotL * (1.0 - k) + k);
+
+  return ggxV * ggxL;
+}
+
+vec3 fresnelSchlick(float cosTheta, vec3 F0) {
+  return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+// Fresnel for image based lighting, where rough surfaces reflect less at grazing angles
+vec3 fresnelSchlickRoughness(float cosTheta, vec3 F0, float roughness) {
+  return F0 + (max(vec3(1.0 - roughness), F0) - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
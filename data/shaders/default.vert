@@ -1,27 +1,87 @@
 #version 460
 #extension GL_ARB_separate_shader_objects : enable
+#ifdef MULTIVIEW
+#extension GL_EXT_multiview : enable
+#endif
 
 layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 normal;
 layout(location = 2) in vec2 texCoord;
+// The second UV channel, used by lightmapped effects
+layout(location = 5) in vec2 texCoord1;
+
+#ifdef SKINNED
+layout(location = 3) in uvec4 joints;
+layout(location = 4) in vec4 weights;
+
+layout(set = 0, binding = 1) readonly buffer JointBuffer {
+  mat4 matrices[];
+} jointBuffer;
+#endif
 
 layout(location = 0) out vec4 fragColor;
 layout(location = 1) out vec2 fragTexCoord;
+// World space position and normal, used by lit effects
+layout(location = 2) out vec3 fragPosition;
+layout(location = 3) out vec3 fragNormal;
+layout(location = 4) out vec2 fragTexCoord1;
 
 /* layout(binding = 0, set = 1) uniform UniformBufferObject { */
 /*   mat4 mvp; */
 /* } ubo; */
 
-struct ObjectData {
-  mat4 mvp;
-};
+// The size of points drawn with a point list topology, e.g; point clouds. See
+// `PipelineInfo::point_size`
+layout(constant_id = 0) const float POINT_SIZE = 1.0;
+
+#include "object.glsl"
 
 layout(std140,set = 1, binding = 0) readonly buffer ObjectBuffer{ 
   ObjectData objects[];
 } objectBuffer;
 
+// The index of the object drawn by each instance
+layout(std430, set = 1, binding = 1) readonly buffer InstanceBuffer {
+  uint objects[];
+} instanceBuffer;
+
+layout(std140, set = 1, binding = 2) uniform CameraData {
+  mat4 viewProjection;
+} camera;
+
+#ifdef MULTIVIEW
+// The view projection of each eye of a stereo view, indexed by the view rendered by the
+// multiview pass. See `stereo::VIEW_SET`
+layout(std140, set = 9, binding = 0) uniform ViewData {
+  mat4 viewProjection[2];
+} views;
+#endif
+
 void main() {
-  gl_Position = objectBuffer.objects[gl_BaseInstance].mvp * vec4(inPosition, 1.0);
+#ifdef SKINNED
+  mat4 skin = weights.x * jointBuffer.matrices[joints.x] +
+              weights.y * jointBuffer.matrices[joints.y] +
+              weights.z * jointBuffer.matrices[joints.z] +
+              weights.w * jointBuffer.matrices[joints.w];
+
+  vec4 position = skin * vec4(inPosition, 1.0);
+  vec3 localNormal = mat3(skin) * normal;
+#else
+  vec4 position = vec4(inPosition, 1.0);
+  vec3 localNormal = normal;
+#endif
+
+  ObjectData object = objectBuffer.objects[instanceBuffer.objects[gl_InstanceIndex]];
+
+#ifdef MULTIVIEW
+  gl_Position = views.viewProjection[gl_ViewIndex] * object.model * position;
+#else
+  gl_Position = camera.viewProjection * object.model * position;
+#endif
+  gl_PointSize = POINT_SIZE;
   fragColor = vec4(0.0, 0.0, 0.0, 1.0);
   fragTexCoord = texCoord;
+  fragTexCoord1 = texCoord1;
+  fragPosition = (object.model * position).xyz;
+  fragNormal = transpose(inverse(mat3(object.model))) * localNormal;
 }
@@ -0,0 +1,19 @@
+// The emitted light of a material, see `src/material/material.rs`
+// EMISSIVE_BINDING selects the first of the two bindings in the material set
+
+layout(binding = EMISSIVE_BINDING) uniform sampler2D emissiveMap;
+
+layout(std140, binding = EMISSIVE_BINDING + 1) uniform Emissive {
+  // The emitted color in xyz. The emissive map is sampled if w is 1.0
+  vec4 factor;
+} emissive;
+
+// Returns the light emitted by the surface, which is added after lighting
+vec3 emission(vec2 texCoord) {
+  vec3 color = emissive.factor.rgb;
+  if (emissive.factor.w > 0.5) {
+    color *= texture(emissiveMap, texCoord).rgb;
+  }
+
+  return color;
+}
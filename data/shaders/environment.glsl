@@ -0,0 +1,28 @@
+// Image based lighting from the environment maps, see `src/environment.rs`
+
+#include "brdf.glsl"
+
+layout(set = 5, binding = 0) uniform samplerCube irradianceMap;
+// GGX prefiltered mip levels from smooth to rough
+layout(set = 5, binding = 1) uniform samplerCube specularMap;
+// The scale and bias to F0 indexed by NdotV and roughness
+layout(set = 5, binding = 2) uniform sampler2D brdfLut;
+
+// Returns the diffuse and specular lighting reflected towards the camera in the direction
+// `view` from the environment
+vec3 environmentLighting(vec3 normal, vec3 view, vec3 albedo, float metallic, float roughness) {
+  float NdotV = max(dot(normal, view), 0.0);
+  vec3 F0 = mix(vec3(0.04), albedo, metallic);
+  vec3 F = fresnelSchlickRoughness(NdotV, F0, roughness);
+
+  // Metals have no diffuse reflection
+  vec3 kD = (1.0 - F) * (1.0 - metallic);
+  vec3 diffuse = texture(irradianceMap, normal).rgb * albedo;
+
+  float maxLod = float(textureQueryLevels(specularMap) - 1);
+  vec3 prefiltered = textureLod(specularMap, reflect(-view, normal), roughness * maxLod).rgb;
+  vec2 brdf = texture(brdfLut, vec2(NdotV, roughness)).rg;
+  vec3 specular = prefiltered * (F * brdf.x + brdf.y);
+
+  return kD * diffuse + specular;
+}
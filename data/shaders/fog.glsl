@@ -0,0 +1,43 @@
+// Distance and height fog of the scene, see `src/fog.rs`
+
+#define FOG_NONE 0
+#define FOG_LINEAR 1
+#define FOG_EXPONENTIAL 2
+
+layout(std140, set = 6, binding = 0) uniform FogData {
+  // The alpha is the opacity of fully fogged surfaces
+  vec4 color;
+  uint mode;
+  // The distances linear fog starts and ends at
+  float start;
+  float end;
+  float density;
+  // The height below which the fog has its full density
+  float height;
+  float heightFalloff;
+} fog;
+
+// Returns the opacity of the fog between the camera and the world space position
+float fogAmount(vec3 camera, vec3 position) {
+  vec3 ray = position - camera;
+  float distance = length(ray);
+  float amount = 0.0;
+
+  if (fog.mode == FOG_LINEAR) {
+    amount = clamp((distance - fog.start) / max(fog.end - fog.start, 0.0001), 0.0, 1.0);
+    amount *= exp(-fog.heightFalloff * max(position.y - fog.height, 0.0));
+  } else if (fog.mode == FOG_EXPONENTIAL) {
+    // Integrates the density, which decays exponentially with height, along the ray
+    float density = fog.density * exp(-fog.heightFalloff * (camera.y - fog.height));
+    float falloff = fog.heightFalloff * ray.y;
+    float integral = abs(falloff) > 0.0001 ? (1.0 - exp(-falloff)) / falloff : 1.0;
+    amount = 1.0 - exp(-density * distance * integral);
+  }
+
+  return clamp(amount, 0.0, 1.0) * fog.color.a;
+}
+
+// Blends the lit color of the surface at the world space position with the fog
+vec3 applyFog(vec3 color, vec3 camera, vec3 position) {
+  return mix(color, fog.color.rgb, fogAmount(camera, position));
+}
@@ -0,0 +1,15 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// The position in normalized device coordinates, e.g; for unprojecting view rays
+layout(location = 0) out vec2 fragNdc;
+
+// Covers the viewport with a single triangle without any vertex buffers. The triangle lies on
+// the far plane, so that it is only visible where nothing else was drawn when the depth test
+// passes for equal depths.
+void main() {
+  vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+
+  fragNdc = uv * 2.0 - 1.0;
+  gl_Position = vec4(fragNdc, 1.0, 1.0);
+}
@@ -0,0 +1,19 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 inPosition;
+// The index of the axis the vertex belongs to
+layout(location = 1) in uint inAxis;
+
+layout(location = 0) flat out vec4 fragColor;
+
+layout(push_constant) uniform Parameters {
+  mat4 mvp;
+  // The color of each axis, with the hovered axis highlighted
+  vec4 colors[3];
+} params;
+
+void main() {
+  gl_Position = params.mvp * vec4(inPosition, 1.0);
+  fragColor = params.colors[inAxis];
+}
@@ -0,0 +1,80 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// An editor style grid on the horizontal ground plane. The view ray through each fragment is
+// intersected with the plane, and the depth of the intersection is written so that the grid is
+// occluded by the scene.
+
+layout(location = 0) in vec2 fragNdc;
+
+layout(location = 0) out vec4 outColor;
+
+layout(push_constant) uniform Parameters {
+  mat4 invViewProjection;
+  vec4 color;
+  vec4 majorColor;
+  float cellSize;
+  // The number of cells between major lines
+  float majorInterval;
+  // The distance from the camera at which the grid has faded out
+  float fadeDistance;
+  float height;
+} params;
+
+const vec4 X_AXIS_COLOR = vec4(0.8, 0.1, 0.1, 1.0);
+const vec4 Z_AXIS_COLOR = vec4(0.1, 0.1, 0.8, 1.0);
+
+// Returns the coverage of the lines at integer coordinates, antialiased over a pixel
+float lines(vec2 coord) {
+  vec2 width = fwidth(coord);
+  vec2 dist = abs(fract(coord - 0.5) - 0.5) / width;
+
+  return 1.0 - min(min(dist.x, dist.y), 1.0);
+}
+
+void main() {
+  vec4 nearPoint = params.invViewProjection * vec4(fragNdc, 0.0, 1.0);
+  vec4 farPoint = params.invViewProjection * vec4(fragNdc, 1.0, 1.0);
+
+  vec3 near = nearPoint.xyz / nearPoint.w;
+  vec3 far = farPoint.xyz / farPoint.w;
+
+  // Also discards rays parallel to the plane
+  float t = (params.height - near.y) / (far.y - near.y);
+  if (!(t >= 0.0 && t <= 1.0)) {
+    discard;
+  }
+
+  vec3 position = mix(near, far, t);
+
+  // The clip position is linear along the ray
+  vec4 clip = mix(vec4(fragNdc, 0.0, 1.0) / nearPoint.w, vec4(fragNdc, 1.0, 1.0) / farPoint.w, t);
+  gl_FragDepth = clip.z / clip.w;
+
+  vec2 coord = position.xz / params.cellSize;
+
+  // Minor lines fade out where they are closer together than a pixel
+  float minor = lines(coord) * (1.0 - clamp(length(fwidth(coord)), 0.0, 1.0));
+  float major = lines(coord / params.majorInterval);
+
+  vec4 color = params.color;
+  color.a *= minor;
+  color = mix(color, params.majorColor, major);
+
+  vec2 axisWidth = fwidth(position.xz);
+  if (abs(position.z) < axisWidth.y) {
+    color = X_AXIS_COLOR;
+  }
+  if (abs(position.x) < axisWidth.x) {
+    color = Z_AXIS_COLOR;
+  }
+
+  float fade = 1.0 - clamp(distance(near, position) / params.fadeDistance, 0.0, 1.0);
+  color.a *= fade * fade;
+
+  if (color.a <= 0.0) {
+    discard;
+  }
+
+  outColor = color;
+}
@@ -0,0 +1,47 @@
+// Helpers for generating the environment maps, see `src/environment.rs`
+
+#include "brdf.glsl"
+
+// Returns the direction through `uv` between 0.0 and 1.0 on `face` of a cube map, matching the
+// face layout of cube map sampling
+vec3 cubeDirection(vec2 uv, uint face) {
+  vec2 st = uv * 2.0 - 1.0;
+
+  switch (face) {
+    case 0: return normalize(vec3(1.0, -st.y, -st.x));
+    case 1: return normalize(vec3(-1.0, -st.y, st.x));
+    case 2: return normalize(vec3(st.x, 1.0, st.y));
+    case 3: return normalize(vec3(st.x, -1.0, -st.y));
+    case 4: return normalize(vec3(st.x, -st.y, 1.0));
+    default: return normalize(vec3(-st.x, -st.y, -1.0));
+  }
+}
+
+// Returns the `i`th of `count` points of the low discrepancy Hammersley sequence
+vec2 hammersley(uint i, uint count) {
+  uint bits = i;
+  bits = (bits << 16u) | (bits >> 16u);
+  bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+  bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+  bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+  bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+
+  return vec2(float(i) / float(count), float(bits) * 2.3283064365386963e-10);
+}
+
+// Returns a halfway vector around `normal` distributed by the GGX distribution of `roughness`
+vec3 importanceSampleGGX(vec2 xi, vec3 normal, float roughness) {
+  float a = roughness * roughness;
+
+  float phi = 2.0 * PI * xi.x;
+  float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+  float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+
+  vec3 halfway = vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+
+  vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+  vec3 tangent = normalize(cross(up, normal));
+  vec3 bitangent = cross(normal, tangent);
+
+  return normalize(tangent * halfway.x + bitangent * halfway.y + normal * halfway.z);
+}
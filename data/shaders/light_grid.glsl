@@ -0,0 +1,35 @@
+// The light grid shared by the culling and lighting shaders, see `src/light_culling.rs`
+// LIGHT_GRID_SET selects the set the grid is bound to
+
+#define TILE_SIZE 16
+#define MAX_LIGHTS_PER_TILE 128
+
+struct Light {
+  // The position in xyz and range in w
+  vec4 positionRange;
+  // The color multiplied by the intensity in xyz
+  vec4 color;
+};
+
+layout(std140, set = LIGHT_GRID_SET, binding = 0) uniform LightGrid {
+  mat4 view;
+  mat4 inverseProjection;
+  // The top left corner of the viewport in pixels
+  vec2 viewportOffset;
+  vec2 viewportSize;
+  uvec2 tileCount;
+  uint lightCount;
+} lightGrid;
+
+layout(std430, set = LIGHT_GRID_SET, binding = 1) readonly buffer Lights {
+  Light lights[];
+};
+
+// MAX_LIGHTS_PER_TILE light indices for each tile
+layout(std430, set = LIGHT_GRID_SET, binding = 2) LIGHT_GRID_ACCESS buffer TileLights {
+  uint tileLights[];
+};
+
+layout(std430, set = LIGHT_GRID_SET, binding = 3) LIGHT_GRID_ACCESS buffer TileCounts {
+  uint tileCounts[];
+};
@@ -0,0 +1,35 @@
+// Lighting from the lights culled into the tile of the fragment, see `src/light_culling.rs`
+
+#define LIGHT_GRID_SET 4
+#define LIGHT_GRID_ACCESS readonly
+
+#include "light_grid.glsl"
+
+// Returns the colored diffuse lighting at the world space position from the lights affecting
+// the tile of the fragment
+vec3 tiledLighting(vec3 position, vec3 normal) {
+  uvec2 tile = uvec2(max(gl_FragCoord.xy - lightGrid.viewportOffset, vec2(0.0))) / TILE_SIZE;
+  tile = min(tile, lightGrid.tileCount - 1);
+  uint index = tile.y * lightGrid.tileCount.x + tile.x;
+
+  vec3 lighting = vec3(0.0);
+
+  for (uint i = 0; i < tileCounts[index]; i++) {
+    Light light = lights[tileLights[index * MAX_LIGHTS_PER_TILE + i]];
+    vec3 toLight = light.positionRange.xyz - position;
+    float distance = length(toLight);
+
+    // Falls off smoothly to zero at the range
+    float attenuation = clamp(1.0 - distance / light.positionRange.w, 0.0, 1.0);
+    float diffuse = max(dot(normal, toLight / max(distance, 0.0001)), 0.0);
+
+    lighting += light.color.rgb * diffuse * attenuation * attenuation;
+  }
+
+  return lighting;
+}
+
+// Returns the world space position of the camera of the view
+vec3 cameraPosition() {
+  return -transpose(mat3(lightGrid.view)) * lightGrid.view[3].xyz;
+}
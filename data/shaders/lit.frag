@@ -0,0 +1,61 @@
+#version 460
+#extension GL_ARB_separate_shader_objects : enable
+#ifdef RAY_QUERY
+#extension GL_EXT_ray_query : require
+#endif
+
+layout(location = 0) in vec4 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragPosition;
+layout(location = 3) in vec3 fragNormal;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 0) uniform sampler2D texSampler;
+
+#define EMISSIVE_BINDING 1
+#include "emissive.glsl"
+
+#ifdef LIGHTMAP
+layout(location = 4) in vec2 fragTexCoord1;
+
+// Baked indirect lighting of static geometry, replacing the constant ambient term
+layout(binding = 3) uniform sampler2D lightmap;
+#endif
+
+#include "shadow.glsl"
+#include "point_shadow.glsl"
+#include "lights.glsl"
+#include "fog.glsl"
+
+#ifdef RAY_QUERY
+// Traced shadows and occlusion replacing the shadow map of the sun
+#include "ray_query.glsl"
+#endif
+
+const float AMBIENT = 0.2;
+
+void main() {
+  vec3 normal = normalize(fragNormal);
+  float diffuse = max(dot(normal, -shadowData.direction.xyz), 0.0);
+#ifdef RAY_QUERY
+  float shadow = rayTracedShadow(fragPosition, normal, -shadowData.direction.xyz);
+#else
+  float shadow = cascadeShadow(fragPosition, normal);
+#endif
+#ifdef LIGHTMAP
+  vec3 ambient = texture(lightmap, fragTexCoord1).rgb;
+#else
+  vec3 ambient = vec3(AMBIENT);
+#endif
+#ifdef RAY_QUERY
+  ambient *= rayTracedOcclusion(fragPosition, normal);
+#endif
+
+  vec3 lighting = ambient + vec3(diffuse * shadow + pointLighting(fragPosition, normal)) +
+                  tiledLighting(fragPosition, normal);
+
+  vec4 albedo = texture(texSampler, fragTexCoord);
+  vec3 color = albedo.rgb * lighting + emission(fragTexCoord);
+  outColor = vec4(applyFog(color, cameraPosition(), fragPosition), albedo.a);
+}
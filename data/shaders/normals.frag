@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in float fragAlong;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 0) uniform sampler2D texSampler;
+
+const vec3 TIP_COLOR = vec3(1.0, 1.0, 0.0);
+
+// Fades from the albedo at the vertex to yellow at the tip of the normal
+void main() {
+  outColor = vec4(mix(texture(texSampler, fragTexCoord).rgb, TIP_COLOR, fragAlong), 1.0);
+}
@@ -0,0 +1,42 @@
+#version 460
+#extension GL_ARB_separate_shader_objects : enable
+
+// Projects each vertex along with the tip of its normal, which the geometry shader connects
+// with a line, see `data/effects/normals.toml`
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 texCoord;
+
+// The clip space position of the tip of the normal
+layout(location = 0) out vec4 geomTip;
+layout(location = 1) out vec2 geomTexCoord;
+
+#ifndef NORMAL_LENGTH
+// The length of the lines in object space
+#define NORMAL_LENGTH 1.0
+#endif
+
+#include "object.glsl"
+
+layout(std140,set = 1, binding = 0) readonly buffer ObjectBuffer{
+  ObjectData objects[];
+} objectBuffer;
+
+// The index of the object drawn by each instance
+layout(std430, set = 1, binding = 1) readonly buffer InstanceBuffer {
+  uint objects[];
+} instanceBuffer;
+
+layout(std140, set = 1, binding = 2) uniform CameraData {
+  mat4 viewProjection;
+} camera;
+
+void main() {
+  ObjectData object = objectBuffer.objects[instanceBuffer.objects[gl_InstanceIndex]];
+  mat4 mvp = camera.viewProjection * object.model;
+
+  gl_Position = mvp * vec4(inPosition, 1.0);
+  geomTip = mvp * vec4(inPosition + normalize(normal) * NORMAL_LENGTH, 1.0);
+  geomTexCoord = texCoord;
+}
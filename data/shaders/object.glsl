@@ -0,0 +1,9 @@
+// Per object data shared by all effects
+
+// Indexed by the object. Effects read the object of each instance from the instance buffer and
+// the view projection from the camera data, which keeps the objects unchanged while the camera
+// moves.
+struct ObjectData {
+  // Transforms from object to world space
+  mat4 model;
+};
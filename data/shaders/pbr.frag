@@ -0,0 +1,62 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec4 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragPosition;
+layout(location = 3) in vec3 fragNormal;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 0) uniform sampler2D texSampler;
+
+layout(std140, binding = 1) uniform PbrMaterial {
+  float metallic;
+  float roughness;
+} material;
+
+#define EMISSIVE_BINDING 2
+#include "emissive.glsl"
+
+#include "shadow.glsl"
+#include "point_shadow.glsl"
+#include "lights.glsl"
+#include "environment.glsl"
+#include "fog.glsl"
+
+// Matches the brightness of the directional light of the lit effect
+const float SUN_RADIANCE = PI;
+
+void main() {
+  vec4 albedo = texture(texSampler, fragTexCoord);
+  float metallic = clamp(material.metallic, 0.0, 1.0);
+  float roughness = clamp(material.roughness, 0.04, 1.0);
+
+  vec3 normal = normalize(fragNormal);
+  vec3 view = normalize(cameraPosition() - fragPosition);
+  vec3 light = -shadowData.direction.xyz;
+  vec3 halfway = normalize(view + light);
+
+  float NdotV = max(dot(normal, view), 0.0001);
+  float NdotL = max(dot(normal, light), 0.0);
+  float NdotH = max(dot(normal, halfway), 0.0);
+
+  vec3 F0 = mix(vec3(0.04), albedo.rgb, metallic);
+  vec3 F = fresnelSchlick(max(dot(halfway, view), 0.0), F0);
+  float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+  vec3 specular = distributionGGX(NdotH, roughness) * geometrySmith(NdotV, NdotL, k) * F /
+                  (4.0 * NdotV * max(NdotL, 0.0001));
+
+  vec3 kD = (1.0 - F) * (1.0 - metallic);
+  vec3 sun = (kD * albedo.rgb / PI + specular) * SUN_RADIANCE * NdotL *
+             cascadeShadow(fragPosition, normal);
+
+  // Point lights only contribute diffuse lighting
+  vec3 points = albedo.rgb * (1.0 - metallic) *
+                (vec3(pointLighting(fragPosition, normal)) + tiledLighting(fragPosition, normal));
+
+  vec3 ambient = environmentLighting(normal, view, albedo.rgb, metallic, roughness);
+
+  vec3 color = sun + points + ambient + emission(fragTexCoord);
+  outColor = vec4(applyFog(color, cameraPosition(), fragPosition), albedo.a);
+}
@@ -0,0 +1,22 @@
+#version 460
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 inPosition;
+
+layout(location = 0) flat out uint objectId;
+
+#include "object.glsl"
+
+layout(std140,set = 0, binding = 0) readonly buffer ObjectBuffer{
+  ObjectData objects[];
+} objectBuffer;
+
+layout(push_constant) uniform CameraData {
+  mat4 viewProjection;
+} camera;
+
+void main() {
+  gl_Position = camera.viewProjection * objectBuffer.objects[gl_BaseInstance].model * vec4(inPosition, 1.0);
+  // Zero is reserved for no object
+  objectId = gl_BaseInstance + 1;
+}
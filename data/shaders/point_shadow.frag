@@ -0,0 +1,26 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec3 fragPosition;
+
+// Declared to keep the material set compatible with the other passes of the effect
+layout(binding = 0) uniform sampler2D texSampler;
+
+// Bound in place of the shadow set when drawing the faces of a point light
+layout(set = 2, binding = 0) uniform PointLight {
+  // The position in xyz and range in w
+  vec4 light;
+} pointLight;
+
+void main() {
+  // Cutout textures do not cast shadows through their transparent parts
+  if (texture(texSampler, fragTexCoord).a < 0.5) {
+    discard;
+  }
+
+  // The distance is stored rather than the projected depth, which makes the cube map
+  // independent of the face it is sampled from
+  vec4 light = pointLight.light;
+  gl_FragDepth = length(fragPosition - light.xyz) / light.w;
+}
@@ -0,0 +1,71 @@
+// Point lights and sampling of their cube shadow maps, see `src/point_shadow.rs`
+
+#define MAX_POINT_LIGHTS 4
+
+layout(std140, set = 3, binding = 0) uniform PointLightData {
+  // The position in xyz and range in w of each light
+  vec4 lights[MAX_POINT_LIGHTS];
+  // Non zero if the light at the same index casts shadows
+  uvec4 castShadows;
+  uint count;
+} pointLights;
+
+// Lights without a cube map are bound to a placeholder
+layout(set = 3, binding = 1) uniform samplerCubeShadow pointShadowMap0;
+layout(set = 3, binding = 2) uniform samplerCubeShadow pointShadowMap1;
+layout(set = 3, binding = 3) uniform samplerCubeShadow pointShadowMap2;
+layout(set = 3, binding = 4) uniform samplerCubeShadow pointShadowMap3;
+
+// Compares `depth` against the cube map of `light` in `direction`
+float samplePointShadowMap(int light, vec3 direction, float depth) {
+  // The faces are rendered with the z axis mirrored
+  vec4 coords = vec4(direction.xy, -direction.z, depth);
+
+  switch (light) {
+    case 0: return texture(pointShadowMap0, coords);
+    case 1: return texture(pointShadowMap1, coords);
+    case 2: return texture(pointShadowMap2, coords);
+    default: return texture(pointShadowMap3, coords);
+  }
+}
+
+// Returns how lit the world space position is by `light` between 0.0 and 1.0
+float pointShadow(int light, vec3 position, vec3 normal) {
+  if (pointLights.castShadows[light] == 0) {
+    return 1.0;
+  }
+
+  vec4 data = pointLights.lights[light];
+  vec3 direction = position - data.xyz;
+  float distance = length(direction);
+
+  // Beyond the range of the light
+  if (distance > data.w) {
+    return 1.0;
+  }
+
+  // Surfaces facing away from the light need a larger bias to avoid acne
+  float cosTheta = clamp(dot(normal, -direction / distance), 0.0, 1.0);
+  float bias = mix(0.15, 0.03, cosTheta);
+
+  return samplePointShadowMap(light, direction, (distance - bias) / data.w);
+}
+
+// Returns the diffuse lighting of all point lights at the world space position
+float pointLighting(vec3 position, vec3 normal) {
+  float lighting = 0.0;
+
+  for (int i = 0; i < int(pointLights.count); i++) {
+    vec4 light = pointLights.lights[i];
+    vec3 toLight = light.xyz - position;
+    float distance = length(toLight);
+
+    // Falls off smoothly to zero at the range
+    float attenuation = clamp(1.0 - distance / light.w, 0.0, 1.0);
+    float diffuse = max(dot(normal, toLight / distance), 0.0);
+
+    lighting += diffuse * attenuation * attenuation * pointShadow(i, position, normal);
+  }
+
+  return lighting;
+}
@@ -0,0 +1,53 @@
+#version 460
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 texCoord;
+
+#ifdef SKINNED
+layout(location = 3) in uvec4 joints;
+layout(location = 4) in vec4 weights;
+
+layout(set = 0, binding = 1) readonly buffer JointBuffer {
+  mat4 matrices[];
+} jointBuffer;
+#endif
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec3 fragPosition;
+
+#include "object.glsl"
+
+layout(std140,set = 1, binding = 0) readonly buffer ObjectBuffer{
+  ObjectData objects[];
+} objectBuffer;
+
+// The index of the object drawn by each instance
+layout(std430, set = 1, binding = 1) readonly buffer InstanceBuffer {
+  uint objects[];
+} instanceBuffer;
+
+// The view projection of the cube face being drawn
+layout(std140, set = 1, binding = 2) uniform CameraData {
+  mat4 viewProjection;
+} camera;
+
+void main() {
+#ifdef SKINNED
+  mat4 skin = weights.x * jointBuffer.matrices[joints.x] +
+              weights.y * jointBuffer.matrices[joints.y] +
+              weights.z * jointBuffer.matrices[joints.z] +
+              weights.w * jointBuffer.matrices[joints.w];
+
+  vec4 position = skin * vec4(inPosition, 1.0);
+#else
+  vec4 position = vec4(inPosition, 1.0);
+#endif
+
+  ObjectData object = objectBuffer.objects[instanceBuffer.objects[gl_InstanceIndex]];
+
+  gl_Position = camera.viewProjection * object.model * position;
+  fragPosition = (object.model * position).xyz;
+  fragTexCoord = texCoord;
+}
@@ -0,0 +1,65 @@
+// Ray traced shadows and ambient occlusion against the objects of the scene, see
+// `src/ray_query.rs`. The including shader needs to enable `GL_EXT_ray_query`.
+
+layout(set = 8, binding = 0) uniform accelerationStructureEXT sceneStructure;
+
+// Offsets ray origins along the normal to avoid hitting the surface itself
+#define RAY_BIAS 0.01
+// The distance at which the sun is occluded
+#define SHADOW_DISTANCE 1000.0
+
+#define OCCLUSION_SAMPLES 8
+// The distance at which geometry occludes the ambient light
+#define OCCLUSION_RADIUS 1.0
+
+const float GOLDEN_ANGLE = 2.39996323;
+
+// Returns true if nothing is hit from origin along direction within maxDistance
+bool traceVisibility(vec3 origin, vec3 direction, float maxDistance) {
+  rayQueryEXT query;
+  rayQueryInitializeEXT(query, sceneStructure,
+                        gl_RayFlagsTerminateOnFirstHitEXT | gl_RayFlagsOpaqueEXT, 0xFF,
+                        origin, 0.0, direction, maxDistance);
+
+  while (rayQueryProceedEXT(query)) {
+  }
+
+  return rayQueryGetIntersectionTypeEXT(query, true) ==
+         gl_RayQueryCommittedIntersectionNoneEXT;
+}
+
+// Returns 1.0 if the world space position is lit by a light in direction, otherwise 0.0
+float rayTracedShadow(vec3 position, vec3 normal, vec3 direction) {
+  if (dot(normal, direction) <= 0.0) {
+    return 0.0;
+  }
+
+  return traceVisibility(position + normal * RAY_BIAS, direction, SHADOW_DISTANCE) ? 1.0 : 0.0;
+}
+
+// Returns the fraction of the hemisphere around normal which is not occluded within
+// OCCLUSION_RADIUS of the world space position
+float rayTracedOcclusion(vec3 position, vec3 normal) {
+  vec3 tangent = normalize(cross(normal, abs(normal.y) < 0.99 ? vec3(0, 1, 0) : vec3(1, 0, 0)));
+  vec3 bitangent = cross(normal, tangent);
+
+  // Rotates the samples per fragment to trade banding for noise
+  float rotation =
+      fract(sin(dot(position, vec3(12.9898, 78.233, 37.719))) * 43758.5453) * 6.2831853;
+
+  vec3 origin = position + normal * RAY_BIAS;
+  float visible = 0.0;
+
+  // Cosine weighted directions on a spiral over the hemisphere
+  for (int i = 0; i < OCCLUSION_SAMPLES; i++) {
+    float r = sqrt((float(i) + 0.5) / float(OCCLUSION_SAMPLES));
+    float phi = float(i) * GOLDEN_ANGLE + rotation;
+
+    vec3 direction = normalize(tangent * (r * cos(phi)) + bitangent * (r * sin(phi)) +
+                               normal * sqrt(max(1.0 - r * r, 0.0)));
+
+    visible += traceVisibility(origin, direction, OCCLUSION_RADIUS) ? 1.0 : 0.0;
+  }
+
+  return visible / float(OCCLUSION_SAMPLES);
+}
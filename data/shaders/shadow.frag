@@ -0,0 +1,14 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 fragTexCoord;
+
+// Declared to keep the material set compatible with the other passes of the effect
+layout(binding = 0) uniform sampler2D texSampler;
+
+void main() {
+  // Cutout textures do not cast shadows through their transparent parts
+  if (texture(texSampler, fragTexCoord).a < 0.5) {
+    discard;
+  }
+}
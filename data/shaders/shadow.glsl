@@ -0,0 +1,60 @@
+// Sampling of cascaded shadow maps, see `src/shadow.rs`
+
+#define MAX_CASCADES 4
+
+layout(set = 2, binding = 0) uniform sampler2DArrayShadow shadowMap;
+
+layout(std140, set = 2, binding = 1) uniform ShadowData {
+  mat4 viewProjections[MAX_CASCADES];
+  // The view distance each cascade ends at
+  vec4 splits;
+  // The view matrix of the camera the cascades are fit to
+  mat4 view;
+  // The light direction in xyz and the number of cascades in w
+  vec4 direction;
+} shadowData;
+
+// Returns the first cascade covering the world space position
+int selectCascade(vec3 position) {
+  float depth = -(shadowData.view * vec4(position, 1.0)).z;
+  int count = int(shadowData.direction.w);
+
+  for (int i = 0; i < count - 1; i++) {
+    if (depth < shadowData.splits[i]) {
+      return i;
+    }
+  }
+
+  return count - 1;
+}
+
+// Returns how lit the world space position is between 0.0 and 1.0
+float cascadeShadow(vec3 position, vec3 normal) {
+  int cascade = selectCascade(position);
+
+  vec4 lightSpace = shadowData.viewProjections[cascade] * vec4(position, 1.0);
+  vec3 coords = lightSpace.xyz / lightSpace.w;
+  coords.xy = coords.xy * 0.5 + 0.5;
+
+  // Beyond the last cascade
+  if (coords.z > 1.0) {
+    return 1.0;
+  }
+
+  // Surfaces facing away from the light need a larger bias to avoid acne
+  float cosTheta = clamp(dot(normal, -shadowData.direction.xyz), 0.0, 1.0);
+  float bias = clamp(0.005 * tan(acos(cosTheta)), 0.0005, 0.01);
+
+  vec2 texelSize = 1.0 / vec2(textureSize(shadowMap, 0).xy);
+  float lit = 0.0;
+
+  // Percentage closer filtering over a 3x3 kernel
+  for (int x = -1; x <= 1; x++) {
+    for (int y = -1; y <= 1; y++) {
+      vec2 offset = vec2(x, y) * texelSize;
+      lit += texture(shadowMap, vec4(coords.xy + offset, cascade, coords.z - bias));
+    }
+  }
+
+  return lit / 9.0;
+}
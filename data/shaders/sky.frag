@@ -0,0 +1,53 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// A gradient sky with a sun disc. Drawn on the far plane, so only the fragments not covered by
+// the scene remain.
+
+layout(location = 0) in vec2 fragNdc;
+
+layout(location = 0) out vec4 outColor;
+
+layout(push_constant) uniform Parameters {
+  // The inverse of the view projection without any translation
+  mat4 invViewProjection;
+  vec4 zenithColor;
+  vec4 horizonColor;
+  vec4 groundColor;
+  // The direction towards the sun, and the cosine of the angular radius of the disc in w
+  vec4 sun;
+} params;
+
+const vec3 SUN_COLOR = vec3(1.0, 0.95, 0.85) * 4.0;
+const vec3 GLOW_COLOR = vec3(1.0, 0.8, 0.6);
+
+void main() {
+  vec4 farPoint = params.invViewProjection * vec4(fragNdc, 1.0, 1.0);
+  vec3 dir = normalize(farPoint.xyz / farPoint.w);
+
+  vec3 sunDir = params.sun.xyz;
+
+  vec3 color;
+  if (dir.y >= 0.0) {
+    color = mix(params.horizonColor.rgb, params.zenithColor.rgb, pow(dir.y, 0.5));
+  } else {
+    color = mix(params.horizonColor.rgb, params.groundColor.rgb, clamp(-dir.y * 8.0, 0.0, 1.0));
+  }
+
+  // Darkens the sky as the sun sets, keeping a faint glow after sunset
+  float daylight = clamp(sunDir.y * 4.0 + 0.2, 0.05, 1.0);
+  color *= daylight;
+
+  float cosAngle = dot(dir, sunDir);
+
+  // A wide glow around the sun, strongest when the sun is close to the horizon
+  float glow = pow(max(cosAngle, 0.0), 8.0) * (1.0 - abs(sunDir.y) * 0.5);
+  color += GLOW_COLOR * glow * 0.5 * daylight;
+
+  // The disc is antialiased over a pixel and hidden below the horizon
+  float width = fwidth(cosAngle);
+  float disc = smoothstep(params.sun.w - width, params.sun.w + width, cosAngle);
+  color += SUN_COLOR * disc * step(0.0, dir.y);
+
+  outColor = vec4(color, 1.0);
+}
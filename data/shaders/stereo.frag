@@ -0,0 +1,24 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 fragNdc;
+
+layout(location = 0) out vec4 outColor;
+
+// The left eye in the first layer and the right eye in the second
+layout(set = 0, binding = 0) uniform sampler2DArray eyes;
+
+layout(push_constant) uniform Parameters {
+  // Presents the right eye on the left half, e.g; for cross-eyed viewing
+  uint swapEyes;
+} parameters;
+
+// Presents the eyes side by side, each squeezed into half of the viewport
+void main() {
+  vec2 uv = fragNdc * 0.5 + 0.5;
+
+  uint eye = uv.x < 0.5 ? 0 : 1;
+  eye ^= parameters.swapEyes;
+
+  outColor = texture(eyes, vec3(fract(uv.x * 2.0), uv.y, float(eye)));
+}
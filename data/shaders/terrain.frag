@@ -0,0 +1,48 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Blends four tiled layers by the weights of a splat map, see `src/terrain.rs`
+
+layout(location = 0) in vec4 fragColor;
+// Spans the whole terrain and samples the splat map
+layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragPosition;
+layout(location = 3) in vec3 fragNormal;
+// Repeats the layers across the terrain
+layout(location = 4) in vec2 fragTexCoord1;
+
+layout(location = 0) out vec4 outColor;
+
+// The first layer, bound as the albedo of the material
+layout(binding = 0) uniform sampler2D texSampler;
+// The weight of each layer in rgba
+layout(binding = 1) uniform sampler2D splatMap;
+layout(binding = 2) uniform sampler2D layer1;
+layout(binding = 3) uniform sampler2D layer2;
+layout(binding = 4) uniform sampler2D layer3;
+
+#include "shadow.glsl"
+#include "point_shadow.glsl"
+#include "lights.glsl"
+#include "fog.glsl"
+
+const float AMBIENT = 0.2;
+
+void main() {
+  vec4 weights = texture(splatMap, fragTexCoord);
+  weights /= max(dot(weights, vec4(1.0)), 0.0001);
+
+  vec3 albedo = texture(texSampler, fragTexCoord1).rgb * weights.r +
+                texture(layer1, fragTexCoord1).rgb * weights.g +
+                texture(layer2, fragTexCoord1).rgb * weights.b +
+                texture(layer3, fragTexCoord1).rgb * weights.a;
+
+  vec3 normal = normalize(fragNormal);
+  float diffuse = max(dot(normal, -shadowData.direction.xyz), 0.0);
+  float shadow = cascadeShadow(fragPosition, normal);
+
+  vec3 lighting = vec3(AMBIENT) + vec3(diffuse * shadow + pointLighting(fragPosition, normal)) +
+                  tiledLighting(fragPosition, normal);
+
+  outColor = vec4(applyFog(albedo * lighting, cameraPosition(), fragPosition), 1.0);
+}
@@ -0,0 +1,80 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// A water surface perturbed by two scrolling normal maps, blending from the water color to the
+// reflection with a Fresnel term, see `src/water.rs`
+
+layout(location = 0) in vec4 fragColor;
+// In world units along x and z
+layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragPosition;
+layout(location = 3) in vec3 fragNormal;
+layout(location = 5) in vec4 fragClipPosition;
+
+layout(location = 0) out vec4 outColor;
+
+// The planar reflection, drawn upside down from the camera mirrored below the surface. A flat
+// sky color without a reflection.
+layout(binding = 0) uniform sampler2D texSampler;
+layout(binding = 1) uniform sampler2D normalMap;
+
+layout(std140, binding = 2) uniform WaterData {
+  // The color of the water in rgb and the opacity of it when seen from above in a
+  vec4 color;
+  // The velocity in world units per second of both normal map layers
+  vec4 scroll;
+  // Seconds since the first frame
+  float time;
+  // Normal map repeats per world unit
+  float tiling;
+  float normalStrength;
+  float fresnelPower;
+  float reflectivity;
+  // How far the normals offset the reflection in screen space
+  float distortion;
+  float shininess;
+} water;
+
+#include "shadow.glsl"
+#include "point_shadow.glsl"
+#include "lights.glsl"
+#include "fog.glsl"
+
+const float AMBIENT = 0.2;
+
+// Returns the normal map sample at `uv` in world space, with the surface facing up
+vec3 sampleNormal(vec2 uv) {
+  vec3 normal = texture(normalMap, uv).rgb * 2.0 - 1.0;
+  return vec3(normal.x * water.normalStrength, normal.z, normal.y * water.normalStrength);
+}
+
+void main() {
+  vec2 uv = fragTexCoord * water.tiling;
+
+  // The layers scroll in different directions at slightly different scales, which hides the
+  // repetition of the normal map
+  vec3 normal = normalize(sampleNormal(uv + water.scroll.xy * water.time * water.tiling) +
+                          sampleNormal(uv * 0.7 + water.scroll.zw * water.time * water.tiling));
+
+  vec3 view = normalize(cameraPosition() - fragPosition);
+  float fresnel = pow(1.0 - max(dot(view, normal), 0.0), water.fresnelPower);
+  float reflectance = mix(0.02, 1.0, fresnel) * water.reflectivity;
+
+  // The reflection is mirrored vertically in screen space
+  vec2 ndc = fragClipPosition.xy / fragClipPosition.w;
+  vec2 reflectionCoord = vec2(ndc.x, -ndc.y) * 0.5 + 0.5 + normal.xz * water.distortion;
+  vec3 reflection = texture(texSampler, clamp(reflectionCoord, 0.001, 0.999)).rgb;
+
+  vec3 toSun = -shadowData.direction.xyz;
+  float shadow = cascadeShadow(fragPosition, normal);
+  float diffuse = max(dot(normal, toSun), 0.0);
+  float specular = pow(max(dot(normal, normalize(toSun + view)), 0.0), water.shininess);
+
+  vec3 lighting = vec3(AMBIENT) + vec3(diffuse * shadow + pointLighting(fragPosition, normal)) +
+                  tiledLighting(fragPosition, normal);
+
+  vec3 color = mix(water.color.rgb * lighting, reflection, reflectance) + specular * shadow;
+  float alpha = mix(water.color.a, 1.0, reflectance);
+
+  outColor = vec4(applyFog(color, cameraPosition(), fragPosition), alpha);
+}
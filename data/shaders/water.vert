@@ -0,0 +1,43 @@
+#version 460
+#extension GL_ARB_separate_shader_objects : enable
+
+// The default vertex shader passing on the clip space position, which the water samples its
+// planar reflection with, see `src/water.rs`
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 texCoord;
+
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 fragTexCoord;
+layout(location = 2) out vec3 fragPosition;
+layout(location = 3) out vec3 fragNormal;
+layout(location = 5) out vec4 fragClipPosition;
+
+#include "object.glsl"
+
+layout(std140,set = 1, binding = 0) readonly buffer ObjectBuffer{
+  ObjectData objects[];
+} objectBuffer;
+
+// The index of the object drawn by each instance
+layout(std430, set = 1, binding = 1) readonly buffer InstanceBuffer {
+  uint objects[];
+} instanceBuffer;
+
+layout(std140, set = 1, binding = 2) uniform CameraData {
+  mat4 viewProjection;
+} camera;
+
+void main() {
+  vec4 position = vec4(inPosition, 1.0);
+
+  ObjectData object = objectBuffer.objects[instanceBuffer.objects[gl_InstanceIndex]];
+
+  gl_Position = camera.viewProjection * object.model * position;
+  fragColor = vec4(0.0, 0.0, 0.0, 1.0);
+  fragTexCoord = texCoord;
+  fragPosition = (object.model * position).xyz;
+  fragNormal = transpose(inverse(mat3(object.model))) * normal;
+  fragClipPosition = gl_Position;
+}
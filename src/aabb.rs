@@ -0,0 +1,133 @@
+use ultraviolet::{Mat4, Vec3};
+
+use crate::raycast::Ray;
+
+/// An axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Returns the smallest box containing all points, or None if there are no points.
+    pub fn from_points<I>(points: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = Vec3>,
+    {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+
+        Some(points.fold(Self::new(first, first), |acc, point| {
+            Self::new(
+                acc.min.min_by_component(point),
+                acc.max.max_by_component(point),
+            )
+        }))
+    }
+
+    /// Returns the smallest box containing both boxes
+    pub fn union(&self, other: &Self) -> Self {
+        Self::new(
+            self.min.min_by_component(other.min),
+            self.max.max_by_component(other.max),
+        )
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Returns the half size along each axis
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Returns the eight corners of the box
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vec3::new(min.x, min.y, min.z),
+            Vec3::new(max.x, min.y, min.z),
+            Vec3::new(min.x, max.y, min.z),
+            Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z),
+            Vec3::new(max.x, min.y, max.z),
+            Vec3::new(min.x, max.y, max.z),
+            Vec3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    /// Returns the axis aligned box containing this box transformed by `matrix`.
+    pub fn transformed(&self, matrix: &Mat4) -> Self {
+        // Each axis of the matrix contributes the extents projected onto it
+        let center = matrix.transform_point3(self.center());
+        let extents = self.extents();
+
+        let half = (0..3).fold(Vec3::zero(), |acc, axis| {
+            let column = matrix.cols[axis].truncated();
+            acc + Vec3::new(column.x.abs(), column.y.abs(), column.z.abs()) * extents[axis]
+        });
+
+        Self::new(center - half, center + half)
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.x >= self.min.x
+            && point.y >= self.min.y
+            && point.z >= self.min.z
+            && point.x <= self.max.x
+            && point.y <= self.max.y
+            && point.z <= self.max.z
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.x <= other.max.x
+            && self.min.y <= other.max.y
+            && self.min.z <= other.max.z
+            && self.max.x >= other.min.x
+            && self.max.y >= other.min.y
+            && self.max.z >= other.min.z
+    }
+
+    /// Returns the distance along `ray` to where it enters the box along with the normal of the
+    /// entered face. Rays starting inside the box hit it at their origin with the normal
+    /// opposing the ray.
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<(f32, Vec3)> {
+        let mut near = f32::NEG_INFINITY;
+        let mut far = f32::INFINITY;
+        let mut normal = -ray.direction;
+
+        for axis in 0..3 {
+            let inv_direction = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_direction;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_direction;
+
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            if t0 > near {
+                near = t0;
+                normal = Vec3::zero();
+                normal[axis] = -ray.direction[axis].signum();
+            }
+
+            far = far.min(t1);
+        }
+
+        if near > far || far < 0.0 {
+            return None;
+        }
+
+        if near < 0.0 {
+            return Some((0.0, -ray.direction));
+        }
+
+        Some((near, normal))
+    }
+}
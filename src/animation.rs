@@ -0,0 +1,266 @@
+//! Keyframed node animations imported from glTF documents and a player applying them to the
+//! scene objects instantiated from the document.
+use ultraviolet::{Rotor3, Vec4};
+
+use crate::document::{Document, Transform};
+use crate::Scene;
+
+/// How values are interpolated between keyframes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// The value of the previous keyframe is held until the next keyframe
+    Step,
+    Linear,
+    /// Hermite spline with an in and out tangent for each keyframe
+    CubicSpline,
+}
+
+impl From<gltf::animation::Interpolation> for Interpolation {
+    fn from(interpolation: gltf::animation::Interpolation) -> Self {
+        match interpolation {
+            gltf::animation::Interpolation::Step => Interpolation::Step,
+            gltf::animation::Interpolation::Linear => Interpolation::Linear,
+            gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+        }
+    }
+}
+
+/// The node transform component animated by a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Property {
+    Translation,
+    /// Stored as XYZW quaternions
+    Rotation,
+    Scale,
+}
+
+/// Keyframes of a single transform component of a node
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    /// The index of the animated node in the document
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds in increasing order
+    pub times: Vec<f32>,
+    /// One value per keyframe, or an in tangent, value and out tangent per keyframe for cubic
+    /// splines. Translations and scales leave w unused.
+    pub values: Vec<Vec4>,
+}
+
+impl Channel {
+    /// Returns the interpolated value at `time`. Times outside the keyframes are clamped.
+    pub fn sample(&self, time: f32) -> Vec4 {
+        let value = |i: usize| match self.interpolation {
+            Interpolation::CubicSpline => self.values[i * 3 + 1],
+            _ => self.values[i],
+        };
+
+        let last = self.times.len() - 1;
+
+        let next = match self.times.iter().position(|t| *t > time) {
+            Some(0) => return value(0),
+            Some(next) => next,
+            None => return value(last),
+        };
+
+        let prev = next - 1;
+        let dt = self.times[next] - self.times[prev];
+        let t = (time - self.times[prev]) / dt;
+
+        match self.interpolation {
+            Interpolation::Step => value(prev),
+            Interpolation::Linear => {
+                let (a, mut b) = (value(prev), value(next));
+
+                // Interpolate along the shortest arc of the quaternion double cover
+                if self.property == Property::Rotation && a.dot(b) < 0.0 {
+                    b = -b;
+                }
+
+                a + (b - a) * t
+            }
+            Interpolation::CubicSpline => {
+                let out_tangent = self.values[prev * 3 + 2] * dt;
+                let in_tangent = self.values[next * 3] * dt;
+
+                let t2 = t * t;
+                let t3 = t2 * t;
+
+                value(prev) * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + out_tangent * (t3 - 2.0 * t2 + t)
+                    + value(next) * (-2.0 * t3 + 3.0 * t2)
+                    + in_tangent * (t3 - t2)
+            }
+        }
+    }
+
+    /// Writes the value at `time` to the animated component of `transform`.
+    pub fn apply(&self, time: f32, transform: &mut Transform) {
+        let value = self.sample(time);
+
+        match self.property {
+            Property::Translation => transform.position = value.truncated(),
+            Property::Rotation => {
+                transform.rotation =
+                    Rotor3::from_quaternion_array([value.x, value.y, value.z, value.w]).normalized()
+            }
+            Property::Scale => transform.scale = value.truncated(),
+        }
+    }
+}
+
+/// A named set of channels animating the nodes of a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    name: String,
+    channels: Vec<Channel>,
+    duration: f32,
+}
+
+impl Animation {
+    /// Reads the translation, rotation and scale channels of a glTF animation. Morph target
+    /// weights are not supported and skipped.
+    pub fn from_gltf(animation: gltf::Animation, buffers: &[gltf::buffer::Data]) -> Self {
+        use gltf::animation::util::ReadOutputs;
+
+        let channels = animation
+            .channels()
+            .filter_map(|channel| {
+                let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+
+                let times = reader.read_inputs()?.collect::<Vec<_>>();
+
+                let (property, values) = match reader.read_outputs()? {
+                    ReadOutputs::Translations(values) => (
+                        Property::Translation,
+                        values.map(|[x, y, z]| Vec4::new(x, y, z, 0.0)).collect(),
+                    ),
+                    ReadOutputs::Rotations(values) => (
+                        Property::Rotation,
+                        values.into_f32().map(Vec4::from).collect(),
+                    ),
+                    ReadOutputs::Scales(values) => (
+                        Property::Scale,
+                        values.map(|[x, y, z]| Vec4::new(x, y, z, 0.0)).collect(),
+                    ),
+                    ReadOutputs::MorphTargetWeights(_) => return None,
+                };
+
+                if times.is_empty() {
+                    return None;
+                }
+
+                Some(Channel {
+                    node: channel.target().node().index(),
+                    property,
+                    interpolation: channel.sampler().interpolation().into(),
+                    times,
+                    values,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last())
+            .fold(0.0, |acc: f32, t| acc.max(*t));
+
+        Self {
+            name: animation.name().unwrap_or_default().to_owned(),
+            channels,
+            duration,
+        }
+    }
+
+    /// Applies all channels at `time` to the local node transforms.
+    pub fn sample(&self, time: f32, transforms: &mut [Transform]) {
+        for channel in &self.channels {
+            if let Some(transform) = transforms.get_mut(channel.node) {
+                channel.apply(time, transform);
+            }
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+
+    /// Returns the time of the last keyframe in seconds
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+}
+
+/// Plays an animation of a document on the scene objects instantiated from it.
+/// See `Document::instantiate`.
+pub struct AnimationPlayer {
+    animation: usize,
+    /// Pairs of node and scene object indices
+    bindings: Vec<(usize, usize)>,
+    /// The current playback time in seconds
+    pub time: f32,
+    /// Playback speed multiplier
+    pub speed: f32,
+    /// Restart from the beginning when reaching the end
+    pub looping: bool,
+    pub playing: bool,
+}
+
+impl AnimationPlayer {
+    /// Creates a player for the animation at index `animation` in the document driving the
+    /// objects in `bindings`, given as pairs of node and scene object indices.
+    pub fn new(animation: usize, bindings: Vec<(usize, usize)>) -> Self {
+        Self {
+            animation,
+            bindings,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            playing: true,
+        }
+    }
+
+    /// Advances the playback time by `dt` seconds and updates the transforms of the bound
+    /// objects.
+    pub fn update(&mut self, dt: f32, document: &Document, scene: &mut Scene) {
+        let animation = &document.animations()[self.animation];
+
+        if self.playing {
+            self.time += dt * self.speed;
+        }
+
+        let duration = animation.duration();
+
+        if self.looping && duration > 0.0 {
+            self.time = self.time.rem_euclid(duration);
+        } else {
+            self.time = self.time.clamp(0.0, duration);
+        }
+
+        let mut transforms = document.local_transforms();
+        animation.sample(self.time, &mut transforms);
+
+        let transforms = document.world_transforms(&transforms);
+
+        // Only the bound objects are marked as changed
+        for (node, object) in &self.bindings {
+            if let (Some(transform), Some(object)) =
+                (transforms.get(*node), scene.object_at_mut(*object))
+            {
+                object.position = transform.position;
+                object.rotation = transform.rotation;
+                object.scale = transform.scale;
+            }
+        }
+    }
+
+    /// Returns the index of the played animation in the document
+    pub fn animation(&self) -> usize {
+        self.animation
+    }
+}
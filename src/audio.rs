@@ -0,0 +1,464 @@
+//! Positional sound playback. Sounds are loaded as resources and played as voices, whose volume
+//! and panning follow the camera as listener and the scene objects as emitters.
+//!
+//! Output goes through cpal when the `cpal` feature is enabled. Without it, or when no output
+//! device is available, the audio runs silently and voices finish immediately.
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use ultraviolet::Vec3;
+
+use crate::{Camera, Error, Scene};
+
+/// Decoded PCM audio, shared with the voices playing it
+#[derive(Clone)]
+pub struct Sound {
+    /// Interleaved samples in [-1, 1]
+    samples: Arc<[f32]>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Sound {
+    /// Loads an uncompressed integer or float WAV file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let error = |source| Error::SoundError {
+            path: path.to_owned(),
+            source,
+        };
+
+        let reader = hound::WavReader::open(path).map_err(error)?;
+        let spec = reader.spec();
+
+        let samples: Result<Vec<f32>, _> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.into_samples::<f32>().collect(),
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1_i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .into_samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 * scale))
+                    .collect()
+            }
+        };
+
+        Ok(Self::new(
+            samples.map_err(error)?,
+            spec.channels,
+            spec.sample_rate,
+        ))
+    }
+
+    /// Creates a sound from interleaved samples, e.g; generated tones
+    pub fn new(samples: Vec<f32>, channels: u16, sample_rate: u32) -> Self {
+        Self {
+            samples: samples.into(),
+            channels: channels.max(1),
+            sample_rate,
+        }
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns the length in seconds
+    pub fn duration(&self) -> f32 {
+        self.frames() as f32 / self.sample_rate as f32
+    }
+
+    fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    // Returns the sample of the frame at `frame` mixed down to mono
+    fn mono(&self, frame: usize) -> f32 {
+        let channels = self.channels as usize;
+        let frame = &self.samples[frame * channels..(frame + 1) * channels];
+        frame.iter().sum::<f32>() / channels as f32
+    }
+}
+
+/// Where a voice is heard from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emitter {
+    /// Heard at full volume and centered regardless of the listener, e.g; music and interface
+    /// sounds
+    Ambient,
+    /// A fixed position in world space
+    Position(Vec3),
+    /// Follows the object at the index in the scene
+    Object(usize),
+}
+
+/// Specifies how a sound is played
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayInfo {
+    pub emitter: Emitter,
+    pub volume: f32,
+    /// Restart the sound when it ends until stopped
+    pub looping: bool,
+}
+
+impl Default for PlayInfo {
+    fn default() -> Self {
+        Self {
+            emitter: Emitter::Ambient,
+            volume: 1.0,
+            looping: false,
+        }
+    }
+}
+
+/// Specifies the output and attenuation of the audio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioInfo {
+    /// Multiplied with the volume of all voices
+    pub volume: f32,
+    /// Positional voices within the distance are played at full volume
+    pub reference_distance: f32,
+    /// How quickly positional voices fade beyond the reference distance
+    pub rolloff: f32,
+    /// Positional voices further away than the distance are silent
+    pub max_distance: f32,
+}
+
+impl Default for AudioInfo {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            reference_distance: 1.0,
+            rolloff: 1.0,
+            max_distance: 100.0,
+        }
+    }
+}
+
+/// Identifies a playing sound
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Voice(u64);
+
+struct VoiceState {
+    voice: Voice,
+    sound: Sound,
+    info: PlayInfo,
+    /// The current frame of the sound, fractional when resampling
+    cursor: f64,
+    /// The left and right gain, updated from the emitter each frame
+    gains: [f32; 2],
+    finished: bool,
+}
+
+#[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+impl VoiceState {
+    /// Adds the voice to the interleaved output samples. Returns false when the sound ended.
+    fn mix(&mut self, output: &mut [f32], channels: usize, sample_rate: u32) -> bool {
+        let frames = self.sound.frames();
+        if frames == 0 {
+            return false;
+        }
+
+        let step = self.sound.sample_rate as f64 / sample_rate as f64;
+
+        for frame in output.chunks_exact_mut(channels) {
+            if self.cursor as usize >= frames {
+                if !self.info.looping {
+                    return false;
+                }
+
+                self.cursor -= frames as f64;
+            }
+
+            let value = self.sound.mono(self.cursor as usize);
+
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                // Channels beyond stereo receive the average
+                let gain = match channel {
+                    0 | 1 if channels > 1 => self.gains[channel],
+                    _ => (self.gains[0] + self.gains[1]) * 0.5,
+                };
+
+                *sample += value * gain;
+            }
+
+            self.cursor += step;
+        }
+
+        true
+    }
+}
+
+/// The voices shared with the output stream
+#[derive(Default)]
+struct Mixer {
+    voices: Vec<VoiceState>,
+    /// The sample rate and channel count of the output
+    sample_rate: u32,
+    channels: u16,
+}
+
+#[cfg_attr(not(feature = "cpal"), allow(dead_code))]
+impl Mixer {
+    /// Mixes the voices into the interleaved output samples and removes finished voices
+    fn mix(&mut self, output: &mut [f32]) {
+        output.iter_mut().for_each(|sample| *sample = 0.0);
+
+        let channels = self.channels.max(1) as usize;
+        let sample_rate = self.sample_rate;
+
+        for voice in &mut self.voices {
+            voice.finished = !voice.mix(output, channels, sample_rate);
+        }
+
+        self.voices.retain(|voice| !voice.finished);
+    }
+}
+
+/// Plays sounds on the default output device. Call `update` once per frame to move the
+/// listener and emitters.
+pub struct Audio {
+    info: AudioInfo,
+    mixer: Arc<Mutex<Mixer>>,
+    next_voice: u64,
+    /// None when running silently
+    #[cfg(feature = "cpal")]
+    stream: Option<backend::Stream>,
+}
+
+impl Audio {
+    /// Opens the default output device. Runs silently if no device is available.
+    pub fn new(info: AudioInfo) -> Self {
+        let mixer = Arc::new(Mutex::new(Mixer::default()));
+
+        #[cfg(feature = "cpal")]
+        let stream = match backend::Stream::new(mixer.clone()) {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                log::warn!("Audio output is unavailable: {}", e);
+                None
+            }
+        };
+
+        #[cfg(not(feature = "cpal"))]
+        log::info!("Audio output requires the `cpal` feature, running silently");
+
+        Self {
+            info,
+            mixer,
+            next_voice: 0,
+            #[cfg(feature = "cpal")]
+            stream,
+        }
+    }
+
+    /// Returns true if the sounds are heard, i.e; an output device is open
+    pub fn has_output(&self) -> bool {
+        #[cfg(feature = "cpal")]
+        let has_output = self.stream.is_some();
+
+        #[cfg(not(feature = "cpal"))]
+        let has_output = false;
+
+        has_output
+    }
+
+    pub fn info(&self) -> &AudioInfo {
+        &self.info
+    }
+
+    pub fn set_info(&mut self, info: AudioInfo) {
+        self.info = info;
+    }
+
+    /// Starts playing `sound`. The voice is heard from the next `update`, except for ambient
+    /// voices which are heard immediately.
+    pub fn play(&mut self, sound: &Sound, info: PlayInfo) -> Voice {
+        let voice = Voice(self.next_voice);
+        self.next_voice += 1;
+
+        if !self.has_output() {
+            return voice;
+        }
+
+        let gains = match info.emitter {
+            Emitter::Ambient => [info.volume * self.info.volume; 2],
+            _ => [0.0; 2],
+        };
+
+        self.mixer().voices.push(VoiceState {
+            voice,
+            sound: sound.clone(),
+            info,
+            cursor: 0.0,
+            gains,
+            finished: false,
+        });
+
+        voice
+    }
+
+    /// Stops a voice. Does nothing if the voice already finished.
+    pub fn stop(&mut self, voice: Voice) {
+        self.mixer().voices.retain(|state| state.voice != voice);
+    }
+
+    pub fn stop_all(&mut self) {
+        self.mixer().voices.clear();
+    }
+
+    /// Returns true if the voice is still playing
+    pub fn is_playing(&self, voice: Voice) -> bool {
+        self.mixer().voices.iter().any(|state| state.voice == voice)
+    }
+
+    /// Moves a voice to another emitter
+    pub fn set_emitter(&mut self, voice: Voice, emitter: Emitter) {
+        if let Some(state) = self.mixer().voices.iter_mut().find(|s| s.voice == voice) {
+            state.info.emitter = emitter;
+        }
+    }
+
+    /// Updates the volume and panning of the voices from the camera as listener and the
+    /// objects of the scene. Voices following removed objects are stopped.
+    pub fn update(&mut self, camera: &Camera, scene: &Scene) {
+        let info = self.info;
+        let objects = scene.objects();
+
+        let mut mixer = self.mixer();
+
+        for state in &mut mixer.voices {
+            let position = match state.info.emitter {
+                Emitter::Ambient => None,
+                Emitter::Position(position) => Some(position),
+                Emitter::Object(index) => match objects.get(index) {
+                    Some(object) => Some(object.position),
+                    None => {
+                        state.finished = true;
+                        continue;
+                    }
+                },
+            };
+
+            let volume = state.info.volume * info.volume;
+
+            state.gains = match position {
+                Some(position) => spatialize(&info, camera, position, volume),
+                None => [volume; 2],
+            };
+        }
+
+        mixer.voices.retain(|state| !state.finished);
+    }
+
+    fn mixer(&self) -> std::sync::MutexGuard<'_, Mixer> {
+        // The mixer is never left inconsistent, so a panic on the audio thread is recoverable
+        self.mixer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Returns the left and right gain of a sound at `position` heard by `listener`. Attenuates by
+/// the inverse distance and pans with equal power by the direction relative to the listener.
+pub fn spatialize(info: &AudioInfo, listener: &Camera, position: Vec3, volume: f32) -> [f32; 2] {
+    let offset = position - listener.position;
+    let distance = offset.mag();
+
+    if distance >= info.max_distance {
+        return [0.0; 2];
+    }
+
+    let excess = (distance - info.reference_distance).max(0.0);
+    let attenuation = info.reference_distance / (info.reference_distance + info.rolloff * excess);
+
+    let pan = if distance > 0.0 {
+        offset.normalized().dot(listener.right())
+    } else {
+        0.0
+    };
+
+    let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+    let gain = volume * attenuation;
+
+    [angle.cos() * gain, angle.sin() * gain]
+}
+
+#[cfg(feature = "cpal")]
+mod backend {
+    use std::sync::{Arc, Mutex};
+
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::{Sample, SampleFormat};
+
+    use super::Mixer;
+
+    /// An output stream on the default device pulling samples from the mixer
+    pub struct Stream {
+        _stream: cpal::Stream,
+    }
+
+    impl Stream {
+        pub fn new(mixer: Arc<Mutex<Mixer>>) -> Result<Self, String> {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or_else(|| "No output device".to_owned())?;
+
+            let config = device.default_output_config().map_err(|e| e.to_string())?;
+            let format = config.sample_format();
+            let config: cpal::StreamConfig = config.into();
+
+            {
+                let mut mixer = mixer.lock().unwrap();
+                mixer.sample_rate = config.sample_rate.0;
+                mixer.channels = config.channels;
+            }
+
+            log::info!(
+                "Opened audio output {:?} with {} channels at {} Hz",
+                device.name().unwrap_or_default(),
+                config.channels,
+                config.sample_rate.0
+            );
+
+            let stream = match format {
+                SampleFormat::F32 => build::<f32>(&device, &config, mixer),
+                SampleFormat::I16 => build::<i16>(&device, &config, mixer),
+                SampleFormat::U16 => build::<u16>(&device, &config, mixer),
+            }
+            .map_err(|e| e.to_string())?;
+
+            stream.play().map_err(|e| e.to_string())?;
+
+            Ok(Self { _stream: stream })
+        }
+    }
+
+    fn build<T: Sample>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        mixer: Arc<Mutex<Mixer>>,
+    ) -> Result<cpal::Stream, cpal::BuildStreamError> {
+        let mut buffer = Vec::new();
+
+        device.build_output_stream(
+            config,
+            move |output: &mut [T], _| {
+                buffer.resize(output.len(), 0.0);
+
+                if let Ok(mut mixer) = mixer.lock() {
+                    mixer.mix(&mut buffer);
+                } else {
+                    buffer.iter_mut().for_each(|sample| *sample = 0.0);
+                }
+
+                for (output, sample) in output.iter_mut().zip(&buffer) {
+                    *output = T::from(&sample.clamp(-1.0, 1.0));
+                }
+            },
+            |e| log::error!("Audio output error: {}", e),
+        )
+    }
+}
@@ -0,0 +1,473 @@
+//! Reproducible benchmarks of the renderer, which fly the camera along a scripted path once for
+//! each of a series of object counts and record the frame times.
+//!
+//! The camera advances by a fixed timestep each frame rather than the measured frame time, so
+//! every run draws the same frames regardless of how fast they are drawn. Objects are spawned at
+//! seeded random positions, which places them identically between benchmarks.
+use rand::prelude::*;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+use ultraviolet::{Rotor3, Vec3};
+
+use crate::aabb::Aabb;
+use crate::camera::Camera;
+use crate::layers::Layers;
+use crate::resources::Handle;
+use crate::{Error, Material, Mesh, Object, Scene};
+
+/// A point the camera passes through
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraKeyframe {
+    /// The time in seconds from the start of the path
+    pub time: f32,
+    pub position: Vec3,
+    /// The point the camera looks at
+    pub target: Vec3,
+}
+
+/// A camera path through keyframes, interpolated by a Catmull-Rom spline
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CameraPath {
+    /// Keyframes in increasing order of time
+    pub keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraPath {
+    pub fn new(keyframes: Vec<CameraKeyframe>) -> Self {
+        Self { keyframes }
+    }
+
+    /// Creates a path circling `target` once at `radius` and `height` above it in `duration`
+    /// seconds, through `points` keyframes
+    pub fn orbit(target: Vec3, radius: f32, height: f32, duration: f32, points: usize) -> Self {
+        let points = points.max(2);
+
+        let keyframes = (0..=points)
+            .map(|i| {
+                let t = i as f32 / points as f32;
+                let angle = t * std::f32::consts::TAU;
+
+                CameraKeyframe {
+                    time: t * duration,
+                    position: target
+                        + Vec3::new(angle.cos() * radius, height, angle.sin() * radius),
+                    target,
+                }
+            })
+            .collect();
+
+        Self { keyframes }
+    }
+
+    /// Returns the time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// Returns the position and target at `time`. Times outside the keyframes are clamped.
+    /// None if the path has no keyframes.
+    pub fn sample(&self, time: f32) -> Option<(Vec3, Vec3)> {
+        let keyframes = &self.keyframes;
+        let last = keyframes.len().checked_sub(1)?;
+
+        let next = match keyframes.iter().position(|keyframe| keyframe.time > time) {
+            Some(0) => return Some((keyframes[0].position, keyframes[0].target)),
+            Some(next) => next,
+            None => return Some((keyframes[last].position, keyframes[last].target)),
+        };
+
+        let prev = next - 1;
+        let t = (time - keyframes[prev].time) / (keyframes[next].time - keyframes[prev].time);
+
+        // The end keyframes are repeated as the outer control points
+        let points = [
+            &keyframes[prev.saturating_sub(1)],
+            &keyframes[prev],
+            &keyframes[next],
+            &keyframes[(next + 1).min(last)],
+        ];
+
+        Some((
+            catmull_rom(points.map(|keyframe| keyframe.position), t),
+            catmull_rom(points.map(|keyframe| keyframe.target), t),
+        ))
+    }
+
+    /// Moves `camera` to the position at `time` and turns it towards the target
+    pub fn apply(&self, time: f32, camera: &mut Camera) {
+        if let Some((position, target)) = self.sample(time) {
+            camera.position = position;
+            camera.look_at(target, Vec3::unit_y());
+        }
+    }
+}
+
+/// Specifies the runs of a benchmark
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkInfo {
+    /// Played once for each run
+    pub path: CameraPath,
+    /// The number of spawned objects in each run, in increasing order since spawned objects
+    /// are not removed between runs
+    pub object_counts: Vec<usize>,
+    /// The number of frames drawn at the start of each run which are not recorded, e.g; while
+    /// the spawned objects are uploaded
+    pub warmup_frames: u32,
+    /// The time in seconds the camera advances along the path each frame
+    pub timestep: f32,
+    /// Objects are spawned at random positions within the bounds
+    pub spawn_bounds: Aabb,
+    /// The layers of the spawned objects
+    pub spawn_layers: Layers,
+    /// Seeds the positions of the spawned objects
+    pub seed: u64,
+}
+
+impl Default for BenchmarkInfo {
+    fn default() -> Self {
+        Self {
+            path: CameraPath::orbit(Vec3::zero(), 20.0, 5.0, 10.0, 8),
+            object_counts: vec![0, 1000, 5000, 10000],
+            warmup_frames: 30,
+            timestep: 1.0 / 60.0,
+            spawn_bounds: Aabb::new(Vec3::broadcast(-15.0), Vec3::broadcast(15.0)),
+            spawn_layers: Layers::ALL,
+            seed: 0,
+        }
+    }
+}
+
+/// The recorded time of a single frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameRecord {
+    /// The index of the run the frame was drawn in
+    pub run: usize,
+    /// The number of spawned objects during the run
+    pub objects: usize,
+    /// The index of the frame in the run, excluding the warmup frames
+    pub frame: u32,
+    /// The time along the camera path
+    pub time: f32,
+    pub frame_time: Duration,
+}
+
+/// Frame time statistics of a single run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunReport {
+    pub objects: usize,
+    pub frames: usize,
+    pub average: Duration,
+    /// 95% of the frames were drawn within this time
+    pub percentile_95: Duration,
+    /// 99% of the frames were drawn within this time
+    pub percentile_99: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl RunReport {
+    /// Summarizes the frame times of a run with `objects` spawned objects
+    pub fn new(objects: usize, frame_times: &[Duration]) -> Self {
+        let mut frame_times = frame_times.to_vec();
+        frame_times.sort_unstable();
+
+        let average = if frame_times.is_empty() {
+            Duration::default()
+        } else {
+            frame_times.iter().sum::<Duration>() / frame_times.len() as u32
+        };
+
+        Self {
+            objects,
+            frames: frame_times.len(),
+            average,
+            percentile_95: percentile(&frame_times, 95),
+            percentile_99: percentile(&frame_times, 99),
+            min: frame_times.first().copied().unwrap_or_default(),
+            max: frame_times.last().copied().unwrap_or_default(),
+        }
+    }
+
+    /// Returns the average frames per second
+    pub fn average_fps(&self) -> f32 {
+        if self.average == Duration::default() {
+            0.0
+        } else {
+            1.0 / self.average.as_secs_f32()
+        }
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} objects, {} frames: average {:.2} ms ({:.1} fps), 95% {:.2} ms, 99% {:.2} ms, \
+             min {:.2} ms, max {:.2} ms",
+            self.objects,
+            self.frames,
+            millis(self.average),
+            self.average_fps(),
+            millis(self.percentile_95),
+            millis(self.percentile_99),
+            millis(self.min),
+            millis(self.max),
+        )
+    }
+}
+
+/// The summarized frame times of each run of a benchmark
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BenchmarkReport {
+    pub runs: Vec<RunReport>,
+}
+
+impl fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, run) in self.runs.iter().enumerate() {
+            writeln!(f, "Run {}: {}", i, run)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Plays the runs of a benchmark, moving the camera and spawning objects. `update` is called
+/// once at the start of each frame.
+pub struct Benchmark {
+    info: BenchmarkInfo,
+    mesh: Handle<Mesh>,
+    material: Handle<Material>,
+    rng: StdRng,
+    /// The run and frame within it drawn since the last update
+    run: usize,
+    frame: u32,
+    started: bool,
+    spawned: usize,
+    records: Vec<FrameRecord>,
+}
+
+impl Benchmark {
+    /// Creates a benchmark spawning objects of `mesh` and `material`
+    pub fn new(info: BenchmarkInfo, mesh: Handle<Mesh>, material: Handle<Material>) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(info.seed),
+            info,
+            mesh,
+            material,
+            run: 0,
+            frame: 0,
+            started: false,
+            spawned: 0,
+            records: Vec::new(),
+        }
+    }
+
+    /// Records `frame_time` as the time of the previous frame and advances to the next frame,
+    /// spawning the objects of a run as it starts and moving `camera` along the path. Returns
+    /// false once every run has finished.
+    pub fn update(&mut self, frame_time: Duration, camera: &mut Camera, scene: &mut Scene) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+
+        if self.started {
+            if self.frame >= self.info.warmup_frames {
+                self.records.push(FrameRecord {
+                    run: self.run,
+                    objects: self.info.object_counts[self.run],
+                    frame: self.frame - self.info.warmup_frames,
+                    time: self.path_time(self.frame),
+                    frame_time,
+                });
+            }
+
+            self.frame += 1;
+
+            if self.path_time(self.frame) > self.info.path.duration() {
+                log::info!("Finished benchmark run {}", self.run);
+                self.run += 1;
+                self.frame = 0;
+            }
+
+            if self.is_finished() {
+                return false;
+            }
+        }
+
+        if self.frame == 0 {
+            self.spawn(scene);
+        }
+
+        self.started = true;
+        self.info.path.apply(self.path_time(self.frame), camera);
+
+        true
+    }
+
+    /// Returns true once every run has finished
+    pub fn is_finished(&self) -> bool {
+        self.run >= self.info.object_counts.len()
+    }
+
+    pub fn info(&self) -> &BenchmarkInfo {
+        &self.info
+    }
+
+    /// Returns the recorded frames of every run so far
+    pub fn records(&self) -> &[FrameRecord] {
+        &self.records
+    }
+
+    /// Summarizes the recorded frames of each run
+    pub fn report(&self) -> BenchmarkReport {
+        let runs = self
+            .info
+            .object_counts
+            .iter()
+            .enumerate()
+            .map(|(run, &objects)| RunReport::new(objects, &self.frame_times(run)))
+            .collect();
+
+        BenchmarkReport { runs }
+    }
+
+    /// Writes the recorded frames to a CSV file with a row for each frame
+    pub fn write_csv(&self, path: &Path) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writeln!(writer, "run,objects,frame,time,frame_time_ms")?;
+
+        for record in &self.records {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                record.run,
+                record.objects,
+                record.frame,
+                record.time,
+                millis(record.frame_time)
+            )?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the report of each run along with its frame times to a JSON file
+    pub fn write_json(&self, path: &Path) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writeln!(writer, "{{\n  \"runs\": [")?;
+
+        let report = self.report();
+
+        for (i, run) in report.runs.iter().enumerate() {
+            let frame_times = self
+                .frame_times(i)
+                .iter()
+                .map(|frame_time| millis(*frame_time).to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            writeln!(writer, "    {{")?;
+            writeln!(writer, "      \"objects\": {},", run.objects)?;
+            writeln!(writer, "      \"frames\": {},", run.frames)?;
+            writeln!(writer, "      \"average_ms\": {},", millis(run.average))?;
+            writeln!(writer, "      \"p95_ms\": {},", millis(run.percentile_95))?;
+            writeln!(writer, "      \"p99_ms\": {},", millis(run.percentile_99))?;
+            writeln!(writer, "      \"min_ms\": {},", millis(run.min))?;
+            writeln!(writer, "      \"max_ms\": {},", millis(run.max))?;
+            writeln!(writer, "      \"frame_times_ms\": [{}]", frame_times)?;
+
+            let separator = if i + 1 < report.runs.len() { "," } else { "" };
+            writeln!(writer, "    }}{}", separator)?;
+        }
+
+        writeln!(writer, "  ]\n}}")?;
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Returns the time along the path of frame `frame` of a run
+    fn path_time(&self, frame: u32) -> f32 {
+        frame.saturating_sub(self.info.warmup_frames) as f32 * self.info.timestep
+    }
+
+    fn frame_times(&self, run: usize) -> Vec<Duration> {
+        self.records
+            .iter()
+            .filter(|record| record.run == run)
+            .map(|record| record.frame_time)
+            .collect()
+    }
+
+    // Spawns objects until the object count of the current run is reached
+    fn spawn(&mut self, scene: &mut Scene) {
+        let count = self.info.object_counts[self.run];
+
+        if count < self.spawned {
+            log::warn!(
+                "Benchmark run {} expects {} objects, but {} are already spawned",
+                self.run,
+                count,
+                self.spawned
+            );
+        }
+
+        let bounds = self.info.spawn_bounds;
+
+        for _ in self.spawned..count {
+            let t = Vec3::new(self.rng.gen(), self.rng.gen(), self.rng.gen());
+
+            scene.add(Object {
+                mesh: self.mesh,
+                material: self.material,
+                lods: None,
+                position: bounds.min + (bounds.max - bounds.min) * t,
+                rotation: Rotor3::from_rotation_xz(self.rng.gen::<f32>() * std::f32::consts::TAU),
+                scale: Vec3::one(),
+                is_static: true,
+                layers: self.info.spawn_layers,
+            });
+        }
+
+        self.spawned = self.spawned.max(count);
+
+        log::info!(
+            "Starting benchmark run {} with {} objects",
+            self.run,
+            self.spawned
+        );
+    }
+}
+
+// Evaluates a uniform Catmull-Rom spline between the middle two of `points`
+fn catmull_rom([p0, p1, p2, p3]: [Vec3; 4], t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+// Returns the nearest rank percentile of sorted frame times
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
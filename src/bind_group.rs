@@ -0,0 +1,164 @@
+//! The descriptor sets bound when drawing objects with material effects, grouped by how often
+//! they change. Each set index of effect shaders has a fixed meaning, see `SET_SLOTS`:
+//!
+//! - `MATERIAL_SET` changes with the material of each batch.
+//! - `OBJECT_SET` and `MESHLET_SET` hold the objects and meshlets drawn by a batch.
+//! - All other sets change at most once per frame, e.g; the shadows, lights and fog of the
+//!   scene, and are only bound to pipelines whose shaders use them.
+//!
+//! Shaders using a set index without a slot are rejected when the effect is loaded.
+use arrayvec::ArrayVec;
+use ash::vk::DescriptorSet;
+
+use crate::environment::ENVIRONMENT_SET;
+use crate::fog::FOG_SET;
+use crate::light_culling::LIGHT_SET;
+use crate::material::MATERIAL_SET;
+use crate::mesh::MESHLET_SET;
+use crate::point_shadow::POINT_SHADOW_SET;
+use crate::ray_query::RAY_QUERY_SET;
+use crate::shadow::SHADOW_SET;
+use crate::stereo::VIEW_SET;
+use crate::vulkan;
+use vulkan::commands::CommandBuffer;
+use vulkan::pipeline::Pipeline;
+
+/// The descriptor set index of the object buffer in material effect shaders
+pub const OBJECT_SET: u32 = 1;
+
+/// The number of set indices available to effect shaders
+pub const MAX_SETS: usize = 10;
+
+/// How often the descriptor set bound at a slot changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BindFrequency {
+    /// Bound once per frame to all pipelines using it
+    Frame,
+    /// Bound for each material
+    Material,
+    /// Bound for each batch of objects
+    Object,
+}
+
+/// A descriptor set index of effect shaders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetSlot {
+    pub index: u32,
+    pub frequency: BindFrequency,
+    /// Describes the contents of the set in errors
+    pub name: &'static str,
+}
+
+impl SetSlot {
+    const fn new(index: u32, frequency: BindFrequency, name: &'static str) -> Self {
+        Self {
+            index,
+            frequency,
+            name,
+        }
+    }
+}
+
+/// The slots of all sets bound when drawing with material effects, in set order
+pub const SET_SLOTS: [SetSlot; MAX_SETS] = [
+    SetSlot::new(MATERIAL_SET, BindFrequency::Material, "material"),
+    SetSlot::new(OBJECT_SET, BindFrequency::Object, "objects"),
+    SetSlot::new(SHADOW_SET, BindFrequency::Frame, "shadow map"),
+    SetSlot::new(POINT_SHADOW_SET, BindFrequency::Frame, "point shadows"),
+    SetSlot::new(LIGHT_SET, BindFrequency::Frame, "light grid"),
+    SetSlot::new(ENVIRONMENT_SET, BindFrequency::Frame, "environment"),
+    SetSlot::new(FOG_SET, BindFrequency::Frame, "fog"),
+    SetSlot::new(MESHLET_SET, BindFrequency::Object, "meshlets"),
+    SetSlot::new(RAY_QUERY_SET, BindFrequency::Frame, "ray query"),
+    SetSlot::new(VIEW_SET, BindFrequency::Frame, "views"),
+];
+
+/// Returns the slot of set `index`, if any
+pub fn slot(index: u32) -> Option<&'static SetSlot> {
+    SET_SLOTS.iter().find(|slot| slot.index == index)
+}
+
+/// Returns an error if the shaders of `pipeline` use a set index without a slot, which would
+/// never be bound
+pub fn validate(pipeline: &Pipeline) -> Result<(), vulkan::Error> {
+    match pipeline
+        .bindings()
+        .iter()
+        .find(|binding| slot(binding.set).is_none())
+    {
+        Some(binding) => Err(vulkan::Error::UnknownSet {
+            set: binding.set,
+            name: binding.name.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The sets bound once per frame, bound to each pipeline whose shaders use them
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameSets {
+    sets: [Option<DescriptorSet>; MAX_SETS],
+}
+
+impl FrameSets {
+    /// Sets or removes the set bound at `index`.
+    /// Panics if `index` is not the index of a per-frame slot.
+    pub fn set(&mut self, index: u32, set: Option<DescriptorSet>) {
+        assert_eq!(
+            slot(index).map(|slot| slot.frequency),
+            Some(BindFrequency::Frame),
+            "Set {} is not bound per frame",
+            index
+        );
+
+        self.sets[index as usize] = set;
+    }
+
+    pub fn get(&self, index: u32) -> Option<DescriptorSet> {
+        self.sets.get(index as usize).copied().flatten()
+    }
+
+    /// Appends the per-frame sets used by `pipeline` to `sets`. Returns None if the pipeline uses
+    /// a per-frame set which is not available, e.g; samples shadows without a shadow map.
+    pub fn extend_for(
+        &self,
+        pipeline: &Pipeline,
+        sets: &mut ArrayVec<[(u32, DescriptorSet); MAX_SETS]>,
+    ) -> Option<()> {
+        for slot in SET_SLOTS
+            .iter()
+            .filter(|slot| slot.frequency == BindFrequency::Frame)
+            .filter(|slot| pipeline.uses_set(slot.index))
+        {
+            sets.push((slot.index, self.get(slot.index)?));
+        }
+
+        Some(())
+    }
+}
+
+/// Binds `sets` given as pairs of set index and set, sorted by index. Sets with consecutive
+/// indices are bound together.
+pub fn bind_sets(
+    commandbuffer: &CommandBuffer,
+    pipeline: &Pipeline,
+    sets: &[(u32, DescriptorSet)],
+) {
+    let mut rest = sets;
+
+    while let Some((first, _)) = rest.first() {
+        let count = rest
+            .iter()
+            .enumerate()
+            .take_while(|(i, (index, _))| *index == first + *i as u32)
+            .count();
+
+        let run = rest[..count]
+            .iter()
+            .map(|(_, set)| *set)
+            .collect::<ArrayVec<[_; MAX_SETS]>>();
+
+        commandbuffer.bind_descriptor_sets(pipeline, *first, &run, &[]);
+        rest = &rest[count..];
+    }
+}
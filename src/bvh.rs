@@ -0,0 +1,254 @@
+//! A dynamic bounding volume hierarchy over the bounding boxes of the scene objects. Leaves
+//! store enlarged boxes, so objects moving within them do not change the tree. Objects which
+//! leave their box are removed and reinserted next to the node whose box grows the least.
+
+use crate::aabb::Aabb;
+use crate::frustum::Frustum;
+use crate::raycast::Ray;
+
+/// The fraction of the size of an object its leaf box is enlarged by on each side
+pub const FAT_RATIO: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NodeKind {
+    /// Contains the index of the object in the scene
+    Leaf(usize),
+    Branch([usize; 2]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Node {
+    aabb: Aabb,
+    parent: Option<usize>,
+    kind: NodeKind,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// Unused slots in nodes
+    free: Vec<usize>,
+    root: Option<usize>,
+    /// The leaf node of each object, None for objects without bounds
+    leaves: Vec<Option<usize>>,
+}
+
+impl Bvh {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the tree with the current bounds of each object. Objects still contained in their
+    /// leaf are left as is. Objects past the end of `bounds` are removed.
+    pub fn update<I>(&mut self, bounds: I)
+    where
+        I: IntoIterator<Item = Option<Aabb>>,
+    {
+        let mut count = 0;
+
+        for (object, aabb) in bounds.into_iter().enumerate() {
+            count += 1;
+
+            if object >= self.leaves.len() {
+                self.leaves.push(None);
+            }
+
+            let leaf = self.leaves[object];
+
+            match (leaf, aabb) {
+                (Some(leaf), Some(aabb)) if contains(&self.nodes[leaf].aabb, &aabb) => {}
+                (leaf, aabb) => {
+                    if let Some(leaf) = leaf {
+                        self.remove(leaf);
+                    }
+
+                    let leaf = aabb.map(|aabb| self.insert(object, fatten(&aabb)));
+                    self.leaves[object] = leaf;
+                }
+            }
+        }
+
+        for leaf in self.leaves.drain(count..).flatten().collect::<Vec<_>>() {
+            self.remove(leaf);
+        }
+    }
+
+    /// Returns the number of objects the tree was last updated with. Later objects are not
+    /// contained in the tree.
+    pub fn object_count(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns the box containing all leaves, or None if the tree is empty.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.root.map(|root| self.nodes[root].aabb)
+    }
+
+    /// Calls `visit` with each object whose leaf box passes `test`. Children are only tested if
+    /// their parent passes.
+    pub fn query<T, V>(&self, mut test: T, mut visit: V)
+    where
+        T: FnMut(&Aabb) -> bool,
+        V: FnMut(usize),
+    {
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !test(&node.aabb) {
+                continue;
+            }
+
+            match node.kind {
+                NodeKind::Leaf(object) => visit(object),
+                NodeKind::Branch(children) => stack.extend_from_slice(&children),
+            }
+        }
+    }
+
+    /// Calls `visit` with each object whose leaf box intersects `aabb`.
+    pub fn query_aabb<V: FnMut(usize)>(&self, aabb: &Aabb, visit: V) {
+        self.query(|node| node.intersects(aabb), visit)
+    }
+
+    /// Calls `visit` with each object whose leaf box intersects `frustum`.
+    pub fn query_frustum<V: FnMut(usize)>(&self, frustum: &Frustum, visit: V) {
+        self.query(|node| frustum.intersects_aabb(node), visit)
+    }
+
+    /// Calls `visit` with each object whose leaf box is hit by `ray`.
+    pub fn query_ray<V: FnMut(usize)>(&self, ray: &Ray, visit: V) {
+        self.query(|node| node.intersect_ray(ray).is_some(), visit)
+    }
+
+    fn allocate(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    /// Inserts a leaf for `object` and returns its node.
+    fn insert(&mut self, object: usize, aabb: Aabb) -> usize {
+        let leaf = self.allocate(Node {
+            aabb,
+            parent: None,
+            kind: NodeKind::Leaf(object),
+        });
+
+        let mut sibling = match self.root {
+            Some(root) => root,
+            None => {
+                self.root = Some(leaf);
+                return leaf;
+            }
+        };
+
+        // Descend into the child whose box grows the least
+        while let NodeKind::Branch(children) = self.nodes[sibling].kind {
+            let growth = |child: usize| {
+                let child = &self.nodes[child].aabb;
+                area(&child.union(&aabb)) - area(child)
+            };
+
+            sibling = if growth(children[0]) <= growth(children[1]) {
+                children[0]
+            } else {
+                children[1]
+            };
+        }
+
+        let parent = self.nodes[sibling].parent;
+        let branch = self.allocate(Node {
+            aabb: self.nodes[sibling].aabb.union(&aabb),
+            parent,
+            kind: NodeKind::Branch([sibling, leaf]),
+        });
+
+        self.nodes[sibling].parent = Some(branch);
+        self.nodes[leaf].parent = Some(branch);
+
+        match parent {
+            Some(parent) => {
+                self.replace_child(parent, sibling, branch);
+                self.refit(parent);
+            }
+            None => self.root = Some(branch),
+        }
+
+        leaf
+    }
+
+    /// Removes a leaf and replaces its parent with its sibling.
+    fn remove(&mut self, leaf: usize) {
+        self.free.push(leaf);
+
+        let parent = match self.nodes[leaf].parent {
+            Some(parent) => parent,
+            None => {
+                self.root = None;
+                return;
+            }
+        };
+
+        let sibling = match self.nodes[parent].kind {
+            NodeKind::Branch([a, b]) if a == leaf => b,
+            NodeKind::Branch([a, _]) => a,
+            NodeKind::Leaf(_) => unreachable!("Parent of a node is a leaf"),
+        };
+
+        let grandparent = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grandparent;
+        self.free.push(parent);
+
+        match grandparent {
+            Some(grandparent) => {
+                self.replace_child(grandparent, parent, sibling);
+                self.refit(grandparent);
+            }
+            None => self.root = Some(sibling),
+        }
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if let NodeKind::Branch(children) = &mut self.nodes[parent].kind {
+            for child in children.iter_mut().filter(|child| **child == old) {
+                *child = new;
+            }
+        }
+    }
+
+    /// Recomputes the boxes of `index` and its ancestors from their children
+    fn refit(&mut self, index: usize) {
+        let mut current = Some(index);
+
+        while let Some(index) = current {
+            if let NodeKind::Branch([a, b]) = self.nodes[index].kind {
+                self.nodes[index].aabb = self.nodes[a].aabb.union(&self.nodes[b].aabb);
+            }
+
+            current = self.nodes[index].parent;
+        }
+    }
+}
+
+fn fatten(aabb: &Aabb) -> Aabb {
+    let margin = aabb.extents() * 2.0 * FAT_RATIO;
+    Aabb::new(aabb.min - margin, aabb.max + margin)
+}
+
+fn contains(outer: &Aabb, inner: &Aabb) -> bool {
+    outer.contains(inner.min) && outer.contains(inner.max)
+}
+
+/// Returns the surface area of a box
+fn area(aabb: &Aabb) -> f32 {
+    let size = aabb.max - aabb.min;
+    2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+}
@@ -1,31 +1,162 @@
 use ultraviolet::projection;
 use ultraviolet::vec::*;
-use ultraviolet::Mat4;
+use ultraviolet::{Mat3, Mat4, Rotor3};
 
+use crate::aabb::Aabb;
+use crate::frustum::Frustum;
+use crate::layers::Layers;
+use crate::raycast::Ray;
+use crate::vulkan::Extent;
+use crate::Viewport;
+
+/// The parameters the projection matrix is built from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// Vertical field of view in radians
+        fov: f32,
+        aspect_ratio: f32,
+        near: f32,
+        far: f32,
+    },
+    Orthographic {
+        width: f32,
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Projection {
+    fn matrix(&self) -> Mat4 {
+        match *self {
+            Projection::Perspective {
+                fov,
+                aspect_ratio,
+                near,
+                far,
+            } => projection::perspective_vk(fov, aspect_ratio, near, far),
+            Projection::Orthographic {
+                width,
+                height,
+                near,
+                far,
+            } => {
+                let hw = width / 2.0;
+                let hh = height / 2.0;
+                projection::orthographic_vk(-hw, hw, -hh, hh, near, far)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Camera {
     pub position: Vec3,
+    /// Rotates from view space, looking down -Z with Y up, to world space
+    pub rotation: Rotor3,
+    /// Take the aspect ratio from the viewport the camera is drawn into each frame, which keeps
+    /// the proportions correct when the window is resized. Enabled by default.
+    pub auto_aspect: bool,
+    /// Only objects in any of the layers are drawn by the camera. All layers by default.
+    pub layers: Layers,
+    kind: Projection,
     projection: Mat4,
 }
 
 impl Camera {
     /// Creates a new perspective projection camera.
     pub fn perspective(position: Vec3, fov: f32, aspect_ratio: f32, near: f32, far: f32) -> Self {
-        let projection = projection::perspective_vk(fov, aspect_ratio, near, far);
-        Self {
+        Self::new(
             position,
-            projection,
-        }
+            Projection::Perspective {
+                fov,
+                aspect_ratio,
+                near,
+                far,
+            },
+        )
     }
 
     /// Creates a new orthographic projection camera.
     pub fn orthographic(position: Vec3, width: f32, height: f32, near: f32, far: f32) -> Self {
-        let hw = width / 2.0;
-        let hh = height / 2.0;
-        let projection = projection::orthographic_vk(-hw, hw, -hh, hh, near, far);
+        Self::new(
+            position,
+            Projection::Orthographic {
+                width,
+                height,
+                near,
+                far,
+            },
+        )
+    }
+
+    /// Creates a camera from projection parameters.
+    pub fn new(position: Vec3, kind: Projection) -> Self {
         Self {
             position,
-            projection,
+            rotation: Rotor3::identity(),
+            auto_aspect: true,
+            layers: Layers::ALL,
+            kind,
+            projection: kind.matrix(),
+        }
+    }
+
+    /// Sets the aspect ratio and rebuilds the projection. Orthographic cameras keep their
+    /// height and adjust the width.
+    pub fn set_aspect(&mut self, aspect: f32) {
+        match &mut self.kind {
+            Projection::Perspective { aspect_ratio, .. } => *aspect_ratio = aspect,
+            Projection::Orthographic { width, height, .. } => *width = *height * aspect,
         }
+
+        self.projection = self.kind.matrix();
+    }
+
+    /// Returns the width divided by the height of the projection
+    pub fn aspect(&self) -> f32 {
+        match self.kind {
+            Projection::Perspective { aspect_ratio, .. } => aspect_ratio,
+            Projection::Orthographic { width, height, .. } => width / height,
+        }
+    }
+
+    /// Sets the vertical field of view in radians of perspective cameras and rebuilds the
+    /// projection. Does nothing for orthographic cameras.
+    pub fn set_fov(&mut self, new_fov: f32) {
+        if let Projection::Perspective { fov, .. } = &mut self.kind {
+            *fov = new_fov;
+            self.projection = self.kind.matrix();
+        }
+    }
+
+    /// Replaces the projection parameters and rebuilds the projection.
+    pub fn set_projection(&mut self, kind: Projection) {
+        self.kind = kind;
+        self.projection = kind.matrix();
+    }
+
+    /// Returns the parameters of the projection.
+    pub fn projection_kind(&self) -> Projection {
+        self.kind
+    }
+
+    /// Returns a copy with the aspect ratio of the viewport if `auto_aspect` is enabled.
+    pub fn with_viewport_aspect(&self, aspect: f32) -> Self {
+        let mut camera = *self;
+
+        if camera.auto_aspect && (camera.aspect() - aspect).abs() > f32::EPSILON {
+            camera.set_aspect(aspect);
+        }
+
+        camera
+    }
+
+    /// Returns a copy with the aspect ratio of `viewport` drawn into a render area of `extent`
+    /// if `auto_aspect` is enabled.
+    pub fn with_viewport(&self, viewport: &Viewport, extent: Extent) -> Self {
+        self.with_viewport_aspect(viewport.aspect(extent))
     }
 
     /// Return the camera's projection matrix.
@@ -33,8 +164,116 @@ impl Camera {
         self.projection
     }
 
+    /// Returns the approximate fraction of the screen height covered by a sphere at `center`
+    /// with `radius`.
+    pub fn screen_coverage(&self, center: Vec3, radius: f32) -> f32 {
+        // Perspective projections divide by depth, orthographic projections do not
+        let perspective = self.projection.cols[3].w == 0.0;
+
+        let scale = self.projection.cols[1].y.abs();
+
+        if perspective {
+            let distance = (center - self.position).mag();
+            if distance <= radius {
+                return 1.0;
+            }
+
+            radius * scale / distance
+        } else {
+            radius * scale
+        }
+    }
+
+    /// Returns the radius of a sphere at `center` covering `coverage` of the screen height, e.g;
+    /// for drawing overlays at a constant size on screen. The inverse of `screen_coverage`.
+    pub fn world_size(&self, center: Vec3, coverage: f32) -> f32 {
+        let perspective = self.projection.cols[3].w == 0.0;
+
+        let scale = self.projection.cols[1].y.abs();
+
+        if perspective {
+            coverage * (center - self.position).mag() / scale
+        } else {
+            coverage / scale
+        }
+    }
+
+    /// Rotates the camera to face `target`. `up` is the world direction which will appear
+    /// upwards, and can not be parallel to the direction of `target`.
+    /// Does nothing if `target` is at the camera position.
+    pub fn look_at(&mut self, target: Vec3, up: Vec3) {
+        let forward = target - self.position;
+        if forward.mag_sq() == 0.0 {
+            return;
+        }
+
+        self.look_in(forward, up)
+    }
+
+    /// Rotates the camera to face along `direction`. See `look_at`.
+    pub fn look_in(&mut self, direction: Vec3, up: Vec3) {
+        let forward = direction.normalized();
+        let right = forward.cross(up).normalized();
+        let up = right.cross(forward);
+
+        self.rotation = Mat3::new(right, up, -forward).into_rotor3().normalized();
+    }
+
+    /// Returns the world direction the camera is facing
+    pub fn forward(&self) -> Vec3 {
+        self.rotation * -Vec3::unit_z()
+    }
+
+    /// Returns the world direction to the right of the camera
+    pub fn right(&self) -> Vec3 {
+        self.rotation * Vec3::unit_x()
+    }
+
+    /// Returns the world direction upwards from the camera
+    pub fn up(&self) -> Vec3 {
+        self.rotation * Vec3::unit_y()
+    }
+
+    /// Moves the camera backwards along its current direction until the bounding sphere of
+    /// `bounds` fits in view. Orthographic cameras only move in front of the bounds.
+    pub fn frame_bounds(&mut self, bounds: &Aabb) {
+        let radius = bounds.extents().mag();
+
+        let distance = match self.kind {
+            Projection::Perspective {
+                fov,
+                aspect_ratio,
+                near,
+                ..
+            } => {
+                let horizontal = 2.0 * ((fov * 0.5).tan() * aspect_ratio).atan();
+                let half_fov = fov.min(horizontal) * 0.5;
+                (radius / half_fov.sin()).max(radius + near)
+            }
+            Projection::Orthographic { near, .. } => radius + near,
+        };
+
+        self.position = bounds.center() - self.forward() * distance;
+    }
+
+    /// Returns the world space ray through `ndc` in normalized device coordinates, where
+    /// (-1, -1) is the top left corner of the viewport. The ray starts at the near plane.
+    pub fn screen_ray(&self, ndc: Vec2) -> Ray {
+        let inverse = (self.projection * self.calculate_view()).inversed();
+        let near = inverse.transform_point3(Vec3::new(ndc.x, ndc.y, 0.0));
+        let far = inverse.transform_point3(Vec3::new(ndc.x, ndc.y, 1.0));
+
+        Ray::new(near, far - near)
+    }
+
+    /// Returns the world space volume visible through the camera
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&(self.projection * self.calculate_view()))
+    }
+
     /// Calculates the cameras view matrix
     pub fn calculate_view(&self) -> Mat4 {
-        Mat4::from_translation(self.position).inversed()
+        self.rotation.reversed().into_matrix().into_homogeneous()
+            * Mat4::from_translation(-self.position)
     }
 }
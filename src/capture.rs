@@ -0,0 +1,485 @@
+//! Captures the presented frames to numbered images or an external encoder process, e.g; for
+//! rendering videos of simulations offline.
+//!
+//! Each captured frame is copied from the swapchain image into a readback buffer of its frame in
+//! flight and written out once the frame is waited on again, so capturing does not stall the
+//! device. The application is expected to advance by `CaptureInfo::timestep` each frame instead
+//! of the measured frame time, which makes the output independent of how long frames take.
+use ash::vk;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+use thiserror::Error;
+
+use super::vulkan;
+use vulkan::commands::CommandBuffer;
+use vulkan::{device, Buffer, BufferType, BufferUsage, Extent, Texture, VulkanContext};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Vulkan(#[from] vulkan::Error),
+    #[error("Failed to write {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("Failed to encode {path:?}: {source}")]
+    Png {
+        path: PathBuf,
+        source: png::EncodingError,
+    },
+    #[error("Failed to run the encoder {program:?}: {source}")]
+    Encoder { program: String, source: io::Error },
+    #[error("Frames of format {0:?} can not be captured")]
+    UnsupportedFormat(vk::Format),
+}
+
+/// Where the captured frames are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureOutput {
+    /// Writes each frame as a PNG image named `<prefix>_<number>.png` into `directory`, which
+    /// is created if it does not exist. Frames are numbered from 0 in the order captured.
+    Images { directory: PathBuf, prefix: String },
+    /// Pipes the raw 8 bit RGBA pixels of each frame to the standard input of `program`, which
+    /// is started on the first captured frame. `{width}`, `{height}` and `{rate}` in `args` are
+    /// replaced by the frame size and the frames per second of the captured frames, e.g;
+    /// `ffmpeg -f rawvideo -pixel_format rgba -video_size {width}x{height} -framerate {rate}
+    /// -i - out.mp4`
+    Encoder { program: String, args: Vec<String> },
+}
+
+/// Specifies how frames are captured
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureInfo {
+    pub output: CaptureOutput,
+    /// Captures every nth frame, e.g; 2 for every other frame
+    pub interval: u32,
+    /// The fixed time in seconds the application advances by each frame
+    pub timestep: f32,
+    /// Stops capturing after this many frames have been captured if Some
+    pub frame_count: Option<u32>,
+}
+
+impl Default for CaptureInfo {
+    fn default() -> Self {
+        Self {
+            output: CaptureOutput::Images {
+                directory: "captures".into(),
+                prefix: "frame".into(),
+            },
+            interval: 1,
+            timestep: 1.0 / 60.0,
+            frame_count: None,
+        }
+    }
+}
+
+impl CaptureInfo {
+    /// Returns the frames per second of the captured frames
+    pub fn rate(&self) -> f32 {
+        1.0 / (self.timestep * self.interval.max(1) as f32)
+    }
+}
+
+/// A frame copied into a readback buffer which is yet to be written out
+struct PendingFrame {
+    number: u32,
+    extent: Extent,
+    format: vk::Format,
+}
+
+#[derive(Default)]
+struct Readback {
+    /// Grown to fit the largest frame
+    buffer: Option<Buffer>,
+    pending: Option<PendingFrame>,
+}
+
+/// The encoder process and the size of the frames piped to it
+struct Encoder {
+    child: Child,
+    extent: Extent,
+}
+
+/// Captures the frames drawn by the master renderer. See `MasterRenderer::set_capture`.
+pub struct Capture {
+    context: Rc<VulkanContext>,
+    info: CaptureInfo,
+    /// One for each frame in flight
+    readbacks: Vec<Readback>,
+    /// None until the first frame is written, or when writing images
+    encoder: Option<Encoder>,
+    /// The number of frames drawn since capturing started
+    frame: u64,
+    /// The number of frames copied, which numbers the next captured frame
+    captured: u32,
+}
+
+impl Capture {
+    pub fn new(
+        context: Rc<VulkanContext>,
+        info: CaptureInfo,
+        frames_in_flight: usize,
+    ) -> Result<Self, Error> {
+        if let CaptureOutput::Images { directory, .. } = &info.output {
+            fs::create_dir_all(directory).map_err(|source| Error::Io {
+                path: directory.clone(),
+                source,
+            })?;
+        }
+
+        Ok(Self {
+            context,
+            info,
+            readbacks: (0..frames_in_flight).map(|_| Readback::default()).collect(),
+            encoder: None,
+            frame: 0,
+            captured: 0,
+        })
+    }
+
+    pub fn info(&self) -> &CaptureInfo {
+        &self.info
+    }
+
+    /// Returns the fixed time in seconds the application advances by each frame
+    pub fn timestep(&self) -> f32 {
+        self.info.timestep
+    }
+
+    /// Returns the number of frames captured so far
+    pub fn captured(&self) -> u32 {
+        self.captured
+    }
+
+    /// Returns true if `CaptureInfo::frame_count` frames have been captured
+    pub fn is_finished(&self) -> bool {
+        self.info
+            .frame_count
+            .is_some_and(|frame_count| self.captured >= frame_count)
+    }
+
+    /// Writes out the frame previously copied by frame in flight `frame_index`. Must be called
+    /// after the frame has been waited on and before it is recorded again.
+    pub fn collect(&mut self, frame_index: usize) -> Result<(), Error> {
+        let readback = &mut self.readbacks[frame_index];
+
+        let frame = match readback.pending.take() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        let len = frame.extent.width as vk::DeviceSize * frame.extent.height as vk::DeviceSize * 4;
+
+        let pixels = readback
+            .buffer
+            .as_ref()
+            .expect("Pending frame without a readback buffer")
+            .read_slice(len, 0, |pixels: &[u8]| to_rgba(frame.format, pixels))?;
+
+        self.write(&frame, &pixels)
+    }
+
+    /// Records copying `image`, the swapchain image in the present layout, into the readback
+    /// buffer of frame in flight `frame_index` if the frame is to be captured. The image
+    /// is left in the present layout.
+    pub fn record(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        frame_index: usize,
+        image: &Texture,
+    ) -> Result<(), Error> {
+        let frame = self.frame;
+        self.frame += 1;
+
+        if self.is_finished() || !frame.is_multiple_of(self.info.interval.max(1) as u64) {
+            return Ok(());
+        }
+
+        let format = image.format();
+        if !is_supported(format) {
+            return Err(Error::UnsupportedFormat(format));
+        }
+
+        let extent = image.extent();
+        let size = extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * 4;
+
+        let readback = &mut self.readbacks[frame_index];
+
+        if readback
+            .buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.size() < size)
+        {
+            readback.buffer = Some(Buffer::new_uninit(
+                self.context.clone(),
+                BufferType::Readback,
+                BufferUsage::Mapped,
+                size,
+            )?);
+        }
+
+        let buffer = readback.buffer.as_ref().unwrap();
+
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        commandbuffer.pipeline_barrier(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            &[vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                old_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: image.image(),
+                subresource_range: range,
+                ..Default::default()
+            }],
+        );
+
+        commandbuffer.copy_image_buffer(
+            image.image(),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer.buffer(),
+            &[vk::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image_offset: vk::Offset3D::default(),
+                image_extent: vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                },
+            }],
+        );
+
+        // Presentation waits on the semaphore signaled after submission, which needs no access
+        // mask
+        commandbuffer.pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            &[vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::TRANSFER_READ,
+                dst_access_mask: vk::AccessFlags::empty(),
+                old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: image.image(),
+                subresource_range: range,
+                ..Default::default()
+            }],
+        );
+
+        commandbuffer.buffer_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            &[vk::BufferMemoryBarrier {
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::HOST_READ,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                buffer: buffer.buffer(),
+                offset: 0,
+                size,
+                ..Default::default()
+            }],
+        );
+
+        readback.pending = Some(PendingFrame {
+            number: self.captured,
+            extent,
+            format,
+        });
+
+        self.captured += 1;
+
+        Ok(())
+    }
+
+    /// Waits for the device to become idle and writes out the frames still in flight, then
+    /// closes the encoder and waits for it to exit.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        device::wait_idle(self.context.device())?;
+
+        // Write the frames in the order they were captured
+        let mut frame_indices = (0..self.readbacks.len())
+            .filter(|&index| self.readbacks[index].pending.is_some())
+            .collect::<Vec<_>>();
+
+        frame_indices.sort_by_key(|&index| self.readbacks[index].pending.as_ref().unwrap().number);
+
+        for frame_index in frame_indices {
+            self.collect(frame_index)?;
+        }
+
+        if let Some(mut encoder) = self.encoder.take() {
+            let program = self.program().to_owned();
+
+            // Closing standard input ends the stream
+            drop(encoder.child.stdin.take());
+
+            let status = encoder.child.wait().map_err(|source| Error::Encoder {
+                program: program.clone(),
+                source,
+            })?;
+
+            if !status.success() {
+                log::warn!("Encoder {:?} exited with {}", program, status);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, frame: &PendingFrame, pixels: &[u8]) -> Result<(), Error> {
+        match &self.info.output {
+            CaptureOutput::Images { directory, prefix } => {
+                let path = directory.join(format!("{}_{:06}.png", prefix, frame.number));
+                write_png(&path, frame.extent, pixels)
+            }
+            CaptureOutput::Encoder { program, args } => {
+                if self.encoder.is_none() {
+                    self.encoder = Some(spawn_encoder(
+                        program,
+                        args,
+                        frame.extent,
+                        self.info.rate(),
+                    )?);
+                }
+
+                let encoder = self.encoder.as_mut().unwrap();
+
+                // The encoder reads frames of a fixed size
+                if encoder.extent != frame.extent {
+                    log::warn!(
+                        "Skipping frame {} of size {:?}, the encoder expects {:?}",
+                        frame.number,
+                        frame.extent,
+                        encoder.extent
+                    );
+                    return Ok(());
+                }
+
+                encoder
+                    .child
+                    .stdin
+                    .as_mut()
+                    .expect("Encoder without standard input")
+                    .write_all(pixels)
+                    .map_err(|source| Error::Encoder {
+                        program: program.clone(),
+                        source,
+                    })
+            }
+        }
+    }
+
+    fn program(&self) -> &str {
+        match &self.info.output {
+            CaptureOutput::Encoder { program, .. } => program,
+            CaptureOutput::Images { .. } => "",
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::error!("Failed to finish capture: {}", e);
+        }
+    }
+}
+
+/// Returns true if frames of `format` can be captured
+pub fn is_supported(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8G8B8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::B8G8R8A8_UNORM
+            | vk::Format::B8G8R8A8_SRGB
+    )
+}
+
+/// Converts the pixels of a frame of `format` to RGBA, which needs to be supported. The frames
+/// are presented opaque, so the alpha is discarded.
+pub fn to_rgba(format: vk::Format, pixels: &[u8]) -> Vec<u8> {
+    let swizzle = matches!(
+        format,
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB
+    );
+
+    let mut pixels = pixels.to_vec();
+
+    for pixel in pixels.chunks_exact_mut(4) {
+        if swizzle {
+            pixel.swap(0, 2);
+        }
+
+        pixel[3] = 255;
+    }
+
+    pixels
+}
+
+/// Writes `extent` sized 8 bit RGBA pixels to a PNG image
+pub fn write_png(path: &Path, extent: Extent, pixels: &[u8]) -> Result<(), Error> {
+    let file = File::create(path).map_err(|source| Error::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), extent.width, extent.height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(pixels))
+        .map_err(|source| Error::Png {
+            path: path.to_owned(),
+            source,
+        })
+}
+
+fn spawn_encoder(
+    program: &str,
+    args: &[String],
+    extent: Extent,
+    rate: f32,
+) -> Result<Encoder, Error> {
+    let args = args.iter().map(|arg| {
+        arg.replace("{width}", &extent.width.to_string())
+            .replace("{height}", &extent.height.to_string())
+            .replace("{rate}", &rate.to_string())
+    });
+
+    let child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|source| Error::Encoder {
+            program: program.to_owned(),
+            source,
+        })?;
+
+    log::info!("Started encoder {:?} for {:?} frames", program, extent);
+
+    Ok(Encoder { child, extent })
+}
@@ -190,8 +190,8 @@ impl Color {
 
         Self {
             r: percent_to_byte(hue_to_rgb(p, q, h + 1.0 / 3.0)),
-            b: percent_to_byte(hue_to_rgb(p, q, h)),
-            g: percent_to_byte(hue_to_rgb(p, q, h - 1.0 / 3.0)),
+            g: percent_to_byte(hue_to_rgb(p, q, h)),
+            b: percent_to_byte(hue_to_rgb(p, q, h - 1.0 / 3.0)),
             a: percent_to_byte(a),
         }
     }
@@ -221,7 +221,7 @@ impl Color {
         let delta: f32 = max - min;
         if delta == 0.0 {
             // it's gray
-            return (0.0, 0.0, 1.0, byte_to_percent(self.a));
+            return (0.0, 0.0, l, byte_to_percent(self.a));
         }
 
         // it's not gray
@@ -259,6 +259,8 @@ impl Color {
         [self.r, self.g, self.b, self.a]
     }
 
+    /// Returns the sRGB encoded channels in range 0..1, i.e; without conversion to linear.
+    /// Suitable for writing to sRGB textures and attachments.
     pub fn to_array_f32(&self) -> [f32; 4] {
         [
             byte_to_percent(self.r),
@@ -268,6 +270,87 @@ impl Color {
         ]
     }
 
+    /// Linearly interpolates between `self` and `other` in RGB space. `t` is clamped to 0..1.
+    /// Interpolation is done on the linear channels to avoid darkened transitions.
+    pub fn lerp(&self, other: Color, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let a = self.to_linear();
+        let b = other.to_linear();
+
+        Self::from_linear(
+            a[0] + (b[0] - a[0]) * t,
+            a[1] + (b[1] - a[1]) * t,
+            a[2] + (b[2] - a[2]) * t,
+            a[3] + (b[3] - a[3]) * t,
+        )
+    }
+
+    /// Interpolates between `self` and `other` in HSL space along the shortest hue arc.
+    /// `t` is clamped to 0..1. Preserves saturation better than `lerp`, e.g; for rainbows.
+    pub fn lerp_hsl(&self, other: Color, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let (mut h0, s0, l0, a0) = self.to_hsla();
+        let (mut h1, s1, l1, a1) = other.to_hsla();
+
+        // Grays have no hue and take the hue of the other color
+        if s0 == 0.0 {
+            h0 = h1;
+        } else if s1 == 0.0 {
+            h1 = h0;
+        }
+
+        // Take the shortest way around the hue circle
+        let mut delta = h1 - h0;
+        if delta > 180.0 {
+            delta -= 360.0;
+        } else if delta < -180.0 {
+            delta += 360.0;
+        }
+
+        let h = (h0 + delta * t).rem_euclid(360.0);
+
+        Self::hsla(
+            h,
+            s0 + (s1 - s0) * t,
+            l0 + (l1 - l0) * t,
+            a0 + (a1 - a0) * t,
+        )
+    }
+
+    /// Constructs a color from linear channels in range 0..1 by sRGB encoding them. Alpha is
+    /// always linear.
+    pub fn from_linear(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self {
+            r: percent_to_byte(linear_to_srgb(r)),
+            g: percent_to_byte(linear_to_srgb(g)),
+            b: percent_to_byte(linear_to_srgb(b)),
+            a: percent_to_byte(a.clamp(0.0, 1.0)),
+        }
+    }
+
+    /// Returns the linear channels in range 0..1. Colors passed to shaders through buffers,
+    /// push constants or clear values of UNORM or floating point attachments need to be linear
+    /// since blending and lighting is done in linear space. Alpha is always linear.
+    pub fn to_linear(&self) -> [f32; 4] {
+        [
+            srgb_to_linear(byte_to_percent(self.r)),
+            srgb_to_linear(byte_to_percent(self.g)),
+            srgb_to_linear(byte_to_percent(self.b)),
+            byte_to_percent(self.a),
+        ]
+    }
+
+    /// Returns the linear channels as a vector. See `to_linear`.
+    pub fn to_linear_vec4(&self) -> Vec4 {
+        self.to_linear().into()
+    }
+
+    /// Returns the linear color channels as a vector. See `to_linear`.
+    pub fn to_linear_vec3(&self) -> Vec3 {
+        let [r, g, b, _] = self.to_linear();
+        Vec3::new(r, g, b)
+    }
+
     // Converts color to R32G32B32A32 vector in range 0..1
     pub fn to_vec4(&self) -> Vec4 {
         Vec4::new(
@@ -298,6 +381,98 @@ impl Display for Color {
     }
 }
 
+impl From<Color> for Vec4 {
+    fn from(color: Color) -> Self {
+        color.to_vec4()
+    }
+}
+
+impl From<Color> for Vec3 {
+    fn from(color: Color) -> Self {
+        color.to_vec3()
+    }
+}
+
+/// The space colors of a gradient are interpolated in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// See `Color::lerp`
+    Rgb,
+    /// See `Color::lerp_hsl`
+    Hsl,
+}
+
+/// Colors keyed at positions which are interpolated between, e.g; for particle colors over
+/// their lifetime or sky colors over the height.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    /// Sorted by position
+    stops: Vec<(f32, Color)>,
+    interpolation: Interpolation,
+}
+
+impl Gradient {
+    /// Creates a gradient interpolating in RGB space from `(position, color)` stops in any
+    /// order.
+    pub fn new(stops: &[(f32, Color)]) -> Self {
+        Self::new_with_interpolation(stops, Interpolation::Rgb)
+    }
+
+    /// Creates a gradient interpolating in the given space.
+    pub fn new_with_interpolation(stops: &[(f32, Color)], interpolation: Interpolation) -> Self {
+        let mut gradient = Self {
+            stops: Vec::with_capacity(stops.len()),
+            interpolation,
+        };
+
+        for (position, color) in stops {
+            gradient.add_stop(*position, *color);
+        }
+
+        gradient
+    }
+
+    /// Inserts a stop, keeping the stops sorted. Stops at an existing position are placed
+    /// after it, which creates a hard edge.
+    pub fn add_stop(&mut self, position: f32, color: Color) {
+        let index = self
+            .stops
+            .iter()
+            .position(|(p, _)| *p > position)
+            .unwrap_or(self.stops.len());
+
+        self.stops.insert(index, (position, color));
+    }
+
+    /// Returns the color at `position`. Positions outside the stops take the color of the
+    /// nearest stop. Returns transparent black if the gradient has no stops.
+    pub fn sample(&self, position: f32) -> Color {
+        let next = self.stops.iter().position(|(p, _)| *p > position);
+
+        let (start, end) = match next {
+            Some(0) => return self.stops[0].1,
+            Some(next) => (self.stops[next - 1], self.stops[next]),
+            None => return self.stops.last().map(|stop| stop.1).unwrap_or_default(),
+        };
+
+        let t = (position - start.0) / (end.0 - start.0);
+
+        match self.interpolation {
+            Interpolation::Rgb => start.1.lerp(end.1, t),
+            Interpolation::Hsl => start.1.lerp_hsl(end.1, t),
+        }
+    }
+
+    /// Returns the stops sorted by position
+    pub fn stops(&self) -> &[(f32, Color)] {
+        &self.stops
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+}
+
 impl FromStr for Color {
     type Err = ColorParseError;
 
@@ -306,6 +481,26 @@ impl FromStr for Color {
     }
 }
 
+/// Converts an sRGB encoded channel in range 0..1 to linear.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a linear channel to sRGB encoding. The value is clamped to 0..1.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
 // Helper functions
 fn byte_to_percent(a: u8) -> f32 {
     (a as f32) / 255.0
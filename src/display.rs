@@ -0,0 +1,113 @@
+//! Switching the window between windowed and fullscreen modes at runtime.
+use crate::Error;
+
+/// How the window covers the display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayMode {
+    Windowed,
+    /// An undecorated window covering the primary monitor. Switching to and from other windows
+    /// is instant.
+    Borderless,
+    /// Fullscreen on the primary monitor using its current video mode. Presentation may bypass
+    /// the compositor.
+    Exclusive,
+}
+
+impl DisplayMode {
+    /// Returns the mode after self when cycling through all modes
+    pub fn next(&self) -> Self {
+        match self {
+            DisplayMode::Windowed => DisplayMode::Borderless,
+            DisplayMode::Borderless => DisplayMode::Exclusive,
+            DisplayMode::Exclusive => DisplayMode::Windowed,
+        }
+    }
+}
+
+/// Tracks the display mode of a window and restores the windowed position and size when
+/// leaving fullscreen.
+pub struct Display {
+    mode: DisplayMode,
+    /// Position and size of the window before leaving windowed mode
+    windowed: (i32, i32, u32, u32),
+}
+
+impl Display {
+    /// Creates a display for a window created in windowed mode.
+    pub fn new(window: &glfw::Window) -> Self {
+        Self {
+            mode: DisplayMode::Windowed,
+            windowed: windowed_rect(window),
+        }
+    }
+
+    /// Moves and resizes the window according to `mode`. The swapchain needs to be recreated
+    /// afterwards. See `MasterRenderer::set_display_mode`.
+    pub fn set_mode(
+        &mut self,
+        glfw: &mut glfw::Glfw,
+        window: &mut glfw::Window,
+        mode: DisplayMode,
+    ) -> Result<(), Error> {
+        if mode == self.mode {
+            return Ok(());
+        }
+
+        if self.mode == DisplayMode::Windowed {
+            self.windowed = windowed_rect(window);
+        }
+
+        if mode == DisplayMode::Windowed {
+            let (x, y, width, height) = self.windowed;
+            window.set_decorated(true);
+            window.set_monitor(glfw::WindowMode::Windowed, x, y, width, height, None);
+            self.mode = mode;
+            return Ok(());
+        }
+
+        glfw.with_primary_monitor_mut(|_, monitor| {
+            let monitor = monitor.ok_or(Error::NoMonitor)?;
+            let video_mode = monitor.get_video_mode().ok_or(Error::NoMonitor)?;
+
+            match mode {
+                DisplayMode::Borderless => {
+                    let (x, y) = monitor.get_pos();
+                    window.set_decorated(false);
+                    window.set_monitor(
+                        glfw::WindowMode::Windowed,
+                        x,
+                        y,
+                        video_mode.width,
+                        video_mode.height,
+                        None,
+                    );
+                }
+                DisplayMode::Exclusive => window.set_monitor(
+                    glfw::WindowMode::FullScreen(monitor),
+                    0,
+                    0,
+                    video_mode.width,
+                    video_mode.height,
+                    Some(video_mode.refresh_rate),
+                ),
+                DisplayMode::Windowed => unreachable!(),
+            }
+
+            Ok::<_, Error>(())
+        })?;
+
+        self.mode = mode;
+        Ok(())
+    }
+
+    pub fn mode(&self) -> DisplayMode {
+        self.mode
+    }
+}
+
+fn windowed_rect(window: &glfw::Window) -> (i32, i32, u32, u32) {
+    let (x, y) = window.get_pos();
+    let (width, height) = window.get_size();
+
+    (x, y, width as u32, height as u32)
+}
@@ -1,25 +1,165 @@
+use super::animation::Animation;
+use super::camera::{Camera, Projection};
+use super::layers::Layers;
 use super::resources::*;
-use super::Mesh;
+use super::{Material, MaterialInfo, Mesh, Object, Scene};
 use ultraviolet::*;
 
+/// A decomposed node transform
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub position: Vec3,
+    pub rotation: Rotor3,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    /// Returns the transform of `child` placed relative to self. Shear from non uniform scales
+    /// combined with rotated children is discarded.
+    pub fn then(&self, child: &Transform) -> Transform {
+        Transform {
+            position: self.position + self.rotation * (self.scale * child.position),
+            rotation: self.rotation * child.rotation,
+            scale: self.scale * child.scale,
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vec3::zero(),
+            rotation: Rotor3::identity(),
+            scale: Vec3::one(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Node {
     /// The name of this node.
     name: String,
     /// The mesh index references by this node.
     mesh: Option<usize>,
-    position: Vec3,
-    rotation: Rotor3,
-    scale: Vec3,
+    /// The camera index referenced by this node.
+    camera: Option<usize>,
+    /// The light index referenced by this node.
+    light: Option<usize>,
+    children: Vec<usize>,
+    /// The transform relative to the parent node
+    transform: Transform,
+}
+
+impl Node {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mesh(&self) -> Option<usize> {
+        self.mesh
+    }
+
+    pub fn camera(&self) -> Option<usize> {
+        self.camera
+    }
+
+    pub fn light(&self) -> Option<usize> {
+        self.light
+    }
+
+    pub fn children(&self) -> &[usize] {
+        &self.children
+    }
+
+    /// Returns the transform relative to the parent node
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+}
+
+/// A camera defined in a document, placed by the nodes referencing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentCamera {
+    pub name: String,
+    pub projection: Projection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    /// Infinitely far away light shining along the local -Z axis of the node
+    Directional,
+    /// Light emitted in all directions from the node position
+    Point,
+    /// Light emitted in a cone along the local -Z axis of the node. Angles are in radians.
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+/// A light defined by the KHR_lights_punctual extension, placed by the nodes referencing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Light {
+    pub name: String,
+    /// Linear RGB color
+    pub color: Vec3,
+    /// Candela for point and spot lights, lux for directional lights
+    pub intensity: f32,
+    /// The distance at which the light reaches zero intensity. Infinite if None.
+    pub range: Option<f32>,
+    pub kind: LightKind,
+}
+
+/// The textures and emission of a glTF material. Textures are given by the names they were
+/// loaded as into the resource manager.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentMaterial {
+    pub name: String,
+    pub albedo: Option<String>,
+    pub emissive: Option<String>,
+    /// Linear RGB color
+    pub emissive_factor: Vec3,
+}
+
+impl DocumentMaterial {
+    /// Returns the info for loading the material with `effect`. `default_albedo` is used if the
+    /// material has no base color texture. The effect needs to support emission if the material
+    /// emits anything.
+    pub fn material_info(&self, effect: &str, default_albedo: &str) -> MaterialInfo {
+        MaterialInfo {
+            effect: effect.to_owned(),
+            albedo: self
+                .albedo
+                .clone()
+                .unwrap_or_else(|| default_albedo.to_owned()),
+            emissive: self.emissive.clone(),
+            emissive_factor: self.emissive_factor,
+            ..Default::default()
+        }
+    }
 }
 
 pub struct Document {
     meshes: Vec<Handle<Mesh>>,
     nodes: Vec<Node>,
+    /// Nodes without a parent
+    roots: Vec<usize>,
+    cameras: Vec<DocumentCamera>,
+    lights: Vec<Light>,
+    materials: Vec<DocumentMaterial>,
+    animations: Vec<Animation>,
 }
 
 impl Document {
-    pub fn from_gltf(document: gltf::Document, meshes: Vec<Handle<Mesh>>) -> Self {
+    /// Creates a document from an imported glTF document. `meshes` contains the loaded mesh of
+    /// each glTF mesh in order, and `buffers` the imported buffer data used for animations.
+    /// `textures` contains the name of the loaded texture of each glTF image, if any.
+    pub fn from_gltf(
+        document: gltf::Document,
+        buffers: &[gltf::buffer::Data],
+        meshes: Vec<Handle<Mesh>>,
+        textures: &[Option<String>],
+    ) -> Self {
         let nodes = document
             .nodes()
             .map(|node| {
@@ -27,14 +167,103 @@ impl Document {
                 Node {
                     name: node.name().unwrap_or_default().to_owned(),
                     mesh: node.mesh().map(|mesh| mesh.index()),
-                    position: Vec3::from(position),
-                    rotation: Rotor3::from_quaternion_array(rotation),
-                    scale: Vec3::from(scale),
+                    camera: node.camera().map(|camera| camera.index()),
+                    light: node.light().map(|light| light.index()),
+                    children: node.children().map(|child| child.index()).collect(),
+                    transform: Transform {
+                        position: Vec3::from(position),
+                        rotation: Rotor3::from_quaternion_array(rotation),
+                        scale: Vec3::from(scale),
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut has_parent = vec![false; nodes.len()];
+        for child in nodes.iter().flat_map(|node| node.children.iter()) {
+            has_parent[*child] = true;
+        }
+
+        let roots = (0..nodes.len()).filter(|i| !has_parent[*i]).collect();
+
+        let cameras = document
+            .cameras()
+            .map(|camera| DocumentCamera {
+                name: camera.name().unwrap_or_default().to_owned(),
+                projection: match camera.projection() {
+                    gltf::camera::Projection::Perspective(perspective) => Projection::Perspective {
+                        fov: perspective.yfov(),
+                        aspect_ratio: perspective.aspect_ratio().unwrap_or(1.0),
+                        near: perspective.znear(),
+                        far: perspective.zfar().unwrap_or(1000.0),
+                    },
+                    // Magnifications are half the extents
+                    gltf::camera::Projection::Orthographic(orthographic) => {
+                        Projection::Orthographic {
+                            width: orthographic.xmag() * 2.0,
+                            height: orthographic.ymag() * 2.0,
+                            near: orthographic.znear(),
+                            far: orthographic.zfar(),
+                        }
+                    }
+                },
+            })
+            .collect();
+
+        let lights = document
+            .lights()
+            .into_iter()
+            .flatten()
+            .map(|light| {
+                use gltf::khr_lights_punctual::Kind;
+
+                Light {
+                    name: light.name().unwrap_or_default().to_owned(),
+                    color: Vec3::from(light.color()),
+                    intensity: light.intensity(),
+                    range: light.range(),
+                    kind: match light.kind() {
+                        Kind::Directional => LightKind::Directional,
+                        Kind::Point => LightKind::Point,
+                        Kind::Spot {
+                            inner_cone_angle,
+                            outer_cone_angle,
+                        } => LightKind::Spot {
+                            inner_cone_angle,
+                            outer_cone_angle,
+                        },
+                    },
                 }
             })
             .collect();
 
-        Self { nodes, meshes }
+        let texture_name =
+            |info: Option<gltf::texture::Info>| textures[info?.texture().source().index()].clone();
+
+        let materials = document
+            .materials()
+            .map(|material| DocumentMaterial {
+                name: material.name().unwrap_or_default().to_owned(),
+                albedo: texture_name(material.pbr_metallic_roughness().base_color_texture()),
+                emissive: texture_name(material.emissive_texture()),
+                emissive_factor: Vec3::from(material.emissive_factor()),
+            })
+            .collect();
+
+        let animations = document
+            .animations()
+            .map(|animation| Animation::from_gltf(animation, buffers))
+            .collect();
+
+        Self {
+            nodes,
+            meshes,
+            roots,
+            cameras,
+            lights,
+            materials,
+            animations,
+        }
     }
 
     /// Returns a handle to the mesh at index.
@@ -47,6 +276,10 @@ impl Document {
         &self.nodes[index]
     }
 
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
     /// Searches for the node with name.
     pub fn find_node<S>(&self, name: S) -> Option<&Node>
     where
@@ -55,4 +288,104 @@ impl Document {
         let name = name.as_ref();
         self.nodes.iter().find(|node| node.name == name)
     }
+
+    pub fn cameras(&self) -> &[DocumentCamera] {
+        &self.cameras
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// Returns the materials of the document in glTF order.
+    pub fn materials(&self) -> &[DocumentMaterial] {
+        &self.materials
+    }
+
+    pub fn animations(&self) -> &[Animation] {
+        &self.animations
+    }
+
+    /// Searches for the animation with name.
+    pub fn find_animation<S>(&self, name: S) -> Option<usize>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+        self.animations
+            .iter()
+            .position(|animation| animation.name() == name)
+    }
+
+    /// Returns the transform of each node relative to its parent
+    pub fn local_transforms(&self) -> Vec<Transform> {
+        self.nodes.iter().map(|node| node.transform).collect()
+    }
+
+    /// Returns the world transform of each node from the local transforms of all nodes, e.g;
+    /// as sampled from an animation.
+    pub fn world_transforms(&self, local: &[Transform]) -> Vec<Transform> {
+        let mut world = local.to_vec();
+        let mut stack = self
+            .roots
+            .iter()
+            .map(|root| (*root, Transform::default()))
+            .collect::<Vec<_>>();
+
+        while let Some((node, parent)) = stack.pop() {
+            world[node] = parent.then(&local[node]);
+
+            for child in &self.nodes[node].children {
+                stack.push((*child, world[node]));
+            }
+        }
+
+        world
+    }
+
+    /// Creates a camera placed at the world transform of the node at index, if the node
+    /// references a camera.
+    pub fn camera(&self, node: usize) -> Option<Camera> {
+        let index = self.nodes[node].camera?;
+        let transform = self.world_transforms(&self.local_transforms())[node];
+
+        let mut camera = Camera::new(transform.position, self.cameras[index].projection);
+        camera.rotation = transform.rotation;
+
+        Some(camera)
+    }
+
+    /// Adds an object to the scene for each node with a mesh, placed at the world transform of
+    /// the node in the default layer. Returns pairs of node and object indices, which can be
+    /// used to animate the objects with an `AnimationPlayer`.
+    pub fn instantiate(
+        &self,
+        scene: &mut Scene,
+        material: Handle<Material>,
+        is_static: bool,
+    ) -> Vec<(usize, usize)> {
+        let transforms = self.world_transforms(&self.local_transforms());
+
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, node)| Some((i, node.mesh?)))
+            .map(|(i, mesh)| {
+                let transform = transforms[i];
+
+                scene.add(Object {
+                    material,
+                    mesh: self.meshes[mesh],
+                    lods: None,
+                    position: transform.position,
+                    rotation: transform.rotation,
+                    scale: transform.scale,
+                    is_static,
+                    layers: Layers::DEFAULT,
+                });
+
+                (i, scene.objects().len() - 1)
+            })
+            .collect()
+    }
 }
@@ -0,0 +1,360 @@
+//! Optional sparse set component storage, as an alternative to the fixed fields of `Object`.
+//! Entities are plain ids, and each component type is stored densely in its own `SparseSet`, so
+//! iterating a component touches only the entities which have it. Gameplay systems can attach
+//! any `Component` of their own.
+//!
+//! The scene mirrors the entities with a `Transform`, `Handle<Mesh>` and `Handle<Material>` into
+//! its objects, and those with a `Light` into its lights, see `Scene::sync_world`.
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// Any type stored as a component. Components are required to be Send and Sync like the rest of
+/// the scene.
+pub trait Component: Send + Sync + 'static {}
+
+impl<T: Send + Sync + 'static> Component for T {}
+
+/// Marks an entity as static, see `Object::is_static`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Static;
+
+/// An entity of a `World`. Ids of despawned entities are reused with a new generation, so a
+/// stale entity never refers to the components of another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+impl Entity {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+/// Densely stores the components of one type along with a sparse lookup from entity index.
+/// Removing a component moves the last component into its place, so the order of iteration is
+/// not stable.
+pub struct SparseSet<T> {
+    /// The position in `components` of each entity index
+    sparse: Vec<Option<u32>>,
+    entities: Vec<Entity>,
+    components: Vec<T>,
+}
+
+impl<T> SparseSet<T> {
+    pub fn new() -> Self {
+        Self {
+            sparse: Vec::new(),
+            entities: Vec::new(),
+            components: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.position(entity).is_some()
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        let position = self.position(entity)?;
+        Some(&self.components[position])
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        let position = self.position(entity)?;
+        Some(&mut self.components[position])
+    }
+
+    /// Inserts or replaces the component of `entity`. Returns the replaced component.
+    pub fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        if let Some(position) = self.position(entity) {
+            return Some(std::mem::replace(&mut self.components[position], component));
+        }
+
+        let index = entity.index as usize;
+        if self.sparse.len() <= index {
+            self.sparse.resize(index + 1, None);
+        }
+
+        self.sparse[index] = Some(self.components.len() as u32);
+        self.entities.push(entity);
+        self.components.push(component);
+
+        None
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        let position = self.position(entity)?;
+
+        self.sparse[entity.index as usize] = None;
+        self.entities.swap_remove(position);
+        let component = self.components.swap_remove(position);
+
+        if let Some(moved) = self.entities.get(position) {
+            self.sparse[moved.index as usize] = Some(position as u32);
+        }
+
+        Some(component)
+    }
+
+    /// Returns the entities with the component in the order of iteration
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.entities.iter().copied().zip(&self.components)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.entities.iter().copied().zip(&mut self.components)
+    }
+
+    fn position(&self, entity: Entity) -> Option<usize> {
+        let position = (*self.sparse.get(entity.index as usize)?)? as usize;
+
+        if self.entities[position] == entity {
+            Some(position)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Default for SparseSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Type erased operations on the storage of a component type
+trait Storage: Send + Sync {
+    fn remove_entity(&mut self, entity: Entity);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Component> Storage for SparseSet<T> {
+    fn remove_entity(&mut self, entity: Entity) {
+        self.remove(entity);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Owns the entities and their components
+pub struct World {
+    /// The current generation of each entity index
+    generations: Vec<u32>,
+    /// Indices of despawned entities available for reuse
+    free: Vec<u32>,
+    storages: HashMap<TypeId, Box<dyn Storage>>,
+    changed: bool,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self {
+            generations: Vec::new(),
+            free: Vec::new(),
+            storages: HashMap::new(),
+            changed: false,
+        }
+    }
+
+    /// Creates a new entity without any components
+    pub fn spawn(&mut self) -> Entity {
+        self.changed = true;
+
+        match self.free.pop() {
+            Some(index) => Entity {
+                index,
+                generation: self.generations[index as usize],
+            },
+            None => {
+                self.generations.push(0);
+                Entity {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    /// Removes the entity along with all its components. Returns false if the entity was
+    /// already despawned.
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        for storage in self.storages.values_mut() {
+            storage.remove_entity(entity);
+        }
+
+        self.generations[entity.index as usize] += 1;
+        self.free.push(entity.index);
+        self.changed = true;
+
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.generations.get(entity.index as usize) == Some(&entity.generation)
+    }
+
+    /// Returns the number of alive entities
+    pub fn len(&self) -> usize {
+        self.generations.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Inserts or replaces the component of type `T` of `entity`. Returns the replaced
+    /// component. Does nothing if the entity was despawned.
+    pub fn insert<T: Component>(&mut self, entity: Entity, component: T) -> Option<T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+
+        self.changed = true;
+
+        self.storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(SparseSet::<T>::new()))
+            .as_any_mut()
+            .downcast_mut::<SparseSet<T>>()
+            .and_then(|storage| storage.insert(entity, component))
+    }
+
+    pub fn remove<T: Component>(&mut self, entity: Entity) -> Option<T> {
+        let component = self.storage_mut::<T>()?.remove(entity)?;
+        self.changed = true;
+        Some(component)
+    }
+
+    pub fn get<T: Component>(&self, entity: Entity) -> Option<&T> {
+        self.storage::<T>()?.get(entity)
+    }
+
+    pub fn get_mut<T: Component>(&mut self, entity: Entity) -> Option<&mut T> {
+        self.changed = true;
+        self.storage_mut::<T>()?.get_mut(entity)
+    }
+
+    pub fn has<T: Component>(&self, entity: Entity) -> bool {
+        self.get::<T>(entity).is_some()
+    }
+
+    /// Returns the storage of the components of type `T`, or None if no such component was
+    /// ever inserted.
+    pub fn storage<T: Component>(&self) -> Option<&SparseSet<T>> {
+        self.storages
+            .get(&TypeId::of::<T>())
+            .and_then(|storage| storage.as_any().downcast_ref())
+    }
+
+    pub fn storage_mut<T: Component>(&mut self) -> Option<&mut SparseSet<T>> {
+        self.changed = true;
+        self.storages
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|storage| storage.as_any_mut().downcast_mut())
+    }
+
+    /// Iterates the entities with a component of type `A`
+    pub fn query<A: Component>(&self) -> impl Iterator<Item = (Entity, &A)> {
+        self.storage::<A>()
+            .into_iter()
+            .flat_map(|storage| storage.iter())
+    }
+
+    /// Iterates the components of type `A` mutably
+    pub fn query_mut<A: Component>(&mut self) -> impl Iterator<Item = (Entity, &mut A)> {
+        self.storage_mut::<A>()
+            .into_iter()
+            .flat_map(|storage| storage.iter_mut())
+    }
+
+    /// Iterates the entities with components of both types. The entities are visited in the
+    /// order of `A`, so the least common component should be placed first.
+    pub fn query2<A: Component, B: Component>(&self) -> impl Iterator<Item = (Entity, &A, &B)> {
+        let b = self.storage::<B>();
+
+        self.query::<A>()
+            .filter_map(move |(entity, a)| Some((entity, a, b?.get(entity)?)))
+    }
+
+    /// Iterates the entities with components of all three types in the order of `A`
+    pub fn query3<A: Component, B: Component, C: Component>(
+        &self,
+    ) -> impl Iterator<Item = (Entity, &A, &B, &C)> {
+        let c = self.storage::<C>();
+
+        self.query2::<A, B>()
+            .filter_map(move |(entity, a, b)| Some((entity, a, b, c?.get(entity)?)))
+    }
+
+    /// Calls `f` with the component of type `A` mutably and of type `B` of each entity which has
+    /// both, e.g; to integrate a velocity into the transform. `A` and `B` must differ.
+    pub fn for_each_mut<A: Component, B: Component>(
+        &mut self,
+        mut f: impl FnMut(Entity, &mut A, &B),
+    ) {
+        self.changed = true;
+
+        // The storage of `A` is taken out of the map to borrow both at once
+        let mut a = match self.storages.remove(&TypeId::of::<A>()) {
+            Some(a) => a,
+            None => return,
+        };
+
+        if let (Some(a), Some(b)) = (
+            a.as_any_mut().downcast_mut::<SparseSet<A>>(),
+            self.storage::<B>(),
+        ) {
+            for (entity, a) in a.iter_mut() {
+                if let Some(b) = b.get(entity) {
+                    f(entity, a, b);
+                }
+            }
+        }
+
+        self.storages.insert(TypeId::of::<A>(), a);
+    }
+
+    /// Returns true if entities or components may have changed since the last `clear_changed`.
+    /// Any mutable access counts as a change.
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    pub fn clear_changed(&mut self) {
+        self.changed = false
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
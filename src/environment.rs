@@ -0,0 +1,354 @@
+//! Image based lighting from an environment. The environment is projected onto a cube map, from
+//! which a diffuse irradiance cube map and a GGX prefiltered specular cube map are generated
+//! along with a BRDF lookup table using compute passes. See `data/shaders/environment.glsl`.
+use ash::vk;
+use std::rc::Rc;
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::descriptors::*;
+use vulkan::pipeline::{ComputePipeline, ComputePipelineInfo};
+use vulkan::sampler::*;
+use vulkan::texture::*;
+use vulkan::*;
+
+/// The descriptor set index of the environment set in material effect shaders. Holds the
+/// irradiance cube map at binding 0, the specular cube map at binding 1 and the BRDF lookup
+/// table at binding 2.
+pub const ENVIRONMENT_SET: u32 = 5;
+
+/// The format of the generated maps. Devices are required to support storage writes to it,
+/// unlike two component formats.
+pub const ENVIRONMENT_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// The width and height of the workgroups of the generating shaders
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Specifies the resolution and quality of the generated environment maps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvironmentInfo {
+    /// The width and height of each face of the environment cube map
+    pub resolution: u32,
+    /// The width and height of each face of the irradiance cube map. The irradiance has little
+    /// detail, so a low resolution suffices.
+    pub irradiance_resolution: u32,
+    /// The width and height of each face of the first mip level of the specular cube map
+    pub specular_resolution: u32,
+    /// The number of mip levels of the specular cube map, prefiltered from smooth to rough
+    pub specular_mip_levels: u32,
+    /// The width and height of the BRDF lookup table
+    pub brdf_resolution: u32,
+    /// The number of samples taken for each texel when prefiltering and integrating the BRDF
+    pub sample_count: u32,
+}
+
+impl Default for EnvironmentInfo {
+    fn default() -> Self {
+        Self {
+            resolution: 512,
+            irradiance_resolution: 32,
+            specular_resolution: 128,
+            specular_mip_levels: 5,
+            brdf_resolution: 256,
+            sample_count: 1024,
+        }
+    }
+}
+
+#[repr(C)]
+struct PrefilterParameters {
+    roughness: f32,
+    sample_count: u32,
+}
+
+/// The environment maps used for image based lighting, generated once on creation.
+pub struct Environment {
+    cube: Texture,
+    irradiance: Texture,
+    specular: Texture,
+    brdf_lut: Texture,
+    sampler: Sampler,
+    set: DescriptorSet,
+    set_layout: DescriptorSetLayout,
+}
+
+impl Environment {
+    /// Generates the environment maps from an equirectangular `source` texture, which needs to
+    /// be in SHADER_READ_ONLY_OPTIMAL. Mip levels of the source are used to avoid aliasing.
+    /// Waits for the generation to complete.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        descriptor_allocator: &mut DescriptorAllocator,
+        source: &Texture,
+        info: EnvironmentInfo,
+    ) -> Result<Self, vulkan::Error> {
+        let cube_info = |resolution: u32, mip_levels: u32| TextureInfo {
+            extent: (resolution, resolution).into(),
+            mip_levels,
+            usage: TextureUsage::Storage,
+            format: ENVIRONMENT_FORMAT,
+            array_layers: 6,
+            view_type: ImageViewType::CUBE,
+            ..Default::default()
+        };
+
+        let cube = Texture::new(context.clone(), cube_info(info.resolution, 0))?;
+        let irradiance = Texture::new(context.clone(), cube_info(info.irradiance_resolution, 1))?;
+        let specular = Texture::new(
+            context.clone(),
+            cube_info(info.specular_resolution, info.specular_mip_levels.max(1)),
+        )?;
+
+        let brdf_lut = Texture::new(
+            context.clone(),
+            TextureInfo {
+                extent: (info.brdf_resolution, info.brdf_resolution).into(),
+                mip_levels: 1,
+                usage: TextureUsage::Storage,
+                format: ENVIRONMENT_FORMAT,
+                ..Default::default()
+            },
+        )?;
+
+        let sampler = Sampler::new(
+            context.clone(),
+            SamplerInfo {
+                address_mode: AddressMode::CLAMP_TO_EDGE,
+                anisotropy: Some(1.0),
+                ..Default::default()
+            },
+        )?;
+
+        let environment = Self {
+            cube,
+            irradiance,
+            specular,
+            brdf_lut,
+            sampler,
+            set: Default::default(),
+            set_layout: Default::default(),
+        };
+
+        environment.generate(&context, descriptor_layout_cache, source, info.sample_count)?;
+
+        let mut set = Default::default();
+        let mut set_layout = Default::default();
+
+        DescriptorBuilder::new()
+            .bind_combined_image_sampler(
+                0,
+                vk::ShaderStageFlags::FRAGMENT,
+                &environment.irradiance,
+                &environment.sampler,
+            )
+            .bind_combined_image_sampler(
+                1,
+                vk::ShaderStageFlags::FRAGMENT,
+                &environment.specular,
+                &environment.sampler,
+            )
+            .bind_combined_image_sampler(
+                2,
+                vk::ShaderStageFlags::FRAGMENT,
+                &environment.brdf_lut,
+                &environment.sampler,
+            )
+            .build(
+                context.device(),
+                descriptor_layout_cache,
+                descriptor_allocator,
+                &mut set,
+            )?
+            .layout(descriptor_layout_cache, &mut set_layout)?;
+
+        Ok(Self {
+            set,
+            set_layout,
+            ..environment
+        })
+    }
+
+    /// Returns the descriptor set bound at `ENVIRONMENT_SET`.
+    pub fn set(&self) -> DescriptorSet {
+        self.set
+    }
+
+    pub fn set_layout(&self) -> DescriptorSetLayout {
+        self.set_layout
+    }
+
+    /// Returns the environment projected onto a cube map, e.g; for drawing a skybox.
+    pub fn cube(&self) -> &Texture {
+        &self.cube
+    }
+
+    pub fn irradiance(&self) -> &Texture {
+        &self.irradiance
+    }
+
+    pub fn specular(&self) -> &Texture {
+        &self.specular
+    }
+
+    pub fn brdf_lut(&self) -> &Texture {
+        &self.brdf_lut
+    }
+
+    // Records and waits for the passes generating the maps from the source texture. The
+    // pipelines and descriptor sets of the passes are only needed once, and are freed
+    // afterwards.
+    fn generate(
+        &self,
+        context: &Rc<VulkanContext>,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        source: &Texture,
+        sample_count: u32,
+    ) -> Result<(), vulkan::Error> {
+        let mut descriptor_allocator = DescriptorAllocator::new(context.device_ref(), 8);
+
+        let pipeline = |descriptor_layout_cache: &mut DescriptorLayoutCache, shader: &str| {
+            ComputePipeline::new(
+                context.device_ref(),
+                descriptor_layout_cache,
+                ComputePipelineInfo {
+                    shader: format!("./data/shaders/{}.comp.spv", shader).into(),
+                    ..Default::default()
+                },
+            )
+        };
+
+        let equirect_pipeline = pipeline(descriptor_layout_cache, "equirect_to_cube")?;
+        let irradiance_pipeline = pipeline(descriptor_layout_cache, "irradiance")?;
+        let prefilter_pipeline = pipeline(descriptor_layout_cache, "prefilter")?;
+        let brdf_pipeline = pipeline(descriptor_layout_cache, "brdf_lut")?;
+
+        let source_sampler = Sampler::new(context.clone(), SamplerInfo::default())?;
+
+        // Each pass reads a sampled texture at binding 0 and writes a storage image at
+        // binding 1
+        let mut filter_set = |input: &Texture, sampler: &Sampler, output: &TextureView| {
+            let mut set = Default::default();
+
+            DescriptorBuilder::new()
+                .bind_combined_image_sampler(0, vk::ShaderStageFlags::COMPUTE, input, sampler)
+                .bind_storage_image_view(1, vk::ShaderStageFlags::COMPUTE, output)
+                .build(
+                    context.device(),
+                    descriptor_layout_cache,
+                    &mut descriptor_allocator,
+                    &mut set,
+                )?;
+
+            Ok::<_, vulkan::Error>(set)
+        };
+
+        let cube_views = mip_views(&self.cube)?;
+        let cube_sets = cube_views
+            .iter()
+            .map(|view| filter_set(source, &source_sampler, view))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let irradiance_view = self.irradiance.mip_view(ImageViewType::TYPE_2D_ARRAY, 0)?;
+        let irradiance_set = filter_set(&self.cube, &self.sampler, &irradiance_view)?;
+
+        let specular_views = mip_views(&self.specular)?;
+        let specular_sets = specular_views
+            .iter()
+            .map(|view| filter_set(&self.cube, &self.sampler, view))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut brdf_set = Default::default();
+        DescriptorBuilder::new()
+            .bind_storage_image(0, vk::ShaderStageFlags::COMPUTE, &self.brdf_lut)
+            .build(
+                context.device(),
+                descriptor_layout_cache,
+                &mut descriptor_allocator,
+                &mut brdf_set,
+            )?;
+
+        let dispatch = |commandbuffer: &CommandBuffer, extent: Extent, mip_level: u32, z: u32| {
+            let size = extent.mip(mip_level).width;
+            let groups = size.div_ceil(WORKGROUP_SIZE);
+            commandbuffer.dispatch(groups, groups, z);
+        };
+
+        context
+            .transfer_pool()
+            .single_time_command(context.graphics_queue(), |commandbuffer| {
+                commandbuffer.image_barriers(&[
+                    self.cube.barrier(vk::ImageLayout::GENERAL),
+                    self.irradiance.barrier(vk::ImageLayout::GENERAL),
+                    self.specular.barrier(vk::ImageLayout::GENERAL),
+                    self.brdf_lut.barrier(vk::ImageLayout::GENERAL),
+                ]);
+
+                commandbuffer.bind_compute_pipeline(&equirect_pipeline);
+                for (mip_level, set) in cube_sets.iter().enumerate() {
+                    commandbuffer.bind_compute_descriptor_sets(&equirect_pipeline, 0, &[*set], &[]);
+                    dispatch(commandbuffer, self.cube.extent(), mip_level as u32, 6);
+                }
+
+                // Waits for all mip levels of the cube map to be written
+                self.cube
+                    .transition(commandbuffer, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+                commandbuffer.bind_compute_pipeline(&irradiance_pipeline);
+                commandbuffer.bind_compute_descriptor_sets(
+                    &irradiance_pipeline,
+                    0,
+                    &[irradiance_set],
+                    &[],
+                );
+                dispatch(commandbuffer, self.irradiance.extent(), 0, 6);
+
+                commandbuffer.bind_compute_pipeline(&prefilter_pipeline);
+                let max_mip = (specular_sets.len() - 1).max(1) as f32;
+                for (mip_level, set) in specular_sets.iter().enumerate() {
+                    commandbuffer.bind_compute_descriptor_sets(
+                        &prefilter_pipeline,
+                        0,
+                        &[*set],
+                        &[],
+                    );
+                    commandbuffer.push_constants(
+                        &prefilter_pipeline,
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                        &PrefilterParameters {
+                            roughness: mip_level as f32 / max_mip,
+                            sample_count,
+                        },
+                    );
+                    dispatch(commandbuffer, self.specular.extent(), mip_level as u32, 6);
+                }
+
+                commandbuffer.bind_compute_pipeline(&brdf_pipeline);
+                commandbuffer.bind_compute_descriptor_sets(&brdf_pipeline, 0, &[brdf_set], &[]);
+                commandbuffer.push_constants(
+                    &brdf_pipeline,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    &sample_count,
+                );
+                dispatch(commandbuffer, self.brdf_lut.extent(), 0, 1);
+
+                commandbuffer.image_barriers(&[
+                    self.irradiance
+                        .barrier(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                    self.specular
+                        .barrier(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                    self.brdf_lut
+                        .barrier(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                ]);
+            })
+    }
+}
+
+// Returns a view of each mip level of a cube map for writing the faces as a storage image
+fn mip_views(texture: &Texture) -> Result<Vec<TextureView>, vulkan::Error> {
+    (0..texture.mip_levels())
+        .map(|mip_level| texture.mip_view(ImageViewType::TYPE_2D_ARRAY, mip_level))
+        .collect()
+}
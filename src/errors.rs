@@ -1,4 +1,6 @@
-use crate::resources;
+use crate::capture;
+use crate::render_target::RenderTarget;
+use crate::resources::{self, Handle};
 use crate::vulkan;
 use thiserror::Error;
 
@@ -10,7 +12,97 @@ pub enum Error {
     SparseAccessor,
     #[error("{0}")]
     ResourceError(#[from] resources::Error),
+    #[error("{0}")]
+    RenderError(#[from] RenderError),
+
+    #[error("Frames in flight must be between 1 and {max}, got {count}")]
+    InvalidFramesInFlight { count: usize, max: usize },
+    #[error("No monitor is available for fullscreen")]
+    NoMonitor,
+    #[error("At most {max} point lights are supported")]
+    TooManyPointLights { max: usize },
 
     #[error("GLTF import error '{0}'")]
     GLTFImport(#[from] gltf::Error),
+    #[error("IO error {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Invalid mesh cache {path:?}: {reason}")]
+    InvalidMeshCache {
+        path: std::path::PathBuf,
+        reason: &'static str,
+    },
+    #[error("Failed to load sound {path:?}: {source}")]
+    SoundError {
+        path: std::path::PathBuf,
+        source: hound::Error,
+    },
+    #[error("Invalid definition file {path:?}: {message}")]
+    InvalidDefinition {
+        path: std::path::PathBuf,
+        message: String,
+    },
+    #[error("Failed to parse mesh {path:?}: {message}")]
+    MeshParse {
+        path: std::path::PathBuf,
+        message: String,
+    },
+
+    /// An error along with a description of what failed, e.g; the file being loaded
+    #[error("{context}: {source}")]
+    Context { context: String, source: Box<Error> },
+}
+
+/// Attaches a description of what failed to the error of a result
+pub trait ErrorContext<T> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T, Error>;
+
+    /// Lazily evaluated version of `context`, which avoids formatting on success
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, f: F) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> ErrorContext<T> for Result<T, E> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T, Error> {
+        self.with_context(|| context)
+    }
+
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, f: F) -> Result<T, Error> {
+        self.map_err(|e| Error::Context {
+            context: f().into(),
+            source: Box::new(e.into()),
+        })
+    }
+}
+
+/// An error from drawing a frame, describing the stage of the frame which failed
+#[derive(Error, Debug)]
+pub enum RenderError {
+    #[error("Failed to recreate the swapchain: {0}")]
+    Resize(#[source] vulkan::Error),
+    #[error("Failed to create the renderers of {count} views: {source}")]
+    Views { count: usize, source: vulkan::Error },
+    #[error("Failed to acquire the next swapchain image: {0}")]
+    Acquire(#[source] vulkan::Error),
+    #[error("Failed to record the frame commands: {0}")]
+    Record(#[source] vulkan::Error),
+    #[error("Failed to draw the shadow map: {0}")]
+    Shadows(#[source] vulkan::Error),
+    #[error("Failed to draw the shadows of point light {light}: {source}")]
+    PointShadows { light: usize, source: vulkan::Error },
+    #[error("Failed to draw render target {target:?}: {source}")]
+    RenderTarget {
+        target: Handle<RenderTarget>,
+        source: vulkan::Error,
+    },
+    #[error("Failed to draw the stereo view: {0}")]
+    Stereo(#[source] vulkan::Error),
+    #[error("Failed to draw view {index}: {source}")]
+    View { index: usize, source: vulkan::Error },
+    #[error("Failed to record pass {name:?}: {source}")]
+    Pass { name: String, source: vulkan::Error },
+    #[error("Failed to capture the frame: {0}")]
+    Capture(#[source] capture::Error),
+    #[error("Failed to submit the frame: {0}")]
+    Submit(#[source] vulkan::Error),
+    #[error("Failed to present the frame: {0}")]
+    Present(#[source] vulkan::Error),
 }
@@ -0,0 +1,154 @@
+//! Distance and height fog of the scene, applied by the lit and PBR effects after lighting. The
+//! fog is uploaded to a uniform shared by all views each frame. See `data/shaders/fog.glsl`.
+use ash::vk;
+use std::{rc::Rc, slice};
+use ultraviolet::Vec4;
+
+use crate::color::Color;
+use crate::light_culling::update_buffer;
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::descriptors::*;
+use vulkan::*;
+
+/// The descriptor set index of the fog set in material effect shaders. Holds the fog uniform at
+/// binding 0.
+pub const FOG_SET: u32 = 6;
+
+/// How the fog thickens with the distance from the camera
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FogMode {
+    /// Fades linearly from `Fog::start` to `Fog::end`
+    Linear,
+    /// Thickens exponentially with `Fog::density`, similar to light scattered in a medium
+    Exponential,
+}
+
+/// Specifies the fog of a scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    pub mode: FogMode,
+    /// The color surfaces fade to. The alpha is the opacity of fully fogged surfaces.
+    pub color: Color,
+    /// The distance at which linear fog starts
+    pub start: f32,
+    /// The distance at which linear fog reaches full opacity
+    pub end: f32,
+    /// The extinction per unit of distance of exponential fog at `height`
+    pub density: f32,
+    /// The height below which the fog has its full density
+    pub height: f32,
+    /// How quickly the fog thins out above `height`. Zero gives a uniform fog.
+    pub height_falloff: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            mode: FogMode::Exponential,
+            color: Color::rgb(160, 170, 185),
+            start: 10.0,
+            end: 100.0,
+            density: 0.02,
+            height: 0.0,
+            height_falloff: 0.2,
+        }
+    }
+}
+
+/// The fog uniform, matching the std140 `FogData` block of the shaders
+#[derive(Default)]
+#[repr(C)]
+struct FogData {
+    color: Vec4,
+    /// 0 without fog, 1 for linear and 2 for exponential fog
+    mode: u32,
+    start: f32,
+    end: f32,
+    density: f32,
+    height: f32,
+    height_falloff: f32,
+    _padding: [f32; 2],
+}
+
+impl From<Option<&Fog>> for FogData {
+    fn from(fog: Option<&Fog>) -> Self {
+        let fog = match fog {
+            Some(fog) => fog,
+            None => return Self::default(),
+        };
+
+        Self {
+            color: fog.color.to_linear_vec4(),
+            mode: match fog.mode {
+                FogMode::Linear => 1,
+                FogMode::Exponential => 2,
+            },
+            start: fog.start,
+            end: fog.end,
+            density: fog.density,
+            height: fog.height,
+            height_falloff: fog.height_falloff,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// The uniform holding the fog of the scene, bound at `FOG_SET` for all views
+pub struct FogUniform {
+    buffer: Buffer,
+    set: DescriptorSet,
+    set_layout: DescriptorSetLayout,
+}
+
+impl FogUniform {
+    /// Creates the uniform without any fog
+    pub fn new(
+        context: Rc<VulkanContext>,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        descriptor_allocator: &mut DescriptorAllocator,
+    ) -> Result<Self, vulkan::Error> {
+        let buffer = Buffer::new(
+            context.clone(),
+            BufferType::Uniform,
+            BufferUsage::Staged,
+            slice::from_ref(&FogData::default()),
+        )?;
+
+        let mut set = Default::default();
+        let mut set_layout = Default::default();
+
+        DescriptorBuilder::new()
+            .bind_uniform_buffer(0, vk::ShaderStageFlags::FRAGMENT, &buffer)
+            .build(
+                context.device(),
+                descriptor_layout_cache,
+                descriptor_allocator,
+                &mut set,
+            )?
+            .layout(descriptor_layout_cache, &mut set_layout)?;
+
+        Ok(Self {
+            buffer,
+            set,
+            set_layout,
+        })
+    }
+
+    /// Records the upload of `fog`, or disables the fog if None. Needs to be recorded outside of
+    /// a renderpass before the views are drawn.
+    pub fn update(&self, commandbuffer: &CommandBuffer, fog: Option<&Fog>) {
+        let data = FogData::from(fog);
+        update_buffer(commandbuffer, &self.buffer, slice::from_ref(&data));
+    }
+
+    /// Returns the descriptor set bound at `FOG_SET`.
+    pub fn set(&self) -> DescriptorSet {
+        self.set
+    }
+
+    pub fn set_layout(&self) -> DescriptorSetLayout {
+        self.set_layout
+    }
+}
@@ -0,0 +1,248 @@
+//! Limiting the frame rate and collecting frame time statistics, e.g; when presenting with the
+//! IMMEDIATE present mode which does not wait for vertical blanks.
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The number of frames kept for the frame statistics
+pub const DEFAULT_FRAME_HISTORY: usize = 1000;
+
+/// Sleeping is only accurate to about a millisecond on most platforms, so the remainder is spent
+/// spinning
+const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameLimit {
+    Unlimited,
+    /// Target frames per second
+    Fps(f32),
+    /// Target duration of each frame
+    FrameTime(Duration),
+}
+
+impl FrameLimit {
+    /// Returns the target duration of each frame, or None if unlimited
+    pub fn frame_time(&self) -> Option<Duration> {
+        match *self {
+            FrameLimit::Unlimited => None,
+            FrameLimit::Fps(fps) if fps > 0.0 => Some(Duration::from_secs_f32(1.0 / fps)),
+            FrameLimit::Fps(_) => None,
+            FrameLimit::FrameTime(frame_time) => Some(frame_time),
+        }
+    }
+}
+
+/// Waits at the end of each frame until the frame limit is reached
+pub struct FrameLimiter {
+    limit: FrameLimit,
+    /// The end of the previous frame
+    last: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(limit: FrameLimit) -> Self {
+        Self {
+            limit,
+            last: Instant::now(),
+        }
+    }
+
+    /// Sleeps and spins until the target frame time has passed since the end of the previous
+    /// frame. Returns the duration of the frame including the wait.
+    /// Frames running behind are not caught up on.
+    pub fn wait(&mut self) -> Duration {
+        if let Some(frame_time) = self.limit.frame_time() {
+            let deadline = self.last + frame_time;
+
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+
+                let remaining = deadline - now;
+                if remaining > SPIN_THRESHOLD {
+                    thread::sleep(remaining - SPIN_THRESHOLD);
+                } else {
+                    thread::yield_now();
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+
+        elapsed
+    }
+
+    pub fn limit(&self) -> FrameLimit {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: FrameLimit) {
+        self.limit = limit;
+    }
+}
+
+/// Specifies when the host is throttled to the device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThrottleInfo {
+    /// The number of recent frames considered
+    pub history: usize,
+    /// The fraction of recent frames which found the device still busy before the host is
+    /// throttled, e.g; 0.5 for half of the frames
+    pub threshold: f32,
+}
+
+impl Default for ThrottleInfo {
+    fn default() -> Self {
+        Self {
+            history: 8,
+            threshold: 0.5,
+        }
+    }
+}
+
+/// Keeps the host from recording further ahead of the device than the frames in flight allow
+/// when the device is the bottleneck. Frames queued behind others are presented long after the
+/// input they were recorded from, which adds to the latency.
+#[derive(Debug, Clone)]
+pub struct FrameThrottle {
+    info: ThrottleInfo,
+    /// Whether the device was still busy with the awaited frame, from oldest to newest
+    pending: VecDeque<bool>,
+}
+
+impl FrameThrottle {
+    pub fn new(info: ThrottleInfo) -> Self {
+        Self {
+            info,
+            pending: VecDeque::with_capacity(info.history),
+        }
+    }
+
+    /// Records whether the device was still processing the frame the host waited for
+    pub fn push(&mut self, pending: bool) {
+        if self.pending.len() >= self.info.history.max(1) {
+            self.pending.pop_front();
+        }
+
+        self.pending.push_back(pending);
+    }
+
+    /// Returns true if the host should wait for the previous frame to complete before
+    /// recording, which is when the device was busy in at least `threshold` of the recent
+    /// frames
+    pub fn should_wait(&self) -> bool {
+        if self.pending.is_empty() {
+            return false;
+        }
+
+        let pending = self.pending.iter().filter(|pending| **pending).count();
+        pending as f32 >= self.pending.len() as f32 * self.info.threshold
+    }
+
+    pub fn info(&self) -> &ThrottleInfo {
+        &self.info
+    }
+}
+
+/// Frame times of the most recent frames
+#[derive(Debug, Clone)]
+pub struct FrameStats {
+    frame_times: VecDeque<Duration>,
+    capacity: usize,
+}
+
+impl FrameStats {
+    /// Creates statistics over the last `capacity` frames
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frame_times: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Records the duration of a frame, discarding the oldest frame if full
+    pub fn push(&mut self, frame_time: Duration) {
+        if self.frame_times.len() == self.capacity {
+            self.frame_times.pop_front();
+        }
+
+        self.frame_times.push_back(frame_time);
+    }
+
+    /// Returns the recorded frame times from oldest to newest
+    pub fn frame_times(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.frame_times.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frame_times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frame_times.is_empty()
+    }
+
+    /// Returns the average frame time. Zero if no frames are recorded.
+    pub fn average(&self) -> Duration {
+        if self.frame_times.is_empty() {
+            return Duration::default();
+        }
+
+        self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32
+    }
+
+    /// Returns the average frames per second
+    pub fn average_fps(&self) -> f32 {
+        to_fps(self.average())
+    }
+
+    /// Returns the average frames per second of the slowest 1% of frames
+    pub fn one_percent_low(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+
+        let mut frame_times = self.frame_times.iter().copied().collect::<Vec<_>>();
+        frame_times.sort_unstable_by(|a, b| b.cmp(a));
+
+        let count = (frame_times.len() / 100).max(1);
+        let slowest = frame_times[..count].iter().sum::<Duration>() / count as u32;
+
+        to_fps(slowest)
+    }
+
+    /// Counts the frame times into `buckets` buckets `bucket_size` wide. Frame times beyond the
+    /// last bucket are counted in the last bucket.
+    pub fn histogram(&self, bucket_size: Duration, buckets: usize) -> Vec<usize> {
+        let mut histogram = vec![0; buckets];
+
+        if buckets == 0 || bucket_size == Duration::default() {
+            return histogram;
+        }
+
+        for frame_time in &self.frame_times {
+            let bucket = (frame_time.as_nanos() / bucket_size.as_nanos()) as usize;
+            histogram[bucket.min(buckets - 1)] += 1;
+        }
+
+        histogram
+    }
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self::new(DEFAULT_FRAME_HISTORY)
+    }
+}
+
+fn to_fps(frame_time: Duration) -> f32 {
+    if frame_time == Duration::default() {
+        0.0
+    } else {
+        1.0 / frame_time.as_secs_f32()
+    }
+}
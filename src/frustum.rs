@@ -0,0 +1,66 @@
+use ultraviolet::{Mat4, Vec3, Vec4};
+
+use crate::aabb::Aabb;
+
+/// The six planes bounding the volume visible through a projection. Plane normals point
+/// inwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far planes with the normal in xyz and the distance
+    /// in w
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a view projection matrix with the Vulkan depth range of 0 to 1.
+    pub fn from_matrix(matrix: &Mat4) -> Self {
+        let row = |i: usize| {
+            Vec4::new(
+                matrix.cols[0][i],
+                matrix.cols[1][i],
+                matrix.cols[2][i],
+                matrix.cols[3][i],
+            )
+        };
+
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        let planes = [w + x, w - x, w + y, w - y, z, w - z];
+
+        Self {
+            planes: planes.map(|plane| plane / plane.truncated().mag()),
+        }
+    }
+
+    /// Returns false if the box is entirely outside any of the planes. Boxes close to the
+    /// corners may be reported as intersecting while being outside.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncated();
+
+            // The corner furthest along the plane normal
+            let corner = Vec3::new(
+                furthest(normal.x, aabb.min.x, aabb.max.x),
+                furthest(normal.y, aabb.min.y, aabb.max.y),
+                furthest(normal.z, aabb.min.z, aabb.max.z),
+            );
+
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+
+    /// Returns false if the sphere is entirely outside any of the planes.
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncated().dot(center) + plane.w >= -radius)
+    }
+}
+
+fn furthest(direction: f32, min: f32, max: f32) -> f32 {
+    if direction >= 0.0 {
+        max
+    } else {
+        min
+    }
+}
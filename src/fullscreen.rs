@@ -0,0 +1,95 @@
+//! Passes drawing a single triangle covering the viewport without any vertex buffers, e.g; for
+//! procedural backgrounds and overlays. The fragment shader receives the normalized device
+//! coordinates of the fragment, see `data/shaders/fullscreen.vert`.
+use std::rc::Rc;
+
+use ash::vk;
+
+use crate::view_commands::ViewCommands;
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::descriptors::DescriptorSet;
+use vulkan::pipeline::PipelineInfo;
+use vulkan::*;
+
+/// Returns the info of a pipeline drawing the fullscreen triangle with `fragmentshader` into
+/// attachments of `samples`.
+pub fn pipeline_info<P: Into<std::path::PathBuf>>(
+    fragmentshader: P,
+    samples: vk::SampleCountFlags,
+) -> PipelineInfo {
+    PipelineInfo {
+        vertexshader: "./data/shaders/fullscreen.vert.spv".into(),
+        fragmentshader: fragmentshader.into(),
+        samples,
+        cull_mode: vk::CullModeFlags::NONE,
+        ..Default::default()
+    }
+}
+
+/// Draws the fullscreen triangle into the viewport of each view, either inline or through
+/// secondary command buffers
+pub struct FullscreenPass {
+    pipeline: Pipeline,
+    commands: ViewCommands,
+    /// Bound at set 0 before drawing if Some
+    set: Option<DescriptorSet>,
+}
+
+impl FullscreenPass {
+    /// Creates a pass drawing with `pipeline`, which should be created from `pipeline_info`.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        pipeline: Pipeline,
+        frames_in_flight: usize,
+    ) -> Result<Self, vulkan::Error> {
+        Ok(Self {
+            pipeline,
+            commands: ViewCommands::new(context, frames_in_flight)?,
+            set: None,
+        })
+    }
+
+    /// Sets the descriptor set bound at set 0 of the pipeline, e.g; for sampling textures in
+    /// the fragment shader. Takes effect on the next draw.
+    pub fn set_descriptor_set(&mut self, set: Option<DescriptorSet>) {
+        self.set = set;
+    }
+
+    /// Draws the triangle into `viewport` with `parameters` as the fragment shader push
+    /// constants. See `ViewCommands::record`.
+    pub fn draw<T>(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        frame_index: usize,
+        view_index: usize,
+        viewport: vk::Rect2D,
+        inheritance: Option<&Inheritance>,
+        parameters: &T,
+    ) -> Result<(), vulkan::Error> {
+        let pipeline = &self.pipeline;
+        let set = self.set;
+
+        self.commands.record(
+            commandbuffer,
+            frame_index,
+            view_index,
+            inheritance,
+            |commandbuffer| {
+                commandbuffer.bind_pipeline(pipeline);
+                if let Some(set) = set {
+                    commandbuffer.bind_descriptor_sets(pipeline, 0, &[set], &[]);
+                }
+                commandbuffer.set_viewport_rect(viewport);
+                commandbuffer.push_constants(
+                    pipeline,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    parameters,
+                );
+                commandbuffer.draw(3, 1, 0, 0);
+            },
+        )
+    }
+}
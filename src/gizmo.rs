@@ -0,0 +1,639 @@
+//! Translate, rotate and scale handles drawn over a selected object, which modify the transform
+//! of the object when dragged along an axis. The handles are drawn as lines at a constant size
+//! on screen and are hit tested against rays through the screen, see `Camera::screen_ray`.
+use std::{f32::consts::PI, rc::Rc};
+
+use ash::vk;
+use ultraviolet::{Mat4, Rotor3, Vec2, Vec3, Vec4};
+
+use crate::color::Color;
+use crate::pass::{Pass, PassFrame, PassStage, PassTargets};
+use crate::raycast::Ray;
+use crate::view_commands::ViewCommands;
+use crate::{Camera, Layers, ObjectHandle, Scene};
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::pipeline::PipelineInfo;
+use vulkan::*;
+
+/// The number of line segments of each rotation circle
+const CIRCLE_SEGMENTS: usize = 64;
+
+/// The length and width of the arrow heads of the translation handles relative to the size of
+/// the gizmo
+const ARROW_LENGTH: f32 = 0.15;
+const ARROW_WIDTH: f32 = 0.05;
+
+/// The half extent of the boxes at the end of the scale handles relative to the size of the
+/// gizmo
+const BOX_SIZE: f32 = 0.05;
+
+/// The distance from a handle within which it is hit, relative to the size of the gizmo
+const HIT_TOLERANCE: f32 = 0.08;
+
+/// Scale handles do not scale objects below this fraction of the scale at the start of the drag
+const MIN_SCALE_FACTOR: f32 = 0.01;
+
+/// The transform the gizmo modifies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+impl GizmoMode {
+    /// All modes in the order of their handle meshes
+    pub const ALL: &'static [GizmoMode] =
+        &[GizmoMode::Translate, GizmoMode::Rotate, GizmoMode::Scale];
+}
+
+/// An axis of the gizmo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub const ALL: &'static [Axis] = &[Axis::X, Axis::Y, Axis::Z];
+
+    /// Returns the unit vector along the axis
+    pub fn unit(self) -> Vec3 {
+        match self {
+            Axis::X => Vec3::unit_x(),
+            Axis::Y => Vec3::unit_y(),
+            Axis::Z => Vec3::unit_z(),
+        }
+    }
+
+    /// Returns the two unit vectors perpendicular to the axis
+    fn perpendicular(self) -> (Vec3, Vec3) {
+        match self {
+            Axis::X => (Vec3::unit_y(), Vec3::unit_z()),
+            Axis::Y => (Vec3::unit_z(), Vec3::unit_x()),
+            Axis::Z => (Vec3::unit_x(), Vec3::unit_y()),
+        }
+    }
+}
+
+/// An axis being dragged and the state at the start of the drag
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Drag {
+    axis: Axis,
+    /// The point on the axis where the drag started, or the direction from the center where
+    /// the rotation handle was last dragged to
+    last: Vec3,
+    /// The position and scale of the object when the drag started
+    position: Vec3,
+    scale: Vec3,
+}
+
+/// The selected object and the handle being hovered or dragged. Translation and rotation
+/// handles are aligned to the world axes, while scale handles follow the rotation of the object
+/// since the scale of objects is applied in object space.
+/// The cameras passed need to have the aspect ratio of the viewport the gizmo is drawn in, see
+/// `Camera::with_viewport_aspect`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    /// The fraction of the screen height covered by the handles
+    pub size: f32,
+    pub colors: [Color; 3],
+    pub hover_color: Color,
+    target: Option<ObjectHandle>,
+    hovered: Option<Axis>,
+    drag: Option<Drag>,
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            size: 0.15,
+            colors: [
+                Color::rgb(220, 50, 50),
+                Color::rgb(50, 200, 50),
+                Color::rgb(50, 90, 230),
+            ],
+            hover_color: Color::rgb(240, 220, 40),
+            target: None,
+            hovered: None,
+            drag: None,
+        }
+    }
+}
+
+impl Gizmo {
+    pub fn new(mode: GizmoMode) -> Self {
+        Self {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the handle of the scene object the gizmo manipulates
+    pub fn target(&self) -> Option<ObjectHandle> {
+        self.target
+    }
+
+    /// Selects the scene object `target` for manipulation, or hides the gizmo if None.
+    /// Cancels the current drag.
+    pub fn set_target(&mut self, target: Option<ObjectHandle>) {
+        self.target = target;
+        self.hovered = None;
+        self.drag = None;
+    }
+
+    /// Returns the axis under the cursor as of the last `hover`, or the dragged axis
+    pub fn hovered(&self) -> Option<Axis> {
+        self.drag.map(|drag| drag.axis).or(self.hovered)
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Returns the axis of the handle hit by the ray through `ndc` closest to the camera.
+    pub fn hit_test(&self, camera: &Camera, ndc: Vec2, scene: &Scene) -> Option<Axis> {
+        let (center, rotation) = self.frame(scene)?;
+        let size = camera.world_size(center, self.size);
+        let tolerance = size * HIT_TOLERANCE;
+        let ray = camera.screen_ray(ndc);
+
+        Axis::ALL
+            .iter()
+            .filter_map(|&axis| {
+                let direction = rotation * axis.unit();
+
+                let distance = match self.mode {
+                    GizmoMode::Translate | GizmoMode::Scale => {
+                        let (distance, t) = closest_on_axis(&ray, center, direction)?;
+                        let point = center + direction * t;
+
+                        if t < 0.0
+                            || t > size * (1.0 + BOX_SIZE)
+                            || (ray.at(distance) - point).mag() > tolerance
+                        {
+                            return None;
+                        }
+
+                        distance
+                    }
+                    GizmoMode::Rotate => {
+                        let distance = intersect_plane(&ray, center, direction)?;
+                        let radius = (ray.at(distance) - center).mag();
+
+                        if (radius - size).abs() > tolerance {
+                            return None;
+                        }
+
+                        distance
+                    }
+                };
+
+                Some((axis, distance))
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(axis, _)| axis)
+    }
+
+    /// Updates the hovered axis from the cursor at `ndc`, e.g; when the cursor moves. Returns
+    /// the hovered axis.
+    pub fn hover(&mut self, camera: &Camera, ndc: Vec2, scene: &Scene) -> Option<Axis> {
+        self.hovered = self.hit_test(camera, ndc, scene);
+        self.hovered
+    }
+
+    /// Starts dragging the handle under the cursor at `ndc`. Returns false if no handle was hit,
+    /// e.g; to select another object instead.
+    pub fn begin_drag(&mut self, camera: &Camera, ndc: Vec2, scene: &Scene) -> bool {
+        let axis = match self.hit_test(camera, ndc, scene) {
+            Some(axis) => axis,
+            None => return false,
+        };
+
+        let object = scene.object(self.target.unwrap()).unwrap();
+        let ray = camera.screen_ray(ndc);
+
+        let last = match self.drag_point(&ray, axis, scene) {
+            Some(last) => last,
+            None => return false,
+        };
+
+        self.drag = Some(Drag {
+            axis,
+            last,
+            position: object.position,
+            scale: object.scale,
+        });
+
+        true
+    }
+
+    /// Moves the dragged handle to the cursor at `ndc` and updates the transform of the target.
+    /// Returns true if the target was modified.
+    pub fn drag(&mut self, camera: &Camera, ndc: Vec2, scene: &mut Scene) -> bool {
+        let drag = match self.drag {
+            Some(drag) => drag,
+            None => return false,
+        };
+
+        let ray = camera.screen_ray(ndc);
+
+        let point = match self.drag_point(&ray, drag.axis, scene) {
+            Some(point) => point,
+            None => return false,
+        };
+
+        let (center, rotation) = self.frame(scene).unwrap();
+        let object = scene.object_mut(self.target.unwrap()).unwrap();
+        let direction = rotation * drag.axis.unit();
+
+        match self.mode {
+            // The center moves along with the object, so the offset is taken from the start of
+            // the drag
+            GizmoMode::Translate => {
+                object.position = drag.position + direction * (point - drag.last).dot(direction)
+            }
+            // Rotations are applied incrementally, since the rotation between opposite
+            // directions is undefined
+            GizmoMode::Rotate => {
+                object.rotation = (Rotor3::from_rotation_between(drag.last, point)
+                    * object.rotation)
+                    .normalized();
+
+                if let Some(drag) = &mut self.drag {
+                    drag.last = point;
+                }
+            }
+            GizmoMode::Scale => {
+                let start = (drag.last - center).dot(direction);
+                if start.abs() <= f32::EPSILON {
+                    return false;
+                }
+
+                let factor = ((point - center).dot(direction) / start).max(MIN_SCALE_FACTOR);
+                let mut scale = drag.scale;
+                match drag.axis {
+                    Axis::X => scale.x *= factor,
+                    Axis::Y => scale.y *= factor,
+                    Axis::Z => scale.z *= factor,
+                }
+
+                object.scale = scale;
+            }
+        }
+
+        true
+    }
+
+    /// Stops dragging. The target keeps its current transform.
+    pub fn end_drag(&mut self) {
+        self.drag = None;
+    }
+
+    /// Returns the color of each axis, with the hovered axis highlighted
+    fn axis_colors(&self) -> [Vec4; 3] {
+        let hovered = self.hovered();
+        let mut colors = [Vec4::zero(); 3];
+
+        for (i, axis) in Axis::ALL.iter().enumerate() {
+            colors[i] = if hovered == Some(*axis) {
+                self.hover_color.to_linear_vec4()
+            } else {
+                self.colors[i].to_linear_vec4()
+            };
+        }
+
+        colors
+    }
+
+    /// Returns the center and orientation of the handles, or None if the target is not in the
+    /// scene or in a hidden layer
+    fn frame(&self, scene: &Scene) -> Option<(Vec3, Rotor3)> {
+        let target = scene.index_of(self.target?)?;
+        let object = &scene.objects()[target];
+
+        if !scene.is_drawn(target, Layers::ALL) {
+            return None;
+        }
+
+        let rotation = match self.mode {
+            GizmoMode::Scale => object.rotation,
+            _ => Rotor3::identity(),
+        };
+
+        Some((object.position, rotation))
+    }
+
+    /// Returns the point on the dragged axis closest to `ray`, or the normalized direction
+    /// from the center to where the ray hits the rotation plane
+    fn drag_point(&self, ray: &Ray, axis: Axis, scene: &Scene) -> Option<Vec3> {
+        let (center, rotation) = self.frame(scene)?;
+        let direction = rotation * axis.unit();
+
+        match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                let (_, t) = closest_on_axis(ray, center, direction)?;
+                Some(center + direction * t)
+            }
+            GizmoMode::Rotate => {
+                let distance = intersect_plane(ray, center, direction)?;
+                let offset = ray.at(distance) - center;
+                let offset = offset - direction * offset.dot(direction);
+
+                if offset.mag_sq() <= f32::EPSILON {
+                    return None;
+                }
+
+                Some(offset.normalized())
+            }
+        }
+    }
+}
+
+/// Returns the distance along `ray` and along the line through `center` in `direction` of the
+/// closest points between them, or None if they are parallel
+fn closest_on_axis(ray: &Ray, center: Vec3, direction: Vec3) -> Option<(f32, f32)> {
+    let w = ray.origin - center;
+    let b = ray.direction.dot(direction);
+    let d = ray.direction.dot(w);
+    let e = direction.dot(w);
+
+    let denom = 1.0 - b * b;
+    if denom.abs() < 1e-6 {
+        return None;
+    }
+
+    Some(((b * e - d) / denom, (e - b * d) / denom))
+}
+
+/// Returns the distance along `ray` to the plane through `center` with `normal`, or None if the
+/// plane is behind or parallel to the ray
+fn intersect_plane(ray: &Ray, center: Vec3, normal: Vec3) -> Option<f32> {
+    let denom = ray.direction.dot(normal);
+    if denom.abs() < 1e-6 {
+        return None;
+    }
+
+    let distance = (center - ray.origin).dot(normal) / denom;
+    if distance < 0.0 {
+        return None;
+    }
+
+    Some(distance)
+}
+
+/// A vertex of the handle lines
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct GizmoVertex {
+    position: Vec3,
+    /// The index of the axis the vertex belongs to, which selects its color
+    axis: u32,
+}
+
+crate::vertex_desc!(GizmoVertex {
+    0 => position: Vec3,
+    1 => axis: u32,
+});
+
+/// Returns the line list of the handles of `mode` with a size of 1
+fn handle_lines(mode: GizmoMode) -> Vec<GizmoVertex> {
+    let mut vertices = Vec::new();
+
+    for (index, axis) in Axis::ALL.iter().enumerate() {
+        let mut line = |a: Vec3, b: Vec3| {
+            vertices.push(GizmoVertex {
+                position: a,
+                axis: index as u32,
+            });
+            vertices.push(GizmoVertex {
+                position: b,
+                axis: index as u32,
+            });
+        };
+
+        let direction = axis.unit();
+        let (u, v) = axis.perpendicular();
+
+        match mode {
+            GizmoMode::Translate => {
+                line(Vec3::zero(), direction);
+
+                let base = direction * (1.0 - ARROW_LENGTH);
+                for offset in &[u, -u, v, -v] {
+                    line(direction, base + *offset * ARROW_WIDTH);
+                }
+            }
+            GizmoMode::Rotate => {
+                let point = |i: usize| {
+                    let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+                    u * angle.cos() + v * angle.sin()
+                };
+
+                for i in 0..CIRCLE_SEGMENTS {
+                    line(point(i), point(i + 1));
+                }
+            }
+            GizmoMode::Scale => {
+                line(Vec3::zero(), direction);
+
+                // The edges of a box centered at the end of the handle
+                let corner =
+                    |a: f32, b: f32, c: f32| direction + (direction * a + u * b + v * c) * BOX_SIZE;
+
+                for &a in &[-1.0, 1.0] {
+                    for &b in &[-1.0, 1.0] {
+                        line(corner(-1.0, a, b), corner(1.0, a, b));
+                        line(corner(a, -1.0, b), corner(a, 1.0, b));
+                        line(corner(a, b, -1.0), corner(a, b, 1.0));
+                    }
+                }
+            }
+        }
+    }
+
+    vertices
+}
+
+/// The name of the gizmo pass
+pub const NAME: &str = "gizmo";
+
+/// The push constants of the vertex shader
+#[repr(C)]
+struct GizmoParameters {
+    mvp: Mat4,
+    colors: [Vec4; 3],
+}
+
+/// Returns the info of the gizmo pipeline for attachments of `samples`. The handles are drawn
+/// over the scene regardless of depth, with lines two pixels wide where supported.
+pub fn pipeline_info(samples: vk::SampleCountFlags) -> PipelineInfo {
+    PipelineInfo {
+        vertexshader: "./data/shaders/gizmo.vert.spv".into(),
+        fragmentshader: "./data/shaders/gizmo.frag.spv".into(),
+        vertex_binding: GizmoVertex::binding_description(),
+        vertex_attributes: GizmoVertex::attribute_descriptions(),
+        samples,
+        topology: vk::PrimitiveTopology::LINE_LIST,
+        line_width: 2.0,
+        cull_mode: vk::CullModeFlags::NONE,
+        depth_compare: vk::CompareOp::ALWAYS,
+        depth_write: false,
+        ..Default::default()
+    }
+}
+
+/// Draws the handles of a gizmo over the objects of each view as a `PassStage::View` pass
+pub struct GizmoRenderer {
+    /// The pipeline and secondary command buffers of each view. None until the pass is set up.
+    target: Option<(Pipeline, ViewCommands)>,
+    /// The handle lines of each mode in the order of `GizmoMode::ALL`
+    handles: Vec<TypedBuffer<GizmoVertex>>,
+    gizmo: Gizmo,
+    /// The center and orientation of the handles as of the last `update`
+    model: Option<(Vec3, Rotor3)>,
+}
+
+impl GizmoRenderer {
+    /// Creates a gizmo renderer along with the vertex buffers of the handles. The pipeline is
+    /// created when it is set up as a pass.
+    pub fn new(context: Rc<VulkanContext>, gizmo: Gizmo) -> Result<Self, vulkan::Error> {
+        let handles = GizmoMode::ALL
+            .iter()
+            .map(|mode| {
+                TypedBuffer::from_slice(
+                    context.clone(),
+                    BufferType::Vertex,
+                    BufferUsage::Staged,
+                    &handle_lines(*mode),
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            target: None,
+            handles,
+            gizmo,
+            model: None,
+        })
+    }
+
+    pub fn gizmo(&self) -> &Gizmo {
+        &self.gizmo
+    }
+
+    pub fn gizmo_mut(&mut self) -> &mut Gizmo {
+        &mut self.gizmo
+    }
+
+    /// Updates the handles to the current transform of the target in `scene`. Called before
+    /// drawing each view when recorded as a pass.
+    pub fn update(&mut self, scene: &Scene) {
+        self.model = self.gizmo.frame(scene);
+    }
+
+    /// Draws the handles as seen by `camera` into `viewport`, if the gizmo has a target. Needs
+    /// to be recorded within the pass after the objects of the view. See `ViewCommands::record`.
+    /// Does nothing before the pass is set up.
+    pub fn draw(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        frame_index: usize,
+        view_index: usize,
+        camera: &Camera,
+        viewport: vk::Rect2D,
+        inheritance: Option<&Inheritance>,
+    ) -> Result<(), vulkan::Error> {
+        let ((center, rotation), (pipeline, commands)) = match (self.model, &mut self.target) {
+            (Some(model), Some(target)) => (model, target),
+            _ => return Ok(()),
+        };
+
+        let size = camera.world_size(center, self.gizmo.size);
+        let model = Mat4::from_translation(center)
+            * rotation.into_matrix().into_homogeneous()
+            * Mat4::from_scale(size);
+
+        let parameters = GizmoParameters {
+            mvp: camera.projection() * camera.calculate_view() * model,
+            colors: self.gizmo.axis_colors(),
+        };
+
+        let current = self.gizmo.mode;
+        let mode = GizmoMode::ALL
+            .iter()
+            .position(|mode| *mode == current)
+            .unwrap();
+
+        let pipeline = &*pipeline;
+        let handles = &self.handles[mode];
+
+        commands.record(
+            commandbuffer,
+            frame_index,
+            view_index,
+            inheritance,
+            |commandbuffer| {
+                commandbuffer.bind_pipeline(pipeline);
+                commandbuffer.set_viewport_rect(viewport);
+                commandbuffer.push_constants(
+                    pipeline,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    &parameters,
+                );
+                commandbuffer.bind_vertexbuffers(0, &[handles.buffer()]);
+                commandbuffer.draw(handles.len() as u32, 1, 0, 0);
+            },
+        )
+    }
+}
+
+impl Pass for GizmoRenderer {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn stage(&self) -> PassStage {
+        PassStage::View
+    }
+
+    fn setup(
+        &mut self,
+        context: &Rc<VulkanContext>,
+        targets: &mut PassTargets,
+    ) -> Result<(), vulkan::Error> {
+        let pipeline = targets.create_pipeline(pipeline_info(targets.samples))?;
+        let commands = ViewCommands::new(context.clone(), targets.frames_in_flight)?;
+
+        self.target = Some((pipeline, commands));
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        frame: &PassFrame,
+        scene: &Scene,
+    ) -> Result<(), vulkan::Error> {
+        let (index, camera, viewport) = match frame.current_view() {
+            Some(view) => view,
+            None => return Ok(()),
+        };
+
+        self.update(scene);
+        self.draw(
+            commandbuffer,
+            frame.frame_index,
+            index,
+            &camera,
+            viewport,
+            frame.inheritance,
+        )
+    }
+}
@@ -0,0 +1,438 @@
+//! Golden image regression tests, which render a scene offscreen at a fixed resolution and
+//! compare the result against a stored reference image, e.g;
+//!
+//! ```ignore
+//! #[test]
+//! fn lit_cube() {
+//!     let info = GoldenInfo::default();
+//!
+//!     golden::assert_matches("lit_cube", &info, |master_renderer, resources, scene| {
+//!         // Load the effects and add the objects and lights
+//!         Ok(Camera::perspective(Vec3::new(0.0, 2.0, 5.0), 1.0, 1.0, 0.1, 100.0))
+//!     });
+//! }
+//! ```
+//!
+//! A missing reference is written from the rendered image, as are all references when
+//! `UPDATE_VAR` is set. On mismatch the rendered image and the difference to the reference are
+//! written next to the reference for inspection.
+//!
+//! The scene is drawn through a render target in a hidden window, advancing a fixed timestep
+//! each frame, so the output only depends on the scene and the device. The tests in `tests/`
+//! which render require the `gpu-tests` feature.
+use std::env;
+use std::error::Error as StdError;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use thiserror::Error;
+
+use crate::capture;
+use crate::master_renderer::{MasterRenderer, MasterRendererInfo};
+use crate::render_target::RenderTargetInfo;
+use crate::resources::ResourceManager;
+use crate::vulkan::{self, device, Extent, VulkanContext};
+use crate::{Camera, Scene, Viewport};
+
+/// Set to write the rendered images as the new references instead of comparing
+pub const UPDATE_VAR: &str = "UPDATE_GOLDEN";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to read reference {path:?}: {source}")]
+    Read {
+        path: PathBuf,
+        source: png::DecodingError,
+    },
+    #[error("Reference {path:?} is not an 8 bit RGBA image")]
+    UnsupportedReference { path: PathBuf },
+    #[error("{0}")]
+    Write(#[from] capture::Error),
+    #[error("Render target format {0:?} can not be read back")]
+    UnsupportedFormat(vulkan::texture::Format),
+}
+
+/// How much a rendered image may differ from its reference, e.g; from differences in
+/// rasterization and precision between devices
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    /// The largest difference of any channel for a pixel to still match
+    pub channel: u8,
+    /// The fraction of pixels allowed to not match
+    pub pixels: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            channel: 2,
+            pixels: 0.001,
+        }
+    }
+}
+
+/// Specifies how golden images are rendered and compared
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenInfo {
+    /// The resolution of the rendered image
+    pub extent: Extent,
+    /// The number of frames drawn before the image is read, which lets temporal effects such
+    /// as streamed textures settle
+    pub frames: u32,
+    /// The time in seconds passed to each frame
+    pub timestep: f32,
+    /// The directory of the reference images
+    pub directory: PathBuf,
+    pub tolerance: Tolerance,
+    /// The color the image is cleared to before drawing
+    pub clear_color: [f32; 4],
+}
+
+impl Default for GoldenInfo {
+    fn default() -> Self {
+        Self {
+            extent: (256, 256).into(),
+            frames: 3,
+            timestep: 1.0 / 60.0,
+            directory: "tests/golden".into(),
+            tolerance: Tolerance::default(),
+            clear_color: [0.0, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+/// An 8 bit RGBA image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenImage {
+    pub extent: Extent,
+    pub pixels: Vec<u8>,
+}
+
+/// The differing pixels of two images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Comparison {
+    /// The number of pixels differing by more than the channel tolerance. Every pixel if the
+    /// sizes differ.
+    pub mismatched: usize,
+    pub pixels: usize,
+    /// The largest difference of any channel
+    pub max_difference: u8,
+}
+
+impl Comparison {
+    /// Returns true if few enough pixels differ
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.mismatched as f32 <= self.pixels as f32 * tolerance.pixels
+    }
+}
+
+impl GoldenImage {
+    /// Loads an 8 bit RGBA PNG image
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let read_error = |source| Error::Read {
+            path: path.to_owned(),
+            source,
+        };
+
+        let file = File::open(path).map_err(|e| read_error(e.into()))?;
+        let (info, mut reader) = png::Decoder::new(file).read_info().map_err(read_error)?;
+
+        if info.color_type != png::ColorType::RGBA || info.bit_depth != png::BitDepth::Eight {
+            return Err(Error::UnsupportedReference {
+                path: path.to_owned(),
+            });
+        }
+
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels).map_err(read_error)?;
+
+        Ok(Self {
+            extent: Extent::new(info.width, info.height),
+            pixels,
+        })
+    }
+
+    /// Writes the image as a PNG, creating the parent directories
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|source| capture::Error::Io {
+                path: parent.to_owned(),
+                source,
+            })?;
+        }
+
+        capture::write_png(path, self.extent, &self.pixels)?;
+        Ok(())
+    }
+
+    /// Compares the image against `reference`. Pixels differing by at most `channel` in every
+    /// channel match.
+    pub fn compare(&self, reference: &GoldenImage, channel: u8) -> Comparison {
+        let pixels = (self.extent.width * self.extent.height) as usize;
+
+        if self.extent != reference.extent {
+            return Comparison {
+                mismatched: pixels,
+                pixels,
+                max_difference: u8::MAX,
+            };
+        }
+
+        self.pixels
+            .chunks_exact(4)
+            .zip(reference.pixels.chunks_exact(4))
+            .map(|(a, b)| {
+                a.iter()
+                    .zip(b)
+                    .map(|(a, b)| a.abs_diff(*b))
+                    .max()
+                    .unwrap_or_default()
+            })
+            .fold(
+                Comparison {
+                    mismatched: 0,
+                    pixels,
+                    max_difference: 0,
+                },
+                |comparison, difference| Comparison {
+                    mismatched: comparison.mismatched + (difference > channel) as usize,
+                    max_difference: comparison.max_difference.max(difference),
+                    ..comparison
+                },
+            )
+    }
+
+    /// Returns the per channel difference to a same sized `other`, which is opaque black where
+    /// the images match
+    pub fn difference(&self, other: &GoldenImage) -> GoldenImage {
+        let pixels = self
+            .pixels
+            .chunks_exact(4)
+            .zip(other.pixels.chunks_exact(4))
+            .flat_map(|(a, b)| {
+                [
+                    a[0].abs_diff(b[0]),
+                    a[1].abs_diff(b[1]),
+                    a[2].abs_diff(b[2]),
+                    u8::MAX,
+                ]
+            })
+            .collect();
+
+        GoldenImage {
+            extent: self.extent,
+            pixels,
+        }
+    }
+}
+
+/// Renders the scene populated by `setup` from the returned camera in a hidden window. `setup`
+/// is responsible for loading the effects of the materials through the master renderer.
+pub fn render<F>(info: &GoldenInfo, setup: F) -> Result<GoldenImage, Box<dyn StdError>>
+where
+    F: FnOnce(
+        &mut MasterRenderer,
+        &mut ResourceManager,
+        &mut Scene,
+    ) -> Result<Camera, Box<dyn StdError>>,
+{
+    let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS)?;
+
+    glfw.window_hint(glfw::WindowHint::ClientApi(glfw::ClientApiHint::NoApi));
+    glfw.window_hint(glfw::WindowHint::Visible(false));
+
+    let (window, _events) = glfw
+        .create_window(
+            info.extent.width,
+            info.extent.height,
+            "Golden",
+            glfw::WindowMode::Windowed,
+        )
+        .ok_or("Failed to create window")?;
+
+    let context = Rc::new(VulkanContext::new(&glfw, &window)?);
+
+    let mut master_renderer =
+        MasterRenderer::new(context.clone(), &window, MasterRendererInfo::default())?;
+
+    let mut resources = ResourceManager::new(context.clone());
+    let mut scene = Scene::new();
+
+    let camera = setup(&mut master_renderer, &mut resources, &mut scene)?;
+
+    let target = master_renderer.create_render_target(
+        &mut resources,
+        "golden",
+        RenderTargetInfo {
+            camera,
+            extent: info.extent,
+            clear_color: info.clear_color,
+            ..Default::default()
+        },
+    )?;
+
+    for _ in 0..info.frames.max(1) {
+        master_renderer.draw(
+            &window,
+            info.timestep,
+            &[(camera, Viewport::full())],
+            &mut scene,
+            &resources,
+        )?;
+    }
+
+    device::wait_idle(context.device())?;
+
+    let texture = master_renderer.render_targets().raw(target)?.texture();
+    let texture = resources.textures().raw(texture)?;
+
+    if !capture::is_supported(texture.format()) {
+        return Err(Error::UnsupportedFormat(texture.format()).into());
+    }
+
+    let extent = texture.extent();
+    let pixels = texture.read(extent.width as u64 * extent.height as u64 * 4)?;
+
+    Ok(GoldenImage {
+        extent,
+        pixels: capture::to_rgba(texture.format(), &pixels),
+    })
+}
+
+/// Renders the scene populated by `setup` and panics if it differs from the reference image
+/// `name` beyond the tolerance. Writes the reference if it is missing or `UPDATE_VAR` is set.
+pub fn assert_matches<F>(name: &str, info: &GoldenInfo, setup: F)
+where
+    F: FnOnce(
+        &mut MasterRenderer,
+        &mut ResourceManager,
+        &mut Scene,
+    ) -> Result<Camera, Box<dyn StdError>>,
+{
+    let image =
+        render(info, setup).unwrap_or_else(|e| panic!("Failed to render {:?}: {}", name, e));
+
+    let path = info.directory.join(format!("{}.png", name));
+
+    if env::var_os(UPDATE_VAR).is_some() || !path.exists() {
+        image
+            .save(&path)
+            .unwrap_or_else(|e| panic!("Failed to write reference {:?}: {}", name, e));
+
+        log::info!("Wrote reference {:?}", path);
+        return;
+    }
+
+    let reference =
+        GoldenImage::load(&path).unwrap_or_else(|e| panic!("Failed to load {:?}: {}", name, e));
+
+    let comparison = image.compare(&reference, info.tolerance.channel);
+
+    if comparison.passes(&info.tolerance) {
+        return;
+    }
+
+    let actual = info.directory.join(format!("{}.actual.png", name));
+    let diff = info.directory.join(format!("{}.diff.png", name));
+
+    if let Err(e) = image.save(&actual) {
+        log::error!("Failed to write {:?}: {}", actual, e);
+    }
+
+    if image.extent == reference.extent {
+        if let Err(e) = image.difference(&reference).save(&diff) {
+            log::error!("Failed to write {:?}: {}", diff, e);
+        }
+    }
+
+    panic!(
+        "{:?} differs from the reference {:?}: {} of {} pixels differ by up to {}, see {:?}",
+        name, path, comparison.mismatched, comparison.pixels, comparison.max_difference, actual
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, pixel: [u8; 4]) -> GoldenImage {
+        GoldenImage {
+            extent: Extent::new(width, height),
+            pixels: pixel.repeat((width * height) as usize),
+        }
+    }
+
+    #[test]
+    fn compare_identical() {
+        let a = image(4, 4, [10, 20, 30, 255]);
+        let comparison = a.compare(&a.clone(), 0);
+
+        assert_eq!(
+            comparison,
+            Comparison {
+                mismatched: 0,
+                pixels: 16,
+                max_difference: 0,
+            }
+        );
+        assert!(comparison.passes(&Tolerance::default()));
+    }
+
+    #[test]
+    fn compare_channel_tolerance() {
+        let reference = image(2, 2, [100, 100, 100, 255]);
+        let mut a = reference.clone();
+        a.pixels[0] = 102;
+        a.pixels[6] = 97;
+
+        let comparison = a.compare(&reference, 2);
+        assert_eq!(comparison.mismatched, 1);
+        assert_eq!(comparison.max_difference, 3);
+
+        assert_eq!(a.compare(&reference, 3).mismatched, 0);
+    }
+
+    #[test]
+    fn compare_different_sizes() {
+        let comparison = image(4, 2, [0; 4]).compare(&image(2, 4, [0; 4]), 255);
+
+        assert_eq!(comparison.mismatched, 8);
+        assert_eq!(comparison.max_difference, u8::MAX);
+        assert!(!comparison.passes(&Tolerance::default()));
+    }
+
+    #[test]
+    fn passes_pixel_fraction() {
+        let tolerance = Tolerance {
+            channel: 0,
+            pixels: 0.01,
+        };
+
+        let comparison = |mismatched| Comparison {
+            mismatched,
+            pixels: 1000,
+            max_difference: 1,
+        };
+
+        assert!(comparison(10).passes(&tolerance));
+        assert!(!comparison(11).passes(&tolerance));
+        assert!(!comparison(1).passes(&Tolerance {
+            pixels: 0.0,
+            ..tolerance
+        }));
+    }
+
+    #[test]
+    fn difference_is_opaque() {
+        let a = GoldenImage {
+            extent: Extent::new(2, 1),
+            pixels: vec![10, 20, 30, 0, 5, 5, 5, 255],
+        };
+        let b = GoldenImage {
+            extent: Extent::new(2, 1),
+            pixels: vec![15, 20, 0, 255, 5, 5, 5, 0],
+        };
+
+        assert_eq!(a.difference(&b).pixels, [5, 0, 30, 255, 0, 0, 0, 255]);
+    }
+}
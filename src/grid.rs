@@ -0,0 +1,178 @@
+//! An editor style grid on a horizontal ground plane extending to the horizon, for spatial
+//! orientation in scenes without a floor. Drawn as a fullscreen triangle whose fragments are
+//! intersected with the plane, see `data/shaders/grid.frag`.
+use std::rc::Rc;
+
+use ash::vk;
+use ultraviolet::{Mat4, Vec4};
+
+use crate::color::Color;
+use crate::fullscreen::{self, FullscreenPass};
+use crate::pass::{Pass, PassFrame, PassStage, PassTargets};
+use crate::{Camera, Scene};
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::pipeline::{BlendState, PipelineInfo};
+use vulkan::*;
+
+/// Specifies the spacing, colors and extent of the grid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridInfo {
+    /// The height of the ground plane
+    pub height: f32,
+    /// The distance between minor lines
+    pub cell_size: f32,
+    /// The number of cells between major lines
+    pub major_interval: u32,
+    /// The distance from the camera at which the grid has faded out entirely
+    pub fade_distance: f32,
+    pub color: Color,
+    pub major_color: Color,
+}
+
+impl Default for GridInfo {
+    fn default() -> Self {
+        Self {
+            height: 0.0,
+            cell_size: 1.0,
+            major_interval: 10,
+            fade_distance: 100.0,
+            color: Color::rgba(128, 128, 128, 96),
+            major_color: Color::rgba(192, 192, 192, 160),
+        }
+    }
+}
+
+/// The name of the grid pass
+pub const NAME: &str = "grid";
+
+/// The push constants of the fragment shader
+#[repr(C)]
+struct GridParameters {
+    inv_view_projection: Mat4,
+    color: Vec4,
+    major_color: Vec4,
+    cell_size: f32,
+    major_interval: f32,
+    fade_distance: f32,
+    height: f32,
+}
+
+/// Returns the info of the grid pipeline for attachments of `samples`. The grid is blended over
+/// the scene and writes the depth of the plane.
+pub fn pipeline_info(samples: vk::SampleCountFlags) -> PipelineInfo {
+    PipelineInfo {
+        blend: BlendState::alpha(),
+        ..fullscreen::pipeline_info("./data/shaders/grid.frag.spv", samples)
+    }
+}
+
+/// Draws the grid after the objects of each view as a `PassStage::View` pass
+pub struct GridRenderer {
+    /// None until the pass is set up
+    pass: Option<FullscreenPass>,
+    info: GridInfo,
+}
+
+impl GridRenderer {
+    /// Creates a grid renderer, whose pipeline is created when it is set up as a pass
+    pub fn new(info: GridInfo) -> Self {
+        Self { pass: None, info }
+    }
+
+    pub fn info(&self) -> &GridInfo {
+        &self.info
+    }
+
+    /// Sets the spacing and colors of the grid, which take effect on the next draw
+    pub fn set_info(&mut self, info: GridInfo) {
+        self.info = info
+    }
+
+    /// Draws the grid as seen by `camera` into `viewport`. Needs to be recorded within the pass
+    /// after the objects of the view, since the grid is blended over them. If `inheritance` is
+    /// Some the grid is recorded into a secondary command buffer of the view, which requires
+    /// the previous submission of the frame in flight `frame_index` to have completed.
+    /// Does nothing before the pass is set up.
+    pub fn draw(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        frame_index: usize,
+        view_index: usize,
+        camera: &Camera,
+        viewport: vk::Rect2D,
+        inheritance: Option<&Inheritance>,
+    ) -> Result<(), vulkan::Error> {
+        let pass = match &mut self.pass {
+            Some(pass) => pass,
+            None => return Ok(()),
+        };
+
+        let view_projection = camera.projection() * camera.calculate_view();
+
+        let parameters = GridParameters {
+            inv_view_projection: view_projection.inversed(),
+            color: self.info.color.to_linear_vec4(),
+            major_color: self.info.major_color.to_linear_vec4(),
+            cell_size: self.info.cell_size,
+            major_interval: self.info.major_interval as f32,
+            fade_distance: self.info.fade_distance,
+            height: self.info.height,
+        };
+
+        pass.draw(
+            commandbuffer,
+            frame_index,
+            view_index,
+            viewport,
+            inheritance,
+            &parameters,
+        )
+    }
+}
+
+impl Pass for GridRenderer {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn stage(&self) -> PassStage {
+        PassStage::View
+    }
+
+    fn setup(
+        &mut self,
+        context: &Rc<VulkanContext>,
+        targets: &mut PassTargets,
+    ) -> Result<(), vulkan::Error> {
+        let pipeline = targets.create_pipeline(pipeline_info(targets.samples))?;
+
+        self.pass = Some(FullscreenPass::new(
+            context.clone(),
+            pipeline,
+            targets.frames_in_flight,
+        )?);
+
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        frame: &PassFrame,
+        _scene: &Scene,
+    ) -> Result<(), vulkan::Error> {
+        match frame.current_view() {
+            Some((index, camera, viewport)) => self.draw(
+                commandbuffer,
+                frame.frame_index,
+                index,
+                &camera,
+                viewport,
+                frame.inheritance,
+            ),
+            None => Ok(()),
+        }
+    }
+}
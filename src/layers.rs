@@ -0,0 +1,75 @@
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not};
+
+/// A set of the 32 layers of a scene. Objects are placed in one or more layers, and are only
+/// drawn by cameras and passes whose layers include any of them, e.g; an overlay layer only
+/// drawn by an orthographic camera, or decorative objects excluded from casting shadows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Layers(pub u32);
+
+impl Layers {
+    /// The layer objects are placed in unless specified otherwise
+    pub const DEFAULT: Self = Self::layer(0);
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self(!0);
+
+    /// Returns the set of only the layer at `index`, which needs to be less than 32.
+    pub const fn layer(index: u32) -> Self {
+        Self(1 << index)
+    }
+
+    /// Returns true if the sets share any layer
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Returns true if all layers of `other` are in the set
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl BitOr for Layers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Layers {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0
+    }
+}
+
+impl BitAnd for Layers {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl BitAndAssign for Layers {
+    fn bitand_assign(&mut self, rhs: Self) {
+        self.0 &= rhs.0
+    }
+}
+
+impl Not for Layers {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
@@ -1,21 +1,68 @@
+pub mod aabb;
+pub mod animation;
+pub mod audio;
+pub mod benchmark;
+pub mod bind_group;
+pub mod bvh;
 pub mod camera;
+pub mod capture;
 pub mod clock;
 pub mod color;
+pub mod display;
 pub mod document;
+pub mod ecs;
+pub mod environment;
 pub mod errors;
+pub mod fog;
+pub mod frame_pacing;
+pub mod frustum;
+pub mod fullscreen;
+pub mod gizmo;
+pub mod golden;
+pub mod grid;
+pub mod layers;
+pub mod light;
+pub mod light_culling;
+pub mod lod;
 pub mod logger;
 pub mod master_renderer;
 pub mod material;
 pub mod mesh;
 pub mod mesh_renderer;
 pub mod object;
+pub mod pass;
+pub mod physics;
+pub mod picking_renderer;
+pub mod point_shadow;
+pub mod ray_query;
+pub mod raycast;
+pub mod render_target;
+#[cfg(feature = "renderdoc")]
+pub mod renderdoc;
 pub mod resources;
 pub mod scene;
+pub mod shading_rate;
+pub mod shadow;
+pub mod sky;
+pub mod stereo;
+pub mod terrain;
+pub mod view_commands;
+pub mod viewport;
 pub mod vulkan;
+pub mod water;
+#[cfg(feature = "openxr")]
+pub mod xr;
 
+pub use aabb::Aabb;
 pub use camera::*;
 pub use errors::*;
+pub use frustum::Frustum;
+pub use layers::Layers;
+pub use light::*;
+pub use lod::*;
 pub use material::*;
 pub use mesh::*;
 pub use object::*;
+pub use raycast::{Ray, RayHit};
 pub use scene::*;
+pub use viewport::*;
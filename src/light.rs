@@ -0,0 +1,24 @@
+use ultraviolet::Vec3;
+
+/// A point light without shadows. Lights are culled into screen tiles each frame, which keeps
+/// the cost of many small lights proportional to the area they cover. See `light_culling`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub position: Vec3,
+    /// Linear RGB color
+    pub color: Vec3,
+    pub intensity: f32,
+    /// The distance at which the light reaches zero intensity
+    pub range: f32,
+}
+
+impl Light {
+    pub fn new(position: Vec3, color: Vec3, intensity: f32, range: f32) -> Self {
+        Self {
+            position,
+            color,
+            intensity,
+            range,
+        }
+    }
+}
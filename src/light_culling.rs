@@ -0,0 +1,457 @@
+//! Tiled forward lighting. A compute pass bins the lights of the scene into screen tiles of each
+//! view before the main pass, and the lighting shaders only iterate the lights of the tile of
+//! each fragment. See `data/shaders/light_culling.comp` and `data/shaders/lights.glsl`.
+use ash::vk;
+use std::{mem, rc::Rc, slice};
+use ultraviolet::{Mat4, Vec4};
+
+use crate::{Camera, Scene};
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::descriptors::*;
+use vulkan::pipeline::{ComputePipeline, ComputePipelineInfo};
+use vulkan::*;
+
+/// The descriptor set index of the light set in material effect shaders. Holds the light grid
+/// uniform at binding 0, the lights at binding 1, and the light indices and counts of each tile
+/// at binding 2 and 3.
+pub const LIGHT_SET: u32 = 4;
+
+/// The width and height in pixels of each tile. Matches the workgroup size of the culling
+/// shader.
+pub const TILE_SIZE: u32 = 16;
+
+/// The maximum number of lights in the scene. Further lights are ignored.
+pub const MAX_LIGHTS: usize = 1024;
+
+/// The maximum number of lights affecting a single tile. Further lights are ignored.
+pub const MAX_LIGHTS_PER_TILE: usize = 128;
+
+/// A light as read by the shaders, matching the std430 `Light` struct of the shaders
+#[repr(C)]
+struct LightData {
+    /// The position in xyz and range in w
+    position: Vec4,
+    /// The color multiplied by the intensity in xyz
+    color: Vec4,
+}
+
+/// The light grid uniform, matching the std140 `LightGrid` block of the shaders
+#[derive(Default)]
+#[repr(C)]
+struct GridData {
+    view: Mat4,
+    inverse_projection: Mat4,
+    /// The top left corner of the viewport in pixels
+    viewport_offset: [f32; 2],
+    viewport_size: [f32; 2],
+    tile_count: [u32; 2],
+    light_count: u32,
+    _padding: u32,
+}
+
+/// Returns the number of tiles covering `extent` in each dimension.
+pub fn tile_count(extent: Extent) -> (u32, u32) {
+    (
+        extent.width.div_ceil(TILE_SIZE),
+        extent.height.div_ceil(TILE_SIZE),
+    )
+}
+
+/// The queue the upload and culling are recorded on, which determines the pipeline stages
+/// synchronized within the queue. Accesses from other queues are synchronized with semaphores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CullingQueue {
+    /// Culled in the same queue as the lighting shaders reading the grid
+    Graphics,
+    /// Culled on a dedicated compute queue, overlapping the rendering on the graphics queue
+    Compute,
+}
+
+impl CullingQueue {
+    fn stages(self) -> vk::PipelineStageFlags {
+        match self {
+            CullingQueue::Graphics => {
+                vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER
+            }
+            CullingQueue::Compute => vk::PipelineStageFlags::COMPUTE_SHADER,
+        }
+    }
+}
+
+/// Holds the lights of the scene and the culling pipeline shared by the light grids of all
+/// views.
+pub struct LightCulling {
+    pipeline: ComputePipeline,
+    light_buffer: Buffer,
+    light_count: usize,
+    sharing: QueueSharing,
+}
+
+impl LightCulling {
+    /// Creates the culling pipeline and light buffer. The buffers of the culling and the light
+    /// grids need `QueueSharing::Concurrent` to be culled on a dedicated compute queue.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        sharing: QueueSharing,
+    ) -> Result<Self, vulkan::Error> {
+        let pipeline = ComputePipeline::new(
+            context.device_ref(),
+            descriptor_layout_cache,
+            ComputePipelineInfo {
+                shader: "./data/shaders/light_culling.comp.spv".into(),
+                ..Default::default()
+            },
+        )?;
+
+        let light_buffer = Buffer::new_uninit_with_sharing(
+            context,
+            BufferType::Storage,
+            BufferUsage::Staged,
+            (mem::size_of::<LightData>() * MAX_LIGHTS) as u64,
+            sharing,
+        )?;
+
+        Ok(Self {
+            pipeline,
+            light_buffer,
+            light_count: 0,
+            sharing,
+        })
+    }
+
+    /// Records the upload of the first `MAX_LIGHTS` lights of the scene into `commandbuffer` of
+    /// `queue`. Needs to be recorded before culling the light grids of the frame.
+    pub fn update(&mut self, commandbuffer: &CommandBuffer, scene: &Scene, queue: CullingQueue) {
+        let lights = scene
+            .lights()
+            .iter()
+            .take(MAX_LIGHTS)
+            .map(|light| {
+                let position = light.position;
+                let color = light.color * light.intensity;
+                LightData {
+                    position: Vec4::new(position.x, position.y, position.z, light.range),
+                    color: Vec4::new(color.x, color.y, color.z, 0.0),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.light_count = lights.len();
+
+        if !lights.is_empty() {
+            update_buffer_in(commandbuffer, &self.light_buffer, &lights, queue.stages());
+        }
+    }
+
+    /// Returns the sharing of the light buffers
+    pub fn sharing(&self) -> QueueSharing {
+        self.sharing
+    }
+
+    /// Returns the number of lights uploaded by the last update.
+    pub fn light_count(&self) -> usize {
+        self.light_count
+    }
+}
+
+/// The lights affecting each screen tile of a view, culled each frame. Bound at `LIGHT_SET`
+/// when drawing the view.
+pub struct LightGrid {
+    context: Rc<VulkanContext>,
+    /// Written by the culling shader
+    compute_set: DescriptorSet,
+    /// Read by the lighting shaders
+    set: DescriptorSet,
+    uniform_buffer: Buffer,
+    /// `MAX_LIGHTS_PER_TILE` light indices for each tile
+    tile_lights: Buffer,
+    tile_counts: Buffer,
+    /// The extent the tile buffers fit
+    extent: Extent,
+}
+
+impl LightGrid {
+    /// Creates a grid for views within `extent`.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        descriptor_allocator: &mut DescriptorAllocator,
+        culling: &LightCulling,
+        extent: Extent,
+    ) -> Result<Self, vulkan::Error> {
+        let uniform_buffer = Buffer::new_uninit_with_sharing(
+            context.clone(),
+            BufferType::Uniform,
+            BufferUsage::Staged,
+            mem::size_of::<GridData>() as u64,
+            culling.sharing,
+        )?;
+
+        let (tile_lights, tile_counts) = create_tile_buffers(&context, extent, culling.sharing)?;
+
+        let mut compute_set = Default::default();
+        let mut set = Default::default();
+
+        bind_grid(
+            &mut DescriptorBuilder::new(),
+            vk::ShaderStageFlags::COMPUTE,
+            &uniform_buffer,
+            &culling.light_buffer,
+            &tile_lights,
+            &tile_counts,
+        )
+        .build(
+            context.device(),
+            descriptor_layout_cache,
+            descriptor_allocator,
+            &mut compute_set,
+        )?;
+
+        bind_grid(
+            &mut DescriptorBuilder::new(),
+            vk::ShaderStageFlags::FRAGMENT,
+            &uniform_buffer,
+            &culling.light_buffer,
+            &tile_lights,
+            &tile_counts,
+        )
+        .build(
+            context.device(),
+            descriptor_layout_cache,
+            descriptor_allocator,
+            &mut set,
+        )?;
+
+        Ok(Self {
+            context,
+            compute_set,
+            set,
+            uniform_buffer,
+            tile_lights,
+            tile_counts,
+            extent,
+        })
+    }
+
+    /// Recreates the tile buffers to fit views within `extent` and updates the sets in place.
+    /// The sets must not be in use by the device, and recorded command buffers binding them are
+    /// invalidated.
+    pub fn resize(&mut self, culling: &LightCulling, extent: Extent) -> Result<(), vulkan::Error> {
+        let (tile_lights, tile_counts) =
+            create_tile_buffers(&self.context, extent, culling.sharing)?;
+        self.tile_lights = tile_lights;
+        self.tile_counts = tile_counts;
+        self.extent = extent;
+
+        let device = self.context.device();
+
+        for (stage, set) in &[
+            (vk::ShaderStageFlags::COMPUTE, self.compute_set),
+            (vk::ShaderStageFlags::FRAGMENT, self.set),
+        ] {
+            bind_grid(
+                &mut DescriptorBuilder::new(),
+                *stage,
+                &self.uniform_buffer,
+                &culling.light_buffer,
+                &self.tile_lights,
+                &self.tile_counts,
+            )
+            .update(device, *set);
+        }
+
+        Ok(())
+    }
+
+    /// Records the culling of the lights of `culling` against the tiles of the view of `camera`
+    /// covering `rect` into `commandbuffer` of `queue`. Needs to be recorded outside of a
+    /// renderpass.
+    pub fn cull(
+        &self,
+        commandbuffer: &CommandBuffer,
+        culling: &LightCulling,
+        camera: &Camera,
+        rect: vk::Rect2D,
+        queue: CullingQueue,
+    ) {
+        let extent = Extent::new(
+            rect.extent.width.min(self.extent.width),
+            rect.extent.height.min(self.extent.height),
+        );
+
+        let (x, y) = tile_count(extent);
+
+        let data = GridData {
+            view: camera.calculate_view(),
+            inverse_projection: camera.projection().inversed(),
+            viewport_offset: [rect.offset.x as f32, rect.offset.y as f32],
+            viewport_size: [extent.width as f32, extent.height as f32],
+            tile_count: [x, y],
+            light_count: culling.light_count as u32,
+            _padding: 0,
+        };
+
+        let stages = queue.stages();
+        update_buffer_in(
+            commandbuffer,
+            &self.uniform_buffer,
+            slice::from_ref(&data),
+            stages,
+        );
+
+        let barrier = |buffer: &Buffer, src_access_mask, dst_access_mask| vk::BufferMemoryBarrier {
+            src_access_mask,
+            dst_access_mask,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: buffer.buffer(),
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        };
+
+        // Waits for the previous frame to finish reading the tiles
+        commandbuffer.buffer_barrier(
+            stages,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            &[
+                barrier(
+                    &self.tile_lights,
+                    vk::AccessFlags::SHADER_READ,
+                    vk::AccessFlags::SHADER_WRITE,
+                ),
+                barrier(
+                    &self.tile_counts,
+                    vk::AccessFlags::SHADER_READ,
+                    vk::AccessFlags::SHADER_WRITE,
+                ),
+            ],
+        );
+
+        commandbuffer.bind_compute_pipeline(&culling.pipeline);
+        commandbuffer.bind_compute_descriptor_sets(&culling.pipeline, 0, &[self.compute_set], &[]);
+        commandbuffer.dispatch(x, y, 1);
+
+        commandbuffer.buffer_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            stages,
+            &[
+                barrier(
+                    &self.tile_lights,
+                    vk::AccessFlags::SHADER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+                barrier(
+                    &self.tile_counts,
+                    vk::AccessFlags::SHADER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+            ],
+        );
+    }
+
+    /// Returns the descriptor set bound at `LIGHT_SET`.
+    pub fn set(&self) -> DescriptorSet {
+        self.set
+    }
+
+    /// Returns the extent the grid fits.
+    pub fn extent(&self) -> Extent {
+        self.extent
+    }
+}
+
+fn create_tile_buffers(
+    context: &Rc<VulkanContext>,
+    extent: Extent,
+    sharing: QueueSharing,
+) -> Result<(Buffer, Buffer), vulkan::Error> {
+    let (x, y) = tile_count(extent);
+    let tiles = (x * y).max(1) as u64;
+
+    let tile_lights = Buffer::new_uninit_with_sharing(
+        context.clone(),
+        BufferType::Storage,
+        BufferUsage::Staged,
+        tiles * MAX_LIGHTS_PER_TILE as u64 * mem::size_of::<u32>() as u64,
+        sharing,
+    )?;
+
+    let tile_counts = Buffer::new_uninit_with_sharing(
+        context.clone(),
+        BufferType::Storage,
+        BufferUsage::Staged,
+        tiles * mem::size_of::<u32>() as u64,
+        sharing,
+    )?;
+
+    Ok((tile_lights, tile_counts))
+}
+
+// Binds the buffers of a light grid in the layout shared by the culling and lighting shaders
+fn bind_grid<'a>(
+    builder: &'a mut DescriptorBuilder,
+    stage: vk::ShaderStageFlags,
+    uniform_buffer: &Buffer,
+    light_buffer: &Buffer,
+    tile_lights: &Buffer,
+    tile_counts: &Buffer,
+) -> &'a mut DescriptorBuilder {
+    builder
+        .bind_uniform_buffer(0, stage, uniform_buffer)
+        .bind_storage_buffer(1, stage, light_buffer)
+        .bind_storage_buffer(2, stage, tile_lights)
+        .bind_storage_buffer(3, stage, tile_counts)
+}
+
+/// Records an update of a buffer read by compute or fragment shaders in order with the other
+/// commands, which keeps the previous frame reading the previous contents. Needs to be recorded
+/// outside of a renderpass, and `data` may not be larger than 65536 bytes.
+pub fn update_buffer<T>(commandbuffer: &CommandBuffer, buffer: &Buffer, data: &[T]) {
+    update_buffer_in(commandbuffer, buffer, data, CullingQueue::Graphics.stages())
+}
+
+/// Records an update of a buffer read by `stages` of the queue, e.g; by vertex shaders. See
+/// `update_buffer`.
+pub fn update_buffer_in<T>(
+    commandbuffer: &CommandBuffer,
+    buffer: &Buffer,
+    data: &[T],
+    stages: vk::PipelineStageFlags,
+) {
+    let access = match buffer.ty() {
+        BufferType::Uniform => vk::AccessFlags::UNIFORM_READ,
+        _ => vk::AccessFlags::SHADER_READ,
+    };
+
+    let barrier = vk::BufferMemoryBarrier {
+        src_access_mask: access,
+        dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        buffer: buffer.buffer(),
+        offset: 0,
+        size: vk::WHOLE_SIZE,
+        ..Default::default()
+    };
+
+    commandbuffer.buffer_barrier(stages, vk::PipelineStageFlags::TRANSFER, &[barrier]);
+
+    let bytes =
+        unsafe { slice::from_raw_parts(data.as_ptr() as *const u8, mem::size_of_val(data)) };
+
+    commandbuffer.update_buffer(buffer.buffer(), 0, bytes);
+
+    commandbuffer.buffer_barrier(
+        vk::PipelineStageFlags::TRANSFER,
+        stages,
+        &[vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: access,
+            ..barrier
+        }],
+    );
+}
@@ -0,0 +1,86 @@
+use arrayvec::ArrayVec;
+
+use crate::{mesh::Mesh, resources::Handle};
+
+/// The maximum number of levels in a LOD chain
+pub const MAX_LOD_LEVELS: usize = 8;
+
+/// The suffix separating a mesh name from the LOD level in glTF documents. E.g; 'Rock_LOD1'
+pub const LOD_SUFFIX: &str = "_LOD";
+
+/// A single level of detail
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodLevel {
+    pub mesh: Handle<Mesh>,
+    /// The minimum fraction of the screen height the object needs to cover for the level to be
+    /// used.
+    pub min_coverage: f32,
+}
+
+/// An ordered chain of meshes from most to least detailed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodChain {
+    levels: ArrayVec<[LodLevel; MAX_LOD_LEVELS]>,
+}
+
+impl LodChain {
+    /// Creates a LOD chain from meshes ordered from most to least detailed. Each level is used
+    /// down to half the screen coverage of the previous level, starting at half the screen.
+    /// Panics if `meshes` is empty or contains more than `MAX_LOD_LEVELS` meshes.
+    pub fn new(meshes: &[Handle<Mesh>]) -> Self {
+        let mut min_coverage = 0.5;
+
+        let levels = meshes
+            .iter()
+            .map(|mesh| {
+                let level = LodLevel {
+                    mesh: *mesh,
+                    min_coverage,
+                };
+
+                min_coverage *= 0.5;
+                level
+            })
+            .collect();
+
+        Self::from_levels(levels)
+    }
+
+    /// Creates a LOD chain from levels with custom coverage thresholds.
+    /// The last level is used for all coverages below the thresholds of the other levels.
+    /// Panics if `levels` is empty.
+    pub fn from_levels(levels: ArrayVec<[LodLevel; MAX_LOD_LEVELS]>) -> Self {
+        assert!(
+            !levels.is_empty(),
+            "A LOD chain requires at least one level"
+        );
+        Self { levels }
+    }
+
+    /// Returns the mesh of the most detailed level whose minimum coverage is satisfied
+    pub fn select(&self, coverage: f32) -> Handle<Mesh> {
+        self.levels
+            .iter()
+            .find(|level| coverage >= level.min_coverage)
+            .unwrap_or(self.levels.last().unwrap())
+            .mesh
+    }
+
+    /// Returns the mesh of the most detailed level
+    pub fn base(&self) -> Handle<Mesh> {
+        self.levels[0].mesh
+    }
+
+    pub fn levels(&self) -> &[LodLevel] {
+        &self.levels
+    }
+}
+
+/// Splits a mesh name following the '<name>_LOD<level>' naming convention into the base name
+/// and level. Returns None if the name does not follow the convention.
+pub fn parse_lod_name(name: &str) -> Option<(&str, usize)> {
+    let index = name.rfind(LOD_SUFFIX)?;
+    let level = name[index + LOD_SUFFIX.len()..].parse().ok()?;
+
+    Some((&name[..index], level))
+}
@@ -1,22 +1,46 @@
 use log::*;
-use master_renderer::MasterRenderer;
+use master_renderer::{MasterRenderer, MasterRendererInfo};
+use mesh_renderer::MeshRendererInfo;
 use rand::prelude::*;
-use std::{error::Error, rc::Rc, thread, time::Duration};
-use ultraviolet::Vec3;
+use render_target::RenderTargetInfo;
+use std::{error::Error, path::Path, rc::Rc, thread, time::Duration};
+use ultraviolet::{Rotor3, Vec2, Vec3};
 
+use vulkan_sandbox::audio::{Audio, AudioInfo, Emitter, PlayInfo, Sound};
+use vulkan_sandbox::benchmark::{Benchmark, BenchmarkInfo};
 use vulkan_sandbox::camera::Camera;
+use vulkan_sandbox::capture::CaptureInfo;
 use vulkan_sandbox::clock::*;
+use vulkan_sandbox::document::Transform;
+use vulkan_sandbox::environment::EnvironmentInfo;
+use vulkan_sandbox::fog::{Fog, FogMode};
+use vulkan_sandbox::frame_pacing::{FrameLimit, ThrottleInfo};
+use vulkan_sandbox::gizmo::{Gizmo, GizmoMode};
+use vulkan_sandbox::grid::GridInfo;
+use vulkan_sandbox::physics::{Collider, ColliderShape, Physics, PhysicsInfo, RigidBody};
+use vulkan_sandbox::point_shadow::PointLightInfo;
+use vulkan_sandbox::shadow::ShadowInfo;
+use vulkan_sandbox::sky::SkyInfo;
+use vulkan_sandbox::stereo::StereoInfo;
+use vulkan_sandbox::terrain::{self, Heightmap, Terrain, TerrainInfo};
 use vulkan_sandbox::vulkan;
+use vulkan_sandbox::water::{Water, WaterInfo};
+#[cfg(feature = "openxr")]
+use vulkan_sandbox::xr::{XrInfo, XrSession, XrSystem};
 
 use vulkan::pipeline::*;
-use vulkan::VertexDesc;
+use vulkan::texture::{ColorSpace, Texture};
+use vulkan::{Extent, VertexDesc};
 
 use resources::*;
 use vulkan_sandbox::*;
 
 use vulkan::VulkanContext;
 
-use glfw::{self, Action, Key, WindowEvent};
+use glfw::{self, Action, Key, MouseButton, WindowEvent};
+
+/// The randomly spawned cubes, which are toggled with L and hidden from the overview
+const CUBE_LAYER: Layers = Layers::layer(1);
 
 fn main() -> Result<(), Box<dyn Error>> {
     logger::init();
@@ -33,12 +57,36 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     window.set_all_polling(true);
 
+    // Renders to a connected headset when built with the `openxr` feature
+    #[cfg(feature = "openxr")]
+    let xr_system = XrSystem::new(XrInfo::default())
+        .map_err(|e| info!("Not using a headset: {}", e))
+        .ok();
+
+    #[cfg(feature = "openxr")]
+    let context = Rc::new(match &xr_system {
+        Some(xr_system) => VulkanContext::new_with_info(
+            &glfw,
+            &window,
+            vulkan::ContextInfo {
+                instance_extensions: xr_system.instance_extensions()?,
+                device_extensions: xr_system.device_extensions()?,
+                ..Default::default()
+            },
+        )?,
+        None => VulkanContext::new(&glfw, &window)?,
+    });
+
+    #[cfg(not(feature = "openxr"))]
     let context = Rc::new(VulkanContext::new(&glfw, &window)?);
 
-    let clock = Clock::new();
     let mut frame_clock = Clock::new();
+    let mut elapsed = Duration::from_secs(0);
     let mut last_status = Clock::new();
     let mut last_spawn = Clock::new();
+    let mut spawned = 0;
+    let mut benchmark: Option<Benchmark> = None;
+    let mut bakes = 0;
 
     let aspect = 800.0 / 600.0;
     let mut perspective_camera =
@@ -48,38 +96,276 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let mut camera = &mut perspective_camera;
 
+    // Shown in the top right corner when picture-in-picture is enabled, without the cubes
+    let mut overview_camera =
+        Camera::perspective(Vec3::new(0.0, 0.0, 40.0), 1.0, aspect, 0.1, 1000.0);
+    overview_camera.layers = !CUBE_LAYER;
+    let mut picture_in_picture = false;
+
     let mut scene = Scene::new();
-    let mut master_renderer = MasterRenderer::new(context.clone(), &window)?;
+    let mut master_renderer = MasterRenderer::new(
+        context.clone(),
+        &window,
+        MasterRendererInfo {
+            mesh_renderer: MeshRendererInfo {
+                record_static: true,
+                ..Default::default()
+            },
+            grid: Some(GridInfo {
+                height: -2.0,
+                ..Default::default()
+            }),
+            sky: Some(SkyInfo::default()),
+            ..Default::default()
+        },
+    )?;
 
     let mut resources = ResourceManager::new(context.clone());
+    resources.set_texture_feedback(cfg!(debug_assertions));
+
+    // The cube geometry is kept for baking the spawned cubes into static batches
+    resources.load_document_with_settings(
+        "cube",
+        "./data/models/cube.gltf",
+        &MeshImportSettings {
+            keep_geometry: true,
+            ..Default::default()
+        },
+    )?;
 
-    resources.load_document("cube", "./data/models/cube.gltf")?;
-    resources.load_document("monkey", "./data/models/monkey.gltf")?;
-
-    let default_pass = Pipeline::new(
-        context.device_ref(),
-        &mut master_renderer.descriptor_layout_cache,
-        &master_renderer.renderpass,
-        PipelineInfo {
-            vertexshader: "./data/shaders/default.vert.spv".into(),
-            fragmentshader: "./data/shaders/default.frag.spv".into(),
-            vertex_binding: mesh::Vertex::binding_description(),
-            vertex_attributes: mesh::Vertex::attribute_descriptions(),
-            samples: context.msaa_samples(),
-            extent: master_renderer.swapchain.extent(),
-            subpass: 0,
+    // Later runs read the compiled meshes instead of the glTF primitives
+    resources.load_document_with_settings(
+        "monkey",
+        "./data/models/monkey.gltf",
+        &MeshImportSettings {
+            cache: true,
+            compress_cache: true,
             ..Default::default()
         },
     )?;
 
-    resources.load_effect("default", vec![default_pass])?;
+    let default_pass = master_renderer.create_pipeline(PipelineInfo {
+        vertexshader: "./data/shaders/default.vert.spv".into(),
+        fragmentshader: "./data/shaders/default.frag.spv".into(),
+        vertex_binding: mesh::Vertex::binding_description(),
+        vertex_attributes: mesh::Vertex::attribute_descriptions(),
+        samples: context.msaa_samples(),
+        subpass: 0,
+        ..Default::default()
+    })?;
+
+    let shadow_pass = master_renderer.create_shadow_pipeline(PipelineInfo {
+        vertexshader: "./data/shaders/shadow.vert.spv".into(),
+        fragmentshader: "./data/shaders/shadow.frag.spv".into(),
+        vertex_binding: mesh::Vertex::binding_description(),
+        vertex_attributes: mesh::Vertex::attribute_descriptions(),
+        ..Default::default()
+    })?;
+
+    let point_shadow_pass = master_renderer.create_shadow_pipeline(PipelineInfo {
+        vertexshader: "./data/shaders/point_shadow.vert.spv".into(),
+        fragmentshader: "./data/shaders/point_shadow.frag.spv".into(),
+        vertex_binding: mesh::Vertex::binding_description(),
+        vertex_attributes: mesh::Vertex::attribute_descriptions(),
+        ..Default::default()
+    })?;
+
+    let lit_pass = master_renderer.create_pipeline(PipelineInfo {
+        vertexshader: "./data/shaders/default.vert.spv".into(),
+        fragmentshader: "./data/shaders/lit.frag.spv".into(),
+        vertex_binding: mesh::Vertex::binding_description(),
+        vertex_attributes: mesh::Vertex::attribute_descriptions(),
+        samples: context.msaa_samples(),
+        subpass: 0,
+        ..Default::default()
+    })?;
+
+    let mut lit_passes = vec![
+        (PassTag::Shadow, shadow_pass),
+        (PassTag::PointShadow, point_shadow_pass),
+        (PassTag::Opaque, lit_pass),
+    ];
+
+    // Drawn for both eyes when the stereo view is toggled with V
+    if context.capabilities().features.multiview {
+        lit_passes.push((
+            PassTag::Stereo,
+            master_renderer.create_stereo_pipeline(PipelineInfo {
+                vertexshader: "./data/shaders/default.multiview.vert.spv".into(),
+                fragmentshader: "./data/shaders/lit.frag.spv".into(),
+                vertex_binding: mesh::Vertex::binding_description(),
+                vertex_attributes: mesh::Vertex::attribute_descriptions(),
+                samples: context.msaa_samples(),
+                subpass: 0,
+                ..Default::default()
+            })?,
+        ));
+    }
+
+    // The headset shows the eyes of the stereo view, which the window mirrors side by side
+    #[cfg(feature = "openxr")]
+    let mut xr_session = match xr_system {
+        Some(xr_system) => {
+            let extent = xr_system.eye_extent()?;
+            let xr_session = XrSession::new(context.clone(), xr_system)?;
+
+            master_renderer.set_stereo(Some(StereoInfo {
+                extent: Some(extent),
+                ..Default::default()
+            }))?;
+
+            Some(xr_session)
+        }
+        None => None,
+    };
+
+    resources.load_effect("default", vec![(PassTag::Opaque, default_pass)])?;
+    resources.load_effect("lit", lit_passes)?;
+    resources.load_effect_file("pbr", "./data/effects/pbr.toml", |tag, info| match tag {
+        PassTag::Shadow | PassTag::PointShadow => master_renderer.create_shadow_pipeline(info),
+        PassTag::Stereo => master_renderer.create_stereo_pipeline(info),
+        _ => master_renderer.create_pipeline(info),
+    })?;
+
+    master_renderer.enable_shadows(ShadowInfo::default())?;
+
+    let point_light = master_renderer.add_point_light(PointLightInfo {
+        position: Vec3::new(0.0, 3.0, 2.0),
+        range: 15.0,
+        ..Default::default()
+    })?;
     resources.load_texture("uv", "./data/textures/uv.png")?;
 
+    // Any equirectangular image can be used as the environment
+    let environment = resources.load_texture("environment", "./data/textures/statue.jpg")?;
+    master_renderer.set_environment(
+        resources.textures().raw(environment)?,
+        EnvironmentInfo::default(),
+    )?;
+
+    // Heard from the picked object, panned and attenuated relative to the camera
+    let mut audio = Audio::new(AudioInfo::default());
+    let click = resources.insert_sound("click", click_sound());
+
+    // Click an object to select it, then drag the handles. W, E and R switch between moving,
+    // rotating and scaling
+    master_renderer.set_gizmo(Some(Gizmo::default()))?;
+
+    resources.load_material_file("metal", "./data/materials/metal.toml")?;
+
     resources.load_material(
         "default",
         MaterialInfo {
             albedo: "uv".into(),
+            albedo_sampler: None,
+            effect: "lit".into(),
+            buffers: Vec::new(),
+            ..Default::default()
+        },
+    )?;
+
+    resources.load_material(
+        "glow",
+        MaterialInfo {
+            albedo: "uv".into(),
+            effect: "lit".into(),
+            emissive_factor: Vec3::new(1.0, 0.4, 0.1),
+            ..Default::default()
+        },
+    )?;
+
+    // Rolling hills below the scene, streamed in around the camera
+    resources.load_effect_file(
+        "terrain",
+        "./data/effects/terrain.toml",
+        |tag, info| match tag {
+            PassTag::Shadow | PassTag::PointShadow => master_renderer.create_shadow_pipeline(info),
+            PassTag::Stereo => master_renderer.create_stereo_pipeline(info),
+            _ => master_renderer.create_pipeline(info),
+        },
+    )?;
+
+    let terrain_info = TerrainInfo {
+        origin: Vec3::new(-64.0, -12.0, -64.0),
+        ..Default::default()
+    };
+
+    let heightmap = Heightmap::from_fn(129, 129, |x, z| {
+        let (x, z) = (x as f32 / 128.0, z as f32 / 128.0);
+        let hills = (x * 9.0).sin() * (z * 7.0).cos() * 0.25 + 0.25;
+        let ridge = (1.0 - ((x - z) * 3.0).sin().abs()) * 0.5;
+        (hills + ridge * x * z).clamp(0.0, 1.0)
+    });
+
+    let splat_map = Texture::from_pixels(
+        context.clone(),
+        Extent {
+            width: heightmap.width(),
+            height: heightmap.depth(),
+        },
+        &heightmap.splat_map(terrain_info.size, terrain_info.height),
+        ColorSpace::Linear,
+    )?;
+    resources.insert_texture("terrain_splat", splat_map);
+
+    let terrain_material = resources.load_material(
+        "terrain",
+        terrain::material_info(
+            "terrain",
+            "terrain_splat",
+            ["uv", "environment", "uv", "uv"],
+        ),
+    )?;
+
+    let mut terrain = Terrain::new("terrain", heightmap, terrain_material, terrain_info);
+
+    // A lake filling the valleys of the terrain, reflecting the scene above it
+    resources.load_effect_file("water", "./data/effects/water.toml", |_, info| {
+        master_renderer.create_pipeline(info)
+    })?;
+
+    let water_normals = Texture::from_pixels(
+        context.clone(),
+        Extent {
+            width: 128,
+            height: 128,
+        },
+        &water_normals(128),
+        ColorSpace::Linear,
+    )?;
+    resources.insert_texture("water_normals", water_normals);
+
+    let water = Water::new(
+        "water",
+        context.clone(),
+        &mut master_renderer,
+        &mut resources,
+        &mut scene,
+        WaterInfo {
+            center: Vec3::new(0.0, -6.0, 0.0),
+            size: Vec2::new(128.0, 128.0),
+            ..Default::default()
+        },
+    )?;
+
+    // Renders the scene from a distance onto a monitor
+    let security_camera = master_renderer.create_render_target(
+        &mut resources,
+        "security_camera",
+        RenderTargetInfo {
+            camera: Camera::perspective(Vec3::new(0.0, 0.0, 25.0), 1.0, 1.0, 0.1, 1000.0),
+            ..Default::default()
+        },
+    )?;
+
+    resources.load_material(
+        "monitor",
+        MaterialInfo {
+            albedo: "security_camera".into(),
+            albedo_sampler: None,
             effect: "default".into(),
+            buffers: Vec::new(),
+            ..Default::default()
         },
     )?;
 
@@ -90,24 +376,125 @@ fn main() -> Result<(), Box<dyn Error>> {
         Vec3::new(2.0, 0.0, 3.0),
     ];
 
-    for position in &positions {
+    for (i, position) in positions.iter().enumerate() {
         let position = *position;
+        // The first object is metallic and the last glows
+        let material = match i {
+            0 => "metal",
+            3 => "glow",
+            _ => "default",
+        };
+
         scene.add(Object {
-            material: resources.material("default")?,
+            material: resources.material(material)?,
             mesh: resources.mesh("monkey::Suzanne")?,
+            lods: resources.lod_chain("monkey::Suzanne").ok(),
             position,
+            rotation: Rotor3::identity(),
+            scale: Vec3::one(),
+            // The first object is animated
+            is_static: i != 0,
+            layers: Layers::DEFAULT,
         });
     }
 
+    scene.add(Object {
+        material: resources.material("monitor")?,
+        mesh: resources.mesh("cube::Cube")?,
+        lods: None,
+        position: Vec3::new(0.0, 4.0, 0.0),
+        rotation: Rotor3::identity(),
+        scale: Vec3::one(),
+        is_static: true,
+        layers: Layers::DEFAULT,
+    });
+
     let mut rng = rand::thread_rng();
 
+    // Small colored lights culled into screen tiles each frame
+    for _ in 0..256 {
+        scene.add_light(Light::new(
+            Vec3::new(
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(-2.0..2.0),
+                rng.gen_range(-15.0..15.0),
+            ),
+            Vec3::new(rng.gen(), rng.gen(), rng.gen()),
+            2.0,
+            rng.gen_range(1.0..4.0),
+        ));
+    }
+
+    // A stack of cubes falling onto a floor, simulated when built with the rapier3d feature
+    let mut physics = Physics::new(PhysicsInfo::default());
+
+    let floor = scene.world_mut().spawn();
+    scene.world_mut().insert(
+        floor,
+        Transform {
+            position: Vec3::new(0.0, -3.0, -8.0),
+            rotation: Rotor3::identity(),
+            scale: Vec3::new(40.0, 1.0, 40.0),
+        },
+    );
+    scene
+        .world_mut()
+        .insert(floor, resources.mesh("cube::Cube")?);
+    scene
+        .world_mut()
+        .insert(floor, resources.material("default")?);
+    scene
+        .world_mut()
+        .insert(floor, Collider::new(ColliderShape::Box(Vec3::one())));
+
+    for i in 0..8 {
+        let cube = scene.world_mut().spawn();
+        scene.world_mut().insert(
+            cube,
+            Transform {
+                position: Vec3::new((i % 2) as f32 * 0.1, i as f32 * 0.5, -8.0),
+                rotation: Rotor3::from_rotation_xz(i as f32),
+                scale: Vec3::one(),
+            },
+        );
+        scene
+            .world_mut()
+            .insert(cube, resources.mesh("cube::Cube")?);
+        scene
+            .world_mut()
+            .insert(cube, resources.material("default")?);
+        scene
+            .world_mut()
+            .insert(cube, Collider::new(ColliderShape::Box(Vec3::one())));
+        scene.world_mut().insert(cube, RigidBody::default());
+    }
+
     while !window.should_close() {
-        let elapsed = clock.elapsed();
-        let dt = frame_clock.reset();
+        // Blocks until the next image is available before the input is polled, so that the
+        // frame is drawn from the latest input
+        master_renderer.acquire(&window)?;
+
+        // Advances by a fixed timestep while capturing, which keeps the captured frames
+        // independent of the frame times
+        let dt = match master_renderer.capture() {
+            Some(capture) => {
+                frame_clock.reset();
+                Duration::from_secs_f32(capture.timestep())
+            }
+            None => frame_clock.reset(),
+        };
+        elapsed += dt;
 
         glfw.poll_events();
 
-        scene.objects_mut()[0].position.x = elapsed.secs().sin();
+        scene.object_at_mut(0).unwrap().position.x = elapsed.secs().sin();
+        let monitor_camera = &mut master_renderer.render_target_mut(security_camera)?.camera;
+        monitor_camera.position.x = (elapsed.secs() * 0.5).cos() * 5.0;
+        monitor_camera.look_at(Vec3::zero(), Vec3::unit_y());
+
+        for (i, light) in scene.lights_mut().iter_mut().enumerate() {
+            light.position.y = (elapsed.secs() + i as f32).sin() * 2.0;
+        }
 
         for (_, event) in glfw::flush_messages(&events) {
             match event {
@@ -117,7 +504,220 @@ fn main() -> Result<(), Box<dyn Error>> {
                 WindowEvent::Key(Key::F2, _, Action::Release, _) => {
                     camera = &mut orthographic_camera
                 }
-                WindowEvent::CursorPos(_, _) => {}
+                WindowEvent::Key(Key::F3, _, Action::Release, _) => {
+                    info!("Memory report:\n{}", context.memory_report()?);
+                    info!("Live objects: {:?}", context.objects().live());
+
+                    let layouts = master_renderer.descriptor_layout_cache();
+                    info!("Descriptor layouts: {:?}", layouts.stats());
+                    log::debug!("Cached descriptor layouts:\n{}", layouts.dump());
+
+                    for texture in resources.texture_report().into_iter().flatten() {
+                        info!(
+                            "Texture {:?} {}x{}: {:?}",
+                            texture.name,
+                            texture.extent.width,
+                            texture.extent.height,
+                            texture.sampling
+                        );
+                    }
+                }
+                WindowEvent::Key(Key::F4, _, Action::Release, _) => {
+                    picture_in_picture = !picture_in_picture
+                }
+                WindowEvent::Key(Key::F5, _, Action::Release, _) => {
+                    let limit = match master_renderer.frame_limit() {
+                        FrameLimit::Unlimited => FrameLimit::Fps(60.0),
+                        _ => FrameLimit::Unlimited,
+                    };
+
+                    info!("Frame limit: {:?}", limit);
+                    master_renderer.set_frame_limit(limit);
+                }
+                WindowEvent::Key(Key::F6, _, Action::Release, _) => {
+                    if master_renderer.shadow_map().is_some() {
+                        master_renderer.disable_shadows()?;
+                    } else {
+                        master_renderer.enable_shadows(ShadowInfo::default())?;
+                    }
+
+                    info!("Shadows: {}", master_renderer.shadow_map().is_some());
+                }
+                WindowEvent::Key(Key::F7, _, Action::Release, _) => {
+                    if let Some(light) = master_renderer.point_light_mut(point_light) {
+                        light.cast_shadows = !light.cast_shadows;
+                        info!("Point light shadows: {}", light.cast_shadows);
+                    }
+                }
+                WindowEvent::Key(Key::F8, _, Action::Release, _) => {
+                    let grid = match master_renderer.grid() {
+                        Some(_) => None,
+                        None => Some(GridInfo::default()),
+                    };
+
+                    master_renderer.set_grid(grid)?;
+                    info!("Grid: {}", grid.is_some());
+                }
+                WindowEvent::Key(Key::F9, _, Action::Release, _) => {
+                    let sky = match master_renderer.sky() {
+                        Some(_) => None,
+                        None => Some(SkyInfo::default()),
+                    };
+
+                    master_renderer.set_sky(sky)?;
+                    info!("Sky: {}", sky.is_some());
+                }
+                WindowEvent::Key(Key::F10, _, Action::Release, _) => {
+                    if master_renderer.trigger_capture() {
+                        info!("Capturing frame");
+                    }
+                }
+                WindowEvent::Key(Key::F11, _, Action::Release, _) => {
+                    let mode = master_renderer.display_mode().next();
+                    info!("Display mode: {:?}", mode);
+                    master_renderer.set_display_mode(&mut glfw, &mut window, mode)?;
+                }
+                WindowEvent::Key(Key::F12, _, Action::Release, _) => {
+                    let fog = match scene.fog().map(|fog| fog.mode) {
+                        None => Some(Fog {
+                            height: -2.0,
+                            ..Default::default()
+                        }),
+                        Some(FogMode::Exponential) => Some(Fog {
+                            mode: FogMode::Linear,
+                            height: -2.0,
+                            ..Default::default()
+                        }),
+                        Some(FogMode::Linear) => None,
+                    };
+
+                    info!("Fog: {:?}", fog.map(|fog| fog.mode));
+                    scene.set_fog(fog);
+                }
+                WindowEvent::Key(Key::V, _, Action::Release, _) => {
+                    let stereo = match master_renderer.stereo() {
+                        Some(_) => None,
+                        None => Some(StereoInfo::default()),
+                    };
+
+                    master_renderer.set_stereo(stereo)?;
+                    info!("Stereo: {}", master_renderer.stereo().is_some());
+                }
+                WindowEvent::Key(Key::C, _, Action::Release, _) => {
+                    let capture = match master_renderer.capture() {
+                        Some(_) => None,
+                        None => Some(CaptureInfo::default()),
+                    };
+
+                    master_renderer.set_capture(capture)?;
+                    info!("Capture: {}", master_renderer.capture().is_some());
+                }
+                WindowEvent::Key(Key::T, _, Action::Release, _) => {
+                    // Toggles between double and triple buffering
+                    let image_count = if master_renderer.image_count() > 2 {
+                        2
+                    } else {
+                        3
+                    };
+
+                    master_renderer.set_image_count(Some(image_count));
+                    info!("Swapchain images: {}", image_count);
+                }
+                WindowEvent::Key(Key::Y, _, Action::Release, _) => {
+                    let throttle = match master_renderer.throttle() {
+                        Some(_) => None,
+                        None => Some(ThrottleInfo::default()),
+                    };
+
+                    master_renderer.set_throttle(throttle);
+                    info!("Throttle: {}", master_renderer.throttle().is_some());
+                }
+                WindowEvent::Key(Key::N, _, Action::Release, _) => {
+                    benchmark = match benchmark {
+                        Some(_) => None,
+                        None => Some(Benchmark::new(
+                            BenchmarkInfo {
+                                spawn_layers: CUBE_LAYER,
+                                ..Default::default()
+                            },
+                            resources.mesh("cube::Cube")?,
+                            resources.material("default")?,
+                        )),
+                    };
+
+                    info!("Benchmark: {}", benchmark.is_some());
+                }
+                WindowEvent::Key(Key::L, _, Action::Release, _) => {
+                    let visible = !scene.visible_layers().intersects(CUBE_LAYER);
+                    scene.set_layers_visible(CUBE_LAYER, visible);
+                    info!("Cubes: {}", visible);
+                }
+                WindowEvent::Key(Key::B, _, Action::Release, _) => {
+                    let name = format!("static_batch{}", bakes);
+                    bakes += 1;
+
+                    let removed = scene.bake_static(&name, &mut resources)?;
+                    info!("Baked static objects, {} draws removed", removed);
+
+                    // The object indices changed
+                    if let Some(gizmo) = master_renderer.gizmo_mut() {
+                        gizmo.set_target(None);
+                    }
+                }
+                WindowEvent::Key(key @ (Key::W | Key::E | Key::R), _, Action::Release, _) => {
+                    if let Some(gizmo) = master_renderer.gizmo_mut() {
+                        gizmo.mode = match key {
+                            Key::W => GizmoMode::Translate,
+                            Key::E => GizmoMode::Rotate,
+                            _ => GizmoMode::Scale,
+                        };
+                    }
+                }
+                WindowEvent::CursorPos(x, y) => {
+                    let (camera, ndc) = cursor_ndc(&window, camera, x, y);
+                    if let Some(gizmo) = master_renderer.gizmo_mut() {
+                        if gizmo.is_dragging() {
+                            gizmo.drag(&camera, ndc, &mut scene);
+                        } else {
+                            gizmo.hover(&camera, ndc, &scene);
+                        }
+                    }
+                }
+                WindowEvent::MouseButton(MouseButton::Button1, Action::Press, _) => {
+                    let (x, y) = window.get_cursor_pos();
+                    let (view_camera, ndc) = cursor_ndc(&window, camera, x, y);
+
+                    // Clicking a handle of the gizmo takes precedence over picking
+                    if let Some(gizmo) = master_renderer.gizmo_mut() {
+                        if gizmo.begin_drag(&view_camera, ndc, &scene) {
+                            continue;
+                        }
+                    }
+
+                    let (x, y) = cursor_pixel(&window, x, y);
+                    let picked = master_renderer.pick(x, y, camera, &scene, &resources)?;
+
+                    info!("Picked: {:?}", picked);
+
+                    if let Some(index) = picked.and_then(|picked| scene.index_of(picked)) {
+                        audio.play(
+                            resources.sounds().raw(click)?,
+                            PlayInfo {
+                                emitter: Emitter::Object(index),
+                                ..Default::default()
+                            },
+                        );
+                    }
+
+                    if let Some(gizmo) = master_renderer.gizmo_mut() {
+                        gizmo.set_target(picked);
+                    }
+                }
+                WindowEvent::MouseButton(MouseButton::Button1, Action::Release, _) => {
+                    if let Some(gizmo) = master_renderer.gizmo_mut() {
+                        gizmo.end_drag();
+                    }
+                }
                 WindowEvent::FramebufferSize(w, h) => {
                     info!("Resized: {}, {}", w, h);
                     master_renderer.on_resize();
@@ -132,7 +732,18 @@ fn main() -> Result<(), Box<dyn Error>> {
 
         camera.position.y = (elapsed.secs() * 0.25).sin() * 2.0;
 
-        if scene.objects().len() < 5000 {
+        if let Some(current) = &mut benchmark {
+            if !current.update(dt, camera, &mut scene) {
+                info!("Benchmark finished\n{}", current.report());
+                current.write_csv(Path::new("benchmark.csv"))?;
+                current.write_json(Path::new("benchmark.json"))?;
+                benchmark = None;
+            }
+        }
+
+        // The benchmark spawns its own objects
+        if spawned < 5000 && benchmark.is_none() {
+            spawned += 1;
             last_spawn.reset();
             let position = Vec3::new(
                 rng.gen_range(-15.0..15.0),
@@ -145,25 +756,149 @@ fn main() -> Result<(), Box<dyn Error>> {
             scene.add(Object {
                 mesh: resources.mesh("cube::Cube")?,
                 material: resources.material("default")?,
+                lods: None,
                 position,
-            })
+                rotation: Rotor3::identity(),
+                scale: Vec3::one(),
+                is_static: true,
+                layers: CUBE_LAYER,
+            });
         }
 
         if last_status.elapsed().secs() > 1.0 {
             last_status.reset();
+            let stats = master_renderer.frame_stats();
             log::info!(
-                "Elapsed: {:?}\tFrametime: {:?}\tFramerate: {}\t Objects: {:?}",
+                "Elapsed: {:?}\tFrametime: {:?}\tFramerate: {}\t1% low: {}\t Objects: {:?}",
                 elapsed,
-                dt,
-                1.0 / dt.secs(),
+                stats.average(),
+                stats.average_fps(),
+                stats.one_percent_low(),
                 scene.objects().len(),
             );
         }
 
-        master_renderer.draw(&window, dt.secs(), &camera, &mut scene, &resources)?;
+        // Picks up changes to loaded textures and documents on disk
+        resources.reload_changed();
+
+        terrain.update(camera.position, &mut scene, &mut resources)?;
+
+        physics.update(dt, &mut scene, resources.meshes());
+
+        audio.update(camera, &scene);
+
+        let mut views = vec![(*camera, Viewport::full())];
+
+        if picture_in_picture {
+            views.push((overview_camera, Viewport::new(0.7, 0.05, 0.25, 0.25)));
+        }
+
+        water.update_reflection(
+            &camera.with_viewport_aspect(Extent::from(window.get_size()).aspect()),
+            &mut master_renderer,
+        )?;
+
+        #[cfg(feature = "openxr")]
+        if let Some(xr_session) = &mut xr_session {
+            if !xr_session.poll_events()? {
+                break;
+            }
+
+            let frame = xr_session.begin_frame()?;
+
+            if let Some(stereo) = master_renderer.stereo_mut() {
+                stereo.set_views(frame.map(|frame| frame.stereo_views(camera)));
+            }
+        }
+
+        master_renderer.draw(&window, dt.secs(), &views, &mut scene, &resources)?;
+
+        #[cfg(feature = "openxr")]
+        if let Some(xr_session) = &mut xr_session {
+            xr_session.end_frame()?;
+        }
+
+        if let Some(capture) = master_renderer.capture() {
+            if capture.is_finished() {
+                info!("Captured {} frames", capture.captured());
+                master_renderer.set_capture(None)?;
+            }
+        }
     }
 
     std::mem::drop(master_renderer);
 
     Ok(())
 }
+
+/// Generates a short decaying tone
+fn click_sound() -> Sound {
+    let sample_rate = 44100;
+
+    let samples = (0..sample_rate / 10)
+        .map(|i| {
+            let time = i as f32 / sample_rate as f32;
+            (time * 880.0 * std::f32::consts::TAU).sin() * (-time * 40.0).exp() * 0.5
+        })
+        .collect();
+
+    Sound::new(samples, 1, sample_rate)
+}
+
+/// Generates a tiling normal map of overlapping waves as RGBA pixels
+fn water_normals(size: u32) -> Vec<u8> {
+    // The number of periods across the texture along x and z, and the amplitude of each wave
+    let waves = [
+        (1.0, 2.0, 0.5),
+        (3.0, -1.0, 0.3),
+        (-2.0, 5.0, 0.15),
+        (7.0, 4.0, 0.08),
+    ];
+
+    (0..size)
+        .flat_map(|z| (0..size).map(move |x| (x, z)))
+        .flat_map(|(x, z)| {
+            let (u, v) = (x as f32 / size as f32, z as f32 / size as f32);
+
+            // The slope of the summed waves along x and z
+            let (dx, dz) = waves
+                .iter()
+                .fold((0.0, 0.0), |(dx, dz), (kx, kz, amplitude)| {
+                    let phase = (kx * u + kz * v) * std::f32::consts::TAU;
+                    let slope = phase.cos() * amplitude * 0.1;
+                    (dx + slope * kx, dz + slope * kz)
+                });
+
+            let normal = Vec3::new(-dx, -dz, 1.0).normalized() * 0.5 + Vec3::broadcast(0.5);
+
+            vec![
+                (normal.x * 255.0) as u8,
+                (normal.y * 255.0) as u8,
+                (normal.z * 255.0) as u8,
+                255,
+            ]
+        })
+        .collect()
+}
+
+/// Returns the camera as drawn into the window and the cursor position `x`, `y` in normalized
+/// device coordinates
+fn cursor_ndc(window: &glfw::Window, camera: &Camera, x: f64, y: f64) -> (Camera, Vec2) {
+    let extent = Extent::from(window.get_size());
+    let camera = camera.with_viewport_aspect(extent.aspect());
+    let ndc = Vec2::new(x as f32, y as f32) / Vec2::from(extent) * 2.0 - Vec2::one();
+
+    (camera, ndc)
+}
+
+// Converts the cursor position in screen coordinates to framebuffer pixels, which differ on
+// scaled displays
+fn cursor_pixel(window: &glfw::Window, x: f64, y: f64) -> (u32, u32) {
+    let (width, height) = window.get_size();
+    let (framebuffer_width, framebuffer_height) = window.get_framebuffer_size();
+
+    (
+        (x * framebuffer_width as f64 / width.max(1) as f64) as u32,
+        (y * framebuffer_height as f64 / height.max(1) as f64) as u32,
+    )
+}
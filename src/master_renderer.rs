@@ -3,8 +3,29 @@ use ash::vk;
 use log::info;
 use ultraviolet::mat::*;
 
-use crate::mesh_renderer::MeshRenderer;
+use crate::capture::{self, Capture, CaptureInfo};
+use crate::display::{Display, DisplayMode};
+use crate::environment::{Environment, EnvironmentInfo};
+use crate::fog::{FogUniform, FOG_SET};
+use crate::frame_pacing::{FrameLimit, FrameLimiter, FrameStats, FrameThrottle, ThrottleInfo};
+use crate::fullscreen::FullscreenPass;
+use crate::gizmo::{self, Gizmo, GizmoRenderer};
+use crate::grid::{self, GridInfo, GridRenderer};
+use crate::light_culling::{CullingQueue, LightCulling, LightGrid, LIGHT_SET};
+use crate::mesh_renderer::{MeshRenderer, MeshRendererInfo};
+use crate::pass::{Pass, PassFrame, PassStage, PassTargets};
+use crate::picking_renderer::PickingRenderer;
+use crate::point_shadow::{PointLight, PointLightInfo, PointShadows};
+use crate::ray_query::{SceneAccelerationStructure, RAY_QUERY_SET};
+use crate::render_target::{RenderTarget, RenderTargetInfo};
+#[cfg(feature = "renderdoc")]
+use crate::renderdoc::RenderDoc;
 use crate::resources::*;
+use crate::shading_rate::{ShadingRateImage, ShadingRateInfo};
+use crate::shadow::{self, CascadedShadowMap, ShadowInfo};
+use crate::sky::{self, SkyInfo, SkyRenderer};
+use crate::stereo::{self, StereoInfo, StereoRenderer, StereoTarget, EYE_COUNT, VIEW_SET};
+use crate::water;
 
 use super::*;
 
@@ -18,13 +39,109 @@ use vulkan::swapchain;
 
 use vulkan::commands::*;
 use vulkan::descriptors::*;
+use vulkan::pipeline::{Pipeline, PipelineInfo};
 use vulkan::swapchain::*;
-use vulkan::Framebuffer;
+use vulkan::{
+    Extent, Framebuffer, QueueSharing, RenderingAttachment, RenderingAttachments, RenderingFormats,
+    ShadingRateAttachment,
+};
 
 use glfw;
-use std::{error::Error, rc::Rc};
+use std::{any::Any, error::Error, rc::Rc};
+
+/// The default number of frames recorded ahead of the device
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+/// The format of the depth attachments when no stencil is used
+pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// Depth stencil formats in order of preference. Devices are required to support at least one
+/// of them.
+pub const DEPTH_STENCIL_FORMATS: &[vk::Format] = &[
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+];
+
+/// The effect passes recorded into the main renderpass
+const MAIN_PASSES: &[PassTag] = &[PassTag::Opaque, PassTag::Transparent];
+
+/// The names of the optional built-in passes, in the order they are drawn after the objects of
+/// each view
+pub const BUILTIN_PASSES: &[&str] = &[sky::NAME, grid::NAME, gizmo::NAME];
+
+// TODO Autogenerate clear color based on one value
+const CLEAR_VALUES: [vk::ClearValue; 2] = [
+    vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 0.0],
+        },
+    },
+    vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: 1.0,
+            stencil: 0,
+        },
+    },
+];
+
+/// Specifies how the master renderer renders
+pub struct MasterRendererInfo {
+    /// Render using `VK_KHR_dynamic_rendering` instead of renderpasses and framebuffers.
+    /// Falls back to renderpasses if the extension is not supported.
+    pub dynamic_rendering: bool,
+    /// Swapchain preferences, e.g; the preferred surface formats
+    pub swapchain: SwapchainInfo,
+    /// Object capacity and growth of the mesh renderer of each view
+    pub mesh_renderer: MeshRendererInfo,
+    /// The number of frames the host may record while the device is still processing previous
+    /// frames. Must be between 1 and `MAX_FRAMES`.
+    pub frames_in_flight: usize,
+    /// Use a depth attachment with a stencil component, which is required by pipelines using
+    /// the stencil test
+    pub stencil: bool,
+    /// Limits the frame rate by waiting after presenting each frame
+    pub frame_limit: FrameLimit,
+    /// Throttles the host to the device when it falls behind if Some. See
+    /// `MasterRenderer::set_throttle`.
+    pub throttle: Option<ThrottleInfo>,
+    /// Draws a grid on the ground plane of each view if Some. See `MasterRenderer::set_grid`.
+    pub grid: Option<GridInfo>,
+    /// Draws a procedural sky behind the objects of each view if Some. See
+    /// `MasterRenderer::set_sky`.
+    pub sky: Option<SkyInfo>,
+    /// Culls the lights of the views on a dedicated compute queue if the device has one, which
+    /// overlaps the culling with the shadow passes
+    pub async_compute: bool,
+    /// Lowers the shading rate of the main pass towards the periphery of the screen if Some.
+    /// Requires `DeviceFeatures::fragment_shading_rate` with shading rate attachments and
+    /// dynamic rendering, and shades every pixel otherwise.
+    pub shading_rate: Option<ShadingRateInfo>,
+    /// Presents the first view side by side for both eyes if Some. See
+    /// `MasterRenderer::set_stereo`.
+    pub stereo: Option<StereoInfo>,
+    /// Captures the presented frames if Some. See `MasterRenderer::set_capture`.
+    pub capture: Option<CaptureInfo>,
+}
 
-const FRAMES_IN_FLIGHT: usize = 2;
+impl Default for MasterRendererInfo {
+    fn default() -> Self {
+        Self {
+            dynamic_rendering: true,
+            swapchain: SwapchainInfo::default(),
+            mesh_renderer: MeshRendererInfo::default(),
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            stencil: false,
+            frame_limit: FrameLimit::Unlimited,
+            throttle: None,
+            grid: None,
+            sky: None,
+            async_compute: true,
+            shading_rate: None,
+            stereo: None,
+            capture: None,
+        }
+    }
+}
 
 #[derive(Default)]
 #[repr(C)]
@@ -32,30 +149,22 @@ struct ObjectData {
     mvp: Mat4,
 }
 
-/// Represents data needed to be duplicated for each swapchain image
+/// Represents data needed to be duplicated for each frame in flight. Reused once the fence
+/// of the frame is signaled.
 struct PerFrameData {
+    context: Rc<VulkanContext>,
     commandpool: CommandPool,
     commandbuffer: CommandBuffer,
-    framebuffer: Framebuffer,
-    // The fence currently associated to this image_index
-    image_in_flight: vk::Fence,
+    /// Signaled when the device has finished the frame
+    in_flight_fence: vk::Fence,
+    image_available_semaphore: vk::Semaphore,
+    render_finished_semaphore: vk::Semaphore,
+    /// Some when culling the lights on the compute queue
+    compute: Option<ComputeFrameData>,
 }
 
 impl PerFrameData {
-    fn new(
-        context: Rc<VulkanContext>,
-        renderpass: &RenderPass,
-        color_attachment: &Texture,
-        depth_attachment: &Texture,
-        swapchain_image: &Texture,
-    ) -> Result<Self, vulkan::Error> {
-        let framebuffer = Framebuffer::new(
-            context.device_ref(),
-            &renderpass,
-            &[color_attachment, depth_attachment, swapchain_image],
-            swapchain_image.extent(),
-        )?;
-
+    fn new(context: Rc<VulkanContext>, async_compute: bool) -> Result<Self, vulkan::Error> {
         // Create and record command buffers
         let commandpool = CommandPool::new(
             context.device_ref(),
@@ -66,10 +175,119 @@ impl PerFrameData {
 
         let commandbuffer = commandpool.allocate(1)?.pop().unwrap();
 
+        let in_flight_fence = fence::create(context.device(), true)?;
+        let image_available_semaphore = semaphore::create(context.device())?;
+        let render_finished_semaphore = semaphore::create(context.device())?;
+
+        let compute = if async_compute {
+            Some(ComputeFrameData::new(context.clone(), &commandpool)?)
+        } else {
+            None
+        };
+
         Ok(PerFrameData {
-            framebuffer,
+            context,
+            commandpool,
+            commandbuffer,
+            in_flight_fence,
+            image_available_semaphore,
+            render_finished_semaphore,
+            compute,
+        })
+    }
+}
+
+impl Drop for PerFrameData {
+    fn drop(&mut self) {
+        let device = self.context.device();
+
+        fence::destroy(device, self.in_flight_fence);
+        semaphore::destroy(device, self.image_available_semaphore);
+        semaphore::destroy(device, self.render_finished_semaphore);
+    }
+}
+
+/// The work of a frame in flight culling the lights on the compute queue. The shadow passes are
+/// submitted separately ahead of the main pass, so that they overlap the culling, and the main
+/// pass waits for the culling to finish.
+struct ComputeFrameData {
+    context: Rc<VulkanContext>,
+    commandpool: CommandPool,
+    /// Submitted to the compute queue
+    commandbuffer: CommandBuffer,
+    /// Allocated from the graphics command pool of the frame
+    shadow_commandbuffer: CommandBuffer,
+    /// Signaled by the culling and waited on by the main pass
+    culled_semaphore: vk::Semaphore,
+    /// Signaled by the main pass and waited on by the culling of the next frame, which
+    /// overwrites the light buffers read by the main pass
+    rendered_semaphore: vk::Semaphore,
+}
+
+impl ComputeFrameData {
+    fn new(context: Rc<VulkanContext>, graphics_pool: &CommandPool) -> Result<Self, vulkan::Error> {
+        let commandpool = CommandPool::new(
+            context.device_ref(),
+            context.queue_families().compute().unwrap(),
+            true,
+            false,
+        )?;
+
+        let commandbuffer = commandpool.allocate(1)?.pop().unwrap();
+        let shadow_commandbuffer = graphics_pool.allocate(1)?.pop().unwrap();
+
+        let culled_semaphore = semaphore::create(context.device())?;
+        let rendered_semaphore = semaphore::create(context.device())?;
+
+        Ok(Self {
+            context,
             commandpool,
             commandbuffer,
+            shadow_commandbuffer,
+            culled_semaphore,
+            rendered_semaphore,
+        })
+    }
+}
+
+impl Drop for ComputeFrameData {
+    fn drop(&mut self) {
+        let device = self.context.device();
+
+        semaphore::destroy(device, self.culled_semaphore);
+        semaphore::destroy(device, self.rendered_semaphore);
+    }
+}
+
+/// Represents data needed to be duplicated for each swapchain image
+struct PerImageData {
+    /// None when using dynamic rendering
+    framebuffer: Option<Framebuffer>,
+    // The fence of the frame currently rendering to this image
+    image_in_flight: vk::Fence,
+}
+
+impl PerImageData {
+    fn new(
+        context: &VulkanContext,
+        renderpass: Option<&RenderPass>,
+        color_attachment: &Texture,
+        depth_attachment: &Texture,
+        swapchain_image: &Texture,
+    ) -> Result<Self, vulkan::Error> {
+        let framebuffer = renderpass
+            .map(|renderpass| {
+                Framebuffer::new(
+                    context.device_ref(),
+                    renderpass,
+                    &[color_attachment, depth_attachment, swapchain_image],
+                    swapchain_image.extent(),
+                )
+            })
+            .transpose()?;
+
+        Ok(PerImageData {
+            framebuffer,
             image_in_flight: vk::Fence::null(),
         })
     }
@@ -78,21 +296,31 @@ impl PerFrameData {
 pub struct MasterRenderer {
     swapchain_loader: Rc<ash::extensions::khr::Swapchain>,
     pub swapchain: Swapchain,
+    swapchain_info: SwapchainInfo,
 
-    in_flight_fences: ArrayVec<[vk::Fence; FRAMES_IN_FLIGHT]>,
-    image_available_semaphores: ArrayVec<[vk::Semaphore; FRAMES_IN_FLIGHT]>,
-    render_finished_semaphores: ArrayVec<[vk::Semaphore; FRAMES_IN_FLIGHT]>,
-
-    pub renderpass: RenderPass,
+    /// None when using dynamic rendering
+    pub renderpass: Option<RenderPass>,
+    /// The attachment formats used when rendering with dynamic rendering
+    rendering_formats: RenderingFormats,
+    depth_format: vk::Format,
+    shadow_format: vk::Format,
 
     pub descriptor_layout_cache: DescriptorLayoutCache,
     pub descriptor_allocator: DescriptorAllocator,
 
     per_frame_data: ArrayVec<[PerFrameData; MAX_FRAMES]>,
+    per_image_data: ArrayVec<[PerImageData; MAX_FRAMES]>,
+    /// The rendered semaphore of the last frame culling on the compute queue, which is yet to be
+    /// waited on
+    pending_rendered_semaphore: Option<vk::Semaphore>,
 
     // The current frame-in-flight index
     current_frame: usize,
+    /// The swapchain image acquired for the next frame
+    acquired: Option<u32>,
     should_resize: bool,
+    // The surface needs to be recreated along with the swapchain
+    surface_lost: bool,
 
     // Multisampled color and depth renderpass attachments
     color_attachment: Texture,
@@ -101,107 +329,306 @@ pub struct MasterRenderer {
     // Drop context last
     context: Rc<VulkanContext>,
 
-    mesh_renderer: MeshRenderer,
+    /// One mesh renderer for each view drawn into the swapchain, since each view requires its
+    /// own object transforms. Created when first drawing with more views.
+    mesh_renderers: Vec<MeshRenderer>,
+    mesh_renderer_info: MeshRendererInfo,
+    /// Created on first pick and recreated after resize
+    picking_renderer: Option<PickingRenderer>,
+    /// Drawn before the render targets and main pass each frame
+    shadow_map: Option<CascadedShadowMap>,
+    /// Drawn after the shadow map
+    point_shadows: PointShadows,
+    /// None if the device can not bind the light set
+    light_culling: Option<LightCulling>,
+    /// The culled lights of each view, parallel to the mesh renderers. Empty without light
+    /// culling.
+    light_grids: Vec<LightGrid>,
+    /// Image based lighting of the effects using the environment set
+    environment: Option<Environment>,
+    /// The fog of the scene, uploaded each frame. None if the device can not bind the fog set.
+    fog: Option<FogUniform>,
+    /// The objects of the scene traced by ray queries, rebuilt each frame. None unless ray
+    /// queries are enabled and the ray query set can be bound.
+    acceleration_structure: Option<SceneAccelerationStructure>,
+    /// The shading rate attachment of the main pass. None unless enabled and supported.
+    shading_rate: Option<ShadingRateImage>,
+    /// Drawn before the main pass each frame
+    render_targets: ResourceCache<RenderTarget>,
+    /// Draws the eyes of the first view before the main pass, and presents them in place of it
+    stereo: Option<StereoRenderer>,
+    /// Copies the swapchain image after the main pass of each captured frame
+    capture: Option<Capture>,
+    /// Executed in the order added within their stage. Includes the enabled built-in passes,
+    /// which are kept in the order of `BUILTIN_PASSES`.
+    passes: Vec<Box<dyn Pass>>,
+    display: Display,
+    frame_limiter: FrameLimiter,
+    frame_stats: FrameStats,
+    throttle: Option<FrameThrottle>,
+    /// The sum of the frame times passed to `draw` in seconds, which animates the water
+    time: f32,
+    /// None when not running under RenderDoc
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<RenderDoc>,
 }
 
 impl MasterRenderer {
-    pub fn new(context: Rc<VulkanContext>, window: &glfw::Window) -> Result<Self, Box<dyn Error>> {
+    pub fn new(
+        context: Rc<VulkanContext>,
+        window: &glfw::Window,
+        info: MasterRendererInfo,
+    ) -> Result<Self, Box<dyn Error>> {
+        if info.frames_in_flight == 0 || info.frames_in_flight > MAX_FRAMES {
+            return Err(crate::Error::InvalidFramesInFlight {
+                count: info.frames_in_flight,
+                max: MAX_FRAMES,
+            }
+            .into());
+        }
+
         let swapchain_loader = Rc::new(swapchain::create_loader(
             context.instance(),
             context.device(),
         ));
 
-        let swapchain = Swapchain::new(context.clone(), Rc::clone(&swapchain_loader), &window)?;
-        log::debug!("Created swapchain");
-        log::debug!("Swapchain image format: {:?}", swapchain.image_format());
+        let mut swapchain_info = info.swapchain;
+        swapchain_info.transfer_src |= info.capture.is_some();
 
-        let color_attachment = Texture::new(
+        let swapchain = Swapchain::new(
             context.clone(),
-            TextureInfo {
-                extent: swapchain.extent(),
-                mip_levels: 1,
-                usage: TextureUsage::ColorAttachment,
-                format: swapchain.image_format(),
-                samples: context.msaa_samples(),
-            },
+            Rc::clone(&swapchain_loader),
+            &window,
+            &swapchain_info,
         )?;
+        log::debug!("Created swapchain");
+        log::debug!("Swapchain image format: {:?}", swapchain.image_format());
+        log::debug!("Swapchain color space: {:?}", swapchain.color_space());
 
-        let depth_attachment = Texture::new(
-            context.clone(),
-            TextureInfo {
-                extent: swapchain.extent(),
-                mip_levels: 1,
-                usage: TextureUsage::DepthAttachment,
-                format: Format::D32_SFLOAT,
-                samples: context.msaa_samples(),
-            },
-        )?;
+        let depth_format = depth_format(&context, info.stencil);
+        log::debug!("Depth format: {:?}", depth_format);
 
-        let renderpass = create_renderpass(
-            context.device_ref(),
-            &color_attachment,
-            &depth_attachment,
+        // The stereo target is created along with the attachments it shares memory with
+        let ((color_attachment, depth_attachment), _) = create_attachments(
+            &context,
+            swapchain.extent(),
             swapchain.image_format(),
+            depth_format,
+            None,
         )?;
 
+        let shadow_format = shadow::shadow_format(&context);
+        log::debug!("Shadow format: {:?}", shadow_format);
+
+        let dynamic_rendering = info.dynamic_rendering && context.dynamic_rendering().is_some();
+        log::debug!("Using dynamic rendering: {}", dynamic_rendering);
+
+        let renderpass = if dynamic_rendering {
+            None
+        } else {
+            Some(create_renderpass(
+                context.device_ref(),
+                &color_attachment,
+                &depth_attachment,
+                swapchain.image_format(),
+            )?)
+        };
+
+        let shading_rate = match info.shading_rate {
+            Some(shading_rate_info) if dynamic_rendering => {
+                let image =
+                    ShadingRateImage::new(context.clone(), shading_rate_info, swapchain.extent())?;
+
+                if image.is_none() {
+                    log::warn!("Shading rate attachments are not supported, shading every pixel");
+                }
+
+                image
+            }
+            Some(_) => {
+                log::warn!(
+                    "The shading rate image requires dynamic rendering, shading every pixel"
+                );
+                None
+            }
+            None => None,
+        };
+
+        let rendering_formats = rendering_formats(
+            swapchain.image_format(),
+            depth_format,
+            shading_rate.is_some(),
+        );
+
         let mut descriptor_layout_cache = DescriptorLayoutCache::new(context.device_ref());
 
         let mut descriptor_allocator = DescriptorAllocator::new(context.device_ref(), 2);
 
-        let image_available_semaphores = (0..FRAMES_IN_FLIGHT)
-            .into_iter()
-            .map(|_| semaphore::create(context.device()))
-            .collect::<Result<_, _>>()?;
-
-        let render_finished_semaphores = (0..FRAMES_IN_FLIGHT)
-            .into_iter()
-            .map(|_| semaphore::create(context.device()))
-            .collect::<Result<_, _>>()?;
+        let async_compute = info.async_compute
+            && context.capabilities().dedicated_compute
+            && context.capabilities().max_bound_descriptor_sets > LIGHT_SET;
+        log::debug!("Using async compute: {}", async_compute);
 
-        let in_flight_fences = (0..FRAMES_IN_FLIGHT)
-            .into_iter()
-            .map(|_| fence::create(context.device(), true))
-            .collect::<Result<_, _>>()?;
+        let per_frame_data = (0..info.frames_in_flight)
+            .map(|_| PerFrameData::new(context.clone(), async_compute))
+            .collect::<Result<ArrayVec<[PerFrameData; MAX_FRAMES]>, _>>()?;
 
-        let per_frame_data = swapchain
+        let per_image_data = swapchain
             .images()
             .iter()
             .map(|swapchain_image| {
-                PerFrameData::new(
-                    context.clone(),
-                    &renderpass,
+                PerImageData::new(
+                    &context,
+                    renderpass.as_ref(),
                     &color_attachment,
                     &depth_attachment,
                     swapchain_image,
                 )
             })
-            .collect::<Result<ArrayVec<[PerFrameData; MAX_FRAMES]>, _>>()?;
+            .collect::<Result<ArrayVec<[PerImageData; MAX_FRAMES]>, _>>()?;
+
+        let mut mesh_renderer = MeshRenderer::new(
+            context.clone(),
+            &mut descriptor_layout_cache,
+            &mut descriptor_allocator,
+            info.frames_in_flight,
+            info.mesh_renderer,
+        )?;
+
+        mesh_renderer.set_inheritance(inheritance(
+            &context,
+            renderpass.as_ref(),
+            &rendering_formats,
+        ));
+        mesh_renderer.set_extent(swapchain.extent());
 
-        let mesh_renderer = MeshRenderer::new(
+        let point_shadows = PointShadows::new(
             context.clone(),
             &mut descriptor_layout_cache,
             &mut descriptor_allocator,
-            swapchain.image_count() as usize,
+            info.frames_in_flight,
+            renderpass.is_none(),
+            shadow_format,
         )?;
 
-        let master_renderer = MasterRenderer {
+        mesh_renderer.set_point_shadow_set(Some(point_shadows.set()));
+
+        let light_culling = if context.capabilities().max_bound_descriptor_sets > LIGHT_SET {
+            // The light buffers are accessed by both the graphics and compute queue families
+            let sharing = if async_compute {
+                QueueSharing::Concurrent
+            } else {
+                QueueSharing::Exclusive
+            };
+
+            Some(LightCulling::new(
+                context.clone(),
+                &mut descriptor_layout_cache,
+                sharing,
+            )?)
+        } else {
+            log::warn!("Light culling is not supported, the light set can not be bound");
+            None
+        };
+
+        let light_grids = light_culling
+            .as_ref()
+            .map(|light_culling| {
+                LightGrid::new(
+                    context.clone(),
+                    &mut descriptor_layout_cache,
+                    &mut descriptor_allocator,
+                    light_culling,
+                    swapchain.extent(),
+                )
+            })
+            .transpose()?
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        mesh_renderer.set_light_set(light_grids.first().map(|light_grid| light_grid.set()));
+
+        let fog = if context.capabilities().max_bound_descriptor_sets > FOG_SET {
+            Some(FogUniform::new(
+                context.clone(),
+                &mut descriptor_layout_cache,
+                &mut descriptor_allocator,
+            )?)
+        } else {
+            log::warn!("Fog is not supported, the fog set can not be bound");
+            None
+        };
+
+        mesh_renderer.set_fog_set(fog.as_ref().map(|fog| fog.set()));
+
+        let acceleration_structure = if !context.capabilities().features.ray_query {
+            None
+        } else if context.capabilities().max_bound_descriptor_sets > RAY_QUERY_SET {
+            Some(SceneAccelerationStructure::new(
+                context.clone(),
+                &mut descriptor_layout_cache,
+                &mut descriptor_allocator,
+            )?)
+        } else {
+            log::warn!("Ray queries are not supported, the ray query set can not be bound");
+            None
+        };
+
+        mesh_renderer.set_ray_query_set(
+            acceleration_structure
+                .as_ref()
+                .map(|acceleration_structure| acceleration_structure.set()),
+        );
+
+        let mut master_renderer = MasterRenderer {
             context,
             swapchain_loader,
             swapchain,
-            in_flight_fences,
-            image_available_semaphores,
-            render_finished_semaphores,
+            swapchain_info,
             renderpass,
+            rendering_formats,
+            depth_format,
+            shadow_format,
             current_frame: 0,
+            acquired: None,
             should_resize: false,
+            surface_lost: false,
             descriptor_layout_cache,
             color_attachment,
             depth_attachment,
             descriptor_allocator,
             per_frame_data,
-            mesh_renderer,
+            per_image_data,
+            pending_rendered_semaphore: None,
+            mesh_renderers: vec![mesh_renderer],
+            mesh_renderer_info: info.mesh_renderer,
+            picking_renderer: None,
+            shadow_map: None,
+            point_shadows,
+            light_culling,
+            light_grids,
+            environment: None,
+            fog,
+            acceleration_structure,
+            shading_rate,
+            render_targets: ResourceCache::new(),
+            stereo: None,
+            capture: None,
+            passes: Vec::new(),
+            display: Display::new(window),
+            frame_limiter: FrameLimiter::new(info.frame_limit),
+            throttle: info.throttle.map(FrameThrottle::new),
+            frame_stats: FrameStats::default(),
+            time: 0.0,
+            #[cfg(feature = "renderdoc")]
+            renderdoc: RenderDoc::new(),
         };
 
+        master_renderer.set_grid(info.grid)?;
+        master_renderer.set_sky(info.sky)?;
+        master_renderer.set_stereo(info.stereo)?;
+        master_renderer.set_capture(info.capture)?;
+
         Ok(master_renderer)
     }
 
@@ -211,6 +638,86 @@ impl MasterRenderer {
         self.should_resize = true;
     }
 
+    /// Switches the window between windowed, borderless and exclusive fullscreen. The swapchain
+    /// is recreated on the next frame, which allows exclusive fullscreen when
+    /// `VK_EXT_full_screen_exclusive` is supported and reselects the present mode for the
+    /// monitor.
+    pub fn set_display_mode(
+        &mut self,
+        glfw: &mut glfw::Glfw,
+        window: &mut glfw::Window,
+        mode: DisplayMode,
+    ) -> Result<(), crate::Error> {
+        self.display.set_mode(glfw, window, mode)?;
+
+        self.swapchain_info.full_screen_exclusive = mode == DisplayMode::Exclusive;
+        self.on_resize();
+
+        Ok(())
+    }
+
+    pub fn display_mode(&self) -> DisplayMode {
+        self.display.mode()
+    }
+
+    pub fn set_frame_limit(&mut self, limit: FrameLimit) {
+        self.frame_limiter.set_limit(limit);
+    }
+
+    pub fn frame_limit(&self) -> FrameLimit {
+        self.frame_limiter.limit()
+    }
+
+    /// Waits for the previous frame before recording when the device falls behind, trading
+    /// throughput for latency, or never if None. See `FrameThrottle`.
+    pub fn set_throttle(&mut self, info: Option<ThrottleInfo>) {
+        self.throttle = info.map(FrameThrottle::new);
+    }
+
+    pub fn throttle(&self) -> Option<&FrameThrottle> {
+        self.throttle.as_ref()
+    }
+
+    /// Sets the preferred number of swapchain images, e.g; 2 to lower the latency or 3 to raise
+    /// the throughput. The swapchain is recreated on the next frame. See
+    /// `SwapchainInfo::image_count`.
+    pub fn set_image_count(&mut self, image_count: Option<u32>) {
+        self.swapchain_info.image_count = image_count;
+        self.on_resize();
+    }
+
+    /// Returns the number of images of the current swapchain, which may be more than requested
+    pub fn image_count(&self) -> u32 {
+        self.swapchain.image_count()
+    }
+
+    /// Returns the frame times of the most recently presented frames, including the time
+    /// spent waiting for the frame limit.
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    /// Captures the next presented frame with RenderDoc, e.g; from a hotkey. Returns false if
+    /// the `renderdoc` feature is disabled or the application is not running under RenderDoc.
+    pub fn trigger_capture(&mut self) -> bool {
+        #[cfg(feature = "renderdoc")]
+        if let Some(renderdoc) = &mut self.renderdoc {
+            renderdoc.trigger_capture();
+            return true;
+        }
+
+        log::warn!("Frame capture requested without RenderDoc");
+        false
+    }
+
+    // Called when the surface was lost, e.g; when the display was disconnected
+    // Recreates the surface and swapchain on the next frame
+    fn on_surface_lost(&mut self) {
+        log::warn!("Surface lost");
+        self.surface_lost = true;
+        self.should_resize = true;
+    }
+
     // Does the resizing
     fn resize(&mut self, window: &glfw::Window) -> Result<(), vulkan::Error> {
         log::debug!("Resizing");
@@ -220,179 +727,1444 @@ impl MasterRenderer {
 
         let old_surface_format = self.swapchain.surface_format();
 
+        let lost_surface = if self.surface_lost {
+            log::info!("Recreating lost surface");
+            Some(self.context.recreate_surface(window)?)
+        } else {
+            None
+        };
+
         // Recreate swapchain
         self.swapchain = Swapchain::new(
             self.context.clone(),
             Rc::clone(&self.swapchain_loader),
             window,
+            &self.swapchain_info,
         )?;
 
-        self.color_attachment = Texture::new(
-            self.context.clone(),
-            TextureInfo {
-                extent: self.swapchain.extent(),
-                mip_levels: 1,
-                usage: TextureUsage::ColorAttachment,
-                format: self.swapchain.image_format(),
-                samples: self.context.msaa_samples(),
-            },
-        )?;
+        // The old swapchain was destroyed with the assignment
+        if let Some(surface) = lost_surface {
+            self.context.destroy_surface(surface);
+            self.surface_lost = false;
+        }
 
-        self.depth_attachment = Texture::new(
-            self.context.clone(),
-            TextureInfo {
-                extent: self.swapchain.extent(),
-                mip_levels: 1,
-                usage: TextureUsage::DepthAttachment,
-                format: Format::D32_SFLOAT,
-                samples: self.context.msaa_samples(),
-            },
-        )?;
+        let stereo = self.stereo.as_ref().map(|stereo| *stereo.info());
+        let stereo_target = self.create_attachments(stereo.as_ref())?;
+
+        if let (Some(stereo), Some(target)) = (&mut self.stereo, stereo_target) {
+            stereo.set_target(target);
+        }
+
+        if let Some(shading_rate) = &mut self.shading_rate {
+            shading_rate.resize(self.swapchain.extent())?;
+        }
 
         // Renderpass depends on swapchain surface format
         if old_surface_format != self.swapchain.surface_format() {
             info!("Surface format changed");
-            self.renderpass = create_renderpass(
-                self.context.device_ref(),
-                &self.color_attachment,
-                &self.depth_attachment,
+            if self.renderpass.is_some() {
+                self.renderpass = Some(create_renderpass(
+                    self.context.device_ref(),
+                    &self.color_attachment,
+                    &self.depth_attachment,
+                    self.swapchain.image_format(),
+                )?);
+            }
+
+            self.rendering_formats = rendering_formats(
                 self.swapchain.image_format(),
-            )?;
+                self.depth_format,
+                self.shading_rate.is_some(),
+            );
+
+            // The stereo output pipeline and the pipelines of the passes depend on the
+            // renderpass or rendering formats
+            let stereo = self.stereo.as_ref().map(|stereo| *stereo.info());
+            self.set_stereo(stereo)?;
+
+            self.setup_passes()?;
+        } else {
+            self.resize_passes()?;
+        }
+
+        // The descriptor sets of the renderers outlive the swapchain, so the allocator is not
+        // reset
+        if let Some(light_culling) = &self.light_culling {
+            for light_grid in &mut self.light_grids {
+                light_grid.resize(light_culling, self.swapchain.extent())?;
+            }
+        }
+
+        // Recorded commands may refer to the old renderpass
+        for mesh_renderer in &mut self.mesh_renderers {
+            mesh_renderer.set_inheritance(inheritance(
+                &self.context,
+                self.renderpass.as_ref(),
+                &self.rendering_formats,
+            ));
         }
 
-        self.descriptor_allocator.reset()?;
+        // Attachments depend on the swapchain extent
+        self.picking_renderer = None;
+
+        self.create_per_image_data()
+    }
+
+    // Recreates the multisampled attachments of the main pass. Returns the target of the eyes
+    // of `stereo`, whose attachments share memory with those of the main pass. The eyes each
+    // cover half of the swapchain unless `stereo` specifies the resolution.
+    fn create_attachments(
+        &mut self,
+        stereo: Option<&StereoInfo>,
+    ) -> Result<Option<StereoTarget>, vulkan::Error> {
+        let extent = self.swapchain.extent();
+
+        let stereo_extent = stereo.map(|info| {
+            info.extent
+                .unwrap_or_else(|| Extent::new((extent.width / 2).max(1), extent.height))
+        });
+
+        let ((color_attachment, depth_attachment), stereo_attachments) = create_attachments(
+            &self.context,
+            extent,
+            self.swapchain.image_format(),
+            self.depth_format,
+            stereo_extent,
+        )?;
+
+        self.color_attachment = color_attachment;
+        self.depth_attachment = depth_attachment;
+
+        stereo_attachments
+            .map(|(color_attachment, depth_attachment)| {
+                StereoTarget::new(
+                    self.context.clone(),
+                    color_attachment,
+                    depth_attachment,
+                    self.renderpass.is_none(),
+                )
+            })
+            .transpose()
+    }
 
-        log::debug!("Recreating per frame data");
-        self.per_frame_data.clear();
+    // Recreates the framebuffers of the swapchain images, which refer to the attachments
+    fn create_per_image_data(&mut self) -> Result<(), vulkan::Error> {
+        log::debug!("Recreating per image data");
+        self.per_image_data.clear();
         for swapchain_image in self.swapchain.images() {
-            let frame = PerFrameData::new(
-                self.context.clone(),
-                &self.renderpass,
+            let image = PerImageData::new(
+                &self.context,
+                self.renderpass.as_ref(),
                 &self.color_attachment,
                 &self.depth_attachment,
                 swapchain_image,
             )?;
 
-            self.per_frame_data.push(frame);
+            self.per_image_data.push(image);
         }
 
         Ok(())
     }
 
-    pub fn draw(
-        &mut self,
-        window: &glfw::Window,
-        _dt: f32,
-        camera: &Camera,
-        scene: &mut Scene,
-        resources: &ResourceManager,
-    ) -> Result<(), vulkan::Error> {
+    /// Waits for the frame in flight to become available and acquires the next swapchain image,
+    /// which are the parts of a frame that block. Calling this before polling input and
+    /// updating the views, and `draw` after, records the frame from the most recent input,
+    /// which lowers the latency. Called by `draw` if no image was acquired. Returns false if no
+    /// image could be acquired, e.g; as the swapchain is out of date, and the frame is skipped.
+    pub fn acquire(&mut self, window: &glfw::Window) -> Result<bool, RenderError> {
+        if self.acquired.is_some() {
+            return Ok(true);
+        }
+
         if self.should_resize {
-            self.resize(window)?;
+            self.resize(window).map_err(RenderError::Resize)?;
         }
 
         let device = self.context.device();
 
+        let frame = &self.per_frame_data[self.current_frame];
+
+        // Whether the device is still processing the previous use of current_frame
+        let pending =
+            !fence::is_signaled(device, frame.in_flight_fence).map_err(RenderError::Acquire)?;
+
         // Wait for current_frame to not be in use
-        fence::wait(device, &[self.in_flight_fences[self.current_frame]], true)?;
+        fence::wait(device, &[frame.in_flight_fence], true).map_err(RenderError::Acquire)?;
 
-        // Acquire the next image from swapchain
-        let image_index = match self
-            .swapchain
-            .next_image(self.image_available_semaphores[self.current_frame])
-        {
-            Ok(image_index) => image_index,
-            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                self.on_resize();
-                return Ok(());
-            }
+        if let Some(throttle) = &mut self.throttle {
+            // Waiting for the previous frame as well keeps the device from having more than the
+            // frame being recorded queued. The previous frame is considered while throttled, as
+            // the frame in flight has then always completed.
+            let pending = if throttle.should_wait() {
+                let frame_count = self.per_frame_data.len();
+                let previous =
+                    &self.per_frame_data[(self.current_frame + frame_count - 1) % frame_count];
 
-            Err(e) => return Err(e.into()),
-        };
+                let pending = !fence::is_signaled(device, previous.in_flight_fence)
+                    .map_err(RenderError::Acquire)?;
+
+                fence::wait(device, &[previous.in_flight_fence], true)
+                    .map_err(RenderError::Acquire)?;
+
+                pending
+            } else {
+                pending
+            };
+
+            throttle.push(pending);
+        }
+
+        if let Some(capture) = &mut self.capture {
+            capture
+                .collect(self.current_frame)
+                .map_err(RenderError::Capture)?;
+        }
+
+        // Acquire the next image from swapchain
+        let (image_index, suboptimal) =
+            match self.swapchain.next_image(frame.image_available_semaphore) {
+                Ok(acquired) => acquired,
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    self.on_resize();
+                    return Ok(false);
+                }
+                Err(vk::Result::ERROR_SURFACE_LOST_KHR) => {
+                    self.on_surface_lost();
+                    return Ok(false);
+                }
+
+                Err(e) => return Err(RenderError::Acquire(e.into())),
+            };
+
+        // The acquired image still needs to be presented, so the swapchain is recreated on the
+        // next frame
+        if suboptimal && self.swapchain_info.recreate_on_suboptimal {
+            self.should_resize = true;
+        }
 
         // Extract data for this image in swapchain
-        let frame = &mut self.per_frame_data[image_index as usize];
+        let image = &mut self.per_image_data[image_index as usize];
 
         // Wait if previous frame is using this image
-        if frame.image_in_flight != ash::vk::Fence::null() {
-            fence::wait(device, &[frame.image_in_flight], true)?;
+        if image.image_in_flight != ash::vk::Fence::null() {
+            fence::wait(device, &[image.image_in_flight], true).map_err(RenderError::Acquire)?;
         }
 
         // Mark the image as being used by the frame in flight
-        frame.image_in_flight = self.in_flight_fences[self.current_frame];
+        image.image_in_flight = frame.in_flight_fence;
 
-        frame.commandpool.reset(false)?;
-        frame
-            .commandbuffer
-            .begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)?;
+        self.acquired = Some(image_index);
 
-        frame.commandbuffer.begin_renderpass(
-            &self.renderpass,
-            &frame.framebuffer,
-            self.swapchain.extent(),
-            // TODO Autogenerate clear color based on one value
-            &[
-                vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 0.0],
-                    },
-                },
-                vk::ClearValue {
-                    depth_stencil: vk::ClearDepthStencilValue {
-                        depth: 1.0,
-                        stencil: 0,
-                    },
-                },
-            ],
-        );
+        Ok(true)
+    }
 
-        self.mesh_renderer
-            .draw(&frame.commandbuffer, resources, camera, image_index, scene)?;
+    /// Draws the scene from each camera into its viewport of the swapchain image, e.g; for
+    /// split-screen or picture-in-picture. Later views are drawn on top of earlier views. `dt` is
+    /// the frame time in seconds, which advances the animation of the water. Acquires the image
+    /// first unless `acquire` was called.
+    pub fn draw(
+        &mut self,
+        window: &glfw::Window,
+        dt: f32,
+        views: &[(Camera, Viewport)],
+        scene: &mut Scene,
+        resources: &ResourceManager,
+    ) -> Result<(), RenderError> {
+        if !self.acquire(window)? {
+            return Ok(());
+        }
 
-        frame.commandbuffer.end_renderpass();
-        frame.commandbuffer.end()?;
+        let image_index = self
+            .acquired
+            .take()
+            .expect("No swapchain image was acquired");
 
-        // Present
-        let wait_semaphores = [self.image_available_semaphores[self.current_frame]];
+        self.reserve_views(views.len())
+            .map_err(|source| RenderError::Views {
+                count: views.len(),
+                source,
+            })?;
 
-        let signal_semaphores = [self.render_finished_semaphores[self.current_frame]];
+        scene.sync_world();
 
-        // Reset fence before
-        fence::reset(device, &[self.in_flight_fences[self.current_frame]])?;
-
-        // Submit command buffers
-        frame.commandbuffer.submit(
-            self.context.graphics_queue(),
-            &wait_semaphores,
-            &signal_semaphores,
-            self.in_flight_fences[self.current_frame],
-            &[ash::vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT],
-        )?;
+        // Objects are culled against the view of each camera through the hierarchy
+        scene.update_bvh(resources.meshes());
 
-        let _suboptimal = match self.swapchain.present(
-            self.context.present_queue(),
-            &signal_semaphores,
-            image_index,
-        ) {
-            Ok(image_index) => image_index,
-            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                self.on_resize();
-                return Ok(());
+        let device = self.context.device();
+
+        let frame = &mut self.per_frame_data[self.current_frame];
+        let image = &mut self.per_image_data[image_index as usize];
+
+        frame
+            .commandpool
+            .reset(false)
+            .map_err(RenderError::Record)?;
+        frame
+            .commandbuffer
+            .begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .map_err(RenderError::Record)?;
+
+        let compute = frame.compute.as_ref();
+
+        if let Some(compute) = compute {
+            compute
+                .commandpool
+                .reset(false)
+                .map_err(RenderError::Record)?;
+
+            for commandbuffer in &[&compute.commandbuffer, &compute.shadow_commandbuffer] {
+                commandbuffer
+                    .begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                    .map_err(RenderError::Record)?;
             }
+        }
 
-            Err(e) => return Err(e.into()),
+        // The shadows and culling are recorded separately when overlapping on the compute queue
+        let (shadow_commandbuffer, culling_commandbuffer, culling_queue) = match compute {
+            Some(compute) => (
+                &compute.shadow_commandbuffer,
+                &compute.commandbuffer,
+                CullingQueue::Compute,
+            ),
+            None => (
+                &frame.commandbuffer,
+                &frame.commandbuffer,
+                CullingQueue::Graphics,
+            ),
         };
 
-        self.current_frame = (self.current_frame + 1) % FRAMES_IN_FLIGHT as usize;
+        let debug_utils = self.context.debug_utils();
 
-        Ok(())
-    }
+        // Shadows are drawn first so that all later passes can sample them
+        if let (Some(shadow_map), Some((camera, viewport))) = (&mut self.shadow_map, views.first())
+        {
+            let camera = camera.with_viewport(viewport, self.swapchain.extent());
+
+            shadow_commandbuffer.begin_label(debug_utils, "Shadows");
+            shadow_map
+                .draw(
+                    shadow_commandbuffer,
+                    resources,
+                    &camera,
+                    self.current_frame,
+                    scene,
+                )
+                .map_err(RenderError::Shadows)?;
+            shadow_commandbuffer.end_label(debug_utils);
+        }
+
+        shadow_commandbuffer.begin_label(debug_utils, "Point shadows");
+        self.point_shadows
+            .draw(shadow_commandbuffer, resources, self.current_frame, scene)
+            .map_err(|(light, source)| RenderError::PointShadows { light, source })?;
+        shadow_commandbuffer.end_label(debug_utils);
+
+        if let Some(light_culling) = &mut self.light_culling {
+            culling_commandbuffer.begin_label(debug_utils, "Light upload");
+            light_culling.update(culling_commandbuffer, scene, culling_queue);
+            culling_commandbuffer.end_label(debug_utils);
+        }
+
+        if let Some(fog) = &self.fog {
+            fog.update(&frame.commandbuffer, scene.fog());
+        }
+
+        if let Some(acceleration_structure) = &self.acceleration_structure {
+            frame
+                .commandbuffer
+                .begin_label(debug_utils, "Acceleration structure");
+            acceleration_structure.update(&frame.commandbuffer, scene, resources.meshes());
+            frame.commandbuffer.end_label(debug_utils);
+        }
+
+        self.time += dt;
+        water::update_uniform(&frame.commandbuffer, resources, scene.water(), self.time);
+
+        let extent = self.swapchain.extent();
+        let swapchain_image = self.swapchain.image(image_index as usize);
+
+        let pass_frame = PassFrame {
+            frame_index: self.current_frame,
+            dt,
+            views,
+            resources,
+            extent,
+            swapchain_image,
+            inheritance: None,
+            view: None,
+            sun_direction: self
+                .shadow_map
+                .as_ref()
+                .map(|shadow_map| shadow_map.direction),
+        };
+
+        record_passes(
+            &self.context,
+            &mut self.passes,
+            PassStage::PreMain,
+            &frame.commandbuffer,
+            &pass_frame,
+            scene,
+        )?;
+
+        // Render targets are drawn first so that the main pass can sample their textures
+        for (handle, target) in self.render_targets.iter_mut() {
+            if target.enabled {
+                frame
+                    .commandbuffer
+                    .begin_label(debug_utils, &format!("Render target {:?}", handle));
+                target
+                    .draw(
+                        &frame.commandbuffer,
+                        resources,
+                        self.current_frame,
+                        scene,
+                        self.light_culling.as_ref(),
+                    )
+                    .map_err(|source| RenderError::RenderTarget {
+                        target: handle,
+                        source,
+                    })?;
+                frame.commandbuffer.end_label(debug_utils);
+            }
+        }
+
+        // The eyes are drawn before the main pass, which presents them in place of the first view
+        if let (Some(stereo), Some((camera, _))) = (&mut self.stereo, views.first()) {
+            frame.commandbuffer.begin_label(debug_utils, "Stereo");
+            stereo
+                .draw(
+                    &frame.commandbuffer,
+                    resources,
+                    camera,
+                    self.current_frame,
+                    scene,
+                )
+                .map_err(RenderError::Stereo)?;
+            frame.commandbuffer.end_label(debug_utils);
+        }
+
+        // Culling is recorded outside of the renderpass
+        if let Some(light_culling) = &self.light_culling {
+            culling_commandbuffer.begin_label(debug_utils, "Light culling");
+            for (light_grid, (camera, viewport)) in self.light_grids.iter().zip(views) {
+                let camera = camera.with_viewport(viewport, extent);
+                light_grid.cull(
+                    culling_commandbuffer,
+                    light_culling,
+                    &camera,
+                    viewport.rect(extent),
+                    culling_queue,
+                );
+            }
+            culling_commandbuffer.end_label(debug_utils);
+        }
+
+        let contents = if self.mesh_renderers[0].uses_secondary() {
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+        } else {
+            vk::SubpassContents::INLINE
+        };
+
+        // Draws within the pass need to be recorded into secondary command buffers as well
+        let secondary_inheritance = match contents {
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS => Some(inheritance(
+                &self.context,
+                self.renderpass.as_ref(),
+                &self.rendering_formats,
+            )),
+            _ => None,
+        };
+
+        // Secondary command buffers may not be labeled within the pass, so the label covers
+        // all views
+        frame.commandbuffer.begin_label(debug_utils, "Main pass");
+
+        match (&self.renderpass, &image.framebuffer) {
+            (Some(renderpass), Some(framebuffer)) => frame.commandbuffer.begin_renderpass(
+                renderpass,
+                framebuffer,
+                self.swapchain.extent(),
+                &CLEAR_VALUES,
+                contents,
+            ),
+            _ => begin_dynamic_rendering(
+                &self.context,
+                &frame.commandbuffer,
+                &self.color_attachment,
+                &self.depth_attachment,
+                swapchain_image,
+                self.shading_rate.as_ref().map(|image| image.attachment()),
+                contents,
+            ),
+        }
+
+        for (i, (mesh_renderer, (camera, viewport))) in
+            self.mesh_renderers.iter_mut().zip(views).enumerate()
+        {
+            if let (0, Some(stereo)) = (i, &mut self.stereo) {
+                stereo
+                    .draw_output(
+                        &frame.commandbuffer,
+                        self.current_frame,
+                        viewport.rect(extent),
+                        secondary_inheritance.as_ref(),
+                    )
+                    .map_err(|source| RenderError::View { index: i, source })?;
+                continue;
+            }
+
+            // The depth was cleared with the attachment for the first view
+            mesh_renderer.set_clear_depth(i > 0);
+            let camera = camera.with_viewport(viewport, extent);
+
+            mesh_renderer.set_viewport(viewport.rect(extent));
+            mesh_renderer
+                .draw(
+                    &frame.commandbuffer,
+                    resources,
+                    &camera,
+                    self.current_frame,
+                    scene,
+                    MAIN_PASSES,
+                )
+                .map_err(|source| RenderError::View { index: i, source })?;
+
+            // The sky, grid and gizmo among others, which depend on the depth of the view
+            record_passes(
+                &self.context,
+                &mut self.passes,
+                PassStage::View,
+                &frame.commandbuffer,
+                &PassFrame {
+                    inheritance: secondary_inheritance.as_ref(),
+                    view: Some(i),
+                    ..pass_frame
+                },
+                scene,
+            )?;
+        }
+
+        record_passes(
+            &self.context,
+            &mut self.passes,
+            PassStage::Overlay,
+            &frame.commandbuffer,
+            &PassFrame {
+                inheritance: secondary_inheritance.as_ref(),
+                ..pass_frame
+            },
+            scene,
+        )?;
+
+        match image.framebuffer {
+            Some(_) => frame.commandbuffer.end_renderpass(),
+            None => end_dynamic_rendering(&self.context, &frame.commandbuffer, swapchain_image),
+        }
+
+        frame.commandbuffer.end_label(debug_utils);
+
+        record_passes(
+            &self.context,
+            &mut self.passes,
+            PassStage::PostMain,
+            &frame.commandbuffer,
+            &pass_frame,
+            scene,
+        )?;
+
+        if let Some(capture) = &mut self.capture {
+            capture
+                .record(&frame.commandbuffer, self.current_frame, swapchain_image)
+                .map_err(RenderError::Capture)?;
+        }
+
+        frame.commandbuffer.end().map_err(RenderError::Record)?;
+
+        let mut wait_semaphores = ArrayVec::<[vk::Semaphore; 2]>::new();
+        let mut wait_stages = ArrayVec::<[vk::PipelineStageFlags; 2]>::new();
+        let mut signal_semaphores = ArrayVec::<[vk::Semaphore; 2]>::new();
+
+        wait_semaphores.push(frame.image_available_semaphore);
+        wait_stages.push(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+        signal_semaphores.push(frame.render_finished_semaphore);
+
+        if let Some(compute) = compute {
+            compute.commandbuffer.end().map_err(RenderError::Record)?;
+            compute
+                .shadow_commandbuffer
+                .end()
+                .map_err(RenderError::Record)?;
+
+            // Waits for the previous frame to finish reading the light buffers
+            let rendered: Vec<_> = self.pending_rendered_semaphore.take().into_iter().collect();
+            let rendered_stages: Vec<_> = rendered
+                .iter()
+                .map(|_| vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER)
+                .collect();
+
+            compute
+                .commandbuffer
+                .submit(
+                    self.context.compute_queue(),
+                    &rendered,
+                    &[compute.culled_semaphore],
+                    vk::Fence::null(),
+                    &rendered_stages,
+                )
+                .map_err(RenderError::Submit)?;
+
+            // The shadows do not depend on the culling and are rendered while it runs
+            compute
+                .shadow_commandbuffer
+                .submit(
+                    self.context.graphics_queue(),
+                    &[],
+                    &[],
+                    vk::Fence::null(),
+                    &[],
+                )
+                .map_err(RenderError::Submit)?;
+
+            // The render targets cull their own grids from the uploaded lights
+            wait_semaphores.push(compute.culled_semaphore);
+            wait_stages.push(
+                vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            );
+            signal_semaphores.push(compute.rendered_semaphore);
+        }
+
+        // Reset fence before
+        fence::reset(device, &[frame.in_flight_fence]).map_err(RenderError::Submit)?;
+
+        // Submit command buffers. The fence also covers the shadows submitted before, and the
+        // culling waited on.
+        frame
+            .commandbuffer
+            .submit(
+                self.context.graphics_queue(),
+                &wait_semaphores,
+                &signal_semaphores,
+                frame.in_flight_fence,
+                &wait_stages,
+            )
+            .map_err(RenderError::Submit)?;
+
+        self.pending_rendered_semaphore = compute.map(|compute| compute.rendered_semaphore);
+
+        match self.swapchain.present(
+            self.context.present_queue(),
+            &signal_semaphores[..1],
+            image_index,
+        ) {
+            Ok(suboptimal) => {
+                if suboptimal && self.swapchain_info.recreate_on_suboptimal {
+                    self.on_resize();
+                }
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.on_resize();
+                return Ok(());
+            }
+            Err(vk::Result::ERROR_SURFACE_LOST_KHR) => {
+                self.on_surface_lost();
+                return Ok(());
+            }
+
+            Err(e) => return Err(RenderError::Present(e.into())),
+        };
+
+        self.current_frame = (self.current_frame + 1) % self.per_frame_data.len();
+
+        let frame_time = self.frame_limiter.wait();
+        self.frame_stats.push(frame_time);
+
+        Ok(())
+    }
+
+    /// Returns the handle of the scene object visible at framebuffer pixel `x`, `y`, or None if
+    /// there is no object at that position.
+    /// Renders the object ids in a separate pass and waits for the result.
+    pub fn pick(
+        &mut self,
+        x: u32,
+        y: u32,
+        camera: &Camera,
+        scene: &Scene,
+        resources: &ResourceManager,
+    ) -> Result<Option<ObjectHandle>, vulkan::Error> {
+        if self.picking_renderer.is_none() {
+            self.picking_renderer = Some(PickingRenderer::new(
+                self.context.clone(),
+                &mut self.descriptor_layout_cache,
+                &mut self.descriptor_allocator,
+                self.swapchain.extent(),
+            )?);
+        }
+
+        let camera = camera.with_viewport_aspect(self.swapchain.extent().aspect());
+
+        self.picking_renderer
+            .as_mut()
+            .unwrap()
+            .pick(x, y, resources, &camera, scene)
+    }
+
+    // Creates mesh renderers until there is one for each of `view_count` views
+    fn reserve_views(&mut self, view_count: usize) -> Result<(), vulkan::Error> {
+        while self.mesh_renderers.len() < view_count {
+            let mut mesh_renderer = MeshRenderer::new(
+                self.context.clone(),
+                &mut self.descriptor_layout_cache,
+                &mut self.descriptor_allocator,
+                self.per_frame_data.len(),
+                self.mesh_renderer_info,
+            )?;
+
+            mesh_renderer.set_inheritance(inheritance(
+                &self.context,
+                self.renderpass.as_ref(),
+                &self.rendering_formats,
+            ));
+
+            mesh_renderer
+                .set_shadow_set(self.shadow_map.as_ref().map(|shadow_map| shadow_map.set()));
+            mesh_renderer.set_point_shadow_set(Some(self.point_shadows.set()));
+
+            if let Some(light_culling) = &self.light_culling {
+                let light_grid = LightGrid::new(
+                    self.context.clone(),
+                    &mut self.descriptor_layout_cache,
+                    &mut self.descriptor_allocator,
+                    light_culling,
+                    self.swapchain.extent(),
+                )?;
+
+                mesh_renderer.set_light_set(Some(light_grid.set()));
+                self.light_grids.push(light_grid);
+            }
+
+            mesh_renderer.set_environment_set(
+                self.environment
+                    .as_ref()
+                    .map(|environment| environment.set()),
+            );
+            mesh_renderer.set_fog_set(self.fog.as_ref().map(|fog| fog.set()));
+            mesh_renderer.set_ray_query_set(
+                self.acceleration_structure
+                    .as_ref()
+                    .map(|acceleration_structure| acceleration_structure.set()),
+            );
+
+            self.mesh_renderers.push(mesh_renderer);
+        }
+
+        Ok(())
+    }
+
+    /// Creates a render target drawing the scene from `camera` into a texture before the main
+    /// pass each frame. The texture is inserted into `resources` with the same name and can be
+    /// used as the albedo of materials.
+    /// Returns the existing render target if one with the same name is already present.
+    pub fn create_render_target<S>(
+        &mut self,
+        resources: &mut ResourceManager,
+        name: S,
+        info: RenderTargetInfo,
+    ) -> Result<Handle<RenderTarget>, vulkan::Error>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        let context = self.context.clone();
+        let descriptor_layout_cache = &mut self.descriptor_layout_cache;
+        let descriptor_allocator = &mut self.descriptor_allocator;
+        let frames_in_flight = self.per_frame_data.len();
+        let dynamic_rendering = self.renderpass.is_none();
+        let depth_format = self.depth_format;
+        let shadow_set = self.shadow_map.as_ref().map(|shadow_map| shadow_map.set());
+        let point_shadow_set = self.point_shadows.set();
+        let light_culling = self.light_culling.as_ref();
+        let environment_set = self
+            .environment
+            .as_ref()
+            .map(|environment| environment.set());
+        let fog_set = self.fog.as_ref().map(|fog| fog.set());
+        let ray_query_set = self
+            .acceleration_structure
+            .as_ref()
+            .map(|acceleration_structure| acceleration_structure.set());
+        let texture_name = name.as_ref().to_owned();
+
+        // The texture uses the color format of the main pass for pipeline compatibility
+        let texture_info = TextureInfo {
+            extent: info.extent,
+            mip_levels: 1,
+            usage: TextureUsage::RenderTarget,
+            format: self.swapchain.image_format(),
+            ..Default::default()
+        };
+
+        self.render_targets.insert(name, || {
+            let mut mesh_renderer = MeshRenderer::new(
+                context.clone(),
+                descriptor_layout_cache,
+                descriptor_allocator,
+                frames_in_flight,
+                info.mesh_renderer,
+            )?;
+
+            mesh_renderer.set_shadow_set(shadow_set);
+            mesh_renderer.set_point_shadow_set(Some(point_shadow_set));
+            mesh_renderer.set_environment_set(environment_set);
+            mesh_renderer.set_fog_set(fog_set);
+            mesh_renderer.set_ray_query_set(ray_query_set);
+
+            let texture = Texture::new(context.clone(), texture_info)?;
+            let texture = resources.insert_texture(texture_name, texture);
+
+            let light_grid = light_culling
+                .map(|light_culling| {
+                    LightGrid::new(
+                        context.clone(),
+                        descriptor_layout_cache,
+                        descriptor_allocator,
+                        light_culling,
+                        info.extent,
+                    )
+                })
+                .transpose()?;
+
+            let mut render_target = RenderTarget::new(
+                context,
+                texture,
+                resources.textures().raw(texture).unwrap(),
+                mesh_renderer,
+                dynamic_rendering,
+                depth_format,
+                info,
+            )?;
+
+            render_target.set_light_grid(light_grid);
+            Ok(render_target)
+        })
+    }
+
+    /// Returns the render targets drawn before the main pass.
+    pub fn render_targets(&self) -> &ResourceCache<RenderTarget> {
+        &self.render_targets
+    }
+
+    /// Returns a mutable reference to a render target, e.g; for moving the camera.
+    pub fn render_target_mut(
+        &mut self,
+        handle: Handle<RenderTarget>,
+    ) -> Result<&mut RenderTarget, resources::Error> {
+        self.render_targets.raw_mut(handle)
+    }
+
+    /// Creates a graphics pipeline compatible with the master renderer's attachments, see
+    /// `PassTargets::create_pipeline`.
+    pub fn create_pipeline(&mut self, info: PipelineInfo) -> Result<Pipeline, vulkan::Error> {
+        self.pass_targets().create_pipeline(info)
+    }
+
+    /// Returns the attachments of the main pass along with the descriptor allocators, which
+    /// passes create their pipelines and descriptor sets from.
+    pub fn pass_targets(&mut self) -> PassTargets<'_> {
+        PassTargets::new(
+            &self.context,
+            self.swapchain.extent(),
+            self.per_frame_data.len(),
+            &mut self.descriptor_layout_cache,
+            &mut self.descriptor_allocator,
+            self.renderpass.as_ref(),
+            &self.rendering_formats,
+        )
+    }
+
+    /// Sets up `pass` and executes it each frame after the passes of the same stage already
+    /// added. The names of `BUILTIN_PASSES` are reserved for the built-in passes.
+    pub fn add_pass(&mut self, pass: Box<dyn Pass>) -> Result<(), vulkan::Error> {
+        self.insert_pass(self.passes.len(), pass)
+    }
+
+    /// Sets up `pass` and executes it each frame before the pass at `index`, and after earlier
+    /// passes of the same stage.
+    pub fn insert_pass(
+        &mut self,
+        index: usize,
+        mut pass: Box<dyn Pass>,
+    ) -> Result<(), vulkan::Error> {
+        let context = self.context.clone();
+        pass.setup(&context, &mut self.pass_targets())?;
+
+        self.passes.insert(index, pass);
+        Ok(())
+    }
+
+    /// Removes the first pass named `name`, which waits for the device to become idle. Returns
+    /// None if there is no such pass.
+    pub fn remove_pass(&mut self, name: &str) -> Result<Option<Box<dyn Pass>>, vulkan::Error> {
+        let index = match self.passes.iter().position(|pass| pass.name() == name) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        device::wait_idle(self.context.device())?;
+
+        Ok(Some(self.passes.remove(index)))
+    }
+
+    /// Returns the passes in the order they are added.
+    pub fn passes(&self) -> &[Box<dyn Pass>] {
+        &self.passes
+    }
+
+    /// Returns a mutable reference to the first pass named `name`, e.g; for changing its
+    /// settings.
+    pub fn pass_mut(&mut self, name: &str) -> Option<&mut Box<dyn Pass>> {
+        self.passes.iter_mut().find(|pass| pass.name() == name)
+    }
+
+    /// Sets up the passes again after the formats of the main pass changed
+    fn setup_passes(&mut self) -> Result<(), vulkan::Error> {
+        let context = self.context.clone();
+        let mut passes = std::mem::take(&mut self.passes);
+
+        let result = passes
+            .iter_mut()
+            .try_for_each(|pass| pass.setup(&context, &mut self.pass_targets()));
+
+        self.passes = passes;
+        result
+    }
+
+    // Replaces the built-in pass named `name`, or removes it if None, which waits for the
+    // device to become idle. New built-in passes are inserted before the built-in passes
+    // following them in `BUILTIN_PASSES`.
+    fn set_builtin_pass(
+        &mut self,
+        name: &str,
+        pass: Option<Box<dyn Pass>>,
+    ) -> Result<(), vulkan::Error> {
+        device::wait_idle(self.context.device())?;
+
+        let index = match self.passes.iter().position(|pass| pass.name() == name) {
+            Some(index) => {
+                self.passes.remove(index);
+                index
+            }
+            None => {
+                let following = BUILTIN_PASSES
+                    .iter()
+                    .position(|builtin| *builtin == name)
+                    .map_or(&[][..], |order| &BUILTIN_PASSES[order + 1..]);
+
+                self.passes
+                    .iter()
+                    .position(|pass| following.contains(&pass.name()))
+                    .unwrap_or(self.passes.len())
+            }
+        };
+
+        match pass {
+            Some(pass) => self.insert_pass(index, pass),
+            None => Ok(()),
+        }
+    }
+
+    // Returns the built-in pass named `name` if it is enabled
+    fn builtin_pass<T: Pass>(&self, name: &str) -> Option<&T> {
+        let pass = self.passes.iter().find(|pass| pass.name() == name)?;
+        (pass.as_ref() as &dyn Any).downcast_ref()
+    }
+
+    fn builtin_pass_mut<T: Pass>(&mut self, name: &str) -> Option<&mut T> {
+        let pass = self.passes.iter_mut().find(|pass| pass.name() == name)?;
+        (pass.as_mut() as &mut dyn Any).downcast_mut()
+    }
+
+    fn resize_passes(&mut self) -> Result<(), vulkan::Error> {
+        let mut passes = std::mem::take(&mut self.passes);
+
+        let result = passes
+            .iter_mut()
+            .try_for_each(|pass| pass.on_resize(&mut self.pass_targets()));
+
+        self.passes = passes;
+        result
+    }
+
+    /// Renders the shadows of a directional light into cascades fit to the camera of the first
+    /// view each frame. Effects sample the shadows through the shadow set, see
+    /// `shadow::SHADOW_SET`, and are drawn into the cascades with their `PassTag::Shadow`
+    /// pipeline. Replaces the current shadow map, which waits for the device to become idle.
+    pub fn enable_shadows(&mut self, info: ShadowInfo) -> Result<(), vulkan::Error> {
+        device::wait_idle(self.context.device())?;
+
+        let shadow_map = CascadedShadowMap::new(
+            self.context.clone(),
+            &mut self.descriptor_layout_cache,
+            &mut self.descriptor_allocator,
+            self.per_frame_data.len(),
+            self.renderpass.is_none(),
+            self.shadow_format,
+            info,
+        )?;
+
+        for mesh_renderer in &mut self.mesh_renderers {
+            mesh_renderer.set_shadow_set(Some(shadow_map.set()));
+        }
+
+        for (_, render_target) in self.render_targets.iter_mut() {
+            render_target.set_shadow_set(Some(shadow_map.set()));
+        }
+
+        if let Some(stereo) = &mut self.stereo {
+            stereo.set_shadow_set(Some(shadow_map.set()));
+        }
+
+        self.shadow_map = Some(shadow_map);
+        Ok(())
+    }
+
+    /// Stops rendering shadows. Objects whose effect samples shadows are no longer drawn.
+    /// Waits for the device to become idle.
+    pub fn disable_shadows(&mut self) -> Result<(), vulkan::Error> {
+        device::wait_idle(self.context.device())?;
+
+        for mesh_renderer in &mut self.mesh_renderers {
+            mesh_renderer.set_shadow_set(None);
+        }
+
+        for (_, render_target) in self.render_targets.iter_mut() {
+            render_target.set_shadow_set(None);
+        }
+
+        if let Some(stereo) = &mut self.stereo {
+            stereo.set_shadow_set(None);
+        }
+
+        self.shadow_map = None;
+        Ok(())
+    }
+
+    /// Returns the shadow map if shadows are enabled.
+    pub fn shadow_map(&self) -> Option<&CascadedShadowMap> {
+        self.shadow_map.as_ref()
+    }
+
+    /// Returns a mutable reference to the shadow map, e.g; for changing the light direction.
+    pub fn shadow_map_mut(&mut self) -> Option<&mut CascadedShadowMap> {
+        self.shadow_map.as_mut()
+    }
+
+    /// Draws an editor style grid on the ground plane after the objects of each view, or stops
+    /// drawing it if None. Replaces the current grid, which waits for the device to become
+    /// idle.
+    pub fn set_grid(&mut self, info: Option<GridInfo>) -> Result<(), vulkan::Error> {
+        let pass = info.map(|info| Box::new(GridRenderer::new(info)) as Box<dyn Pass>);
+        self.set_builtin_pass(grid::NAME, pass)
+    }
+
+    /// Returns the grid renderer if the grid is drawn.
+    pub fn grid(&self) -> Option<&GridRenderer> {
+        self.builtin_pass(grid::NAME)
+    }
+
+    /// Returns a mutable reference to the grid renderer, e.g; for changing the spacing.
+    pub fn grid_mut(&mut self) -> Option<&mut GridRenderer> {
+        self.builtin_pass_mut(grid::NAME)
+    }
+
+    /// Draws a procedural sky behind the objects of each view, or stops drawing it if None.
+    /// The sun follows the direction of the shadow map while shadows are enabled. Replaces the
+    /// current sky, which waits for the device to become idle.
+    pub fn set_sky(&mut self, info: Option<SkyInfo>) -> Result<(), vulkan::Error> {
+        let pass = info.map(|info| Box::new(SkyRenderer::new(info)) as Box<dyn Pass>);
+        self.set_builtin_pass(sky::NAME, pass)
+    }
+
+    /// Returns the sky renderer if the sky is drawn.
+    pub fn sky(&self) -> Option<&SkyRenderer> {
+        self.builtin_pass(sky::NAME)
+    }
+
+    /// Returns a mutable reference to the sky renderer, e.g; for changing the colors.
+    pub fn sky_mut(&mut self) -> Option<&mut SkyRenderer> {
+        self.builtin_pass_mut(sky::NAME)
+    }
+
+    /// Draws the scene for both eyes of the camera of the first view through multiview, and
+    /// presents them side by side in its place, or stops if None. Only effects with a
+    /// `PassTag::Stereo` pipeline, see `create_stereo_pipeline`, are drawn for the eyes.
+    /// Does nothing if multiview is not supported. Replaces the current stereo renderer, which
+    /// waits for the device to become idle.
+    pub fn set_stereo(&mut self, info: Option<StereoInfo>) -> Result<(), vulkan::Error> {
+        device::wait_idle(self.context.device())?;
+
+        self.stereo = None;
+
+        let info = match info {
+            Some(info) => info,
+            None => return Ok(()),
+        };
+
+        let capabilities = self.context.capabilities();
+
+        if !capabilities.features.multiview
+            || capabilities.max_multiview_view_count < EYE_COUNT as u32
+        {
+            log::warn!("Multiview is not supported, the stereo view can not be drawn");
+            return Ok(());
+        }
+
+        if capabilities.max_bound_descriptor_sets <= VIEW_SET {
+            log::warn!("The view set can not be bound, the stereo view can not be drawn");
+            return Ok(());
+        }
+
+        let mut mesh_renderer = MeshRenderer::new(
+            self.context.clone(),
+            &mut self.descriptor_layout_cache,
+            &mut self.descriptor_allocator,
+            self.per_frame_data.len(),
+            info.mesh_renderer,
+        )?;
+
+        mesh_renderer.set_shadow_set(self.shadow_map.as_ref().map(|shadow_map| shadow_map.set()));
+        mesh_renderer.set_point_shadow_set(Some(self.point_shadows.set()));
+        mesh_renderer.set_environment_set(
+            self.environment
+                .as_ref()
+                .map(|environment| environment.set()),
+        );
+        mesh_renderer.set_fog_set(self.fog.as_ref().map(|fog| fog.set()));
+        mesh_renderer.set_ray_query_set(
+            self.acceleration_structure
+                .as_ref()
+                .map(|acceleration_structure| acceleration_structure.set()),
+        );
+
+        // The main pass attachments are recreated to share memory with the eyes
+        let target = self
+            .create_attachments(Some(&info))?
+            .expect("Stereo target not created");
+        self.create_per_image_data()?;
+
+        let pipeline =
+            self.create_pipeline(stereo::output_pipeline_info(self.context.msaa_samples()))?;
+
+        let output =
+            FullscreenPass::new(self.context.clone(), pipeline, self.per_frame_data.len())?;
+
+        self.stereo = Some(StereoRenderer::new(
+            self.context.clone(),
+            &mut self.descriptor_layout_cache,
+            &mut self.descriptor_allocator,
+            mesh_renderer,
+            target,
+            output,
+            info,
+        )?);
+
+        Ok(())
+    }
+
+    /// Returns the stereo renderer if the first view is drawn in stereo.
+    pub fn stereo(&self) -> Option<&StereoRenderer> {
+        self.stereo.as_ref()
+    }
+
+    /// Returns a mutable reference to the stereo renderer, e.g; for changing the eye
+    /// separation.
+    pub fn stereo_mut(&mut self) -> Option<&mut StereoRenderer> {
+        self.stereo.as_mut()
+    }
+
+    /// Captures the presented frames to images or an encoder process, or stops if None. Writes
+    /// out the frames of the current capture still in flight, which waits for the device to
+    /// become idle. The swapchain is recreated on the next frame if its images can not be
+    /// copied from, and nothing is captured if the surface does not support it.
+    pub fn set_capture(&mut self, info: Option<CaptureInfo>) -> Result<(), capture::Error> {
+        if let Some(mut capture) = self.capture.take() {
+            capture.finish()?;
+        }
+
+        let info = match info {
+            Some(info) => info,
+            None => return Ok(()),
+        };
+
+        let support = swapchain::query_support(
+            self.context.surface_loader(),
+            self.context.surface(),
+            self.context.physical_device(),
+        )?;
+
+        if !support
+            .capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            log::warn!("The swapchain images can not be copied, frames will not be captured");
+            return Ok(());
+        }
+
+        if !self
+            .swapchain
+            .usage()
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            self.swapchain_info.transfer_src = true;
+            self.should_resize = true;
+        }
+
+        self.capture = Some(Capture::new(
+            self.context.clone(),
+            info,
+            self.per_frame_data.len(),
+        )?);
+
+        Ok(())
+    }
+
+    /// Returns the current capture, e.g; for the fixed timestep to advance the application by.
+    pub fn capture(&self) -> Option<&Capture> {
+        self.capture.as_ref()
+    }
+
+    /// Creates a multiview pipeline for the `PassTag::Stereo` pass of effects, compatible with
+    /// the attachments of the stereo view. The vertex shader needs to read the view projection
+    /// of each eye from `stereo::VIEW_SET`. Fails if multiview is not supported.
+    pub fn create_stereo_pipeline(
+        &mut self,
+        info: PipelineInfo,
+    ) -> Result<Pipeline, vulkan::Error> {
+        if !self.context.capabilities().features.multiview {
+            return Err(vulkan::Error::MissingFeature("multiview"));
+        }
+
+        let info = info.supported(self.context.capabilities())?;
+        let color_format = self.swapchain.image_format();
+
+        match &self.renderpass {
+            Some(_) => {
+                // Pipelines only need a compatible renderpass
+                let renderpass = stereo::create_renderpass(
+                    self.context.device_ref(),
+                    color_format,
+                    self.depth_format,
+                    self.context.msaa_samples(),
+                )?;
+
+                Pipeline::new(
+                    self.context.device_ref(),
+                    &mut self.descriptor_layout_cache,
+                    &renderpass,
+                    info,
+                )
+            }
+            None => Pipeline::new_dynamic(
+                self.context.device_ref(),
+                &mut self.descriptor_layout_cache,
+                &stereo::rendering_formats(color_format, self.depth_format),
+                info,
+            ),
+        }
+    }
+
+    /// Draws the handles of `gizmo` over its target in each view, or stops drawing them if
+    /// None. Replaces the current gizmo, which waits for the device to become idle.
+    pub fn set_gizmo(&mut self, gizmo: Option<Gizmo>) -> Result<(), vulkan::Error> {
+        let pass = match gizmo {
+            Some(gizmo) => Some(Box::new(GizmoRenderer::new(self.context.clone(), gizmo)?) as _),
+            None => None,
+        };
+
+        self.set_builtin_pass(gizmo::NAME, pass)
+    }
+
+    /// Returns the gizmo if its handles are drawn.
+    pub fn gizmo(&self) -> Option<&Gizmo> {
+        self.builtin_pass::<GizmoRenderer>(gizmo::NAME)
+            .map(|gizmo| gizmo.gizmo())
+    }
+
+    /// Returns a mutable reference to the gizmo, e.g; for selecting the target and dragging
+    /// the handles. Changes are drawn from the next frame.
+    pub fn gizmo_mut(&mut self) -> Option<&mut Gizmo> {
+        self.builtin_pass_mut::<GizmoRenderer>(gizmo::NAME)
+            .map(|gizmo| gizmo.gizmo_mut())
+    }
+
+    /// Generates the environment maps for image based lighting from an equirectangular
+    /// `source` texture. Effects sample the maps through the environment set, see
+    /// `environment::ENVIRONMENT_SET`. Replaces the current environment, which waits for the
+    /// device to become idle.
+    pub fn set_environment(
+        &mut self,
+        source: &Texture,
+        info: EnvironmentInfo,
+    ) -> Result<(), vulkan::Error> {
+        device::wait_idle(self.context.device())?;
+
+        let environment = Environment::new(
+            self.context.clone(),
+            &mut self.descriptor_layout_cache,
+            &mut self.descriptor_allocator,
+            source,
+            info,
+        )?;
+
+        for mesh_renderer in &mut self.mesh_renderers {
+            mesh_renderer.set_environment_set(Some(environment.set()));
+        }
+
+        for (_, render_target) in self.render_targets.iter_mut() {
+            render_target.set_environment_set(Some(environment.set()));
+        }
+
+        if let Some(stereo) = &mut self.stereo {
+            stereo.set_environment_set(Some(environment.set()));
+        }
+
+        self.environment = Some(environment);
+        Ok(())
+    }
+
+    /// Removes the environment. Objects whose effect is lit by the environment are no longer
+    /// drawn. Waits for the device to become idle.
+    pub fn remove_environment(&mut self) -> Result<(), vulkan::Error> {
+        device::wait_idle(self.context.device())?;
+
+        for mesh_renderer in &mut self.mesh_renderers {
+            mesh_renderer.set_environment_set(None);
+        }
+
+        for (_, render_target) in self.render_targets.iter_mut() {
+            render_target.set_environment_set(None);
+        }
+
+        if let Some(stereo) = &mut self.stereo {
+            stereo.set_environment_set(None);
+        }
+
+        self.environment = None;
+        Ok(())
+    }
+
+    pub fn environment(&self) -> Option<&Environment> {
+        self.environment.as_ref()
+    }
+
+    /// Adds a point light lighting effects through the point shadow set, see
+    /// `point_shadow::POINT_SHADOW_SET`. Shadows are drawn into a cube map with the
+    /// `PassTag::PointShadow` pipeline of effects if `info.cast_shadows` is set.
+    /// Returns the index of the light. Waits for the device to become idle.
+    pub fn add_point_light(&mut self, info: PointLightInfo) -> Result<usize, crate::Error> {
+        device::wait_idle(self.context.device())?;
+
+        self.point_shadows.add(
+            &mut self.descriptor_layout_cache,
+            &mut self.descriptor_allocator,
+            info,
+        )
+    }
+
+    /// Removes the point light at `index`. Later lights move down one index.
+    /// Waits for the device to become idle.
+    pub fn remove_point_light(&mut self, index: usize) -> Result<PointLight, vulkan::Error> {
+        device::wait_idle(self.context.device())?;
+        Ok(self.point_shadows.remove(index))
+    }
+
+    /// Changes the resolution of the cube map of the point light at `index`.
+    /// Waits for the device to become idle.
+    pub fn set_point_shadow_resolution(
+        &mut self,
+        index: usize,
+        resolution: u32,
+    ) -> Result<(), vulkan::Error> {
+        device::wait_idle(self.context.device())?;
+
+        self.point_shadows.set_resolution(
+            &mut self.descriptor_layout_cache,
+            &mut self.descriptor_allocator,
+            index,
+            resolution,
+        )
+    }
+
+    pub fn point_lights(&self) -> &[PointLight] {
+        self.point_shadows.lights()
+    }
+
+    /// Returns a mutable reference to the point light at `index`, e.g; for moving it or
+    /// toggling its shadows.
+    pub fn point_light_mut(&mut self, index: usize) -> Option<&mut PointLight> {
+        self.point_shadows.light_mut(index)
+    }
+
+    /// Creates a depth only pipeline for the `PassTag::Shadow` or `PassTag::PointShadow` pass of
+    /// effects, compatible with the shadow maps. Shadow maps are single sampled, so
+    /// `info.samples` is ignored.
+    pub fn create_shadow_pipeline(
+        &mut self,
+        info: PipelineInfo,
+    ) -> Result<Pipeline, vulkan::Error> {
+        let info = PipelineInfo {
+            samples: vk::SampleCountFlags::TYPE_1,
+            ..info.supported(self.context.capabilities())?
+        };
+
+        match &self.renderpass {
+            Some(_) => {
+                // The renderpass is only needed for compatibility during creation
+                let renderpass =
+                    shadow::create_renderpass(self.context.device_ref(), self.shadow_format)?;
+
+                Pipeline::new(
+                    self.context.device_ref(),
+                    &mut self.descriptor_layout_cache,
+                    &renderpass,
+                    info,
+                )
+            }
+            None => Pipeline::new_dynamic(
+                self.context.device_ref(),
+                &mut self.descriptor_layout_cache,
+                &shadow::rendering_formats(self.shadow_format),
+                info,
+            ),
+        }
+    }
+
+    /// Returns the format of the depth attachment, which has a stencil component if requested.
+    pub fn depth_format(&self) -> vk::Format {
+        self.depth_format
+    }
+
+    /// Returns the depth format of the shadow maps.
+    pub fn shadow_format(&self) -> vk::Format {
+        self.shadow_format
+    }
+
+    /// Returns true if rendering is done using dynamic rendering rather than renderpasses.
+    pub fn uses_dynamic_rendering(&self) -> bool {
+        self.renderpass.is_none()
+    }
 
     /// Get a reference to the master renderer's descriptor layout cache.
     pub fn descriptor_layout_cache(&self) -> &DescriptorLayoutCache {
         &self.descriptor_layout_cache
     }
 
+    /// Returns the number of frames recorded ahead of the device.
+    pub fn frames_in_flight(&self) -> usize {
+        self.per_frame_data.len()
+    }
+
     /// Get a reference to the master renderer's descriptor allocator.
     pub fn descriptor_allocator(&self) -> &DescriptorAllocator {
         &self.descriptor_allocator
@@ -402,20 +2174,287 @@ impl MasterRenderer {
 impl Drop for MasterRenderer {
     fn drop(&mut self) {
         info!("Destroying master renderer");
-        device::wait_idle(self.context.device()).unwrap();
+        // Destroying resources in use is undefined, but panicking in drop would abort during an
+        // unwind
+        if let Err(e) = device::wait_idle(self.context.device()) {
+            log::error!("Failed to wait for the device to become idle: {}", e);
+        }
+    }
+}
 
-        self.image_available_semaphores
-            .iter()
-            .for_each(|s| semaphore::destroy(&self.context.device(), *s));
+/// Records the passes of `stage` in order, labeled by their names unless recorded within a
+/// pass into secondary command buffers
+fn record_passes(
+    context: &VulkanContext,
+    passes: &mut [Box<dyn Pass>],
+    stage: PassStage,
+    commandbuffer: &CommandBuffer,
+    frame: &PassFrame,
+    scene: &Scene,
+) -> Result<(), RenderError> {
+    let debug_utils = context.debug_utils();
+    let labeled = frame.inheritance.is_none();
+
+    for pass in passes.iter_mut().filter(|pass| pass.stage() == stage) {
+        if labeled {
+            commandbuffer.begin_label(debug_utils, pass.name());
+        }
 
-        self.render_finished_semaphores
-            .iter()
-            .for_each(|s| semaphore::destroy(&self.context.device(), *s));
+        pass.record(commandbuffer, frame, scene)
+            .map_err(|source| RenderError::Pass {
+                name: pass.name().to_owned(),
+                source,
+            })?;
 
-        self.in_flight_fences
-            .iter()
-            .for_each(|f| fence::destroy(&self.context.device(), *f));
+        if labeled {
+            commandbuffer.end_label(debug_utils);
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the inheritance of secondary command buffers drawing into the main pass
+fn inheritance(
+    context: &VulkanContext,
+    renderpass: Option<&RenderPass>,
+    rendering_formats: &RenderingFormats,
+) -> Inheritance {
+    match renderpass {
+        Some(renderpass) => Inheritance::RenderPass {
+            renderpass: renderpass.renderpass(),
+            subpass: 0,
+        },
+        None => Inheritance::Dynamic {
+            formats: rendering_formats.clone(),
+            samples: context.msaa_samples(),
+        },
+    }
+}
+
+/// Returns the preferred supported depth format, with a stencil component if `stencil`
+fn depth_format(context: &VulkanContext, stencil: bool) -> vk::Format {
+    if !stencil {
+        return DEPTH_FORMAT;
     }
+
+    *DEPTH_STENCIL_FORMATS
+        .iter()
+        .find(|format| {
+            context.supports_format(**format, vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .expect("Devices are required to support a depth stencil format")
+}
+
+// The multisampled color and depth attachments of a pass
+type Attachments = (Texture, Texture);
+
+// Creates the multisampled color and depth attachments of the main pass, and those of the eyes
+// if `stereo_extent` is Some. The eyes are resolved before the main pass begins, so their
+// attachments are never used at the same time as those of the main pass and share their memory.
+fn create_attachments(
+    context: &Rc<VulkanContext>,
+    extent: Extent,
+    color_format: vk::Format,
+    depth_format: vk::Format,
+    stereo_extent: Option<Extent>,
+) -> Result<(Attachments, Option<Attachments>), vulkan::Error> {
+    let attachment_info = |usage, format| TextureInfo {
+        extent,
+        mip_levels: 1,
+        usage,
+        format,
+        samples: context.msaa_samples(),
+        ..Default::default()
+    };
+
+    let color_info = attachment_info(TextureUsage::ColorAttachment, color_format);
+    let depth_info = attachment_info(TextureUsage::DepthAttachment, depth_format);
+
+    let stereo_extent = match stereo_extent {
+        Some(stereo_extent) => stereo_extent,
+        None => {
+            let attachments = (
+                Texture::new(context.clone(), color_info)?,
+                Texture::new(context.clone(), depth_info)?,
+            );
+
+            return Ok((attachments, None));
+        }
+    };
+
+    let [stereo_color_info, stereo_depth_info] =
+        StereoTarget::attachment_infos(context, color_format, depth_format, stereo_extent);
+
+    let mut colors = Texture::new_aliased(context.clone(), &[color_info, stereo_color_info])?;
+    let mut depths = Texture::new_aliased(context.clone(), &[depth_info, stereo_depth_info])?;
+
+    let stereo_attachments = (colors.remove(1), depths.remove(1));
+    let attachments = (colors.remove(0), depths.remove(0));
+
+    Ok((attachments, Some(stereo_attachments)))
+}
+
+fn rendering_formats(
+    swapchain_format: vk::Format,
+    depth_format: vk::Format,
+    shading_rate_attachment: bool,
+) -> RenderingFormats {
+    let mut color_formats = ArrayVec::new();
+    color_formats.push(swapchain_format);
+
+    RenderingFormats {
+        color_formats,
+        depth_format,
+        shading_rate_attachment,
+        view_mask: 0,
+    }
+}
+
+fn attachment_barrier(
+    texture: &Texture,
+    aspect_mask: vk::ImageAspectFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access_mask: vk::AccessFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier {
+        src_access_mask,
+        dst_access_mask,
+        old_layout,
+        new_layout,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image: texture.image(),
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        ..Default::default()
+    }
+}
+
+/// Transitions the attachments and begins dynamic rendering. Performs the same work as the
+/// renderpass would through its layout transitions.
+fn begin_dynamic_rendering(
+    context: &VulkanContext,
+    commandbuffer: &CommandBuffer,
+    color_attachment: &Texture,
+    depth_attachment: &Texture,
+    swapchain_image: &Texture,
+    shading_rate: Option<ShadingRateAttachment>,
+    contents: vk::SubpassContents,
+) {
+    let dynamic_rendering = context
+        .dynamic_rendering()
+        .expect("Dynamic rendering is not supported");
+
+    // Contents of the previous frame are discarded
+    commandbuffer.pipeline_barrier(
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        &[
+            attachment_barrier(
+                color_attachment,
+                vk::ImageAspectFlags::COLOR,
+                ImageLayout::UNDEFINED,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+            attachment_barrier(
+                depth_attachment,
+                depth_attachment
+                    .usage()
+                    .aspect_mask(depth_attachment.format()),
+                ImageLayout::UNDEFINED,
+                ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            attachment_barrier(
+                swapchain_image,
+                vk::ImageAspectFlags::COLOR,
+                ImageLayout::UNDEFINED,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::AccessFlags::default(),
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+        ],
+    );
+
+    let depth = RenderingAttachment {
+        image_view: depth_attachment.image_view(),
+        layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        load: LoadOp::CLEAR,
+        store: StoreOp::DONT_CARE,
+        clear_value: CLEAR_VALUES[1],
+        resolve: None,
+    };
+
+    // Combined depth stencil formats are used for both attachments
+    let stencil = if has_stencil(depth_attachment.format()) {
+        Some(&depth)
+    } else {
+        None
+    };
+
+    commandbuffer.begin_rendering(
+        dynamic_rendering,
+        swapchain_image.extent(),
+        &RenderingAttachments {
+            color: &[RenderingAttachment {
+                image_view: color_attachment.image_view(),
+                layout: ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                load: LoadOp::CLEAR,
+                // Resolved into the swapchain image, so the transient contents are never stored
+                store: StoreOp::DONT_CARE,
+                clear_value: CLEAR_VALUES[0],
+                resolve: Some((
+                    swapchain_image.image_view(),
+                    ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                )),
+            }],
+            depth: Some(&depth),
+            stencil,
+            shading_rate,
+            view_mask: 0,
+        },
+        contents,
+    );
+}
+
+/// Ends dynamic rendering and transitions the swapchain image for presentation
+fn end_dynamic_rendering(
+    context: &VulkanContext,
+    commandbuffer: &CommandBuffer,
+    swapchain_image: &Texture,
+) {
+    let dynamic_rendering = context
+        .dynamic_rendering()
+        .expect("Dynamic rendering is not supported");
+
+    commandbuffer.end_rendering(dynamic_rendering);
+
+    commandbuffer.pipeline_barrier(
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        &[attachment_barrier(
+            swapchain_image,
+            vk::ImageAspectFlags::COLOR,
+            ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ImageLayout::PRESENT_SRC_KHR,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::AccessFlags::default(),
+        )],
+    );
 }
 
 fn create_renderpass(
@@ -426,11 +2465,11 @@ fn create_renderpass(
 ) -> Result<RenderPass, vulkan::Error> {
     let renderpass_info = RenderPassInfo {
         attachments: &[
-            // Color attachment
+            // Color attachment, resolved into the present attachment
             AttachmentInfo::from_texture(
                 color_attachment,
                 LoadOp::CLEAR,
-                StoreOp::STORE,
+                StoreOp::DONT_CARE,
                 ImageLayout::UNDEFINED,
                 ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
             ),
@@ -467,6 +2506,7 @@ fn create_renderpass(
                 layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
             }),
         }],
+        view_mask: 0,
     };
 
     let renderpass = RenderPass::new(device, &renderpass_info)?;
@@ -0,0 +1,422 @@
+//! Effects and materials described by TOML files, which allows adding and tweaking materials
+//! without recompiling the application. Paths within a file are relative to the file.
+//!
+//! An effect file declares a pipeline for each pass it is drawn in:
+//! ```toml
+//! [[pass]]
+//! tag = "shadow"
+//! vertex = "../shaders/shadow.vert.spv"
+//! fragment = "../shaders/shadow.frag.spv"
+//!
+//! [[pass]]
+//! tag = "opaque"
+//! vertex = "../shaders/default.vert.spv"
+//! fragment = "../shaders/pbr.frag.spv"
+//! cull_mode = "none"
+//! defines = { ALPHA_CUTOFF = "0.5" }
+//! ```
+//!
+//! A material file names its effect and fills the texture slots and parameter blocks of it:
+//! ```toml
+//! effect = "pbr"
+//! albedo = { path = "../textures/uv.png" }
+//! emissive_factor = [1.0, 0.4, 0.1]
+//!
+//! [parameters]
+//! material = [0.9, 0.25]
+//!
+//! [textures]
+//! detailMap = { path = "../textures/detail.png", linear = true }
+//! ```
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ash::vk;
+use serde::Deserialize;
+use ultraviolet::Vec3;
+
+use super::{MaterialInfo, PassTag};
+use crate::mesh::Vertex;
+use crate::vulkan::pipeline::{
+    BlendState, MeshShadingInfo, PipelineInfo, ShadingRateState, TessellationInfo,
+};
+use crate::vulkan::sampler::{AddressMode, FilterMode, MipmapMode, SamplerInfo};
+use crate::vulkan::VertexDesc;
+use crate::Error;
+
+/// The pipelines of a material effect. See the module documentation for the format.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EffectDefinition {
+    #[serde(rename = "pass")]
+    pub passes: Vec<PassDefinition>,
+}
+
+/// The shaders and fixed function state of the pipeline of a pass. Unspecified state uses the
+/// defaults of `PipelineInfo`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PassDefinition {
+    /// One of 'shadow', 'point_shadow', 'stereo', 'opaque', 'transparent' or 'ui'
+    pub tag: String,
+    pub vertex: PathBuf,
+    pub fragment: PathBuf,
+    /// The tessellation shaders, which are given together
+    pub tessellation_control: Option<PathBuf>,
+    pub tessellation_evaluation: Option<PathBuf>,
+    /// The number of vertices of each tessellated patch. 3 by default.
+    pub patch_control_points: Option<u32>,
+    pub geometry: Option<PathBuf>,
+    /// The mesh shader, and optionally a task shader, drawing meshlets instead of the vertex
+    /// shader where mesh shaders are enabled
+    pub task: Option<PathBuf>,
+    pub mesh: Option<PathBuf>,
+    #[serde(default)]
+    pub defines: BTreeMap<String, String>,
+    pub topology: Option<String>,
+    pub polygon_mode: Option<String>,
+    /// The width in pixels of lines, clamped to the widths supported by the device
+    pub line_width: Option<f32>,
+    /// The size in pixels of points, see `PipelineInfo::point_size`
+    pub point_size: Option<f32>,
+    pub cull_mode: Option<String>,
+    pub front_face: Option<String>,
+    /// One of 'opaque', 'alpha', 'additive' or 'no_color'
+    pub blend: Option<String>,
+    pub depth_compare: Option<String>,
+    pub depth_write: Option<bool>,
+    /// Shades a single fragment for each block of pixels, e.g; [2, 2] for effects with little
+    /// detail, regardless of the shading rate image of the main pass. See
+    /// `PipelineInfo::shading_rate`.
+    pub fragment_size: Option<[u32; 2]>,
+}
+
+/// A material of an effect. See the module documentation for the format.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDefinition {
+    /// The name of the loaded effect
+    pub effect: String,
+    pub albedo: TextureSlot,
+    pub emissive: Option<TextureSlot>,
+    #[serde(default)]
+    pub emissive_factor: [f32; 3],
+    pub lightmap: Option<TextureSlot>,
+    /// Additional textures by the name of their sampler binding in the effect shaders
+    #[serde(default)]
+    pub textures: BTreeMap<String, TextureSlot>,
+    /// Overrides how the albedo texture is sampled
+    pub sampler: Option<SamplerDefinition>,
+    /// The initial contents of the uniform blocks of the effect by binding name. Written as
+    /// tightly packed 32 bit floats, and thus need to match the std140 layout of the block.
+    #[serde(default)]
+    pub parameters: BTreeMap<String, Vec<f32>>,
+}
+
+/// The texture bound to a slot of a material
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum TextureSlot {
+    /// The name of an already loaded texture, e.g; a render target
+    Name(String),
+    /// An image file, loaded as a texture named by its path. Non-color data needs to be loaded
+    /// as linear.
+    File {
+        path: PathBuf,
+        #[serde(default)]
+        linear: bool,
+    },
+}
+
+/// The sampler state of a material. Unspecified state uses the defaults of `SamplerInfo`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SamplerDefinition {
+    pub address_mode: Option<String>,
+    /// Used for both minification and magnification
+    pub filter: Option<String>,
+    pub mipmap_mode: Option<String>,
+    pub anisotropy: Option<f32>,
+    pub mip_lod_bias: Option<f32>,
+}
+
+impl EffectDefinition {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        load_toml(path)
+    }
+
+    /// Returns the pipeline info of each pass. `path` is the file the effect was loaded from,
+    /// which the shader paths are relative to.
+    pub fn pipeline_infos(
+        &self,
+        path: &Path,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Vec<(PassTag, PipelineInfo)>, Error> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let error = |message: String| invalid(path, message);
+
+        self.passes
+            .iter()
+            .map(|pass| {
+                let defaults = PipelineInfo::default();
+
+                let tag = parse_enum(&pass.tag, PASS_TAGS).map_err(error)?;
+
+                let tessellation = match (&pass.tessellation_control, &pass.tessellation_evaluation)
+                {
+                    (Some(control), Some(evaluation)) => Some(TessellationInfo {
+                        control_shader: dir.join(control),
+                        evaluation_shader: dir.join(evaluation),
+                        patch_control_points: pass.patch_control_points.unwrap_or(3),
+                    }),
+                    (None, None) => None,
+                    _ => {
+                        return Err(error(
+                            "Tessellation requires both a control and an evaluation shader"
+                                .to_owned(),
+                        ))
+                    }
+                };
+
+                let mesh_shading = match (&pass.task, &pass.mesh) {
+                    (task, Some(mesh)) => Some(MeshShadingInfo {
+                        task_shader: task.as_ref().map(|task| dir.join(task)),
+                        mesh_shader: dir.join(mesh),
+                    }),
+                    (None, None) => None,
+                    (Some(_), None) => {
+                        return Err(error("A task shader requires a mesh shader".to_owned()))
+                    }
+                };
+
+                let info = PipelineInfo {
+                    vertexshader: dir.join(&pass.vertex),
+                    fragmentshader: dir.join(&pass.fragment),
+                    tessellation,
+                    geometryshader: pass.geometry.as_ref().map(|geometry| dir.join(geometry)),
+                    mesh_shading,
+                    defines: pass
+                        .defines
+                        .iter()
+                        .map(|(name, value)| (name.clone(), value.clone()))
+                        .collect(),
+                    vertex_binding: Vertex::binding_description(),
+                    vertex_attributes: Vertex::attribute_descriptions(),
+                    samples,
+                    topology: parse_optional(&pass.topology, TOPOLOGIES, defaults.topology)
+                        .map_err(error)?,
+                    polygon_mode: parse_optional(
+                        &pass.polygon_mode,
+                        POLYGON_MODES,
+                        defaults.polygon_mode,
+                    )
+                    .map_err(error)?,
+                    line_width: pass.line_width.unwrap_or(defaults.line_width),
+                    point_size: pass.point_size.unwrap_or(defaults.point_size),
+                    cull_mode: parse_optional(&pass.cull_mode, CULL_MODES, defaults.cull_mode)
+                        .map_err(error)?,
+                    front_face: parse_optional(&pass.front_face, FRONT_FACES, defaults.front_face)
+                        .map_err(error)?,
+                    blend: match pass.blend.as_deref() {
+                        None | Some("opaque") => BlendState::opaque(),
+                        Some("alpha") => BlendState::alpha(),
+                        Some("additive") => BlendState::additive(),
+                        Some("no_color") => BlendState::no_color(),
+                        Some(blend) => {
+                            return Err(error(format!("Unknown blend state {:?}", blend)))
+                        }
+                    },
+                    depth_compare: parse_optional(
+                        &pass.depth_compare,
+                        COMPARE_OPS,
+                        defaults.depth_compare,
+                    )
+                    .map_err(error)?,
+                    depth_write: pass.depth_write.unwrap_or(defaults.depth_write),
+                    shading_rate: pass.fragment_size.map(|size| ShadingRateState {
+                        fragment_size: size.into(),
+                        attachment: false,
+                        dynamic: false,
+                    }),
+                    ..defaults
+                };
+
+                Ok((tag, info))
+            })
+            .collect()
+    }
+}
+
+impl MaterialDefinition {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        load_toml(path)
+    }
+
+    /// Returns the texture slots used by the material
+    pub fn textures(&self) -> impl Iterator<Item = &TextureSlot> {
+        std::iter::once(&self.albedo)
+            .chain(self.emissive.as_ref())
+            .chain(self.lightmap.as_ref())
+            .chain(self.textures.values())
+    }
+
+    /// Returns the info for loading the material from the file at `path` with the parameter
+    /// buffers given as pairs of binding and buffer names. The textures need to be loaded by
+    /// the names of their slots.
+    pub fn material_info(
+        &self,
+        path: &Path,
+        buffers: Vec<(String, String)>,
+    ) -> Result<MaterialInfo, Error> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let albedo_sampler = self
+            .sampler
+            .as_ref()
+            .map(|sampler| sampler.sampler_info())
+            .transpose()
+            .map_err(|message| invalid(path, message))?;
+
+        let [r, g, b] = self.emissive_factor;
+
+        Ok(MaterialInfo {
+            effect: self.effect.clone(),
+            albedo: self.albedo.texture_name(dir),
+            albedo_sampler,
+            buffers,
+            emissive: self.emissive.as_ref().map(|slot| slot.texture_name(dir)),
+            emissive_factor: Vec3::new(r, g, b),
+            lightmap: self.lightmap.as_ref().map(|slot| slot.texture_name(dir)),
+            textures: self
+                .textures
+                .iter()
+                .map(|(binding, slot)| (binding.clone(), slot.texture_name(dir)))
+                .collect(),
+        })
+    }
+}
+
+impl TextureSlot {
+    /// Returns the name the texture is loaded as, given the directory of the material file
+    pub fn texture_name(&self, dir: &Path) -> String {
+        match self {
+            TextureSlot::Name(name) => name.clone(),
+            TextureSlot::File { path, .. } => dir.join(path).to_string_lossy().into_owned(),
+        }
+    }
+}
+
+impl SamplerDefinition {
+    fn sampler_info(&self) -> Result<SamplerInfo, String> {
+        let defaults = SamplerInfo::default();
+        let filter = parse_optional(&self.filter, FILTERS, defaults.mag_filter)?;
+
+        Ok(SamplerInfo {
+            address_mode: parse_optional(&self.address_mode, ADDRESS_MODES, defaults.address_mode)?,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_mode: parse_optional(&self.mipmap_mode, MIPMAP_MODES, defaults.mipmap_mode)?,
+            anisotropy: self.anisotropy.or(defaults.anisotropy),
+            mip_lod_bias: self.mip_lod_bias.unwrap_or(defaults.mip_lod_bias),
+            ..defaults
+        })
+    }
+}
+
+const PASS_TAGS: &[(&str, PassTag)] = &[
+    ("shadow", PassTag::Shadow),
+    ("point_shadow", PassTag::PointShadow),
+    ("stereo", PassTag::Stereo),
+    ("opaque", PassTag::Opaque),
+    ("transparent", PassTag::Transparent),
+    ("ui", PassTag::Ui),
+];
+
+const TOPOLOGIES: &[(&str, vk::PrimitiveTopology)] = &[
+    ("point_list", vk::PrimitiveTopology::POINT_LIST),
+    ("line_list", vk::PrimitiveTopology::LINE_LIST),
+    ("line_strip", vk::PrimitiveTopology::LINE_STRIP),
+    ("triangle_list", vk::PrimitiveTopology::TRIANGLE_LIST),
+    ("triangle_strip", vk::PrimitiveTopology::TRIANGLE_STRIP),
+];
+
+const POLYGON_MODES: &[(&str, vk::PolygonMode)] = &[
+    ("fill", vk::PolygonMode::FILL),
+    ("line", vk::PolygonMode::LINE),
+    ("point", vk::PolygonMode::POINT),
+];
+
+const CULL_MODES: &[(&str, vk::CullModeFlags)] = &[
+    ("none", vk::CullModeFlags::NONE),
+    ("front", vk::CullModeFlags::FRONT),
+    ("back", vk::CullModeFlags::BACK),
+    ("front_and_back", vk::CullModeFlags::FRONT_AND_BACK),
+];
+
+const FRONT_FACES: &[(&str, vk::FrontFace)] = &[
+    ("counter_clockwise", vk::FrontFace::COUNTER_CLOCKWISE),
+    ("clockwise", vk::FrontFace::CLOCKWISE),
+];
+
+const COMPARE_OPS: &[(&str, vk::CompareOp)] = &[
+    ("never", vk::CompareOp::NEVER),
+    ("less", vk::CompareOp::LESS),
+    ("equal", vk::CompareOp::EQUAL),
+    ("less_or_equal", vk::CompareOp::LESS_OR_EQUAL),
+    ("greater", vk::CompareOp::GREATER),
+    ("not_equal", vk::CompareOp::NOT_EQUAL),
+    ("greater_or_equal", vk::CompareOp::GREATER_OR_EQUAL),
+    ("always", vk::CompareOp::ALWAYS),
+];
+
+const ADDRESS_MODES: &[(&str, AddressMode)] = &[
+    ("repeat", AddressMode::REPEAT),
+    ("mirrored_repeat", AddressMode::MIRRORED_REPEAT),
+    ("clamp_to_edge", AddressMode::CLAMP_TO_EDGE),
+    ("clamp_to_border", AddressMode::CLAMP_TO_BORDER),
+];
+
+const FILTERS: &[(&str, FilterMode)] = &[
+    ("nearest", FilterMode::NEAREST),
+    ("linear", FilterMode::LINEAR),
+];
+
+const MIPMAP_MODES: &[(&str, MipmapMode)] = &[
+    ("nearest", MipmapMode::NEAREST),
+    ("linear", MipmapMode::LINEAR),
+];
+
+fn parse_enum<T: Copy>(value: &str, options: &[(&str, T)]) -> Result<T, String> {
+    options
+        .iter()
+        .find(|(name, _)| *name == value)
+        .map(|(_, option)| *option)
+        .ok_or_else(|| {
+            let names = options.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            format!("Unknown value {:?}, expected one of {:?}", value, names)
+        })
+}
+
+fn parse_optional<T: Copy>(
+    value: &Option<String>,
+    options: &[(&str, T)],
+    default: T,
+) -> Result<T, String> {
+    match value {
+        Some(value) => parse_enum(value, options),
+        None => Ok(default),
+    }
+}
+
+fn load_toml<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, Error> {
+    let source = fs::read_to_string(path)?;
+    toml::from_str(&source).map_err(|e| invalid(path, e.to_string()))
+}
+
+fn invalid(path: &Path, message: String) -> Error {
+    Error::InvalidDefinition {
+        path: path.to_owned(),
+        message,
+    }
+}
@@ -1,18 +1,129 @@
+use crate::bind_group;
 use crate::vulkan;
-use vulkan::Pipeline;
+use vulkan::pipeline::{Pipeline, PipelineInfo, ShaderBinding};
+use vulkan::{DebugName, VulkanContext};
+
+/// Identifies the pass a pipeline of an effect is used in. Passes are recorded in the order of
+/// declaration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PassTag {
+    /// Depth only rendering from the view of a light
+    Shadow,
+    /// Rendering of the distance to a point light into the faces of a cube map
+    PointShadow,
+    /// Geometry drawn into both eyes of the stereo view at once through multiview, see
+    /// `stereo::StereoRenderer`
+    Stereo,
+    /// Opaque geometry in the main pass
+    Opaque,
+    /// Blended geometry in the main pass, drawn after all opaque geometry
+    Transparent,
+    /// Screen space overlays drawn last
+    Ui,
+}
+
+impl PassTag {
+    /// All pass tags in execution order
+    pub const ALL: &'static [PassTag] = &[
+        PassTag::Shadow,
+        PassTag::PointShadow,
+        PassTag::Stereo,
+        PassTag::Opaque,
+        PassTag::Transparent,
+        PassTag::Ui,
+    ];
+}
 
 /// A material effect is shared among several materials and define the pipelines associated for each
 /// renderpass.
 pub struct MaterialEffect {
-    passes: Vec<Pipeline>,
+    /// Sorted by pass tag. Pipelines of the same pass keep their order of declaration.
+    passes: Vec<(PassTag, Pipeline)>,
 }
 
 impl MaterialEffect {
-    pub fn new(passes: Vec<Pipeline>) -> Self {
+    /// Creates an effect from the pipelines of each pass the effect participates in.
+    /// A pass may be specified more than once for multi-pass techniques, e.g; an outline or
+    /// the shells of fur, in which case the objects are drawn with each pipeline of the pass in
+    /// the order of declaration.
+    pub fn new(passes: Vec<(PassTag, Pipeline)>) -> Self {
+        let mut passes = passes;
+        passes.sort_by_key(|(tag, _)| *tag);
+
         Self { passes }
     }
 
-    pub fn pass(&self, index: usize) -> &Pipeline {
-        &self.passes[index]
+    /// Creates a variant of an effect by compiling the shaders of each pass with `defines` in
+    /// addition to the defines of the pass. Allows several variants, e.g; 'default' and
+    /// 'skinned', to be created from the same shader sources.
+    /// `create_pipeline` creates a pipeline compatible with the pass.
+    pub fn new_variant<F>(
+        passes: &[(PassTag, PipelineInfo)],
+        defines: &[(String, String)],
+        mut create_pipeline: F,
+    ) -> Result<Self, vulkan::Error>
+    where
+        F: FnMut(PassTag, PipelineInfo) -> Result<Pipeline, vulkan::Error>,
+    {
+        let passes = passes
+            .iter()
+            .map(|(tag, info)| {
+                let mut info = info.clone();
+                info.defines.extend_from_slice(defines);
+
+                Ok((*tag, create_pipeline(*tag, info)?))
+            })
+            .collect::<Result<Vec<_>, vulkan::Error>>()?;
+
+        let effect = Self::new(passes);
+        effect.validate()?;
+        Ok(effect)
+    }
+
+    /// Returns an error if the shaders of any pass use a descriptor set which the renderer does
+    /// not bind, see `bind_group`.
+    pub fn validate(&self) -> Result<(), vulkan::Error> {
+        self.passes
+            .iter()
+            .try_for_each(|(_, pipeline)| bind_group::validate(pipeline))
+    }
+
+    /// Returns the first pipeline used in the pass tagged by `tag`, or None if the effect does
+    /// not participate in the pass.
+    pub fn pass(&self, tag: PassTag) -> Option<&Pipeline> {
+        self.pipelines(tag).next()
+    }
+
+    /// Returns all pipelines used in the pass tagged by `tag` in the order they are drawn with.
+    pub fn pipelines(&self, tag: PassTag) -> impl Iterator<Item = &Pipeline> {
+        self.passes
+            .iter()
+            .filter(move |(pass, _)| *pass == tag)
+            .map(|(_, pipeline)| pipeline)
+    }
+
+    /// Returns the descriptor binding named `name` from the first pass that uses it.
+    pub fn binding(&self, name: &str) -> Option<&ShaderBinding> {
+        self.passes
+            .iter()
+            .find_map(|(_, pipeline)| pipeline.binding(name))
+    }
+
+    /// Returns the tags of the passes the effect participates in, in execution order. Tags of
+    /// multi-pass techniques are only returned once.
+    pub fn tags(&self) -> impl Iterator<Item = PassTag> + '_ {
+        let mut previous = None;
+        self.passes
+            .iter()
+            .map(|(tag, _)| *tag)
+            .filter(move |tag| previous.replace(*tag) != Some(*tag))
+    }
+}
+
+impl DebugName for MaterialEffect {
+    fn set_debug_name(&self, context: &VulkanContext, name: &str) {
+        for (tag, pipeline) in &self.passes {
+            pipeline.set_debug_name(context, &format!("{} {:?}", name, tag));
+        }
     }
 }
@@ -1,57 +1,389 @@
 use ash::vk;
 use std::rc::Rc;
+use ultraviolet::{Vec3, Vec4};
 
 use super::MaterialEffect;
 use crate::resources::*;
 use crate::vulkan;
 use vulkan::descriptors::*;
+use vulkan::pipeline::ShaderBinding;
 use vulkan::sampler::*;
 use vulkan::texture::*;
+use vulkan::DebugName;
 use vulkan::Error;
 use vulkan::VulkanContext;
+use vulkan::{Buffer, BufferType, BufferUsage};
 
+/// The descriptor set index of the material set in material effect shaders
+pub const MATERIAL_SET: u32 = 0;
+
+/// The name of the emissive texture binding in the material set of effects supporting emission
+pub const EMISSIVE_MAP_BINDING: &str = "emissiveMap";
+
+/// The name of the uniform block holding the emissive factor. The rgb components contain the
+/// emitted color and w is 1.0 when the emissive texture should be sampled.
+pub const EMISSIVE_DATA_BINDING: &str = "emissive";
+
+/// The name of the lightmap texture binding in the material set of effects using baked lighting
+pub const LIGHTMAP_BINDING: &str = "lightmap";
+
+#[derive(Default)]
 pub struct MaterialInfo {
     pub effect: String,
+    /// The name of the sRGB color texture. Textures loaded as `ColorSpace::Linear` are rejected
+    /// since their colors would be sampled without conversion. Render targets are accepted
+    /// regardless of format since they store the shader output as is.
     pub albedo: String,
+    /// Overrides how the albedo texture is sampled. Uses `SamplerInfo::default()` if None
+    pub albedo_sampler: Option<SamplerInfo>,
+    /// Buffers bound to named uniform or storage blocks of the effect shaders. Given as pairs of
+    /// the shader binding name and the name of the buffer resource.
+    pub buffers: Vec<(String, String)>,
+    /// The name of the sRGB texture the emitted color is multiplied with. Sampled with the albedo
+    /// sampler.
+    pub emissive: Option<String>,
+    /// The emitted color, added after lighting. Values above 1.0 are only retained by floating
+    /// point targets and are clamped when drawn to an sRGB swapchain.
+    pub emissive_factor: Vec3,
+    /// The name of the texture containing the baked lighting, sampled with the second UV channel
+    /// of the mesh. Required by effects declaring a lightmap binding and rejected otherwise.
+    pub lightmap: Option<String>,
+    /// Textures bound to named sampler bindings of the effect shaders, e.g; the layers of a
+    /// splatted terrain. Given as pairs of the shader binding name and the name of the texture.
+    /// Sampled with the albedo sampler.
+    pub textures: Vec<(String, String)>,
+}
+
+/// The resources of a material instance which differ from the material it is instantiated
+/// from, see `ResourceManager::instantiate_material`. Textures are given by name as in
+/// `MaterialInfo`, and everything not overridden is shared with the parent.
+#[derive(Default)]
+pub struct MaterialOverrides {
+    /// The name of the sRGB color texture replacing the albedo of the parent
+    pub albedo: Option<String>,
+    pub albedo_sampler: Option<SamplerInfo>,
+    /// The name of the sRGB texture replacing the emissive texture of the parent
+    pub emissive: Option<String>,
+    pub emissive_factor: Option<Vec3>,
+    /// Textures replacing those of the parent bound to the same shader bindings, or bound in
+    /// addition. Given as pairs of the shader binding name and the name of the texture.
+    pub textures: Vec<(String, String)>,
+}
+
+/// A user provided buffer validated against a uniform or storage block in the material set of
+/// an effect.
+pub struct MaterialBuffer<'a> {
+    binding: &'a ShaderBinding,
+    handle: Handle<Buffer>,
+    buffer: &'a Buffer,
+}
+
+impl<'a> MaterialBuffer<'a> {
+    /// Looks up the binding named `name` in the material set of `effect` and ensures the buffer
+    /// type and size is compatible with the shader block.
+    pub fn new(
+        effect: &'a MaterialEffect,
+        name: &str,
+        handle: Handle<Buffer>,
+        buffer: &'a Buffer,
+    ) -> Result<Self, Error> {
+        let binding = effect
+            .binding(name)
+            .filter(|binding| binding.set == MATERIAL_SET)
+            .ok_or_else(|| Error::MissingBinding(name.to_owned()))?;
+
+        let expected = match buffer.ty() {
+            BufferType::Uniform => vk::DescriptorType::UNIFORM_BUFFER,
+            BufferType::Storage => vk::DescriptorType::STORAGE_BUFFER,
+            _ => vk::DescriptorType::default(),
+        };
+
+        if binding.descriptor_type != expected {
+            return Err(Error::BindingTypeMismatch {
+                name: name.to_owned(),
+                expected: binding.descriptor_type,
+                found: buffer.ty(),
+            });
+        }
+
+        if buffer.size() < binding.size {
+            return Err(Error::BindingTooSmall {
+                name: name.to_owned(),
+                size: buffer.size(),
+                required: binding.size,
+            });
+        }
+
+        Ok(Self {
+            binding,
+            handle,
+            buffer,
+        })
+    }
+}
+
+/// A user provided texture validated against a sampler binding in the material set of an
+/// effect.
+pub struct MaterialTextureBinding<'a> {
+    binding: &'a ShaderBinding,
+    texture: Handle<Texture>,
+}
+
+impl<'a> MaterialTextureBinding<'a> {
+    /// Looks up the binding named `name` in the material set of `effect` and ensures it samples
+    /// a texture.
+    pub fn new(
+        effect: &'a MaterialEffect,
+        name: &str,
+        texture: Handle<Texture>,
+    ) -> Result<Self, Error> {
+        let binding = effect
+            .binding(name)
+            .filter(|binding| binding.set == MATERIAL_SET)
+            .ok_or_else(|| Error::MissingBinding(name.to_owned()))?;
+
+        if binding.descriptor_type != vk::DescriptorType::COMBINED_IMAGE_SAMPLER {
+            return Err(Error::TextureBindingMismatch {
+                name: name.to_owned(),
+                expected: binding.descriptor_type,
+            });
+        }
+
+        Ok(Self { binding, texture })
+    }
+}
+
+/// The emissive texture and color of a material validated against the emissive bindings in the
+/// material set of an effect.
+pub struct MaterialEmissive<'a> {
+    map_binding: &'a ShaderBinding,
+    data_binding: &'a ShaderBinding,
+    texture: Option<Handle<Texture>>,
+    factor: Vec3,
+}
+
+impl<'a> MaterialEmissive<'a> {
+    /// Looks up the emissive bindings in the material set of `effect`. Returns
+    /// `Error::MissingBinding` if the effect does not support emission.
+    pub fn new(
+        effect: &'a MaterialEffect,
+        texture: Option<Handle<Texture>>,
+        factor: Vec3,
+    ) -> Result<Self, Error> {
+        let lookup = |name: &str| {
+            effect
+                .binding(name)
+                .filter(|binding| binding.set == MATERIAL_SET)
+                .ok_or_else(|| Error::MissingBinding(name.to_owned()))
+        };
+
+        let map_binding = lookup(EMISSIVE_MAP_BINDING)?;
+        let data_binding = lookup(EMISSIVE_DATA_BINDING)?;
+
+        if data_binding.descriptor_type != vk::DescriptorType::UNIFORM_BUFFER {
+            return Err(Error::BindingTypeMismatch {
+                name: EMISSIVE_DATA_BINDING.to_owned(),
+                expected: data_binding.descriptor_type,
+                found: BufferType::Uniform,
+            });
+        }
+
+        Ok(Self {
+            map_binding,
+            data_binding,
+            texture,
+            factor,
+        })
+    }
+
+    /// Returns true if `effect` declares the emissive bindings.
+    pub fn supported(effect: &MaterialEffect) -> bool {
+        [EMISSIVE_MAP_BINDING, EMISSIVE_DATA_BINDING]
+            .iter()
+            .all(|name| {
+                effect
+                    .binding(name)
+                    .filter(|binding| binding.set == MATERIAL_SET)
+                    .is_some()
+            })
+    }
+}
+
+/// A lightmap texture validated against the lightmap binding in the material set of an effect
+pub struct MaterialLightmap<'a> {
+    binding: &'a ShaderBinding,
+    texture: Handle<Texture>,
+}
+
+impl<'a> MaterialLightmap<'a> {
+    /// Looks up the lightmap binding in the material set of `effect`. Returns None if neither
+    /// the effect nor the material use a lightmap.
+    pub fn new(
+        effect: &'a MaterialEffect,
+        texture: Option<Handle<Texture>>,
+    ) -> Result<Option<Self>, Error> {
+        let binding = effect
+            .binding(LIGHTMAP_BINDING)
+            .filter(|binding| binding.set == MATERIAL_SET);
+
+        match (binding, texture) {
+            (Some(binding), Some(texture)) => Ok(Some(Self { binding, texture })),
+            (Some(_), None) => Err(Error::MissingTexture(LIGHTMAP_BINDING.to_owned())),
+            (None, Some(_)) => Err(Error::MissingBinding(LIGHTMAP_BINDING.to_owned())),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// The resources bound to the material set of an effect
+pub struct MaterialBindings<'a> {
+    pub albedo: MaterialTexture,
+    /// None if the effect does not declare the emissive bindings
+    pub emissive: Option<MaterialEmissive<'a>>,
+    pub lightmap: Option<MaterialLightmap<'a>>,
+    pub buffers: Vec<MaterialBuffer<'a>>,
+    pub textures: Vec<MaterialTextureBinding<'a>>,
+}
+
+/// A texture along with the options it is sampled with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialTexture {
+    pub texture: Handle<Texture>,
+    pub sampler: SamplerInfo,
 }
 
 pub struct Material {
     effect: Handle<MaterialEffect>,
     albedo: Handle<Texture>,
-    sampler: Sampler,
+    emissive: Option<Handle<Texture>>,
+    emissive_factor: Vec3,
+    /// Kept alive for the descriptor set
+    _emissive_buffer: Option<Buffer>,
+    lightmap: Option<Handle<Texture>>,
+    buffers: Vec<(String, Handle<Buffer>)>,
+    textures: Vec<(String, Handle<Texture>)>,
+    /// Shared with other materials sampling with the same options
+    sampler: Rc<Sampler>,
+    /// Shared with other materials bound to identical resources
     set: DescriptorSet,
     set_layout: DescriptorSetLayout,
 }
 
 impl Material {
-    /// Creates a new material derived from a base material
+    /// Creates a new material derived from a base material. The descriptor set is shared with
+    /// earlier materials bound to the same resources, and needs to be invalidated in
+    /// `descriptor_allocator` when the material is destroyed.
     pub fn new(
         context: Rc<VulkanContext>,
         layout_cache: &mut DescriptorLayoutCache,
         descriptor_allocator: &mut DescriptorAllocator,
+        samplers: &mut SamplerCache,
         textures: &ResourceCache<Texture>,
         effect: Handle<MaterialEffect>,
-        albedo: Handle<Texture>,
+        bindings: MaterialBindings,
     ) -> Result<Self, Error> {
-        let albedo_raw = textures.raw(albedo).unwrap();
-
-        let sampler_info = SamplerInfo {
-            address_mode: AddressMode::REPEAT,
-            mag_filter: FilterMode::LINEAR,
-            min_filter: FilterMode::LINEAR,
-            unnormalized_coordinates: false,
-            anisotropy: 16.0,
-            mip_levels: albedo_raw.mip_levels(),
+        let MaterialBindings {
+            albedo,
+            emissive,
+            lightmap,
+            buffers,
+            textures: texture_bindings,
+        } = bindings;
+
+        let albedo_raw = textures.raw(albedo.texture).unwrap();
+        check_color_space(albedo_raw)?;
+
+        // The albedo texture is bound in place of a missing emissive texture and ignored by the
+        // shader
+        let emissive_raw = match emissive.as_ref().and_then(|emissive| emissive.texture) {
+            Some(texture) => {
+                let raw = textures.raw(texture).unwrap();
+                check_color_space(raw)?;
+                Some(raw)
+            }
+            None => None,
         };
 
-        let sampler = Sampler::new(context.clone(), sampler_info)?;
+        let emissive_buffer = emissive
+            .as_ref()
+            .map(|emissive| {
+                let w = if emissive.texture.is_some() { 1.0 } else { 0.0 };
+                Buffer::new(
+                    context.clone(),
+                    BufferType::Uniform,
+                    BufferUsage::Staged,
+                    &[Vec4::new(
+                        emissive.factor.x,
+                        emissive.factor.y,
+                        emissive.factor.z,
+                        w,
+                    )],
+                )
+            })
+            .transpose()?;
+
+        let sampler = samplers.get(albedo.sampler)?;
 
         let mut set = Default::default();
         let mut set_layout = Default::default();
 
-        DescriptorBuilder::new()
-            .bind_combined_image_sampler(0, vk::ShaderStageFlags::FRAGMENT, &albedo_raw, &sampler)
-            .build(
+        let mut builder = DescriptorBuilder::new();
+        builder.bind_combined_image_sampler(
+            0,
+            vk::ShaderStageFlags::FRAGMENT,
+            &albedo_raw,
+            &sampler,
+        );
+
+        if let (Some(emissive), Some(emissive_buffer)) = (&emissive, &emissive_buffer) {
+            builder.bind_combined_image_sampler(
+                emissive.map_binding.binding,
+                emissive.map_binding.stage_flags,
+                emissive_raw.unwrap_or(albedo_raw),
+                &sampler,
+            );
+
+            builder.bind_uniform_buffer(
+                emissive.data_binding.binding,
+                emissive.data_binding.stage_flags,
+                emissive_buffer,
+            );
+        }
+
+        // Lightmaps store lighting rather than color and are read as is
+        if let Some(lightmap) = &lightmap {
+            builder.bind_combined_image_sampler(
+                lightmap.binding.binding,
+                lightmap.binding.stage_flags,
+                textures.raw(lightmap.texture).unwrap(),
+                &sampler,
+            );
+        }
+
+        for texture in &texture_bindings {
+            builder.bind_combined_image_sampler(
+                texture.binding.binding,
+                texture.binding.stage_flags,
+                textures.raw(texture.texture).unwrap(),
+                &sampler,
+            );
+        }
+
+        for buffer in &buffers {
+            let binding = buffer.binding;
+
+            match buffer.buffer.ty() {
+                BufferType::Uniform => {
+                    builder.bind_uniform_buffer(binding.binding, binding.stage_flags, buffer.buffer)
+                }
+                _ => {
+                    builder.bind_storage_buffer(binding.binding, binding.stage_flags, buffer.buffer)
+                }
+            };
+        }
+
+        builder
+            .build_cached(
                 context.device(),
                 layout_cache,
                 descriptor_allocator,
@@ -59,9 +391,28 @@ impl Material {
             )?
             .layout(layout_cache, &mut set_layout)?;
 
+        let buffers = buffers
+            .iter()
+            .map(|buffer| (buffer.binding.name.clone(), buffer.handle))
+            .collect();
+
+        let textures = texture_bindings
+            .iter()
+            .map(|texture| (texture.binding.name.clone(), texture.texture))
+            .collect();
+
         Ok(Self {
-            albedo,
+            albedo: albedo.texture,
+            emissive: emissive.as_ref().and_then(|emissive| emissive.texture),
+            emissive_factor: emissive
+                .as_ref()
+                .map(|emissive| emissive.factor)
+                .unwrap_or_else(Vec3::zero),
+            _emissive_buffer: emissive_buffer,
+            lightmap: lightmap.map(|lightmap| lightmap.texture),
             effect,
+            buffers,
+            textures,
             sampler,
             set,
             set_layout,
@@ -83,6 +434,32 @@ impl Material {
         self.albedo
     }
 
+    /// Returns the emissive texture of the material, if any.
+    pub fn emissive(&self) -> Option<Handle<Texture>> {
+        self.emissive
+    }
+
+    /// Returns the emitted color of the material. Zero if the effect does not support emission.
+    pub fn emissive_factor(&self) -> Vec3 {
+        self.emissive_factor
+    }
+
+    /// Returns the lightmap texture of the material, if any.
+    pub fn lightmap(&self) -> Option<Handle<Texture>> {
+        self.lightmap
+    }
+
+    /// Returns the buffers bound to the material along with the names of their shader bindings.
+    pub fn buffers(&self) -> &[(String, Handle<Buffer>)] {
+        &self.buffers
+    }
+
+    /// Returns the additional textures of the material along with the names of their shader
+    /// bindings.
+    pub fn textures(&self) -> &[(String, Handle<Texture>)] {
+        &self.textures
+    }
+
     /// Return the material's sampler.
     pub fn sampler(&self) -> &Sampler {
         &self.sampler
@@ -93,3 +470,26 @@ impl Material {
         &self.effect
     }
 }
+
+impl DebugName for Material {
+    fn set_debug_name(&self, context: &VulkanContext, name: &str) {
+        context.set_object_name(self.set, name);
+
+        if let Some(buffer) = &self._emissive_buffer {
+            buffer.set_debug_name(context, &format!("{} emissive", name));
+        }
+    }
+}
+
+/// Rejects sampled textures loaded as linear since their colors would be read without conversion.
+fn check_color_space(texture: &Texture) -> Result<(), Error> {
+    if texture.usage() == TextureUsage::Sampled && texture.color_space() == Some(ColorSpace::Linear)
+    {
+        return Err(Error::ColorSpaceMismatch {
+            expected: ColorSpace::Srgb,
+            found: ColorSpace::Linear,
+        });
+    }
+
+    Ok(())
+}
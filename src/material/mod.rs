@@ -1,5 +1,7 @@
+mod definition;
 mod effect;
 mod material;
 
+pub use definition::*;
 pub use effect::*;
 pub use material::*;
@@ -0,0 +1,345 @@
+//! A compiled binary cache of the processed meshes of a document, stored next to the source
+//! file. Reading the cache skips extracting and optimizing the glTF primitives, which dominates
+//! the load time of large levels.
+//!
+//! The file consists of a header followed by the payload, all little-endian:
+//! - magic `VSMC`, format version, flags, mesh count (u32 each)
+//! - size and modification time in nanoseconds of the source file (u64 each)
+//! - size of the uncompressed payload (u64)
+//! - for each mesh the vertex and index count (u32 each) followed by the vertices as f32
+//!   position, normal, texcoord and texcoord1, and the u32 indices.
+//!
+//! The payload is optionally LZ4 compressed. Caches of another version, flags or source file are
+//! ignored and rewritten.
+use std::convert::TryInto;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use memmap2::Mmap;
+use ultraviolet::{Vec2, Vec3};
+
+use super::{MeshData, Vertex};
+use crate::Error;
+
+/// Incremented whenever the layout of the cache or the mesh processing changes
+pub const MESH_CACHE_VERSION: u32 = 1;
+
+const MAGIC: [u8; 4] = *b"VSMC";
+const HEADER_SIZE: usize = 4 * 4 + 3 * 8;
+const VERTEX_SIZE: usize = 10 * 4;
+
+/// The largest ratio of uncompressed to compressed size LZ4 can achieve
+const MAX_COMPRESSION_RATIO: usize = 255;
+
+const FLAG_COMPRESSED: u32 = 1;
+const FLAG_OPTIMIZED: u32 = 2;
+
+/// Identifies the version of the source file a cache was compiled from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceStamp {
+    pub size: u64,
+    /// Nanoseconds since the unix epoch
+    pub modified: u64,
+}
+
+impl SourceStamp {
+    pub fn of(path: &Path) -> Result<Self, Error> {
+        let metadata = fs::metadata(path)?;
+
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos() as u64);
+
+        Ok(Self {
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/// Returns the path of the cache of `source`, e.g; 'level.gltf.meshcache'
+pub fn cache_path(source: &Path) -> PathBuf {
+    let mut path = OsString::from(source.as_os_str());
+    path.push(".meshcache");
+    path.into()
+}
+
+/// Reads the meshes of the cache at `path` by memory mapping it. Returns None if the cache does
+/// not exist, or was compiled from another version of the source or with other settings.
+pub fn read_mesh_cache(
+    path: &Path,
+    stamp: SourceStamp,
+    optimized: bool,
+) -> Result<Option<Vec<MeshData>>, Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Ok(None),
+    };
+
+    // The cache is only written by `write_mesh_cache`, which replaces rather than modifies it
+    let map = unsafe { Mmap::map(&file)? };
+
+    let mut header = Reader::new(path, &map);
+    if header.bytes(4)? != MAGIC {
+        return Err(invalid(path, "not a mesh cache"));
+    }
+
+    let version = header.u32()?;
+    let flags = header.u32()?;
+    let mesh_count = header.u32()?;
+    let source = SourceStamp {
+        size: header.u64()?,
+        modified: header.u64()?,
+    };
+    let payload_size = header.u64()? as usize;
+
+    if version != MESH_CACHE_VERSION
+        || source != stamp
+        || (flags & FLAG_OPTIMIZED != 0) != optimized
+    {
+        return Ok(None);
+    }
+
+    let payload = &map[HEADER_SIZE..];
+
+    // The size is checked before decompressing to avoid allocating for a corrupt header
+    let decompressed;
+    let payload = if flags & FLAG_COMPRESSED != 0 {
+        if payload_size > payload.len().saturating_mul(MAX_COMPRESSION_RATIO) {
+            return Err(invalid(path, "payload size exceeds the compressed data"));
+        }
+
+        decompressed = lz4_flex::block::decompress(payload, payload_size)
+            .map_err(|_| invalid(path, "corrupt compressed payload"))?;
+        &decompressed[..]
+    } else {
+        payload
+    };
+
+    let mut reader = Reader::new(path, payload);
+
+    let meshes = (0..mesh_count)
+        .map(|_| {
+            let vertex_count = reader.u32()? as usize;
+            let index_count = reader.u32()? as usize;
+
+            let vertices = reader
+                .bytes(vertex_count * VERTEX_SIZE)?
+                .chunks_exact(VERTEX_SIZE)
+                .map(read_vertex)
+                .collect();
+
+            let indices: Vec<u32> = reader
+                .bytes(index_count * 4)?
+                .chunks_exact(4)
+                .map(|index| u32::from_le_bytes(index.try_into().unwrap()))
+                .collect();
+
+            if indices.iter().any(|&index| index as usize >= vertex_count) {
+                return Err(invalid(path, "index out of bounds"));
+            }
+
+            Ok(MeshData::new(vertices, indices))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(Some(meshes))
+}
+
+/// Writes `meshes` processed from the source file identified by `stamp` into the cache at
+/// `path`, replacing any existing cache.
+pub fn write_mesh_cache(
+    path: &Path,
+    stamp: SourceStamp,
+    optimized: bool,
+    compress: bool,
+    meshes: &[MeshData],
+) -> Result<(), Error> {
+    let mut payload = Vec::new();
+
+    for mesh in meshes {
+        payload.extend_from_slice(&(mesh.vertices.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&(mesh.indices.len() as u32).to_le_bytes());
+
+        for vertex in &mesh.vertices {
+            write_vertex(&mut payload, vertex);
+        }
+
+        for index in &mesh.indices {
+            payload.extend_from_slice(&index.to_le_bytes());
+        }
+    }
+
+    let mut flags = 0;
+    if optimized {
+        flags |= FLAG_OPTIMIZED;
+    }
+
+    let payload_size = payload.len() as u64;
+    if compress {
+        flags |= FLAG_COMPRESSED;
+        payload = lz4_flex::block::compress(&payload);
+    }
+
+    let mut data = Vec::with_capacity(HEADER_SIZE + payload.len());
+    data.extend_from_slice(&MAGIC);
+    data.extend_from_slice(&MESH_CACHE_VERSION.to_le_bytes());
+    data.extend_from_slice(&flags.to_le_bytes());
+    data.extend_from_slice(&(meshes.len() as u32).to_le_bytes());
+    data.extend_from_slice(&stamp.size.to_le_bytes());
+    data.extend_from_slice(&stamp.modified.to_le_bytes());
+    data.extend_from_slice(&payload_size.to_le_bytes());
+    data.extend_from_slice(&payload);
+
+    // Written to a temporary file first so that a mapped cache is never modified
+    let temporary = path.with_extension("meshcache.tmp");
+    fs::write(&temporary, data)?;
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+fn read_vertex(bytes: &[u8]) -> Vertex {
+    let mut floats = bytes
+        .chunks_exact(4)
+        .map(|float| f32::from_le_bytes(float.try_into().unwrap()));
+
+    let mut next = || floats.next().unwrap();
+
+    Vertex {
+        position: Vec3::new(next(), next(), next()),
+        normal: Vec3::new(next(), next(), next()),
+        texcoord: Vec2::new(next(), next()),
+        texcoord1: Vec2::new(next(), next()),
+    }
+}
+
+fn write_vertex(data: &mut Vec<u8>, vertex: &Vertex) {
+    let floats = [
+        vertex.position.x,
+        vertex.position.y,
+        vertex.position.z,
+        vertex.normal.x,
+        vertex.normal.y,
+        vertex.normal.z,
+        vertex.texcoord.x,
+        vertex.texcoord.y,
+        vertex.texcoord1.x,
+        vertex.texcoord1.y,
+    ];
+
+    for float in &floats {
+        data.extend_from_slice(&float.to_le_bytes());
+    }
+}
+
+fn invalid(path: &Path, reason: &'static str) -> Error {
+    Error::InvalidMeshCache {
+        path: path.to_owned(),
+        reason,
+    }
+}
+
+/// Reads little-endian values from the front of a byte slice
+struct Reader<'a> {
+    path: &'a Path,
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(path: &'a Path, data: &'a [u8]) -> Self {
+        Self { path, data }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err(invalid(self.path, "unexpected end of file"));
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh() -> MeshData {
+        let vertex = |x| Vertex::new(Vec3::new(x, 0.0, 0.0), Vec3::unit_y(), Vec2::zero());
+        MeshData::new(vec![vertex(0.0), vertex(1.0), vertex(2.0)], vec![0, 1, 2])
+    }
+
+    fn stamp() -> SourceStamp {
+        SourceStamp {
+            size: 1,
+            modified: 2,
+        }
+    }
+
+    /// Writes a cache of `mesh` to a unique temporary path and applies `corrupt` to its bytes
+    fn write(
+        name: &str,
+        compress: bool,
+        mesh: &MeshData,
+        corrupt: impl FnOnce(&mut Vec<u8>),
+    ) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "vulkan-sandbox-{}-{}.meshcache",
+            name,
+            std::process::id()
+        ));
+
+        write_mesh_cache(&path, stamp(), false, compress, std::slice::from_ref(mesh)).unwrap();
+
+        let mut data = fs::read(&path).unwrap();
+        corrupt(&mut data);
+        fs::write(&path, data).unwrap();
+
+        path
+    }
+
+    fn read(path: &Path) -> Result<Option<Vec<MeshData>>, Error> {
+        let result = read_mesh_cache(path, stamp(), false);
+        fs::remove_file(path).unwrap();
+        result
+    }
+
+    #[test]
+    fn roundtrip() {
+        for &compress in &[false, true] {
+            let path = write("roundtrip", compress, &mesh(), |_| {});
+            assert_eq!(read(&path).unwrap(), Some(vec![mesh()]));
+        }
+    }
+
+    #[test]
+    fn huge_payload_size() {
+        let path = write("huge-payload", true, &mesh(), |data| {
+            data[HEADER_SIZE - 8..HEADER_SIZE].copy_from_slice(&u64::MAX.to_le_bytes());
+        });
+
+        assert!(read(&path).is_err());
+    }
+
+    #[test]
+    fn index_out_of_bounds() {
+        let mut mesh = mesh();
+        mesh.indices[2] = 3;
+
+        let path = write("index-bounds", false, &mesh, |_| {});
+        assert!(read(&path).is_err());
+    }
+}
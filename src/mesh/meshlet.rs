@@ -0,0 +1,258 @@
+//! Meshlets divide the triangles of a mesh into small clusters for the experimental mesh shader
+//! path. Each mesh shader workgroup outputs one meshlet, which references at most
+//! `MeshletLimits::max_vertices` vertices of the mesh through a local vertex list and has at
+//! most `MeshletLimits::max_primitives` triangles indexing into that list.
+//!
+//! The meshlets are greedily built in the order of the indices, so meshes optimized for the
+//! vertex cache give meshlets with fewer shared vertices.
+use std::rc::Rc;
+
+use ash::vk;
+
+use super::MeshData;
+use crate::vulkan::descriptors::{
+    DescriptorAllocator, DescriptorBuilder, DescriptorLayoutCache, DescriptorSet,
+};
+use crate::vulkan::mesh_shader::MESH_STAGE;
+use crate::vulkan::{self, Buffer, BufferType, BufferUsage, DeviceCapabilities, VulkanContext};
+
+/// The descriptor set index of the meshlets of the drawn mesh in mesh shading pipelines. Holds
+/// the vertices as tightly packed floats at binding 0, the meshlets at binding 1, the mesh
+/// vertex of each meshlet vertex at binding 2 and the meshlet vertex of each triangle corner
+/// packed as bytes at binding 3.
+pub const MESHLET_SET: u32 = 7;
+
+/// Meshlets reference their vertices by 8 bit indices
+const MAX_MESHLET_VERTICES: u32 = 256;
+
+/// The maximum size of each meshlet, which needs to fit the output of the mesh shader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshletLimits {
+    pub max_vertices: u32,
+    pub max_primitives: u32,
+}
+
+impl Default for MeshletLimits {
+    fn default() -> Self {
+        Self {
+            max_vertices: 64,
+            max_primitives: 124,
+        }
+    }
+}
+
+impl MeshletLimits {
+    /// Returns the limits reduced to the mesh shader output supported by the device
+    pub fn supported(self, capabilities: &DeviceCapabilities) -> Self {
+        Self {
+            max_vertices: self.max_vertices.min(capabilities.max_mesh_output_vertices),
+            max_primitives: self
+                .max_primitives
+                .min(capabilities.max_mesh_output_primitives),
+        }
+    }
+}
+
+/// A cluster of triangles, matching the `uvec4` of each meshlet in the meshlet buffer
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Meshlet {
+    /// The first entry of the meshlet in `MeshletData::vertices`
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    /// The first triangle of the meshlet in `MeshletData::primitives`
+    pub primitive_offset: u32,
+    pub primitive_count: u32,
+}
+
+/// The meshlets of a mesh on the CPU
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MeshletData {
+    pub meshlets: Vec<Meshlet>,
+    /// The index of the mesh vertex of each meshlet vertex
+    pub vertices: Vec<u32>,
+    /// Three meshlet vertices per triangle, relative to the meshlet's `vertex_offset`
+    pub primitives: Vec<u8>,
+}
+
+impl MeshletData {
+    /// Divides the triangles of `indices` into meshlets within `limits`. Limits below a
+    /// single triangle are raised to fit one.
+    pub fn build(indices: &[u32], limits: MeshletLimits) -> Self {
+        let max_vertices = limits.max_vertices.clamp(3, MAX_MESHLET_VERTICES) as usize;
+        let max_primitives = limits.max_primitives.max(1) as usize;
+
+        let vertex_count = indices.iter().max().map_or(0, |max| *max as usize + 1);
+
+        // The local index of each mesh vertex in the current meshlet
+        let mut local = vec![None; vertex_count];
+
+        let mut data = Self::default();
+        let mut current = Meshlet::default();
+
+        for triangle in indices.chunks_exact(3) {
+            let new_vertices = triangle
+                .iter()
+                .enumerate()
+                .filter(|(i, index)| {
+                    local[**index as usize].is_none() && !triangle[..*i].contains(*index)
+                })
+                .count();
+
+            if current.vertex_count as usize + new_vertices > max_vertices
+                || current.primitive_count as usize + 1 > max_primitives
+            {
+                data.finish(&mut current, &mut local);
+            }
+
+            for index in triangle {
+                let vertex = match local[*index as usize] {
+                    Some(vertex) => vertex,
+                    None => {
+                        let vertex = current.vertex_count as u8;
+                        local[*index as usize] = Some(vertex);
+                        data.vertices.push(*index);
+                        current.vertex_count += 1;
+                        vertex
+                    }
+                };
+
+                data.primitives.push(vertex);
+            }
+
+            current.primitive_count += 1;
+        }
+
+        data.finish(&mut current, &mut local);
+        data
+    }
+
+    /// Returns the triangle corners packed four to a word, as read by the mesh shader
+    pub fn packed_primitives(&self) -> Vec<u32> {
+        self.primitives
+            .chunks(4)
+            .map(|bytes| {
+                bytes
+                    .iter()
+                    .enumerate()
+                    .fold(0, |word, (i, byte)| word | (*byte as u32) << (i * 8))
+            })
+            .collect()
+    }
+
+    // Pushes the current meshlet if it has any triangles and starts the next one
+    fn finish(&mut self, current: &mut Meshlet, local: &mut [Option<u8>]) {
+        if current.primitive_count == 0 {
+            return;
+        }
+
+        let start = current.vertex_offset as usize;
+        for index in &self.vertices[start..] {
+            local[*index as usize] = None;
+        }
+
+        self.meshlets.push(*current);
+
+        *current = Meshlet {
+            vertex_offset: self.vertices.len() as u32,
+            vertex_count: 0,
+            primitive_offset: self.primitives.len() as u32 / 3,
+            primitive_count: 0,
+        };
+    }
+}
+
+/// The meshlets of a mesh uploaded for mesh shading pipelines, along with the descriptor set
+/// bound at `MESHLET_SET`.
+pub struct Meshlets {
+    set: DescriptorSet,
+    meshlet_count: u32,
+    // The set is allocated from a layout and pool of its own, which are destroyed with the mesh
+    _allocator: DescriptorAllocator,
+    _layout_cache: DescriptorLayoutCache,
+    _vertices: Buffer,
+    _meshlets: Buffer,
+    _meshlet_vertices: Buffer,
+    _primitives: Buffer,
+}
+
+impl Meshlets {
+    /// Builds and uploads the meshlets of `data`. Returns None if the mesh has no triangles.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        data: &MeshData,
+        limits: MeshletLimits,
+    ) -> Result<Option<Self>, vulkan::Error> {
+        let meshlets = MeshletData::build(&data.indices, limits);
+
+        if meshlets.meshlets.is_empty() {
+            return Ok(None);
+        }
+
+        let vertices = Buffer::new(
+            context.clone(),
+            BufferType::Storage,
+            BufferUsage::Staged,
+            &data.vertices,
+        )?;
+
+        let meshlet_buffer = Buffer::new(
+            context.clone(),
+            BufferType::Storage,
+            BufferUsage::Staged,
+            &meshlets.meshlets,
+        )?;
+
+        let meshlet_vertices = Buffer::new(
+            context.clone(),
+            BufferType::Storage,
+            BufferUsage::Staged,
+            &meshlets.vertices,
+        )?;
+
+        let primitives = Buffer::new(
+            context.clone(),
+            BufferType::Storage,
+            BufferUsage::Staged,
+            &meshlets.packed_primitives(),
+        )?;
+
+        let mut allocator = DescriptorAllocator::new(context.device_ref(), 1);
+        let mut layout_cache = DescriptorLayoutCache::new(context.device_ref());
+        let mut set = vk::DescriptorSet::null();
+
+        DescriptorBuilder::new()
+            .bind_storage_buffer(0, MESH_STAGE, &vertices)
+            .bind_storage_buffer(1, MESH_STAGE, &meshlet_buffer)
+            .bind_storage_buffer(2, MESH_STAGE, &meshlet_vertices)
+            .bind_storage_buffer(3, MESH_STAGE, &primitives)
+            .build(
+                context.device(),
+                &mut layout_cache,
+                &mut allocator,
+                &mut set,
+            )?;
+
+        Ok(Some(Self {
+            set,
+            meshlet_count: meshlets.meshlets.len() as u32,
+            _allocator: allocator,
+            _layout_cache: layout_cache,
+            _vertices: vertices,
+            _meshlets: meshlet_buffer,
+            _meshlet_vertices: meshlet_vertices,
+            _primitives: primitives,
+        }))
+    }
+
+    /// Returns the descriptor set to bind at `MESHLET_SET`
+    pub fn set(&self) -> DescriptorSet {
+        self.set
+    }
+
+    /// Returns the number of meshlets, which is the number of mesh shader workgroups drawing
+    /// the mesh once
+    pub fn meshlet_count(&self) -> u32 {
+        self.meshlet_count
+    }
+}
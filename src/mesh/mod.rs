@@ -0,0 +1,571 @@
+use gltf::{buffer, Semantic};
+use std::iter::repeat;
+use std::path::Path;
+use std::rc::Rc;
+use ultraviolet::{Mat4, Vec2, Vec3};
+
+use crate::aabb::Aabb;
+use crate::vulkan::ray_tracing::AccelerationStructure;
+use crate::vulkan::{self, VulkanContext};
+use crate::Error;
+use vulkan::{Buffer, BufferType, BufferUsage, DebugName};
+
+mod cache;
+mod meshlet;
+mod obj;
+mod optimize;
+mod ply;
+mod simplify;
+
+pub use cache::*;
+pub use meshlet::*;
+pub use obj::*;
+pub use optimize::{optimize_vertex_cache, optimize_vertex_fetch};
+pub use ply::*;
+pub use simplify::simplify;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vertex {
+    position: Vec3,
+    normal: Vec3,
+    texcoord: Vec2,
+    /// The second UV channel, used to sample lightmaps
+    texcoord1: Vec2,
+}
+
+impl Vertex {
+    /// Creates a vertex which uses `texcoord` for both UV channels
+    pub fn new(position: Vec3, normal: Vec3, texcoord: Vec2) -> Self {
+        Self {
+            position,
+            normal,
+            texcoord,
+            texcoord1: texcoord,
+        }
+    }
+
+    /// Replaces the second UV channel
+    pub fn with_texcoord1(self, texcoord1: Vec2) -> Self {
+        Self { texcoord1, ..self }
+    }
+}
+
+// Locations 3 and 4 are used by the joints and weights of skinned shader variants
+crate::vertex_desc!(Vertex {
+    0 => position: Vec3,
+    1 => normal: Vec3,
+    2 => texcoord: Vec2,
+    5 => texcoord1: Vec2,
+});
+
+/// Specifies how meshes are processed when imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshImportSettings {
+    /// Reorder indices and vertices for vertex cache efficiency and fetch locality
+    pub optimize: bool,
+    /// Keep a CPU copy of the vertices and indices for triangle accurate raycasts and static
+    /// batching
+    pub keep_geometry: bool,
+    /// Store the processed meshes of documents in a compiled cache next to the source file,
+    /// which is read instead of the glTF primitives while the source is unchanged
+    pub cache: bool,
+    /// LZ4 compress the cache, which trades load time for disk space
+    pub compress_cache: bool,
+    /// Build meshlets within the limits for mesh shading pipelines. Ignored unless
+    /// `DeviceFeatures::mesh_shader` is enabled.
+    pub meshlets: Option<MeshletLimits>,
+    /// Build a bottom level acceleration structure of the triangles for ray queries. Ignored
+    /// unless `DeviceFeatures::ray_query` is enabled.
+    pub acceleration_structure: bool,
+}
+
+impl Default for MeshImportSettings {
+    fn default() -> Self {
+        Self {
+            optimize: true,
+            keep_geometry: false,
+            cache: false,
+            compress_cache: false,
+            meshlets: None,
+            acceleration_structure: true,
+        }
+    }
+}
+
+/// CPU side mesh geometry which can be processed before being uploaded as a `Mesh`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
+        Self { vertices, indices }
+    }
+
+    /// Creates mesh data from an structure-of-arrays vertex data
+    /// Each index refers to the direct index of positions, normals and texcoords
+    pub fn from_soa(
+        positions: &[Vec3],
+        normals: &[Vec3],
+        texcoords: &[Vec2],
+        indices: &[u32],
+    ) -> Self {
+        let mut vertices = Vec::with_capacity(positions.len());
+
+        for i in 0..positions.len() {
+            vertices.push(Vertex::new(positions[i], normals[i], texcoords[i]));
+        }
+
+        Self::new(vertices, indices.to_vec())
+    }
+
+    /// Loads the first primitive of a gltf mesh. The second UV channel is loaded from
+    /// TEXCOORD_1, and falls back to TEXCOORD_0 if not present.
+    pub fn from_gltf(mesh: gltf::Mesh, buffers: &[buffer::Data]) -> Result<Self, Error> {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut texcoords = Vec::new();
+        let mut texcoords1 = Vec::new();
+        let mut raw_indices = Vec::new();
+
+        if let Some(primitive) = mesh.primitives().next() {
+            let indices_accessor = primitive.indices().ok_or(Error::SparseAccessor)?;
+            let indices_view = indices_accessor.view().ok_or(Error::SparseAccessor)?;
+
+            raw_indices = match indices_accessor.size() {
+                2 => load_u16_as_u32(&indices_view, buffers),
+                4 => load_u32(&indices_view, buffers),
+                _ => unreachable!(),
+            };
+
+            for (semantic, accessor) in primitive.attributes() {
+                let view = accessor.view().ok_or(Error::SparseAccessor)?;
+                match semantic {
+                    Semantic::Positions => positions = load_vec3(&view, buffers),
+                    Semantic::Normals => normals = load_vec3(&view, buffers),
+                    Semantic::TexCoords(0) => texcoords = load_vec2(&view, buffers),
+                    Semantic::TexCoords(1) => texcoords1 = load_vec2(&view, buffers),
+                    Semantic::TexCoords(_) => {}
+                    Semantic::Tangents => {}
+                    Semantic::Colors(_) => {}
+                    Semantic::Joints(_) => {}
+                    Semantic::Weights(_) => {}
+                };
+            }
+        }
+
+        // Pad incase these weren't included in geometry
+        pad_vec(&mut normals, Vec3::unit_z(), positions.len());
+        pad_vec(&mut texcoords, Vec2::zero(), positions.len());
+
+        let mut data = Self::from_soa(&positions, &normals, &texcoords, &raw_indices);
+
+        if !texcoords1.is_empty() {
+            pad_vec(&mut texcoords1, Vec2::zero(), positions.len());
+
+            for (vertex, texcoord1) in data.vertices.iter_mut().zip(texcoords1) {
+                *vertex = vertex.with_texcoord1(texcoord1);
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Loads the meshes of a gltf document processed according to `settings`. The meshes are
+    /// read from the compiled cache next to `path` if enabled and up to date, and the cache is
+    /// otherwise rewritten. Failing to use the cache is logged and falls back to the document.
+    pub fn from_gltf_document(
+        path: &Path,
+        document: &gltf::Document,
+        buffers: &[buffer::Data],
+        settings: &MeshImportSettings,
+    ) -> Result<Vec<Self>, Error> {
+        let cache = if settings.cache {
+            Some((cache_path(path), SourceStamp::of(path)?))
+        } else {
+            None
+        };
+
+        if let Some((cache, stamp)) = &cache {
+            match read_mesh_cache(cache, *stamp, settings.optimize) {
+                Ok(Some(meshes)) if meshes.len() == document.meshes().len() => return Ok(meshes),
+                Ok(_) => log::debug!("Compiling mesh cache {:?}", cache),
+                Err(e) => log::warn!("Ignoring mesh cache: {}", e),
+            }
+        }
+
+        let meshes = document
+            .meshes()
+            .map(|mesh| {
+                let mut data = Self::from_gltf(mesh, buffers)?;
+                if settings.optimize {
+                    data.optimize();
+                }
+
+                Ok(data)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if let Some((cache, stamp)) = &cache {
+            let result = write_mesh_cache(
+                cache,
+                *stamp,
+                settings.optimize,
+                settings.compress_cache,
+                &meshes,
+            );
+
+            if let Err(e) = result {
+                log::warn!("Failed to write mesh cache {:?}: {}", cache, e);
+            }
+        }
+
+        Ok(meshes)
+    }
+
+    /// Reorders the indices for post-transform vertex cache efficiency followed by the vertices
+    /// for fetch locality. The rendered result is unchanged.
+    pub fn optimize(&mut self) {
+        self.indices = optimize_vertex_cache(&self.indices, self.vertices.len());
+        optimize_vertex_fetch(self);
+    }
+
+    /// Returns a copy with the triangle count reduced to approximately `target_ratio` of the
+    /// original. The vertices are kept as is.
+    pub fn simplified(&self, target_ratio: f32) -> Self {
+        Self::new(self.vertices.clone(), simplify(self, target_ratio))
+    }
+
+    /// Transforms the positions and normals by `matrix`, e.g; to bake the model matrix of an
+    /// object into its geometry.
+    pub fn transform(&mut self, matrix: &Mat4) {
+        let normal_matrix = matrix.truncate().inversed().transposed();
+
+        for vertex in &mut self.vertices {
+            vertex.position = matrix.transform_point3(vertex.position);
+            vertex.normal = (normal_matrix * vertex.normal).normalized();
+        }
+    }
+
+    /// Appends the vertices and triangles of `other`.
+    pub fn append(&mut self, other: &MeshData) {
+        let offset = self.vertices.len() as u32;
+
+        self.vertices.extend_from_slice(&other.vertices);
+        self.indices
+            .extend(other.indices.iter().map(|index| index + offset));
+    }
+}
+
+/// The model space triangles of a mesh kept on the CPU
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshGeometry {
+    vertices: Vec<Vertex>,
+    positions: Vec<Vec3>,
+    indices: Vec<u32>,
+}
+
+impl MeshGeometry {
+    pub fn from_data(data: &MeshData) -> Self {
+        Self {
+            vertices: data.vertices.clone(),
+            positions: data.vertices.iter().map(|vertex| vertex.position).collect(),
+            indices: data.indices.clone(),
+        }
+    }
+
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    /// Returns the indices into positions, three per triangle
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    /// Returns a copy of the vertices and indices for processing, e.g; static batching
+    pub fn to_data(&self) -> MeshData {
+        MeshData::new(self.vertices.clone(), self.indices.clone())
+    }
+}
+
+pub struct Mesh {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    vertex_count: u32,
+    index_count: u32,
+    bounding_radius: f32,
+    aabb: Aabb,
+    geometry: Option<MeshGeometry>,
+    meshlets: Option<Meshlets>,
+    acceleration_structure: Option<AccelerationStructure>,
+}
+
+impl Mesh {
+    pub fn new(
+        context: Rc<VulkanContext>,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> Result<Self, Error> {
+        let vertex_buffer = Buffer::new(
+            context.clone(),
+            BufferType::Vertex,
+            BufferUsage::Staged,
+            vertices,
+        )?;
+
+        let index_buffer = Buffer::new(context, BufferType::Index32, BufferUsage::Staged, indices)?;
+
+        let bounding_radius = vertices
+            .iter()
+            .map(|vertex| vertex.position.mag())
+            .fold(0.0, f32::max);
+
+        let aabb = Aabb::from_points(vertices.iter().map(|vertex| vertex.position))
+            .unwrap_or_else(|| Aabb::new(Vec3::zero(), Vec3::zero()));
+
+        Ok(Self {
+            vertex_buffer,
+            index_buffer,
+            vertex_count: vertices.len() as u32,
+            index_count: indices.len() as u32,
+            bounding_radius,
+            aabb,
+            geometry: None,
+            meshlets: None,
+            acceleration_structure: None,
+        })
+    }
+
+    /// Creates a mesh from an structure-of-arrays vertex data
+    /// Each index refers to the direct index of positions, normals and texcoords
+    pub fn from_soa(
+        context: Rc<VulkanContext>,
+        positions: &[Vec3],
+        normals: &[Vec3],
+        texcoords: &[Vec2],
+        indices: &[u32],
+    ) -> Result<Self, Error> {
+        Self::from_data(
+            context,
+            &MeshData::from_soa(positions, normals, texcoords, indices),
+        )
+    }
+
+    /// Uploads mesh data
+    pub fn from_data(context: Rc<VulkanContext>, data: &MeshData) -> Result<Self, Error> {
+        Self::new(context, &data.vertices, &data.indices)
+    }
+
+    /// Processes and uploads mesh data according to `settings`
+    pub fn from_data_with_settings(
+        context: Rc<VulkanContext>,
+        mut data: MeshData,
+        settings: &MeshImportSettings,
+    ) -> Result<Self, Error> {
+        if settings.optimize {
+            data.optimize();
+        }
+
+        let mut mesh = Self::from_data(context.clone(), &data)?;
+
+        if settings.keep_geometry {
+            mesh.geometry = Some(MeshGeometry::from_data(&data));
+        }
+
+        if let Some(limits) = settings.meshlets {
+            let capabilities = context.capabilities();
+
+            if capabilities.features.mesh_shader {
+                mesh.meshlets =
+                    Meshlets::new(context.clone(), &data, limits.supported(capabilities))?;
+            }
+        }
+
+        if settings.acceleration_structure
+            && context.capabilities().features.ray_query
+            && !data.indices.is_empty()
+        {
+            mesh.acceleration_structure = Some(AccelerationStructure::bottom_level(
+                context.clone(),
+                &data.vertices,
+                &data.indices,
+            )?);
+        }
+
+        Ok(mesh)
+    }
+
+    pub fn from_gltf(
+        context: Rc<VulkanContext>,
+        mesh: gltf::Mesh,
+        buffers: &[buffer::Data],
+    ) -> Result<Self, Error> {
+        Self::from_gltf_with_settings(context, mesh, buffers, &MeshImportSettings::default())
+    }
+
+    /// Loads a gltf mesh and processes it according to `settings`
+    pub fn from_gltf_with_settings(
+        context: Rc<VulkanContext>,
+        mesh: gltf::Mesh,
+        buffers: &[buffer::Data],
+        settings: &MeshImportSettings,
+    ) -> Result<Self, Error> {
+        let data = MeshData::from_gltf(mesh, buffers)?;
+        Self::from_data_with_settings(context, data, settings)
+    }
+
+    // Returns the internal vertex buffer
+    pub fn vertex_buffer(&self) -> &Buffer {
+        &self.vertex_buffer
+    }
+
+    // Returns the internal index buffer
+    pub fn index_buffer(&self) -> &Buffer {
+        &self.index_buffer
+    }
+
+    // Returns the number of vertices
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    // Returns the number of indices
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    /// Returns the radius of the bounding sphere centered at the origin in model space
+    pub fn bounding_radius(&self) -> f32 {
+        self.bounding_radius
+    }
+
+    /// Returns the bounding box in model space
+    pub fn aabb(&self) -> Aabb {
+        self.aabb
+    }
+
+    /// Returns the CPU copy of the triangles if the mesh was imported with
+    /// `MeshImportSettings::keep_geometry`
+    pub fn geometry(&self) -> Option<&MeshGeometry> {
+        self.geometry.as_ref()
+    }
+
+    /// Returns the meshlets drawn by mesh shading pipelines if the mesh was imported with
+    /// `MeshImportSettings::meshlets` and mesh shaders are enabled
+    pub fn meshlets(&self) -> Option<&Meshlets> {
+        self.meshlets.as_ref()
+    }
+
+    /// Returns the bottom level acceleration structure traced by ray queries if the mesh was
+    /// imported with `MeshImportSettings::acceleration_structure` and ray queries are enabled
+    pub fn acceleration_structure(&self) -> Option<&AccelerationStructure> {
+        self.acceleration_structure.as_ref()
+    }
+}
+
+impl DebugName for Mesh {
+    fn set_debug_name(&self, context: &VulkanContext, name: &str) {
+        self.vertex_buffer
+            .set_debug_name(context, &format!("{} vertices", name));
+        self.index_buffer
+            .set_debug_name(context, &format!("{} indices", name));
+    }
+}
+
+// Pads a vector with copies of val to ensure it is atleast `len` elements
+// Accumulates the area weighted face normals into the vertices marked as missing a normal
+fn generate_normals(vertices: &mut [Vertex], indices: &[u32], missing: &[bool]) {
+    if !missing.iter().any(|missing| *missing) {
+        return;
+    }
+
+    for triangle in indices.chunks_exact(3) {
+        let (a, b, c) = (
+            triangle[0] as usize,
+            triangle[1] as usize,
+            triangle[2] as usize,
+        );
+        let normal = (vertices[b].position - vertices[a].position)
+            .cross(vertices[c].position - vertices[a].position);
+
+        for &i in &[a, b, c] {
+            if missing[i] {
+                vertices[i].normal += normal;
+            }
+        }
+    }
+
+    for (vertex, _) in vertices
+        .iter_mut()
+        .zip(missing)
+        .filter(|(_, missing)| **missing)
+    {
+        if vertex.normal.mag_sq() > 0.0 {
+            vertex.normal.normalize();
+        } else {
+            vertex.normal = Vec3::unit_z();
+        }
+    }
+}
+
+fn pad_vec<T: Copy>(vec: &mut Vec<T>, val: T, len: usize) {
+    vec.extend(repeat(val).take(len - vec.len()))
+}
+
+fn load_u16_as_u32(view: &buffer::View, buffers: &[buffer::Data]) -> Vec<u32> {
+    let buffer = &buffers[view.buffer().index()];
+
+    let raw_data = &buffer[view.offset()..view.offset() + view.length()];
+    raw_data
+        .chunks_exact(2)
+        .map(|val| u16::from_le_bytes([val[0], val[1]]) as u32)
+        .collect()
+}
+
+fn load_u32(view: &buffer::View, buffers: &[buffer::Data]) -> Vec<u32> {
+    let buffer = &buffers[view.buffer().index()];
+
+    let raw_data = &buffer[view.offset()..view.offset() + view.length()];
+    raw_data
+        .chunks_exact(4)
+        .map(|val| u32::from_le_bytes([val[0], val[1], val[2], val[3]]))
+        .collect()
+}
+
+fn load_vec2(view: &buffer::View, buffers: &[buffer::Data]) -> Vec<Vec2> {
+    let buffer = &buffers[view.buffer().index()];
+
+    let raw_data = &buffer[view.offset()..view.offset() + view.length()];
+    raw_data
+        .chunks_exact(8)
+        .map(|val| {
+            Vec2::new(
+                f32::from_le_bytes([val[0], val[1], val[2], val[3]]),
+                f32::from_le_bytes([val[4], val[5], val[6], val[7]]),
+            )
+        })
+        .collect()
+}
+
+fn load_vec3(view: &buffer::View, buffers: &[buffer::Data]) -> Vec<Vec3> {
+    let buffer = &buffers[view.buffer().index()];
+
+    let raw_data = &buffer[view.offset()..view.offset() + view.length()];
+    raw_data
+        .chunks_exact(12)
+        .map(|val| {
+            Vec3::new(
+                f32::from_le_bytes([val[0], val[1], val[2], val[3]]),
+                f32::from_le_bytes([val[4], val[5], val[6], val[7]]),
+                f32::from_le_bytes([val[8], val[9], val[10], val[11]]),
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ultraviolet::{Vec2, Vec3};
+
+use super::{generate_normals, MeshData, Vertex};
+use crate::Error;
+
+/// A material of an MTL library. Texture paths are relative to the working directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjMaterial {
+    pub name: String,
+    /// The diffuse texture, map_Kd
+    pub albedo: Option<PathBuf>,
+    /// The emissive texture, map_Ke
+    pub emissive: Option<PathBuf>,
+    /// The emitted color, Ke
+    pub emissive_factor: Vec3,
+}
+
+/// The triangles of an object or group of an OBJ file drawn with the same material
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjObject {
+    /// The name of the object or group. Objects using several materials are split into one
+    /// object per material with the same name.
+    pub name: String,
+    /// The name of the material in `ObjModel::materials`
+    pub material: Option<String>,
+    pub data: MeshData,
+}
+
+/// A Wavefront OBJ file along with the materials of its MTL libraries
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjModel {
+    pub objects: Vec<ObjObject>,
+    pub materials: Vec<ObjMaterial>,
+}
+
+/// An OBJ face vertex given by the position, texcoord and normal indices
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+/// The object currently being parsed
+struct Group {
+    name: String,
+    material: Option<String>,
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    /// Vertices without a normal, which are computed from the faces
+    missing_normals: Vec<bool>,
+    lookup: HashMap<FaceVertex, u32>,
+}
+
+impl Group {
+    fn new(name: String, material: Option<String>) -> Self {
+        Self {
+            name,
+            material,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            missing_normals: Vec::new(),
+            lookup: HashMap::new(),
+        }
+    }
+
+    fn finish(mut self) -> Option<ObjObject> {
+        if self.indices.is_empty() {
+            return None;
+        }
+
+        generate_normals(&mut self.vertices, &self.indices, &self.missing_normals);
+
+        Some(ObjObject {
+            name: self.name,
+            material: self.material,
+            data: MeshData::new(self.vertices, self.indices),
+        })
+    }
+}
+
+impl ObjModel {
+    /// Loads an OBJ file along with the MTL libraries it references. Polygons are triangulated
+    /// as fans, and missing normals are generated from the faces.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let source = fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut positions: Vec<Vec3> = Vec::new();
+        let mut texcoords: Vec<Vec2> = Vec::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+        let mut materials = Vec::new();
+        let mut objects = Vec::new();
+
+        let mut group = Group::new("default".to_owned(), None);
+
+        for (line_index, line) in source.lines().enumerate() {
+            let error = |message: &str| parse_error(path, line_index + 1, message);
+
+            let mut words = line.split_whitespace();
+            let keyword = match words.next() {
+                Some(keyword) if !keyword.starts_with('#') => keyword,
+                _ => continue,
+            };
+
+            match keyword {
+                "v" => positions.push(parse_vec3(&mut words).ok_or_else(|| error("bad vertex"))?),
+                "vn" => normals.push(parse_vec3(&mut words).ok_or_else(|| error("bad normal"))?),
+                "vt" => {
+                    let texcoord = parse_vec2(&mut words).ok_or_else(|| error("bad texcoord"))?;
+                    // OBJ places the origin of textures in the bottom left corner
+                    texcoords.push(Vec2::new(texcoord.x, 1.0 - texcoord.y));
+                }
+                "f" => {
+                    let face = words
+                        .map(|word| {
+                            parse_face_vertex(word, positions.len(), texcoords.len(), normals.len())
+                        })
+                        .collect::<Option<Vec<_>>>()
+                        .filter(|face| face.len() >= 3)
+                        .ok_or_else(|| error("bad face"))?;
+
+                    let indices = face
+                        .iter()
+                        .map(|vertex| {
+                            add_vertex(&mut group, *vertex, &positions, &texcoords, &normals)
+                        })
+                        .collect::<Vec<_>>();
+
+                    for i in 1..indices.len() - 1 {
+                        group
+                            .indices
+                            .extend_from_slice(&[indices[0], indices[i], indices[i + 1]]);
+                    }
+                }
+                "o" | "g" => {
+                    let name = words.collect::<Vec<_>>().join(" ");
+                    let material = group.material.clone();
+                    objects
+                        .extend(std::mem::replace(&mut group, Group::new(name, material)).finish());
+                }
+                "usemtl" => {
+                    let material = words.next().map(str::to_owned);
+                    let name = group.name.clone();
+                    objects
+                        .extend(std::mem::replace(&mut group, Group::new(name, material)).finish());
+                }
+                "mtllib" => {
+                    for library in words {
+                        let library = dir.join(library);
+                        let source = fs::read_to_string(&library)?;
+                        materials.extend(parse_mtl(&library, &source)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        objects.extend(group.finish());
+
+        Ok(Self { objects, materials })
+    }
+
+    /// Returns the material named `name`
+    pub fn material(&self, name: &str) -> Option<&ObjMaterial> {
+        self.materials.iter().find(|material| material.name == name)
+    }
+}
+
+// Parses the materials of an MTL library at `path`. Only the diffuse texture and emission are
+// used, other properties are ignored.
+fn parse_mtl(path: &Path, source: &str) -> Result<Vec<ObjMaterial>, Error> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            materials.push(ObjMaterial {
+                name: words.collect::<Vec<_>>().join(" "),
+                albedo: None,
+                emissive: None,
+                emissive_factor: Vec3::zero(),
+            });
+
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+
+        match keyword {
+            // Texture options preceding the file name are not supported
+            "map_Kd" => material.albedo = words.last().map(|file| dir.join(file)),
+            "map_Ke" => material.emissive = words.last().map(|file| dir.join(file)),
+            "Ke" => {
+                material.emissive_factor = parse_vec3(&mut words)
+                    .ok_or_else(|| parse_error(path, line_index + 1, "bad emission"))?
+            }
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+// Returns the index of the vertex in the group, adding it if not already present
+fn add_vertex(
+    group: &mut Group,
+    key: FaceVertex,
+    positions: &[Vec3],
+    texcoords: &[Vec2],
+    normals: &[Vec3],
+) -> u32 {
+    if let Some(index) = group.lookup.get(&key) {
+        return *index;
+    }
+
+    let (position, texcoord, normal) = key;
+    let index = group.vertices.len() as u32;
+
+    group.vertices.push(Vertex::new(
+        positions[position],
+        normal.map_or_else(Vec3::zero, |normal| normals[normal]),
+        texcoord.map_or_else(Vec2::zero, |texcoord| texcoords[texcoord]),
+    ));
+
+    group.missing_normals.push(normal.is_none());
+    group.lookup.insert(key, index);
+
+    index
+}
+
+// Parses a face vertex of the forms 'v', 'v/vt', 'v//vn' or 'v/vt/vn'. Negative indices are
+// relative to the end of the lists.
+fn parse_face_vertex(
+    word: &str,
+    position_count: usize,
+    texcoord_count: usize,
+    normal_count: usize,
+) -> Option<FaceVertex> {
+    let mut parts = word.split('/');
+
+    let position = resolve_index(parts.next()?, position_count)?;
+
+    let texcoord = match parts.next() {
+        Some(part) if !part.is_empty() => Some(resolve_index(part, texcoord_count)?),
+        _ => None,
+    };
+
+    let normal = match parts.next() {
+        Some(part) if !part.is_empty() => Some(resolve_index(part, normal_count)?),
+        _ => None,
+    };
+
+    Some((position, texcoord, normal))
+}
+
+// Converts a one based or negative relative OBJ index into an index into a list of `count`
+fn resolve_index(word: &str, count: usize) -> Option<usize> {
+    let index: isize = word.parse().ok()?;
+
+    let index = if index < 0 {
+        count as isize + index
+    } else {
+        index - 1
+    };
+
+    if index >= 0 && (index as usize) < count {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+fn parse_vec3<'a>(words: &mut impl Iterator<Item = &'a str>) -> Option<Vec3> {
+    let mut next = || words.next()?.parse::<f32>().ok();
+    Some(Vec3::new(next()?, next()?, next()?))
+}
+
+fn parse_vec2<'a>(words: &mut impl Iterator<Item = &'a str>) -> Option<Vec2> {
+    let mut next = || words.next()?.parse::<f32>().ok();
+    Some(Vec2::new(next()?, next().unwrap_or(0.0)))
+}
+
+fn parse_error(path: &Path, line: usize, message: &str) -> Error {
+    Error::MeshParse {
+        path: path.to_owned(),
+        message: format!("line {}: {}", line, message),
+    }
+}
@@ -0,0 +1,159 @@
+//! Index and vertex reordering for efficient vertex processing on the GPU.
+use super::MeshData;
+
+/// The simulated post-transform vertex cache size. Small enough to be a conservative estimate
+/// for most hardware.
+const CACHE_SIZE: u32 = 16;
+
+/// Reorders the triangles for post-transform vertex cache efficiency using the Tipsify
+/// algorithm. The returned indices describe the same triangles as `indices`.
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+
+    // Triangles adjacent to each vertex in compressed form
+    let mut offsets = vec![0; vertex_count + 1];
+    for index in indices {
+        offsets[*index as usize + 1] += 1;
+    }
+
+    // The number of not yet emitted triangles of each vertex
+    let mut live = offsets[1..]
+        .iter()
+        .map(|count| *count as u32)
+        .collect::<Vec<_>>();
+
+    for i in 0..vertex_count {
+        offsets[i + 1] += offsets[i];
+    }
+
+    let mut adjacency = vec![0; indices.len()];
+    let mut fill = offsets.clone();
+    for (triangle, vertices) in indices.chunks_exact(3).enumerate() {
+        for index in vertices {
+            adjacency[fill[*index as usize]] = triangle;
+            fill[*index as usize] += 1;
+        }
+    }
+
+    let mut cache_time = vec![0; vertex_count];
+    let mut timestamp = CACHE_SIZE + 1;
+    let mut emitted = vec![false; triangle_count];
+    let mut dead_end = Vec::new();
+    let mut cursor = 0;
+
+    let mut result = Vec::with_capacity(indices.len());
+    let mut fanning = if vertex_count > 0 { Some(0) } else { None };
+
+    while let Some(vertex) = fanning {
+        let mut candidates = Vec::new();
+
+        // Emit all remaining triangles around the fanning vertex
+        for &triangle in &adjacency[offsets[vertex]..offsets[vertex + 1]] {
+            if emitted[triangle] {
+                continue;
+            }
+
+            for &index in &indices[triangle * 3..triangle * 3 + 3] {
+                let index = index as usize;
+
+                result.push(index as u32);
+                dead_end.push(index);
+                candidates.push(index);
+                live[index] -= 1;
+
+                if timestamp - cache_time[index] > CACHE_SIZE {
+                    cache_time[index] = timestamp;
+                    timestamp += 1;
+                }
+            }
+
+            emitted[triangle] = true;
+        }
+
+        fanning = next_vertex(
+            &candidates,
+            &live,
+            &cache_time,
+            timestamp,
+            &mut dead_end,
+            &mut cursor,
+        );
+    }
+
+    result
+}
+
+/// Returns the candidate which is expected to remain in the cache for all of its remaining
+/// triangles, falling back to recently used vertices and then to any vertex with remaining
+/// triangles.
+fn next_vertex(
+    candidates: &[usize],
+    live: &[u32],
+    cache_time: &[u32],
+    timestamp: u32,
+    dead_end: &mut Vec<usize>,
+    cursor: &mut usize,
+) -> Option<usize> {
+    let mut best = None;
+    let mut best_priority = -1;
+
+    for &candidate in candidates {
+        if live[candidate] == 0 {
+            continue;
+        }
+
+        // Prefer the oldest vertex that will still be in the cache after emitting its fan
+        let age = timestamp - cache_time[candidate];
+        let priority = if age + 2 * live[candidate] <= CACHE_SIZE {
+            age as i64
+        } else {
+            0
+        };
+
+        if priority > best_priority {
+            best = Some(candidate);
+            best_priority = priority;
+        }
+    }
+
+    if best.is_some() {
+        return best;
+    }
+
+    // Continue with the most recently emitted vertex that has remaining triangles
+    while let Some(vertex) = dead_end.pop() {
+        if live[vertex] > 0 {
+            return Some(vertex);
+        }
+    }
+
+    // Continue with the next vertex in input order
+    while *cursor < live.len() {
+        *cursor += 1;
+        if live[*cursor - 1] > 0 {
+            return Some(*cursor - 1);
+        }
+    }
+
+    None
+}
+
+/// Reorders the vertices of `data` in the order they are first referenced by the indices to
+/// improve vertex fetch locality. Unreferenced vertices are removed.
+pub fn optimize_vertex_fetch(data: &mut MeshData) {
+    let mut remap = vec![std::u32::MAX; data.vertices.len()];
+    let mut vertices = Vec::with_capacity(data.vertices.len());
+
+    for index in &mut data.indices {
+        let old = *index as usize;
+
+        if remap[old] == std::u32::MAX {
+            remap[old] = vertices.len() as u32;
+            vertices.push(data.vertices[old]);
+        }
+
+        *index = remap[old];
+    }
+
+    data.vertices = vertices;
+}
@@ -0,0 +1,374 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::str::SplitAsciiWhitespace;
+
+use ultraviolet::{Vec2, Vec3};
+
+use super::{generate_normals, MeshData, Vertex};
+use crate::Error;
+
+/// The geometry of a PLY file. Files without faces are point clouds, whose indices enumerate the
+/// vertices and which are drawn with a point list topology.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlyMesh {
+    pub data: MeshData,
+    pub is_point_cloud: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        let ty = match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return None,
+        };
+
+        Some(ty)
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar {
+        name: String,
+        ty: ScalarType,
+    },
+    List {
+        name: String,
+        count: ScalarType,
+        item: ScalarType,
+    },
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl PlyMesh {
+    /// Loads the vertices and faces of an ascii or binary PLY file. Vertices are read from the
+    /// x, y, z, nx, ny, nz and s, t or u, v properties, and faces from the vertex_indices list.
+    /// Polygons are triangulated as fans, and normals are generated from the faces if missing.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let source = fs::read(path)?;
+        let error = |message: &str| parse_error(path, message);
+
+        let header_end = find(&source, b"end_header").ok_or_else(|| error("missing end_header"))?;
+        let body_start = source[header_end..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(source.len(), |i| header_end + i + 1);
+
+        let header = std::str::from_utf8(&source[..header_end])
+            .map_err(|_| error("header is not valid utf-8"))?;
+
+        let (format, elements) = parse_header(path, header)?;
+
+        let mut values = match format {
+            Format::Ascii => Values::Ascii(
+                std::str::from_utf8(&source[body_start..])
+                    .map_err(|_| error("body is not valid utf-8"))?
+                    .split_ascii_whitespace(),
+            ),
+            Format::LittleEndian | Format::BigEndian => Values::Binary {
+                data: &source[body_start..],
+                big_endian: format == Format::BigEndian,
+            },
+        };
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut has_normals = false;
+        let mut has_faces = false;
+
+        for element in &elements {
+            match element.name.as_str() {
+                "vertex" => {
+                    has_normals = element.properties.iter().any(|p| p.name() == "nx");
+                    vertices.reserve(element.count);
+
+                    for _ in 0..element.count {
+                        vertices.push(read_vertex(path, element, &mut values)?);
+                    }
+                }
+                "face" => {
+                    has_faces = true;
+
+                    for _ in 0..element.count {
+                        read_face(path, element, &mut values, vertices.len(), &mut indices)?;
+                    }
+                }
+                _ => {
+                    for _ in 0..element.count {
+                        skip_element(path, element, &mut values)?;
+                    }
+                }
+            }
+        }
+
+        if !has_faces {
+            indices = (0..vertices.len() as u32).collect();
+        } else if !has_normals {
+            let missing = vec![true; vertices.len()];
+            generate_normals(&mut vertices, &indices, &missing);
+        }
+
+        Ok(Self {
+            data: MeshData::new(vertices, indices),
+            is_point_cloud: !has_faces,
+        })
+    }
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Self::Scalar { name, .. } | Self::List { name, .. } => name,
+        }
+    }
+}
+
+fn parse_header(path: &Path, header: &str) -> Result<(Format, Vec<Element>), Error> {
+    let error = |message: &str| parse_error(path, message);
+
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(error("not a ply file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    for line in lines {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+
+        match words.as_slice() {
+            ["format", kind, _] => {
+                format = Some(match *kind {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::LittleEndian,
+                    "binary_big_endian" => Format::BigEndian,
+                    _ => return Err(error("unknown format")),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: (*name).to_owned(),
+                count: count.parse().map_err(|_| error("bad element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or_else(|| error("property outside of element"))?
+                .properties
+                .push(Property::List {
+                    name: (*name).to_owned(),
+                    count: ScalarType::parse(count).ok_or_else(|| error("bad list type"))?,
+                    item: ScalarType::parse(item).ok_or_else(|| error("bad list type"))?,
+                }),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or_else(|| error("property outside of element"))?
+                .properties
+                .push(Property::Scalar {
+                    name: (*name).to_owned(),
+                    ty: ScalarType::parse(ty).ok_or_else(|| error("bad property type"))?,
+                }),
+            _ => {}
+        }
+    }
+
+    let format = format.ok_or_else(|| error("missing format"))?;
+    Ok((format, elements))
+}
+
+fn read_vertex(path: &Path, element: &Element, values: &mut Values) -> Result<Vertex, Error> {
+    let mut position = Vec3::zero();
+    let mut normal = Vec3::zero();
+    let mut texcoord = Vec2::zero();
+
+    for property in &element.properties {
+        let ty = match property {
+            Property::Scalar { ty, .. } => *ty,
+            Property::List { .. } => {
+                skip_property(path, property, values)?;
+                continue;
+            }
+        };
+
+        let value = values.read(ty).ok_or_else(|| truncated(path))? as f32;
+
+        match property.name() {
+            "x" => position.x = value,
+            "y" => position.y = value,
+            "z" => position.z = value,
+            "nx" => normal.x = value,
+            "ny" => normal.y = value,
+            "nz" => normal.z = value,
+            "s" | "u" | "texture_u" => texcoord.x = value,
+            // Like OBJ, PLY places the origin of textures in the bottom left corner
+            "t" | "v" | "texture_v" => texcoord.y = 1.0 - value,
+            _ => {}
+        }
+    }
+
+    Ok(Vertex::new(position, normal, texcoord))
+}
+
+fn read_face(
+    path: &Path,
+    element: &Element,
+    values: &mut Values,
+    vertex_count: usize,
+    indices: &mut Vec<u32>,
+) -> Result<(), Error> {
+    for property in &element.properties {
+        match property {
+            Property::List { name, count, item }
+                if name == "vertex_indices" || name == "vertex_index" =>
+            {
+                let count = values.read(*count).ok_or_else(|| truncated(path))? as usize;
+
+                let face = (0..count)
+                    .map(|_| values.read(*item).map(|index| index as u32))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| truncated(path))?;
+
+                if face.len() < 3 || face.iter().any(|index| *index as usize >= vertex_count) {
+                    return Err(parse_error(path, "bad face"));
+                }
+
+                for i in 1..face.len() - 1 {
+                    indices.extend_from_slice(&[face[0], face[i], face[i + 1]]);
+                }
+            }
+            _ => skip_property(path, property, values)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn skip_element(path: &Path, element: &Element, values: &mut Values) -> Result<(), Error> {
+    element
+        .properties
+        .iter()
+        .try_for_each(|property| skip_property(path, property, values))
+}
+
+fn skip_property(path: &Path, property: &Property, values: &mut Values) -> Result<(), Error> {
+    match property {
+        Property::Scalar { ty, .. } => {
+            values.read(*ty).ok_or_else(|| truncated(path))?;
+        }
+        Property::List { count, item, .. } => {
+            let count = values.read(*count).ok_or_else(|| truncated(path))? as usize;
+
+            for _ in 0..count {
+                values.read(*item).ok_or_else(|| truncated(path))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the values of the body of a PLY file
+enum Values<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl<'a> Values<'a> {
+    /// Reads the next value as a double, which represents all PLY types exactly
+    fn read(&mut self, ty: ScalarType) -> Option<f64> {
+        let (data, big_endian) = match self {
+            Self::Ascii(words) => return words.next()?.parse().ok(),
+            Self::Binary { data, big_endian } => (data, *big_endian),
+        };
+
+        if data.len() < ty.size() {
+            return None;
+        }
+
+        let (bytes, rest) = data.split_at(ty.size());
+        *data = rest;
+
+        macro_rules! read {
+            ($ty: ty) => {{
+                let bytes = bytes.try_into().unwrap();
+                if big_endian {
+                    <$ty>::from_be_bytes(bytes) as f64
+                } else {
+                    <$ty>::from_le_bytes(bytes) as f64
+                }
+            }};
+        }
+
+        let value = match ty {
+            ScalarType::I8 => read!(i8),
+            ScalarType::U8 => read!(u8),
+            ScalarType::I16 => read!(i16),
+            ScalarType::U16 => read!(u16),
+            ScalarType::I32 => read!(i32),
+            ScalarType::U32 => read!(u32),
+            ScalarType::F32 => read!(f32),
+            ScalarType::F64 => read!(f64),
+        };
+
+        Some(value)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn truncated(path: &Path) -> Error {
+    parse_error(path, "unexpected end of file")
+}
+
+fn parse_error(path: &Path, message: &str) -> Error {
+    Error::MeshParse {
+        path: path.to_owned(),
+        message: message.to_owned(),
+    }
+}
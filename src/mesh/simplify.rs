@@ -0,0 +1,346 @@
+//! Mesh simplification through iterative edge collapses ordered by quadric error metrics.
+//! Vertices are collapsed onto existing neighbouring vertices, so the simplified index buffer
+//! can be used with the original vertex buffer.
+use std::collections::{HashMap, HashSet};
+
+use ultraviolet::Vec3;
+
+use super::MeshData;
+
+/// Symmetric 4x4 matrix accumulating the squared distance to a set of planes
+#[derive(Debug, Default, Clone, Copy)]
+struct Quadric {
+    a00: f64,
+    a11: f64,
+    a22: f64,
+    a10: f64,
+    a20: f64,
+    a21: f64,
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    c: f64,
+}
+
+impl Quadric {
+    /// Creates a quadric from the plane `normal` . p + `d` = 0 scaled by `weight`
+    fn from_plane(normal: Vec3, d: f32, weight: f32) -> Self {
+        let (a, b, c, d, w) = (
+            normal.x as f64,
+            normal.y as f64,
+            normal.z as f64,
+            d as f64,
+            weight as f64,
+        );
+
+        Self {
+            a00: a * a * w,
+            a11: b * b * w,
+            a22: c * c * w,
+            a10: a * b * w,
+            a20: a * c * w,
+            a21: b * c * w,
+            b0: a * d * w,
+            b1: b * d * w,
+            b2: c * d * w,
+            c: d * d * w,
+        }
+    }
+
+    /// Creates an area weighted quadric of the triangle plane
+    fn from_triangle(p0: Vec3, p1: Vec3, p2: Vec3) -> Self {
+        let normal = (p1 - p0).cross(p2 - p0);
+        let area = normal.mag();
+
+        if area == 0.0 {
+            return Self::default();
+        }
+
+        let normal = normal / area;
+        Self::from_plane(normal, -normal.dot(p0), area)
+    }
+
+    /// Creates a quadric of the plane perpendicular to the triangle through the edge p0 p1.
+    /// Keeps collapses from moving open borders.
+    fn from_border(p0: Vec3, p1: Vec3, p2: Vec3) -> Self {
+        let edge = p1 - p0;
+        let length = edge.mag();
+        let normal = edge.cross((p2 - p0).cross(edge));
+        let mag = normal.mag();
+
+        if length == 0.0 || mag == 0.0 {
+            return Self::default();
+        }
+
+        let normal = normal / mag;
+
+        // Weigh the border strongly to preserve the silhouette
+        Self::from_plane(normal, -normal.dot(p0), length * length * BORDER_WEIGHT)
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.a00 += other.a00;
+        self.a11 += other.a11;
+        self.a22 += other.a22;
+        self.a10 += other.a10;
+        self.a20 += other.a20;
+        self.a21 += other.a21;
+        self.b0 += other.b0;
+        self.b1 += other.b1;
+        self.b2 += other.b2;
+        self.c += other.c;
+    }
+
+    /// Returns the weighted sum of the squared distances from p to the planes
+    fn error(&self, p: Vec3) -> f64 {
+        let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+
+        let rx = self.a00 * x + self.a10 * y + self.a20 * z + self.b0;
+        let ry = self.a10 * x + self.a11 * y + self.a21 * z + self.b1;
+        let rz = self.a20 * x + self.a21 * y + self.a22 * z + self.b2;
+
+        (rx * x + ry * y + rz * z + self.b0 * x + self.b1 * y + self.b2 * z + self.c).abs()
+    }
+}
+
+const BORDER_WEIGHT: f32 = 10.0;
+
+/// Collapse of the vertex `source` onto `target`
+struct Collapse {
+    source: u32,
+    target: u32,
+    error: f64,
+}
+
+/// Simplifies the triangles of `data` to approximately `target_ratio` of the original index
+/// count. Returns the new index buffer referencing the unchanged vertices of `data`.
+/// Vertices on open borders and attribute seams are never moved, so the result may contain
+/// more triangles than targeted.
+pub fn simplify(data: &MeshData, target_ratio: f32) -> Vec<u32> {
+    let positions = data
+        .vertices
+        .iter()
+        .map(|vertex| vertex.position)
+        .collect::<Vec<_>>();
+
+    let mut indices = data.indices.clone();
+
+    let target_index_count = (indices.len() as f32 * target_ratio.max(0.0).min(1.0)) as usize;
+    let target_index_count = target_index_count - target_index_count % 3;
+
+    let locked = locked_vertices(&positions, &indices);
+    let mut quadrics = vertex_quadrics(&positions, &indices);
+
+    while indices.len() > target_index_count {
+        let collapses = rank_collapses(&positions, &quadrics, &indices, &locked);
+        let adjacency = triangle_adjacency(positions.len(), &indices);
+
+        let triangle_goal = (indices.len() - target_index_count) / 3;
+        let mut removed = 0;
+
+        // Each vertex is only involved in a single collapse per pass
+        let mut touched = vec![false; positions.len()];
+        let mut remap = (0..positions.len() as u32).collect::<Vec<_>>();
+
+        for collapse in collapses {
+            if removed >= triangle_goal {
+                break;
+            }
+
+            let (source, target) = (collapse.source as usize, collapse.target as usize);
+
+            if touched[source] || touched[target] {
+                continue;
+            }
+
+            if has_triangle_flip(&positions, &indices, &adjacency[source], source, target) {
+                continue;
+            }
+
+            touched[source] = true;
+            touched[target] = true;
+            remap[source] = collapse.target;
+
+            let source_quadric = quadrics[source];
+            quadrics[target].add(&source_quadric);
+
+            // Triangles sharing the collapsed edge degenerate
+            removed += adjacency[source]
+                .iter()
+                .filter(|&&triangle| {
+                    indices[triangle * 3..triangle * 3 + 3].contains(&collapse.target)
+                })
+                .count();
+        }
+
+        if removed == 0 {
+            break;
+        }
+
+        indices = indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                [
+                    remap[triangle[0] as usize],
+                    remap[triangle[1] as usize],
+                    remap[triangle[2] as usize],
+                ]
+            })
+            .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+            .flat_map(|t| t.to_vec())
+            .collect();
+    }
+
+    indices
+}
+
+/// Returns the accumulated quadrics of the triangles and borders around each vertex
+fn vertex_quadrics(positions: &[Vec3], indices: &[u32]) -> Vec<Quadric> {
+    let mut quadrics = vec![Quadric::default(); positions.len()];
+
+    let edges = indices
+        .chunks_exact(3)
+        .flat_map(|t| vec![(t[0], t[1]), (t[1], t[2]), (t[2], t[0])])
+        .collect::<HashSet<_>>();
+
+    for triangle in indices.chunks_exact(3) {
+        let p = [
+            positions[triangle[0] as usize],
+            positions[triangle[1] as usize],
+            positions[triangle[2] as usize],
+        ];
+
+        let quadric = Quadric::from_triangle(p[0], p[1], p[2]);
+
+        for i in 0..3 {
+            quadrics[triangle[i] as usize].add(&quadric);
+
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+
+            // Border edge without an opposite triangle
+            if !edges.contains(&(b, a)) {
+                let border = Quadric::from_border(p[i], p[(i + 1) % 3], p[(i + 2) % 3]);
+                quadrics[a as usize].add(&border);
+                quadrics[b as usize].add(&border);
+            }
+        }
+    }
+
+    quadrics
+}
+
+/// Returns which vertices can not be collapsed. These are vertices on open borders, and
+/// vertices sharing a position with another vertex, i.e; on normal or texture coordinate seams.
+fn locked_vertices(positions: &[Vec3], indices: &[u32]) -> Vec<bool> {
+    let mut locked = vec![false; positions.len()];
+
+    let mut position_counts: HashMap<[u32; 3], usize> = HashMap::new();
+    for p in positions {
+        *position_counts.entry(position_key(*p)).or_default() += 1;
+    }
+
+    for (i, p) in positions.iter().enumerate() {
+        if position_counts[&position_key(*p)] > 1 {
+            locked[i] = true;
+        }
+    }
+
+    let edges = indices
+        .chunks_exact(3)
+        .flat_map(|t| vec![(t[0], t[1]), (t[1], t[2]), (t[2], t[0])])
+        .collect::<HashSet<_>>();
+
+    for &(a, b) in &edges {
+        if !edges.contains(&(b, a)) {
+            locked[a as usize] = true;
+            locked[b as usize] = true;
+        }
+    }
+
+    locked
+}
+
+fn position_key(p: Vec3) -> [u32; 3] {
+    [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]
+}
+
+/// Returns the possible collapses along the triangle edges sorted by increasing error
+fn rank_collapses(
+    positions: &[Vec3],
+    quadrics: &[Quadric],
+    indices: &[u32],
+    locked: &[bool],
+) -> Vec<Collapse> {
+    let mut collapses = Vec::with_capacity(indices.len());
+
+    for triangle in indices.chunks_exact(3) {
+        for i in 0..3 {
+            let (source, target) = (triangle[i], triangle[(i + 1) % 3]);
+
+            for &(source, target) in &[(source, target), (target, source)] {
+                if locked[source as usize] {
+                    continue;
+                }
+
+                let mut quadric = quadrics[source as usize];
+                quadric.add(&quadrics[target as usize]);
+
+                collapses.push(Collapse {
+                    source,
+                    target,
+                    error: quadric.error(positions[target as usize]),
+                })
+            }
+        }
+    }
+
+    collapses.sort_by(|a, b| a.error.partial_cmp(&b.error).unwrap());
+    collapses
+}
+
+/// Returns the triangles each vertex is part of
+fn triangle_adjacency(vertex_count: usize, indices: &[u32]) -> Vec<Vec<usize>> {
+    let mut adjacency = vec![Vec::new(); vertex_count];
+
+    for (i, triangle) in indices.chunks_exact(3).enumerate() {
+        for index in triangle {
+            adjacency[*index as usize].push(i);
+        }
+    }
+
+    adjacency
+}
+
+/// Returns true if moving source onto target flips any of the remaining triangles around
+/// source
+fn has_triangle_flip(
+    positions: &[Vec3],
+    indices: &[u32],
+    triangles: &[usize],
+    source: usize,
+    target: usize,
+) -> bool {
+    triangles
+        .iter()
+        .map(|triangle| &indices[triangle * 3..triangle * 3 + 3])
+        .filter(|triangle| !triangle.contains(&(target as u32)))
+        .any(|triangle| {
+            let p = [
+                positions[triangle[0] as usize],
+                positions[triangle[1] as usize],
+                positions[triangle[2] as usize],
+            ];
+
+            let mut moved = p;
+            for i in 0..3 {
+                if triangle[i] as usize == source {
+                    moved[i] = positions[target];
+                }
+            }
+
+            let before = (p[1] - p[0]).cross(p[2] - p[0]);
+            let after = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+
+            before.dot(after) <= 0.0
+        })
+}
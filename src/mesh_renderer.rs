@@ -5,28 +5,138 @@ use ultraviolet::*;
 use ash::vk;
 use vk::{DescriptorSet, DescriptorSetLayout};
 
+use crate::bind_group::{self, FrameSets, MAX_SETS, OBJECT_SET};
+use crate::environment::ENVIRONMENT_SET;
+use crate::fog::FOG_SET;
+use crate::light_culling::LIGHT_SET;
+use crate::material::MATERIAL_SET;
+use crate::mesh::{Meshlets, MESHLET_SET};
+use crate::point_shadow::POINT_SHADOW_SET;
+use crate::ray_query::RAY_QUERY_SET;
 use crate::resources::*;
-use crate::{vulkan::descriptors::DescriptorBuilder, Camera, Scene};
+use crate::shadow::SHADOW_SET;
+use crate::stereo::VIEW_SET;
+use crate::{vulkan::descriptors::DescriptorBuilder, Camera, Object, Scene};
 
 use super::vulkan;
-use super::Material;
 use super::Mesh;
+use super::{Material, PassTag};
 use vulkan::commands::*;
 use vulkan::descriptors::*;
+use vulkan::mesh_shader::MESH_STAGE;
 use vulkan::*;
 
-pub const MAX_OBJECTS: usize = 8192;
+/// The number of objects the object buffers are allocated for by default
+pub const DEFAULT_OBJECT_CAPACITY: usize = 1024;
 
-#[derive(Default)]
+/// Determines how the object capacity grows when the scene exceeds it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GrowthPolicy {
+    /// Multiply the capacity by the factor until all objects fit
+    Factor(f32),
+    /// Grow the capacity in increments of a fixed number of objects
+    Linear(usize),
+    /// Grow to exactly fit the objects
+    Exact,
+}
+
+impl GrowthPolicy {
+    /// Returns the new capacity required to fit `required` objects
+    pub fn grow(&self, capacity: usize, required: usize) -> usize {
+        if required <= capacity {
+            return capacity;
+        }
+
+        match *self {
+            GrowthPolicy::Factor(factor) => {
+                let mut capacity = capacity.max(1);
+
+                while capacity < required {
+                    capacity = ((capacity as f32 * factor) as usize).max(capacity + 1);
+                }
+
+                capacity
+            }
+            GrowthPolicy::Linear(increment) => {
+                let increment = increment.max(1);
+                let increments = (required - capacity + increment - 1) / increment;
+
+                capacity + increments * increment
+            }
+            GrowthPolicy::Exact => required,
+        }
+    }
+}
+
+impl Default for GrowthPolicy {
+    fn default() -> Self {
+        GrowthPolicy::Factor(2.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshRendererInfo {
+    /// The number of objects the object buffers are initially allocated for
+    pub initial_capacity: usize,
+    /// How the object buffers grow when the scene exceeds the capacity
+    pub growth: GrowthPolicy,
+    /// Record the draws of static objects into secondary command buffers once and only
+    /// re-record the dynamic objects each frame. Requires an inheritance to be set.
+    pub record_static: bool,
+}
+
+impl Default for MeshRendererInfo {
+    fn default() -> Self {
+        Self {
+            initial_capacity: DEFAULT_OBJECT_CAPACITY,
+            growth: GrowthPolicy::default(),
+            record_static: false,
+        }
+    }
+}
+
+/// The data of each scene object, matching `ObjectData` in `object.glsl`. Only rewritten when
+/// the object changes.
+#[derive(Default, Clone, Copy)]
 #[repr(C)]
 struct ObjectData {
-    mvp: Mat4,
+    model: Mat4,
+}
+
+/// The camera uniform, matching the std140 `CameraData` block of the shaders
+#[derive(Default, Clone, Copy)]
+#[repr(C)]
+struct CameraData {
+    view_projection: Mat4,
 }
 
 struct FrameData {
+    context: Rc<VulkanContext>,
     set: DescriptorSet,
     set_layout: DescriptorSetLayout,
-    object_buffer: Buffer,
+    /// The object buffer visible to mesh shaders, bound at `OBJECT_SET` of mesh shading
+    /// pipelines.
+    /// None without mesh shader support.
+    mesh_set: Option<DescriptorSet>,
+    commandpool: CommandPool,
+    /// Secondary command buffers of each recorded pass
+    pass_commands: Vec<PassCommands>,
+    /// The static batches, passes and resource generation the static command buffers were
+    /// recorded with. None if the static command buffers need to be re-recorded.
+    static_key: Option<(Vec<Batch>, Vec<PassTag>, u64)>,
+    /// The scene revision the object buffer of the frame was last written at. Zero after the
+    /// buffer is reallocated, which rewrites all objects.
+    synced: u64,
+    /// The per-frame sets bound to the effects using them, e.g; the shadows and fog of the scene
+    frame_sets: FrameSets,
+}
+
+/// The secondary command buffers of a single pass
+struct PassCommands {
+    /// Reused until the static objects change
+    static_commands: CommandBuffer,
+    /// Re-recorded every frame
+    dynamic_commands: CommandBuffer,
 }
 
 impl FrameData {
@@ -34,19 +144,17 @@ impl FrameData {
         context: Rc<VulkanContext>,
         descriptor_layout_cache: &mut DescriptorLayoutCache,
         descriptor_allocator: &mut DescriptorAllocator,
+        object_buffer: &Buffer,
+        instance_buffer: &Buffer,
+        camera_buffer: &Buffer,
     ) -> Result<Self, vulkan::Error> {
-        let object_buffer = Buffer::new_uninit(
-            context.clone(),
-            BufferType::Storage,
-            BufferUsage::MappedPersistent,
-            mem::size_of::<ObjectData>() as u64 * MAX_OBJECTS as u64,
-        )?;
-
         let mut set = Default::default();
         let mut set_layout = Default::default();
 
         DescriptorBuilder::new()
-            .bind_storage_buffer(0, vk::ShaderStageFlags::VERTEX, &object_buffer)
+            .bind_storage_buffer(0, vk::ShaderStageFlags::VERTEX, object_buffer)
+            .bind_storage_buffer(1, vk::ShaderStageFlags::VERTEX, instance_buffer)
+            .bind_uniform_buffer(2, vk::ShaderStageFlags::VERTEX, camera_buffer)
             .build(
                 context.device(),
                 descriptor_layout_cache,
@@ -55,17 +163,62 @@ impl FrameData {
             )?
             .layout(descriptor_layout_cache, &mut set_layout)?;
 
+        let mesh_set = match context.mesh_shader() {
+            Some(_) => {
+                let mut mesh_set = Default::default();
+
+                DescriptorBuilder::new()
+                    .bind_storage_buffer(0, MESH_STAGE, object_buffer)
+                    .bind_storage_buffer(1, MESH_STAGE, instance_buffer)
+                    .bind_uniform_buffer(2, MESH_STAGE, camera_buffer)
+                    .build(
+                        context.device(),
+                        descriptor_layout_cache,
+                        descriptor_allocator,
+                        &mut mesh_set,
+                    )?;
+
+                Some(mesh_set)
+            }
+            None => None,
+        };
+
+        let commandpool = CommandPool::new(
+            context.device_ref(),
+            context.queue_families().graphics().unwrap(),
+            false,
+            true,
+        )?;
+
         Ok(Self {
-            object_buffer,
+            context,
             set,
             set_layout,
+            mesh_set,
+            commandpool,
+            pass_commands: Vec::new(),
+            static_key: None,
+            synced: 0,
+            frame_sets: FrameSets::default(),
         })
     }
 }
 
+/// The region of the attachments objects are drawn into
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Region {
+    viewport: vk::Rect2D,
+    /// Clear the depth within the viewport before drawing, which prevents the objects of
+    /// overlapping views from occluding each other
+    clear_depth: bool,
+}
+
+/// A range of consecutive instances sharing material and mesh drawn with a single instanced
+/// draw. Each instance reads the index of its object from the instance buffer.
+#[derive(Clone, PartialEq)]
 struct Batch {
-    material: Rc<Material>,
-    mesh: Rc<Mesh>,
+    material: Handle<Material>,
+    mesh: Handle<Mesh>,
     range: Range<usize>,
 }
 
@@ -78,6 +231,22 @@ struct RenderObject {
 pub struct MeshRenderer {
     context: Rc<VulkanContext>,
     frames: ArrayVec<[FrameData; swapchain::MAX_FRAMES]>,
+    /// The data of each scene object, indexed by the object
+    object_buffers: PerFrameBuffer<ObjectData>,
+    /// The index of the object drawn by each instance, in the order of the batches
+    instance_buffers: PerFrameBuffer<u32>,
+    camera_buffers: PerFrameBuffer<CameraData>,
+    /// One indexed indirect draw command per batch. Every object may in the worst case be its
+    /// own batch, so each copy fits as many commands as the object buffer fits objects.
+    indirect_buffers: PerFrameBuffer<vk::DrawIndexedIndirectCommand>,
+    growth: GrowthPolicy,
+    record_static: bool,
+    /// The renderpass or dynamic rendering secondary command buffers are recorded for
+    inheritance: Option<Inheritance>,
+    region: Region,
+    /// The texture drawn into when rendering for a render target. Objects whose material
+    /// samples it are not drawn.
+    render_target: Option<Handle<Texture>>,
 }
 
 impl MeshRenderer {
@@ -85,65 +254,339 @@ impl MeshRenderer {
         context: Rc<VulkanContext>,
         descriptor_layout_cache: &mut DescriptorLayoutCache,
         descriptor_allocator: &mut DescriptorAllocator,
-        image_count: usize,
+        frames_in_flight: usize,
+        info: MeshRendererInfo,
     ) -> Result<Self, vulkan::Error> {
-        let frames = (0..image_count)
-            .map(|_| {
+        let object_buffers = PerFrameBuffer::new(
+            context.clone(),
+            BufferType::Storage,
+            BufferUsage::MappedPersistent,
+            frames_in_flight,
+            info.initial_capacity,
+        )?;
+
+        let instance_buffers = PerFrameBuffer::new(
+            context.clone(),
+            BufferType::Storage,
+            BufferUsage::MappedPersistent,
+            frames_in_flight,
+            info.initial_capacity,
+        )?;
+
+        let camera_buffers = PerFrameBuffer::new(
+            context.clone(),
+            BufferType::Uniform,
+            BufferUsage::MappedPersistent,
+            frames_in_flight,
+            1,
+        )?;
+
+        let indirect_buffers = PerFrameBuffer::new(
+            context.clone(),
+            BufferType::Indirect,
+            BufferUsage::MappedPersistent,
+            frames_in_flight,
+            info.initial_capacity,
+        )?;
+
+        let frames = (0..frames_in_flight)
+            .map(|frame_index| {
                 FrameData::new(
                     context.clone(),
                     descriptor_layout_cache,
                     descriptor_allocator,
+                    object_buffers.current(frame_index).buffer(),
+                    instance_buffers.current(frame_index).buffer(),
+                    camera_buffers.current(frame_index).buffer(),
                 )
             })
             .collect::<Result<_, _>>()?;
 
-        Ok(Self { context, frames })
+        Ok(Self {
+            context,
+            frames,
+            object_buffers,
+            instance_buffers,
+            camera_buffers,
+            indirect_buffers,
+            growth: info.growth,
+            record_static: info.record_static,
+            inheritance: None,
+            region: Region {
+                viewport: vk::Rect2D::default(),
+                clear_depth: false,
+            },
+            render_target: None,
+        })
+    }
+
+    /// Sets the renderpass or dynamic rendering the objects are drawn within, which is required
+    /// for recording into secondary command buffers. Invalidates the recorded command buffers.
+    pub fn set_inheritance(&mut self, inheritance: Inheritance) {
+        self.inheritance = Some(inheritance);
+        self.invalidate();
+    }
+
+    /// Sets the extent of the attachments the objects are drawn into, which determines the
+    /// viewport. Invalidates the recorded command buffers.
+    pub fn set_extent(&mut self, extent: Extent) {
+        self.set_viewport(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: extent.into(),
+        })
+    }
+
+    /// Sets the region of the attachments the objects are drawn into. Invalidates the recorded
+    /// command buffers if the region changed.
+    pub fn set_viewport(&mut self, viewport: vk::Rect2D) {
+        if self.region.viewport != viewport {
+            self.region.viewport = viewport;
+            self.invalidate();
+        }
+    }
+
+    /// Clear the depth within the viewport before drawing. Required when drawing on top of
+    /// other views. Invalidates the recorded command buffers if changed.
+    pub fn set_clear_depth(&mut self, clear_depth: bool) {
+        if self.region.clear_depth != clear_depth {
+            self.region.clear_depth = clear_depth;
+            self.invalidate();
+        }
+    }
+
+    /// Sets the texture drawn into when rendering for a render target. Objects whose material
+    /// samples the texture are skipped, since a texture can not be read while rendered to.
+    pub fn set_render_target(&mut self, texture: Handle<Texture>) {
+        self.render_target = Some(texture);
+        self.invalidate();
+    }
+
+    /// Sets the descriptor set bound at `SHADOW_SET` for pipelines using it. Objects whose
+    /// effect samples shadows are not drawn without a shadow set. Invalidates the recorded
+    /// command buffers.
+    pub fn set_shadow_set(&mut self, shadow_set: Option<DescriptorSet>) {
+        self.set_frame_set(SHADOW_SET, shadow_set)
+    }
+
+    /// Sets the descriptor set bound at `POINT_SHADOW_SET` for pipelines using it. Objects whose
+    /// effect is lit by point lights are not drawn without a point shadow set. Invalidates the
+    /// recorded command buffers.
+    pub fn set_point_shadow_set(&mut self, point_shadow_set: Option<DescriptorSet>) {
+        self.set_frame_set(POINT_SHADOW_SET, point_shadow_set)
+    }
+
+    /// Sets the descriptor set bound at `LIGHT_SET` for pipelines using it. Objects whose effect
+    /// is lit by the culled lights are not drawn without a light set. Invalidates the recorded
+    /// command buffers.
+    pub fn set_light_set(&mut self, light_set: Option<DescriptorSet>) {
+        self.set_frame_set(LIGHT_SET, light_set)
+    }
+
+    /// Sets the descriptor set bound at `ENVIRONMENT_SET` for pipelines using it. Objects whose
+    /// effect is lit by the environment are not drawn without an environment set. Invalidates
+    /// the recorded command buffers.
+    pub fn set_environment_set(&mut self, environment_set: Option<DescriptorSet>) {
+        self.set_frame_set(ENVIRONMENT_SET, environment_set)
+    }
+
+    /// Sets the descriptor set bound at `FOG_SET` for pipelines using it. Objects whose effect
+    /// applies fog are not drawn without a fog set. Invalidates the recorded command buffers.
+    pub fn set_fog_set(&mut self, fog_set: Option<DescriptorSet>) {
+        self.set_frame_set(FOG_SET, fog_set)
+    }
+
+    /// Sets the descriptor set bound at `RAY_QUERY_SET` for pipelines using it. Objects whose
+    /// effect traces the scene are not drawn without a ray query set. Invalidates the recorded
+    /// command buffers.
+    pub fn set_ray_query_set(&mut self, ray_query_set: Option<DescriptorSet>) {
+        self.set_frame_set(RAY_QUERY_SET, ray_query_set)
+    }
+
+    /// Sets the descriptor set bound at `VIEW_SET` for pipelines using it. Objects whose effect
+    /// reads the views of a multiview pass are not drawn without a view set. Invalidates the
+    /// recorded command buffers.
+    pub fn set_view_set(&mut self, view_set: Option<DescriptorSet>) {
+        self.set_frame_set(VIEW_SET, view_set)
+    }
+
+    /// Sets the descriptor set bound at the per-frame slot `index` for pipelines using it, see
+    /// `bind_group`. Objects whose effect uses the set are not drawn without it. Invalidates the
+    /// recorded command buffers.
+    pub fn set_frame_set(&mut self, index: u32, set: Option<DescriptorSet>) {
+        for frame in &mut self.frames {
+            frame.frame_sets.set(index, set);
+        }
+
+        self.invalidate();
+    }
+
+    /// Forces the static objects to be re-recorded on the next draw of each frame.
+    pub fn invalidate(&mut self) {
+        self.frames
+            .iter_mut()
+            .for_each(|frame| frame.static_key = None);
     }
 
+    /// Returns true if the draws are recorded into secondary command buffers. `draw` must then
+    /// be called within a renderpass or dynamic rendering begun with secondary command buffer
+    /// contents.
+    pub fn uses_secondary(&self) -> bool {
+        self.record_static && self.inheritance.is_some()
+    }
+
+    /// Draws the scene objects in the visible layers of `camera` whose material effect
+    /// participates in any of `passes`. The passes are recorded in the order of their tags, and
+    /// objects are drawn once for each pipeline their effect declares for a pass.
+    /// The object buffers of the frame grow to fit the scene, which requires the previous
+    /// submission of the frame in flight `frame_index` to have completed.
     pub fn draw(
         &mut self,
         commandbuffer: &CommandBuffer,
         resources: &ResourceManager,
         camera: &Camera,
-        image_index: u32,
+        frame_index: usize,
         scene: &Scene,
+        passes: &[PassTag],
     ) -> Result<(), vulkan::Error> {
-        let frame = &mut self.frames[image_index as usize];
+        self.reserve(frame_index, scene.objects().len())?;
+
+        // Only the objects changed since the object buffer of the frame was last written are
+        // uploaded, which leaves static objects untouched while the camera moves
+        let frame = &mut self.frames[frame_index];
+        let all_objects = scene.objects();
 
-        let view_projection = camera.projection() * camera.calculate_view();
+        self.object_buffers
+            .write_indices(frame_index, scene.changed_since(frame.synced), |i| {
+                ObjectData {
+                    model: all_objects[i].model_matrix(),
+                }
+            })?;
+
+        frame.synced = scene.revision();
+
+        self.camera_buffers.current_mut(frame_index).write_at(
+            0,
+            &CameraData {
+                view_projection: camera.projection() * camera.calculate_view(),
+            },
+        )?;
 
-        if scene.objects().len() > MAX_OBJECTS {
-            log::error!("Scene objects exceed MAX_OBJECTS of {}", MAX_OBJECTS);
+        let render_target = self.render_target;
+
+        let visible = scene.cull(&camera.frustum());
+        let layers = camera.layers & scene.visible_layers();
+
+        // Select the level of detail of each visible object
+        let mut objects = scene
+            .objects()
+            .iter()
+            .enumerate()
+            .zip(visible)
+            .filter(|((_, object), visible)| *visible && object.layers.intersects(layers))
+            .map(|(object, _)| object)
+            .filter(|(_, object)| match render_target {
+                Some(texture) => !samples_texture(object, resources, texture),
+                None => true,
+            })
+            .map(|(i, object)| (i, object, select_lod(object, resources, camera)))
+            .collect::<Vec<_>>();
+
+        // Depth only passes do not sample the material textures
+        let samples_textures = passes
+            .iter()
+            .any(|pass| matches!(pass, PassTag::Opaque | PassTag::Transparent));
+
+        if samples_textures && resources.texture_feedback_enabled() {
+            let height = self.region.viewport.extent.height as f32;
+
+            for (_, object, mesh) in &objects {
+                let mesh = resources.meshes().raw(*mesh).unwrap();
+                let coverage =
+                    camera.screen_coverage(object.position, object.bounding_radius(mesh));
+
+                resources.record_texture_samples(object.material, coverage * height);
+            }
         }
 
-        frame.object_buffer.write_slice(
-            scene.objects().len().min(MAX_OBJECTS) as u64,
+        // Sort the objects so that objects sharing material and mesh are adjacent. Static
+        // objects are placed first when recorded separately.
+        let separate_static = self.uses_secondary();
+        objects.sort_by_key(|(_, object, mesh)| {
+            (separate_static && !object.is_static, object.material, *mesh)
+        });
+
+        let static_count = if separate_static {
+            objects
+                .iter()
+                .take_while(|(_, object, _)| object.is_static)
+                .count()
+        } else {
+            0
+        };
+
+        self.instance_buffers
+            .current_mut(frame_index)
+            .write_iter(0, objects.iter().map(|(i, _, _)| *i as u32))?;
+
+        let mut batches = create_batches(&objects[..static_count], 0);
+        let static_batch_count = batches.len();
+        batches.extend(create_batches(&objects[static_count..], static_count));
+
+        self.indirect_buffers.current_mut(frame_index).write_iter(
             0,
-            |slice| {
-                for (i, object) in scene.objects().iter().enumerate() {
-                    let object_data = ObjectData {
-                        mvp: view_projection
-                            * Mat4::from_translation(object.position)
-                            * Mat4::from_scale(0.1),
-                    };
-
-                    slice[i] = object_data;
+            batches.iter().map(|batch| {
+                let mesh = resources.meshes().raw(batch.mesh).unwrap();
+
+                vk::DrawIndexedIndirectCommand {
+                    index_count: mesh.index_count(),
+                    instance_count: batch.range.len() as u32,
+                    first_index: 0,
+                    vertex_offset: 0,
+                    first_instance: batch.range.start as u32,
                 }
-            },
+            }),
         )?;
 
-        for (i, object) in scene.objects().iter().enumerate() {
-            let material = resources.materials().raw(object.material).unwrap();
-            let effect = resources.effects().raw(*material.effect()).unwrap();
+        let mut passes = passes.to_vec();
+        passes.sort();
 
-            let mesh = resources.meshes().raw(object.mesh).unwrap();
-            commandbuffer.bind_pipeline(effect.pass(0));
-            commandbuffer.bind_descriptor_sets(effect.pass(0), 0, &[material.set(), frame.set]);
+        if separate_static {
+            self.record_secondary(
+                frame_index,
+                resources,
+                &batches,
+                static_batch_count,
+                &passes,
+            )?;
 
-            commandbuffer.bind_vertexbuffers(0, &[&mesh.vertex_buffer()]);
+            let commandbuffers = self.frames[frame_index].pass_commands[..passes.len()]
+                .iter()
+                .flat_map(|commands| vec![&commands.static_commands, &commands.dynamic_commands])
+                .collect::<Vec<_>>();
 
-            commandbuffer.bind_indexbuffer(&mesh.index_buffer(), 0);
-            commandbuffer.draw_indexed(mesh.index_count(), 1, 0, 0, i as u32);
+            commandbuffer.execute_commands(&commandbuffers);
+            return Ok(());
+        }
+
+        if self.region.clear_depth {
+            commandbuffer.clear_depth(self.region.viewport);
+        }
+
+        commandbuffer.set_viewport_rect(self.region.viewport);
+
+        let frame = &self.frames[frame_index];
+        let indirect_buffer = self.indirect_buffers.current(frame_index);
+
+        for pass in passes {
+            draw_pass(
+                commandbuffer,
+                resources,
+                frame,
+                indirect_buffer.buffer(),
+                &batches,
+                0,
+                pass,
+            );
         }
 
         Ok(())
@@ -152,4 +595,337 @@ impl MeshRenderer {
     pub fn set_layout(&self) -> DescriptorSetLayout {
         self.frames[0].set_layout
     }
+
+    /// Returns the number of objects the buffers of each frame currently fit
+    pub fn capacity(&self) -> usize {
+        self.object_buffers
+            .iter()
+            .map(|object_buffer| object_buffer.len())
+            .min()
+            .unwrap_or(0)
+    }
+
+    // Records the batches into secondary command buffers of the frame for each pass. The first
+    // `static_count` batches are only re-recorded if they changed since the last frame.
+    fn record_secondary(
+        &mut self,
+        frame_index: usize,
+        resources: &ResourceManager,
+        batches: &[Batch],
+        static_count: usize,
+        passes: &[PassTag],
+    ) -> Result<(), vulkan::Error> {
+        let inheritance = match &self.inheritance {
+            Some(inheritance) => inheritance,
+            None => return Ok(()),
+        };
+
+        let region = self.region;
+        let indirect_buffer = self.indirect_buffers.current(frame_index).buffer();
+        let frame = &mut self.frames[frame_index];
+
+        while frame.pass_commands.len() < passes.len() {
+            let mut commandbuffers = frame.commandpool.allocate_secondary(2)?;
+
+            frame.pass_commands.push(PassCommands {
+                dynamic_commands: commandbuffers.pop().unwrap(),
+                static_commands: commandbuffers.pop().unwrap(),
+            });
+        }
+
+        let (static_batches, dynamic_batches) = batches.split_at(static_count);
+
+        let key = (
+            static_batches.to_vec(),
+            passes.to_vec(),
+            resources.generation(),
+        );
+
+        if frame.static_key.as_ref() != Some(&key) {
+            for (i, (commands, pass)) in frame.pass_commands.iter().zip(passes).enumerate() {
+                let commandbuffer = &commands.static_commands;
+
+                commandbuffer
+                    .begin_secondary(vk::CommandBufferUsageFlags::default(), inheritance)?;
+
+                // The static commands of the first pass are executed first
+                if i == 0 && region.clear_depth {
+                    commandbuffer.clear_depth(region.viewport);
+                }
+
+                commandbuffer.set_viewport_rect(region.viewport);
+                draw_pass(
+                    commandbuffer,
+                    resources,
+                    frame,
+                    indirect_buffer,
+                    static_batches,
+                    0,
+                    *pass,
+                );
+                commandbuffer.end()?;
+            }
+
+            frame.static_key = Some(key);
+        }
+
+        for (commands, pass) in frame.pass_commands.iter().zip(passes) {
+            let commandbuffer = &commands.dynamic_commands;
+
+            commandbuffer
+                .begin_secondary(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT, inheritance)?;
+            commandbuffer.set_viewport_rect(region.viewport);
+            draw_pass(
+                commandbuffer,
+                resources,
+                frame,
+                indirect_buffer,
+                dynamic_batches,
+                static_count,
+                *pass,
+            );
+            commandbuffer.end()?;
+        }
+
+        Ok(())
+    }
+
+    // Reallocates the buffers of the frame to fit at least `object_count` objects and updates
+    // the descriptor set. The frame must not be in use by the device.
+    fn reserve(&mut self, frame_index: usize, object_count: usize) -> Result<(), vulkan::Error> {
+        let current = self.object_buffers.current(frame_index).len();
+        if object_count <= current {
+            return Ok(());
+        }
+
+        let capacity = self.growth.grow(current, object_count);
+
+        log::debug!("Growing object capacity from {} to {}", current, capacity);
+
+        self.object_buffers.resize(frame_index, capacity)?;
+        self.instance_buffers.resize(frame_index, capacity)?;
+        self.indirect_buffers.resize(frame_index, capacity)?;
+
+        let frame = &mut self.frames[frame_index];
+        let object_buffer = self.object_buffers.current(frame_index);
+        let instance_buffer = self.instance_buffers.current(frame_index);
+
+        DescriptorBuilder::new()
+            .bind_storage_buffer(0, vk::ShaderStageFlags::VERTEX, object_buffer.buffer())
+            .bind_storage_buffer(1, vk::ShaderStageFlags::VERTEX, instance_buffer.buffer())
+            .update(self.context.device(), frame.set);
+
+        if let Some(mesh_set) = frame.mesh_set {
+            DescriptorBuilder::new()
+                .bind_storage_buffer(0, MESH_STAGE, object_buffer.buffer())
+                .bind_storage_buffer(1, MESH_STAGE, instance_buffer.buffer())
+                .update(self.context.device(), mesh_set);
+        }
+
+        // The recorded commands reference the old buffers, and the new object buffer is empty
+        frame.static_key = None;
+        frame.synced = 0;
+
+        Ok(())
+    }
+}
+
+// Records the indirect draws of the batches whose material participates in pass.
+// `first_batch` is the index of the first batch's draw command in the indirect buffer.
+// Batches drawn with mesh shading pipelines are drawn from their meshlets instead.
+fn draw_pass(
+    commandbuffer: &CommandBuffer,
+    resources: &ResourceManager,
+    frame: &FrameData,
+    indirect_buffer: &Buffer,
+    batches: &[Batch],
+    first_batch: usize,
+    pass: PassTag,
+) {
+    let stride = mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+
+    let features = &frame.context.capabilities().features;
+
+    // Indirect draws can only start at the first object of the batch with
+    // `drawIndirectFirstInstance`, otherwise each batch is drawn directly
+    let indirect = features.draw_indirect_first_instance;
+
+    // Several consecutive draws can be issued at once if they share vertex and index buffers
+    let multi_draw = indirect && features.multi_draw_indirect;
+
+    // The material and the index of the pipeline within the pass last bound
+    let mut bound = None;
+    let mut first_draw = 0;
+
+    for (i, batch) in batches.iter().enumerate() {
+        let mesh = resources.meshes().raw(batch.mesh).unwrap();
+        let material = resources.materials().raw(batch.material).unwrap();
+        let effect = resources.effects().raw(*material.effect()).unwrap();
+
+        // The material does not participate in this pass, or uses a set which is not available,
+        // e.g; samples shadows without a shadow map
+        let pipelines = effect
+            .pipelines(pass)
+            .map(|pipeline| {
+                pipeline_sets(pipeline, material, mesh, frame).map(|sets| (pipeline, sets))
+            })
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default();
+
+        if pipelines.is_empty() {
+            first_draw = i + 1;
+            continue;
+        }
+
+        // Mesh shading pipelines draw each batch separately
+        let mesh_shading = pipelines
+            .iter()
+            .any(|(pipeline, _)| pipeline.uses_mesh_shading());
+
+        let next = batches
+            .get(i + 1)
+            .map(|next| (next.material, resources.meshes().raw(next.mesh).unwrap()));
+
+        // Defer the draw while the next batch can be merged into the same multi draw
+        if let Some((next_material, next_mesh)) = next {
+            if multi_draw
+                && !mesh_shading
+                && next_material == batch.material
+                && next_mesh.vertex_buffer().buffer() == mesh.vertex_buffer().buffer()
+                && next_mesh.index_buffer().buffer() == mesh.index_buffer().buffer()
+            {
+                continue;
+            }
+        }
+
+        commandbuffer.bind_vertexbuffers(0, &[&mesh.vertex_buffer()]);
+        commandbuffer.bind_indexbuffer(&mesh.index_buffer(), 0);
+
+        // Multi-pass effects draw the batches once for each pipeline of the pass
+        for (index, (pipeline, sets)) in pipelines.iter().enumerate() {
+            if bound != Some((batch.material, index)) {
+                bound = Some((batch.material, index));
+
+                commandbuffer.bind_pipeline(pipeline);
+                bind_group::bind_sets(commandbuffer, pipeline, sets);
+            }
+
+            match mesh.meshlets() {
+                Some(meshlets) if pipeline.uses_mesh_shading() => {
+                    draw_meshlets(commandbuffer, frame, pipeline, meshlets, batch)
+                }
+                _ if !indirect => commandbuffer.draw_indexed(
+                    mesh.index_count(),
+                    batch.range.len() as u32,
+                    0,
+                    0,
+                    batch.range.start as u32,
+                ),
+                _ => commandbuffer.draw_indexed_indirect(
+                    indirect_buffer,
+                    (first_batch + first_draw) as u64 * stride as u64,
+                    (i + 1 - first_draw) as u32,
+                    stride,
+                ),
+            }
+        }
+
+        first_draw = i + 1;
+    }
+}
+
+// Draws the objects of the batch with the bound mesh shading pipeline as one workgroup per
+// meshlet along x and per object along y. The index of the first instance is pushed as a
+// constant to all task and mesh stages, which all need to declare it.
+fn draw_meshlets(
+    commandbuffer: &CommandBuffer,
+    frame: &FrameData,
+    pipeline: &Pipeline,
+    meshlets: &Meshlets,
+    batch: &Batch,
+) {
+    let mesh_shader = match frame.context.mesh_shader() {
+        Some(mesh_shader) => mesh_shader,
+        None => return,
+    };
+
+    let first_instance = batch.range.start as u32;
+
+    commandbuffer.bind_descriptor_sets(pipeline, MESHLET_SET, &[meshlets.set()], &[]);
+    commandbuffer.push_constants(pipeline, pipeline.mesh_stages(), 0, &first_instance);
+    commandbuffer.draw_mesh_tasks(
+        mesh_shader,
+        meshlets.meshlet_count(),
+        batch.range.len() as u32,
+        1,
+    );
+}
+
+// Returns true if the material of the object samples `texture`
+fn samples_texture(object: &Object, resources: &ResourceManager, texture: Handle<Texture>) -> bool {
+    let material = resources.materials().raw(object.material).unwrap();
+    material.albedo() == texture
+}
+
+// Returns the mesh of the level of detail matching the screen coverage of the object
+fn select_lod(object: &Object, resources: &ResourceManager, camera: &Camera) -> Handle<Mesh> {
+    let lods = match object.lods {
+        Some(lods) => resources.lods().raw(lods).unwrap(),
+        None => return object.mesh,
+    };
+
+    // Use the bounds of the most detailed level for consistent selection across levels
+    let base = resources.meshes().raw(lods.base()).unwrap();
+    let coverage = camera.screen_coverage(object.position, object.bounding_radius(base));
+
+    lods.select(coverage)
+}
+
+// Groups consecutive objects with the same material and selected mesh into batches.
+// `first_instance` is the index of the first object in the instance buffer.
+fn create_batches(objects: &[(usize, &Object, Handle<Mesh>)], first_instance: usize) -> Vec<Batch> {
+    let mut batches: Vec<Batch> = Vec::new();
+
+    for (i, (_, object, mesh)) in objects.iter().enumerate() {
+        let i = first_instance + i;
+
+        match batches.last_mut() {
+            Some(batch) if batch.material == object.material && batch.mesh == *mesh => {
+                batch.range.end = i + 1
+            }
+            _ => batches.push(Batch {
+                material: object.material,
+                mesh: *mesh,
+                range: i..i + 1,
+            }),
+        }
+    }
+
+    batches
+}
+
+// Returns the descriptor sets used by `pipeline` along with their index, or None if the
+// pipeline uses a shadow, light, environment, fog, ray query or view set which is not
+// available. Sets which are not used are skipped. Mesh shading pipelines also require the mesh
+// to have meshlets, whose set is bound for each batch.
+fn pipeline_sets(
+    pipeline: &Pipeline,
+    material: &Material,
+    mesh: &Mesh,
+    frame: &FrameData,
+) -> Option<ArrayVec<[(u32, DescriptorSet); MAX_SETS]>> {
+    let object_set = if pipeline.uses_mesh_shading() {
+        mesh.meshlets()?;
+        frame.mesh_set?
+    } else {
+        frame.set
+    };
+
+    let mut sets = ArrayVec::new();
+    sets.push((MATERIAL_SET, material.set()));
+    sets.push((OBJECT_SET, object_set));
+
+    frame.frame_sets.extend_for(pipeline, &mut sets)?;
+
+    Some(sets)
 }
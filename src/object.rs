@@ -1,12 +1,46 @@
 use std::rc::Rc;
 
-use ultraviolet::Vec3;
+use ultraviolet::{Mat4, Rotor3, Vec3};
 
-use crate::{material::Material, mesh::Mesh, resources::Handle};
+use crate::{
+    aabb::Aabb, layers::Layers, lod::LodChain, material::Material, mesh::Mesh, resources::Handle,
+};
+
+/// The uniform scale applied to all objects
+pub const SCALE: f32 = 0.1;
 
 /// Represents an object that can be rendered.
 pub struct Object {
     pub material: Handle<Material>,
     pub mesh: Handle<Mesh>,
+    /// Levels of detail selected by screen coverage. `mesh` is used if None.
+    pub lods: Option<Handle<LodChain>>,
     pub position: Vec3,
+    pub rotation: Rotor3,
+    /// Scale applied on top of the uniform object scale
+    pub scale: Vec3,
+    /// The object rarely changes material or mesh. Draws of static objects are recorded once
+    /// and reused across frames when enabled in the mesh renderer.
+    pub is_static: bool,
+    /// The layers the object is placed in. See `Layers`.
+    pub layers: Layers,
+}
+
+impl Object {
+    /// Returns the model matrix transforming from object to world space.
+    pub fn model_matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.position)
+            * self.rotation.into_matrix().into_homogeneous()
+            * Mat4::from_nonuniform_scale(self.scale * SCALE)
+    }
+
+    /// Returns the world space bounding radius of the object rendered with mesh.
+    pub fn bounding_radius(&self, mesh: &Mesh) -> f32 {
+        mesh.bounding_radius() * self.scale.component_max().abs() * SCALE
+    }
+
+    /// Returns the world space bounding box of the object rendered with mesh.
+    pub fn aabb(&self, mesh: &Mesh) -> Aabb {
+        mesh.aabb().transformed(&self.model_matrix())
+    }
 }
@@ -0,0 +1,185 @@
+//! Passes executed by the master renderer each frame. The optional built-in passes, i.e; the
+//! sky, grid and gizmo, run through the same list as user defined passes, e.g; for custom
+//! shadow, post processing or UI passes, which extend the frame without modifying the master
+//! renderer itself.
+//!
+//! Passes are executed in the order they were added within their stage. Each pass records
+//! into the primary command buffer of the frame and is labeled with its name.
+use std::{any::Any, rc::Rc};
+
+use ash::vk;
+use ultraviolet::Vec3;
+
+use crate::resources::ResourceManager;
+use crate::{Camera, Scene, Viewport};
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::descriptors::{DescriptorAllocator, DescriptorLayoutCache};
+use vulkan::pipeline::{Pipeline, PipelineInfo, ShadingRateState};
+use vulkan::renderpass::RenderPass;
+use vulkan::{Extent, RenderingFormats, Texture, VulkanContext};
+
+/// When a pass is executed within a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PassStage {
+    /// After the shadows and before the render targets and the main pass, outside of any
+    /// renderpass, e.g; for custom shadow maps or compute work sampled by the main pass
+    PreMain,
+    /// Within the main pass after the objects of each view, e.g; for backgrounds, grids and
+    /// handles which depend on the depth of the view. Recorded once for each view, see
+    /// `PassFrame::view`. Pipelines need to be created through `PassTargets::create_pipeline`.
+    View,
+    /// Within the main pass after all views are drawn, e.g; for UI drawn over the scene.
+    /// Pipelines need to be created through `PassTargets::create_pipeline`.
+    Overlay,
+    /// After the main pass with the swapchain image in the present layout, outside of any
+    /// renderpass, e.g; for copying or post processing the presented image
+    PostMain,
+}
+
+/// The attachments of the main pass and the allocators of the master renderer, for creating
+/// the pipelines and descriptor sets of a pass
+pub struct PassTargets<'a> {
+    /// The size of the swapchain images
+    pub extent: Extent,
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+    /// The samples of the color and depth attachments of the main pass
+    pub samples: vk::SampleCountFlags,
+    pub frames_in_flight: usize,
+    pub descriptor_layout_cache: &'a mut DescriptorLayoutCache,
+    pub descriptor_allocator: &'a mut DescriptorAllocator,
+    context: &'a VulkanContext,
+    /// None when using dynamic rendering
+    renderpass: Option<&'a RenderPass>,
+    rendering_formats: &'a RenderingFormats,
+}
+
+impl<'a> PassTargets<'a> {
+    /// Describes the main pass rendered through `renderpass`, or through dynamic rendering to
+    /// attachments of `rendering_formats` when None
+    pub fn new(
+        context: &'a VulkanContext,
+        extent: Extent,
+        frames_in_flight: usize,
+        descriptor_layout_cache: &'a mut DescriptorLayoutCache,
+        descriptor_allocator: &'a mut DescriptorAllocator,
+        renderpass: Option<&'a RenderPass>,
+        rendering_formats: &'a RenderingFormats,
+    ) -> Self {
+        Self {
+            extent,
+            color_format: rendering_formats.color_formats[0],
+            depth_format: rendering_formats.depth_format,
+            samples: context.msaa_samples(),
+            frames_in_flight,
+            descriptor_layout_cache,
+            descriptor_allocator,
+            context,
+            renderpass,
+            rendering_formats,
+        }
+    }
+
+    /// Creates a graphics pipeline compatible with the main pass.
+    /// Uses the renderpass or the dynamic rendering formats depending on the rendering path.
+    /// The line width and point size are clamped to the ranges supported by the device, and
+    /// tessellation or geometry shaders fail without device support. Pipelines without a
+    /// shading rate use the rate of the shading rate image when there is one.
+    pub fn create_pipeline(&mut self, info: PipelineInfo) -> Result<Pipeline, vulkan::Error> {
+        let info = match (
+            self.rendering_formats.shading_rate_attachment,
+            info.shading_rate,
+        ) {
+            (true, None) => PipelineInfo {
+                shading_rate: Some(ShadingRateState::default()),
+                ..info
+            },
+            _ => info,
+        };
+
+        let info = info.supported(self.context.capabilities())?;
+
+        match self.renderpass {
+            Some(renderpass) => Pipeline::new(
+                self.context.device_ref(),
+                self.descriptor_layout_cache,
+                renderpass,
+                info,
+            ),
+            None => Pipeline::new_dynamic(
+                self.context.device_ref(),
+                self.descriptor_layout_cache,
+                self.rendering_formats,
+                info,
+            ),
+        }
+    }
+}
+
+/// The frame being recorded
+pub struct PassFrame<'a> {
+    /// The index of the frame in flight, e.g; for per frame buffers
+    pub frame_index: usize,
+    /// The frame time in seconds
+    pub dt: f32,
+    /// The cameras and viewports drawn in the main pass
+    pub views: &'a [(Camera, Viewport)],
+    pub resources: &'a ResourceManager,
+    pub extent: Extent,
+    /// The swapchain image presented at the end of the frame
+    pub swapchain_image: &'a Texture,
+    /// Set in the `View` and `Overlay` stages when the main pass is recorded into secondary
+    /// command buffers, which draws within the pass then need to be recorded into as well
+    pub inheritance: Option<&'a Inheritance>,
+    /// The index into `views` of the view being drawn in the `View` stage
+    pub view: Option<usize>,
+    /// The direction the shadow casting directional light shines in while shadows are enabled
+    pub sun_direction: Option<Vec3>,
+}
+
+impl<'a> PassFrame<'a> {
+    /// Returns the index, camera and region of the view being drawn in the `View` stage. The
+    /// aspect of the camera follows the viewport.
+    pub fn current_view(&self) -> Option<(usize, Camera, vk::Rect2D)> {
+        let index = self.view?;
+        let (camera, viewport) = &self.views[index];
+
+        Some((
+            index,
+            camera.with_viewport(viewport, self.extent),
+            viewport.rect(self.extent),
+        ))
+    }
+}
+
+/// A pass executed by the master renderer each frame. See `MasterRenderer::add_pass`.
+pub trait Pass: Any {
+    /// Returns the name of the pass, which labels its commands and identifies it for removal
+    fn name(&self) -> &str;
+
+    fn stage(&self) -> PassStage;
+
+    /// Creates the resources of the pass. Called when the pass is added, and again when the
+    /// main pass changes formats, which makes previously created pipelines incompatible.
+    fn setup(
+        &mut self,
+        context: &Rc<VulkanContext>,
+        targets: &mut PassTargets,
+    ) -> Result<(), vulkan::Error>;
+
+    /// Records the commands of the pass for a frame
+    fn record(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        frame: &PassFrame,
+        scene: &Scene,
+    ) -> Result<(), vulkan::Error>;
+
+    /// Called after the swapchain is recreated with a new extent and the same formats, e.g; for
+    /// recreating attachments sized to the swapchain. Does nothing by default.
+    fn on_resize(&mut self, _targets: &mut PassTargets) -> Result<(), vulkan::Error> {
+        Ok(())
+    }
+}
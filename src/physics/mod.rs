@@ -0,0 +1,219 @@
+//! Physics integration through a `PhysicsProvider`, which simulates the entities of the scene
+//! world at a fixed rate. Entities with a `Collider` and a `Transform` are simulated, and the
+//! provider writes the transforms of moving bodies back into the world, which the scene mirrors
+//! into its objects before rendering. See `Scene::sync_world`.
+//!
+//! A provider backed by rapier is available with the `rapier3d` feature.
+use std::time::Duration;
+
+use ultraviolet::Vec3;
+
+use crate::resources::{Handle, ResourceCache};
+use crate::{Mesh, Scene};
+
+#[cfg(feature = "rapier3d")]
+mod rapier;
+#[cfg(feature = "rapier3d")]
+pub use rapier::*;
+
+/// The shape of a collider in object space. Shapes are scaled along with the object by the
+/// scale of the transform and the uniform object scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColliderShape {
+    /// A box given by its half extents
+    Box(Vec3),
+    /// A sphere given by its radius. Non uniform scales use the largest axis.
+    Sphere(f32),
+    /// The triangles of a mesh imported with `MeshImportSettings::keep_geometry`. Triangle
+    /// meshes have no volume and are best used for fixed level geometry.
+    Mesh(Handle<Mesh>),
+}
+
+/// Describes the collision shape and surface of an entity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Collider {
+    pub shape: ColliderShape,
+    pub friction: f32,
+    pub restitution: f32,
+    /// The mass per unit volume of dynamic bodies
+    pub density: f32,
+}
+
+impl Collider {
+    pub fn new(shape: ColliderShape) -> Self {
+        Self {
+            shape,
+            friction: 0.5,
+            restitution: 0.0,
+            density: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyKind {
+    /// Moved by the simulation
+    Dynamic,
+    /// Moved by the application through the transform, and pushes dynamic bodies away
+    Kinematic,
+    /// Never moves after creation
+    Fixed,
+}
+
+/// The simulated body of an entity with a `Collider`. Entities with a collider but without a
+/// body are fixed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RigidBody {
+    pub kind: BodyKind,
+    /// The velocity in world units per second. Used as the initial velocity, and updated with
+    /// the simulated velocity of dynamic bodies after each step.
+    pub linear_velocity: Vec3,
+    /// The angular velocity in radians per second around each axis
+    pub angular_velocity: Vec3,
+}
+
+impl Default for RigidBody {
+    fn default() -> Self {
+        Self {
+            kind: BodyKind::Dynamic,
+            linear_velocity: Vec3::zero(),
+            angular_velocity: Vec3::zero(),
+        }
+    }
+}
+
+/// Simulates the colliders of the scene world
+pub trait PhysicsProvider {
+    /// Advances the simulation by `dt` seconds.
+    /// Bodies are to be created for new entities with a `Collider` and `Transform`, and removed
+    /// for despawned entities or entities which lost either component. The transforms of
+    /// kinematic bodies are read from the world, and the transforms and velocities of dynamic
+    /// bodies are written back.
+    fn step(&mut self, dt: f32, scene: &mut Scene, meshes: &ResourceCache<Mesh>);
+}
+
+/// Accumulates the frame time and divides it into steps of a fixed length, which keeps the
+/// simulation independent of the frame rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedTimestep {
+    step: Duration,
+    max_steps: u32,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    pub fn new(step: Duration, max_steps: u32) -> Self {
+        Self {
+            step,
+            max_steps,
+            accumulator: Duration::default(),
+        }
+    }
+
+    /// Adds the frame time `dt` and returns the number of steps to take. Time exceeding
+    /// `max_steps` is discarded, which slows the simulation down rather than falling further
+    /// behind on slow frames.
+    pub fn advance(&mut self, dt: Duration) -> u32 {
+        self.accumulator += dt;
+
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < self.max_steps {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+
+        if steps == self.max_steps {
+            self.accumulator = self.accumulator.min(self.step);
+        }
+
+        steps
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// Returns how far the accumulated time is into the next step, in [0, 1). Useful to
+    /// interpolate between the two most recent steps.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+/// Specifies the rate of the simulation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhysicsInfo {
+    /// The length of each simulation step
+    pub timestep: Duration,
+    /// The maximum number of steps per update
+    pub max_steps: u32,
+    /// The acceleration of dynamic bodies due to gravity, used by the default provider
+    pub gravity: Vec3,
+}
+
+impl Default for PhysicsInfo {
+    fn default() -> Self {
+        Self {
+            timestep: Duration::from_secs(1) / 60,
+            max_steps: 8,
+            gravity: Vec3::new(0.0, -9.81, 0.0),
+        }
+    }
+}
+
+/// Steps a physics provider from the frame loop
+pub struct Physics {
+    provider: Option<Box<dyn PhysicsProvider>>,
+    timestep: FixedTimestep,
+}
+
+impl Physics {
+    /// Creates the physics with the default provider. Without the `rapier3d` feature there is
+    /// no default provider, and updating does nothing.
+    pub fn new(info: PhysicsInfo) -> Self {
+        #[cfg(feature = "rapier3d")]
+        let provider: Option<Box<dyn PhysicsProvider>> =
+            Some(Box::new(RapierProvider::new(info.gravity)));
+        #[cfg(not(feature = "rapier3d"))]
+        let provider = None;
+
+        Self {
+            provider,
+            timestep: FixedTimestep::new(info.timestep, info.max_steps),
+        }
+    }
+
+    /// Creates the physics with an application defined provider
+    pub fn with_provider(info: PhysicsInfo, provider: Box<dyn PhysicsProvider>) -> Self {
+        Self {
+            provider: Some(provider),
+            timestep: FixedTimestep::new(info.timestep, info.max_steps),
+        }
+    }
+
+    pub fn has_provider(&self) -> bool {
+        self.provider.is_some()
+    }
+
+    pub fn timestep(&self) -> &FixedTimestep {
+        &self.timestep
+    }
+
+    /// Advances the simulation by the frame time `dt` in fixed steps. Should be called once per
+    /// frame before drawing. Returns the number of steps taken.
+    pub fn update(&mut self, dt: Duration, scene: &mut Scene, meshes: &ResourceCache<Mesh>) -> u32 {
+        let provider = match &mut self.provider {
+            Some(provider) => provider,
+            None => return 0,
+        };
+
+        let steps = self.timestep.advance(dt);
+        let step = self.timestep.step().as_secs_f32();
+
+        for _ in 0..steps {
+            provider.step(step, scene, meshes);
+        }
+
+        steps
+    }
+}
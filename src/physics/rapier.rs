@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use rapier3d::na::{Isometry3, Point3, Quaternion, Translation3, UnitQuaternion, Vector3};
+use rapier3d::prelude::*;
+use ultraviolet::{Rotor3, Vec3};
+
+use super::{BodyKind, Collider, ColliderShape, PhysicsProvider, RigidBody};
+use crate::document::Transform;
+use crate::ecs::Entity;
+use crate::object::SCALE;
+use crate::resources::ResourceCache;
+use crate::{Mesh, Scene};
+
+/// Simulates the colliders of the scene world with rapier
+pub struct RapierProvider {
+    gravity: Vector3<f32>,
+    parameters: IntegrationParameters,
+    pipeline: PhysicsPipeline,
+    islands: IslandManager,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    /// The body of each simulated entity. None if the collider could not be created, e.g; a
+    /// mesh without geometry.
+    entities: HashMap<Entity, Option<RigidBodyHandle>>,
+}
+
+impl RapierProvider {
+    pub fn new(gravity: Vec3) -> Self {
+        Self {
+            gravity: to_vector(gravity),
+            parameters: IntegrationParameters::default(),
+            pipeline: PhysicsPipeline::new(),
+            islands: IslandManager::new(),
+            broad_phase: BroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            bodies: RigidBodySet::new(),
+            colliders: ColliderSet::new(),
+            impulse_joints: ImpulseJointSet::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+            entities: HashMap::new(),
+        }
+    }
+
+    /// Returns the rapier body of `entity`
+    pub fn body(&self, entity: Entity) -> Option<&rapier3d::dynamics::RigidBody> {
+        self.bodies.get((*self.entities.get(&entity)?)?)
+    }
+
+    fn remove_stale(&mut self, scene: &Scene) {
+        let world = scene.world();
+
+        let stale = self
+            .entities
+            .keys()
+            .filter(|entity| !world.has::<Collider>(**entity) || !world.has::<Transform>(**entity))
+            .copied()
+            .collect::<Vec<_>>();
+
+        for entity in stale {
+            if let Some(Some(handle)) = self.entities.remove(&entity) {
+                self.bodies.remove(
+                    handle,
+                    &mut self.islands,
+                    &mut self.colliders,
+                    &mut self.impulse_joints,
+                    &mut self.multibody_joints,
+                    true,
+                );
+            }
+        }
+    }
+
+    fn insert_new(&mut self, scene: &Scene, meshes: &ResourceCache<Mesh>) {
+        let world = scene.world();
+
+        for (entity, collider, transform) in world.query2::<Collider, Transform>() {
+            if self.entities.contains_key(&entity) {
+                continue;
+            }
+
+            let body = world
+                .get::<RigidBody>(entity)
+                .copied()
+                .unwrap_or(RigidBody {
+                    kind: BodyKind::Fixed,
+                    ..Default::default()
+                });
+
+            let handle = match collider_builder(collider, transform.scale * SCALE, meshes) {
+                Some(builder) => {
+                    let handle = self.bodies.insert(body_builder(&body, transform));
+                    self.colliders
+                        .insert_with_parent(builder, handle, &mut self.bodies);
+                    Some(handle)
+                }
+                None => {
+                    log::warn!("Mesh collider of {:?} has no geometry", entity);
+                    None
+                }
+            };
+
+            self.entities.insert(entity, handle);
+        }
+    }
+}
+
+impl PhysicsProvider for RapierProvider {
+    fn step(&mut self, dt: f32, scene: &mut Scene, meshes: &ResourceCache<Mesh>) {
+        self.remove_stale(scene);
+        self.insert_new(scene, meshes);
+
+        let world = scene.world();
+        let bodies = &mut self.bodies;
+
+        for (entity, handle) in &self.entities {
+            let body = match handle.and_then(|handle| bodies.get_mut(handle)) {
+                Some(body) if body.is_kinematic() => body,
+                _ => continue,
+            };
+
+            if let Some(transform) = world.get::<Transform>(*entity) {
+                body.set_next_kinematic_position(to_isometry(transform));
+            }
+        }
+
+        self.parameters.dt = dt;
+
+        self.pipeline.step(
+            &self.gravity,
+            &self.parameters,
+            &mut self.islands,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.bodies,
+            &mut self.colliders,
+            &mut self.impulse_joints,
+            &mut self.multibody_joints,
+            &mut self.ccd_solver,
+            None,
+            &(),
+            &(),
+        );
+
+        // Only moving bodies are written back, as any mutable access marks the world as changed
+        let world = scene.world_mut();
+
+        for (entity, handle) in &self.entities {
+            let body = match handle.and_then(|handle| self.bodies.get(handle)) {
+                Some(body) if body.is_dynamic() && !body.is_sleeping() => body,
+                _ => continue,
+            };
+
+            if let Some(transform) = world.get_mut::<Transform>(*entity) {
+                let translation = body.translation();
+                let rotation = body.rotation();
+
+                transform.position = Vec3::new(translation.x, translation.y, translation.z);
+                transform.rotation =
+                    Rotor3::from_quaternion_array([rotation.i, rotation.j, rotation.k, rotation.w]);
+            }
+
+            if let Some(rigid_body) = world.get_mut::<RigidBody>(*entity) {
+                rigid_body.linear_velocity = from_vector(body.linvel());
+                rigid_body.angular_velocity = from_vector(body.angvel());
+            }
+        }
+    }
+}
+
+fn body_builder(body: &RigidBody, transform: &Transform) -> RigidBodyBuilder {
+    let builder = match body.kind {
+        BodyKind::Dynamic => RigidBodyBuilder::dynamic(),
+        BodyKind::Kinematic => RigidBodyBuilder::kinematic_position_based(),
+        BodyKind::Fixed => RigidBodyBuilder::fixed(),
+    };
+
+    builder
+        .position(to_isometry(transform))
+        .linvel(to_vector(body.linear_velocity))
+        .angvel(to_vector(body.angular_velocity))
+}
+
+// Returns the collider scaled by `scale`, or None if the mesh has no geometry
+fn collider_builder(
+    collider: &Collider,
+    scale: Vec3,
+    meshes: &ResourceCache<Mesh>,
+) -> Option<ColliderBuilder> {
+    let builder = match collider.shape {
+        ColliderShape::Box(half_extents) => {
+            let half_extents = half_extents * scale.abs();
+            ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+        }
+        ColliderShape::Sphere(radius) => {
+            ColliderBuilder::ball(radius * scale.abs().component_max())
+        }
+        ColliderShape::Mesh(mesh) => {
+            let geometry = meshes.raw(mesh).ok()?.geometry()?;
+
+            let vertices = geometry
+                .positions()
+                .iter()
+                .map(|position| {
+                    let position = *position * scale;
+                    Point3::new(position.x, position.y, position.z)
+                })
+                .collect();
+
+            let indices = geometry
+                .indices()
+                .chunks_exact(3)
+                .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                .collect();
+
+            ColliderBuilder::trimesh(vertices, indices)
+        }
+    };
+
+    Some(
+        builder
+            .friction(collider.friction)
+            .restitution(collider.restitution)
+            .density(collider.density),
+    )
+}
+
+fn to_isometry(transform: &Transform) -> Isometry3<f32> {
+    let position = transform.position;
+    let [x, y, z, w] = transform.rotation.into_quaternion_array();
+
+    Isometry3::from_parts(
+        Translation3::new(position.x, position.y, position.z),
+        UnitQuaternion::from_quaternion(Quaternion::new(w, x, y, z)),
+    )
+}
+
+fn to_vector(vector: Vec3) -> Vector3<f32> {
+    Vector3::new(vector.x, vector.y, vector.z)
+}
+
+fn from_vector(vector: &Vector3<f32>) -> Vec3 {
+    Vec3::new(vector.x, vector.y, vector.z)
+}
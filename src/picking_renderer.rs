@@ -0,0 +1,330 @@
+use std::{mem, rc::Rc};
+use ultraviolet::*;
+
+use ash::vk;
+use vk::DescriptorSet;
+
+use crate::resources::*;
+use crate::{vulkan::descriptors::DescriptorBuilder, Camera, ObjectHandle, Scene};
+
+use super::vulkan;
+use vulkan::descriptors::*;
+use vulkan::pipeline::*;
+use vulkan::renderpass::*;
+use vulkan::texture::*;
+use vulkan::*;
+
+use crate::mesh;
+use crate::mesh_renderer::{GrowthPolicy, DEFAULT_OBJECT_CAPACITY};
+
+/// The format of the object id attachment. Each pixel holds the index of the object + 1, zero
+/// is used where no object was drawn.
+pub const ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+#[derive(Default)]
+#[repr(C)]
+struct ObjectData {
+    model: Mat4,
+}
+
+/// Renders object ids into an offscreen attachment and reads back single pixels to determine
+/// which object is under a point on the screen.
+pub struct PickingRenderer {
+    context: Rc<VulkanContext>,
+    pipeline: Pipeline,
+    framebuffer: Framebuffer,
+    renderpass: RenderPass,
+    id_attachment: Texture,
+    _depth_attachment: Texture,
+    set: DescriptorSet,
+    object_buffer: Buffer,
+    /// The number of objects the object buffer fits
+    object_capacity: usize,
+    readback_buffer: Buffer,
+}
+
+impl PickingRenderer {
+    pub fn new(
+        context: Rc<VulkanContext>,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        descriptor_allocator: &mut DescriptorAllocator,
+        extent: Extent,
+    ) -> Result<Self, vulkan::Error> {
+        let id_attachment = Texture::new(
+            context.clone(),
+            TextureInfo {
+                extent,
+                mip_levels: 1,
+                usage: TextureUsage::ReadbackAttachment,
+                format: ID_FORMAT,
+                ..Default::default()
+            },
+        )?;
+
+        let depth_attachment = Texture::new(
+            context.clone(),
+            TextureInfo {
+                extent,
+                mip_levels: 1,
+                usage: TextureUsage::DepthAttachment,
+                format: Format::D32_SFLOAT,
+                ..Default::default()
+            },
+        )?;
+
+        let renderpass = RenderPass::new(
+            context.device_ref(),
+            &RenderPassInfo {
+                attachments: &[
+                    AttachmentInfo::from_texture(
+                        &id_attachment,
+                        LoadOp::CLEAR,
+                        StoreOp::STORE,
+                        ImageLayout::UNDEFINED,
+                        ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ),
+                    AttachmentInfo::from_texture(
+                        &depth_attachment,
+                        LoadOp::CLEAR,
+                        StoreOp::DONT_CARE,
+                        ImageLayout::UNDEFINED,
+                        ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    ),
+                ],
+                subpasses: &[SubpassInfo {
+                    color_attachments: &[AttachmentReference {
+                        attachment: 0,
+                        layout: ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    }],
+                    resolve_attachments: &[],
+                    depth_attachment: Some(AttachmentReference {
+                        attachment: 1,
+                        layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    }),
+                }],
+                view_mask: 0,
+            },
+        )?;
+
+        let framebuffer = Framebuffer::new(
+            context.device_ref(),
+            &renderpass,
+            &[&id_attachment, &depth_attachment],
+            extent,
+        )?;
+
+        let pipeline = Pipeline::new(
+            context.device_ref(),
+            descriptor_layout_cache,
+            &renderpass,
+            PipelineInfo {
+                vertexshader: "./data/shaders/picking.vert.spv".into(),
+                fragmentshader: "./data/shaders/picking.frag.spv".into(),
+                vertex_binding: mesh::Vertex::binding_description(),
+                vertex_attributes: mesh::Vertex::attribute_descriptions(),
+                ..Default::default()
+            },
+        )?;
+
+        let object_capacity = DEFAULT_OBJECT_CAPACITY;
+        let object_buffer = create_object_buffer(context.clone(), object_capacity)?;
+
+        let readback_buffer = Buffer::new_uninit(
+            context.clone(),
+            BufferType::Readback,
+            BufferUsage::Mapped,
+            mem::size_of::<u32>() as u64,
+        )?;
+
+        let mut set = Default::default();
+
+        DescriptorBuilder::new()
+            .bind_storage_buffer(0, vk::ShaderStageFlags::VERTEX, &object_buffer)
+            .build(
+                context.device(),
+                descriptor_layout_cache,
+                descriptor_allocator,
+                &mut set,
+            )?;
+
+        Ok(Self {
+            context,
+            pipeline,
+            framebuffer,
+            renderpass,
+            id_attachment,
+            _depth_attachment: depth_attachment,
+            set,
+            object_buffer,
+            object_capacity,
+            readback_buffer,
+        })
+    }
+
+    /// Renders the object ids of the scene objects in the visible layers of `camera` and returns
+    /// the handle of the object at pixel `x`, `y`, or None if no object covers the pixel.
+    /// Waits for the graphics queue to idle.
+    pub fn pick(
+        &mut self,
+        x: u32,
+        y: u32,
+        resources: &ResourceManager,
+        camera: &Camera,
+        scene: &Scene,
+    ) -> Result<Option<ObjectHandle>, vulkan::Error> {
+        let extent = self.id_attachment.extent();
+        if x >= extent.width || y >= extent.height {
+            return Ok(None);
+        }
+
+        let object_count = scene.objects().len();
+
+        // The previous pick has completed, so the buffer can be replaced
+        if object_count > self.object_capacity {
+            self.object_capacity = GrowthPolicy::default().grow(self.object_capacity, object_count);
+            self.object_buffer = create_object_buffer(self.context.clone(), self.object_capacity)?;
+
+            DescriptorBuilder::new()
+                .bind_storage_buffer(0, vk::ShaderStageFlags::VERTEX, &self.object_buffer)
+                .update(self.context.device(), self.set);
+        }
+
+        let view_projection = camera.projection() * camera.calculate_view();
+
+        self.object_buffer
+            .write_slice(object_count as u64, 0, |mut writer| {
+                let objects = scene.objects().iter().take(object_count);
+
+                writer.write_iter(
+                    0,
+                    objects.map(|object| ObjectData {
+                        model: object.model_matrix(),
+                    }),
+                );
+            })?;
+
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D {
+                x: x as i32,
+                y: y as i32,
+                z: 0,
+            },
+            image_extent: vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            },
+        };
+
+        self.context.transfer_pool().single_time_command(
+            self.context.graphics_queue(),
+            |commandbuffer| {
+                commandbuffer.begin_renderpass(
+                    &self.renderpass,
+                    &self.framebuffer,
+                    extent,
+                    &[
+                        vk::ClearValue {
+                            color: vk::ClearColorValue { uint32: [0; 4] },
+                        },
+                        vk::ClearValue {
+                            depth_stencil: vk::ClearDepthStencilValue {
+                                depth: 1.0,
+                                stencil: 0,
+                            },
+                        },
+                    ],
+                    vk::SubpassContents::INLINE,
+                );
+
+                commandbuffer.set_viewport(extent);
+                commandbuffer.bind_pipeline(&self.pipeline);
+                commandbuffer.bind_descriptor_sets(&self.pipeline, 0, &[self.set], &[]);
+                commandbuffer.push_constants(
+                    &self.pipeline,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    &view_projection,
+                );
+
+                for (i, object) in scene.objects().iter().take(object_count).enumerate() {
+                    if !scene.is_drawn(i, camera.layers) {
+                        continue;
+                    }
+
+                    let mesh = resources.meshes().raw(object.mesh).unwrap();
+
+                    commandbuffer.bind_vertexbuffers(0, &[&mesh.vertex_buffer()]);
+                    commandbuffer.bind_indexbuffer(&mesh.index_buffer(), 0);
+                    commandbuffer.draw_indexed(mesh.index_count(), 1, 0, 0, i as u32);
+                }
+
+                commandbuffer.end_renderpass();
+
+                self.id_attachment
+                    .set_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+                self.id_attachment
+                    .transition(commandbuffer, ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+                commandbuffer.copy_image_buffer(
+                    self.id_attachment.image(),
+                    ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    self.readback_buffer.buffer(),
+                    &[region],
+                );
+
+                // Make the copied id visible to the host
+                commandbuffer.buffer_barrier(
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::HOST,
+                    &[vk::BufferMemoryBarrier {
+                        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                        dst_access_mask: vk::AccessFlags::HOST_READ,
+                        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        buffer: self.readback_buffer.buffer(),
+                        offset: 0,
+                        size: vk::WHOLE_SIZE,
+                        ..Default::default()
+                    }],
+                );
+            },
+        )?;
+
+        let id = self
+            .readback_buffer
+            .read_slice(1, 0, |ids: &[u32]| ids[0])?;
+
+        // Zero is reserved for no object
+        Ok(match id {
+            0 => None,
+            id => scene.handle(id as usize - 1),
+        })
+    }
+
+    /// Returns the extent of the id attachment.
+    pub fn extent(&self) -> Extent {
+        self.id_attachment.extent()
+    }
+}
+
+fn create_object_buffer(
+    context: Rc<VulkanContext>,
+    capacity: usize,
+) -> Result<Buffer, vulkan::Error> {
+    Buffer::new_uninit(
+        context,
+        BufferType::Storage,
+        BufferUsage::MappedPersistent,
+        mem::size_of::<ObjectData>() as u64 * capacity as u64,
+    )
+}
@@ -0,0 +1,603 @@
+//! Omnidirectional shadows for point lights. The scene is rendered from the light into each face
+//! of a cube map, storing the distance to the light rather than the projected depth, which is
+//! compared against the distance of each fragment when lighting.
+use arrayvec::ArrayVec;
+use ash::vk;
+use std::{f32::consts::FRAC_PI_2, mem, rc::Rc};
+use ultraviolet::{Vec3, Vec4};
+
+use crate::mesh_renderer::{MeshRenderer, MeshRendererInfo};
+use crate::resources::*;
+use crate::shadow::{create_renderpass, rendering_formats, update_uniform};
+use crate::{Camera, Error, Layers, PassTag, Scene};
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::descriptors::*;
+use vulkan::dynamic_rendering::DynamicRendering;
+use vulkan::renderpass::*;
+use vulkan::sampler::*;
+use vulkan::texture::*;
+use vulkan::*;
+
+/// The maximum number of point lights
+pub const MAX_POINT_LIGHTS: usize = 4;
+
+/// The descriptor set index of the point light set in material effect shaders. Holds the light
+/// uniform at binding 0 and the cube map of each light at the following bindings. See
+/// `data/shaders/point_shadow.glsl`.
+pub const POINT_SHADOW_SET: u32 = 3;
+
+/// The world direction and up direction the camera of each cube face is rendered with, in layer
+/// order. Cube maps are sampled with the z axis mirrored, which matches the faces rendered by
+/// right handed cameras to the left handed layout of cube maps.
+const CUBE_FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+    (Vec3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+    (Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)),
+    (Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 0.0, -1.0)),
+    (Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0)),
+    (Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0)),
+];
+
+/// Specifies how a point light renders its shadows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLightInfo {
+    pub position: Vec3,
+    /// The distance at which the light reaches zero intensity. Shadows are only cast within
+    /// the range.
+    pub range: f32,
+    /// Renders the shadows of the light each frame. Lights without shadows light the scene
+    /// unoccluded.
+    pub cast_shadows: bool,
+    /// The layers of the objects casting shadows
+    pub caster_layers: Layers,
+    /// The width and height of each cube face
+    pub resolution: u32,
+    /// Object capacity and growth of the mesh renderer of each cube face
+    pub mesh_renderer: MeshRendererInfo,
+}
+
+impl Default for PointLightInfo {
+    fn default() -> Self {
+        Self {
+            position: Vec3::zero(),
+            range: 25.0,
+            cast_shadows: true,
+            caster_layers: Layers::ALL,
+            resolution: 512,
+            mesh_renderer: MeshRendererInfo::default(),
+        }
+    }
+}
+
+/// The light uniform read by the lighting shaders. Matches the std140 layout of the
+/// `PointLightData` block in `data/shaders/point_shadow.glsl`.
+#[derive(Default)]
+#[repr(C)]
+struct PointLightData {
+    /// The position in xyz and range in w of each light
+    lights: [Vec4; MAX_POINT_LIGHTS],
+    /// Non zero if the light at the same index casts shadows
+    cast_shadows: [u32; MAX_POINT_LIGHTS],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+/// Returns the cameras rendering each face of a cube map centered at `position`, in layer
+/// order.
+pub fn cube_cameras(position: Vec3, range: f32) -> [Camera; 6] {
+    let face = |(direction, up): (Vec3, Vec3)| {
+        let mut camera = Camera::perspective(position, FRAC_PI_2, 1.0, 0.05, range);
+        camera.auto_aspect = false;
+        camera.look_in(direction, up);
+        camera
+    };
+
+    [
+        face(CUBE_FACES[0]),
+        face(CUBE_FACES[1]),
+        face(CUBE_FACES[2]),
+        face(CUBE_FACES[3]),
+        face(CUBE_FACES[4]),
+        face(CUBE_FACES[5]),
+    ]
+}
+
+/// A point light rendering the distance to the surrounding scene into a cube map.
+pub struct PointLight {
+    pub position: Vec3,
+    /// See `PointLightInfo::range`
+    pub range: f32,
+    /// See `PointLightInfo::cast_shadows`
+    pub cast_shadows: bool,
+    /// See `PointLightInfo::caster_layers`
+    pub caster_layers: Layers,
+    context: Rc<VulkanContext>,
+    info: PointLightInfo,
+    /// The position and range read when rendering the faces, through a set bound in place of
+    /// the shadow set. See `data/shaders/point_shadow.frag`.
+    light_buffer: Buffer,
+    mesh_renderers: Vec<MeshRenderer>,
+    /// One for each face. Empty when using dynamic rendering
+    framebuffers: Vec<Framebuffer>,
+    face_views: Vec<TextureView>,
+    texture: Texture,
+}
+
+impl PointLight {
+    fn new(
+        context: Rc<VulkanContext>,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        descriptor_allocator: &mut DescriptorAllocator,
+        frames_in_flight: usize,
+        renderpass: Option<&RenderPass>,
+        format: vk::Format,
+        info: PointLightInfo,
+    ) -> Result<Self, vulkan::Error> {
+        let extent: Extent = (info.resolution, info.resolution).into();
+
+        let texture = create_cube(context.clone(), extent, format)?;
+
+        let face_views = (0..CUBE_FACES.len() as u32)
+            .map(|layer| texture.layer_view(layer))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let light_buffer = Buffer::new_uninit(
+            context.clone(),
+            BufferType::Uniform,
+            BufferUsage::Staged,
+            mem::size_of::<Vec4>() as u64,
+        )?;
+
+        let mut light_set = Default::default();
+
+        DescriptorBuilder::new()
+            .bind_uniform_buffer(0, vk::ShaderStageFlags::FRAGMENT, &light_buffer)
+            .build(
+                context.device(),
+                descriptor_layout_cache,
+                descriptor_allocator,
+                &mut light_set,
+            )?;
+
+        let framebuffers = match renderpass {
+            Some(renderpass) => face_views
+                .iter()
+                .map(|view| Framebuffer::new(context.device_ref(), renderpass, &[view], extent))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+
+        let inheritance = match renderpass {
+            Some(renderpass) => Inheritance::RenderPass {
+                renderpass: renderpass.renderpass(),
+                subpass: 0,
+            },
+            None => Inheritance::Dynamic {
+                formats: rendering_formats(format),
+                samples: vk::SampleCountFlags::TYPE_1,
+            },
+        };
+
+        let mesh_renderers = CUBE_FACES
+            .iter()
+            .map(|_| {
+                let mut mesh_renderer = MeshRenderer::new(
+                    context.clone(),
+                    descriptor_layout_cache,
+                    descriptor_allocator,
+                    frames_in_flight,
+                    info.mesh_renderer,
+                )?;
+
+                mesh_renderer.set_inheritance(inheritance.clone());
+                mesh_renderer.set_extent(extent);
+                mesh_renderer.set_shadow_set(Some(light_set));
+                Ok(mesh_renderer)
+            })
+            .collect::<Result<Vec<_>, vulkan::Error>>()?;
+
+        Ok(Self {
+            position: info.position,
+            range: info.range,
+            cast_shadows: info.cast_shadows,
+            caster_layers: info.caster_layers,
+            context,
+            info,
+            light_buffer,
+            mesh_renderers,
+            framebuffers,
+            face_views,
+            texture,
+        })
+    }
+
+    /// Returns the cube map holding the distance to the light.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Returns the width and height of each cube face.
+    pub fn resolution(&self) -> u32 {
+        self.texture.extent().width
+    }
+
+    // Records the drawing of each cube face. The cube map is transitioned to
+    // SHADER_READ_ONLY_OPTIMAL for sampling by the passes recorded afterwards.
+    fn draw(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        renderpass: Option<&RenderPass>,
+        resources: &ResourceManager,
+        frame_index: usize,
+        scene: &Scene,
+    ) -> Result<(), vulkan::Error> {
+        let position = self.position;
+        update_uniform(
+            commandbuffer,
+            &self.light_buffer,
+            &Vec4::new(position.x, position.y, position.z, self.range),
+        );
+
+        // Waits for the previous frame to finish sampling the cube map
+        self.texture
+            .transition(commandbuffer, ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let mut cameras = cube_cameras(self.position, self.range);
+        for camera in &mut cameras {
+            camera.layers = self.caster_layers;
+        }
+
+        for (i, camera) in cameras.iter().enumerate() {
+            let contents = if self.mesh_renderers[i].uses_secondary() {
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+            } else {
+                vk::SubpassContents::INLINE
+            };
+
+            self.begin_face(commandbuffer, renderpass, i, contents);
+
+            self.mesh_renderers[i].draw(
+                commandbuffer,
+                resources,
+                camera,
+                frame_index,
+                scene,
+                &[PassTag::PointShadow],
+            )?;
+
+            match renderpass {
+                Some(_) => commandbuffer.end_renderpass(),
+                None => commandbuffer.end_rendering(self.dynamic_rendering()),
+            }
+        }
+
+        self.texture
+            .transition(commandbuffer, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        Ok(())
+    }
+
+    fn dynamic_rendering(&self) -> &DynamicRendering {
+        self.context
+            .dynamic_rendering()
+            .expect("Dynamic rendering is not supported")
+    }
+
+    // Begins the renderpass or dynamic rendering into the layer of `face`. The cube is cleared
+    // to the range of the light.
+    fn begin_face(
+        &self,
+        commandbuffer: &CommandBuffer,
+        renderpass: Option<&RenderPass>,
+        face: usize,
+        contents: vk::SubpassContents,
+    ) {
+        let extent = self.texture.extent();
+
+        let clear_value = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        };
+
+        match renderpass {
+            Some(renderpass) => commandbuffer.begin_renderpass(
+                renderpass,
+                &self.framebuffers[face],
+                extent,
+                &[clear_value],
+                contents,
+            ),
+            None => commandbuffer.begin_rendering(
+                self.dynamic_rendering(),
+                extent,
+                &RenderingAttachments {
+                    depth: Some(&RenderingAttachment {
+                        image_view: self.face_views[face].image_view(),
+                        layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                        load: LoadOp::CLEAR,
+                        store: StoreOp::STORE,
+                        clear_value,
+                        resolve: None,
+                    }),
+                    ..Default::default()
+                },
+                contents,
+            ),
+        }
+    }
+}
+
+/// Renders the shadows of up to `MAX_POINT_LIGHTS` point lights before the main pass. Objects
+/// are drawn into the cube maps with the `PassTag::PointShadow` pipeline of their effect, which
+/// writes the distance to the light divided by its range as depth.
+pub struct PointShadows {
+    context: Rc<VulkanContext>,
+    lights: Vec<PointLight>,
+    set: DescriptorSet,
+    set_layout: DescriptorSetLayout,
+    uniform_buffer: Buffer,
+    /// Bound in place of the cube maps of missing lights
+    placeholder: Texture,
+    sampler: Sampler,
+    /// None when using dynamic rendering
+    renderpass: Option<RenderPass>,
+    format: vk::Format,
+    frames_in_flight: usize,
+}
+
+impl PointShadows {
+    /// Creates the point light set without any lights. `format` needs to be the format the
+    /// point shadow pipelines were created with. See `shadow::shadow_format`.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        descriptor_allocator: &mut DescriptorAllocator,
+        frames_in_flight: usize,
+        dynamic_rendering: bool,
+        format: vk::Format,
+    ) -> Result<Self, vulkan::Error> {
+        let placeholder = create_cube(context.clone(), (1, 1).into(), format)?;
+
+        let sampler = Sampler::new(
+            context.clone(),
+            SamplerInfo {
+                address_mode: AddressMode::CLAMP_TO_EDGE,
+                anisotropy: Some(1.0),
+                mipmap_mode: MipmapMode::NEAREST,
+                compare_op: Some(vk::CompareOp::LESS_OR_EQUAL),
+                ..Default::default()
+            },
+        )?;
+
+        let uniform_buffer = Buffer::new(
+            context.clone(),
+            BufferType::Uniform,
+            BufferUsage::Staged,
+            &[PointLightData::default()],
+        )?;
+
+        let renderpass = if dynamic_rendering {
+            None
+        } else {
+            Some(create_renderpass(context.device_ref(), format)?)
+        };
+
+        let mut shadows = Self {
+            context,
+            lights: Vec::new(),
+            set: Default::default(),
+            set_layout: Default::default(),
+            uniform_buffer,
+            placeholder,
+            sampler,
+            renderpass,
+            format,
+            frames_in_flight,
+        };
+
+        let mut set = Default::default();
+        let mut set_layout = Default::default();
+
+        let mut builder = DescriptorBuilder::new();
+        shadows.bind_descriptors(&mut builder);
+
+        builder
+            .build(
+                shadows.context.device(),
+                descriptor_layout_cache,
+                descriptor_allocator,
+                &mut set,
+            )?
+            .layout(descriptor_layout_cache, &mut set_layout)?;
+
+        shadows.set = set;
+        shadows.set_layout = set_layout;
+
+        Ok(shadows)
+    }
+
+    /// Adds a point light and returns its index. Fails if there are already
+    /// `MAX_POINT_LIGHTS` lights. The set is updated, so the device needs to be idle.
+    pub fn add(
+        &mut self,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        descriptor_allocator: &mut DescriptorAllocator,
+        info: PointLightInfo,
+    ) -> Result<usize, Error> {
+        if self.lights.len() == MAX_POINT_LIGHTS {
+            return Err(Error::TooManyPointLights {
+                max: MAX_POINT_LIGHTS,
+            });
+        }
+
+        let light = PointLight::new(
+            self.context.clone(),
+            descriptor_layout_cache,
+            descriptor_allocator,
+            self.frames_in_flight,
+            self.renderpass.as_ref(),
+            self.format,
+            info,
+        )?;
+
+        self.lights.push(light);
+        self.update_set();
+
+        Ok(self.lights.len() - 1)
+    }
+
+    /// Removes the light at `index`. Later lights move down one index. The set is updated, so
+    /// the device needs to be idle.
+    pub fn remove(&mut self, index: usize) -> PointLight {
+        let light = self.lights.remove(index);
+        self.update_set();
+        light
+    }
+
+    /// Recreates the cube map of the light at `index` with a new resolution. The set is
+    /// updated, so the device needs to be idle.
+    pub fn set_resolution(
+        &mut self,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        descriptor_allocator: &mut DescriptorAllocator,
+        index: usize,
+        resolution: u32,
+    ) -> Result<(), vulkan::Error> {
+        let old = &self.lights[index];
+
+        let info = PointLightInfo {
+            position: old.position,
+            range: old.range,
+            cast_shadows: old.cast_shadows,
+            resolution,
+            ..old.info
+        };
+
+        self.lights[index] = PointLight::new(
+            self.context.clone(),
+            descriptor_layout_cache,
+            descriptor_allocator,
+            self.frames_in_flight,
+            self.renderpass.as_ref(),
+            self.format,
+            info,
+        )?;
+
+        self.update_set();
+        Ok(())
+    }
+
+    /// Records the drawing of the cube maps of the lights casting shadows and the update of the
+    /// light uniform.
+    /// Returns the index of the failing light on error.
+    pub fn draw(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        resources: &ResourceManager,
+        frame_index: usize,
+        scene: &Scene,
+    ) -> Result<(), (usize, vulkan::Error)> {
+        let mut data = PointLightData {
+            count: self.lights.len() as u32,
+            ..Default::default()
+        };
+
+        for (i, light) in self.lights.iter().enumerate() {
+            let position = light.position;
+            data.lights[i] = Vec4::new(position.x, position.y, position.z, light.range);
+            data.cast_shadows[i] = light.cast_shadows as u32;
+        }
+
+        update_uniform(commandbuffer, &self.uniform_buffer, &data);
+
+        let renderpass = self.renderpass.as_ref();
+
+        for (i, light) in self
+            .lights
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, light)| light.cast_shadows)
+        {
+            light
+                .draw(commandbuffer, renderpass, resources, frame_index, scene)
+                .map_err(|e| (i, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the descriptor set holding the light uniform and cube maps, bound at
+    /// `POINT_SHADOW_SET`.
+    pub fn set(&self) -> DescriptorSet {
+        self.set
+    }
+
+    pub fn set_layout(&self) -> DescriptorSetLayout {
+        self.set_layout
+    }
+
+    pub fn lights(&self) -> &[PointLight] {
+        &self.lights
+    }
+
+    /// Returns a mutable reference to the light at `index`, e.g; for moving it.
+    pub fn light_mut(&mut self, index: usize) -> Option<&mut PointLight> {
+        self.lights.get_mut(index)
+    }
+
+    // Binds the uniform and the cube map of each light, or the placeholder for missing lights.
+    // The builder refers to its own storage, and can not be returned.
+    fn bind_descriptors(&self, builder: &mut DescriptorBuilder) {
+        builder.bind_uniform_buffer(0, vk::ShaderStageFlags::FRAGMENT, &self.uniform_buffer);
+
+        let textures = self
+            .lights
+            .iter()
+            .map(|light| &light.texture)
+            .chain(std::iter::repeat(&self.placeholder))
+            .take(MAX_POINT_LIGHTS)
+            .collect::<ArrayVec<[&Texture; MAX_POINT_LIGHTS]>>();
+
+        for (i, texture) in textures.iter().enumerate() {
+            builder.bind_combined_image_sampler(
+                i as u32 + 1,
+                vk::ShaderStageFlags::FRAGMENT,
+                texture,
+                &self.sampler,
+            );
+        }
+    }
+
+    fn update_set(&mut self) {
+        let mut builder = DescriptorBuilder::new();
+        self.bind_descriptors(&mut builder);
+        builder.update(self.context.device(), self.set);
+    }
+}
+
+// Creates a depth cube map which can be sampled before the first frame has been drawn
+fn create_cube(
+    context: Rc<VulkanContext>,
+    extent: Extent,
+    format: vk::Format,
+) -> Result<Texture, vulkan::Error> {
+    let texture = Texture::new(
+        context,
+        TextureInfo {
+            extent,
+            mip_levels: 1,
+            usage: TextureUsage::ShadowMap,
+            format,
+            array_layers: CUBE_FACES.len() as u32,
+            view_type: ImageViewType::CUBE,
+            ..Default::default()
+        },
+    )?;
+
+    texture.transition_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)?;
+    Ok(texture)
+}
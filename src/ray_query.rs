@@ -0,0 +1,168 @@
+//! Ray traced shadows and ambient occlusion through ray queries in the fragment shader, see
+//! `data/shaders/ray_query.glsl` and `data/effects/raytraced.toml`.
+//!
+//! Each mesh imported with `MeshImportSettings::acceleration_structure` holds a bottom level
+//! acceleration structure, and the objects of the scene are placed as instances of them in a
+//! top level structure rebuilt at the start of each frame. Objects whose mesh has no
+//! acceleration structure and objects in hidden layers are not traced.
+//!
+//! Requires `DeviceFeatures::ray_query`, which is not requested by default.
+use ash::vk;
+use std::{mem, rc::Rc, slice};
+
+use crate::resources::ResourceCache;
+use crate::{Layers, Mesh, Scene};
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::descriptors::*;
+use vulkan::ray_tracing::{AccelerationStructure, AddressBuffer, GeometryInstance};
+use vulkan::*;
+
+/// The descriptor set index of the ray query set in material effect shaders. Holds the top
+/// level acceleration structure of the scene at binding 0.
+pub const RAY_QUERY_SET: u32 = 8;
+
+/// The maximum number of traced objects. Further objects are ignored.
+pub const MAX_INSTANCES: usize = 4096;
+
+/// `vkCmdUpdateBuffer` writes at most 64 KiB at a time
+const INSTANCES_PER_UPDATE: usize = 65536 / mem::size_of::<GeometryInstance>();
+
+/// The top level acceleration structure of the scene, bound at `RAY_QUERY_SET` for all views
+pub struct SceneAccelerationStructure {
+    top_level: AccelerationStructure,
+    instances: AddressBuffer,
+    scratch: AddressBuffer,
+    set: DescriptorSet,
+    set_layout: DescriptorSetLayout,
+}
+
+impl SceneAccelerationStructure {
+    /// Creates an empty top level structure. Fails if ray queries are not enabled.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        descriptor_allocator: &mut DescriptorAllocator,
+    ) -> Result<Self, vulkan::Error> {
+        let top_level = AccelerationStructure::top_level(context.clone(), MAX_INSTANCES as u32)?;
+
+        let instances = AddressBuffer::new(
+            context.clone(),
+            vk::BufferUsageFlags::TRANSFER_DST
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+            (MAX_INSTANCES * mem::size_of::<GeometryInstance>()) as vk::DeviceSize,
+        )?;
+
+        let scratch =
+            AccelerationStructure::scratch_buffer(context.clone(), top_level.scratch_size())?;
+
+        let mut set = Default::default();
+        let mut set_layout = Default::default();
+
+        DescriptorBuilder::new()
+            .bind_acceleration_structure(0, vk::ShaderStageFlags::FRAGMENT, &top_level)
+            .build(
+                context.device(),
+                descriptor_layout_cache,
+                descriptor_allocator,
+                &mut set,
+            )?
+            .layout(descriptor_layout_cache, &mut set_layout)?;
+
+        Ok(Self {
+            top_level,
+            instances,
+            scratch,
+            set,
+            set_layout,
+        })
+    }
+
+    /// Records the upload of the instances of the visible objects of `scene` and the rebuild of
+    /// the top level structure. Needs to be recorded outside of a renderpass before the views
+    /// are drawn.
+    pub fn update(
+        &self,
+        commandbuffer: &CommandBuffer,
+        scene: &Scene,
+        meshes: &ResourceCache<Mesh>,
+    ) {
+        let instances = scene
+            .objects()
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| scene.is_drawn(*i, Layers::ALL))
+            .filter_map(|(i, object)| {
+                let bottom_level = meshes.raw(object.mesh).ok()?.acceleration_structure()?;
+
+                Some(GeometryInstance::new(
+                    object.model_matrix().as_array(),
+                    i as u32,
+                    bottom_level,
+                ))
+            })
+            .take(MAX_INSTANCES)
+            .collect::<Vec<_>>();
+
+        // The previous frame may still trace or build from the instances
+        commandbuffer.memory_barrier(
+            vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+            vk::PipelineStageFlags::TRANSFER
+                | vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+            &[vk::MemoryBarrier {
+                src_access_mask: vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+                dst_access_mask: vk::AccessFlags::TRANSFER_WRITE
+                    | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+                ..Default::default()
+            }],
+        );
+
+        for (i, chunk) in instances.chunks(INSTANCES_PER_UPDATE).enumerate() {
+            let bytes = unsafe {
+                slice::from_raw_parts(chunk.as_ptr() as *const u8, mem::size_of_val(chunk))
+            };
+
+            let offset = i * INSTANCES_PER_UPDATE * mem::size_of::<GeometryInstance>();
+            commandbuffer.update_buffer(self.instances.buffer(), offset as _, bytes);
+        }
+
+        commandbuffer.memory_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+            &[vk::MemoryBarrier {
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                ..Default::default()
+            }],
+        );
+
+        self.top_level.record_build(
+            commandbuffer,
+            self.instances.address(),
+            instances.len() as u32,
+            &self.scratch,
+        );
+
+        commandbuffer.memory_barrier(
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            &[vk::MemoryBarrier {
+                src_access_mask: vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+                dst_access_mask: vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+                ..Default::default()
+            }],
+        );
+    }
+
+    /// Returns the descriptor set bound at `RAY_QUERY_SET`.
+    pub fn set(&self) -> DescriptorSet {
+        self.set
+    }
+
+    /// Returns the layout of the ray query set
+    pub fn set_layout(&self) -> DescriptorSetLayout {
+        self.set_layout
+    }
+}
@@ -0,0 +1,136 @@
+use ultraviolet::{Mat4, Vec3};
+
+use crate::mesh::{Mesh, MeshGeometry};
+use crate::object::Object;
+
+/// A half line in world space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    /// Normalized direction
+    pub direction: Vec3,
+}
+
+impl Ray {
+    /// Creates a ray from `origin` along `direction`, which does not need to be normalized.
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self {
+            origin,
+            direction: direction.normalized(),
+        }
+    }
+
+    /// Returns the point at `distance` along the ray
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+}
+
+/// The closest intersection of a ray with the objects of a scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// The index of the object in the scene
+    pub object: usize,
+    /// The distance from the ray origin
+    pub distance: f32,
+    /// The world space surface normal facing the ray
+    pub normal: Vec3,
+}
+
+/// Intersects `ray` with `object`. Meshes with CPU geometry are tested per triangle, others
+/// against their world bounding box. Returns the distance and normal of the closest hit.
+pub fn intersect_object(ray: &Ray, object: &Object, mesh: &Mesh) -> Option<(f32, Vec3)> {
+    let hit = object.aabb(mesh).intersect_ray(ray)?;
+
+    match mesh.geometry() {
+        Some(geometry) => intersect_geometry(ray, &object.model_matrix(), geometry),
+        None => Some(hit),
+    }
+}
+
+/// Returns the distance to the first intersection with a sphere. Rays starting inside the
+/// sphere hit it at their origin.
+pub fn intersect_sphere(ray: &Ray, center: Vec3, radius: f32) -> Option<f32> {
+    let offset = ray.origin - center;
+    let b = offset.dot(ray.direction);
+    let c = offset.mag_sq() - radius * radius;
+
+    if c <= 0.0 {
+        return Some(0.0);
+    }
+
+    let discriminant = b * b - c;
+    if b > 0.0 || discriminant < 0.0 {
+        return None;
+    }
+
+    Some(-b - discriminant.sqrt())
+}
+
+/// Returns the distance along the ray and the unnormalized normal of a triangle using the
+/// Möller-Trumbore algorithm. Both sides of the triangle are hit.
+pub fn intersect_triangle(ray: &Ray, a: Vec3, b: Vec3, c: Vec3) -> Option<(f32, Vec3)> {
+    let ab = b - a;
+    let ac = c - a;
+
+    let p = ray.direction.cross(ac);
+    let det = ab.dot(p);
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let offset = ray.origin - a;
+
+    let u = offset.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = offset.cross(ab);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = ac.dot(q) * inv_det;
+    if t < 0.0 {
+        return None;
+    }
+
+    Some((t, ab.cross(ac)))
+}
+
+/// Intersects the triangles of `geometry` placed by `model`. The ray is transformed into model
+/// space, which keeps the test exact for non uniform scales.
+fn intersect_geometry(ray: &Ray, model: &Mat4, geometry: &MeshGeometry) -> Option<(f32, Vec3)> {
+    let inverse = model.inversed();
+    let local = Ray::new(
+        inverse.transform_point3(ray.origin),
+        inverse.transform_vec3(ray.direction),
+    );
+
+    let positions = geometry.positions();
+    let (t, normal) = geometry
+        .indices()
+        .chunks_exact(3)
+        .filter_map(|triangle| {
+            intersect_triangle(
+                &local,
+                positions[triangle[0] as usize],
+                positions[triangle[1] as usize],
+                positions[triangle[2] as usize],
+            )
+        })
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())?;
+
+    let distance = (model.transform_point3(local.at(t)) - ray.origin).mag();
+
+    // Normals transform by the inverse transpose
+    let mut normal = inverse.transposed().transform_vec3(normal).normalized();
+    if normal.dot(ray.direction) > 0.0 {
+        normal = -normal;
+    }
+
+    Some((distance, normal))
+}
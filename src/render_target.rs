@@ -0,0 +1,410 @@
+//! Secondary cameras rendering the scene into textures which can be sampled by materials, e.g;
+//! for mirrors, portals and security cameras.
+use arrayvec::ArrayVec;
+use ash::vk;
+use std::rc::Rc;
+use ultraviolet::Vec3;
+
+use crate::light_culling::{CullingQueue, LightCulling, LightGrid};
+use crate::mesh_renderer::{MeshRenderer, MeshRendererInfo};
+use crate::resources::*;
+use crate::{Camera, PassTag, Scene};
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::descriptors::DescriptorSet;
+use vulkan::dynamic_rendering::DynamicRendering;
+use vulkan::renderpass::*;
+use vulkan::texture::*;
+use vulkan::*;
+
+/// Specifies how a render target renders
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderTargetInfo {
+    /// The camera the scene is initially rendered from
+    pub camera: Camera,
+    /// The resolution of the rendered texture
+    pub extent: Extent,
+    /// The effect passes drawn into the texture
+    pub passes: Vec<PassTag>,
+    /// The color the texture is cleared to before drawing
+    pub clear_color: [f32; 4],
+    /// Object capacity and growth of the render target's mesh renderer
+    pub mesh_renderer: MeshRendererInfo,
+}
+
+impl Default for RenderTargetInfo {
+    fn default() -> Self {
+        Self {
+            camera: Camera::perspective(Vec3::zero(), 1.0, 1.0, 0.1, 1000.0),
+            extent: (512, 512).into(),
+            passes: vec![PassTag::Opaque, PassTag::Transparent],
+            clear_color: [0.0, 0.0, 0.0, 0.0],
+            mesh_renderer: MeshRendererInfo::default(),
+        }
+    }
+}
+
+/// Renders the scene from a secondary camera into a texture before the main pass.
+/// Objects whose material samples the texture are not drawn into it.
+pub struct RenderTarget {
+    /// The camera the scene is rendered from
+    pub camera: Camera,
+    /// Disabled render targets are not drawn and keep the contents of their last frame
+    pub enabled: bool,
+    context: Rc<VulkanContext>,
+    texture: Handle<Texture>,
+    passes: Vec<PassTag>,
+    clear_color: [f32; 4],
+    mesh_renderer: MeshRenderer,
+    /// The lights affecting each tile of the texture, culled before drawing
+    light_grid: Option<LightGrid>,
+    /// None when using dynamic rendering
+    framebuffer: Option<Framebuffer>,
+    /// Compatible with the main renderpass, which allows the same pipelines to be used
+    renderpass: Option<RenderPass>,
+    // Multisampled color and depth attachments resolved into the texture
+    color_attachment: Texture,
+    depth_attachment: Texture,
+}
+
+impl RenderTarget {
+    /// Creates a render target drawing into `texture`, which needs to have the color format of
+    /// the main pass and `TextureUsage::RenderTarget` usage. `depth_format` needs to be the
+    /// depth format of the main pass.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        texture: Handle<Texture>,
+        texture_raw: &Texture,
+        mut mesh_renderer: MeshRenderer,
+        dynamic_rendering: bool,
+        depth_format: vk::Format,
+        info: RenderTargetInfo,
+    ) -> Result<Self, vulkan::Error> {
+        let extent = texture_raw.extent();
+
+        let color_attachment = Texture::new(
+            context.clone(),
+            TextureInfo {
+                extent,
+                mip_levels: 1,
+                usage: TextureUsage::ColorAttachment,
+                format: texture_raw.format(),
+                samples: context.msaa_samples(),
+                ..Default::default()
+            },
+        )?;
+
+        let depth_attachment = Texture::new(
+            context.clone(),
+            TextureInfo {
+                extent,
+                mip_levels: 1,
+                usage: TextureUsage::DepthAttachment,
+                format: depth_format,
+                samples: context.msaa_samples(),
+                ..Default::default()
+            },
+        )?;
+
+        let renderpass = if dynamic_rendering {
+            None
+        } else {
+            Some(create_renderpass(
+                context.device_ref(),
+                &color_attachment,
+                &depth_attachment,
+                texture_raw,
+            )?)
+        };
+
+        let framebuffer = renderpass
+            .as_ref()
+            .map(|renderpass| {
+                Framebuffer::new(
+                    context.device_ref(),
+                    renderpass,
+                    &[&color_attachment, &depth_attachment, texture_raw],
+                    extent,
+                )
+            })
+            .transpose()?;
+
+        let inheritance = match &renderpass {
+            Some(renderpass) => Inheritance::RenderPass {
+                renderpass: renderpass.renderpass(),
+                subpass: 0,
+            },
+            None => {
+                let mut color_formats = ArrayVec::new();
+                color_formats.push(texture_raw.format());
+
+                Inheritance::Dynamic {
+                    formats: RenderingFormats {
+                        color_formats,
+                        depth_format,
+                        shading_rate_attachment: false,
+                        view_mask: 0,
+                    },
+                    samples: context.msaa_samples(),
+                }
+            }
+        };
+
+        mesh_renderer.set_inheritance(inheritance);
+        mesh_renderer.set_extent(extent);
+        mesh_renderer.set_render_target(texture);
+
+        Ok(Self {
+            camera: info.camera,
+            enabled: true,
+            context,
+            texture,
+            passes: info.passes,
+            clear_color: info.clear_color,
+            mesh_renderer,
+            light_grid: None,
+            framebuffer,
+            renderpass,
+            color_attachment,
+            depth_attachment,
+        })
+    }
+
+    /// Records the drawing of the scene from the camera into the texture. The texture is
+    /// transitioned to SHADER_READ_ONLY_OPTIMAL for sampling by the passes recorded afterwards.
+    /// The lights of `light_culling` are culled into the light grid first if both are present.
+    pub fn draw(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        resources: &ResourceManager,
+        frame_index: usize,
+        scene: &Scene,
+        light_culling: Option<&LightCulling>,
+    ) -> Result<(), vulkan::Error> {
+        let texture = resources.textures().raw(self.texture).unwrap();
+        let extent = texture.extent();
+        let camera = self.camera.with_viewport_aspect(extent.aspect());
+
+        if let (Some(light_grid), Some(light_culling)) = (&self.light_grid, light_culling) {
+            let rect = vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: extent.into(),
+            };
+
+            light_grid.cull(
+                commandbuffer,
+                light_culling,
+                &camera,
+                rect,
+                CullingQueue::Graphics,
+            );
+        }
+
+        // Waits for the previous frame to finish sampling the texture
+        texture.transition(commandbuffer, ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: self.clear_color,
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+
+        let contents = if self.mesh_renderer.uses_secondary() {
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+        } else {
+            vk::SubpassContents::INLINE
+        };
+
+        match (&self.renderpass, &self.framebuffer) {
+            (Some(renderpass), Some(framebuffer)) => commandbuffer.begin_renderpass(
+                renderpass,
+                framebuffer,
+                extent,
+                &clear_values,
+                contents,
+            ),
+            _ => self.begin_rendering(commandbuffer, texture, &clear_values, contents),
+        }
+
+        self.mesh_renderer.draw(
+            commandbuffer,
+            resources,
+            &camera,
+            frame_index,
+            scene,
+            &self.passes,
+        )?;
+
+        match self.renderpass {
+            Some(_) => commandbuffer.end_renderpass(),
+            None => commandbuffer.end_rendering(self.dynamic_rendering()),
+        }
+
+        texture.transition(commandbuffer, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        Ok(())
+    }
+
+    /// Returns the texture the scene is rendered into.
+    pub fn texture(&self) -> Handle<Texture> {
+        self.texture
+    }
+
+    /// Returns the effect passes drawn into the texture.
+    pub fn passes(&self) -> &[PassTag] {
+        &self.passes
+    }
+
+    /// Sets the shadow set bound for effects sampling shadows. See
+    /// `MasterRenderer::enable_shadows`.
+    pub fn set_shadow_set(&mut self, shadow_set: Option<DescriptorSet>) {
+        self.mesh_renderer.set_shadow_set(shadow_set);
+    }
+
+    /// Sets the environment set bound for effects lit by the environment. See
+    /// `MasterRenderer::set_environment`.
+    pub fn set_environment_set(&mut self, environment_set: Option<DescriptorSet>) {
+        self.mesh_renderer.set_environment_set(environment_set);
+    }
+
+    /// Sets the light grid the lights are culled into and bound from at `LIGHT_SET`. The grid
+    /// needs to fit the extent of the texture.
+    pub fn set_light_grid(&mut self, light_grid: Option<LightGrid>) {
+        self.mesh_renderer
+            .set_light_set(light_grid.as_ref().map(|light_grid| light_grid.set()));
+        self.light_grid = light_grid;
+    }
+
+    /// Returns the resolution of the rendered texture.
+    pub fn extent(&self) -> Extent {
+        self.color_attachment.extent()
+    }
+
+    fn dynamic_rendering(&self) -> &DynamicRendering {
+        self.context
+            .dynamic_rendering()
+            .expect("Dynamic rendering is not supported")
+    }
+
+    // Transitions the attachments and begins dynamic rendering. The texture needs to be in
+    // COLOR_ATTACHMENT_OPTIMAL.
+    fn begin_rendering(
+        &self,
+        commandbuffer: &CommandBuffer,
+        texture: &Texture,
+        clear_values: &[vk::ClearValue; 2],
+        contents: vk::SubpassContents,
+    ) {
+        // Contents of the previous frame are discarded
+        self.color_attachment.set_layout(ImageLayout::UNDEFINED);
+        self.depth_attachment.set_layout(ImageLayout::UNDEFINED);
+
+        commandbuffer.image_barriers(&[
+            self.color_attachment
+                .barrier(ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            self.depth_attachment
+                .barrier(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+        ]);
+
+        let depth = RenderingAttachment {
+            image_view: self.depth_attachment.image_view(),
+            layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            load: LoadOp::CLEAR,
+            store: StoreOp::DONT_CARE,
+            clear_value: clear_values[1],
+            resolve: None,
+        };
+
+        let stencil = if has_stencil(self.depth_attachment.format()) {
+            Some(&depth)
+        } else {
+            None
+        };
+
+        commandbuffer.begin_rendering(
+            self.dynamic_rendering(),
+            texture.extent(),
+            &RenderingAttachments {
+                color: &[RenderingAttachment {
+                    image_view: self.color_attachment.image_view(),
+                    layout: ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    load: LoadOp::CLEAR,
+                    // Resolved into the texture, so the transient contents are never stored
+                    store: StoreOp::DONT_CARE,
+                    clear_value: clear_values[0],
+                    resolve: Some((texture.image_view(), ImageLayout::COLOR_ATTACHMENT_OPTIMAL)),
+                }],
+                depth: Some(&depth),
+                stencil,
+                shading_rate: None,
+                view_mask: 0,
+            },
+            contents,
+        );
+    }
+}
+
+// Creates a renderpass compatible with the main renderpass which resolves into `texture`
+// instead of a swapchain image. The texture is transitioned outside the renderpass to
+// synchronize with the sampling of the previous frame.
+fn create_renderpass(
+    device: Rc<ash::Device>,
+    color_attachment: &Texture,
+    depth_attachment: &Texture,
+    texture: &Texture,
+) -> Result<RenderPass, vulkan::Error> {
+    let renderpass_info = RenderPassInfo {
+        attachments: &[
+            // Color attachment, resolved into the texture
+            AttachmentInfo::from_texture(
+                color_attachment,
+                LoadOp::CLEAR,
+                StoreOp::DONT_CARE,
+                ImageLayout::UNDEFINED,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            // Depth attachment
+            AttachmentInfo::from_texture(
+                depth_attachment,
+                LoadOp::CLEAR,
+                StoreOp::DONT_CARE,
+                ImageLayout::UNDEFINED,
+                ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ),
+            // Resolve attachment
+            AttachmentInfo::from_texture(
+                texture,
+                LoadOp::DONT_CARE,
+                StoreOp::STORE,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+        ],
+        subpasses: &[SubpassInfo {
+            color_attachments: &[AttachmentReference {
+                attachment: 0,
+                layout: ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            }],
+            resolve_attachments: &[AttachmentReference {
+                attachment: 2,
+                layout: ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            }],
+            depth_attachment: Some(AttachmentReference {
+                attachment: 1,
+                layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            }),
+        }],
+        view_mask: 0,
+    };
+
+    RenderPass::new(device, &renderpass_info)
+}
@@ -0,0 +1,36 @@
+//! In-application captures with RenderDoc, enabled by the `renderdoc` feature. The API is only
+//! available when the application is launched from RenderDoc or RenderDoc is injected into the
+//! process.
+
+use ::renderdoc::{RenderDoc as Api, V110};
+
+/// A connection to the RenderDoc instance the application is running under
+pub struct RenderDoc {
+    api: Api<V110>,
+}
+
+impl RenderDoc {
+    /// Connects to RenderDoc. Returns None if the application is not running under RenderDoc.
+    pub fn new() -> Option<Self> {
+        match Api::new() {
+            Ok(api) => {
+                log::info!("Connected to RenderDoc");
+                Some(Self { api })
+            }
+            Err(e) => {
+                log::debug!("RenderDoc is not available: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Captures the next presented frame
+    pub fn trigger_capture(&mut self) {
+        self.api.trigger_capture()
+    }
+
+    /// Returns the number of captures taken so far
+    pub fn capture_count(&self) -> u32 {
+        self.api.get_num_captures()
+    }
+}
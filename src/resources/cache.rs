@@ -50,6 +50,15 @@ impl<R> ResourceCache<R> {
         Ok(handle)
     }
 
+    /// Returns the name the resource pointed to by handle was inserted with. Searches all names,
+    /// so prefer keeping the name around when it is known.
+    pub fn name(&self, handle: Handle<R>) -> Option<&str> {
+        self.name_cache
+            .iter()
+            .find(|(_, cached)| **cached == handle)
+            .map(|(name, _)| name.as_str())
+    }
+
     /// Returns a reference to the underlying resource pointed to by handle. Returns
     /// `Error::InvalidInvalidHandle` if handle is no longer valid.
     pub fn raw(&self, handle: Handle<R>) -> Result<&R, Error> {
@@ -58,4 +67,46 @@ impl<R> ResourceCache<R> {
             None => Err(Error::InvalidHandle(std::any::type_name::<R>())),
         }
     }
+
+    /// Returns a mutable reference to the underlying resource pointed to by handle. Returns
+    /// `Error::InvalidInvalidHandle` if handle is no longer valid.
+    pub fn raw_mut(&mut self, handle: Handle<R>) -> Result<&mut R, Error> {
+        match self.resources.get_mut(handle.into()) {
+            Some(resource) => Ok(resource),
+            None => Err(Error::InvalidHandle(std::any::type_name::<R>())),
+        }
+    }
+
+    /// Replaces the resource pointed to by handle, keeping the handle valid. Returns the
+    /// previous resource.
+    pub fn replace(&mut self, handle: Handle<R>, resource: R) -> Result<R, Error> {
+        let old = self.raw_mut(handle)?;
+        Ok(std::mem::replace(old, resource))
+    }
+
+    /// Removes the resource pointed to by handle along with its name, invalidating the handle.
+    /// Returns the removed resource.
+    pub fn remove(&mut self, handle: Handle<R>) -> Result<R, Error> {
+        let resource = self
+            .resources
+            .remove(handle.into())
+            .ok_or(Error::InvalidHandle(std::any::type_name::<R>()))?;
+
+        self.name_cache.retain(|_, cached| *cached != handle);
+        Ok(resource)
+    }
+
+    /// Returns an iterator over the handles and resources in the cache.
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<R>, &R)> {
+        self.resources
+            .iter()
+            .map(|(index, resource)| (index.into(), resource))
+    }
+
+    /// Returns an iterator over the handles and resources in the cache.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<R>, &mut R)> {
+        self.resources
+            .iter_mut()
+            .map(|(index, resource)| (index.into(), resource))
+    }
 }
@@ -0,0 +1,48 @@
+use std::any::Any;
+use std::collections::VecDeque;
+
+/// Keeps replaced resources alive until the device can no longer be using them.
+/// Resources are dropped after `advance` has been called `frames` times, which should be done
+/// once per frame.
+pub struct DestructionQueue {
+    frames: u64,
+    frame: u64,
+    queue: VecDeque<(u64, Box<dyn Any>)>,
+}
+
+impl DestructionQueue {
+    pub fn new(frames: usize) -> Self {
+        Self {
+            frames: frames as u64,
+            frame: 0,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Defers the destruction of `resource`
+    pub fn retire<R: 'static>(&mut self, resource: R) {
+        self.queue.push_back((self.frame, Box::new(resource)));
+    }
+
+    /// Advances to the next frame and drops the resources retired `frames` frames ago.
+    pub fn advance(&mut self) {
+        self.frame += 1;
+
+        while let Some((retired, _)) = self.queue.front() {
+            if self.frame - retired <= self.frames {
+                break;
+            }
+
+            self.queue.pop_front();
+        }
+    }
+
+    /// Returns the number of resources awaiting destruction
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
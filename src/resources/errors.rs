@@ -6,4 +6,6 @@ pub enum Error {
     NotFound(&'static str, String),
     #[error("{0} handle is not valid to dereference")]
     InvalidHandle(&'static str),
+    #[error("Resource type {0} is not registered")]
+    UnregisteredType(&'static str),
 }
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use super::Handle;
+use crate::vulkan::{Extent, Texture};
+
+/// How a texture was sampled since the feedback was last cleared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureSampling {
+    /// The number of objects drawn with the texture
+    pub draws: usize,
+    /// The most detailed mip level estimated to have been sampled
+    pub finest_mip: u32,
+}
+
+/// The sampling of a loaded texture, used to guide asset sizing. A texture whose finest sampled
+/// mip level is above 0 could be reduced by that many levels without visible difference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureReport {
+    pub name: String,
+    pub extent: Extent,
+    pub mip_levels: u32,
+    /// None if the texture was never sampled
+    pub sampling: Option<TextureSampling>,
+}
+
+/// Debug statistics of the textures and mip levels sampled by the drawn materials. Recorded per
+/// draw by estimating the mip level from the size of the object on screen, which assumes the
+/// texture is mapped once across the object.
+#[derive(Debug, Default)]
+pub struct TextureFeedback {
+    textures: HashMap<Handle<Texture>, TextureSampling>,
+}
+
+impl TextureFeedback {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `texture` was sampled down to `mip`
+    pub fn record(&mut self, texture: Handle<Texture>, mip: u32) {
+        let sampling = self.textures.entry(texture).or_insert(TextureSampling {
+            draws: 0,
+            finest_mip: mip,
+        });
+
+        sampling.draws += 1;
+        sampling.finest_mip = sampling.finest_mip.min(mip);
+    }
+
+    /// Returns how `texture` was sampled, or None if it was not sampled
+    pub fn get(&self, texture: Handle<Texture>) -> Option<TextureSampling> {
+        self.textures.get(&texture).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<Texture>, TextureSampling)> + '_ {
+        self.textures
+            .iter()
+            .map(|(texture, sampling)| (*texture, *sampling))
+    }
+
+    /// Forgets all recorded samples, e.g; before visiting another part of a level
+    pub fn clear(&mut self) {
+        self.textures.clear();
+    }
+}
+
+/// Estimates the most detailed mip level of a texture sized `extent` with `mip_levels` levels
+/// sampled when stretched across `pixels` on screen.
+pub fn estimate_mip(extent: Extent, mip_levels: u32, pixels: f32) -> u32 {
+    let texels = extent.width.max(extent.height) as f32;
+
+    if pixels <= 0.0 {
+        return mip_levels.saturating_sub(1);
+    }
+
+    let level = (texels / pixels).log2().floor().max(0.0) as u32;
+    level.min(mip_levels.saturating_sub(1))
+}
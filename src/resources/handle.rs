@@ -1,10 +1,15 @@
 //! Wraps an arena index to provide a typesafe handle.
+//!
+//! Handles are plain indices and are Send and Sync regardless of the resource they refer to,
+//! so they can be passed to other threads, e.g; to build a scene, while the resources
+//! themselves stay on the thread owning the `ResourceManager`.
 
 use generational_arena::Index;
 use std::marker::PhantomData;
-use std::{hash::Hash, ops::Deref};
+use std::{fmt, hash::Hash, ops::Deref};
 
-pub struct Handle<R>(Index, PhantomData<R>);
+// `fn() -> R` does not inherit the auto traits of the resource
+pub struct Handle<R>(Index, PhantomData<fn() -> R>);
 
 impl<R> Clone for Handle<R> {
     fn clone(&self) -> Self {
@@ -22,12 +27,30 @@ impl<R> PartialEq for Handle<R> {
 
 impl<R> Eq for Handle<R> {}
 
+impl<R> PartialOrd for Handle<R> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+impl<R> Ord for Handle<R> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
 impl<R> Hash for Handle<R> {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.0.hash(state);
     }
 }
 
+impl<R> fmt::Debug for Handle<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Handle").field(&self.0).finish()
+    }
+}
+
 impl<R> From<Index> for Handle<R> {
     fn from(index: Index) -> Self {
         Self(index, PhantomData)
@@ -39,3 +62,14 @@ impl<R> Into<Index> for Handle<R> {
         self.0
     }
 }
+
+// Fails to compile if handles to resources holding Rc:s, or scenes of them, lose Send or Sync
+#[allow(dead_code)]
+fn assert_send_sync() {
+    fn is_send_sync<T: Send + Sync>() {}
+
+    is_send_sync::<Handle<crate::vulkan::texture::Texture>>();
+    is_send_sync::<Handle<crate::Material>>();
+    is_send_sync::<Handle<crate::Mesh>>();
+    is_send_sync::<crate::Scene>();
+}
@@ -1,50 +1,191 @@
-use std::{path::Path, rc::Rc};
+use std::any::{self, Any, TypeId};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::{collections::HashMap, convert::Infallible, rc::Rc};
 
 use super::*;
-use crate::{material::*, vulkan::Pipeline, Mesh};
+use crate::{material::*, Mesh, MeshData, MeshImportSettings, ObjModel, PlyMesh};
 
-use crate::document::Document;
+use crate::audio::Sound;
+use crate::document::{Document, DocumentMaterial};
+use crate::lod::{self, LodChain};
 use crate::resources;
 use crate::vulkan;
-use crate::Error;
+use crate::{Error, ErrorContext};
 use vulkan::descriptors::*;
+use vulkan::pipeline::{Pipeline, PipelineInfo};
+use vulkan::sampler::SamplerCache;
+use vulkan::swapchain::MAX_FRAMES;
+use vulkan::texture::{ColorSpace, TextureLoadInfo, TextureUsage};
+use vulkan::Buffer;
+use vulkan::DebugName;
 use vulkan::Texture;
 use vulkan::VulkanContext;
 
+use ultraviolet::Vec3;
+
+/// An asset which is reloaded into its handle when the file it was loaded from changes
+#[derive(Clone)]
+enum WatchedAsset {
+    Texture(Handle<Texture>, TextureLoadInfo),
+    /// A document by name along with the settings its meshes were imported with
+    Document(String, MeshImportSettings),
+}
+
+/// A mesh of an OBJ file along with the material of its MTL library
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjMeshHandle {
+    pub mesh: Handle<Mesh>,
+    pub material: Option<DocumentMaterial>,
+}
+
+/// The resources bound by a material, from which it is created and recreated when a texture is
+/// reloaded
+struct MaterialResources {
+    effect: Handle<MaterialEffect>,
+    albedo: MaterialTexture,
+    emissive: Option<Handle<Texture>>,
+    emissive_factor: Vec3,
+    lightmap: Option<Handle<Texture>>,
+    /// Pairs of shader binding names and buffers
+    buffers: Vec<(String, Handle<Buffer>)>,
+    /// Pairs of shader binding names and textures
+    textures: Vec<(String, Handle<Texture>)>,
+}
+
+impl MaterialResources {
+    fn of(material: &Material) -> Self {
+        Self {
+            effect: *material.effect(),
+            albedo: MaterialTexture {
+                texture: material.albedo(),
+                sampler: *material.sampler().info(),
+            },
+            emissive: material.emissive(),
+            emissive_factor: material.emissive_factor(),
+            lightmap: material.lightmap(),
+            buffers: material.buffers().to_vec(),
+            textures: material.textures().to_vec(),
+        }
+    }
+}
+
+/// Owns all loaded resources. Like the `VulkanContext` it is bound to the thread that created
+/// it, while the handles it returns are Send and Sync and can be shared with other threads.
 pub struct ResourceManager {
     context: Rc<VulkanContext>,
     descriptor_allocator: DescriptorAllocator,
     descriptor_layouts: DescriptorLayoutCache,
+    samplers: SamplerCache,
     textures: ResourceCache<Texture>,
     materials: ResourceCache<Material>,
     effects: ResourceCache<MaterialEffect>,
     meshes: ResourceCache<Mesh>,
     documents: ResourceCache<Document>,
+    lods: ResourceCache<LodChain>,
+    buffers: ResourceCache<Buffer>,
+    sounds: ResourceCache<Sound>,
+    watcher: FileWatcher<WatchedAsset>,
+    /// Resources replaced by reloads which may still be used by frames in flight
+    destruction_queue: DestructionQueue,
+    generation: u64,
+    /// Recorded while drawing, which only borrows the manager. None unless enabled.
+    texture_feedback: Option<RefCell<TextureFeedback>>,
+    /// Caches of application defined resource types. Each value is a `ResourceCache` of the
+    /// type of the key.
+    custom: HashMap<TypeId, Box<dyn Any>>,
 }
 
 impl ResourceManager {
     pub fn new(context: Rc<VulkanContext>) -> Self {
         let descriptor_allocator = DescriptorAllocator::new(context.device_ref(), 1024);
         let descriptor_layouts = DescriptorLayoutCache::new(context.device_ref());
+        let samplers = SamplerCache::new(context.clone());
 
         let textures = ResourceCache::new();
         let materials = ResourceCache::new();
         let effects = ResourceCache::new();
         let meshes = ResourceCache::new();
         let documents = ResourceCache::new();
+        let lods = ResourceCache::new();
+        let buffers = ResourceCache::new();
+        let sounds = ResourceCache::new();
 
         Self {
             context,
             descriptor_allocator,
             descriptor_layouts,
+            samplers,
             textures,
             materials,
             effects,
             meshes,
             documents,
+            lods,
+            buffers,
+            sounds,
+            watcher: FileWatcher::new(),
+            destruction_queue: DestructionQueue::new(MAX_FRAMES),
+            generation: 0,
+            texture_feedback: None,
+            custom: HashMap::new(),
         }
     }
 
+    /// Registers an application defined resource type, e.g; audio clips or navigation meshes,
+    /// which is then managed through the same handles as the builtin resources.
+    /// Does nothing if the type is already registered.
+    pub fn register_type<T: 'static>(&mut self) {
+        self.custom
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(ResourceCache::<T>::new()));
+    }
+
+    /// Returns the cache of a registered resource type.
+    pub fn cache<T: 'static>(&self) -> Result<&ResourceCache<T>, resources::Error> {
+        self.custom
+            .get(&TypeId::of::<T>())
+            .and_then(|cache| cache.downcast_ref())
+            .ok_or_else(|| resources::Error::UnregisteredType(any::type_name::<T>()))
+    }
+
+    /// Returns the mutable cache of a registered resource type.
+    pub fn cache_mut<T: 'static>(&mut self) -> Result<&mut ResourceCache<T>, resources::Error> {
+        self.custom
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|cache| cache.downcast_mut())
+            .ok_or_else(|| resources::Error::UnregisteredType(any::type_name::<T>()))
+    }
+
+    /// Get a resource of a registered type or insert the resource loaded by `load`.
+    pub fn load<T, S, E, F>(&mut self, name: S, load: F) -> Result<Handle<T>, E>
+    where
+        T: 'static,
+        S: AsRef<str> + Into<String>,
+        E: From<resources::Error>,
+        F: FnOnce() -> Result<T, E>,
+    {
+        self.cache_mut::<T>()?.insert(name, load)
+    }
+
+    /// Get a resource of a registered type by name.
+    pub fn get<T: 'static, S>(&self, name: S) -> Result<Handle<T>, resources::Error>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        self.cache::<T>()?.get(name)
+    }
+
+    /// Returns a reference to a resource of a registered type.
+    pub fn raw<T: 'static>(&self, handle: Handle<T>) -> Result<&T, resources::Error> {
+        self.cache::<T>()?.raw(handle)
+    }
+
+    /// Returns a mutable reference to a resource of a registered type.
+    pub fn raw_mut<T: 'static>(&mut self, handle: Handle<T>) -> Result<&mut T, resources::Error> {
+        self.cache_mut::<T>()?.raw_mut(handle)
+    }
+
     /// Get a material by name.
     pub fn material<S>(&self, name: S) -> Result<Handle<Material>, resources::Error>
     where
@@ -77,7 +218,30 @@ impl ResourceManager {
         self.meshes.get(name)
     }
 
+    /// Get a LOD chain by name.
+    pub fn lod_chain<S>(&self, name: S) -> Result<Handle<LodChain>, resources::Error>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        self.lods.get(name)
+    }
+
+    /// Get a buffer by name.
+    pub fn buffer<S>(&self, name: S) -> Result<Handle<Buffer>, resources::Error>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        self.buffers.get(name)
+    }
+
     /// Get a document by name.
+    pub fn sound<S>(&self, name: S) -> Result<Handle<Sound>, resources::Error>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        self.sounds.get(name)
+    }
+
     pub fn document<S>(&self, name: S) -> Result<Handle<Document>, resources::Error>
     where
         S: AsRef<str> + Into<String>,
@@ -93,50 +257,364 @@ impl ResourceManager {
     where
         S: AsRef<str> + Into<String>,
     {
+        if let Ok(material) = self.material(name.as_ref()) {
+            return Ok(material);
+        }
+
         let effect = self.effect(info.effect)?;
-        let albedo = self.texture(info.albedo)?;
+        let albedo = MaterialTexture {
+            texture: self.texture(info.albedo)?,
+            sampler: info.albedo_sampler.unwrap_or_default(),
+        };
 
-        let context = self.context.clone();
-        let descriptor_layouts = &mut self.descriptor_layouts;
-        let descriptor_allocator = &mut self.descriptor_allocator;
-        let textures = &self.textures;
+        let buffers = info
+            .buffers
+            .iter()
+            .map(|(binding, buffer)| Ok((binding.clone(), self.buffers.get(buffer.as_str())?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let textures = info
+            .textures
+            .iter()
+            .map(|(binding, texture)| Ok((binding.clone(), self.texture(texture.as_str())?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let emissive = info
+            .emissive
+            .map(|emissive| self.texture(emissive))
+            .transpose()?;
+
+        let lightmap = info
+            .lightmap
+            .map(|lightmap| self.texture(lightmap))
+            .transpose()?;
+
+        let material = self
+            .create_material(MaterialResources {
+                effect,
+                albedo,
+                emissive,
+                emissive_factor: info.emissive_factor,
+                lightmap,
+                buffers,
+                textures,
+            })
+            .with_context(|| format!("Failed to create material {:?}", name.as_ref()))?;
+        let material = named(&self.context, name.as_ref(), material);
 
         self.materials
-            .insert(name, || {
-                Material::new(
-                    context,
-                    descriptor_layouts,
-                    descriptor_allocator,
-                    textures,
-                    effect,
-                    albedo,
-                )
+            .insert(name, || Ok::<_, Infallible>(material))
+            .map_err(|e| match e {})
+    }
+
+    /// Creates an instance named `name` of the material `parent` with the textures and emission
+    /// in `overrides` replaced. Instances share the effect and descriptor set layout of the
+    /// parent, so no pipelines are created, and only need a descriptor set of their own, which
+    /// makes many slightly different materials cheap. Returns the existing material if one with
+    /// the same name is loaded.
+    pub fn instantiate_material<S>(
+        &mut self,
+        name: S,
+        parent: Handle<Material>,
+        overrides: MaterialOverrides,
+    ) -> Result<Handle<Material>, Error>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        if let Ok(material) = self.material(name.as_ref()) {
+            return Ok(material);
+        }
+
+        let mut resources = MaterialResources::of(self.materials.raw(parent)?);
+
+        if let Some(albedo) = overrides.albedo {
+            resources.albedo.texture = self.texture(albedo)?;
+        }
+
+        if let Some(sampler) = overrides.albedo_sampler {
+            resources.albedo.sampler = sampler;
+        }
+
+        if let Some(emissive) = overrides.emissive {
+            resources.emissive = Some(self.texture(emissive)?);
+        }
+
+        if let Some(factor) = overrides.emissive_factor {
+            resources.emissive_factor = factor;
+        }
+
+        for (binding, texture) in overrides.textures {
+            let texture = self.texture(texture.as_str())?;
+
+            match resources
+                .textures
+                .iter_mut()
+                .find(|(name, _)| *name == binding)
+            {
+                Some((_, handle)) => *handle = texture,
+                None => resources.textures.push((binding, texture)),
+            }
+        }
+
+        let material = self
+            .create_material(resources)
+            .with_context(|| format!("Failed to create material instance {:?}", name.as_ref()))?;
+        let material = named(&self.context, name.as_ref(), material);
+
+        self.materials
+            .insert(name, || Ok::<_, Infallible>(material))
+            .map_err(|e| match e {})
+    }
+
+    /// Creates a material from the resources it binds. The emissive bindings are always bound
+    /// for effects declaring them, and required otherwise if the material emits anything.
+    /// Lightmaps are required exactly when the effect samples one.
+    fn create_material(&mut self, resources: MaterialResources) -> Result<Material, Error> {
+        let MaterialResources {
+            effect,
+            albedo,
+            emissive,
+            emissive_factor,
+            lightmap,
+            buffers,
+            textures,
+        } = resources;
+
+        let effect_raw = self.effects.raw(effect)?;
+        let buffer_cache = &self.buffers;
+
+        let buffers = buffers
+            .iter()
+            .map(|(binding, handle)| -> Result<_, Error> {
+                let buffer = buffer_cache.raw(*handle)?;
+                Ok(MaterialBuffer::new(effect_raw, binding, *handle, buffer)?)
             })
-            .map_err(|e| e.into())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let textures = textures
+            .iter()
+            .map(|(binding, handle)| MaterialTextureBinding::new(effect_raw, binding, *handle))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let emissive = if emissive.is_some()
+            || emissive_factor != Vec3::zero()
+            || MaterialEmissive::supported(effect_raw)
+        {
+            Some(MaterialEmissive::new(
+                effect_raw,
+                emissive,
+                emissive_factor,
+            )?)
+        } else {
+            None
+        };
+
+        let lightmap = MaterialLightmap::new(effect_raw, lightmap)?;
+
+        Material::new(
+            self.context.clone(),
+            &mut self.descriptor_layouts,
+            &mut self.descriptor_allocator,
+            &mut self.samplers,
+            &self.textures,
+            effect,
+            MaterialBindings {
+                albedo,
+                emissive,
+                lightmap,
+                buffers,
+                textures,
+            },
+        )
+        .map_err(|e| e.into())
     }
 
     pub fn load_effect<S>(
         &mut self,
         name: S,
-        passes: Vec<Pipeline>,
+        passes: Vec<(PassTag, Pipeline)>,
     ) -> Result<Handle<MaterialEffect>, Error>
     where
         S: AsRef<str> + Into<String>,
     {
+        let context = self.context.clone();
+        let debug_name = name.as_ref().to_owned();
+
+        self.effects.insert(name, || {
+            let effect = MaterialEffect::new(passes);
+            effect.validate()?;
+
+            Ok(named(&context, &debug_name, effect))
+        })
+    }
+
+    /// Loads a variant of an effect compiled with `defines`. See `MaterialEffect::new_variant`.
+    pub fn load_effect_variant<S, F>(
+        &mut self,
+        name: S,
+        passes: &[(PassTag, PipelineInfo)],
+        defines: &[(String, String)],
+        create_pipeline: F,
+    ) -> Result<Handle<MaterialEffect>, Error>
+    where
+        S: AsRef<str> + Into<String>,
+        F: FnMut(PassTag, PipelineInfo) -> Result<Pipeline, vulkan::Error>,
+    {
+        let context = self.context.clone();
+        let debug_name = name.as_ref().to_owned();
+
         self.effects
-            .insert(name, || Ok(MaterialEffect::new(passes)))
+            .insert(name, || {
+                MaterialEffect::new_variant(passes, defines, create_pipeline)
+                    .map(|effect| named(&context, &debug_name, effect))
+            })
+            .map_err(|e| e.into())
+    }
+
+    /// Loads an effect described by a TOML file. The pipelines are created by `create_pipeline`
+    /// for the pass they are declared for, e.g; by the master renderer. See
+    /// `material::definition` for the format.
+    pub fn load_effect_file<S, P, F>(
+        &mut self,
+        name: S,
+        path: P,
+        mut create_pipeline: F,
+    ) -> Result<Handle<MaterialEffect>, Error>
+    where
+        S: AsRef<str> + Into<String>,
+        P: AsRef<Path>,
+        F: FnMut(PassTag, PipelineInfo) -> Result<Pipeline, vulkan::Error>,
+    {
+        if let Ok(effect) = self.effect(name.as_ref()) {
+            return Ok(effect);
+        }
+
+        let path = path.as_ref();
+        let passes = EffectDefinition::load(path)
+            .and_then(|effect| effect.pipeline_infos(path, self.context.msaa_samples()))
+            .and_then(|passes| {
+                passes
+                    .into_iter()
+                    .map(|(tag, info)| Ok((tag, create_pipeline(tag, info)?)))
+                    .collect::<Result<Vec<_>, Error>>()
+            })
+            .with_context(|| format!("Failed to load effect {:?}", path))?;
+
+        self.load_effect(name, passes)
+    }
+
+    /// Loads a material described by a TOML file along with the textures and parameter buffers
+    /// it declares. The effect needs to be loaded beforehand. Parameter buffers are named by
+    /// the material and binding, e.g; 'metal::material'. See `material::definition` for the
+    /// format.
+    pub fn load_material_file<S, P>(&mut self, name: S, path: P) -> Result<Handle<Material>, Error>
+    where
+        S: AsRef<str> + Into<String>,
+        P: AsRef<Path>,
+    {
+        if let Ok(material) = self.material(name.as_ref()) {
+            return Ok(material);
+        }
+
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let definition = MaterialDefinition::load(path)
+            .with_context(|| format!("Failed to load material {:?}", path))?;
+
+        for slot in definition.textures() {
+            if let TextureSlot::File { path: file, linear } = slot {
+                let color_space = if *linear {
+                    ColorSpace::Linear
+                } else {
+                    ColorSpace::Srgb
+                };
+
+                self.load_texture_with_color_space(
+                    slot.texture_name(dir),
+                    dir.join(file),
+                    color_space,
+                )?;
+            }
+        }
+
+        let buffers = definition
+            .parameters
+            .iter()
+            .map(|(binding, values)| -> Result<_, Error> {
+                let buffer = Buffer::new(
+                    self.context.clone(),
+                    vulkan::BufferType::Uniform,
+                    vulkan::BufferUsage::Staged,
+                    values,
+                )?;
+
+                let buffer_name = format!("{}::{}", name.as_ref(), binding);
+                self.insert_buffer(buffer_name.clone(), buffer);
+
+                Ok((binding.clone(), buffer_name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let info = definition.material_info(path, buffers)?;
+        self.load_material(name, info)
     }
 
+    /// Loads an sRGB color texture, e.g; for the albedo of materials.
     pub fn load_texture<P, S>(&mut self, name: S, path: P) -> Result<Handle<Texture>, Error>
     where
         P: AsRef<Path>,
         S: AsRef<str> + Into<String>,
     {
-        let context = self.context.clone();
+        self.load_texture_with_color_space(name, path, ColorSpace::Srgb)
+    }
 
-        self.textures
-            .insert(name, || Texture::load(context, path))
-            .map_err(|e| e.into())
+    /// Loads a texture whose values are interpreted in `color_space`. Normal maps and other
+    /// data textures need to be loaded as `ColorSpace::Linear`.
+    pub fn load_texture_with_color_space<P, S>(
+        &mut self,
+        name: S,
+        path: P,
+        color_space: ColorSpace,
+    ) -> Result<Handle<Texture>, Error>
+    where
+        P: AsRef<Path>,
+        S: AsRef<str> + Into<String>,
+    {
+        self.load_texture_with_info(
+            name,
+            path,
+            TextureLoadInfo {
+                color_space,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Loads a texture according to `info`, e.g; an OpenEXR or Radiance HDR environment as a
+    /// float texture. See `Texture::load_with_info`.
+    pub fn load_texture_with_info<P, S>(
+        &mut self,
+        name: S,
+        path: P,
+        info: TextureLoadInfo,
+    ) -> Result<Handle<Texture>, Error>
+    where
+        P: AsRef<Path>,
+        S: AsRef<str> + Into<String>,
+    {
+        if let Ok(texture) = self.texture(name.as_ref()) {
+            return Ok(texture);
+        }
+
+        let texture = Texture::load_with_info(self.context.clone(), &path, info)
+            .with_context(|| format!("Failed to load texture {:?}", path.as_ref()))?;
+        let handle = self.insert_texture(name, texture);
+
+        self.watcher
+            .watch(path, WatchedAsset::Texture(handle, info));
+
+        Ok(handle)
     }
 
     /// TODO extract gltf model
@@ -146,6 +624,20 @@ impl ResourceManager {
         mesh: gltf::Mesh,
         buffers: &[gltf::buffer::Data],
     ) -> Result<Handle<Mesh>, Error>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        self.load_mesh_with_settings(name, mesh, buffers, &MeshImportSettings::default())
+    }
+
+    /// Loads a gltf mesh processed according to `settings`
+    pub fn load_mesh_with_settings<S>(
+        &mut self,
+        name: S,
+        mesh: gltf::Mesh,
+        buffers: &[gltf::buffer::Data],
+        settings: &MeshImportSettings,
+    ) -> Result<Handle<Mesh>, Error>
     where
         S: AsRef<str> + Into<String>,
     {
@@ -153,15 +645,183 @@ impl ResourceManager {
 
         log::debug!("Loading mesh: {}", name.as_ref());
 
+        let debug_name = name.as_ref().to_owned();
+
+        self.meshes
+            .insert(name, || {
+                Mesh::from_gltf_with_settings(context.clone(), mesh, buffers, settings)
+                    .map(|mesh| named(&context, &debug_name, mesh))
+            })
+            .map_err(|e| e.into())
+    }
+
+    /// Uploads and inserts mesh data, e.g; generated or processed geometry.
+    pub fn load_mesh_data<S>(&mut self, name: S, data: &MeshData) -> Result<Handle<Mesh>, Error>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        let context = self.context.clone();
+
+        let debug_name = name.as_ref().to_owned();
+
         self.meshes
-            .insert(name, || Mesh::from_gltf(context, mesh, buffers))
+            .insert(name, || {
+                Mesh::from_data(context.clone(), data)
+                    .map(|mesh| named(&context, &debug_name, mesh))
+            })
             .map_err(|e| e.into())
     }
 
+    /// Processes and inserts mesh data according to `settings`
+    pub fn load_mesh_data_with_settings<S>(
+        &mut self,
+        name: S,
+        data: MeshData,
+        settings: &MeshImportSettings,
+    ) -> Result<Handle<Mesh>, Error>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        let context = self.context.clone();
+
+        let debug_name = name.as_ref().to_owned();
+
+        self.meshes.insert(name, || {
+            Mesh::from_data_with_settings(context.clone(), data, settings)
+                .map(|mesh| named(&context, &debug_name, mesh))
+        })
+    }
+
+    /// Removes a mesh, e.g; generated geometry which is no longer needed. The mesh is destroyed
+    /// once the frames in flight which may draw it have completed, and the handle is invalid
+    /// immediately.
+    pub fn unload_mesh(&mut self, handle: Handle<Mesh>) -> Result<(), Error> {
+        let mesh = self.meshes.remove(handle)?;
+        self.destruction_queue.retire(mesh);
+
+        // Recorded draws may refer to the mesh
+        self.generation += 1;
+
+        Ok(())
+    }
+
+    /// Generates a LOD chain of `levels` meshes by simplifying `data`. Each level has half the
+    /// triangles of the previous level. The meshes are inserted as '<name>_LOD<level>' and the
+    /// chain as '<name>'.
+    pub fn generate_lod_chain<S>(
+        &mut self,
+        name: S,
+        data: &MeshData,
+        levels: usize,
+    ) -> Result<Handle<LodChain>, Error>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        let levels = levels.max(1).min(lod::MAX_LOD_LEVELS);
+
+        let meshes = (0..levels)
+            .map(|level| {
+                let name = format!("{}{}{}", name.as_ref(), lod::LOD_SUFFIX, level);
+
+                if level == 0 {
+                    self.load_mesh_data(name, data)
+                } else {
+                    let ratio = 0.5_f32.powi(level as i32);
+                    self.load_mesh_data(name, &data.simplified(ratio))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(self.insert_lod_chain(name, LodChain::new(&meshes)))
+    }
+
+    /// Inserts a texture created elsewhere, e.g; the texture of a render target, which can be
+    /// used by materials by name. Returns the existing texture if one with the same name is
+    /// already present.
+    pub fn insert_texture<S>(&mut self, name: S, texture: Texture) -> Handle<Texture>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        let texture = named(&self.context, name.as_ref(), texture);
+        match self.textures.insert(name, || Ok::<_, Infallible>(texture)) {
+            Ok(handle) => handle,
+            Err(e) => match e {},
+        }
+    }
+
+    /// Inserts a user provided buffer which can be bound to materials by name. Returns the
+    /// existing buffer if one with the same name is already present.
+    pub fn insert_buffer<S>(&mut self, name: S, buffer: Buffer) -> Handle<Buffer>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        let buffer = named(&self.context, name.as_ref(), buffer);
+        match self.buffers.insert(name, || Ok::<_, Infallible>(buffer)) {
+            Ok(handle) => handle,
+            Err(e) => match e {},
+        }
+    }
+
+    /// Returns a mutable reference to a buffer for writing new contents.
+    pub fn buffer_mut(&mut self, handle: Handle<Buffer>) -> Result<&mut Buffer, resources::Error> {
+        self.buffers.raw_mut(handle)
+    }
+
+    /// Loads a WAV file for playback with `Audio`
+    pub fn load_sound<P, S>(&mut self, name: S, path: P) -> Result<Handle<Sound>, Error>
+    where
+        P: AsRef<Path>,
+        S: AsRef<str> + Into<String>,
+    {
+        self.sounds.insert(name, || Sound::load(path.as_ref()))
+    }
+
+    /// Inserts a sound created elsewhere, e.g; a generated tone. Returns the existing sound if
+    /// one with the same name is already present.
+    pub fn insert_sound<S>(&mut self, name: S, sound: Sound) -> Handle<Sound>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        match self.sounds.insert(name, || Ok::<_, Infallible>(sound)) {
+            Ok(handle) => handle,
+            Err(e) => match e {},
+        }
+    }
+
+    /// Inserts a LOD chain, e.g; of generated or manually loaded meshes.
+    /// Returns the existing chain if one with the same name is already present.
+    pub fn insert_lod_chain<S>(&mut self, name: S, lods: LodChain) -> Handle<LodChain>
+    where
+        S: AsRef<str> + Into<String>,
+    {
+        match self.lods.insert(name, || Ok::<_, Infallible>(lods)) {
+            Ok(handle) => handle,
+            Err(e) => match e {},
+        }
+    }
+
     /// Loads a document in gltf format from disk. Prefixes all names meshes by the provided
     /// document name
     /// along with '::' and inserts them into storage. E.g; 'map::Ground'
+    /// Unnamed meshes are named by their index, e.g; 'map::Mesh3'.
+    /// Meshes named by the '<name>_LOD<level>' convention are additionally grouped into a LOD
+    /// chain by the prefixed base name. E.g; 'map::Rock'
     pub fn load_document<P, S>(&mut self, name: S, path: P) -> Result<Handle<Document>, Error>
+    where
+        P: AsRef<Path>,
+        S: AsRef<str> + Into<String>,
+    {
+        self.load_document_with_settings(name, path, &MeshImportSettings::default())
+    }
+
+    /// Loads a document in gltf format from disk with the meshes processed according to
+    /// `settings`. See `load_document`.
+    pub fn load_document_with_settings<P, S>(
+        &mut self,
+        name: S,
+        path: P,
+        settings: &MeshImportSettings,
+    ) -> Result<Handle<Document>, Error>
     where
         P: AsRef<Path>,
         S: AsRef<str> + Into<String>,
@@ -170,22 +830,443 @@ impl ResourceManager {
             return Ok(document);
         }
 
-        let (document, buffers, _images) = gltf::import(path)?;
+        let (document, buffers, images) = gltf::import(&path)
+            .with_context(|| format!("Failed to load document {:?}", path.as_ref()))?;
 
         let name = name.into();
 
         let prefix = name.clone() + "::";
+
+        let textures = self
+            .load_document_images(&prefix, &document, &images)
+            .with_context(|| format!("Failed to load images of document {:?}", name))?;
+
+        let data = MeshData::from_gltf_document(path.as_ref(), &document, &buffers, settings)
+            .with_context(|| format!("Failed to load meshes of document {:?}", name))?;
+
+        let processed = processed_settings(settings);
+
         let meshes = document
             .meshes()
-            .filter_map(|mesh| match mesh.name() {
-                Some(name) => Some((mesh, name)),
-                None => None,
+            .zip(data)
+            .map(|(mesh, data)| {
+                let name = prefix.clone() + &mesh_name(&mesh);
+                self.load_mesh_data_with_settings(name.clone(), data, &processed)
+                    .with_context(|| format!("Failed to load mesh {:?}", name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Group the LOD levels by base name
+        let mut chains: HashMap<String, Vec<(usize, Handle<Mesh>)>> = HashMap::new();
+        for (mesh, handle) in document
+            .meshes()
+            .zip(meshes.iter())
+            .filter_map(|(mesh, handle)| Some((mesh.name()?, handle)))
+        {
+            if let Some((base, level)) = lod::parse_lod_name(mesh) {
+                chains
+                    .entry(prefix.clone() + base)
+                    .or_default()
+                    .push((level, *handle));
+            }
+        }
+
+        for (base, mut levels) in chains {
+            levels.sort_by_key(|(level, _)| *level);
+            levels.truncate(lod::MAX_LOD_LEVELS);
+
+            log::debug!("Loaded LOD chain: {} with {} levels", base, levels.len());
+
+            let meshes = levels.into_iter().map(|(_, mesh)| mesh).collect::<Vec<_>>();
+
+            self.insert_lod_chain(base, LodChain::new(&meshes));
+        }
+
+        self.watcher
+            .watch(path, WatchedAsset::Document(name.clone(), *settings));
+
+        self.documents.insert(name, || {
+            Ok(Document::from_gltf(document, &buffers, meshes, &textures))
+        })
+    }
+
+    /// Loads the objects of a Wavefront OBJ file processed according to `settings`. The meshes
+    /// are named by the object or group prefixed by `name` along with '::', e.g; 'crate::Lid'.
+    /// Objects using several materials are split into one mesh per material, with the following
+    /// meshes suffixed by their order, e.g; 'crate::Lid.1'.
+    /// The textures of the MTL materials are loaded as sRGB textures named by their file name,
+    /// e.g; 'crate::wood.png', and can be turned into materials with
+    /// `DocumentMaterial::material_info`.
+    pub fn load_obj<P, S>(
+        &mut self,
+        name: S,
+        path: P,
+        settings: &MeshImportSettings,
+    ) -> Result<Vec<ObjMeshHandle>, Error>
+    where
+        P: AsRef<Path>,
+        S: AsRef<str>,
+    {
+        let model = ObjModel::load(path.as_ref())
+            .with_context(|| format!("Failed to load OBJ {:?}", path.as_ref()))?;
+
+        let prefix = name.as_ref().to_owned() + "::";
+
+        let materials = model
+            .materials
+            .iter()
+            .map(|material| {
+                let mut load = |texture: &Option<PathBuf>| -> Result<_, Error> {
+                    let texture = match texture {
+                        Some(texture) => texture,
+                        None => return Ok(None),
+                    };
+
+                    let name =
+                        prefix.clone() + &texture.file_name().unwrap_or_default().to_string_lossy();
+
+                    self.load_texture(name.clone(), texture)?;
+                    Ok(Some(name))
+                };
+
+                Ok(DocumentMaterial {
+                    name: prefix.clone() + &material.name,
+                    albedo: load(&material.albedo)?,
+                    emissive: load(&material.emissive)?,
+                    emissive_factor: material.emissive_factor,
+                })
             })
-            .map(|(mesh, name)| self.load_mesh(prefix.clone() + name, mesh, &buffers))
-            .collect::<Result<_, _>>()?;
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut occurrences: HashMap<&str, usize> = HashMap::new();
+
+        model
+            .objects
+            .iter()
+            .map(|object| {
+                let occurrence = occurrences.entry(&object.name).or_default();
+                let name = match *occurrence {
+                    0 => prefix.clone() + &object.name,
+                    n => format!("{}{}.{}", prefix, object.name, n),
+                };
+                *occurrence += 1;
+
+                let material = object.material.as_ref().and_then(|material| {
+                    let index = model.materials.iter().position(|m| &m.name == material);
 
-        self.documents
-            .insert(name, || Ok(Document::from_gltf(document, meshes)))
+                    if index.is_none() {
+                        log::warn!("Missing material {:?} of OBJ mesh {:?}", material, name);
+                    }
+
+                    Some(materials[index?].clone())
+                });
+
+                let mesh = self
+                    .load_mesh_data_with_settings(name.clone(), object.data.clone(), settings)
+                    .with_context(|| format!("Failed to load mesh {:?}", name))?;
+
+                Ok(ObjMeshHandle { mesh, material })
+            })
+            .collect()
+    }
+
+    /// Loads the vertices and faces of a PLY file processed according to `settings`.
+    /// Point clouds without faces are never optimized and need to be drawn by an effect with a
+    /// point list topology.
+    pub fn load_ply<P, S>(
+        &mut self,
+        name: S,
+        path: P,
+        settings: &MeshImportSettings,
+    ) -> Result<Handle<Mesh>, Error>
+    where
+        P: AsRef<Path>,
+        S: AsRef<str> + Into<String>,
+    {
+        let ply = PlyMesh::load(path.as_ref())
+            .with_context(|| format!("Failed to load PLY {:?}", path.as_ref()))?;
+
+        let settings = if ply.is_point_cloud {
+            log::debug!("Loading point cloud: {}", name.as_ref());
+            processed_settings(settings)
+        } else {
+            *settings
+        };
+
+        self.load_mesh_data_with_settings(name, ply.data, &settings)
+    }
+
+    /// Loads the images used as base color or emissive textures by the materials of a glTF
+    /// document as sRGB textures named `prefix` followed by the image name. Already loaded
+    /// textures are replaced. Returns the texture name of each image, or None if unused or in
+    /// an unsupported format.
+    fn load_document_images(
+        &mut self,
+        prefix: &str,
+        document: &gltf::Document,
+        images: &[gltf::image::Data],
+    ) -> Result<Vec<Option<String>>, Error> {
+        let mut used = vec![false; images.len()];
+        for material in document.materials() {
+            let textures = [
+                material.pbr_metallic_roughness().base_color_texture(),
+                material.emissive_texture(),
+            ];
+
+            for info in textures.iter().flatten() {
+                used[info.texture().source().index()] = true;
+            }
+        }
+
+        document
+            .images()
+            .zip(images)
+            .zip(used)
+            .map(|((image, data), used)| {
+                let name = prefix.to_owned() + &image_name(&image);
+
+                if !used {
+                    return Ok(None);
+                }
+
+                let pixels = match rgba8_pixels(data) {
+                    Some(pixels) => pixels,
+                    None => {
+                        log::warn!("Unsupported format {:?} of image {:?}", data.format, name);
+                        return Ok(None);
+                    }
+                };
+
+                let texture = Texture::from_pixels(
+                    self.context.clone(),
+                    (data.width, data.height).into(),
+                    &pixels,
+                    ColorSpace::Srgb,
+                )?;
+
+                match self.textures.get(name.as_str()) {
+                    Ok(handle) => self.replace_texture(handle, texture)?,
+                    Err(_) => {
+                        self.insert_texture(name.clone(), texture);
+                    }
+                }
+
+                Ok(Some(name))
+            })
+            .collect()
+    }
+
+    /// Reloads the textures and documents whose files changed on disk since the last call into
+    /// their existing handles. Should be called once per frame before drawing, since replaced
+    /// resources are destroyed after the frames in flight which may use them.
+    /// Assets failing to reload are logged and keep their previous contents.
+    /// Returns the number of reloaded files.
+    pub fn reload_changed(&mut self) -> usize {
+        self.destruction_queue.advance();
+
+        let mut reloaded = 0;
+
+        for (path, asset) in self.watcher.changed() {
+            let result = match asset {
+                WatchedAsset::Texture(handle, info) => self.reload_texture(handle, &path, info),
+                WatchedAsset::Document(name, settings) => {
+                    self.reload_document(&name, &path, &settings)
+                }
+            };
+
+            match result {
+                Ok(()) => {
+                    log::info!("Reloaded {:?}", path);
+                    reloaded += 1;
+                }
+                Err(e) => log::error!("Failed to reload {:?}: {}", path, e),
+            }
+        }
+
+        if reloaded > 0 {
+            self.generation += 1;
+        }
+
+        reloaded
+    }
+
+    /// Returns a counter incremented whenever resources are reloaded. Commands recorded with
+    /// resources of an older generation need to be re-recorded.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Enables recording which textures and mip levels are sampled by the materials of drawn
+    /// objects. Meant for debugging, since every draw records its textures. Disabling discards
+    /// the recorded feedback.
+    pub fn set_texture_feedback(&mut self, enabled: bool) {
+        if enabled != self.texture_feedback.is_some() {
+            self.texture_feedback = if enabled {
+                Some(RefCell::new(TextureFeedback::new()))
+            } else {
+                None
+            };
+        }
+    }
+
+    pub fn texture_feedback_enabled(&self) -> bool {
+        self.texture_feedback.is_some()
+    }
+
+    /// Records that the textures of `material` were sampled by an object covering `pixels` on
+    /// screen. Does nothing unless texture feedback is enabled.
+    pub fn record_texture_samples(&self, material: Handle<Material>, pixels: f32) {
+        let mut feedback = match &self.texture_feedback {
+            Some(feedback) => feedback.borrow_mut(),
+            None => return,
+        };
+
+        let material = match self.materials.raw(material) {
+            Ok(material) => material,
+            Err(_) => return,
+        };
+
+        let textures = Some(material.albedo())
+            .into_iter()
+            .chain(material.emissive())
+            .chain(material.lightmap())
+            .chain(material.textures().iter().map(|(_, texture)| *texture));
+
+        for handle in textures {
+            if let Ok(texture) = self.textures.raw(handle) {
+                feedback.record(
+                    handle,
+                    estimate_mip(texture.extent(), texture.mip_levels(), pixels),
+                );
+            }
+        }
+    }
+
+    /// Returns how each loaded texture was sampled since the feedback was last cleared. Render
+    /// targets are excluded. Returns None unless texture feedback is enabled.
+    pub fn texture_report(&self) -> Option<Vec<TextureReport>> {
+        let feedback = self.texture_feedback.as_ref()?.borrow();
+
+        let report = self
+            .textures
+            .iter()
+            .filter(|(_, texture)| texture.usage() == TextureUsage::Sampled)
+            .map(|(handle, texture)| TextureReport {
+                name: self.textures.name(handle).unwrap_or_default().to_owned(),
+                extent: texture.extent(),
+                mip_levels: texture.mip_levels(),
+                sampling: feedback.get(handle),
+            })
+            .collect();
+
+        Some(report)
+    }
+
+    /// Forgets the recorded texture feedback.
+    pub fn clear_texture_feedback(&self) {
+        if let Some(feedback) = &self.texture_feedback {
+            feedback.borrow_mut().clear();
+        }
+    }
+
+    fn reload_texture(
+        &mut self,
+        handle: Handle<Texture>,
+        path: &Path,
+        info: TextureLoadInfo,
+    ) -> Result<(), Error> {
+        let texture = Texture::load_with_info(self.context.clone(), path, info)?;
+        self.replace_texture(handle, texture)
+    }
+
+    /// Replaces the texture of `handle` and recreates the materials using it.
+    fn replace_texture(&mut self, handle: Handle<Texture>, texture: Texture) -> Result<(), Error> {
+        if let Some(name) = self.textures.name(handle) {
+            texture.set_debug_name(&self.context, name);
+        }
+
+        let old = self.textures.replace(handle, texture)?;
+        self.destruction_queue.retire(old);
+
+        // The descriptor sets of materials refer to the image view of the old texture
+        let materials = self
+            .materials
+            .iter()
+            .filter(|(_, material)| {
+                material.albedo() == handle
+                    || material.emissive() == Some(handle)
+                    || material.lightmap() == Some(handle)
+                    || material
+                        .textures()
+                        .iter()
+                        .any(|(_, texture)| *texture == handle)
+            })
+            .map(|(material, _)| material)
+            .collect::<Vec<_>>();
+
+        for material in materials {
+            let old = self.materials.raw(material)?;
+            let resources = MaterialResources::of(old);
+
+            // The shared set must not be reused by the recreated materials
+            self.descriptor_allocator.invalidate(old.set());
+
+            let new = self.create_material(resources)?;
+
+            if let Some(name) = self.materials.name(material) {
+                new.set_debug_name(&self.context, name);
+            }
+
+            let old = self.materials.replace(material, new)?;
+            self.destruction_queue.retire(old);
+        }
+
+        Ok(())
+    }
+
+    fn reload_document(
+        &mut self,
+        name: &str,
+        path: &Path,
+        settings: &MeshImportSettings,
+    ) -> Result<(), Error> {
+        let (document, buffers, images) = gltf::import(path)?;
+
+        let prefix = name.to_owned() + "::";
+
+        let textures = self.load_document_images(&prefix, &document, &images)?;
+
+        let data = MeshData::from_gltf_document(path, &document, &buffers, settings)?;
+        let processed = processed_settings(settings);
+
+        let meshes = document
+            .meshes()
+            .zip(data)
+            .map(|(mesh, data)| {
+                let mesh_name = prefix.clone() + &mesh_name(&mesh);
+
+                match self.meshes.get(mesh_name.as_str()) {
+                    Ok(handle) => {
+                        let new =
+                            Mesh::from_data_with_settings(self.context.clone(), data, &processed)?;
+                        new.set_debug_name(&self.context, &mesh_name);
+
+                        let old = self.meshes.replace(handle, new)?;
+                        self.destruction_queue.retire(old);
+                        Ok(handle)
+                    }
+                    Err(_) => self.load_mesh_data_with_settings(mesh_name, data, &processed),
+                }
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let handle = self.documents.get(name)?;
+        self.documents.replace(
+            handle,
+            Document::from_gltf(document, &buffers, meshes, &textures),
+        )?;
+
+        Ok(())
     }
 
     /// Get a reference to the resource manager's textures.
@@ -207,4 +1288,80 @@ impl ResourceManager {
     pub fn meshes(&self) -> &ResourceCache<Mesh> {
         &self.meshes
     }
+
+    /// Get a reference to the resource manager's LOD chains.
+    pub fn lods(&self) -> &ResourceCache<LodChain> {
+        &self.lods
+    }
+
+    /// Returns a reference to the resource manager's buffers.
+    pub fn buffers(&self) -> &ResourceCache<Buffer> {
+        &self.buffers
+    }
+
+    /// Returns a reference to the resource manager's sounds.
+    pub fn sounds(&self) -> &ResourceCache<Sound> {
+        &self.sounds
+    }
+}
+
+/// Returns the name of a glTF mesh. Unnamed meshes are named by index to keep the mesh indices
+/// of nodes valid.
+/// Names the Vulkan objects of a resource after its name in the cache for debugging tools
+fn named<R: DebugName>(context: &VulkanContext, name: &str, resource: R) -> R {
+    resource.set_debug_name(context, name);
+    resource
+}
+
+// Returns the settings to upload meshes already processed according to `settings` with
+fn processed_settings(settings: &MeshImportSettings) -> MeshImportSettings {
+    MeshImportSettings {
+        optimize: false,
+        ..*settings
+    }
+}
+
+fn mesh_name(mesh: &gltf::Mesh) -> String {
+    match mesh.name() {
+        Some(name) => name.to_owned(),
+        None => format!("Mesh{}", mesh.index()),
+    }
+}
+
+/// Returns the name of a glTF image. Unnamed images are named by index.
+fn image_name(image: &gltf::Image) -> String {
+    match image.name() {
+        Some(name) => name.to_owned(),
+        None => format!("Image{}", image.index()),
+    }
+}
+
+/// Expands the pixels of an 8 bit glTF image to RGBA. Returns None for 16 bit formats.
+fn rgba8_pixels(image: &gltf::image::Data) -> Option<Vec<u8>> {
+    use gltf::image::Format;
+
+    let pixels = &image.pixels;
+    let rgba = match image.format {
+        Format::R8 => pixels.iter().flat_map(|&r| [r, r, r, 255]).collect(),
+        Format::R8G8 => pixels
+            .chunks_exact(2)
+            .flat_map(|c| [c[0], c[1], 0, 255])
+            .collect(),
+        Format::R8G8B8 => pixels
+            .chunks_exact(3)
+            .flat_map(|c| [c[0], c[1], c[2], 255])
+            .collect(),
+        Format::R8G8B8A8 => pixels.clone(),
+        Format::B8G8R8 => pixels
+            .chunks_exact(3)
+            .flat_map(|c| [c[2], c[1], c[0], 255])
+            .collect(),
+        Format::B8G8R8A8 => pixels
+            .chunks_exact(4)
+            .flat_map(|c| [c[2], c[1], c[0], c[3]])
+            .collect(),
+        _ => return None,
+    };
+
+    Some(rgba)
 }
@@ -1,9 +1,15 @@
 mod cache;
+mod destruction;
 mod errors;
+mod feedback;
 mod handle;
 mod manager;
+mod watcher;
 
 pub use cache::*;
+pub use destruction::*;
 pub use errors::*;
+pub use feedback::*;
 pub use handle::*;
 pub use manager::*;
+pub use watcher::*;
@@ -0,0 +1,62 @@
+//! Polling based change detection of the files resources were loaded from.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A watched file along with the asset loaded from it
+struct WatchedFile<A> {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    asset: A,
+}
+
+/// Detects modifications of files by comparing their modification times.
+pub struct FileWatcher<A> {
+    files: Vec<WatchedFile<A>>,
+}
+
+impl<A: Clone> FileWatcher<A> {
+    pub fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    /// Starts watching the file at `path` which `asset` was loaded from.
+    pub fn watch<P: AsRef<Path>>(&mut self, path: P, asset: A) {
+        let path = path.as_ref().to_owned();
+        let modified = modified_time(&path);
+
+        self.files.push(WatchedFile {
+            path,
+            modified,
+            asset,
+        });
+    }
+
+    /// Returns the assets whose files were modified since the last call. Files which are
+    /// temporarily missing, e.g; while being saved, are reported once they reappear.
+    pub fn changed(&mut self) -> Vec<(PathBuf, A)> {
+        self.files
+            .iter_mut()
+            .filter_map(|file| {
+                let modified = modified_time(&file.path)?;
+
+                if file.modified == Some(modified) {
+                    return None;
+                }
+
+                file.modified = Some(modified);
+                Some((file.path.clone(), file.asset.clone()))
+            })
+            .collect()
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+impl<A: Clone> Default for FileWatcher<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,31 +1,471 @@
-use super::Object;
+use std::{collections::BTreeMap, mem, ops::RangeBounds};
+
+use ultraviolet::{Mat4, Rotor3, Vec3};
+
+use super::aabb::Aabb;
+use super::bvh::Bvh;
+use super::document::Transform;
+use super::ecs::{Entity, Static, World};
+use super::fog::Fog;
+use super::frustum::Frustum;
+use super::layers::Layers;
+use super::lod::LodChain;
+use super::object::SCALE;
+use super::raycast::{self, Ray, RayHit};
+use super::resources::{Handle, ResourceCache, ResourceManager};
+use super::water::WaterSettings;
+use super::{Error, Light, Material, Mesh, MeshData, MeshImportSettings, Object};
+
+/// A reference to an object of the scene which, unlike its index, stays valid when other objects
+/// are added, baked or mirrored from the world
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectHandle {
+    /// An object added through `Scene::add`
+    Added(u32),
+    /// An object mirrored from an entity of the world
+    Entity(Entity),
+}
 
 pub struct Scene {
     objects: Vec<Object>,
+    /// The id of each object added directly, in the order of the objects
+    added: Vec<u32>,
+    next_id: u32,
+    /// Incremented whenever objects are changed through the scene
+    revision: u64,
+    /// The revision each object was last changed at, in the order of the objects
+    changed: Vec<u64>,
+    lights: Vec<Light>,
+    fog: Option<Fog>,
+    water: WaterSettings,
+    visible_layers: Layers,
+    /// Updated once per frame by the master renderer
+    bvh: Bvh,
     modified: bool,
+    /// Optional component storage mirrored into the objects and lights by `sync_world`
+    world: World,
+    /// The entity of each object mirrored from the world, which follow the objects added
+    /// directly
+    world_objects: Vec<Entity>,
+    /// The number of lights mirrored from the world, which follow the lights added directly
+    world_lights: usize,
 }
 
 impl Scene {
     pub fn new() -> Self {
         Self {
             objects: Vec::new(),
+            added: Vec::new(),
+            next_id: 0,
+            revision: 0,
+            changed: Vec::new(),
+            lights: Vec::new(),
+            fog: None,
+            water: WaterSettings::default(),
+            visible_layers: Layers::ALL,
+            bvh: Bvh::new(),
             modified: false,
+            world: World::new(),
+            world_objects: Vec::new(),
+            world_lights: 0,
         }
     }
 
-    pub fn add(&mut self, object: Object) {
-        self.objects.push(object);
+    pub fn add(&mut self, object: Object) -> ObjectHandle {
+        let index = self.objects.len() - self.world_objects.len();
+        self.objects.insert(index, object);
         self.modified = true;
+
+        // The objects mirrored from the world move up by one
+        self.touch(index..);
+
+        self.push_id()
+    }
+
+    /// Returns the handle of the object at `index`
+    pub fn handle(&self, index: usize) -> Option<ObjectHandle> {
+        match self.entity(index) {
+            Some(entity) => Some(ObjectHandle::Entity(entity)),
+            None => self.added.get(index).copied().map(ObjectHandle::Added),
+        }
+    }
+
+    /// Returns the current index of the object referenced by `handle`, or None if it was baked
+    /// or its entity no longer has the components of an object.
+    pub fn index_of(&self, handle: ObjectHandle) -> Option<usize> {
+        match handle {
+            ObjectHandle::Added(id) => self.added.iter().position(|added| *added == id),
+            ObjectHandle::Entity(entity) => self
+                .world_objects
+                .iter()
+                .position(|world_object| *world_object == entity)
+                .map(|i| self.added.len() + i),
+        }
+    }
+
+    pub fn object(&self, handle: ObjectHandle) -> Option<&Object> {
+        self.index_of(handle).map(|index| &self.objects[index])
+    }
+
+    /// Returns the object referenced by `handle` for writing and marks it as changed
+    pub fn object_mut(&mut self, handle: ObjectHandle) -> Option<&mut Object> {
+        let index = self.index_of(handle)?;
+        self.object_at_mut(index)
+    }
+
+    /// Returns the object at `index` for writing and marks it as changed
+    pub fn object_at_mut(&mut self, index: usize) -> Option<&mut Object> {
+        if index >= self.objects.len() {
+            return None;
+        }
+
+        self.touch(index..=index);
+        Some(&mut self.objects[index])
     }
 
     pub fn objects(&self) -> &[Object] {
         &self.objects
     }
 
+    /// Returns all objects for writing and marks all of them as changed. Prefer `object_mut`
+    /// or `object_at_mut` when only some objects change.
     pub fn objects_mut(&mut self) -> &mut [Object] {
+        self.touch(..);
         &mut self.objects
     }
 
+    /// Returns the current revision of the objects, which is incremented whenever objects are
+    /// added or written through the scene
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Returns the indices of the objects which changed after `revision` in ascending order.
+    /// All objects are changed after revision 0.
+    pub fn changed_since(&self, revision: u64) -> impl Iterator<Item = usize> + '_ {
+        self.changed
+            .iter()
+            .enumerate()
+            .filter(move |(_, changed)| **changed > revision)
+            .map(|(i, _)| i)
+    }
+
+    /// Adds a light. Lights can be changed freely without re-recording the static objects.
+    pub fn add_light(&mut self, light: Light) {
+        let index = self.lights.len() - self.world_lights;
+        self.lights.insert(index, light);
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// Returns the lights added directly. The lights mirrored from the world are removed until
+    /// the next `sync_world`.
+    pub fn lights_mut(&mut self) -> &mut Vec<Light> {
+        self.lights.truncate(self.lights.len() - self.world_lights);
+        self.world_lights = 0;
+        &mut self.lights
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    /// Returns the entity the object at `index` was mirrored from, or None if the object was
+    /// added directly.
+    pub fn entity(&self, index: usize) -> Option<Entity> {
+        let first = self.objects.len() - self.world_objects.len();
+        self.world_objects.get(index.checked_sub(first)?).copied()
+    }
+
+    /// Mirrors the entities of the world into objects and lights, which are placed after those
+    /// added directly. Called once per frame by the master renderer.
+    /// Entities with a `Transform`, `Handle<Mesh>` and `Handle<Material>` become objects, which
+    /// use the optional `Handle<LodChain>`, `Layers` and `Static` components. Entities with a
+    /// `Light` become lights, placed relative to the `Transform` if any.
+    pub fn sync_world(&mut self) {
+        let world = &self.world;
+
+        if world.is_changed() {
+            self.objects
+                .truncate(self.objects.len() - self.world_objects.len());
+            self.world_objects.clear();
+
+            for (entity, mesh, material, transform) in
+                world.query3::<Handle<Mesh>, Handle<Material>, Transform>()
+            {
+                self.objects.push(Object {
+                    material: *material,
+                    mesh: *mesh,
+                    lods: world.get::<Handle<LodChain>>(entity).copied(),
+                    position: transform.position,
+                    rotation: transform.rotation,
+                    scale: transform.scale,
+                    is_static: world.has::<Static>(entity),
+                    layers: world.get::<Layers>(entity).copied().unwrap_or_default(),
+                });
+
+                self.world_objects.push(entity);
+            }
+        }
+
+        self.lights.truncate(self.lights.len() - self.world_lights);
+        self.world_lights = 0;
+
+        for (entity, light) in world.query::<Light>() {
+            let mut light = *light;
+            if let Some(transform) = world.get::<Transform>(entity) {
+                light.position = transform.position + transform.rotation * light.position;
+            }
+
+            self.lights.push(light);
+            self.world_lights += 1;
+        }
+
+        if self.world.is_changed() {
+            // The objects mirrored from the world are recreated
+            let first = self.objects.len() - self.world_objects.len();
+            self.touch(first..);
+
+            self.modified = true;
+            self.world.clear_changed();
+        }
+    }
+
+    pub fn fog(&self) -> Option<&Fog> {
+        self.fog.as_ref()
+    }
+
+    /// Sets the fog applied by lit effects, or disables it if None. Takes effect on the next
+    /// frame without re-recording the static objects.
+    pub fn set_fog(&mut self, fog: Option<Fog>) {
+        self.fog = fog;
+    }
+
+    pub fn water(&self) -> &WaterSettings {
+        &self.water
+    }
+
+    /// Sets the appearance of all water surfaces. Takes effect on the next frame.
+    pub fn set_water(&mut self, water: WaterSettings) {
+        self.water = water;
+    }
+
+    /// Returns the layers drawn by any camera. All layers are visible by default.
+    pub fn visible_layers(&self) -> Layers {
+        self.visible_layers
+    }
+
+    pub fn set_visible_layers(&mut self, layers: Layers) {
+        self.visible_layers = layers;
+    }
+
+    /// Shows or hides the objects in `layers` for all cameras and passes.
+    pub fn set_layers_visible(&mut self, layers: Layers, visible: bool) {
+        if visible {
+            self.visible_layers |= layers;
+        } else {
+            self.visible_layers &= !layers;
+        }
+    }
+
+    /// Returns true if the object at `index` is in a visible layer included in `layers`.
+    pub fn is_drawn(&self, index: usize, layers: Layers) -> bool {
+        self.objects[index]
+            .layers
+            .intersects(layers & self.visible_layers)
+    }
+
+    /// Merges the static objects sharing material and layers into a single object each, whose
+    /// mesh holds the geometry of all of them in world space. Reduces the draw calls of level
+    /// geometry which never moves. The merged meshes are inserted as '<name>::<batch>', so `name`
+    /// needs to differ between calls.
+    /// Only objects without LODs whose mesh keeps its geometry are merged, see
+    /// `MeshImportSettings::keep_geometry`. Dynamic objects and objects mirrored from the world
+    /// are left as is.
+    /// The merged objects are moved after the other objects added directly, which invalidates
+    /// the object indices and the handles of the merged objects. Returns the number of objects
+    /// removed.
+    pub fn bake_static(
+        &mut self,
+        name: &str,
+        resources: &mut ResourceManager,
+    ) -> Result<usize, Error> {
+        let mut groups = BTreeMap::<(Handle<Material>, u32), Vec<usize>>::new();
+
+        // Objects mirrored from the world are left as is
+        let first = self.objects.len() - self.world_objects.len();
+
+        for (i, object) in self.objects[..first].iter().enumerate() {
+            let has_geometry = resources
+                .meshes()
+                .raw(object.mesh)
+                .map(|mesh| mesh.geometry().is_some())
+                .unwrap_or(false);
+
+            if object.is_static && object.lods.is_none() && has_geometry {
+                groups
+                    .entry((object.material, object.layers.0))
+                    .or_default()
+                    .push(i);
+            }
+        }
+
+        // The merged objects are drawn with the uniform object scale
+        let to_batch = Mat4::from_scale(1.0 / SCALE);
+
+        let mut merged = vec![false; first];
+        let mut batches = Vec::new();
+
+        for ((material, layers), indices) in groups {
+            if indices.len() < 2 {
+                continue;
+            }
+
+            let mut data = MeshData::new(Vec::new(), Vec::new());
+
+            for &i in &indices {
+                let object = &self.objects[i];
+                let mesh = resources.meshes().raw(object.mesh)?;
+
+                if let Some(geometry) = mesh.geometry() {
+                    let mut object_data = geometry.to_data();
+                    object_data.transform(&(to_batch * object.model_matrix()));
+                    data.append(&object_data);
+                }
+
+                merged[i] = true;
+            }
+
+            let mesh = resources.load_mesh_data_with_settings(
+                format!("{}::{}", name, batches.len()),
+                data,
+                &MeshImportSettings {
+                    optimize: true,
+                    keep_geometry: true,
+                    ..Default::default()
+                },
+            )?;
+
+            batches.push(Object {
+                material,
+                mesh,
+                lods: None,
+                position: Vec3::zero(),
+                rotation: Rotor3::identity(),
+                scale: Vec3::one(),
+                is_static: true,
+                layers: Layers(layers),
+            });
+        }
+
+        let removed = merged.iter().filter(|merged| **merged).count() - batches.len();
+
+        self.added = mem::take(&mut self.added)
+            .into_iter()
+            .zip(&merged)
+            .filter(|(_, merged)| !**merged)
+            .map(|(id, _)| id)
+            .collect();
+
+        for _ in 0..batches.len() {
+            self.push_id();
+        }
+
+        let mirrored = self.objects.split_off(first);
+
+        self.objects = mem::take(&mut self.objects)
+            .into_iter()
+            .zip(merged)
+            .filter(|(_, merged)| !merged)
+            .map(|(object, _)| object)
+            .chain(batches)
+            .chain(mirrored)
+            .collect();
+
+        // The object indices changed
+        self.bvh = Bvh::new();
+        self.modified = true;
+        self.touch(..);
+
+        Ok(removed)
+    }
+
+    /// Returns the closest object hit by `ray`. Objects are tested against the triangles of
+    /// their mesh if it was imported with `MeshImportSettings::keep_geometry`, and against
+    /// their bounding box otherwise. LODs and objects in hidden layers are ignored.
+    pub fn raycast(&self, ray: &Ray, meshes: &ResourceCache<Mesh>) -> Option<RayHit> {
+        let mut candidates = Vec::new();
+        self.bvh.query_ray(ray, |object| candidates.push(object));
+        candidates.extend(self.bvh.object_count()..self.objects.len());
+
+        candidates
+            .into_iter()
+            .filter(|i| self.is_drawn(*i, Layers::ALL))
+            .filter_map(|i| {
+                let object = &self.objects[i];
+                let mesh = meshes.raw(object.mesh).ok()?;
+                let (distance, normal) = raycast::intersect_object(ray, object, mesh)?;
+
+                Some(RayHit {
+                    object: i,
+                    distance,
+                    normal,
+                })
+            })
+            .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())
+    }
+
+    /// Updates the bounding volume hierarchy with the current bounds of the objects. Needs to be
+    /// called after objects are added or moved for them to be found by `raycast` and `cull`.
+    pub fn update_bvh(&mut self, meshes: &ResourceCache<Mesh>) {
+        let bounds = self.object_bounds(meshes);
+        self.bvh.update(bounds);
+    }
+
+    /// Returns the bounding volume hierarchy over the object bounds as of the last
+    /// `update_bvh`.
+    pub fn bvh(&self) -> &Bvh {
+        &self.bvh
+    }
+
+    /// Returns whether each object may be visible in `frustum`. Objects added since the last
+    /// `update_bvh` are always visible.
+    pub fn cull(&self, frustum: &Frustum) -> Vec<bool> {
+        let mut visible = vec![false; self.objects.len()];
+        self.bvh
+            .query_frustum(frustum, |object| visible[object] = true);
+
+        for visible in visible.iter_mut().skip(self.bvh.object_count()) {
+            *visible = true;
+        }
+
+        visible
+    }
+
+    /// Returns the world space bounding box of each object in order. Objects whose mesh is no
+    /// longer valid are None.
+    pub fn object_bounds(&self, meshes: &ResourceCache<Mesh>) -> Vec<Option<Aabb>> {
+        self.objects
+            .iter()
+            .map(|object| Some(object.aabb(meshes.raw(object.mesh).ok()?)))
+            .collect()
+    }
+
+    /// Returns the world space bounding box containing all objects, or None if the scene is
+    /// empty.
+    pub fn bounds(&self, meshes: &ResourceCache<Mesh>) -> Option<Aabb> {
+        self.object_bounds(meshes)
+            .into_iter()
+            .flatten()
+            .reduce(|acc, aabb| acc.union(&aabb))
+    }
+
     pub fn is_modified(&self) -> bool {
         self.modified
     }
@@ -33,4 +473,72 @@ impl Scene {
     pub fn clear_modified(&mut self) {
         self.modified = false
     }
+
+    // Marks the objects in `range` as changed at a new revision
+    fn touch(&mut self, range: impl RangeBounds<usize>) {
+        self.revision += 1;
+        self.changed.resize(self.objects.len(), self.revision);
+
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        for changed in &mut self.changed[range] {
+            *changed = self.revision;
+        }
+    }
+
+    // Assigns an id to the last object added directly
+    fn push_id(&mut self) -> ObjectHandle {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.added.push(id);
+
+        ObjectHandle::Added(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use generational_arena::Index;
+
+    use super::*;
+
+    fn object() -> Object {
+        Object {
+            material: Index::from_raw_parts(0, 0).into(),
+            mesh: Index::from_raw_parts(0, 0).into(),
+            lods: None,
+            position: Vec3::zero(),
+            rotation: Rotor3::identity(),
+            scale: Vec3::one(),
+            is_static: false,
+            layers: Layers::default(),
+        }
+    }
+
+    #[test]
+    fn changed_objects() {
+        let mut scene = Scene::new();
+
+        for _ in 0..4 {
+            scene.add(object());
+        }
+
+        assert_eq!(scene.changed_since(0).count(), 4);
+
+        let revision = scene.revision();
+        assert_eq!(scene.changed_since(revision).count(), 0);
+
+        scene.object_at_mut(2).unwrap().position.x = 1.0;
+        assert_eq!(scene.changed_since(revision).collect::<Vec<_>>(), vec![2]);
+
+        let handle = scene.handle(1).unwrap();
+        scene.object_mut(handle).unwrap().position.y = 1.0;
+        assert_eq!(
+            scene.changed_since(revision).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        let revision = scene.revision();
+        scene.objects_mut();
+        assert_eq!(scene.changed_since(revision).count(), 4);
+    }
 }
@@ -0,0 +1,171 @@
+//! Variable rate shading of the main pass, which shades the periphery of the screen at a lower
+//! rate than the center, e.g; for performance scaling on GPUs with fill rate to spare at the
+//! center but not across the whole screen.
+//!
+//! The rates are read from a shading rate image generated on the CPU when the swapchain is
+//! created or resized. The rates only depend on the distance from the center of the screen, as
+//! there is no velocity buffer to derive rates for moving regions from.
+//!
+//! Requires `DeviceFeatures::fragment_shading_rate` with shading rate attachments, which are
+//! only used with dynamic rendering.
+use ash::vk;
+use std::rc::Rc;
+
+use super::vulkan;
+use vulkan::shading_rate::{ATTACHMENT_FORMAT, ATTACHMENT_LAYOUT};
+use vulkan::{Extent, ShadingRateAttachment, Texture, TextureInfo, TextureUsage, VulkanContext};
+
+/// The preferred size in pixels of each texel of the shading rate image. Clamped to the texel
+/// sizes supported by the device.
+const TEXEL_SIZE: u32 = 16;
+
+/// Specifies how the shading rate falls off towards the periphery of the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadingRateInfo {
+    /// The distance from the center of the screen within which every pixel is shaded, where 1.0
+    /// is the distance to the middle of each edge
+    pub inner_radius: f32,
+    /// The distance from the center beyond which the coarsest rate is used. The fragment size
+    /// doubles in steps between the two radii.
+    pub outer_radius: f32,
+    /// The width and height in pixels of the coarsest fragment, e.g; 4 for a single fragment
+    /// per 4x4 pixels. Rounded down to a power of two and clamped to
+    /// `DeviceCapabilities::max_fragment_size`.
+    pub max_fragment_size: u32,
+}
+
+impl Default for ShadingRateInfo {
+    fn default() -> Self {
+        Self {
+            inner_radius: 0.6,
+            outer_radius: 1.1,
+            max_fragment_size: 4,
+        }
+    }
+}
+
+impl ShadingRateInfo {
+    /// Returns the shading rate image texels of a `extent` sized image, encoded as the log2 of
+    /// the fragment width in bits 2..3 and the log2 of the fragment height in bits 0..1.
+    /// `max_fragment_size` is the largest fragment supported by the device.
+    pub fn rates(&self, extent: Extent, max_fragment_size: Extent) -> Vec<u8> {
+        let max_size = self
+            .max_fragment_size
+            .min(max_fragment_size.width)
+            .min(max_fragment_size.height)
+            .max(1);
+
+        // The rate is lowered one step at a time by doubling the fragment size
+        let max_level = 31 - max_size.leading_zeros();
+
+        let falloff = (self.outer_radius - self.inner_radius).max(f32::EPSILON);
+
+        (0..extent.height)
+            .flat_map(|y| (0..extent.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                // Distance of the texel center from the center of the screen
+                let x = (x as f32 + 0.5) / extent.width as f32 * 2.0 - 1.0;
+                let y = (y as f32 + 0.5) / extent.height as f32 * 2.0 - 1.0;
+                let distance = (x * x + y * y).sqrt();
+
+                let t = ((distance - self.inner_radius) / falloff).clamp(0.0, 1.0);
+                let level = (t * max_level as f32).round() as u8;
+
+                (level << 2) | level
+            })
+            .collect()
+    }
+}
+
+/// The shading rate image of the main pass, recreated with the swapchain
+pub struct ShadingRateImage {
+    context: Rc<VulkanContext>,
+    info: ShadingRateInfo,
+    texel_size: Extent,
+    texture: Texture,
+}
+
+impl ShadingRateImage {
+    /// Creates and fills the shading rate image covering `extent`. Returns None if the device
+    /// does not support shading rate attachments.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        info: ShadingRateInfo,
+        extent: Extent,
+    ) -> Result<Option<Self>, vulkan::Error> {
+        let [min, max] = match context.capabilities().shading_rate_texel_size_range {
+            Some(range) if context.capabilities().features.fragment_shading_rate => range,
+            _ => return Ok(None),
+        };
+
+        let texel_size = Extent::new(
+            TEXEL_SIZE.max(min.width).min(max.width),
+            TEXEL_SIZE.max(min.height).min(max.height),
+        );
+
+        let texture = create_texture(&context, &info, extent, texel_size)?;
+
+        Ok(Some(Self {
+            context,
+            info,
+            texel_size,
+            texture,
+        }))
+    }
+
+    /// Recreates the image to cover `extent`
+    pub fn resize(&mut self, extent: Extent) -> Result<(), vulkan::Error> {
+        self.texture = create_texture(&self.context, &self.info, extent, self.texel_size)?;
+        Ok(())
+    }
+
+    /// Returns the attachment to begin the main pass with
+    pub fn attachment(&self) -> ShadingRateAttachment {
+        ShadingRateAttachment {
+            image_view: self.texture.image_view(),
+            texel_size: self.texel_size,
+        }
+    }
+
+    pub fn info(&self) -> &ShadingRateInfo {
+        &self.info
+    }
+
+    /// Returns the size in pixels of each texel of the image
+    pub fn texel_size(&self) -> Extent {
+        self.texel_size
+    }
+}
+
+// Creates an image with a texel for each `texel_size` block of `extent` and fills it with the
+// rates of `info`
+fn create_texture(
+    context: &Rc<VulkanContext>,
+    info: &ShadingRateInfo,
+    extent: Extent,
+    texel_size: Extent,
+) -> Result<Texture, vulkan::Error> {
+    let extent = Extent::new(
+        extent.width.div_ceil(texel_size.width),
+        extent.height.div_ceil(texel_size.height),
+    );
+
+    let texture = Texture::new(
+        context.clone(),
+        TextureInfo {
+            extent,
+            mip_levels: 1,
+            usage: TextureUsage::ShadingRate,
+            format: ATTACHMENT_FORMAT,
+            samples: vk::SampleCountFlags::TYPE_1,
+            ..Default::default()
+        },
+    )?;
+
+    let rates = info.rates(extent, context.capabilities().max_fragment_size);
+
+    texture.write(rates.len() as vk::DeviceSize, &rates)?;
+    texture.transition_layout(ATTACHMENT_LAYOUT)?;
+
+    Ok(texture)
+}
@@ -0,0 +1,602 @@
+//! Cascaded shadow maps for a directional light. The view frustum of the camera is split into
+//! cascades along the view direction, which are each rendered from the light into a layer of a
+//! depth array texture.
+use arrayvec::ArrayVec;
+use ash::vk;
+use std::{mem, rc::Rc, slice};
+use ultraviolet::{Mat4, Vec3, Vec4};
+
+use crate::mesh_renderer::{MeshRenderer, MeshRendererInfo};
+use crate::resources::*;
+use crate::{Camera, Layers, PassTag, Projection, Scene};
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::descriptors::*;
+use vulkan::dynamic_rendering::DynamicRendering;
+use vulkan::renderpass::*;
+use vulkan::sampler::*;
+use vulkan::texture::*;
+use vulkan::*;
+
+/// The maximum number of cascades of a shadow map
+pub const MAX_CASCADES: usize = 4;
+
+/// The descriptor set index of the shadow set in material effect shaders. Holds the shadow map
+/// at binding 0 and the cascade uniform at binding 1, which are only accessible from the
+/// fragment stage. See `data/shaders/shadow.glsl`.
+pub const SHADOW_SET: u32 = 2;
+
+/// Depth formats of shadow maps in order of preference. Devices are required to support
+/// sampling and rendering to the last one.
+pub const SHADOW_FORMATS: &[vk::Format] = &[vk::Format::D32_SFLOAT, vk::Format::D16_UNORM];
+
+/// Specifies how a cascaded shadow map renders
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowInfo {
+    /// The direction the light shines in
+    pub direction: Vec3,
+    /// The number of cascades the view frustum is split into. Clamped between 1 and
+    /// `MAX_CASCADES`.
+    pub cascade_count: usize,
+    /// Blends the split distances between uniform at 0.0 and logarithmic at 1.0. Higher values
+    /// give the cascades close to the camera more resolution.
+    pub split_lambda: f32,
+    /// The width and height of each cascade
+    pub resolution: u32,
+    /// The view distance shadows are rendered to. Clamped to the far plane of the camera.
+    pub max_distance: f32,
+    /// The distance towards the light beyond each cascade in which objects still cast shadows
+    /// into it
+    pub caster_distance: f32,
+    /// The layers of the objects casting shadows
+    pub caster_layers: Layers,
+    /// Object capacity and growth of the mesh renderer of each cascade
+    pub mesh_renderer: MeshRendererInfo,
+}
+
+impl Default for ShadowInfo {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::new(-0.4, -1.0, -0.3).normalized(),
+            cascade_count: MAX_CASCADES,
+            split_lambda: 0.75,
+            resolution: 2048,
+            max_distance: 100.0,
+            caster_distance: 50.0,
+            caster_layers: Layers::ALL,
+            mesh_renderer: MeshRendererInfo::default(),
+        }
+    }
+}
+
+/// The cascade uniform read by the lighting shaders. Matches the std140 layout of the
+/// `ShadowData` block in `data/shaders/shadow.glsl`.
+#[derive(Default)]
+#[repr(C)]
+struct ShadowData {
+    view_projections: [Mat4; MAX_CASCADES],
+    /// The view distance each cascade ends at
+    splits: [f32; MAX_CASCADES],
+    /// The view matrix of the camera the cascades are fit to
+    view: Mat4,
+    /// The light direction in xyz and the number of cascades in w
+    direction: Vec4,
+}
+
+/// Returns the view distance each of `count` cascades ends at when splitting the view frustum
+/// between `near` and `far`. See `ShadowInfo::split_lambda`.
+pub fn cascade_splits(
+    near: f32,
+    far: f32,
+    count: usize,
+    lambda: f32,
+) -> ArrayVec<[f32; MAX_CASCADES]> {
+    let count = count.clamp(1, MAX_CASCADES);
+
+    (1..=count)
+        .map(|i| {
+            let p = i as f32 / count as f32;
+            let uniform = near + (far - near) * p;
+
+            // Logarithmic splits are undefined for orthographic cameras starting at zero
+            let logarithmic = if near > 0.0 {
+                near * (far / near).powf(p)
+            } else {
+                uniform
+            };
+
+            lambda * logarithmic + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
+
+/// Returns an orthographic camera looking along `direction` covering the slice of the view
+/// frustum of `camera` between the view distances `near` and `far`.
+/// The cascade is fit to a bounding sphere of the slice and moved in whole texels of a
+/// `resolution` wide shadow map, which keeps the shadow edges from shimmering when the camera
+/// moves or rotates.
+pub fn fit_cascade(
+    camera: &Camera,
+    near: f32,
+    far: f32,
+    direction: Vec3,
+    resolution: u32,
+    caster_distance: f32,
+) -> Camera {
+    let direction = direction.normalized();
+    let corners = frustum_corners(camera, near, far);
+
+    let center = corners
+        .iter()
+        .fold(Vec3::zero(), |acc, corner| acc + *corner)
+        / 8.0;
+    let radius = corners
+        .iter()
+        .map(|corner| (*corner - center).mag())
+        .fold(0.0, f32::max);
+
+    // Rounding keeps the extent from changing by floating point errors when rotating
+    let radius = (radius * 16.0).ceil() / 16.0;
+    let extent = radius * 2.0;
+
+    let mut light =
+        Camera::orthographic(Vec3::zero(), extent, extent, 0.0, extent + caster_distance);
+
+    light.auto_aspect = false;
+    light.look_in(direction, light_up(direction));
+
+    let texel = extent / resolution as f32;
+    let mut local = light.rotation.reversed() * center;
+    local.x = (local.x / texel).floor() * texel;
+    local.y = (local.y / texel).floor() * texel;
+
+    light.position = light.rotation * local - direction * (radius + caster_distance);
+    light
+}
+
+// Returns the world space corners of the view frustum of `camera` between the view distances
+// `near` and `far`
+fn frustum_corners(camera: &Camera, near: f32, far: f32) -> [Vec3; 8] {
+    let half_extent = |distance: f32| match camera.projection_kind() {
+        Projection::Perspective {
+            fov, aspect_ratio, ..
+        } => {
+            let height = distance * (fov / 2.0).tan();
+            (height * aspect_ratio, height)
+        }
+        Projection::Orthographic { width, height, .. } => (width / 2.0, height / 2.0),
+    };
+
+    let mut corners = [Vec3::zero(); 8];
+
+    for (i, distance) in [near, far].iter().enumerate() {
+        let (x, y) = half_extent(*distance);
+
+        for (j, (x, y)) in [(-x, -y), (x, -y), (-x, y), (x, y)].iter().enumerate() {
+            corners[i * 4 + j] = camera.position + camera.rotation * Vec3::new(*x, *y, -distance);
+        }
+    }
+
+    corners
+}
+
+// Returns a world direction which is not parallel to `direction`
+fn light_up(direction: Vec3) -> Vec3 {
+    if direction.y.abs() > 0.99 {
+        Vec3::unit_z()
+    } else {
+        Vec3::unit_y()
+    }
+}
+
+/// Returns the preferred shadow map format supported by the device.
+pub fn shadow_format(context: &VulkanContext) -> vk::Format {
+    let features =
+        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE;
+
+    SHADOW_FORMATS
+        .iter()
+        .copied()
+        .find(|format| context.supports_format(*format, features))
+        .unwrap_or(vk::Format::D16_UNORM)
+}
+
+/// Returns the attachment formats of shadow passes using dynamic rendering.
+pub fn rendering_formats(format: vk::Format) -> RenderingFormats {
+    RenderingFormats {
+        color_formats: ArrayVec::new(),
+        depth_format: format,
+        shading_rate_attachment: false,
+        view_mask: 0,
+    }
+}
+
+/// Creates a depth only renderpass rendering into a layer of a shadow map, which is expected
+/// to be in DEPTH_STENCIL_ATTACHMENT_OPTIMAL. All shadow passes of the same format are
+/// compatible.
+pub fn create_renderpass(
+    device: Rc<ash::Device>,
+    format: vk::Format,
+) -> Result<RenderPass, vulkan::Error> {
+    let renderpass_info = RenderPassInfo {
+        attachments: &[AttachmentInfo {
+            usage: TextureUsage::ShadowMap,
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load: LoadOp::CLEAR,
+            store: StoreOp::STORE,
+            initial_layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            final_layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        }],
+        subpasses: &[SubpassInfo {
+            color_attachments: &[],
+            resolve_attachments: &[],
+            depth_attachment: Some(AttachmentReference {
+                attachment: 0,
+                layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            }),
+        }],
+        view_mask: 0,
+    };
+
+    RenderPass::new(device, &renderpass_info)
+}
+
+/// Records an update of a uniform buffer read by the fragment stage in order with the other
+/// commands, which keeps the draws of the previous frame reading the previous contents.
+pub fn update_uniform<T>(commandbuffer: &CommandBuffer, uniform_buffer: &Buffer, data: &T) {
+    let barrier = vk::BufferMemoryBarrier {
+        src_access_mask: vk::AccessFlags::UNIFORM_READ,
+        dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        buffer: uniform_buffer.buffer(),
+        offset: 0,
+        size: vk::WHOLE_SIZE,
+        ..Default::default()
+    };
+
+    commandbuffer.buffer_barrier(
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::PipelineStageFlags::TRANSFER,
+        &[barrier],
+    );
+
+    let bytes =
+        unsafe { slice::from_raw_parts(data as *const T as *const u8, mem::size_of::<T>()) };
+
+    commandbuffer.update_buffer(uniform_buffer.buffer(), 0, bytes);
+
+    commandbuffer.buffer_barrier(
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        &[vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::UNIFORM_READ,
+            ..barrier
+        }],
+    );
+}
+
+/// Renders the scene from a directional light into the cascades of a shadow map before the
+/// main pass. Objects are drawn with the `PassTag::Shadow` pipeline of their effect.
+pub struct CascadedShadowMap {
+    /// The direction the light shines in
+    pub direction: Vec3,
+    /// See `ShadowInfo::split_lambda`
+    pub split_lambda: f32,
+    /// See `ShadowInfo::max_distance`
+    pub max_distance: f32,
+    /// See `ShadowInfo::caster_distance`
+    pub caster_distance: f32,
+    /// See `ShadowInfo::caster_layers`
+    pub caster_layers: Layers,
+    context: Rc<VulkanContext>,
+    set: DescriptorSet,
+    set_layout: DescriptorSetLayout,
+    mesh_renderers: Vec<MeshRenderer>,
+    /// One for each cascade. Empty when using dynamic rendering
+    framebuffers: Vec<Framebuffer>,
+    /// None when using dynamic rendering
+    renderpass: Option<RenderPass>,
+    /// A view of the layer of each cascade
+    layer_views: Vec<TextureView>,
+    texture: Texture,
+    sampler: Sampler,
+    uniform_buffer: Buffer,
+}
+
+impl CascadedShadowMap {
+    /// Creates a shadow map with the layers of `info.cascade_count` cascades. `format` needs to
+    /// be the format the shadow pipelines were created with. See `shadow_format`.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        descriptor_allocator: &mut DescriptorAllocator,
+        frames_in_flight: usize,
+        dynamic_rendering: bool,
+        format: vk::Format,
+        info: ShadowInfo,
+    ) -> Result<Self, vulkan::Error> {
+        let cascade_count = info.cascade_count.clamp(1, MAX_CASCADES);
+        let extent: Extent = (info.resolution, info.resolution).into();
+
+        let texture = Texture::new(
+            context.clone(),
+            TextureInfo {
+                extent,
+                mip_levels: 1,
+                usage: TextureUsage::ShadowMap,
+                format,
+                array_layers: cascade_count as u32,
+                view_type: ImageViewType::TYPE_2D_ARRAY,
+                ..Default::default()
+            },
+        )?;
+
+        // Sampling is valid before the first frame has been drawn
+        texture.transition_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)?;
+
+        let layer_views = (0..cascade_count as u32)
+            .map(|layer| texture.layer_view(layer))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sampler = Sampler::new(
+            context.clone(),
+            SamplerInfo {
+                address_mode: AddressMode::CLAMP_TO_EDGE,
+                anisotropy: Some(1.0),
+                mipmap_mode: MipmapMode::NEAREST,
+                compare_op: Some(vk::CompareOp::LESS_OR_EQUAL),
+                ..Default::default()
+            },
+        )?;
+
+        let uniform_buffer = Buffer::new_uninit(
+            context.clone(),
+            BufferType::Uniform,
+            BufferUsage::Staged,
+            mem::size_of::<ShadowData>() as u64,
+        )?;
+
+        let mut set = Default::default();
+        let mut set_layout = Default::default();
+
+        DescriptorBuilder::new()
+            .bind_combined_image_sampler(0, vk::ShaderStageFlags::FRAGMENT, &texture, &sampler)
+            .bind_uniform_buffer(1, vk::ShaderStageFlags::FRAGMENT, &uniform_buffer)
+            .build(
+                context.device(),
+                descriptor_layout_cache,
+                descriptor_allocator,
+                &mut set,
+            )?
+            .layout(descriptor_layout_cache, &mut set_layout)?;
+
+        let renderpass = if dynamic_rendering {
+            None
+        } else {
+            Some(create_renderpass(context.device_ref(), format)?)
+        };
+
+        let framebuffers = match &renderpass {
+            Some(renderpass) => layer_views
+                .iter()
+                .map(|view| Framebuffer::new(context.device_ref(), renderpass, &[view], extent))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+
+        let inheritance = match &renderpass {
+            Some(renderpass) => Inheritance::RenderPass {
+                renderpass: renderpass.renderpass(),
+                subpass: 0,
+            },
+            None => Inheritance::Dynamic {
+                formats: rendering_formats(format),
+                samples: vk::SampleCountFlags::TYPE_1,
+            },
+        };
+
+        let mesh_renderers = (0..cascade_count)
+            .map(|_| {
+                let mut mesh_renderer = MeshRenderer::new(
+                    context.clone(),
+                    descriptor_layout_cache,
+                    descriptor_allocator,
+                    frames_in_flight,
+                    info.mesh_renderer,
+                )?;
+
+                mesh_renderer.set_inheritance(inheritance.clone());
+                mesh_renderer.set_extent(extent);
+                Ok(mesh_renderer)
+            })
+            .collect::<Result<Vec<_>, vulkan::Error>>()?;
+
+        Ok(Self {
+            direction: info.direction,
+            split_lambda: info.split_lambda,
+            max_distance: info.max_distance,
+            caster_distance: info.caster_distance,
+            caster_layers: info.caster_layers,
+            context,
+            set,
+            set_layout,
+            mesh_renderers,
+            framebuffers,
+            renderpass,
+            layer_views,
+            texture,
+            sampler,
+            uniform_buffer,
+        })
+    }
+
+    /// Records the drawing of each cascade fit to the view frustum of `camera` and the update
+    /// of the cascade uniform. The shadow map is transitioned to SHADER_READ_ONLY_OPTIMAL for
+    /// sampling by the passes recorded afterwards.
+    pub fn draw(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        resources: &ResourceManager,
+        camera: &Camera,
+        frame_index: usize,
+        scene: &Scene,
+    ) -> Result<(), vulkan::Error> {
+        let (near, far) = match camera.projection_kind() {
+            Projection::Perspective { near, far, .. } => (near, far),
+            Projection::Orthographic { near, far, .. } => (near, far),
+        };
+
+        let splits = cascade_splits(
+            near,
+            far.min(self.max_distance),
+            self.mesh_renderers.len(),
+            self.split_lambda,
+        );
+
+        let resolution = self.texture.extent().width;
+
+        let mut cascade_near = near;
+        let cascades = splits
+            .iter()
+            .map(|split| {
+                let mut cascade = fit_cascade(
+                    camera,
+                    cascade_near,
+                    *split,
+                    self.direction,
+                    resolution,
+                    self.caster_distance,
+                );
+
+                cascade.layers = self.caster_layers;
+                cascade_near = *split;
+                cascade
+            })
+            .collect::<ArrayVec<[Camera; MAX_CASCADES]>>();
+
+        let mut data = ShadowData {
+            view: camera.calculate_view(),
+            direction: self.direction.normalized().into_homogeneous_vector(),
+            ..Default::default()
+        };
+
+        data.direction.w = cascades.len() as f32;
+
+        for (i, cascade) in cascades.iter().enumerate() {
+            data.view_projections[i] = cascade.projection() * cascade.calculate_view();
+            data.splits[i] = splits[i];
+        }
+
+        update_uniform(commandbuffer, &self.uniform_buffer, &data);
+
+        // Waits for the previous frame to finish sampling the shadow map
+        self.texture
+            .transition(commandbuffer, ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        for (i, cascade) in cascades.iter().enumerate() {
+            let contents = if self.mesh_renderers[i].uses_secondary() {
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+            } else {
+                vk::SubpassContents::INLINE
+            };
+
+            self.begin_cascade(commandbuffer, i, contents);
+
+            self.mesh_renderers[i].draw(
+                commandbuffer,
+                resources,
+                cascade,
+                frame_index,
+                scene,
+                &[PassTag::Shadow],
+            )?;
+
+            match self.renderpass {
+                Some(_) => commandbuffer.end_renderpass(),
+                None => commandbuffer.end_rendering(self.dynamic_rendering()),
+            }
+        }
+
+        self.texture
+            .transition(commandbuffer, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        Ok(())
+    }
+
+    /// Returns the descriptor set holding the shadow map and cascade uniform, bound at
+    /// `SHADOW_SET`.
+    pub fn set(&self) -> DescriptorSet {
+        self.set
+    }
+
+    pub fn set_layout(&self) -> DescriptorSetLayout {
+        self.set_layout
+    }
+
+    /// Returns the depth array texture holding a layer for each cascade.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Returns the comparison sampler the shadow map is sampled with.
+    pub fn sampler(&self) -> &Sampler {
+        &self.sampler
+    }
+
+    pub fn cascade_count(&self) -> usize {
+        self.mesh_renderers.len()
+    }
+
+    fn dynamic_rendering(&self) -> &DynamicRendering {
+        self.context
+            .dynamic_rendering()
+            .expect("Dynamic rendering is not supported")
+    }
+
+    // Begins the renderpass or dynamic rendering into the layer of `cascade`
+    fn begin_cascade(
+        &self,
+        commandbuffer: &CommandBuffer,
+        cascade: usize,
+        contents: vk::SubpassContents,
+    ) {
+        let extent = self.texture.extent();
+
+        let clear_value = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        };
+
+        match &self.renderpass {
+            Some(renderpass) => commandbuffer.begin_renderpass(
+                renderpass,
+                &self.framebuffers[cascade],
+                extent,
+                &[clear_value],
+                contents,
+            ),
+            None => commandbuffer.begin_rendering(
+                self.dynamic_rendering(),
+                extent,
+                &RenderingAttachments {
+                    depth: Some(&RenderingAttachment {
+                        image_view: self.layer_views[cascade].image_view(),
+                        layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                        load: LoadOp::CLEAR,
+                        store: StoreOp::STORE,
+                        clear_value,
+                        resolve: None,
+                    }),
+                    ..Default::default()
+                },
+                contents,
+            ),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! A procedural sky drawn behind the scene as an alternative to cube map skyboxes. The sky is a
+//! gradient from the horizon to the zenith with a sun disc in the direction of the directional
+//! light, see `data/shaders/sky.frag`.
+use std::rc::Rc;
+
+use ash::vk;
+use ultraviolet::{Mat4, Vec3, Vec4};
+
+use crate::color::Color;
+use crate::fullscreen::{self, FullscreenPass};
+use crate::pass::{Pass, PassFrame, PassStage, PassTargets};
+use crate::{Camera, Scene};
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::pipeline::PipelineInfo;
+use vulkan::*;
+
+/// Specifies the colors of the sky and the sun
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyInfo {
+    /// The color straight up
+    pub zenith_color: Color,
+    /// The color at the horizon, which the zenith and ground colors blend towards
+    pub horizon_color: Color,
+    /// The color below the horizon
+    pub ground_color: Color,
+    /// The direction the sunlight shines in. Follows the direction of the shadow casting
+    /// directional light when drawn by the master renderer with shadows enabled.
+    pub sun_direction: Vec3,
+    /// The angular radius of the sun disc in radians
+    pub sun_size: f32,
+}
+
+impl Default for SkyInfo {
+    fn default() -> Self {
+        Self {
+            zenith_color: Color::rgba(40, 90, 180, 255),
+            horizon_color: Color::rgba(170, 200, 230, 255),
+            ground_color: Color::rgba(60, 60, 70, 255),
+            sun_direction: Vec3::new(-0.4, -1.0, -0.3),
+            sun_size: 0.02,
+        }
+    }
+}
+
+/// The name of the sky pass
+pub const NAME: &str = "sky";
+
+/// The push constants of the fragment shader
+#[repr(C)]
+struct SkyParameters {
+    inv_view_projection: Mat4,
+    zenith_color: Vec4,
+    horizon_color: Vec4,
+    ground_color: Vec4,
+    /// The normalized direction towards the sun, and the cosine of the angular radius in w
+    sun: Vec4,
+}
+
+/// Returns the info of the sky pipeline for attachments of `samples`. The sky is drawn on the
+/// far plane after the scene, so only fragments not covered by any object pass the depth test.
+pub fn pipeline_info(samples: vk::SampleCountFlags) -> PipelineInfo {
+    PipelineInfo {
+        depth_compare: vk::CompareOp::LESS_OR_EQUAL,
+        depth_write: false,
+        ..fullscreen::pipeline_info("./data/shaders/sky.frag.spv", samples)
+    }
+}
+
+/// Draws the sky behind the objects of each view as a `PassStage::View` pass. The sun follows
+/// `PassFrame::sun_direction` when set.
+pub struct SkyRenderer {
+    /// None until the pass is set up
+    pass: Option<FullscreenPass>,
+    info: SkyInfo,
+}
+
+impl SkyRenderer {
+    /// Creates a sky renderer, whose pipeline is created when it is set up as a pass
+    pub fn new(info: SkyInfo) -> Self {
+        Self { pass: None, info }
+    }
+
+    pub fn info(&self) -> &SkyInfo {
+        &self.info
+    }
+
+    /// Sets the colors of the sky, which take effect on the next draw
+    pub fn set_info(&mut self, info: SkyInfo) {
+        self.info = info
+    }
+
+    /// Sets the direction the sunlight shines in, e.g; to follow a directional light
+    pub fn set_sun_direction(&mut self, direction: Vec3) {
+        self.info.sun_direction = direction
+    }
+
+    /// Draws the sky as seen by `camera` into `viewport`. Needs to be recorded within the pass
+    /// after the objects of the view, since the sky is only drawn where the depth is still
+    /// cleared. If `inheritance` is Some the sky is recorded into a secondary command buffer of
+    /// the view, which requires the previous submission of the frame in flight `frame_index` to
+    /// have completed. Does nothing before the pass is set up.
+    pub fn draw(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        frame_index: usize,
+        view_index: usize,
+        camera: &Camera,
+        viewport: vk::Rect2D,
+        inheritance: Option<&Inheritance>,
+    ) -> Result<(), vulkan::Error> {
+        let pass = match &mut self.pass {
+            Some(pass) => pass,
+            None => return Ok(()),
+        };
+
+        // The view is only rotated, so that the sky stays at an infinite distance
+        let mut view = camera.calculate_view();
+        view.cols[3] = Vec4::new(0.0, 0.0, 0.0, 1.0);
+        let view_projection = camera.projection() * view;
+
+        let sun = -self.info.sun_direction.normalized();
+
+        let parameters = SkyParameters {
+            inv_view_projection: view_projection.inversed(),
+            zenith_color: self.info.zenith_color.to_linear_vec4(),
+            horizon_color: self.info.horizon_color.to_linear_vec4(),
+            ground_color: self.info.ground_color.to_linear_vec4(),
+            sun: Vec4::new(sun.x, sun.y, sun.z, self.info.sun_size.cos()),
+        };
+
+        pass.draw(
+            commandbuffer,
+            frame_index,
+            view_index,
+            viewport,
+            inheritance,
+            &parameters,
+        )
+    }
+}
+
+impl Pass for SkyRenderer {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn stage(&self) -> PassStage {
+        PassStage::View
+    }
+
+    fn setup(
+        &mut self,
+        context: &Rc<VulkanContext>,
+        targets: &mut PassTargets,
+    ) -> Result<(), vulkan::Error> {
+        let pipeline = targets.create_pipeline(pipeline_info(targets.samples))?;
+
+        self.pass = Some(FullscreenPass::new(
+            context.clone(),
+            pipeline,
+            targets.frames_in_flight,
+        )?);
+
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        frame: &PassFrame,
+        _scene: &Scene,
+    ) -> Result<(), vulkan::Error> {
+        if let Some(direction) = frame.sun_direction {
+            self.set_sun_direction(direction);
+        }
+
+        match frame.current_view() {
+            Some((index, camera, viewport)) => self.draw(
+                commandbuffer,
+                frame.frame_index,
+                index,
+                &camera,
+                viewport,
+                frame.inheritance,
+            ),
+            None => Ok(()),
+        }
+    }
+}
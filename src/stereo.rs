@@ -0,0 +1,788 @@
+//! Stereoscopic rendering of a view for both eyes at once through multiview, as groundwork for
+//! VR headsets. The eyes are rendered into the two layers of an array texture with the
+//! `PassTag::Stereo` pipelines of effects, which read the view projection of their eye from
+//! `VIEW_SET` by the view index, see `data/shaders/default.vert` compiled with MULTIVIEW
+//! defined. The master renderer presents the eyes side by side in place of the first view, see
+//! `MasterRenderer::set_stereo`.
+//!
+//! The eyes are parallel and offset along the right axis of the camera. Objects are culled and
+//! their level of detail selected once for both eyes, and effects using the light set are not
+//! drawn since the lights are only culled for the views of the main pass.
+//!
+//! The eyes can instead be supplied by the application each frame through `StereoViews`, e.g;
+//! from a head tracked headset, see `xr`.
+//!
+//! Requires `DeviceFeatures::multiview`.
+use arrayvec::ArrayVec;
+use ash::vk;
+use std::{rc::Rc, slice};
+use ultraviolet::Mat4;
+
+use crate::fullscreen::{self, FullscreenPass};
+use crate::light_culling::update_buffer_in;
+use crate::mesh_renderer::{MeshRenderer, MeshRendererInfo};
+use crate::resources::*;
+use crate::{Camera, PassTag, Projection, Scene};
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::descriptors::*;
+use vulkan::dynamic_rendering::DynamicRendering;
+use vulkan::pipeline::PipelineInfo;
+use vulkan::renderpass::*;
+use vulkan::sampler::{AddressMode, Sampler, SamplerInfo};
+use vulkan::texture::*;
+use vulkan::*;
+
+/// The descriptor set index of the view set in multiview effect shaders. Holds the view
+/// projection matrix of each eye at binding 0.
+pub const VIEW_SET: u32 = 9;
+
+/// Renders the left eye to the first layer and the right eye to the second
+pub const VIEW_MASK: u32 = 0b11;
+
+/// The number of views rendered by the multiview pass
+pub const EYE_COUNT: usize = 2;
+
+/// Specifies how the eyes of the stereo view are rendered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StereoInfo {
+    /// The distance between the eyes in world units
+    pub eye_separation: f32,
+    /// Presents the right eye on the left half and the left eye on the right half, e.g; for
+    /// cross-eyed viewing without a headset
+    pub swap_eyes: bool,
+    /// The color the eyes are cleared to before drawing
+    pub clear_color: [f32; 4],
+    /// The resolution of each eye, e.g; the recommended resolution of a headset. Half of the
+    /// window if None.
+    pub extent: Option<Extent>,
+    /// Object capacity and growth of the mesh renderer of the eyes
+    pub mesh_renderer: MeshRendererInfo,
+}
+
+impl Default for StereoInfo {
+    fn default() -> Self {
+        Self {
+            eye_separation: 0.064,
+            swap_eyes: false,
+            clear_color: [0.0, 0.0, 0.0, 0.0],
+            extent: None,
+            mesh_renderer: MeshRendererInfo::default(),
+        }
+    }
+}
+
+/// The eyes of the stereo view supplied by the application in place of those derived from the
+/// camera, see `StereoRenderer::set_views`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StereoViews {
+    /// The view projection matrix of the left and right eye
+    pub view_projection: [Mat4; EYE_COUNT],
+    /// A camera whose frustum contains the frusta of both eyes. Objects are culled and their
+    /// level of detail selected from it.
+    pub culling_camera: Camera,
+    /// An image the eyes are copied into after drawing, e.g; a swapchain image of a headset
+    pub destination: Option<StereoDestination>,
+}
+
+/// An image with a layer for each eye the eyes are scaled into. The image is expected in
+/// COLOR_ATTACHMENT_OPTIMAL and returned in the same layout, and needs transfer destination
+/// usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StereoDestination {
+    pub image: vk::Image,
+    pub extent: Extent,
+}
+
+/// The view uniform, matching the std140 `ViewData` block of the shaders
+#[derive(Default)]
+#[repr(C)]
+struct ViewData {
+    view_projection: [Mat4; EYE_COUNT],
+}
+
+/// The push constants of the output fragment shader
+#[repr(C)]
+struct OutputParameters {
+    swap_eyes: u32,
+}
+
+/// Returns the cameras of the left and right eye of `camera`, which are offset by half of
+/// `eye_separation` to each side along its right axis.
+pub fn eye_cameras(camera: &Camera, eye_separation: f32) -> [Camera; EYE_COUNT] {
+    let offset = camera.right() * eye_separation * 0.5;
+
+    let mut left = *camera;
+    left.position -= offset;
+
+    let mut right = *camera;
+    right.position += offset;
+
+    [left, right]
+}
+
+/// Returns the info of the pipeline presenting the eyes side by side in the main pass with
+/// attachments of `samples`. The eyes cover the viewport regardless of the depth.
+pub fn output_pipeline_info(samples: vk::SampleCountFlags) -> PipelineInfo {
+    PipelineInfo {
+        depth_compare: vk::CompareOp::ALWAYS,
+        depth_write: false,
+        ..fullscreen::pipeline_info("./data/shaders/stereo.frag.spv", samples)
+    }
+}
+
+/// Returns the formats of dynamic rendering into the eyes, which the `PassTag::Stereo`
+/// pipelines are created with.
+pub fn rendering_formats(color_format: vk::Format, depth_format: vk::Format) -> RenderingFormats {
+    let mut color_formats = ArrayVec::new();
+    color_formats.push(color_format);
+
+    RenderingFormats {
+        color_formats,
+        depth_format,
+        shading_rate_attachment: false,
+        view_mask: VIEW_MASK,
+    }
+}
+
+/// Creates the multiview renderpass drawing into the eyes, which the `PassTag::Stereo`
+/// pipelines are created with. The multisampled color attachment is resolved into a texture
+/// which is transitioned outside the renderpass.
+pub fn create_renderpass(
+    device: Rc<ash::Device>,
+    color_format: vk::Format,
+    depth_format: vk::Format,
+    samples: vk::SampleCountFlags,
+) -> Result<RenderPass, vulkan::Error> {
+    let renderpass_info = RenderPassInfo {
+        attachments: &[
+            // Color attachment, resolved into the texture
+            AttachmentInfo {
+                usage: TextureUsage::ColorAttachment,
+                format: color_format,
+                samples,
+                load: LoadOp::CLEAR,
+                store: StoreOp::DONT_CARE,
+                initial_layout: ImageLayout::UNDEFINED,
+                final_layout: ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            },
+            // Depth attachment
+            AttachmentInfo {
+                usage: TextureUsage::DepthAttachment,
+                format: depth_format,
+                samples,
+                load: LoadOp::CLEAR,
+                store: StoreOp::DONT_CARE,
+                initial_layout: ImageLayout::UNDEFINED,
+                final_layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            },
+            // Resolve attachment
+            AttachmentInfo {
+                usage: TextureUsage::RenderTarget,
+                format: color_format,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load: LoadOp::DONT_CARE,
+                store: StoreOp::STORE,
+                initial_layout: ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                final_layout: ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            },
+        ],
+        subpasses: &[SubpassInfo {
+            color_attachments: &[AttachmentReference {
+                attachment: 0,
+                layout: ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            }],
+            resolve_attachments: &[AttachmentReference {
+                attachment: 2,
+                layout: ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            }],
+            depth_attachment: Some(AttachmentReference {
+                attachment: 1,
+                layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            }),
+        }],
+        view_mask: VIEW_MASK,
+    };
+
+    RenderPass::new(device, &renderpass_info)
+}
+
+/// The layered attachments the eyes are rendered into, with a layer for each eye
+pub struct StereoTarget {
+    /// None when using dynamic rendering
+    renderpass: Option<RenderPass>,
+    framebuffer: Option<Framebuffer>,
+    // Multisampled color and depth attachments resolved into the texture
+    color_attachment: Texture,
+    depth_attachment: Texture,
+    texture: Texture,
+}
+
+impl StereoTarget {
+    /// Returns the infos of the multisampled color and depth attachments of eyes of `extent`,
+    /// which are created by the caller so that they can share memory with other attachments,
+    /// see `Texture::new_aliased`.
+    pub fn attachment_infos(
+        context: &VulkanContext,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+        extent: Extent,
+    ) -> [TextureInfo; 2] {
+        let samples = context.msaa_samples();
+
+        [
+            layers_info(extent, TextureUsage::ColorAttachment, color_format, samples),
+            layers_info(extent, TextureUsage::DepthAttachment, depth_format, samples),
+        ]
+    }
+
+    /// Creates the target of the eyes resolved from `color_attachment` and `depth_attachment`,
+    /// created from `attachment_infos`. Their contents are discarded once the eyes are drawn.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        color_attachment: Texture,
+        depth_attachment: Texture,
+        dynamic_rendering: bool,
+    ) -> Result<Self, vulkan::Error> {
+        let extent = color_attachment.extent();
+        let color_format = color_attachment.format();
+        let depth_format = depth_attachment.format();
+        let samples = color_attachment.samples();
+
+        let texture = Texture::new(
+            context.clone(),
+            layers_info(
+                extent,
+                TextureUsage::RenderTarget,
+                color_format,
+                vk::SampleCountFlags::TYPE_1,
+            ),
+        )?;
+
+        let renderpass = if dynamic_rendering {
+            None
+        } else {
+            Some(create_renderpass(
+                context.device_ref(),
+                color_format,
+                depth_format,
+                samples,
+            )?)
+        };
+
+        // Multiview framebuffers have a single layer
+        let framebuffer = renderpass
+            .as_ref()
+            .map(|renderpass| {
+                Framebuffer::new(
+                    context.device_ref(),
+                    renderpass,
+                    &[&color_attachment, &depth_attachment, &texture],
+                    extent,
+                )
+            })
+            .transpose()?;
+
+        Ok(Self {
+            renderpass,
+            framebuffer,
+            color_attachment,
+            depth_attachment,
+            texture,
+        })
+    }
+
+    /// Returns the resolution of each eye.
+    pub fn extent(&self) -> Extent {
+        self.texture.extent()
+    }
+
+    /// Returns the texture holding the left eye in the first layer and the right eye in the
+    /// second.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    // Returns the inheritance of secondary command buffers drawing into the eyes
+    fn inheritance(&self) -> Inheritance {
+        match &self.renderpass {
+            Some(renderpass) => Inheritance::RenderPass {
+                renderpass: renderpass.renderpass(),
+                subpass: 0,
+            },
+            None => Inheritance::Dynamic {
+                formats: rendering_formats(self.texture.format(), self.depth_attachment.format()),
+                samples: self.color_attachment.samples(),
+            },
+        }
+    }
+}
+
+// Returns the info of an array texture with a layer for each eye
+fn layers_info(
+    extent: Extent,
+    usage: TextureUsage,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+) -> TextureInfo {
+    TextureInfo {
+        extent,
+        mip_levels: 1,
+        array_layers: EYE_COUNT as u32,
+        view_type: ImageViewType::TYPE_2D_ARRAY,
+        usage,
+        format,
+        samples,
+        ..Default::default()
+    }
+}
+
+/// Renders the scene for both eyes of a camera in a single multiview pass before the main pass,
+/// and presents the eyes side by side within it.
+pub struct StereoRenderer {
+    context: Rc<VulkanContext>,
+    info: StereoInfo,
+    mesh_renderer: MeshRenderer,
+    target: StereoTarget,
+    /// Holds the view projection of each eye, uploaded each frame
+    view_buffer: Buffer,
+    output: FullscreenPass,
+    output_set: DescriptorSet,
+    sampler: Sampler,
+    /// Supplied by the application in place of the eyes derived from the camera
+    views: Option<StereoViews>,
+}
+
+impl StereoRenderer {
+    /// Creates a stereo renderer drawing the objects with `mesh_renderer` into `target`, and
+    /// presenting the eyes with `output`, whose pipeline should be created from
+    /// `output_pipeline_info`. Fails if the device does not support multiview.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        descriptor_allocator: &mut DescriptorAllocator,
+        mut mesh_renderer: MeshRenderer,
+        target: StereoTarget,
+        mut output: FullscreenPass,
+        info: StereoInfo,
+    ) -> Result<Self, vulkan::Error> {
+        if !context.capabilities().features.multiview {
+            return Err(vulkan::Error::MissingFeature("multiview"));
+        }
+
+        let view_buffer = Buffer::new(
+            context.clone(),
+            BufferType::Uniform,
+            BufferUsage::Staged,
+            slice::from_ref(&ViewData::default()),
+        )?;
+
+        let mut view_set = Default::default();
+
+        DescriptorBuilder::new()
+            .bind_uniform_buffer(0, vk::ShaderStageFlags::VERTEX, &view_buffer)
+            .build(
+                context.device(),
+                descriptor_layout_cache,
+                descriptor_allocator,
+                &mut view_set,
+            )?;
+
+        let sampler = Sampler::new(
+            context.clone(),
+            SamplerInfo {
+                address_mode: AddressMode::CLAMP_TO_EDGE,
+                anisotropy: Some(1.0),
+                ..Default::default()
+            },
+        )?;
+
+        let mut output_set = Default::default();
+
+        DescriptorBuilder::new()
+            .bind_combined_image_sampler(
+                0,
+                vk::ShaderStageFlags::FRAGMENT,
+                target.texture(),
+                &sampler,
+            )
+            .build(
+                context.device(),
+                descriptor_layout_cache,
+                descriptor_allocator,
+                &mut output_set,
+            )?;
+
+        output.set_descriptor_set(Some(output_set));
+
+        mesh_renderer.set_view_set(Some(view_set));
+        mesh_renderer.set_inheritance(target.inheritance());
+        mesh_renderer.set_extent(target.extent());
+
+        Ok(Self {
+            context,
+            info,
+            mesh_renderer,
+            target,
+            view_buffer,
+            output,
+            output_set,
+            sampler,
+            views: None,
+        })
+    }
+
+    /// Replaces the attachments the eyes are drawn into, e.g; after the window was resized.
+    /// The device must not be using the previous attachments.
+    pub fn set_target(&mut self, target: StereoTarget) {
+        DescriptorBuilder::new()
+            .bind_combined_image_sampler(
+                0,
+                vk::ShaderStageFlags::FRAGMENT,
+                target.texture(),
+                &self.sampler,
+            )
+            .update(self.context.device(), self.output_set);
+
+        self.mesh_renderer.set_inheritance(target.inheritance());
+        self.mesh_renderer.set_extent(target.extent());
+        self.target = target;
+    }
+
+    /// Records the drawing of the scene for both eyes of `camera`, or the views set through
+    /// `set_views`, with the `PassTag::Stereo` pipelines of the effects. Needs to be recorded
+    /// outside of a renderpass. The texture is transitioned to SHADER_READ_ONLY_OPTIMAL for
+    /// the output recorded afterwards.
+    pub fn draw(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        resources: &ResourceManager,
+        camera: &Camera,
+        frame_index: usize,
+        scene: &Scene,
+    ) -> Result<(), vulkan::Error> {
+        let extent = self.target.extent();
+
+        let (data, culling_camera) = match &self.views {
+            Some(views) => (
+                ViewData {
+                    view_projection: views.view_projection,
+                },
+                views.culling_camera,
+            ),
+            None => {
+                let camera = camera.with_viewport_aspect(extent.aspect());
+                let [left, right] = eye_cameras(&camera, self.info.eye_separation);
+
+                (
+                    ViewData {
+                        view_projection: [
+                            left.projection() * left.calculate_view(),
+                            right.projection() * right.calculate_view(),
+                        ],
+                    },
+                    // Culled once from behind the eyes, so that objects seen by either eye
+                    // are drawn
+                    culling_camera(&camera, self.info.eye_separation),
+                )
+            }
+        };
+
+        update_buffer_in(
+            commandbuffer,
+            &self.view_buffer,
+            slice::from_ref(&data),
+            vk::PipelineStageFlags::VERTEX_SHADER,
+        );
+
+        let texture = self.target.texture();
+
+        // Waits for the previous frame to finish sampling the texture
+        texture.transition(commandbuffer, ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: self.info.clear_color,
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+
+        let contents = if self.mesh_renderer.uses_secondary() {
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+        } else {
+            vk::SubpassContents::INLINE
+        };
+
+        match (&self.target.renderpass, &self.target.framebuffer) {
+            (Some(renderpass), Some(framebuffer)) => commandbuffer.begin_renderpass(
+                renderpass,
+                framebuffer,
+                extent,
+                &clear_values,
+                contents,
+            ),
+            _ => self.begin_rendering(commandbuffer, &clear_values, contents),
+        }
+
+        self.mesh_renderer.draw(
+            commandbuffer,
+            resources,
+            &culling_camera,
+            frame_index,
+            scene,
+            &[PassTag::Stereo],
+        )?;
+
+        match self.target.renderpass {
+            Some(_) => commandbuffer.end_renderpass(),
+            None => commandbuffer.end_rendering(self.dynamic_rendering()),
+        }
+
+        if let Some(destination) = self.views.and_then(|views| views.destination) {
+            self.blit(commandbuffer, destination);
+        }
+
+        self.target
+            .texture()
+            .transition(commandbuffer, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        Ok(())
+    }
+
+    /// Draws the eyes from `views` instead of the camera until replaced, or follows the camera
+    /// again if None. Views with a destination need to be replaced before each frame.
+    pub fn set_views(&mut self, views: Option<StereoViews>) {
+        self.views = views
+    }
+
+    pub fn views(&self) -> Option<&StereoViews> {
+        self.views.as_ref()
+    }
+
+    /// Draws the eyes side by side into `viewport` of the main pass. Needs to be recorded after
+    /// `draw`. See `FullscreenPass::draw` for `inheritance`.
+    pub fn draw_output(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        frame_index: usize,
+        viewport: vk::Rect2D,
+        inheritance: Option<&Inheritance>,
+    ) -> Result<(), vulkan::Error> {
+        let parameters = OutputParameters {
+            swap_eyes: self.info.swap_eyes as u32,
+        };
+
+        self.output.draw(
+            commandbuffer,
+            frame_index,
+            0,
+            viewport,
+            inheritance,
+            &parameters,
+        )
+    }
+
+    pub fn info(&self) -> &StereoInfo {
+        &self.info
+    }
+
+    /// Sets the distance between the eyes in world units
+    pub fn set_eye_separation(&mut self, eye_separation: f32) {
+        self.info.eye_separation = eye_separation
+    }
+
+    /// Swaps the halves the eyes are presented on
+    pub fn set_swap_eyes(&mut self, swap_eyes: bool) {
+        self.info.swap_eyes = swap_eyes
+    }
+
+    /// Returns the attachments the eyes are drawn into.
+    pub fn target(&self) -> &StereoTarget {
+        &self.target
+    }
+
+    /// Sets the shadow set bound for effects sampling shadows. See
+    /// `MasterRenderer::enable_shadows`.
+    pub fn set_shadow_set(&mut self, shadow_set: Option<DescriptorSet>) {
+        self.mesh_renderer.set_shadow_set(shadow_set);
+    }
+
+    /// Sets the environment set bound for effects lit by the environment. See
+    /// `MasterRenderer::set_environment`.
+    pub fn set_environment_set(&mut self, environment_set: Option<DescriptorSet>) {
+        self.mesh_renderer.set_environment_set(environment_set);
+    }
+
+    // Scales both layers of the texture into the destination
+    fn blit(&self, commandbuffer: &CommandBuffer, destination: StereoDestination) {
+        let texture = self.target.texture();
+
+        commandbuffer.image_barriers(&[
+            texture.barrier(ImageLayout::TRANSFER_SRC_OPTIMAL),
+            ImageBarrier {
+                layer_count: EYE_COUNT as u32,
+                ..ImageBarrier::new(
+                    destination.image,
+                    vk::ImageAspectFlags::COLOR,
+                    1,
+                    ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ImageLayout::TRANSFER_DST_OPTIMAL,
+                )
+            },
+        ]);
+
+        let layers = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: EYE_COUNT as u32,
+        };
+
+        let corner = |extent: Extent| vk::Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: 1,
+        };
+
+        commandbuffer.blit_image(
+            texture.image(),
+            ImageLayout::TRANSFER_SRC_OPTIMAL,
+            destination.image,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[vk::ImageBlit {
+                src_subresource: layers,
+                src_offsets: [vk::Offset3D::default(), corner(texture.extent())],
+                dst_subresource: layers,
+                dst_offsets: [vk::Offset3D::default(), corner(destination.extent)],
+            }],
+            vk::Filter::LINEAR,
+        );
+
+        commandbuffer.image_barriers(&[ImageBarrier {
+            layer_count: EYE_COUNT as u32,
+            ..ImageBarrier::new(
+                destination.image,
+                vk::ImageAspectFlags::COLOR,
+                1,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )
+        }]);
+    }
+
+    fn dynamic_rendering(&self) -> &DynamicRendering {
+        self.context
+            .dynamic_rendering()
+            .expect("Dynamic rendering is not supported")
+    }
+
+    // Transitions the attachments and begins dynamic rendering into both layers. The texture
+    // needs to be in COLOR_ATTACHMENT_OPTIMAL.
+    fn begin_rendering(
+        &self,
+        commandbuffer: &CommandBuffer,
+        clear_values: &[vk::ClearValue; 2],
+        contents: vk::SubpassContents,
+    ) {
+        let target = &self.target;
+
+        // Contents of the previous frame are discarded
+        target.color_attachment.set_layout(ImageLayout::UNDEFINED);
+        target.depth_attachment.set_layout(ImageLayout::UNDEFINED);
+
+        commandbuffer.image_barriers(&[
+            target
+                .color_attachment
+                .barrier(ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            target
+                .depth_attachment
+                .barrier(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+        ]);
+
+        let depth = RenderingAttachment {
+            image_view: target.depth_attachment.image_view(),
+            layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            load: LoadOp::CLEAR,
+            store: StoreOp::DONT_CARE,
+            clear_value: clear_values[1],
+            resolve: None,
+        };
+
+        let stencil = if has_stencil(target.depth_attachment.format()) {
+            Some(&depth)
+        } else {
+            None
+        };
+
+        commandbuffer.begin_rendering(
+            self.dynamic_rendering(),
+            target.extent(),
+            &RenderingAttachments {
+                color: &[RenderingAttachment {
+                    image_view: target.color_attachment.image_view(),
+                    layout: ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    load: LoadOp::CLEAR,
+                    // Resolved into the texture, so the transient contents are never stored
+                    store: StoreOp::DONT_CARE,
+                    clear_value: clear_values[0],
+                    resolve: Some((
+                        target.texture.image_view(),
+                        ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    )),
+                }],
+                depth: Some(&depth),
+                stencil,
+                shading_rate: None,
+                view_mask: VIEW_MASK,
+            },
+            contents,
+        );
+    }
+}
+
+/// Returns a camera whose frustum contains the frusta of both eyes of `camera` separated by
+/// `eye_separation`. Perspective cameras are moved back until the sides of the frustum pass
+/// through the eyes.
+pub fn culling_camera(camera: &Camera, eye_separation: f32) -> Camera {
+    let mut culling = *camera;
+
+    match camera.projection_kind() {
+        Projection::Perspective {
+            fov,
+            aspect_ratio,
+            near,
+            far,
+        } => {
+            let distance = eye_separation * 0.5 / ((fov * 0.5).tan() * aspect_ratio);
+
+            culling.position -= camera.forward() * distance;
+            culling.set_projection(Projection::Perspective {
+                fov,
+                aspect_ratio,
+                near,
+                far: far + distance,
+            });
+        }
+        Projection::Orthographic {
+            width,
+            height,
+            near,
+            far,
+        } => culling.set_projection(Projection::Orthographic {
+            width: width + eye_separation,
+            height,
+            near,
+            far,
+        }),
+    }
+
+    culling
+}
@@ -0,0 +1,565 @@
+//! Terrain generated from a heightmap, divided into a square grid of chunks. Chunk meshes are
+//! generated and streamed around the camera with a level of detail chosen by distance, and drawn
+//! as entities of the scene world with a material of the terrain effect, see
+//! `data/effects/terrain.toml`.
+//!
+//! The terrain effect blends four tiled layer textures by the weights of a splat map. The first
+//! layer is the albedo of the material, and the others are bound by the names in
+//! `SPLAT_MAP_BINDING` and `LAYER_BINDINGS`. See `material_info`.
+//!
+//! Chunks of different detail do not share their edge vertices, so each chunk is surrounded by
+//! a skirt extending downwards which hides the cracks between them.
+use std::path::Path;
+
+use ultraviolet::{Rotor3, Vec2, Vec3};
+
+use crate::document::Transform;
+use crate::ecs::Entity;
+use crate::layers::Layers;
+use crate::object::SCALE;
+use crate::resources::{Handle, ResourceManager};
+use crate::vulkan;
+use crate::{Error, Material, MaterialInfo, Mesh, MeshData, Scene, Vertex};
+
+/// The name of the splat map binding of the terrain effect. The rgba channels weigh the four
+/// layers.
+pub const SPLAT_MAP_BINDING: &str = "splatMap";
+
+/// The names of the bindings of the second to fourth layer of the terrain effect
+pub const LAYER_BINDINGS: [&str; 3] = ["layer1", "layer2", "layer3"];
+
+/// Returns the info of a terrain material of `effect` blending `layers` by `splat_map`, given by
+/// texture names. The splat map needs to be loaded as linear.
+pub fn material_info(effect: &str, splat_map: &str, layers: [&str; 4]) -> MaterialInfo {
+    let mut textures = vec![(SPLAT_MAP_BINDING.to_owned(), splat_map.to_owned())];
+
+    textures.extend(
+        LAYER_BINDINGS
+            .iter()
+            .zip(&layers[1..])
+            .map(|(binding, layer)| ((*binding).to_owned(), (*layer).to_owned())),
+    );
+
+    MaterialInfo {
+        effect: effect.to_owned(),
+        albedo: layers[0].to_owned(),
+        textures,
+        ..Default::default()
+    }
+}
+
+/// A grid of heights in [0, 1]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    width: u32,
+    depth: u32,
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    /// Creates a heightmap from rows of `width` heights along x. Panics if the number of heights
+    /// is not `width * depth`, or the heightmap is smaller than 2x2.
+    pub fn new(width: u32, depth: u32, heights: Vec<f32>) -> Self {
+        assert!(width >= 2 && depth >= 2);
+        assert_eq!(heights.len(), (width * depth) as usize);
+
+        Self {
+            width,
+            depth,
+            heights,
+        }
+    }
+
+    /// Creates a heightmap from the height of each sample, e.g; procedural noise
+    pub fn from_fn(width: u32, depth: u32, f: impl Fn(u32, u32) -> f32) -> Self {
+        let heights = (0..depth)
+            .flat_map(|z| (0..width).map(move |x| (x, z)))
+            .map(|(x, z)| f(x, z))
+            .collect();
+
+        Self::new(width, depth, heights)
+    }
+
+    /// Loads the heights from the red channel of an image. 8 bit images only provide 256
+    /// distinct heights, which causes visible terraces on steep terrain. High dynamic range
+    /// images are read as floats and keep their precision.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let image_error = || Error::from(vulkan::Error::ImageError(path.to_owned()));
+
+        if stb::is_hdr(path) {
+            let image = stb::HdrImage::load(path, 1).ok_or_else(image_error)?;
+            Ok(Self::new(
+                image.width(),
+                image.height(),
+                image.pixels().to_vec(),
+            ))
+        } else {
+            let image = stb::Image::load(path, 1).ok_or_else(image_error)?;
+            Ok(Self::new(
+                image.width(),
+                image.height(),
+                image
+                    .pixels()
+                    .iter()
+                    .map(|height| *height as f32 / 255.0)
+                    .collect(),
+            ))
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn heights(&self) -> &[f32] {
+        &self.heights
+    }
+
+    /// Returns the height of the sample at x, z, clamped to the edges
+    pub fn get(&self, x: i64, z: i64) -> f32 {
+        let x = x.max(0).min(self.width as i64 - 1);
+        let z = z.max(0).min(self.depth as i64 - 1);
+        self.heights[(z * self.width as i64 + x) as usize]
+    }
+
+    /// Returns the bilinearly interpolated height at normalized coordinates, where 0 and 1 are
+    /// the first and last samples.
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = u.clamp(0.0, 1.0) * (self.width - 1) as f32;
+        let z = v.clamp(0.0, 1.0) * (self.depth - 1) as f32;
+
+        let (x0, z0) = (x.floor() as i64, z.floor() as i64);
+        let (tx, tz) = (x.fract(), z.fract());
+
+        let top = self.get(x0, z0) * (1.0 - tx) + self.get(x0 + 1, z0) * tx;
+        let bottom = self.get(x0, z0 + 1) * (1.0 - tx) + self.get(x0 + 1, z0 + 1) * tx;
+
+        top * (1.0 - tz) + bottom * tz
+    }
+
+    /// Returns the world space normal at normalized coordinates of the heightmap spanning `size`
+    /// along x and z and `height` vertically, computed from the slope between the neighbouring
+    /// samples.
+    pub fn normal(&self, u: f32, v: f32, size: f32, height: f32) -> Vec3 {
+        let du = 1.0 / (self.width - 1) as f32;
+        let dv = 1.0 / (self.depth - 1) as f32;
+
+        let sample = |u, v| self.sample(u, v) * height;
+
+        let dx = (sample(u + du, v) - sample(u - du, v)) / (2.0 * du * size);
+        let dz = (sample(u, v + dv) - sample(u, v - dv)) / (2.0 * dv * size);
+
+        Vec3::new(-dx, 1.0, -dz).normalized()
+    }
+
+    /// Returns splat map weights for each sample as tightly packed 8 bit RGBA pixels, for the
+    /// heightmap spanning `size` and `height` as in `normal`. Flat low ground is weighed to the
+    /// first layer, flat high ground to the second, and steep slopes to the third. The fourth
+    /// layer is unused.
+    pub fn splat_map(&self, size: f32, height: f32) -> Vec<u8> {
+        let smoothstep = |edge0: f32, edge1: f32, x: f32| {
+            let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+            t * t * (3.0 - 2.0 * t)
+        };
+
+        let (width, depth) = (self.width, self.depth);
+
+        (0..depth)
+            .flat_map(|z| (0..width).map(move |x| (x, z)))
+            .flat_map(|(x, z)| {
+                let u = x as f32 / (width - 1) as f32;
+                let v = z as f32 / (depth - 1) as f32;
+
+                let steep = smoothstep(0.15, 0.35, 1.0 - self.normal(u, v, size, height).y);
+                let high = smoothstep(0.4, 0.7, self.get(x as i64, z as i64));
+
+                let weight = |weight: f32| (weight * 255.0).round() as u8;
+
+                vec![
+                    weight((1.0 - steep) * (1.0 - high)),
+                    weight((1.0 - steep) * high),
+                    weight(steep),
+                    0,
+                ]
+            })
+            .collect()
+    }
+}
+
+/// Specifies the placement, resolution and streaming of a terrain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainInfo {
+    /// The world position of the corner with the smallest x and z at height zero
+    pub origin: Vec3,
+    /// The extent along x and z in world units
+    pub size: f32,
+    /// The world height of the heightmap value 1.0
+    pub height: f32,
+    /// The number of chunks along each side
+    pub chunks: u32,
+    /// The number of quads along each side of a chunk at the highest level of detail, which is
+    /// halved with each level
+    pub chunk_resolution: u32,
+    /// The number of levels of detail
+    pub lod_levels: u32,
+    /// The distance up to which chunks are drawn at the highest level of detail. Each further
+    /// level extends twice as far as the previous.
+    pub lod_distance: f32,
+    /// Chunks further from the camera are unloaded
+    pub view_distance: f32,
+    /// How far the skirts extend below the edges of each chunk
+    pub skirt_depth: f32,
+    /// The number of times the layer textures repeat across the terrain
+    pub layer_tiling: f32,
+    /// The maximum number of chunk meshes generated per update, which spreads the cost of
+    /// moving the camera over several frames. The closest chunks are generated first.
+    pub max_chunk_updates: usize,
+    /// The layers the chunks are placed in
+    pub layers: Layers,
+}
+
+impl Default for TerrainInfo {
+    fn default() -> Self {
+        Self {
+            origin: Vec3::new(-64.0, 0.0, -64.0),
+            size: 128.0,
+            height: 16.0,
+            chunks: 8,
+            chunk_resolution: 32,
+            lod_levels: 4,
+            lod_distance: 24.0,
+            view_distance: 160.0,
+            skirt_depth: 1.0,
+            layer_tiling: 32.0,
+            max_chunk_updates: 4,
+            layers: Layers::DEFAULT,
+        }
+    }
+}
+
+/// A streamed chunk of the terrain
+#[derive(Debug, Clone, Copy)]
+struct Chunk {
+    x: u32,
+    z: u32,
+    /// The entity drawing the chunk, if loaded
+    entity: Option<Entity>,
+    mesh: Option<Handle<Mesh>>,
+    lod: Option<u32>,
+}
+
+pub struct Terrain {
+    name: String,
+    info: TerrainInfo,
+    heightmap: Heightmap,
+    material: Handle<Material>,
+    chunks: Vec<Chunk>,
+}
+
+impl Terrain {
+    /// Creates a terrain drawn with `material`, whose effect should be the terrain effect. The
+    /// chunk meshes are inserted as '<name>::<x>_<z>_LOD<level>'. No chunks are loaded until the
+    /// first `update`.
+    pub fn new<S: Into<String>>(
+        name: S,
+        heightmap: Heightmap,
+        material: Handle<Material>,
+        info: TerrainInfo,
+    ) -> Self {
+        let chunks = (0..info.chunks)
+            .flat_map(|z| (0..info.chunks).map(move |x| (x, z)))
+            .map(|(x, z)| Chunk {
+                x,
+                z,
+                entity: None,
+                mesh: None,
+                lod: None,
+            })
+            .collect();
+
+        Self {
+            name: name.into(),
+            info,
+            heightmap,
+            material,
+            chunks,
+        }
+    }
+
+    pub fn info(&self) -> &TerrainInfo {
+        &self.info
+    }
+
+    pub fn heightmap(&self) -> &Heightmap {
+        &self.heightmap
+    }
+
+    pub fn material(&self) -> Handle<Material> {
+        self.material
+    }
+
+    /// Returns the number of chunks currently loaded
+    pub fn loaded_chunks(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.lod.is_some())
+            .count()
+    }
+
+    /// Returns the world height of the terrain at the world position x, z, or None outside the
+    /// terrain.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let u = (x - self.info.origin.x) / self.info.size;
+        let v = (z - self.info.origin.z) / self.info.size;
+
+        if (0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v) {
+            Some(self.info.origin.y + self.heightmap.sample(u, v) * self.info.height)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the world space normal at normalized terrain coordinates
+    pub fn normal(&self, u: f32, v: f32) -> Vec3 {
+        self.heightmap
+            .normal(u, v, self.info.size, self.info.height)
+    }
+
+    /// Generates the mesh of the chunk at x, z at level of detail `lod`, relative to the corner
+    /// of the chunk. The first texture coordinates span the whole terrain and sample the splat
+    /// map, and the second repeat the layers by the layer tiling.
+    pub fn chunk_data(&self, x: u32, z: u32, lod: u32) -> MeshData {
+        let quads = (self.info.chunk_resolution >> lod).max(1);
+        let chunk_size = self.info.size / self.info.chunks as f32;
+        let step = chunk_size / quads as f32;
+
+        let row = quads + 1;
+        let mut vertices = Vec::with_capacity((row * row + 4 * quads) as usize);
+        let mut indices = Vec::with_capacity((6 * quads * quads + 24 * quads) as usize);
+
+        // Returns the vertex at grid coordinates i, j lowered by `offset`
+        let vertex = |i: u32, j: u32, offset: f32| {
+            let local = Vec2::new(i as f32 * step, j as f32 * step);
+            let u = (x as f32 * chunk_size + local.x) / self.info.size;
+            let v = (z as f32 * chunk_size + local.y) / self.info.size;
+
+            let height = self.heightmap.sample(u, v) * self.info.height - offset;
+            let texcoord = Vec2::new(u, v);
+
+            Vertex::new(
+                Vec3::new(local.x, height, local.y),
+                self.normal(u, v),
+                texcoord,
+            )
+            .with_texcoord1(texcoord * self.info.layer_tiling)
+        };
+
+        for j in 0..row {
+            for i in 0..row {
+                vertices.push(vertex(i, j, 0.0));
+            }
+        }
+
+        for j in 0..quads {
+            for i in 0..quads {
+                let a = j * row + i;
+                let b = a + 1;
+                let c = a + row;
+                let d = c + 1;
+
+                indices.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+
+        // The edge vertices in order around the chunk
+        let edge = (0..quads)
+            .chain((0..quads).map(|j| j * row + quads))
+            .chain((0..quads).map(|i| quads * row + quads - i))
+            .chain((0..quads).map(|j| (quads - j) * row))
+            .collect::<Vec<_>>();
+
+        let skirt_start = vertices.len() as u32;
+
+        for index in &edge {
+            vertices.push(vertex(index % row, index / row, self.info.skirt_depth));
+        }
+
+        // Skirts are double sided, which keeps them visible regardless of the winding of each
+        // edge
+        for k in 0..edge.len() {
+            let next = (k + 1) % edge.len();
+
+            let (a, b) = (edge[k], edge[next]);
+            let (c, d) = (skirt_start + k as u32, skirt_start + next as u32);
+
+            indices.extend_from_slice(&[a, c, b, b, c, d, a, b, c, b, d, c]);
+        }
+
+        MeshData::new(vertices, indices)
+    }
+
+    /// Returns the level of detail of a chunk at `distance` from the camera, or None if it is
+    /// beyond the view distance.
+    pub fn lod_for_distance(&self, distance: f32) -> Option<u32> {
+        if distance > self.info.view_distance {
+            return None;
+        }
+
+        let mut lod = 0;
+        let mut extent = self.info.lod_distance;
+
+        while distance > extent && lod + 1 < self.info.lod_levels {
+            lod += 1;
+            extent *= 2.0;
+        }
+
+        Some(lod)
+    }
+
+    /// Loads, unloads and changes the level of detail of the chunks around the camera at
+    /// `position`. Should be called once per frame before drawing. At most
+    /// `TerrainInfo::max_chunk_updates` chunks change per call, the closest first. Returns the
+    /// number of chunks which changed.
+    pub fn update(
+        &mut self,
+        position: Vec3,
+        scene: &mut Scene,
+        resources: &mut ResourceManager,
+    ) -> Result<usize, Error> {
+        let chunk_size = self.info.size / self.info.chunks as f32;
+
+        // The horizontal distance from the camera to the bounds of each chunk
+        let mut pending = self
+            .chunks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, chunk)| {
+                let min = Vec2::new(
+                    self.info.origin.x + chunk.x as f32 * chunk_size,
+                    self.info.origin.z + chunk.z as f32 * chunk_size,
+                );
+                let max = min + Vec2::broadcast(chunk_size);
+
+                let closest = Vec2::new(position.x, position.z).clamped(min, max);
+                let distance = (closest - Vec2::new(position.x, position.z)).mag();
+
+                let lod = self.lod_for_distance(distance);
+                if lod != chunk.lod {
+                    Some((distance, index, lod))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        pending.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        pending.truncate(self.info.max_chunk_updates);
+
+        for (_, index, lod) in &pending {
+            match lod {
+                Some(lod) => self.load_chunk(*index, *lod, scene, resources)?,
+                None => self.unload_chunk(*index, scene, resources)?,
+            }
+        }
+
+        Ok(pending.len())
+    }
+
+    /// Removes all chunks from the scene and unloads their meshes
+    pub fn clear(
+        &mut self,
+        scene: &mut Scene,
+        resources: &mut ResourceManager,
+    ) -> Result<(), Error> {
+        for index in 0..self.chunks.len() {
+            self.unload_chunk(index, scene, resources)?;
+        }
+
+        Ok(())
+    }
+
+    fn load_chunk(
+        &mut self,
+        index: usize,
+        lod: u32,
+        scene: &mut Scene,
+        resources: &mut ResourceManager,
+    ) -> Result<(), Error> {
+        let Chunk { x, z, .. } = self.chunks[index];
+        let chunk_size = self.info.size / self.info.chunks as f32;
+
+        let data = self.chunk_data(x, z, lod);
+        let mesh = resources.load_mesh_data(
+            format!(
+                "{}::{}_{}{}{}",
+                self.name,
+                x,
+                z,
+                crate::lod::LOD_SUFFIX,
+                lod
+            ),
+            &data,
+        )?;
+
+        let world = scene.world_mut();
+        let chunk = &mut self.chunks[index];
+
+        match chunk.entity {
+            Some(entity) => {
+                world.insert(entity, mesh);
+            }
+            None => {
+                let entity = world.spawn();
+
+                // The vertices are in world units relative to the corner of the chunk
+                world.insert(
+                    entity,
+                    Transform {
+                        position: self.info.origin
+                            + Vec3::new(x as f32 * chunk_size, 0.0, z as f32 * chunk_size),
+                        rotation: Rotor3::identity(),
+                        scale: Vec3::broadcast(1.0 / SCALE),
+                    },
+                );
+                world.insert(entity, mesh);
+                world.insert(entity, self.material);
+                world.insert(entity, self.info.layers);
+
+                chunk.entity = Some(entity);
+            }
+        }
+
+        if let Some(old) = chunk.mesh.replace(mesh) {
+            resources.unload_mesh(old)?;
+        }
+
+        chunk.lod = Some(lod);
+
+        Ok(())
+    }
+
+    fn unload_chunk(
+        &mut self,
+        index: usize,
+        scene: &mut Scene,
+        resources: &mut ResourceManager,
+    ) -> Result<(), Error> {
+        let chunk = &mut self.chunks[index];
+
+        if let Some(entity) = chunk.entity.take() {
+            scene.world_mut().despawn(entity);
+        }
+
+        if let Some(mesh) = chunk.mesh.take() {
+            resources.unload_mesh(mesh)?;
+        }
+
+        chunk.lod = None;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::rc::Rc;
+
+use ash::vk;
+
+use super::vulkan;
+use vulkan::commands::*;
+use vulkan::*;
+
+/// Secondary command buffers for each view of each frame in flight, for passes drawing into the
+/// main pass after the mesh renderer of each view. The main pass may only execute secondary
+/// command buffers when the mesh renderers record into them.
+pub struct ViewCommands {
+    commandpool: CommandPool,
+    commandbuffers: Vec<Vec<CommandBuffer>>,
+}
+
+impl ViewCommands {
+    pub fn new(context: Rc<VulkanContext>, frames_in_flight: usize) -> Result<Self, vulkan::Error> {
+        let commandpool = CommandPool::new(
+            context.device_ref(),
+            context.queue_families().graphics().unwrap(),
+            false,
+            true,
+        )?;
+
+        Ok(Self {
+            commandpool,
+            commandbuffers: (0..frames_in_flight).map(|_| Vec::new()).collect(),
+        })
+    }
+
+    /// Records `record` directly into `commandbuffer`, or into a secondary command buffer of the
+    /// view which is then executed if `inheritance` is Some. Reusing the secondary command
+    /// buffer requires the previous submission of the frame in flight `frame_index` to have
+    /// completed.
+    pub fn record<F>(
+        &mut self,
+        commandbuffer: &CommandBuffer,
+        frame_index: usize,
+        view_index: usize,
+        inheritance: Option<&Inheritance>,
+        record: F,
+    ) -> Result<(), vulkan::Error>
+    where
+        F: FnOnce(&CommandBuffer),
+    {
+        let inheritance = match inheritance {
+            Some(inheritance) => inheritance,
+            None => {
+                record(commandbuffer);
+                return Ok(());
+            }
+        };
+
+        let commandbuffers = &mut self.commandbuffers[frame_index];
+        while commandbuffers.len() <= view_index {
+            commandbuffers.extend(self.commandpool.allocate_secondary(1)?);
+        }
+
+        let secondary = &commandbuffers[view_index];
+
+        secondary.begin_secondary(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT, inheritance)?;
+        record(secondary);
+        secondary.end()?;
+
+        commandbuffer.execute_commands(&[secondary]);
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use ash::vk;
+
+use crate::vulkan::Extent;
+
+/// A region of the render area relative to its size, which keeps split-screen layouts intact
+/// when the window is resized. (0, 0) is the top left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Returns a viewport covering the whole render area
+    pub fn full() -> Self {
+        Self::new(0.0, 0.0, 1.0, 1.0)
+    }
+
+    /// Splits the render area into `count` equally wide viewports from left to right.
+    pub fn split_horizontal(count: usize) -> Vec<Self> {
+        let width = 1.0 / count as f32;
+
+        (0..count)
+            .map(|i| Self::new(i as f32 * width, 0.0, width, 1.0))
+            .collect()
+    }
+
+    /// Splits the render area into `count` equally tall viewports from top to bottom.
+    pub fn split_vertical(count: usize) -> Vec<Self> {
+        let height = 1.0 / count as f32;
+
+        (0..count)
+            .map(|i| Self::new(0.0, i as f32 * height, 1.0, height))
+            .collect()
+    }
+
+    /// Returns the aspect ratio of the viewport when covering a render area of `extent`.
+    pub fn aspect(&self, extent: Extent) -> f32 {
+        Extent::from(self.rect(extent).extent).aspect()
+    }
+
+    /// Returns the pixel region of the viewport in a render area of `extent`. The region is
+    /// clamped to the render area.
+    pub fn rect(&self, extent: Extent) -> vk::Rect2D {
+        let clamp = |value: f32, max: u32| (value.clamp(0.0, 1.0) * max as f32).round() as u32;
+
+        let x = clamp(self.x, extent.width);
+        let y = clamp(self.y, extent.height);
+
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: x as i32,
+                y: y as i32,
+            },
+            extent: vk::Extent2D {
+                width: clamp(self.x + self.width, extent.width).saturating_sub(x),
+                height: clamp(self.y + self.height, extent.height).saturating_sub(y),
+            },
+        }
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self::full()
+    }
+}
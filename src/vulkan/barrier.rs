@@ -0,0 +1,143 @@
+use ash::vk;
+
+use super::shading_rate;
+
+/// Describes an image layout transition. The access and stage masks are derived from the old
+/// and new layouts rather than specified for every transition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageBarrier {
+    pub image: vk::Image,
+    pub aspect_mask: vk::ImageAspectFlags,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+    pub base_mip_level: u32,
+    /// The number of mip levels to transition starting at `base_mip_level`.
+    pub level_count: u32,
+    /// The number of array layers to transition starting at the first layer.
+    pub layer_count: u32,
+    /// Queue family to release the image from. `vk::QUEUE_FAMILY_IGNORED` for no ownership
+    /// transfer.
+    pub src_queue_family: u32,
+    /// Queue family to acquire the image on. `vk::QUEUE_FAMILY_IGNORED` for no ownership
+    /// transfer.
+    pub dst_queue_family: u32,
+}
+
+impl ImageBarrier {
+    /// Creates a barrier transitioning all mip levels of the first layer of `image`.
+    pub fn new(
+        image: vk::Image,
+        aspect_mask: vk::ImageAspectFlags,
+        mip_levels: u32,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) -> Self {
+        Self {
+            image,
+            aspect_mask,
+            old_layout,
+            new_layout,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            layer_count: 1,
+            src_queue_family: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family: vk::QUEUE_FAMILY_IGNORED,
+        }
+    }
+
+    /// Returns the stages that need to complete before, and wait for, the transition.
+    pub fn stages(&self) -> (vk::PipelineStageFlags, vk::PipelineStageFlags) {
+        (
+            layout_usage(self.old_layout).1,
+            layout_usage(self.new_layout).1,
+        )
+    }
+
+    /// Returns the raw vulkan barrier with the access masks derived from the layouts.
+    pub fn build(&self) -> vk::ImageMemoryBarrier {
+        let (src_access_mask, _) = layout_usage(self.old_layout);
+        let (dst_access_mask, _) = layout_usage(self.new_layout);
+
+        vk::ImageMemoryBarrier {
+            src_access_mask,
+            dst_access_mask,
+            old_layout: self.old_layout,
+            new_layout: self.new_layout,
+            src_queue_family_index: self.src_queue_family,
+            dst_queue_family_index: self.dst_queue_family,
+            image: self.image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: self.aspect_mask,
+                base_mip_level: self.base_mip_level,
+                level_count: self.level_count,
+                base_array_layer: 0,
+                layer_count: self.layer_count,
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Returns the memory accesses and pipeline stages an image in `layout` is used in.
+/// Used for the source scope when transitioning from and the destination scope when
+/// transitioning to `layout`.
+pub fn layout_usage(layout: vk::ImageLayout) -> (vk::AccessFlags, vk::PipelineStageFlags) {
+    match layout {
+        // Contents are discarded, nothing to wait for
+        vk::ImageLayout::UNDEFINED => (
+            vk::AccessFlags::default(),
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        ),
+        vk::ImageLayout::PREINITIALIZED => {
+            (vk::AccessFlags::HOST_WRITE, vk::PipelineStageFlags::HOST)
+        }
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        // Sampled in the vertex stage as well, e.g; displacement maps
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::VERTEX_SHADER
+                | vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::COMPUTE_SHADER,
+        ),
+        // Storage images accessed through image load/store
+        vk::ImageLayout::GENERAL => (
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        shading_rate::ATTACHMENT_LAYOUT => (
+            shading_rate::ATTACHMENT_READ,
+            shading_rate::ATTACHMENT_STAGE,
+        ),
+        // Presentation engine is synchronized with semaphores
+        vk::ImageLayout::PRESENT_SRC_KHR => (
+            vk::AccessFlags::default(),
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        ),
+        // Fall back to a full barrier for less common layouts
+        _ => (
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+        ),
+    }
+}
@@ -1,11 +1,11 @@
 //! This module contains low level buffer helper functions
-use std::{mem, rc::Rc};
+use std::{marker::PhantomData, mem, mem::MaybeUninit, ptr, rc::Rc, slice};
 
 use ash::vk;
 use vk::DeviceSize;
 use vk_mem::Allocator;
 
-use super::{commands::*, context::VulkanContext, Error, Extent};
+use super::{commands::*, context::VulkanContext, memory, DebugName, Error, Extent};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 // Defines the type of a buffer
@@ -20,6 +20,42 @@ pub enum BufferType {
     Uniform,
     /// Storage buffer
     Storage,
+    /// Indirect draw buffer containing `vk::DrawIndirectCommand` or
+    /// `vk::DrawIndexedIndirectCommand`
+    Indirect,
+    /// Buffer written to by transfer operations and read back on the CPU
+    /// Always uses host visible memory, usage should be `Mapped` or `MappedPersistent`
+    Readback,
+}
+
+impl BufferType {
+    /// Returns the access flags the buffer is consumed with by the pipeline
+    pub fn access_mask(&self) -> vk::AccessFlags {
+        match self {
+            BufferType::Vertex => vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            BufferType::Index16 | BufferType::Index32 => vk::AccessFlags::INDEX_READ,
+            BufferType::Uniform => vk::AccessFlags::UNIFORM_READ,
+            BufferType::Storage => vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            BufferType::Indirect => vk::AccessFlags::INDIRECT_COMMAND_READ,
+            BufferType::Readback => vk::AccessFlags::HOST_READ,
+        }
+    }
+
+    /// Returns the pipeline stages the buffer is first consumed in
+    pub fn stage_mask(&self) -> vk::PipelineStageFlags {
+        match self {
+            BufferType::Vertex | BufferType::Index16 | BufferType::Index32 => {
+                vk::PipelineStageFlags::VERTEX_INPUT
+            }
+            BufferType::Uniform | BufferType::Storage => {
+                vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COMPUTE_SHADER
+            }
+            BufferType::Indirect => vk::PipelineStageFlags::DRAW_INDIRECT,
+            BufferType::Readback => vk::PipelineStageFlags::HOST,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -41,6 +77,26 @@ pub enum BufferUsage {
     MappedPersistent,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Defines how a resource is shared between the graphics, transfer and compute queue families
+pub enum QueueSharing {
+    /// Resource is only accessed by the graphics queue family
+    /// Uploads are recorded and submitted on the graphics queue
+    Exclusive,
+    /// Resource is accessed concurrently by the graphics, transfer and compute queue families
+    /// Uploads are submitted on the transfer queue without any ownership transfer
+    Concurrent,
+    /// Resource is exclusively owned by the graphics queue family, but uploads are performed on
+    /// the transfer queue. Ownership is explicitly released and reacquired after each upload
+    OwnershipTransfer,
+}
+
+impl Default for QueueSharing {
+    fn default() -> Self {
+        Self::Exclusive
+    }
+}
+
 /// Higher level construct abstracting buffer and buffer memory for index,
 /// vertex and uniform use
 /// buffer usage
@@ -54,6 +110,9 @@ pub struct Buffer {
     size: DeviceSize,
     ty: BufferType,
     usage: BufferUsage,
+    sharing: QueueSharing,
+    // Mapped ranges need to be flushed and invalidated if not coherent
+    coherent: bool,
 
     // If a staging buffer is persisted
     staging_buffer: Option<(vk::Buffer, vk_mem::Allocation, vk_mem::AllocationInfo)>,
@@ -66,6 +125,37 @@ impl Buffer {
         ty: BufferType,
         usage: BufferUsage,
         size: DeviceSize,
+    ) -> Result<Self, Error> {
+        Self::new_uninit_with_sharing(context, ty, usage, size, QueueSharing::Exclusive)
+    }
+
+    /// Creates a new buffer with size and zeroed contents, e.g; for buffers which are only
+    /// partially written before being read by the device.
+    pub fn new_zeroed(
+        context: Rc<VulkanContext>,
+        ty: BufferType,
+        usage: BufferUsage,
+        size: DeviceSize,
+    ) -> Result<Self, Error> {
+        let mut buffer = Self::new_uninit(context, ty, usage, size)?;
+
+        if size > 0 {
+            buffer.write(size, 0, |mapped| unsafe {
+                ptr::write_bytes(mapped, 0, size as usize)
+            })?;
+        }
+
+        Ok(buffer)
+    }
+
+    /// Creates a new buffer with size and uninitialized contents using the provided queue
+    /// sharing policy.
+    pub fn new_uninit_with_sharing(
+        context: Rc<VulkanContext>,
+        ty: BufferType,
+        usage: BufferUsage,
+        size: DeviceSize,
+        sharing: QueueSharing,
     ) -> Result<Self, Error> {
         // Calculate the buffer usage flags
         let vk_usage = match ty {
@@ -73,6 +163,8 @@ impl Buffer {
             BufferType::Index16 | BufferType::Index32 => vk::BufferUsageFlags::INDEX_BUFFER,
             BufferType::Uniform => vk::BufferUsageFlags::UNIFORM_BUFFER,
             BufferType::Storage => vk::BufferUsageFlags::STORAGE_BUFFER,
+            BufferType::Indirect => vk::BufferUsageFlags::INDIRECT_BUFFER,
+            BufferType::Readback => vk::BufferUsageFlags::TRANSFER_DST,
         } | match usage {
             BufferUsage::Mapped | BufferUsage::MappedPersistent => vk::BufferUsageFlags::default(),
             BufferUsage::Staged | BufferUsage::StagedPersistent => {
@@ -80,33 +172,53 @@ impl Buffer {
             }
         };
 
-        let memory_usage = match usage {
-            BufferUsage::Staged | BufferUsage::StagedPersistent => vk_mem::MemoryUsage::GpuOnly,
-            BufferUsage::Mapped | BufferUsage::MappedPersistent => vk_mem::MemoryUsage::CpuToGpu,
-        };
-
         let flags = match usage {
             BufferUsage::MappedPersistent => vk_mem::AllocationCreateFlags::MAPPED,
             _ => vk_mem::AllocationCreateFlags::NONE,
         };
 
+        let allocation_create_info = match (ty, usage) {
+            (BufferType::Readback, _) => vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuToCpu,
+                flags,
+                ..Default::default()
+            },
+            (_, BufferUsage::Staged) | (_, BufferUsage::StagedPersistent) => {
+                vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::GpuOnly,
+                    flags,
+                    ..Default::default()
+                }
+            }
+            // Frequently updated, prefer device local memory when all of it is host visible
+            (_, BufferUsage::Mapped) | (_, BufferUsage::MappedPersistent) => {
+                memory::host_visible_allocation_info(context.capabilities().resizable_bar, flags)
+            }
+        };
+
+        let queue_family_indices = context.sharing_families(sharing);
+
+        let sharing_mode = if queue_family_indices.is_empty() {
+            vk::SharingMode::EXCLUSIVE
+        } else {
+            vk::SharingMode::CONCURRENT
+        };
+
         // Create the main GPU side buffer
         let buffer_info = vk::BufferCreateInfo::builder()
             .size(size as _)
             .usage(vk_usage)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            .sharing_mode(sharing_mode)
+            .queue_family_indices(&queue_family_indices);
 
         let allocator = context.allocator();
 
         // Create the buffer
-        let (buffer, allocation, allocation_info) = allocator.create_buffer(
-            &buffer_info,
-            &vk_mem::AllocationCreateInfo {
-                usage: memory_usage,
-                flags,
-                ..Default::default()
-            },
-        )?;
+        let (buffer, allocation, allocation_info) =
+            allocator.create_buffer(&buffer_info, &allocation_create_info)?;
+        context.objects().created(vk::ObjectType::BUFFER);
+
+        let coherent = context.is_coherent(allocation_info.get_memory_type());
 
         Ok(Self {
             size,
@@ -116,6 +228,8 @@ impl Buffer {
             allocation_info,
             ty,
             usage,
+            sharing,
+            coherent,
             staging_buffer: None,
         })
     }
@@ -126,19 +240,30 @@ impl Buffer {
         ty: BufferType,
         usage: BufferUsage,
         data: &[T],
+    ) -> Result<Self, Error> {
+        Self::new_with_sharing(context, ty, usage, data, QueueSharing::Exclusive)
+    }
+
+    /// Creates a new buffer and fills it with data using the provided queue sharing policy.
+    pub fn new_with_sharing<T>(
+        context: Rc<VulkanContext>,
+        ty: BufferType,
+        usage: BufferUsage,
+        data: &[T],
+        sharing: QueueSharing,
     ) -> Result<Self, Error> {
         let size = (mem::size_of::<T>() * data.len()) as DeviceSize;
 
-        let mut buffer = Self::new_uninit(context, ty, usage, size)?;
+        let mut buffer = Self::new_uninit_with_sharing(context, ty, usage, size, sharing)?;
         // Fill the buffer with provided data
         buffer.fill(0, data)?;
         Ok(buffer)
     }
 
     /// Update the buffer data by mapping memory and filling it using the
-    /// provided closure.
-    /// `len`: Specifies the number of items of T to map into slice. (is ignored with persistent
-    /// usage).
+    /// provided closure. The closure is given a write only view since the mapped memory may be
+    /// write combined.
+    /// `len`: Specifies the number of items of T to map.
     /// `offset`: Specifies the offset in items T into buffer to map.
     pub fn write_slice<T, F>(
         &mut self,
@@ -147,11 +272,11 @@ impl Buffer {
         write_func: F,
     ) -> Result<(), Error>
     where
-        F: FnOnce(&mut [T]),
+        F: FnOnce(MappedWriter<T>),
     {
         let size = len * mem::size_of::<T>() as u64;
         self.write(size, offset * mem::size_of::<T>() as u64, |ptr| {
-            write_func(unsafe { std::slice::from_raw_parts_mut(ptr as *mut T, len as usize) })
+            write_func(unsafe { MappedWriter::new(ptr as *mut T, len as usize) })
         })
     }
 
@@ -175,13 +300,21 @@ impl Buffer {
                 max_size: self.size,
             });
         }
+
+        #[cfg(debug_assertions)]
+        assert!(
+            offset + size <= self.size,
+            "Write of {} bytes at {} is outside buffer of {} bytes",
+            size,
+            offset,
+            self.size
+        );
+
         match self.usage {
             BufferUsage::Staged => self.write_staged(size, offset, write_func),
-            BufferUsage::StagedPersistent => self.write_staged_persistent(offset, write_func),
-            BufferUsage::Mapped => self.write_mapped(offset, write_func),
-            BufferUsage::MappedPersistent => {
-                self.write_mapped_persistent(size, offset, write_func)
-            }
+            BufferUsage::StagedPersistent => self.write_staged_persistent(size, offset, write_func),
+            BufferUsage::Mapped => self.write_mapped(size, offset, write_func),
+            BufferUsage::MappedPersistent => self.write_mapped_persistent(size, offset, write_func),
         }
     }
 
@@ -196,21 +329,23 @@ impl Buffer {
     where
         F: FnOnce(*mut u8),
     {
-        let allocator = self.context.allocator();
         let mapped = self.allocation_info.get_mapped_data();
 
         unsafe {
             write_func(mapped.offset(offset as _));
         }
 
-        allocator.flush_allocation(&self.allocation, offset as _, size as _)?;
-
-        Ok(())
+        self.flush(size, offset)
     }
 
     // Updates memory by mapping and unmapping
     // Will map the whole buffer
-    fn write_mapped<F>(&self, offset: DeviceSize, write_func: F) -> Result<(), Error>
+    fn write_mapped<F>(
+        &self,
+        size: DeviceSize,
+        offset: DeviceSize,
+        write_func: F,
+    ) -> Result<(), Error>
     where
         F: FnOnce(*mut u8),
     {
@@ -221,10 +356,50 @@ impl Buffer {
             write_func(mapped.offset(offset as _));
         }
 
+        self.flush(size, offset)?;
+
         allocator.unmap_memory(&self.allocation)?;
         Ok(())
     }
 
+    // Makes host writes to a mapped range visible to the device if the memory is not coherent
+    fn flush(&self, size: DeviceSize, offset: DeviceSize) -> Result<(), Error> {
+        if self.coherent || size == 0 {
+            return Ok(());
+        }
+
+        let (offset, size) = self.atom_range(size, offset);
+        self.context
+            .allocator()
+            .flush_allocation(&self.allocation, offset as _, size as _)?;
+
+        Ok(())
+    }
+
+    // Makes device writes to a mapped range visible to the host if the memory is not coherent
+    fn invalidate(&self, size: DeviceSize, offset: DeviceSize) -> Result<(), Error> {
+        if self.coherent || size == 0 {
+            return Ok(());
+        }
+
+        let (offset, size) = self.atom_range(size, offset);
+        self.context
+            .allocator()
+            .invalidate_allocation(&self.allocation, offset as _, size as _)?;
+
+        Ok(())
+    }
+
+    // Returns the range aligned to the non coherent atom size of the device
+    fn atom_range(&self, size: DeviceSize, offset: DeviceSize) -> (DeviceSize, DeviceSize) {
+        memory::align_to_atom(
+            offset,
+            size,
+            self.context.limits().non_coherent_atom_size,
+            self.allocation_info.get_size() as _,
+        )
+    }
+
     fn write_staged<F>(
         &self,
         size: DeviceSize,
@@ -244,14 +419,7 @@ impl Buffer {
         // Use the write function to write into the mapped memory
         write_func(mapped);
 
-        copy(
-            self.context.transfer_pool(),
-            self.context.graphics_queue(),
-            staging_buffer,
-            self.buffer,
-            size as _,
-            offset,
-        )?;
+        self.upload(staging_buffer, size as _, offset)?;
 
         // Destroy the staging buffer
         allocator.destroy_buffer(staging_buffer, &staging_allocation)?;
@@ -261,6 +429,7 @@ impl Buffer {
 
     fn write_staged_persistent<F>(
         &mut self,
+        size: DeviceSize,
         offset: DeviceSize,
         write_func: F,
     ) -> Result<(), Error>
@@ -284,20 +453,152 @@ impl Buffer {
         // Use the write function to write into the mapped memory
         write_func(mapped);
 
-        copy(
-            self.context.transfer_pool(),
-            self.context.graphics_queue(),
-            *staging_buffer,
-            self.buffer,
-            self.size as _,
-            offset,
-        )?;
+        self.upload(*staging_buffer, size, offset)?;
 
         // Unmap but keep staging buffer
         allocator.unmap_memory(&staging_memory)?;
         Ok(())
     }
 
+    // Copies from a staging buffer into self on the queue dictated by the sharing policy
+    fn upload(
+        &self,
+        staging_buffer: vk::Buffer,
+        size: DeviceSize,
+        offset: DeviceSize,
+    ) -> Result<(), Error> {
+        let queue_families = self.context.queue_families();
+
+        match self.sharing {
+            QueueSharing::Exclusive => copy(
+                self.context.transfer_pool(),
+                self.context.graphics_queue(),
+                staging_buffer,
+                self.buffer,
+                size,
+                offset,
+            ),
+            QueueSharing::Concurrent => copy(
+                self.context.dedicated_transfer_pool(),
+                self.context.transfer_queue(),
+                staging_buffer,
+                self.buffer,
+                size,
+                offset,
+            ),
+            QueueSharing::OwnershipTransfer if !queue_families.has_dedicated_transfer() => copy(
+                self.context.transfer_pool(),
+                self.context.graphics_queue(),
+                staging_buffer,
+                self.buffer,
+                size,
+                offset,
+            ),
+            QueueSharing::OwnershipTransfer => {
+                let barrier = vk::BufferMemoryBarrier {
+                    src_queue_family_index: queue_families.transfer().unwrap(),
+                    dst_queue_family_index: queue_families.graphics().unwrap(),
+                    buffer: self.buffer,
+                    offset,
+                    size,
+                    ..Default::default()
+                };
+
+                let region = vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: offset,
+                    size,
+                };
+
+                // Copy and release ownership on the transfer queue
+                self.context.dedicated_transfer_pool().single_time_command(
+                    self.context.transfer_queue(),
+                    |commandbuffer| {
+                        commandbuffer.copy_buffer(staging_buffer, self.buffer, &[region]);
+                        commandbuffer.buffer_barrier(
+                            vk::PipelineStageFlags::TRANSFER,
+                            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                            &[vk::BufferMemoryBarrier {
+                                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                                ..barrier
+                            }],
+                        )
+                    },
+                )?;
+
+                // Acquire ownership on the graphics queue
+                self.context.transfer_pool().single_time_command(
+                    self.context.graphics_queue(),
+                    |commandbuffer| {
+                        commandbuffer.buffer_barrier(
+                            vk::PipelineStageFlags::TOP_OF_PIPE,
+                            self.ty.stage_mask(),
+                            &[vk::BufferMemoryBarrier {
+                                dst_access_mask: self.ty.access_mask(),
+                                ..barrier
+                            }],
+                        )
+                    },
+                )
+            }
+        }
+    }
+
+    /// Reads the buffer contents by mapping memory and passing it to the provided closure.
+    /// `len`: Specifies the number of items of T to read.
+    /// `offset`: Specifies the offset in items T into buffer to read.
+    pub fn read_slice<T, F, R>(
+        &self,
+        len: DeviceSize,
+        offset: DeviceSize,
+        read_func: F,
+    ) -> Result<R, Error>
+    where
+        F: FnOnce(&[T]) -> R,
+    {
+        let size = len * mem::size_of::<T>() as u64;
+        self.read(size, offset * mem::size_of::<T>() as u64, |ptr| {
+            read_func(unsafe { std::slice::from_raw_parts(ptr as *const T, len as usize) })
+        })
+    }
+
+    /// Reads the buffer contents by mapping memory and passing it to the provided closure.
+    /// Only supported for `Mapped` and `MappedPersistent` usage, and fails for staged buffers.
+    /// The caller is responsible for ensuring device writes have completed.
+    /// `size`: Specifies the number of bytes to read
+    /// `offset`: Specifies the offset in bytes into buffer to read
+    pub fn read<F, R>(&self, size: DeviceSize, offset: DeviceSize, read_func: F) -> Result<R, Error>
+    where
+        F: FnOnce(*const u8) -> R,
+    {
+        if size + offset > self.size {
+            return Err(Error::BufferOverflow {
+                size: size + offset,
+                max_size: self.size,
+            });
+        }
+
+        let allocator = self.context.allocator();
+
+        match self.usage {
+            BufferUsage::MappedPersistent => {
+                self.invalidate(size, offset)?;
+                let mapped = self.allocation_info.get_mapped_data();
+                Ok(read_func(unsafe { mapped.offset(offset as _) }))
+            }
+            BufferUsage::Mapped => {
+                let mapped = allocator.map_memory(&self.allocation)?;
+                self.invalidate(size, offset)?;
+                let result = read_func(unsafe { mapped.offset(offset as _) });
+                allocator.unmap_memory(&self.allocation)?;
+                Ok(result)
+            }
+            BufferUsage::Staged | BufferUsage::StagedPersistent => {
+                Err(Error::UnreadableBuffer(self.usage))
+            }
+        }
+    }
+
     /// Fills the buffer  with provided data
     /// Uses write internally
     /// data cannot be larger in size than maximum buffer size
@@ -327,6 +628,11 @@ impl Buffer {
     pub fn ty(&self) -> BufferType {
         self.ty
     }
+
+    /// Returns the queue sharing policy of the buffer
+    pub fn sharing(&self) -> QueueSharing {
+        self.sharing
+    }
 }
 
 impl AsRef<vk::Buffer> for Buffer {
@@ -341,19 +647,119 @@ impl Into<vk::Buffer> for &Buffer {
     }
 }
 
+impl DebugName for Buffer {
+    fn set_debug_name(&self, context: &VulkanContext, name: &str) {
+        context.set_object_name(self.buffer, name);
+    }
+}
+
 impl Drop for Buffer {
     fn drop(&mut self) {
         let allocator = self.context.allocator();
-        allocator
-            .destroy_buffer(self.buffer, &self.allocation)
-            .unwrap();
+        // Errors are logged since panicking in drop aborts when already unwinding
+        if let Err(e) = allocator.destroy_buffer(self.buffer, &self.allocation) {
+            log::error!("Failed to destroy buffer: {}", e);
+        }
+        self.context.objects().destroyed(vk::ObjectType::BUFFER);
 
         // Destroy persistent staging buffer
         if let Some((buffer, memory, _)) = self.staging_buffer.take() {
-            allocator.unmap_memory(&memory).unwrap();
-            allocator.destroy_buffer(buffer, &memory).unwrap();
+            let result = allocator
+                .unmap_memory(&memory)
+                .and_then(|_| allocator.destroy_buffer(buffer, &memory));
+
+            if let Err(e) = result {
+                log::error!("Failed to destroy staging buffer: {}", e);
+            }
+        }
+    }
+}
+
+/// A write only view of `len` elements of mapped memory.
+/// Mapped memory is often write combined, which makes reading from it, e.g; by read-modify-write
+/// patterns such as `+=` or by indexing into a slice, very slow. The writer therefore only allows
+/// writing whole elements.
+pub struct MappedWriter<'a, T> {
+    ptr: *mut T,
+    len: usize,
+    marker: PhantomData<&'a mut [T]>,
+}
+
+impl<'a, T> MappedWriter<'a, T> {
+    /// Creates a writer from mapped memory valid for writes of `len` elements for the lifetime
+    /// of the writer.
+    unsafe fn new(ptr: *mut T, len: usize) -> Self {
+        #[cfg(debug_assertions)]
+        assert!(
+            len == 0 || ptr as usize & (mem::align_of::<T>() - 1) == 0,
+            "Mapped memory is not aligned for {}",
+            std::any::type_name::<T>()
+        );
+
+        Self {
+            ptr,
+            len,
+            marker: PhantomData,
         }
     }
+
+    /// Returns the number of elements the writer fits
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Writes `value` at `index`. Panics if `index` is out of bounds.
+    pub fn write(&mut self, index: usize, value: T) {
+        assert!(
+            index < self.len,
+            "Write at {} is out of bounds for mapped memory of {} elements",
+            index,
+            self.len
+        );
+
+        unsafe { self.ptr.add(index).write(value) }
+    }
+
+    /// Writes each element produced by `iter` starting at `offset` and returns the number of
+    /// written elements. Panics if the elements do not fit.
+    pub fn write_iter<I>(&mut self, offset: usize, iter: I) -> usize
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut count = 0;
+        for (i, value) in iter.into_iter().enumerate() {
+            self.write(offset + i, value);
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Copies `data` starting at `offset`. Panics if the elements do not fit.
+    pub fn write_slice(&mut self, offset: usize, data: &[T])
+    where
+        T: Copy,
+    {
+        assert!(
+            offset + data.len() <= self.len,
+            "Write of {} elements at {} is out of bounds for mapped memory of {} elements",
+            data.len(),
+            offset,
+            self.len
+        );
+
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), self.ptr.add(offset), data.len()) }
+    }
+
+    /// Returns the memory as uninitialized elements, e.g; for passing to functions writing into
+    /// a slice. The contents must not be read.
+    pub fn as_uninit(&mut self) -> &mut [MaybeUninit<T>] {
+        unsafe { slice::from_raw_parts_mut(self.ptr as *mut MaybeUninit<T>, self.len) }
+    }
 }
 
 /// Creates a suitable general purpose staging buffer
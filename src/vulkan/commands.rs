@@ -1,7 +1,14 @@
-use std::rc::Rc;
-
-use super::pipeline::Pipeline;
+use std::cell::RefCell;
+use std::{mem, rc::Rc};
+
+use super::barrier::ImageBarrier;
+use super::debug_utils;
+use super::dynamic_rendering::{DynamicRendering, RenderingAttachments, RenderingFormats};
+use super::mesh_shader::MeshShader;
+use super::pipeline::{ComputePipeline, Pipeline};
 use super::renderpass::RenderPass;
+use super::shading_rate::FragmentShadingRate;
+use super::validation::{self, AttachmentFormats, CommandState};
 use super::Error;
 use super::{
     buffer::{Buffer, BufferType},
@@ -9,6 +16,7 @@ use super::{
 };
 use super::{framebuffer::Framebuffer, Extent};
 use arrayvec::ArrayVec;
+use ash::extensions::ext::DebugUtils;
 use ash::vk;
 use ash::Device;
 use ash::{version::DeviceV1_0, vk::PipelineLayout};
@@ -18,6 +26,21 @@ use ash::{version::DeviceV1_0, vk::PipelineLayout};
 /// binding
 pub const MAX_VB_BINDING: usize = 4;
 
+/// The renderpass or dynamic rendering a secondary command buffer is executed within
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inheritance {
+    /// Executed within `subpass` of `renderpass` with any compatible framebuffer
+    RenderPass {
+        renderpass: vk::RenderPass,
+        subpass: u32,
+    },
+    /// Executed within dynamic rendering to attachments of `formats`
+    Dynamic {
+        formats: RenderingFormats,
+        samples: vk::SampleCountFlags,
+    },
+}
+
 pub struct CommandPool {
     device: Rc<Device>,
     commandpool: vk::CommandPool,
@@ -55,9 +78,22 @@ impl CommandPool {
     }
 
     pub fn allocate(&self, count: u32) -> Result<Vec<CommandBuffer>, Error> {
+        self.allocate_level(count, vk::CommandBufferLevel::PRIMARY)
+    }
+
+    /// Allocates secondary command buffers which are executed from primary command buffers
+    pub fn allocate_secondary(&self, count: u32) -> Result<Vec<CommandBuffer>, Error> {
+        self.allocate_level(count, vk::CommandBufferLevel::SECONDARY)
+    }
+
+    fn allocate_level(
+        &self,
+        count: u32,
+        level: vk::CommandBufferLevel,
+    ) -> Result<Vec<CommandBuffer>, Error> {
         let alloc_info = vk::CommandBufferAllocateInfo::builder()
             .command_pool(self.commandpool)
-            .level(vk::CommandBufferLevel::PRIMARY)
+            .level(level)
             .command_buffer_count(count);
 
         // Allocate handles
@@ -69,6 +105,7 @@ impl CommandPool {
             .map(|commandbuffer| CommandBuffer {
                 device: self.device.clone(),
                 commandbuffer: *commandbuffer,
+                state: RefCell::default(),
             })
             .collect::<Vec<_>>();
 
@@ -137,6 +174,8 @@ impl Drop for CommandPool {
 pub struct CommandBuffer {
     device: Rc<Device>,
     commandbuffer: vk::CommandBuffer,
+    /// Checked against when binding in debug builds, see the `validation` module
+    state: RefCell<CommandState>,
 }
 
 impl CommandBuffer {
@@ -152,22 +191,88 @@ impl CommandBuffer {
                 .begin_command_buffer(self.commandbuffer, &begin_info)?
         };
 
+        self.state.borrow_mut().begin(false, None);
+
         Ok(())
     }
 
-    // Ends recording of commandbuffer
+    /// Starts recording of a secondary commandbuffer which continues the renderpass or dynamic
+    /// rendering described by `inheritance`
+    pub fn begin_secondary(
+        &self,
+        flags: vk::CommandBufferUsageFlags,
+        inheritance: &Inheritance,
+    ) -> Result<(), Error> {
+        let rendering_info = match inheritance {
+            Inheritance::Dynamic { formats, samples } => Some(formats.inheritance_info(*samples)),
+            Inheritance::RenderPass { .. } => None,
+        };
+
+        let inheritance_info = match inheritance {
+            Inheritance::RenderPass {
+                renderpass,
+                subpass,
+            } => vk::CommandBufferInheritanceInfo {
+                render_pass: *renderpass,
+                subpass: *subpass,
+                ..Default::default()
+            },
+            Inheritance::Dynamic { .. } => vk::CommandBufferInheritanceInfo {
+                p_next: rendering_info.as_ref().unwrap() as *const _ as *const std::ffi::c_void,
+                ..Default::default()
+            },
+        };
+
+        let begin_info = vk::CommandBufferBeginInfo {
+            flags: flags | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+            p_inheritance_info: &inheritance_info,
+            ..Default::default()
+        };
+
+        unsafe {
+            self.device
+                .begin_command_buffer(self.commandbuffer, &begin_info)?
+        };
+
+        let target = match inheritance {
+            Inheritance::RenderPass {
+                renderpass,
+                subpass,
+            } => validation::subpass_formats(*renderpass, *subpass),
+            Inheritance::Dynamic { formats, samples } => {
+                Some(AttachmentFormats::dynamic(formats, *samples))
+            }
+        };
+
+        self.state.borrow_mut().begin(true, target);
+
+        Ok(())
+    }
+
+    /// Ends recording of commandbuffer. In debug builds, returns the first pipeline or
+    /// descriptor set bound since `begin` which was incompatible with where it was used.
     pub fn end(&self) -> Result<(), Error> {
         unsafe { self.device.end_command_buffer(self.commandbuffer)? };
-        Ok(())
+
+        let mut state = self.state.borrow_mut();
+        state.end();
+
+        match state.take_error() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 
     // Begins a renderpass
+    // `contents`: Whether the first subpass is recorded inline or executed from secondary
+    // command buffers
     pub fn begin_renderpass(
         &self,
         renderpass: &RenderPass,
         framebuffer: &Framebuffer,
         extent: Extent,
         clear_values: &[vk::ClearValue],
+        contents: vk::SubpassContents,
     ) {
         let begin_info = vk::RenderPassBeginInfo {
             s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
@@ -183,21 +288,117 @@ impl CommandBuffer {
         };
 
         unsafe {
-            self.device.cmd_begin_render_pass(
-                self.commandbuffer,
-                &begin_info,
-                vk::SubpassContents::INLINE,
-            )
+            self.device
+                .cmd_begin_render_pass(self.commandbuffer, &begin_info, contents)
         }
+
+        self.state
+            .borrow_mut()
+            .begin_rendering(Some(renderpass.subpass_formats(0).clone()));
     }
 
     // Ends current renderpass
     pub fn end_renderpass(&self) {
         unsafe { self.device.cmd_end_render_pass(self.commandbuffer) }
+        self.state.borrow_mut().end_rendering();
+    }
+
+    /// Begins dynamic rendering into the given attachments without a renderpass or framebuffer.
+    /// The attachments are expected to already be in their specified layouts.
+    pub fn begin_rendering(
+        &self,
+        dynamic_rendering: &DynamicRendering,
+        extent: Extent,
+        attachments: &RenderingAttachments,
+        contents: vk::SubpassContents,
+    ) {
+        dynamic_rendering.begin_rendering(self.commandbuffer, extent, attachments, contents);
+
+        // The formats of the attachment views are not known
+        self.state.borrow_mut().begin_rendering(None);
+    }
+
+    /// Executes secondary command buffers from within a renderpass or dynamic rendering begun
+    /// with secondary command buffer contents
+    pub fn execute_commands(&self, commandbuffers: &[&CommandBuffer]) {
+        debug_assert!(
+            commandbuffers
+                .iter()
+                .all(|commandbuffer| commandbuffer.state.borrow().is_executable()),
+            "Executed secondary command buffer which was not recorded"
+        );
+
+        let commandbuffers = commandbuffers
+            .iter()
+            .map(|commandbuffer| commandbuffer.commandbuffer)
+            .collect::<Vec<_>>();
+
+        unsafe {
+            self.device
+                .cmd_execute_commands(self.commandbuffer, &commandbuffers)
+        }
+    }
+
+    /// Clears the depth of the bound depth attachment to 1.0 within `rect`. Must be called within
+    /// a renderpass or dynamic rendering.
+    pub fn clear_depth(&self, rect: vk::Rect2D) {
+        let attachment = vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            color_attachment: 0,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        };
+
+        let rect = vk::ClearRect {
+            rect,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        unsafe {
+            self.device
+                .cmd_clear_attachments(self.commandbuffer, &[attachment], &[rect])
+        }
+    }
+
+    /// Ends the current dynamic rendering
+    pub fn end_rendering(&self, dynamic_rendering: &DynamicRendering) {
+        dynamic_rendering.end_rendering(self.commandbuffer);
+        self.state.borrow_mut().end_rendering();
+    }
+
+    /// Opens a labeled region shown by debuggers, e.g; around a pass. Regions can be nested
+    /// and need to be closed with `end_label` in the same command buffer.
+    pub fn begin_label(&self, debug_utils: &DebugUtils, name: &str) {
+        let name = debug_utils::to_cstring(name);
+        unsafe {
+            debug_utils.cmd_begin_debug_utils_label(self.commandbuffer, &debug_utils::label(&name))
+        }
+    }
+
+    /// Closes the innermost labeled region
+    pub fn end_label(&self, debug_utils: &DebugUtils) {
+        unsafe { debug_utils.cmd_end_debug_utils_label(self.commandbuffer) }
+    }
+
+    /// Inserts a single label, e.g; to mark an event within a region
+    pub fn insert_label(&self, debug_utils: &DebugUtils, name: &str) {
+        let name = debug_utils::to_cstring(name);
+        unsafe {
+            debug_utils.cmd_insert_debug_utils_label(self.commandbuffer, &debug_utils::label(&name))
+        }
     }
 
     // Binds a graphics pipeline
     pub fn bind_pipeline(&self, pipeline: &Pipeline) {
+        self.state
+            .borrow_mut()
+            .bind_graphics_pipeline(pipeline.attachment_formats());
+
         unsafe {
             self.device.cmd_bind_pipeline(
                 self.commandbuffer,
@@ -207,6 +408,81 @@ impl CommandBuffer {
         }
     }
 
+    pub fn bind_compute_pipeline(&self, pipeline: &ComputePipeline) {
+        self.state.borrow_mut().bind_compute_pipeline();
+
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                self.commandbuffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.pipeline(),
+            )
+        }
+    }
+
+    /// Sets the viewport and scissor to cover `extent`. Required before drawing since the
+    /// viewport of pipelines is dynamic.
+    pub fn set_viewport(&self, extent: Extent) {
+        self.set_viewport_rect(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: extent.into(),
+        })
+    }
+
+    /// Sets the viewport and scissor to cover the region `rect` of the attachments, e.g; for
+    /// split-screen rendering.
+    pub fn set_viewport_rect(&self, rect: vk::Rect2D) {
+        let viewport = vk::Viewport {
+            x: rect.offset.x as f32,
+            y: rect.offset.y as f32,
+            width: rect.extent.width as f32,
+            height: rect.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+
+        let scissor = rect;
+
+        unsafe {
+            self.device
+                .cmd_set_viewport(self.commandbuffer, 0, &[viewport]);
+            self.device
+                .cmd_set_scissor(self.commandbuffer, 0, &[scissor]);
+        }
+    }
+
+    /// Sets the stencil reference of both faces for pipelines using a dynamic stencil reference.
+    pub fn set_stencil_reference(&self, reference: u32) {
+        unsafe {
+            self.device.cmd_set_stencil_reference(
+                self.commandbuffer,
+                vk::StencilFaceFlags::FRONT_AND_BACK,
+                reference,
+            )
+        }
+    }
+
+    /// Sets the line width for pipelines using a dynamic line width. Widths other than 1.0
+    /// require `DeviceFeatures::wide_lines`.
+    pub fn set_line_width(&self, width: f32) {
+        unsafe { self.device.cmd_set_line_width(self.commandbuffer, width) }
+    }
+
+    /// Sets the fragment size for pipelines using a dynamic shading rate. The rate of the
+    /// shading rate attachment is used instead where `attachment` is true.
+    pub fn set_fragment_shading_rate(
+        &self,
+        fragment_shading_rate: &FragmentShadingRate,
+        fragment_size: Extent,
+        attachment: bool,
+    ) {
+        fragment_shading_rate.set_fragment_shading_rate(
+            self.commandbuffer,
+            fragment_size.into(),
+            attachment,
+        )
+    }
+
     pub fn bind_vertexbuffers(&self, first_binding: u32, vertexbuffers: &[&Buffer]) {
         let buffers: ArrayVec<[vk::Buffer; MAX_VB_BINDING]> =
             vertexbuffers.iter().map(|vb| vb.buffer()).collect();
@@ -238,12 +514,23 @@ impl CommandBuffer {
         }
     }
 
+    /// Binds descriptor sets to the pipeline layout.
+    /// `dynamic_offsets` contains one offset for each dynamic descriptor in the sets, in order.
     pub fn bind_descriptor_sets<P: AsRef<PipelineLayout>>(
         &self,
         pipeline_layout: &P,
         first_set: u32,
         descriptor_sets: &[vk::DescriptorSet],
+        dynamic_offsets: &[u32],
     ) {
+        if validation::ENABLED {
+            self.state.borrow_mut().check_descriptor_sets(
+                *pipeline_layout.as_ref(),
+                first_set,
+                descriptor_sets,
+            );
+        }
+
         unsafe {
             self.device.cmd_bind_descriptor_sets(
                 self.commandbuffer,
@@ -251,11 +538,71 @@ impl CommandBuffer {
                 *pipeline_layout.as_ref(),
                 first_set,
                 descriptor_sets,
-                &[],
+                dynamic_offsets,
             )
         }
     }
 
+    /// Binds descriptor sets to the pipeline layout of a compute pipeline. See
+    /// `bind_descriptor_sets`.
+    pub fn bind_compute_descriptor_sets<P: AsRef<PipelineLayout>>(
+        &self,
+        pipeline_layout: &P,
+        first_set: u32,
+        descriptor_sets: &[vk::DescriptorSet],
+        dynamic_offsets: &[u32],
+    ) {
+        if validation::ENABLED {
+            self.state.borrow_mut().check_descriptor_sets(
+                *pipeline_layout.as_ref(),
+                first_set,
+                descriptor_sets,
+            );
+        }
+
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                self.commandbuffer,
+                vk::PipelineBindPoint::COMPUTE,
+                *pipeline_layout.as_ref(),
+                first_set,
+                descriptor_sets,
+                dynamic_offsets,
+            )
+        }
+    }
+
+    /// Updates the push constants of `stages` at `offset` with the bytes of `data`. The range
+    /// needs to be declared by the shaders of the pipeline layout.
+    pub fn push_constants<P: AsRef<PipelineLayout>, T>(
+        &self,
+        pipeline_layout: &P,
+        stages: vk::ShaderStageFlags,
+        offset: u32,
+        data: &T,
+    ) {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(data as *const T as *const u8, mem::size_of::<T>())
+        };
+
+        unsafe {
+            self.device.cmd_push_constants(
+                self.commandbuffer,
+                *pipeline_layout.as_ref(),
+                stages,
+                offset,
+                bytes,
+            )
+        }
+    }
+
+    /// Dispatches the bound compute pipeline with the given number of workgroups in each
+    /// dimension.
+    pub fn dispatch(&self, x: u32, y: u32, z: u32) {
+        self.state.borrow_mut().dispatch();
+        unsafe { self.device.cmd_dispatch(self.commandbuffer, x, y, z) }
+    }
+
     // Issues a draw command using the currently vertex buffer
     pub fn draw(
         &self,
@@ -264,6 +611,8 @@ impl CommandBuffer {
         first_vertex: u32,
         instance_offset: u32,
     ) {
+        self.state.borrow_mut().draw();
+
         unsafe {
             self.device.cmd_draw(
                 self.commandbuffer,
@@ -284,6 +633,8 @@ impl CommandBuffer {
         vertex_offset: i32,
         first_instance: u32,
     ) {
+        self.state.borrow_mut().draw();
+
         unsafe {
             self.device.cmd_draw_indexed(
                 self.commandbuffer,
@@ -296,6 +647,75 @@ impl CommandBuffer {
         }
     }
 
+    /// Draws `x * y * z` workgroups of the task shader of the bound pipeline, or of the mesh
+    /// shader if the pipeline has no task shader
+    pub fn draw_mesh_tasks(&self, mesh_shader: &MeshShader, x: u32, y: u32, z: u32) {
+        self.state.borrow_mut().draw();
+        mesh_shader.draw_mesh_tasks(self.commandbuffer, x, y, z)
+    }
+
+    /// Issues `draw_count` draws with parameters sourced from `buffer` as
+    /// `vk::DrawIndirectCommand` starting at byte `offset` and separated by `stride` bytes.
+    /// Drawing more than one draw requires the `multiDrawIndirect` feature.
+    pub fn draw_indirect(
+        &self,
+        buffer: &Buffer,
+        offset: vk::DeviceSize,
+        draw_count: u32,
+        stride: u32,
+    ) {
+        validate_indirect(
+            buffer,
+            offset,
+            draw_count,
+            stride,
+            mem::size_of::<vk::DrawIndirectCommand>(),
+        );
+        self.state.borrow_mut().draw();
+
+        unsafe {
+            self.device.cmd_draw_indirect(
+                self.commandbuffer,
+                buffer.buffer(),
+                offset,
+                draw_count,
+                stride,
+            )
+        }
+    }
+
+    /// Issues `draw_count` indexed draws using the currently bound vertex and index buffers
+    /// with parameters sourced from `buffer` as `vk::DrawIndexedIndirectCommand` starting at byte
+    /// `offset` and separated by `stride` bytes.
+    /// Drawing more than one draw requires the `multiDrawIndirect` feature, and draws with a
+    /// first instance other than 0 require the `drawIndirectFirstInstance` feature.
+    pub fn draw_indexed_indirect(
+        &self,
+        buffer: &Buffer,
+        offset: vk::DeviceSize,
+        draw_count: u32,
+        stride: u32,
+    ) {
+        validate_indirect(
+            buffer,
+            offset,
+            draw_count,
+            stride,
+            mem::size_of::<vk::DrawIndexedIndirectCommand>(),
+        );
+        self.state.borrow_mut().draw();
+
+        unsafe {
+            self.device.cmd_draw_indexed_indirect(
+                self.commandbuffer,
+                buffer.buffer(),
+                offset,
+                draw_count,
+                stride,
+            )
+        }
+    }
+
     pub fn copy_buffer(&self, src: vk::Buffer, dst: vk::Buffer, regions: &[vk::BufferCopy]) {
         unsafe {
             self.device
@@ -303,6 +723,16 @@ impl CommandBuffer {
         }
     }
 
+    /// Writes `data` into `buffer` at `offset` inline in the command buffer, ordered with the
+    /// other commands. The buffer needs transfer destination usage, e.g; `BufferUsage::Staged`.
+    /// `offset` and the size of `data` must be multiples of 4 and the size no larger than 65536.
+    pub fn update_buffer(&self, buffer: vk::Buffer, offset: vk::DeviceSize, data: &[u8]) {
+        unsafe {
+            self.device
+                .cmd_update_buffer(self.commandbuffer, buffer, offset, data)
+        }
+    }
+
     /// Copies a buffer to an image
     pub fn copy_buffer_image(
         &self,
@@ -317,6 +747,20 @@ impl CommandBuffer {
         }
     }
 
+    /// Copies regions of an image to a buffer
+    pub fn copy_image_buffer(
+        &self,
+        src: vk::Image,
+        layout: vk::ImageLayout,
+        dst: vk::Buffer,
+        regions: &[vk::BufferImageCopy],
+    ) {
+        unsafe {
+            self.device
+                .cmd_copy_image_to_buffer(self.commandbuffer, src, layout, dst, regions)
+        }
+    }
+
     pub fn pipeline_barrier(
         &self,
         src_stage_mask: vk::PipelineStageFlags,
@@ -336,6 +780,69 @@ impl CommandBuffer {
         }
     }
 
+    /// Inserts a pipeline barrier transitioning images. The stage masks are the union of all
+    /// barriers' stages.
+    pub fn image_barriers(&self, barriers: &[ImageBarrier]) {
+        let (src_stage_mask, dst_stage_mask) = barriers.iter().fold(
+            (
+                vk::PipelineStageFlags::default(),
+                vk::PipelineStageFlags::default(),
+            ),
+            |(src, dst), barrier| {
+                let (barrier_src, barrier_dst) = barrier.stages();
+                (src | barrier_src, dst | barrier_dst)
+            },
+        );
+
+        let barriers = barriers
+            .iter()
+            .map(|barrier| barrier.build())
+            .collect::<Vec<_>>();
+
+        self.pipeline_barrier(src_stage_mask, dst_stage_mask, &barriers)
+    }
+
+    /// Inserts a pipeline barrier with buffer memory barriers.
+    pub fn buffer_barrier(
+        &self,
+        src_stage_mask: vk::PipelineStageFlags,
+        dst_stage_mask: vk::PipelineStageFlags,
+        buffer_barriers: &[vk::BufferMemoryBarrier],
+    ) {
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                self.commandbuffer,
+                src_stage_mask,
+                dst_stage_mask,
+                vk::DependencyFlags::default(),
+                &[],
+                buffer_barriers,
+                &[],
+            )
+        }
+    }
+
+    /// Inserts a pipeline barrier with global memory barriers, e.g; for acceleration structures
+    /// which are not accessed through their buffers
+    pub fn memory_barrier(
+        &self,
+        src_stage_mask: vk::PipelineStageFlags,
+        dst_stage_mask: vk::PipelineStageFlags,
+        memory_barriers: &[vk::MemoryBarrier],
+    ) {
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                self.commandbuffer,
+                src_stage_mask,
+                dst_stage_mask,
+                vk::DependencyFlags::default(),
+                memory_barriers,
+                &[],
+                &[],
+            )
+        }
+    }
+
     pub fn blit_image(
         &self,
         src: vk::Image,
@@ -393,6 +900,11 @@ impl CommandBuffer {
         fence: vk::Fence,
         wait_stages: &[vk::PipelineStageFlags],
     ) -> Result<(), Error> {
+        debug_assert!(
+            self.state.borrow().is_executable(),
+            "Submitted command buffer which was not recorded"
+        );
+
         let submit_info = vk::SubmitInfo {
             s_type: vk::StructureType::SUBMIT_INFO,
             p_next: std::ptr::null(),
@@ -422,3 +934,31 @@ impl Into<vk::CommandBuffer> for &CommandBuffer {
         self.commandbuffer
     }
 }
+
+// Asserts the valid usage rules of indirect draws
+fn validate_indirect(
+    buffer: &Buffer,
+    offset: vk::DeviceSize,
+    draw_count: u32,
+    stride: u32,
+    command_size: usize,
+) {
+    assert_eq!(buffer.ty(), BufferType::Indirect);
+    assert_eq!(offset % 4, 0, "Indirect offset must be a multiple of 4");
+
+    if draw_count > 1 {
+        assert!(
+            stride % 4 == 0 && stride as usize >= command_size,
+            "Indirect stride must be a multiple of 4 and no smaller than the draw command"
+        );
+    }
+
+    if draw_count > 0 {
+        let end = offset + (draw_count as u64 - 1) * stride as u64 + command_size as u64;
+        assert!(
+            end <= buffer.size(),
+            "Indirect draws read {} bytes past the end of the buffer",
+            end - buffer.size()
+        );
+    }
+}
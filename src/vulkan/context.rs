@@ -1,57 +1,222 @@
 use super::commands::CommandPool;
+use super::dynamic_rendering::DynamicRendering;
+use super::memory::{MemoryBudget, MemoryReport};
+use super::mesh_shader::MeshShader;
+use super::mipmap::MipGenerator;
+use super::ray_tracing::RayTracing;
+use super::shading_rate::FragmentShadingRate;
+use super::tracking::{HostAllocator, ObjectTracker};
 use super::*;
+use arrayvec::ArrayVec;
 use ash::extensions::ext::DebugUtils;
 use ash::extensions::khr::Surface;
+use ash::version::InstanceV1_0;
 use ash::vk;
 use log::info;
 
 use glfw::Glfw;
+use std::cell::{Cell, RefCell, RefMut};
 use std::rc::Rc;
 
-use super::device::QueueFamilies;
+use super::device::{DeviceCapabilities, DeviceFeatures, QueueFamilies};
+
+/// Specifies context creation info.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ContextInfo {
+    /// The optional device features to enable if supported
+    pub features: DeviceFeatures,
+    /// Passes allocation callbacks counting the host allocations of the driver when creating
+    /// objects through the context. See `VulkanContext::host_allocations`.
+    pub track_host_allocations: bool,
+    /// Treats all host visible memory as non coherent, flushing and invalidating mapped ranges
+    /// regardless of the memory type. Exercises the non coherent paths on devices where all
+    /// host visible memory is coherent.
+    pub force_non_coherent: bool,
+    /// Additional instance extensions to enable, e.g; those required by an OpenXR runtime.
+    /// Creation fails if any are missing.
+    pub instance_extensions: Vec<String>,
+    /// Additional device extensions to enable. Devices missing any of them are not used.
+    pub device_extensions: Vec<String>,
+}
 
+/// Owns the instance, device and queues. The context and everything created from it hold
+/// `Rc<VulkanContext>` or `Rc<ash::Device>` and are neither Send nor Sync, which confines all
+/// Vulkan objects to the thread that created the context. Work which should happen on other
+/// threads, such as decoding images or building scenes, is done on plain data and handed back
+/// to this thread to be uploaded.
 pub struct VulkanContext {
     _entry: ash::Entry,
     instance: ash::Instance,
     device: Rc<ash::Device>,
     physical_device: vk::PhysicalDevice,
     queue_families: QueueFamilies,
-    debug_utils: Option<(DebugUtils, vk::DebugUtilsMessengerEXT)>,
+    debug_utils: DebugUtils,
+    /// Forwards validation messages to the log if validation layers are enabled
+    debug_messenger: Option<vk::DebugUtilsMessengerEXT>,
 
     surface_loader: Surface,
-    surface: vk::SurfaceKHR,
+    // Replaced when the surface is lost
+    surface: Cell<vk::SurfaceKHR>,
 
     graphics_queue: vk::Queue,
     present_queue: vk::Queue,
+    /// Queue of the transfer family. Same as graphics queue if there is no dedicated transfer
+    /// family.
+    transfer_queue: vk::Queue,
+    /// Queue of the compute family. Same as graphics queue if there is no dedicated compute
+    /// family.
+    compute_queue: vk::Queue,
     allocator: vk_mem::Allocator,
 
     /// CommandPool for allocatig transfer command buffers
     /// Wrap in option to drop early
     transfer_pool: Option<CommandPool>,
 
+    /// CommandPool for allocating command buffers submitted to the transfer queue
+    /// Wrap in option to drop early
+    dedicated_transfer_pool: Option<CommandPool>,
+
+    /// Loaded dynamic rendering commands if supported by the device
+    dynamic_rendering: Option<DynamicRendering>,
+
+    /// Loaded mesh shader commands if the mesh shader feature is enabled
+    mesh_shader: Option<MeshShader>,
+
+    /// Loaded acceleration structure commands if the ray query feature is enabled
+    ray_tracing: Option<RayTracing>,
+
+    /// Loaded variable rate shading commands if the fragment shading rate feature is enabled
+    fragment_shading_rate: Option<FragmentShadingRate>,
+
+    /// Heap budget queries if `VK_EXT_memory_budget` is enabled
+    memory_budget: Option<MemoryBudget>,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    force_non_coherent: bool,
+
+    capabilities: DeviceCapabilities,
     limits: vk::PhysicalDeviceLimits,
+
+    /// Counts the live objects created through the context to report leaks on drop
+    objects: ObjectTracker,
+    /// Referenced by the allocation callbacks and dropped after the instance is destroyed
+    host_allocator: Option<Box<HostAllocator>>,
+    allocation_callbacks: Option<vk::AllocationCallbacks>,
     msaa_samples: vk::SampleCountFlags,
+
+    /// Anisotropy of samplers which do not specify their own
+    default_anisotropy: Cell<f32>,
+
+    /// Created when the mip levels of a texture are first generated in a compute shader.
+    /// Taken to drop before the device.
+    mip_generator: RefCell<Option<MipGenerator>>,
 }
 
 impl VulkanContext {
+    /// Creates a new vulkan context requesting all optional device features.
     pub fn new(glfw: &Glfw, window: &glfw::Window) -> Result<Self, Error> {
+        Self::new_with_features(glfw, window, DeviceFeatures::default())
+    }
+
+    /// Creates a new vulkan context enabling the `requested` optional device features that are
+    /// supported. Check `capabilities` for the enabled features.
+    pub fn new_with_features(
+        glfw: &Glfw,
+        window: &glfw::Window,
+        requested: DeviceFeatures,
+    ) -> Result<Self, Error> {
+        Self::new_with_info(
+            glfw,
+            window,
+            ContextInfo {
+                features: requested,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Creates a new vulkan context according to `info`.
+    pub fn new_with_info(
+        glfw: &Glfw,
+        window: &glfw::Window,
+        info: ContextInfo,
+    ) -> Result<Self, Error> {
+        let host_allocator = if info.track_host_allocations {
+            Some(HostAllocator::new())
+        } else {
+            None
+        };
+
+        let allocation_callbacks = host_allocator
+            .as_ref()
+            .map(|host_allocator| host_allocator.callbacks());
+        let callbacks = allocation_callbacks.as_ref();
+
         let entry = entry::create()?;
-        let instance = instance::create(&entry, &glfw, "Vulkan Application", "Custom")?;
+        let api_version = instance::negotiate_api_version(&entry)?;
+        let instance = instance::create(
+            &entry,
+            &glfw,
+            "Vulkan Application",
+            "Custom",
+            api_version,
+            &info.instance_extensions,
+            callbacks,
+        )?;
 
-        // Create debug utils if validation layers are enabled
-        let debug_utils = if instance::ENABLE_VALIDATION_LAYERS {
-            Some(debug_utils::create(&entry, &instance)?)
+        let debug_utils = debug_utils::create_loader(&entry, &instance);
+
+        // Create the messenger if validation layers are enabled
+        let debug_messenger = if instance::ENABLE_VALIDATION_LAYERS {
+            Some(debug_utils::create_messenger(&debug_utils, callbacks)?)
         } else {
             None
         };
 
-        // debug_utils::create(&entry, &instance)?;
         let surface_loader = surface::create_loader(&entry, &instance);
 
-        let surface = surface::create(&instance, &window)?;
-        let (device, pdevice_info) =
-            device::create(&instance, &surface_loader, surface, instance::get_layers())?;
+        let surface = surface::create(&instance, &window, callbacks)?;
+        let (device, pdevice_info, capabilities) = device::create(
+            &entry,
+            &instance,
+            api_version,
+            &surface_loader,
+            surface,
+            &info,
+            callbacks,
+        )?;
         log::debug!("Using device: {}", pdevice_info.name);
+        log::debug!(
+            "Vulkan version: {}.{}",
+            vk::version_major(capabilities.api_version),
+            vk::version_minor(capabilities.api_version)
+        );
+        log::debug!("Device capabilities: {:#?}", capabilities);
+
+        let dynamic_rendering = if pdevice_info.dynamic_rendering {
+            DynamicRendering::new(&instance, &device)
+        } else {
+            None
+        };
+
+        log::debug!("Dynamic rendering: {}", dynamic_rendering.is_some());
+
+        let mesh_shader = if capabilities.features.mesh_shader {
+            MeshShader::new(&instance, &device)
+        } else {
+            None
+        };
+
+        let ray_tracing = if capabilities.features.ray_query {
+            Some(RayTracing::new(&instance, &device))
+        } else {
+            None
+        };
+
+        let fragment_shading_rate = if capabilities.features.fragment_shading_rate {
+            FragmentShadingRate::new(&instance, &device)
+        } else {
+            None
+        };
 
         // Get the physical device limits
         let limits = device::get_limits(&instance, pdevice_info.physical_device);
@@ -61,6 +226,22 @@ impl VulkanContext {
         let present_queue =
             device::get_queue(&device, pdevice_info.queue_families.present().unwrap(), 0);
 
+        let transfer_family = pdevice_info
+            .queue_families
+            .transfer()
+            .or(pdevice_info.queue_families.graphics())
+            .unwrap();
+
+        let transfer_queue = device::get_queue(&device, transfer_family, 0);
+
+        let compute_family = pdevice_info
+            .queue_families
+            .compute()
+            .or(pdevice_info.queue_families.graphics())
+            .unwrap();
+
+        let compute_queue = device::get_queue(&device, compute_family, 0);
+
         let allocator_info = vk_mem::AllocatorCreateInfo {
             physical_device: pdevice_info.physical_device,
             device: (*device).clone(),
@@ -73,6 +254,15 @@ impl VulkanContext {
 
         let allocator = vk_mem::Allocator::new(&allocator_info)?;
 
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(pdevice_info.physical_device) };
+
+        let memory_budget = if capabilities.memory_budget {
+            Some(MemoryBudget::new(&entry, &instance))
+        } else {
+            None
+        };
+
         let transfer_pool = CommandPool::new(
             device.clone(),
             pdevice_info.queue_families.graphics().unwrap(),
@@ -80,6 +270,9 @@ impl VulkanContext {
             true,
         )?;
 
+        let dedicated_transfer_pool =
+            CommandPool::new(device.clone(), transfer_family, true, true)?;
+
         let msaa_samples = get_max_msaa_samples(
             limits.framebuffer_color_sample_counts & limits.sampled_image_color_sample_counts,
         );
@@ -91,14 +284,31 @@ impl VulkanContext {
             physical_device: pdevice_info.physical_device,
             queue_families: pdevice_info.queue_families,
             debug_utils,
+            debug_messenger,
             surface_loader,
-            surface,
+            surface: Cell::new(surface),
             graphics_queue,
             present_queue,
+            transfer_queue,
+            compute_queue,
             allocator,
             transfer_pool: Some(transfer_pool),
+            dedicated_transfer_pool: Some(dedicated_transfer_pool),
+            dynamic_rendering,
+            mesh_shader,
+            ray_tracing,
+            fragment_shading_rate,
+            memory_budget,
+            memory_properties,
+            force_non_coherent: info.force_non_coherent,
+            default_anisotropy: Cell::new(capabilities.max_sampler_anisotropy),
+            mip_generator: RefCell::new(None),
+            capabilities,
             limits,
             msaa_samples,
+            objects: ObjectTracker::new(),
+            host_allocator,
+            allocation_callbacks,
         })
     }
 
@@ -128,8 +338,45 @@ impl VulkanContext {
         self.graphics_queue
     }
 
+    /// Returns the queue of the transfer family.
+    /// Same as `graphics_queue` when there is no dedicated transfer family.
+    pub fn transfer_queue(&self) -> vk::Queue {
+        self.transfer_queue
+    }
+
+    /// Returns the queue of the compute family.
+    /// Same as `graphics_queue` when there is no dedicated compute family.
+    pub fn compute_queue(&self) -> vk::Queue {
+        self.compute_queue
+    }
+
     pub fn surface(&self) -> vk::SurfaceKHR {
-        self.surface
+        self.surface.get()
+    }
+
+    /// Creates a new surface for `window` after the current one was lost. Returns the lost
+    /// surface, which needs to be destroyed with `destroy_surface` after all swapchains created
+    /// for it have been destroyed.
+    pub fn recreate_surface(&self, window: &glfw::Window) -> Result<vk::SurfaceKHR, Error> {
+        let surface = surface::create(&self.instance, window, self.allocation_callbacks())?;
+
+        Ok(self.surface.replace(surface))
+    }
+
+    /// Destroys a lost surface returned by `recreate_surface`.
+    pub fn destroy_surface(&self, surface: vk::SurfaceKHR) {
+        surface::destroy(&self.surface_loader, surface, self.allocation_callbacks());
+    }
+
+    /// Returns the debug utils functions used to name objects and label command buffers
+    pub fn debug_utils(&self) -> &DebugUtils {
+        &self.debug_utils
+    }
+
+    /// Names a Vulkan object for debuggers and validation messages. See `DebugName` for naming
+    /// the objects of resources.
+    pub fn set_object_name<H: vk::Handle>(&self, handle: H, name: &str) {
+        debug_utils::set_object_name(&self.debug_utils, self.device.handle(), handle, name)
     }
 
     pub fn surface_loader(&self) -> &Surface {
@@ -144,10 +391,63 @@ impl VulkanContext {
         &self.allocator
     }
 
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        &self.memory_properties
+    }
+
+    /// Returns true if host writes to memory of `memory_type` are visible to the device without
+    /// flushing and vice versa.
+    pub fn is_coherent(&self, memory_type: u32) -> bool {
+        !self.force_non_coherent
+            && self.memory_properties.memory_types[memory_type as usize]
+                .property_flags
+                .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+    }
+
+    /// Returns the allocator statistics along with the budget and usage of each memory heap
+    pub fn memory_report(&self) -> Result<MemoryReport, Error> {
+        MemoryReport::new(
+            &self.allocator,
+            self.physical_device,
+            &self.memory_properties,
+            self.memory_budget.as_ref(),
+            self.capabilities.resizable_bar,
+        )
+    }
+
     pub fn limits(&self) -> &vk::PhysicalDeviceLimits {
         &self.limits
     }
 
+    /// Returns the enabled optional features and capabilities of the device
+    pub fn capabilities(&self) -> &DeviceCapabilities {
+        &self.capabilities
+    }
+
+    /// Returns the allocation callbacks to create and destroy objects with. None unless
+    /// `ContextInfo::track_host_allocations` was set.
+    pub fn allocation_callbacks(&self) -> Option<&vk::AllocationCallbacks> {
+        self.allocation_callbacks.as_ref()
+    }
+
+    /// Returns the live host allocations of the driver if `ContextInfo::track_host_allocations`
+    /// was set. Only allocations of objects created with `allocation_callbacks` are counted.
+    pub fn host_allocations(&self) -> Option<&HostAllocator> {
+        self.host_allocator.as_deref()
+    }
+
+    /// Returns the live objects created through the context. Types creating objects through
+    /// the context count them here.
+    pub fn objects(&self) -> &ObjectTracker {
+        &self.objects
+    }
+
+    /// Returns the negotiated Vulkan version, e.g; to choose between core and extension
+    /// functions. Compare against `vk::make_version`.
+    pub fn api_version(&self) -> u32 {
+        self.capabilities.api_version
+    }
+
     /// Returns a commandpool that can be used to allocate for transfer
     /// operations
     pub fn transfer_pool(&self) -> &CommandPool {
@@ -157,31 +457,146 @@ impl VulkanContext {
             .expect("Transfer pool is only None when dropped")
     }
 
+    /// Returns a commandpool that allocates command buffers for submission on `transfer_queue`
+    pub fn dedicated_transfer_pool(&self) -> &CommandPool {
+        &self
+            .dedicated_transfer_pool
+            .as_ref()
+            .expect("Transfer pool is only None when dropped")
+    }
+
+    /// Returns the queue family indices that need concurrent access for `sharing`.
+    /// Returns an empty list if the resource should use exclusive sharing.
+    pub fn sharing_families(&self, sharing: QueueSharing) -> ArrayVec<[u32; 3]> {
+        let mut families = ArrayVec::new();
+
+        if sharing != QueueSharing::Concurrent {
+            return families;
+        }
+
+        let queue_families = &self.queue_families;
+        families.push(queue_families.graphics().unwrap());
+
+        for family in [queue_families.transfer(), queue_families.compute()]
+            .iter()
+            .flatten()
+        {
+            if !families.contains(family) {
+                families.push(*family);
+            }
+        }
+
+        // A single family is accessed exclusively
+        if families.len() == 1 {
+            families.clear();
+        }
+
+        families
+    }
+
+    /// Returns the dynamic rendering commands if `VK_KHR_dynamic_rendering` is supported and
+    /// enabled
+    pub fn dynamic_rendering(&self) -> Option<&DynamicRendering> {
+        self.dynamic_rendering.as_ref()
+    }
+
+    /// Returns the mesh shader commands if `DeviceFeatures::mesh_shader` was requested and is
+    /// supported
+    pub fn mesh_shader(&self) -> Option<&MeshShader> {
+        self.mesh_shader.as_ref()
+    }
+
+    /// Returns the acceleration structure commands if `DeviceFeatures::ray_query` was requested
+    /// and is supported
+    pub fn ray_tracing(&self) -> Option<&RayTracing> {
+        self.ray_tracing.as_ref()
+    }
+
+    /// Returns the variable rate shading commands if `DeviceFeatures::fragment_shading_rate`
+    /// was requested and is supported
+    pub fn fragment_shading_rate(&self) -> Option<&FragmentShadingRate> {
+        self.fragment_shading_rate.as_ref()
+    }
+
+    /// Returns true if optimally tiled images of `format` support `features`
+    pub fn supports_format(&self, format: vk::Format, features: vk::FormatFeatureFlags) -> bool {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+        };
+
+        properties.optimal_tiling_features.contains(features)
+    }
+
+    /// Returns the generator of compute generated mip levels, see `MipGeneration::Compute`
+    pub fn mip_generator(&self) -> RefMut<'_, MipGenerator> {
+        RefMut::map(self.mip_generator.borrow_mut(), |generator| {
+            generator.get_or_insert_with(|| MipGenerator::new(self.device.clone()))
+        })
+    }
+
     /// Returns the maximum number of samples for framebuffer color attachments
     pub fn msaa_samples(&self) -> vk::SampleCountFlags {
         self.msaa_samples
     }
+
+    /// Returns the anisotropy used by samplers which do not specify their own.
+    /// Defaults to the maximum anisotropy supported by the device
+    pub fn default_anisotropy(&self) -> f32 {
+        self.default_anisotropy.get()
+    }
+
+    /// Sets the anisotropy inherited by samplers created afterwards which do not specify their
+    /// own. The value is clamped to the device limit.
+    pub fn set_default_anisotropy(&self, anisotropy: f32) -> Result<(), Error> {
+        self.default_anisotropy
+            .set(sampler::clamp_anisotropy(self, anisotropy)?);
+        Ok(())
+    }
 }
 
 impl Drop for VulkanContext {
     fn drop(&mut self) {
         info!("Destroying vulkan context");
+
+        // All objects created through the context should have been dropped along with their
+        // references to the context
+        for (object_type, count) in self.objects.live() {
+            log::warn!("Leaked {} {:?} objects", count, object_type);
+        }
+
+        let callbacks = self.allocation_callbacks;
+        let callbacks = callbacks.as_ref();
+
         // Destroy the allocator
         self.allocator.destroy();
 
-        // Destroy the transfer pool before device destruction
+        // Destroy the transfer pools and mip pipelines before device destruction
+        self.mip_generator.get_mut().take();
         self.transfer_pool.take();
+        self.dedicated_transfer_pool.take();
 
         // Destroy the device
-        device::destroy(&self.device);
+        device::destroy(&self.device, callbacks);
 
-        // Destroy debug utils if present
-        if let Some((debug_utils, debug_messenger)) = self.debug_utils.take() {
-            debug_utils::destroy(&debug_utils, debug_messenger)
+        // Destroy the debug messenger if present
+        if let Some(debug_messenger) = self.debug_messenger.take() {
+            debug_utils::destroy(&self.debug_utils, debug_messenger, callbacks)
         }
 
-        surface::destroy(&self.surface_loader, self.surface);
-        instance::destroy(&self.instance);
+        surface::destroy(&self.surface_loader, self.surface.get(), callbacks);
+        instance::destroy(&self.instance, callbacks);
+
+        if let Some(host_allocator) = &self.host_allocator {
+            for (scope, stats) in host_allocator.stats() {
+                log::warn!(
+                    "Leaked {} host allocations of {} bytes in {:?} scope",
+                    stats.count,
+                    stats.bytes,
+                    scope
+                );
+            }
+        }
     }
 }
 
@@ -1,18 +1,29 @@
-use super::Error;
+use super::{Error, VulkanContext};
 use ash::extensions::ext::DebugUtils;
 use ash::vk;
 use ash::vk::DebugUtilsMessengerEXT;
 use ash::Entry;
 use ash::Instance;
 use log::*;
-use std::ffi::{c_void, CStr};
+use std::ffi::{c_void, CStr, CString};
 
-pub fn create(
-    entry: &Entry,
-    instance: &Instance,
-) -> Result<(DebugUtils, DebugUtilsMessengerEXT), Error> {
-    let debug_utils = DebugUtils::new(entry, instance);
+/// Vulkan objects which can be named after the resources they belong to. The names are shown
+/// by debuggers such as RenderDoc and in validation messages.
+pub trait DebugName {
+    /// Names the Vulkan objects owned by self after `name`.
+    fn set_debug_name(&self, context: &VulkanContext, name: &str);
+}
+
+/// Loads the debug utils functions. VK_EXT_debug_utils is always enabled on the instance.
+pub fn create_loader(entry: &Entry, instance: &Instance) -> DebugUtils {
+    DebugUtils::new(entry, instance)
+}
 
+/// Creates a messenger forwarding validation messages to the log
+pub fn create_messenger(
+    debug_utils: &DebugUtils,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
+) -> Result<DebugUtilsMessengerEXT, Error> {
     let create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
         .message_severity(
             vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
@@ -27,12 +38,47 @@ pub fn create(
         )
         .pfn_user_callback(Some(debug_callback));
 
-    let messenger = unsafe { debug_utils.create_debug_utils_messenger(&create_info, None)? };
-    return Ok((debug_utils, messenger));
+    let messenger =
+        unsafe { debug_utils.create_debug_utils_messenger(&create_info, allocation_callbacks)? };
+    Ok(messenger)
+}
+
+pub fn destroy(
+    debug_utils: &DebugUtils,
+    messenger: DebugUtilsMessengerEXT,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
+) {
+    unsafe { debug_utils.destroy_debug_utils_messenger(messenger, allocation_callbacks) };
+}
+
+/// Names a Vulkan object. Names containing nul bytes are truncated.
+pub fn set_object_name<H: vk::Handle>(
+    debug_utils: &DebugUtils,
+    device: vk::Device,
+    handle: H,
+    name: &str,
+) {
+    let name = to_cstring(name);
+    let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+        .object_type(H::TYPE)
+        .object_handle(handle.as_raw())
+        .object_name(&name);
+
+    // Naming is a debugging aid and failures are not worth propagating
+    if let Err(e) = unsafe { debug_utils.debug_utils_set_object_name(device, &name_info) } {
+        warn!("Failed to name {:?} {:?}: {}", H::TYPE, name, e);
+    }
+}
+
+/// Returns a label for command buffer regions
+pub fn label(name: &CStr) -> vk::DebugUtilsLabelEXTBuilder<'_> {
+    vk::DebugUtilsLabelEXT::builder().label_name(name)
 }
 
-pub fn destroy(debug_utils: &DebugUtils, messenger: DebugUtilsMessengerEXT) {
-    unsafe { debug_utils.destroy_debug_utils_messenger(messenger, None) };
+/// Converts `name` to a C string, truncated at the first nul byte
+pub fn to_cstring(name: &str) -> CString {
+    let name = name.split('\0').next().unwrap_or_default();
+    CString::new(name).unwrap()
 }
 
 // Debug callback
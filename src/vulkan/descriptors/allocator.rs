@@ -4,11 +4,11 @@ use ash::Device;
 use smallvec::SmallVec;
 use std::{collections::HashMap, iter::repeat, rc::Rc};
 
-use crate::vulkan::Error;
+use crate::vulkan::{validation, Error};
 
 pub use vk::DescriptorSetLayout;
 
-use super::DescriptorLayoutInfo;
+use super::{DescriptorLayoutInfo, DescriptorSetKey};
 
 struct Pool {
     pool: vk::DescriptorPool,
@@ -44,16 +44,21 @@ pub struct DescriptorAllocator {
     device: Rc<Device>,
     sub_allocators: HashMap<DescriptorSetLayout, DescriptorLayoutAllocator>,
     set_count: u32,
+    /// Sets built by `DescriptorBuilder::build_cached`, shared by all users of identical
+    /// resources
+    cached_sets: HashMap<DescriptorSetKey, vk::DescriptorSet>,
 }
 
 impl DescriptorAllocator {
-    /// Creates a new descriptor allocator. `set_count` represents the preferred descriptor count
-    /// per pool. It is possible to allocate more than `set_count` at a time.
+    /// Creates a new descriptor allocator. `set_count` is the number of sets of the first pool of
+    /// each layout. Later pools are sized from the number of sets used. It is possible to
+    /// allocate more than `set_count` at a time.
     pub fn new(device: Rc<Device>, set_count: u32) -> Self {
         Self {
             device,
             sub_allocators: HashMap::new(),
             set_count,
+            cached_sets: HashMap::new(),
         }
     }
 
@@ -77,8 +82,39 @@ impl DescriptorAllocator {
         sub_allocator.allocate(set_count)
     }
 
-    /// Resets all allocated pools and descriptor sets.
+    /// Returns the cached descriptor set written with the resources of `key`, if any.
+    pub fn cached(&self, key: &DescriptorSetKey) -> Option<vk::DescriptorSet> {
+        self.cached_sets.get(key).copied()
+    }
+
+    /// Caches `set` to be shared by later sets built from the same layout and resources.
+    pub fn insert_cached(&mut self, key: DescriptorSetKey, set: vk::DescriptorSet) {
+        self.cached_sets.insert(key, set);
+    }
+
+    /// Removes `set` from the cache. Required before any resource written to the set is
+    /// destroyed, since a new resource could otherwise be created with the same handle and
+    /// match the stale set. The set itself remains valid until the pools are reset.
+    pub fn invalidate(&mut self, set: vk::DescriptorSet) {
+        self.cached_sets.retain(|_, cached| *cached != set);
+    }
+
+    /// Removes all sets from the cache. See `invalidate`.
+    pub fn invalidate_all(&mut self) {
+        self.cached_sets.clear();
+    }
+
+    /// Returns the number of distinct cached descriptor sets
+    pub fn cached_count(&self) -> usize {
+        self.cached_sets.len()
+    }
+
+    /// Resets all allocated pools and descriptor sets. Static sets built with
+    /// `DescriptorBuilder::build_cached` are shared and should rather outlive the pools than
+    /// be rebuilt each frame.
     pub fn reset(&mut self) -> Result<(), Error> {
+        self.cached_sets.clear();
+
         self.sub_allocators
             .iter_mut()
             .map(|(_, sub_allocator)| sub_allocator.reset())
@@ -89,6 +125,7 @@ impl DescriptorAllocator {
 
     // Clears and destroys all allocated pools.
     pub fn clear(&mut self) {
+        self.cached_sets.clear();
         self.sub_allocators.clear();
     }
 
@@ -105,54 +142,85 @@ impl DescriptorAllocator {
             .get(&layout)
             .map(DescriptorLayoutAllocator::full_pool_count)
     }
-}
 
-/// Creates a new descriptor allocator. Stores several pools contains `set_count` available
-/// descriptors each. `sizes` describes the relative count for each descriptor size. Allocates new
-/// pools when no free are available
+    /// Returns the number of descriptors of each type used by the most sets allocated between
+    /// two resets, summed over all layouts.
+    pub fn peak_usage(&self) -> Vec<vk::DescriptorPoolSize> {
+        let mut usage: Vec<vk::DescriptorPoolSize> = Vec::new();
+
+        for size in self
+            .sub_allocators
+            .values()
+            .flat_map(|sub| sub.peak_usage())
+        {
+            match usage.iter_mut().find(|usage| usage.ty == size.ty) {
+                Some(usage) => usage.descriptor_count += size.descriptor_count,
+                None => usage.push(size),
+            }
+        }
+
+        usage
+    }
+}
 
-/// Manages allocation for a single descriptor set layout
+/// Manages allocation for a single descriptor set layout.
+/// Pools are sized from the descriptors of the layout, and grow with the number of sets
+/// allocated between resets.
 struct DescriptorLayoutAllocator {
     device: Rc<Device>,
     layout: DescriptorSetLayout,
+    /// The number of sets of the first pool
     set_count: u32,
     /// A list of pools with atleast 1 descriptor remaining.
     pools: Vec<Pool>,
     /// A list of completely full pools.
     full_pools: Vec<Pool>,
+    /// The descriptors of each type required by a single set
     sizes: Vec<vk::DescriptorPoolSize>,
+    /// The number of sets allocated since the last reset
+    in_use: u32,
+    /// The most sets allocated between two resets
+    peak: u32,
 }
 
 impl DescriptorLayoutAllocator {
-    /// Creates a new descriptor allocator. Stores several pools contains `set_count` available
-    /// descriptors each. `sizes` describes the relative
+    /// Creates a new descriptor allocator whose first pool fits `set_count` sets of `layout`.
     pub fn new(
         device: Rc<Device>,
         layout: DescriptorSetLayout,
         layout_info: &DescriptorLayoutInfo,
         set_count: u32,
     ) -> Self {
-        let sizes = layout_info
-            .bindings()
-            .iter()
-            .map(|binding| vk::DescriptorPoolSize {
-                ty: binding.descriptor_type,
-                descriptor_count: set_count,
-            })
-            .collect();
+        let mut sizes: Vec<vk::DescriptorPoolSize> = Vec::new();
+
+        // Bindings of the same type share a pool size, and arrays need a descriptor per element
+        for binding in layout_info.bindings() {
+            match sizes
+                .iter_mut()
+                .find(|size| size.ty == binding.descriptor_type)
+            {
+                Some(size) => size.descriptor_count += binding.descriptor_count,
+                None => sizes.push(vk::DescriptorPoolSize {
+                    ty: binding.descriptor_type,
+                    descriptor_count: binding.descriptor_count,
+                }),
+            }
+        }
 
         Self {
             device,
             layout,
-            set_count,
+            set_count: set_count.max(1),
             pools: Vec::new(),
             full_pools: Vec::new(),
             sizes,
+            in_use: 0,
+            peak: 0,
         }
     }
 
-    /// Allocates a descriptor set for each element in `layouts`. Will allocate a new pool if no free pools
-    /// are available. Correctly handles when descriptor set count is more than preferred `set_count`.
+    /// Allocates descriptor sets. Will allocate a new pool if no free pools are available.
+    /// Correctly handles when descriptor set count is more than preferred `set_count`.
     pub fn allocate(&mut self, set_count: u32) -> Result<Vec<vk::DescriptorSet>, Error> {
         let layouts = repeat(self.layout)
             .take(set_count as usize)
@@ -165,7 +233,8 @@ impl DescriptorLayoutAllocator {
             ..Default::default()
         };
 
-        // Find a free pool or allocate a new one
+        // Find a free pool or allocate a new one. New pools are at least as large as all
+        // previous pools combined, so the number of pools grows logarithmically.
         let (pool_idx, pool) = match self
             .pools
             .iter_mut()
@@ -173,12 +242,12 @@ impl DescriptorLayoutAllocator {
             .find(|(_, pool)| pool.allocated + set_count <= pool.set_count)
         {
             Some(pool) => pool,
-            None => self.allocate_pool(set_count.max(self.set_count))?,
+            None => {
+                let pool_set_count = set_count.max(self.set_count).max(self.capacity());
+                self.allocate_pool(pool_set_count)?
+            }
         };
 
-        // No free pool found. Allocate a new pool. Override set count if requested descriptor
-        // count is more.
-        // let pool = self.allocate_pool(self.set_count.max(alloc_info.descriptor_set_count))?;
         alloc_info.descriptor_pool = pool.pool;
         pool.allocated += set_count;
 
@@ -188,14 +257,36 @@ impl DescriptorLayoutAllocator {
         }
 
         let sets = unsafe { self.device.allocate_descriptor_sets(&alloc_info)? };
+        validation::register_sets(self.layout, &sets);
+
+        self.in_use += set_count;
+        self.peak = self.peak.max(self.in_use);
 
         Ok(sets)
     }
 
-    /// Resets all allocated pools and descriptor sets.
+    /// Resets all allocated pools and descriptor sets. Pools which were outgrown, or are much
+    /// larger than the most sets ever used, are replaced by a single pool fitting the most sets
+    /// used between two resets.
     pub fn reset(&mut self) -> Result<(), Error> {
         // Move all full pools into pools
         self.pools.extend(self.full_pools.drain(..));
+        self.in_use = 0;
+
+        let target = self.peak.max(self.set_count);
+        let capacity = self.capacity();
+
+        if self.pools.len() > 1 || capacity > target * 4 {
+            log::debug!(
+                "Resizing descriptor pools of {} sets to a single pool of {} sets",
+                capacity,
+                target
+            );
+
+            self.clear();
+            self.allocate_pool(target)?;
+            return Ok(());
+        }
 
         for pool in self.pools.iter_mut().filter(|pool| pool.allocated != 0) {
             pool.allocated = 0;
@@ -217,12 +308,30 @@ impl DescriptorLayoutAllocator {
 
     /// Allocates a new pool with `set_count` descriptors. Ignores `self.set_count`
     fn allocate_pool(&mut self, set_count: u32) -> Result<(usize, &mut Pool), Error> {
-        let pool = Pool::new(&self.device, set_count, &self.sizes)?;
+        let sizes = self
+            .sizes
+            .iter()
+            .map(|size| vk::DescriptorPoolSize {
+                ty: size.ty,
+                descriptor_count: size.descriptor_count * set_count,
+            })
+            .collect::<SmallVec<[_; 8]>>();
+
+        let pool = Pool::new(&self.device, set_count, &sizes)?;
         self.pools.push(pool);
         let idx = self.pools.len() - 1;
         Ok((idx, &mut self.pools[idx]))
     }
 
+    /// Returns the number of sets fitting in all pools
+    fn capacity(&self) -> u32 {
+        self.pools
+            .iter()
+            .chain(&self.full_pools)
+            .map(|pool| pool.set_count)
+            .sum()
+    }
+
     // Diagnostics
     /// Returns the total number of allocated pools
     pub fn total_pool_count(&self) -> usize {
@@ -232,6 +341,14 @@ impl DescriptorLayoutAllocator {
     pub fn full_pool_count(&self) -> usize {
         self.full_pools.len()
     }
+
+    /// Returns the descriptors of each type of the most sets allocated between two resets
+    pub fn peak_usage(&self) -> impl Iterator<Item = vk::DescriptorPoolSize> + '_ {
+        self.sizes.iter().map(move |size| vk::DescriptorPoolSize {
+            ty: size.ty,
+            descriptor_count: size.descriptor_count * self.peak,
+        })
+    }
 }
 
 impl Drop for DescriptorLayoutAllocator {
@@ -7,14 +7,40 @@ use ash::Device;
 
 use super::{DescriptorAllocator, DescriptorLayoutCache, DescriptorSetBinding};
 use super::{DescriptorLayoutInfo, MAX_BINDINGS};
+use crate::vulkan::ray_tracing::AccelerationStructure;
 use vk::{DescriptorType, ShaderStageFlags};
 
+/// Identifies a descriptor set by its layout and the resources written to each binding. Used by
+/// the allocator to share sets built from identical resources.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DescriptorSetKey {
+    layout: vk::DescriptorSetLayout,
+    /// Sorted by binding
+    writes: ArrayVec<[DescriptorWriteKey; MAX_BINDINGS]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct DescriptorWriteKey {
+    binding: u32,
+    descriptor_type: DescriptorType,
+    buffer: vk::Buffer,
+    offset: vk::DeviceSize,
+    range: vk::DeviceSize,
+    sampler: vk::Sampler,
+    image_view: vk::ImageView,
+    image_layout: ImageLayout,
+    acceleration_structure: vk::AccelerationStructureKHR,
+}
+
 pub struct DescriptorBuilder {
     // `bindings` and `writes` are of the same size.
     bindings: ArrayVec<[DescriptorSetBinding; MAX_BINDINGS]>,
     writes: ArrayVec<[WriteDescriptorSet; MAX_BINDINGS]>,
     buffer_infos: [vk::DescriptorBufferInfo; MAX_BINDINGS],
     image_infos: [vk::DescriptorImageInfo; MAX_BINDINGS],
+    acceleration_structures: [vk::AccelerationStructureKHR; MAX_BINDINGS],
+    // Chained to the writes of acceleration structures
+    acceleration_structure_writes: [vk::WriteDescriptorSetAccelerationStructureKHR; MAX_BINDINGS],
     // Holds a map to where in the writes array each binding is, or MAX_BINDINGS
     used_bindings: [usize; MAX_BINDINGS],
     // If nothing is changed, the last layout aquired from cache
@@ -28,6 +54,8 @@ impl Default for DescriptorBuilder {
             writes: Default::default(),
             buffer_infos: Default::default(),
             image_infos: Default::default(),
+            acceleration_structures: Default::default(),
+            acceleration_structure_writes: Default::default(),
             used_bindings: [MAX_BINDINGS; MAX_BINDINGS],
             cached_layout: None,
         }
@@ -94,6 +122,44 @@ impl DescriptorBuilder {
         self
     }
 
+    /// Binds a dynamic uniform buffer. `range` is the size in bytes of each element visible to the
+    /// shader. The offset into the buffer is supplied at bind time through dynamic offsets.
+    pub fn bind_dynamic_uniform(
+        &mut self,
+        binding: u32,
+        stage: ShaderStageFlags,
+        uniform_buffer: &Buffer,
+        range: vk::DeviceSize,
+    ) -> &mut Self {
+        assert_eq!(uniform_buffer.ty(), BufferType::Uniform);
+        self.buffer_infos[binding as usize] = vk::DescriptorBufferInfo {
+            buffer: *uniform_buffer.as_ref(),
+            offset: 0,
+            range,
+        };
+
+        let write = WriteDescriptorSet {
+            dst_binding: binding,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            p_buffer_info: &self.buffer_infos[binding as usize],
+            ..Default::default()
+        };
+
+        let binding = DescriptorSetBinding {
+            binding,
+            descriptor_type: DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: 1,
+            stage_flags: stage,
+            p_immutable_samplers: std::ptr::null(),
+        };
+
+        self.add(binding, write);
+
+        self
+    }
+
     pub fn bind_storage_buffer(
         &mut self,
         binding: u32,
@@ -167,6 +233,119 @@ impl DescriptorBuilder {
         self
     }
 
+    /// Binds a storage image descriptor type for image load/store.
+    /// The texture is expected to be in GENERAL layout.
+    pub fn bind_storage_image(
+        &mut self,
+        binding: u32,
+        stage: ShaderStageFlags,
+        texture: &Texture,
+    ) -> &mut Self {
+        assert_eq!(texture.usage(), TextureUsage::Storage);
+
+        self.image_infos[binding as usize] = vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: texture.into(),
+            image_layout: ImageLayout::GENERAL,
+        };
+
+        let write = WriteDescriptorSet {
+            dst_binding: binding,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: DescriptorType::STORAGE_IMAGE,
+            p_image_info: &self.image_infos[binding as usize],
+            ..Default::default()
+        };
+
+        let binding = DescriptorSetBinding {
+            binding,
+            descriptor_type: DescriptorType::STORAGE_IMAGE,
+            descriptor_count: 1,
+            stage_flags: stage,
+            p_immutable_samplers: std::ptr::null(),
+        };
+
+        self.add(binding, write);
+
+        self
+    }
+
+    /// Binds a view of a storage image for image load/store, e.g; a single mip level. The
+    /// texture of the view is expected to be in GENERAL layout.
+    pub fn bind_storage_image_view(
+        &mut self,
+        binding: u32,
+        stage: ShaderStageFlags,
+        view: &TextureView,
+    ) -> &mut Self {
+        self.image_infos[binding as usize] = vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: view.image_view(),
+            image_layout: ImageLayout::GENERAL,
+        };
+
+        let write = WriteDescriptorSet {
+            dst_binding: binding,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: DescriptorType::STORAGE_IMAGE,
+            p_image_info: &self.image_infos[binding as usize],
+            ..Default::default()
+        };
+
+        let binding = DescriptorSetBinding {
+            binding,
+            descriptor_type: DescriptorType::STORAGE_IMAGE,
+            descriptor_count: 1,
+            stage_flags: stage,
+            p_immutable_samplers: std::ptr::null(),
+        };
+
+        self.add(binding, write);
+
+        self
+    }
+
+    /// Binds a top level acceleration structure for ray queries. Requires the ray query feature.
+    pub fn bind_acceleration_structure(
+        &mut self,
+        binding: u32,
+        stage: ShaderStageFlags,
+        acceleration_structure: &AccelerationStructure,
+    ) -> &mut Self {
+        self.acceleration_structures[binding as usize] = acceleration_structure.handle();
+
+        self.acceleration_structure_writes[binding as usize] =
+            vk::WriteDescriptorSetAccelerationStructureKHR {
+                acceleration_structure_count: 1,
+                p_acceleration_structures: &self.acceleration_structures[binding as usize],
+                ..Default::default()
+            };
+
+        let write = WriteDescriptorSet {
+            p_next: &self.acceleration_structure_writes[binding as usize] as *const _
+                as *const std::ffi::c_void,
+            dst_binding: binding,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            ..Default::default()
+        };
+
+        let binding = DescriptorSetBinding {
+            binding,
+            descriptor_type: DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            descriptor_count: 1,
+            stage_flags: stage,
+            p_immutable_samplers: std::ptr::null(),
+        };
+
+        self.add(binding, write);
+
+        self
+    }
+
     /// Allocates and writes descriptor set into `set`. Can be chained.
     pub fn build(
         &mut self,
@@ -192,6 +371,42 @@ impl DescriptorBuilder {
         Ok(self)
     }
 
+    /// Allocates and writes descriptor set into `set`, or reuses a set previously built by
+    /// `build_cached` with the same layout and resources. Cached sets are shared and must
+    /// therefore not be updated, and need to be invalidated in the allocator before any of their
+    /// resources are destroyed. Can be chained.
+    pub fn build_cached(
+        &mut self,
+        device: &Device,
+        cache: &mut DescriptorLayoutCache,
+        allocator: &mut DescriptorAllocator,
+        set: &mut vk::DescriptorSet,
+    ) -> Result<&mut Self, Error> {
+        let mut layout = Default::default();
+        self.layout(cache, &mut layout)?;
+
+        let key = self.key(layout);
+
+        if let Some(cached) = allocator.cached(&key) {
+            *set = cached;
+            return Ok(self);
+        }
+
+        self.build(device, cache, allocator, set)?;
+        allocator.insert_cached(key, *set);
+
+        Ok(self)
+    }
+
+    /// Writes the bound descriptors into an existing descriptor set with a compatible layout,
+    /// e.g; to replace a reallocated buffer. The set must not be in use by the device.
+    pub fn update(&mut self, device: &Device, set: vk::DescriptorSet) -> &mut Self {
+        self.writes.iter_mut().for_each(|write| write.dst_set = set);
+
+        unsafe { device.update_descriptor_sets(&self.writes, &[]) };
+        self
+    }
+
     /// Returns the descriptor set layout by writing to `layout`. Uses the provided cache to fetch
     /// or create the appropriate layout.
     pub fn layout(
@@ -209,6 +424,34 @@ impl DescriptorBuilder {
         }
     }
 
+    fn key(&self, layout: vk::DescriptorSetLayout) -> DescriptorSetKey {
+        let mut writes = self
+            .writes
+            .iter()
+            .map(|write| {
+                let buffer = &self.buffer_infos[write.dst_binding as usize];
+                let image = &self.image_infos[write.dst_binding as usize];
+
+                DescriptorWriteKey {
+                    binding: write.dst_binding,
+                    descriptor_type: write.descriptor_type,
+                    buffer: buffer.buffer,
+                    offset: buffer.offset,
+                    range: buffer.range,
+                    sampler: image.sampler,
+                    image_view: image.image_view,
+                    image_layout: image.image_layout,
+                    acceleration_structure: self.acceleration_structures
+                        [write.dst_binding as usize],
+                }
+            })
+            .collect::<ArrayVec<[_; MAX_BINDINGS]>>();
+
+        writes.sort_by_key(|write| write.binding);
+
+        DescriptorSetKey { layout, writes }
+    }
+
     fn recache_layout(&mut self, cache: &mut DescriptorLayoutCache) -> Result<(), Error> {
         let mut info = DescriptorLayoutInfo::new(&self.bindings);
         let cached_layout = cache.get(&mut info)?;
@@ -1,3 +1,4 @@
+use std::fmt::{self, Write};
 use std::{collections::HashMap, rc::Rc};
 
 use arrayvec::ArrayVec;
@@ -6,7 +7,7 @@ use ash::vk;
 use ash::Device;
 use std::hash::{Hash, Hasher};
 
-use crate::vulkan::Error;
+use crate::vulkan::{validation, Error};
 
 use super::DescriptorSetBinding;
 use super::MAX_BINDINGS;
@@ -33,8 +34,35 @@ impl DescriptorLayoutInfo {
         &self.bindings
     }
 
-    pub fn add(&mut self, binding: DescriptorSetBinding) {
-        self.bindings.push(binding);
+    /// Adds a binding to the layout. The stage flags are merged if the binding was already added
+    /// by another stage, in which case the descriptor type and count need to match.
+    pub fn add(&mut self, binding: DescriptorSetBinding) -> Result<(), Error> {
+        let existing = self
+            .bindings
+            .iter_mut()
+            .find(|existing| existing.binding == binding.binding);
+
+        match existing {
+            Some(existing)
+                if existing.descriptor_type != binding.descriptor_type
+                    || existing.descriptor_count != binding.descriptor_count =>
+            {
+                Err(Error::BindingConflict {
+                    binding: binding.binding,
+                    existing: existing.descriptor_type,
+                    found: binding.descriptor_type,
+                })
+            }
+            Some(existing) => {
+                existing.stage_flags |= binding.stage_flags;
+                Ok(())
+            }
+            None => {
+                self.bindings.push(binding);
+                self.sorted = false;
+                Ok(())
+            }
+        }
     }
 
     /// Ensures the bindings are sorted
@@ -66,10 +94,14 @@ impl Hash for DescriptorLayoutInfo {
 
 impl PartialEq for DescriptorLayoutInfo {
     fn eq(&self, other: &Self) -> bool {
+        if self.bindings.len() != other.bindings.len() {
+            return false;
+        }
+
         for (a, b) in self.bindings.iter().zip(&other.bindings) {
             if a.binding != b.binding
                 || a.descriptor_type != b.descriptor_type
-                || b.descriptor_count != b.descriptor_count
+                || a.descriptor_count != b.descriptor_count
                 || a.stage_flags != b.stage_flags
             {
                 return false;
@@ -82,9 +114,43 @@ impl PartialEq for DescriptorLayoutInfo {
 
 impl Eq for DescriptorLayoutInfo {}
 
+impl fmt::Display for DescriptorLayoutInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, binding) in self.bindings.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+
+            write!(
+                f,
+                "{}: {:?}[{}] {:?}",
+                binding.binding,
+                binding.descriptor_type,
+                binding.descriptor_count,
+                binding.stage_flags
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Lookup statistics of a `DescriptorLayoutCache`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptorLayoutStats {
+    /// The number of distinct layouts created
+    pub layouts: usize,
+    /// The number of lookups returning an existing layout
+    pub hits: usize,
+    /// The number of lookups creating a new layout
+    pub misses: usize,
+}
+
 pub struct DescriptorLayoutCache {
     device: Rc<Device>,
     layouts: HashMap<DescriptorLayoutInfo, DescriptorSetLayout>,
+    hits: usize,
+    misses: usize,
 }
 
 impl DescriptorLayoutCache {
@@ -92,6 +158,8 @@ impl DescriptorLayoutCache {
         Self {
             device,
             layouts: HashMap::new(),
+            hits: 0,
+            misses: 0,
         }
     }
 
@@ -101,8 +169,10 @@ impl DescriptorLayoutCache {
         info.ensure_sorted();
 
         if let Some(layout) = self.layouts.get(&info) {
+            self.hits += 1;
             return Ok(*layout);
         } else {
+            self.misses += 1;
             let info = info.clone();
             // Create layout
             let layout = create(&self.device, &info)?;
@@ -110,6 +180,31 @@ impl DescriptorLayoutCache {
         }
     }
 
+    /// Returns the number of cached layouts and the hits and misses of all lookups so far. Many
+    /// layouts compared to hits indicate effects declaring near identical sets, e.g; with
+    /// differing stage flags.
+    pub fn stats(&self) -> DescriptorLayoutStats {
+        DescriptorLayoutStats {
+            layouts: self.layouts.len(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+
+    /// Returns a description of each cached layout and its bindings, one per line.
+    pub fn dump(&self) -> String {
+        let mut layouts = self.layouts.iter().collect::<Vec<_>>();
+        layouts.sort_by_key(|(_, layout)| *layout);
+
+        let mut dump = String::new();
+        for (info, layout) in layouts {
+            // Writing to a string does not fail
+            let _ = writeln!(dump, "{:?}: {}", layout, info);
+        }
+
+        dump
+    }
+
     /// Clears and destroys all cached layouts. This is often not needed as there's no limit to
     /// allocating descriptors from the same layout.
     pub fn clear(&mut self) {
@@ -133,6 +228,7 @@ pub fn create(device: &Device, info: &DescriptorLayoutInfo) -> Result<Descriptor
     };
 
     let layout = unsafe { device.create_descriptor_set_layout(&&create_info, None)? };
+    validation::register_set_layout(layout, &info.bindings);
     Ok(layout)
 }
 
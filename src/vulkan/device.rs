@@ -1,10 +1,21 @@
-use super::{swapchain, Error};
+use super::dynamic_rendering::{
+    PhysicalDeviceDynamicRenderingFeaturesKHR, DYNAMIC_RENDERING_EXTENSIONS,
+};
+use super::memory::{self, MEMORY_BUDGET_EXTENSION};
+use super::mesh_shader::{
+    self, PhysicalDeviceMeshShaderFeaturesEXT, PhysicalDeviceMeshShaderPropertiesEXT,
+    MESH_SHADER_EXTENSION,
+};
+use super::ray_tracing::{self, RAY_QUERY_EXTENSIONS};
+use super::shading_rate::{self, FRAGMENT_SHADING_RATE_EXTENSION};
+use super::swapchain::FULL_SCREEN_EXCLUSIVE_EXTENSION;
+use super::{instance, swapchain, ContextInfo, Error, Extent};
 use ash::{
     extensions::khr::Surface,
     vk::{self, SurfaceKHR},
 };
-use ash::{version::DeviceV1_0, version::InstanceV1_0};
-use ash::{Device, Instance};
+use ash::{version::DeviceV1_0, version::EntryV1_0, version::InstanceV1_0, version::InstanceV1_1};
+use ash::{Device, Entry, Instance};
 use std::{
     collections::HashSet,
     ffi::{CStr, CString},
@@ -15,6 +26,7 @@ pub struct QueueFamilies {
     graphics: Option<u32>,
     present: Option<u32>,
     transfer: Option<u32>,
+    compute: Option<u32>,
 }
 
 impl QueueFamilies {
@@ -30,6 +42,7 @@ impl QueueFamilies {
             graphics: None,
             present: None,
             transfer: None,
+            compute: None,
         };
 
         for (i, family) in family_properties.iter().enumerate() {
@@ -43,9 +56,21 @@ impl QueueFamilies {
                 queue_families.present = Some(i as u32);
             }
 
-            if family.queue_flags.contains(vk::QueueFlags::TRANSFER) {
+            // Prefer a dedicated transfer family without graphics capabilities
+            if family.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                && (queue_families.transfer.is_none()
+                    || !family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            {
                 queue_families.transfer = Some(i as u32);
             }
+
+            // Prefer a dedicated compute family which runs asynchronously to graphics
+            if family.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                && (queue_families.compute.is_none()
+                    || !family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            {
+                queue_families.compute = Some(i as u32);
+            }
         }
 
         Ok(queue_families)
@@ -63,6 +88,10 @@ impl QueueFamilies {
         return self.transfer;
     }
 
+    pub fn compute(&self) -> Option<u32> {
+        self.compute
+    }
+
     pub fn has_graphics(&self) -> bool {
         return self.graphics.is_some();
     }
@@ -74,12 +103,167 @@ impl QueueFamilies {
     pub fn has_transfer(&self) -> bool {
         return self.transfer.is_some();
     }
+
+    /// Returns true if the transfer family differs from the graphics family.
+    pub fn has_dedicated_transfer(&self) -> bool {
+        self.transfer.is_some() && self.transfer != self.graphics
+    }
+
+    /// Returns true if the compute family differs from the graphics family.
+    pub fn has_dedicated_compute(&self) -> bool {
+        self.compute.is_some() && self.compute != self.graphics
+    }
 }
 
 type Score = usize;
 
 const DEVICE_EXTENSIONS: &[&str] = &["VK_KHR_swapchain", "VK_KHR_shader_draw_parameters"];
 
+/// Multiview is a core feature from this version on
+const VULKAN_1_1: u32 = vk::make_version(1, 1, 0);
+
+/// Descriptor indexing and timeline semaphores are core features from this version on
+const VULKAN_1_2: u32 = vk::make_version(1, 2, 0);
+
+/// The device extensions required for the descriptor indexing feature before Vulkan 1.2
+const DESCRIPTOR_INDEXING_EXTENSIONS: &[&str] =
+    &["VK_EXT_descriptor_indexing", "VK_KHR_maintenance3"];
+
+/// Specifies optional device features. Each requested feature is enabled if supported by the
+/// device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceFeatures {
+    pub sampler_anisotropy: bool,
+    /// Allows line and point polygon modes
+    pub fill_mode_non_solid: bool,
+    /// Allows line widths other than 1.0
+    pub wide_lines: bool,
+    /// Allows point sizes other than 1.0
+    pub large_points: bool,
+    /// Allows more than one draw per indirect draw command
+    pub multi_draw_indirect: bool,
+    /// Allows indirect draws starting at another instance than 0
+    pub draw_indirect_first_instance: bool,
+    /// Allows pipelines with tessellation control and evaluation shaders
+    pub tessellation_shader: bool,
+    /// Allows pipelines with geometry shaders
+    pub geometry_shader: bool,
+    /// Non uniform indexing, runtime sized and partially bound descriptor arrays. Uses
+    /// `VK_EXT_descriptor_indexing` before Vulkan 1.2
+    pub descriptor_indexing: bool,
+    /// Semaphores with a monotonically increasing counter. Requires Vulkan 1.2
+    pub timeline_semaphores: bool,
+    /// Experimental task and mesh shader pipelines through `VK_EXT_mesh_shader`. Requires
+    /// Vulkan 1.2 and is not requested by default.
+    pub mesh_shader: bool,
+    /// Acceleration structures and ray queries in shaders through `VK_KHR_ray_query`. Requires
+    /// Vulkan 1.2 and is not requested by default.
+    pub ray_query: bool,
+    /// Variable rate shading per pipeline and through shading rate attachments with
+    /// `VK_KHR_fragment_shading_rate`. Requires Vulkan 1.2 and is not requested by default.
+    pub fragment_shading_rate: bool,
+    /// Rendering to several layers of the attachments at once in a single pass, with the layer
+    /// available to shaders as the view index. Requires Vulkan 1.1
+    pub multiview: bool,
+}
+
+impl Default for DeviceFeatures {
+    fn default() -> Self {
+        Self {
+            sampler_anisotropy: true,
+            fill_mode_non_solid: true,
+            wide_lines: true,
+            large_points: true,
+            multi_draw_indirect: true,
+            draw_indirect_first_instance: true,
+            tessellation_shader: true,
+            geometry_shader: true,
+            descriptor_indexing: true,
+            timeline_semaphores: true,
+            mesh_shader: false,
+            ray_query: false,
+            fragment_shading_rate: false,
+            multiview: true,
+        }
+    }
+}
+
+impl DeviceFeatures {
+    /// Returns the features present in both self and `other`
+    pub fn intersect(&self, other: &Self) -> Self {
+        Self {
+            sampler_anisotropy: self.sampler_anisotropy && other.sampler_anisotropy,
+            fill_mode_non_solid: self.fill_mode_non_solid && other.fill_mode_non_solid,
+            wide_lines: self.wide_lines && other.wide_lines,
+            large_points: self.large_points && other.large_points,
+            multi_draw_indirect: self.multi_draw_indirect && other.multi_draw_indirect,
+            draw_indirect_first_instance: self.draw_indirect_first_instance
+                && other.draw_indirect_first_instance,
+            tessellation_shader: self.tessellation_shader && other.tessellation_shader,
+            geometry_shader: self.geometry_shader && other.geometry_shader,
+            descriptor_indexing: self.descriptor_indexing && other.descriptor_indexing,
+            timeline_semaphores: self.timeline_semaphores && other.timeline_semaphores,
+            mesh_shader: self.mesh_shader && other.mesh_shader,
+            ray_query: self.ray_query && other.ray_query,
+            fragment_shading_rate: self.fragment_shading_rate && other.fragment_shading_rate,
+            multiview: self.multiview && other.multiview,
+        }
+    }
+}
+
+/// Reports the enabled features and relevant capabilities of the logical device so that higher
+/// layers can choose code paths.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceCapabilities {
+    pub device_name: String,
+    /// The Vulkan version used with the device, the lower of the instance and device versions
+    /// without the patch version
+    pub api_version: u32,
+    /// The optional features which were both requested and supported
+    pub features: DeviceFeatures,
+    /// `VK_KHR_dynamic_rendering` is enabled
+    pub dynamic_rendering: bool,
+    /// The device has a transfer queue family separate from graphics
+    pub dedicated_transfer: bool,
+    /// The device has a compute queue family separate from graphics, which can overlap compute
+    /// work with rendering
+    pub dedicated_compute: bool,
+    pub max_sampler_anisotropy: f32,
+    /// Maximum absolute mip LOD bias of samplers
+    pub max_sampler_lod_bias: f32,
+    /// Maximum draw count of indirect draws. 1 if multi draw indirect is not enabled
+    pub max_draw_indirect_count: u32,
+    /// Range of supported line widths when `wide_lines` is enabled
+    pub line_width_range: [f32; 2],
+    /// Range of supported point sizes when `large_points` is enabled
+    pub point_size_range: [f32; 2],
+    /// Maximum number of control points of a tessellated patch. 0 if tessellation shaders are
+    /// not enabled
+    pub max_tessellation_patch_size: u32,
+    /// Maximum number of vertices and primitives output by a mesh shader workgroup. 0 if mesh
+    /// shaders are not enabled
+    pub max_mesh_output_vertices: u32,
+    pub max_mesh_output_primitives: u32,
+    /// Required alignment of the scratch buffer address of acceleration structure builds. 0 if
+    /// ray queries are not enabled
+    pub acceleration_structure_scratch_alignment: u32,
+    /// Largest fragment of variable rate shading. 1x1 if fragment shading rate is not enabled
+    pub max_fragment_size: Extent,
+    /// Range of the texel sizes of shading rate attachments. None if shading rate attachments
+    /// are not supported
+    pub shading_rate_texel_size_range: Option<[Extent; 2]>,
+    /// Maximum number of views rendered by a multiview pass. 0 if multiview is not enabled
+    pub max_multiview_view_count: u32,
+    /// Maximum number of descriptor sets bound to a pipeline at once. At least 4
+    pub max_bound_descriptor_sets: u32,
+    /// `VK_EXT_memory_budget` is enabled and heap budgets are reported by the driver
+    pub memory_budget: bool,
+    /// A large device local and host visible heap is available
+    pub resizable_bar: bool,
+    /// `VK_EXT_full_screen_exclusive` is enabled and swapchains can control exclusive fullscreen
+    pub full_screen_exclusive: bool,
+}
+
 /// Represents a physical device along with the queried properties, features, and queue families
 pub struct PhysicalDeviceInfo {
     pub physical_device: vk::PhysicalDevice,
@@ -89,6 +273,8 @@ pub struct PhysicalDeviceInfo {
     pub limits: vk::PhysicalDeviceLimits,
     pub features: vk::PhysicalDeviceFeatures,
     pub properties: vk::PhysicalDeviceProperties,
+    /// True if the device supports `VK_KHR_dynamic_rendering` and its dependencies
+    pub dynamic_rendering: bool,
 }
 
 // Rates physical device suitability
@@ -141,6 +327,14 @@ fn rate_physical_device(
 
     // Device is valid
 
+    let dynamic_rendering = get_missing_extensions(
+        instance,
+        physical_device,
+        &to_cstrings(DYNAMIC_RENDERING_EXTENSIONS),
+    )
+    .map(|missing| missing.is_empty())
+    .unwrap_or(false);
+
     let mut score: Score = 0;
 
     if properties.device_type == vk::PhysicalDeviceType::DISCRETE_GPU {
@@ -162,6 +356,7 @@ fn rate_physical_device(
         properties,
         limits: properties.limits,
         queue_families,
+        dynamic_rendering,
     })
 }
 
@@ -186,6 +381,14 @@ fn get_missing_extensions(
         .collect())
 }
 
+fn to_cstrings<S: AsRef<str>>(names: &[S]) -> Vec<CString> {
+    names
+        .iter()
+        .map(|s| CString::new(s.as_ref()))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+}
+
 // Picks an appropriate physical device
 fn pick_physical_device(
     instance: &Instance,
@@ -202,24 +405,392 @@ fn pick_physical_device(
         .ok_or(Error::UnsuitableDevice)
 }
 
+// Queries the descriptor indexing features of the device. Returns None if the device or
+// instance lacks the required extensions before Vulkan 1.2.
+fn query_descriptor_indexing(
+    entry: &Entry,
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    api_version: u32,
+) -> Option<vk::PhysicalDeviceDescriptorIndexingFeaturesEXT> {
+    let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeaturesEXT::default();
+
+    if api_version >= VULKAN_1_2 {
+        let mut features2 = vk::PhysicalDeviceFeatures2 {
+            p_next: &mut indexing_features as *mut _ as *mut std::ffi::c_void,
+            ..Default::default()
+        };
+
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+        return Some(indexing_features);
+    }
+
+    if !instance::optional_extension_enabled(entry, "VK_KHR_get_physical_device_properties2") {
+        return None;
+    }
+
+    let missing = get_missing_extensions(
+        instance,
+        physical_device,
+        &to_cstrings(DESCRIPTOR_INDEXING_EXTENSIONS),
+    )
+    .ok()?;
+
+    if !missing.is_empty() {
+        return None;
+    }
+
+    let properties2 = vk::KhrGetPhysicalDeviceProperties2Fn::load(|name| unsafe {
+        std::mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+    });
+
+    let mut features2 = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut indexing_features as *mut _ as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+
+    unsafe {
+        properties2.get_physical_device_features2_khr(physical_device, &mut features2);
+    }
+
+    Some(indexing_features)
+}
+
+// Queries the timeline semaphore features of the device. Returns None before Vulkan 1.2.
+fn query_timeline_semaphore(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    api_version: u32,
+) -> Option<vk::PhysicalDeviceTimelineSemaphoreFeatures> {
+    if api_version < VULKAN_1_2 {
+        return None;
+    }
+
+    let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+    let mut features2 = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut timeline_features as *mut _ as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+    Some(timeline_features)
+}
+
+// Queries the mesh shader features and properties of the device. Returns None before Vulkan
+// 1.2 or if the device lacks the extension.
+fn query_mesh_shader(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    api_version: u32,
+) -> Option<(
+    PhysicalDeviceMeshShaderFeaturesEXT,
+    PhysicalDeviceMeshShaderPropertiesEXT,
+)> {
+    if api_version < VULKAN_1_2 {
+        return None;
+    }
+
+    let missing = get_missing_extensions(
+        instance,
+        physical_device,
+        &to_cstrings(&[MESH_SHADER_EXTENSION]),
+    )
+    .ok()?;
+
+    if !missing.is_empty() {
+        return None;
+    }
+
+    Some(mesh_shader::query(instance, physical_device))
+}
+
+/// The features and properties involved in ray queries
+type RayQueryFeatures = (
+    vk::PhysicalDeviceAccelerationStructureFeaturesKHR,
+    vk::PhysicalDeviceRayQueryFeaturesKHR,
+    vk::PhysicalDeviceBufferDeviceAddressFeatures,
+    vk::PhysicalDeviceAccelerationStructurePropertiesKHR,
+);
+
+// Queries the acceleration structure and ray query features of the device. Returns None before
+// Vulkan 1.2 or if the device lacks any of the extensions.
+fn query_ray_query(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    api_version: u32,
+) -> Option<RayQueryFeatures> {
+    if api_version < VULKAN_1_2 {
+        return None;
+    }
+
+    let missing = get_missing_extensions(
+        instance,
+        physical_device,
+        &to_cstrings(RAY_QUERY_EXTENSIONS),
+    )
+    .ok()?;
+
+    if !missing.is_empty() {
+        return None;
+    }
+
+    Some(ray_tracing::query(instance, physical_device))
+}
+
+// Queries the fragment shading rate features and properties of the device. Returns None before
+// Vulkan 1.2 or if the device lacks the extension.
+fn query_fragment_shading_rate(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    api_version: u32,
+) -> Option<(
+    vk::PhysicalDeviceFragmentShadingRateFeaturesKHR,
+    vk::PhysicalDeviceFragmentShadingRatePropertiesKHR,
+)> {
+    if api_version < VULKAN_1_2 {
+        return None;
+    }
+
+    let missing = get_missing_extensions(
+        instance,
+        physical_device,
+        &to_cstrings(&[FRAGMENT_SHADING_RATE_EXTENSION]),
+    )
+    .ok()?;
+
+    if !missing.is_empty() {
+        return None;
+    }
+
+    Some(shading_rate::query(instance, physical_device))
+}
+
+// Queries the multiview features and properties of the device. Returns None before Vulkan 1.1.
+fn query_multiview(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    api_version: u32,
+) -> Option<(
+    vk::PhysicalDeviceMultiviewFeatures,
+    vk::PhysicalDeviceMultiviewProperties,
+)> {
+    if api_version < VULKAN_1_1 {
+        return None;
+    }
+
+    let mut multiview_features = vk::PhysicalDeviceMultiviewFeatures::default();
+    let mut features2 = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut multiview_features as *mut _ as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+
+    let mut multiview_properties = vk::PhysicalDeviceMultiviewProperties::default();
+    let mut properties2 = vk::PhysicalDeviceProperties2 {
+        p_next: &mut multiview_properties as *mut _ as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+
+    unsafe {
+        instance.get_physical_device_features2(physical_device, &mut features2);
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+    }
+
+    multiview_features.p_next = std::ptr::null_mut();
+    multiview_properties.p_next = std::ptr::null_mut();
+
+    Some((multiview_features, multiview_properties))
+}
+
+// Returns the optional features supported by the device
+fn supported_features(
+    features: &vk::PhysicalDeviceFeatures,
+    descriptor_indexing: Option<&vk::PhysicalDeviceDescriptorIndexingFeaturesEXT>,
+    timeline_semaphore: Option<&vk::PhysicalDeviceTimelineSemaphoreFeatures>,
+    mesh_shader: Option<&PhysicalDeviceMeshShaderFeaturesEXT>,
+    ray_query: Option<&RayQueryFeatures>,
+    fragment_shading_rate: Option<&vk::PhysicalDeviceFragmentShadingRateFeaturesKHR>,
+    multiview: Option<&vk::PhysicalDeviceMultiviewFeatures>,
+) -> DeviceFeatures {
+    DeviceFeatures {
+        sampler_anisotropy: features.sampler_anisotropy == vk::TRUE,
+        fill_mode_non_solid: features.fill_mode_non_solid == vk::TRUE,
+        wide_lines: features.wide_lines == vk::TRUE,
+        large_points: features.large_points == vk::TRUE,
+        multi_draw_indirect: features.multi_draw_indirect == vk::TRUE,
+        draw_indirect_first_instance: features.draw_indirect_first_instance == vk::TRUE,
+        tessellation_shader: features.tessellation_shader == vk::TRUE,
+        geometry_shader: features.geometry_shader == vk::TRUE,
+        descriptor_indexing: descriptor_indexing
+            .map(|indexing| {
+                indexing.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+                    && indexing.runtime_descriptor_array == vk::TRUE
+                    && indexing.descriptor_binding_partially_bound == vk::TRUE
+                    && indexing.descriptor_binding_variable_descriptor_count == vk::TRUE
+            })
+            .unwrap_or(false),
+        timeline_semaphores: timeline_semaphore
+            .map(|timeline| timeline.timeline_semaphore == vk::TRUE)
+            .unwrap_or(false),
+        mesh_shader: mesh_shader
+            .map(|mesh| mesh.mesh_shader == vk::TRUE && mesh.task_shader == vk::TRUE)
+            .unwrap_or(false),
+        ray_query: ray_query
+            .map(|(structure, query, address, _)| {
+                structure.acceleration_structure == vk::TRUE
+                    && query.ray_query == vk::TRUE
+                    && address.buffer_device_address == vk::TRUE
+            })
+            .unwrap_or(false),
+        fragment_shading_rate: fragment_shading_rate
+            .map(|rate| rate.pipeline_fragment_shading_rate == vk::TRUE)
+            .unwrap_or(false),
+        multiview: multiview
+            .map(|multiview| multiview.multiview == vk::TRUE)
+            .unwrap_or(false),
+    }
+}
+
 /// Creates a logical device by choosing the best appropriate physical device
+/// The dynamic rendering extensions are enabled if supported by the chosen device and instance.
+/// Each of the `requested` features is enabled if supported by the device and the lower of
+/// `api_version` of the instance and the version of the device.
 pub fn create(
+    entry: &Entry,
     instance: &Instance,
+    api_version: u32,
     surface_loader: &Surface,
     surface: SurfaceKHR,
-    layers: &[&str],
-) -> Result<(Rc<Device>, PhysicalDeviceInfo), Error> {
-    let extensions = DEVICE_EXTENSIONS
-        .iter()
-        .map(|s| CString::new(*s))
-        .collect::<Result<Vec<_>, _>>()
-        .unwrap();
+    info: &ContextInfo,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
+) -> Result<(Rc<Device>, PhysicalDeviceInfo, DeviceCapabilities), Error> {
+    let requested = &info.features;
+    let mut extensions = to_cstrings(DEVICE_EXTENSIONS);
+
+    for extension in to_cstrings(&info.device_extensions) {
+        if !extensions.contains(&extension) {
+            extensions.push(extension);
+        }
+    }
+
+    let mut pdevice_info = pick_physical_device(instance, surface_loader, surface, &extensions)?;
+
+    let api_version = api_version.min(instance::major_minor(pdevice_info.properties.api_version));
+
+    // The dynamic rendering extensions depend on `VK_KHR_multiview`, which requires
+    // `VK_KHR_get_physical_device_properties2` on a Vulkan 1.0 instance
+    pdevice_info.dynamic_rendering &= api_version >= VULKAN_1_1
+        || instance::optional_extension_enabled(entry, "VK_KHR_get_physical_device_properties2");
+
+    if pdevice_info.dynamic_rendering {
+        extensions.extend(to_cstrings(DYNAMIC_RENDERING_EXTENSIONS));
+    }
+
+    let descriptor_indexing = if requested.descriptor_indexing {
+        query_descriptor_indexing(entry, instance, pdevice_info.physical_device, api_version)
+    } else {
+        None
+    };
+
+    let timeline_semaphore = if requested.timeline_semaphores {
+        query_timeline_semaphore(instance, pdevice_info.physical_device, api_version)
+    } else {
+        None
+    };
+
+    let mesh_shader = if requested.mesh_shader {
+        query_mesh_shader(instance, pdevice_info.physical_device, api_version)
+    } else {
+        None
+    };
+
+    let ray_query = if requested.ray_query {
+        query_ray_query(instance, pdevice_info.physical_device, api_version)
+    } else {
+        None
+    };
+
+    let fragment_shading_rate = if requested.fragment_shading_rate {
+        query_fragment_shading_rate(instance, pdevice_info.physical_device, api_version)
+    } else {
+        None
+    };
+
+    let multiview = if requested.multiview {
+        query_multiview(instance, pdevice_info.physical_device, api_version)
+    } else {
+        None
+    };
+
+    let enabled = requested.intersect(&supported_features(
+        &pdevice_info.features,
+        descriptor_indexing.as_ref(),
+        timeline_semaphore.as_ref(),
+        mesh_shader.as_ref().map(|(features, _)| features),
+        ray_query.as_ref(),
+        fragment_shading_rate.as_ref().map(|(features, _)| features),
+        multiview.as_ref().map(|(features, _)| features),
+    ));
+
+    if enabled.mesh_shader {
+        extensions.extend(to_cstrings(&[MESH_SHADER_EXTENSION]));
+    }
+
+    if enabled.ray_query {
+        extensions.extend(to_cstrings(RAY_QUERY_EXTENSIONS));
+    }
+
+    if enabled.fragment_shading_rate {
+        extensions.extend(to_cstrings(&[FRAGMENT_SHADING_RATE_EXTENSION]));
+    }
+
+    if enabled.descriptor_indexing && api_version < VULKAN_1_2 {
+        extensions.extend(to_cstrings(DESCRIPTOR_INDEXING_EXTENSIONS));
+    }
+
+    // Budgets are queried through the physical device properties2 extension
+    let memory_budget =
+        instance::optional_extension_enabled(entry, "VK_KHR_get_physical_device_properties2")
+            && get_missing_extensions(
+                instance,
+                pdevice_info.physical_device,
+                &to_cstrings(&[MEMORY_BUDGET_EXTENSION]),
+            )
+            .map(|missing| missing.is_empty())
+            .unwrap_or(false);
+
+    if memory_budget {
+        extensions.extend(to_cstrings(&[MEMORY_BUDGET_EXTENSION]));
+    }
+
+    let full_screen_exclusive =
+        instance::optional_extension_enabled(entry, "VK_KHR_get_physical_device_properties2")
+            && instance::optional_extension_enabled(entry, "VK_KHR_get_surface_capabilities2")
+            && get_missing_extensions(
+                instance,
+                pdevice_info.physical_device,
+                &to_cstrings(&[FULL_SCREEN_EXCLUSIVE_EXTENSION]),
+            )
+            .map(|missing| missing.is_empty())
+            .unwrap_or(false);
+
+    if full_screen_exclusive {
+        extensions.extend(to_cstrings(&[FULL_SCREEN_EXCLUSIVE_EXTENSION]));
+    }
 
-    let pdevice_info = pick_physical_device(instance, surface_loader, surface, &extensions)?;
+    let memory_properties =
+        unsafe { instance.get_physical_device_memory_properties(pdevice_info.physical_device) };
 
     let mut unique_queue_families = HashSet::new();
     unique_queue_families.insert(pdevice_info.queue_families.graphics().unwrap());
     unique_queue_families.insert(pdevice_info.queue_families.present().unwrap());
+    if let Some(transfer) = pdevice_info.queue_families.transfer() {
+        unique_queue_families.insert(transfer);
+    }
+    if let Some(compute) = pdevice_info.queue_families.compute() {
+        unique_queue_families.insert(compute);
+    }
 
     let queue_create_infos: Vec<_> = unique_queue_families
         .iter()
@@ -232,7 +803,7 @@ pub fn create(
         .collect();
 
     // Get layers
-    let layers = layers
+    let layers = instance::get_layers()
         .iter()
         .map(|s| CString::new(*s))
         .collect::<Result<Vec<_>, _>>()
@@ -248,22 +819,195 @@ pub fn create(
         .map(|ext| ext.as_ptr() as *const i8)
         .collect::<Vec<_>>();
 
-    // TODO May not be present on all devices
     let enabled_features = vk::PhysicalDeviceFeatures {
-        sampler_anisotropy: pdevice_info.features.sampler_anisotropy,
-        fill_mode_non_solid: vk::TRUE,
+        sampler_anisotropy: enabled.sampler_anisotropy as vk::Bool32,
+        fill_mode_non_solid: enabled.fill_mode_non_solid as vk::Bool32,
+        wide_lines: enabled.wide_lines as vk::Bool32,
+        large_points: enabled.large_points as vk::Bool32,
+        multi_draw_indirect: enabled.multi_draw_indirect as vk::Bool32,
+        draw_indirect_first_instance: enabled.draw_indirect_first_instance as vk::Bool32,
+        tessellation_shader: enabled.tessellation_shader as vk::Bool32,
+        geometry_shader: enabled.geometry_shader as vk::Bool32,
+        ..Default::default()
+    };
+
+    let mut dynamic_rendering_features = PhysicalDeviceDynamicRenderingFeaturesKHR::default();
+
+    // Enable all supported descriptor indexing features
+    let mut descriptor_indexing_features = vk::PhysicalDeviceDescriptorIndexingFeaturesEXT {
+        p_next: std::ptr::null_mut(),
+        ..descriptor_indexing.unwrap_or_default()
+    };
+
+    let mut timeline_semaphore_features =
+        vk::PhysicalDeviceTimelineSemaphoreFeatures::builder().timeline_semaphore(true);
+
+    // Only the task and mesh stages themselves are enabled
+    let mut mesh_shader_features = PhysicalDeviceMeshShaderFeaturesEXT {
+        task_shader: vk::TRUE,
+        mesh_shader: vk::TRUE,
         ..Default::default()
     };
 
-    let create_info = vk::DeviceCreateInfo::builder()
+    // Acceleration structures are built on the device from buffers referenced by address
+    let mut acceleration_structure_features = vk::PhysicalDeviceAccelerationStructureFeaturesKHR {
+        acceleration_structure: vk::TRUE,
+        ..Default::default()
+    };
+
+    let mut ray_query_features = vk::PhysicalDeviceRayQueryFeaturesKHR {
+        ray_query: vk::TRUE,
+        ..Default::default()
+    };
+
+    let mut buffer_device_address_features =
+        vk::PhysicalDeviceBufferDeviceAddressFeatures::builder().buffer_device_address(true);
+
+    // Shading rate attachments are enabled along with the pipeline rate when supported. The
+    // primitive rate is never written by the shaders.
+    let mut fragment_shading_rate_features = vk::PhysicalDeviceFragmentShadingRateFeaturesKHR {
+        pipeline_fragment_shading_rate: vk::TRUE,
+        attachment_fragment_shading_rate: fragment_shading_rate
+            .map(|(features, _)| features.attachment_fragment_shading_rate)
+            .unwrap_or(vk::FALSE),
+        ..Default::default()
+    };
+
+    // Multiview is only used with the vertex stages of graphics pipelines
+    let mut multiview_features = vk::PhysicalDeviceMultiviewFeatures::builder().multiview(true);
+
+    let mut create_info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_create_infos)
         .enabled_extension_names(&extension_names_raw)
         .enabled_layer_names(&layer_names_raw)
         .enabled_features(&enabled_features);
 
-    let device =
-        unsafe { instance.create_device(pdevice_info.physical_device, &create_info, None)? };
-    Ok((Rc::new(device), pdevice_info))
+    if pdevice_info.dynamic_rendering {
+        create_info = create_info.push_next(&mut dynamic_rendering_features);
+    }
+
+    if enabled.descriptor_indexing {
+        create_info = create_info.push_next(&mut descriptor_indexing_features);
+    }
+
+    if enabled.timeline_semaphores {
+        create_info = create_info.push_next(&mut timeline_semaphore_features);
+    }
+
+    if enabled.mesh_shader {
+        create_info = create_info.push_next(&mut mesh_shader_features);
+    }
+
+    if enabled.ray_query {
+        create_info = create_info
+            .push_next(&mut acceleration_structure_features)
+            .push_next(&mut ray_query_features)
+            .push_next(&mut buffer_device_address_features);
+    }
+
+    if enabled.fragment_shading_rate {
+        create_info = create_info.push_next(&mut fragment_shading_rate_features);
+    }
+
+    if enabled.multiview {
+        create_info = create_info.push_next(&mut multiview_features);
+    }
+
+    let device = unsafe {
+        instance.create_device(
+            pdevice_info.physical_device,
+            &create_info,
+            allocation_callbacks,
+        )?
+    };
+
+    let limits = &pdevice_info.limits;
+
+    let (max_mesh_output_vertices, max_mesh_output_primitives) = match &mesh_shader {
+        Some((_, properties)) if enabled.mesh_shader => (
+            properties.max_mesh_output_vertices,
+            properties.max_mesh_output_primitives,
+        ),
+        _ => (0, 0),
+    };
+
+    let acceleration_structure_scratch_alignment = match &ray_query {
+        Some((_, _, _, properties)) if enabled.ray_query => {
+            properties.min_acceleration_structure_scratch_offset_alignment
+        }
+        _ => 0,
+    };
+
+    let (max_fragment_size, shading_rate_texel_size_range) = match &fragment_shading_rate {
+        Some((features, properties)) if enabled.fragment_shading_rate => (
+            properties.max_fragment_size.into(),
+            if features.attachment_fragment_shading_rate == vk::TRUE {
+                Some([
+                    properties
+                        .min_fragment_shading_rate_attachment_texel_size
+                        .into(),
+                    properties
+                        .max_fragment_shading_rate_attachment_texel_size
+                        .into(),
+                ])
+            } else {
+                None
+            },
+        ),
+        _ => (Extent::new(1, 1), None),
+    };
+
+    let max_multiview_view_count = match &multiview {
+        Some((_, properties)) if enabled.multiview => properties.max_multiview_view_count,
+        _ => 0,
+    };
+
+    let capabilities = DeviceCapabilities {
+        device_name: pdevice_info.name.clone(),
+        api_version,
+        features: enabled,
+        dynamic_rendering: pdevice_info.dynamic_rendering,
+        dedicated_transfer: pdevice_info.queue_families.has_dedicated_transfer(),
+        dedicated_compute: pdevice_info.queue_families.has_dedicated_compute(),
+        max_sampler_anisotropy: if enabled.sampler_anisotropy {
+            limits.max_sampler_anisotropy
+        } else {
+            1.0
+        },
+        max_sampler_lod_bias: limits.max_sampler_lod_bias,
+        max_draw_indirect_count: if enabled.multi_draw_indirect {
+            limits.max_draw_indirect_count
+        } else {
+            1
+        },
+        line_width_range: if enabled.wide_lines {
+            limits.line_width_range
+        } else {
+            [1.0, 1.0]
+        },
+        point_size_range: if enabled.large_points {
+            limits.point_size_range
+        } else {
+            [1.0, 1.0]
+        },
+        max_tessellation_patch_size: if enabled.tessellation_shader {
+            limits.max_tessellation_patch_size
+        } else {
+            0
+        },
+        max_mesh_output_vertices,
+        max_mesh_output_primitives,
+        acceleration_structure_scratch_alignment,
+        max_fragment_size,
+        shading_rate_texel_size_range,
+        max_multiview_view_count,
+        max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+        memory_budget,
+        resizable_bar: memory::has_resizable_bar(&memory_properties),
+        full_screen_exclusive,
+    };
+
+    Ok((Rc::new(device), pdevice_info, capabilities))
 }
 
 pub fn get_limits(
@@ -289,6 +1033,6 @@ pub fn get_queue(device: &Device, family_index: u32, index: u32) -> vk::Queue {
     unsafe { device.get_device_queue(family_index, index) }
 }
 
-pub fn destroy(device: &Device) {
-    unsafe { device.destroy_device(None) };
+pub fn destroy(device: &Device, allocation_callbacks: Option<&vk::AllocationCallbacks>) {
+    unsafe { device.destroy_device(allocation_callbacks) };
 }
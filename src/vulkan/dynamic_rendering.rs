@@ -0,0 +1,340 @@
+//! Support for rendering without renderpass and framebuffer objects through
+//! `VK_KHR_dynamic_rendering`. The extension is newer than the bundled vulkan headers, so the
+//! required structures are declared here and the commands are loaded manually.
+use std::ffi::{c_void, CStr};
+use std::{mem, ptr};
+
+use arrayvec::ArrayVec;
+use ash::version::InstanceV1_0;
+use ash::vk;
+use ash::{Device, Instance};
+
+use super::renderpass::MAX_ATTACHMENTS;
+use super::shading_rate::RenderingFragmentShadingRateAttachmentInfoKHR;
+use super::texture::has_stencil;
+use super::{Extent, LoadOp, StoreOp};
+
+/// The device extensions required for dynamic rendering on a Vulkan 1.0 instance.
+pub const DYNAMIC_RENDERING_EXTENSIONS: &[&str] = &[
+    "VK_KHR_dynamic_rendering",
+    "VK_KHR_depth_stencil_resolve",
+    "VK_KHR_create_renderpass2",
+    "VK_KHR_multiview",
+    "VK_KHR_maintenance2",
+];
+
+const STRUCTURE_TYPE_RENDERING_INFO_KHR: i32 = 1000044000;
+const STRUCTURE_TYPE_RENDERING_ATTACHMENT_INFO_KHR: i32 = 1000044001;
+const STRUCTURE_TYPE_PIPELINE_RENDERING_CREATE_INFO_KHR: i32 = 1000044002;
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES_KHR: i32 = 1000044003;
+const STRUCTURE_TYPE_COMMAND_BUFFER_INHERITANCE_RENDERING_INFO_KHR: i32 = 1000044004;
+
+/// `VK_RENDERING_CONTENTS_SECONDARY_COMMAND_BUFFERS_BIT_KHR`
+const RENDERING_CONTENTS_SECONDARY_COMMAND_BUFFERS: vk::Flags = 0x1;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct RenderingAttachmentInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub image_view: vk::ImageView,
+    pub image_layout: vk::ImageLayout,
+    pub resolve_mode: vk::ResolveModeFlags,
+    pub resolve_image_view: vk::ImageView,
+    pub resolve_image_layout: vk::ImageLayout,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: vk::ClearValue,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct RenderingInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub flags: vk::Flags,
+    pub render_area: vk::Rect2D,
+    pub layer_count: u32,
+    pub view_mask: u32,
+    pub color_attachment_count: u32,
+    pub p_color_attachments: *const RenderingAttachmentInfoKHR,
+    pub p_depth_attachment: *const RenderingAttachmentInfoKHR,
+    pub p_stencil_attachment: *const RenderingAttachmentInfoKHR,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PipelineRenderingCreateInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub view_mask: u32,
+    pub color_attachment_count: u32,
+    pub p_color_attachment_formats: *const vk::Format,
+    pub depth_attachment_format: vk::Format,
+    pub stencil_attachment_format: vk::Format,
+}
+
+unsafe impl vk::ExtendsGraphicsPipelineCreateInfo for PipelineRenderingCreateInfoKHR {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct CommandBufferInheritanceRenderingInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub flags: vk::Flags,
+    pub view_mask: u32,
+    pub color_attachment_count: u32,
+    pub p_color_attachment_formats: *const vk::Format,
+    pub depth_attachment_format: vk::Format,
+    pub stencil_attachment_format: vk::Format,
+    pub rasterization_samples: vk::SampleCountFlags,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PhysicalDeviceDynamicRenderingFeaturesKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    pub dynamic_rendering: vk::Bool32,
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceDynamicRenderingFeaturesKHR {}
+
+impl Default for PhysicalDeviceDynamicRenderingFeaturesKHR {
+    fn default() -> Self {
+        Self {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES_KHR,
+            ),
+            p_next: ptr::null_mut(),
+            dynamic_rendering: vk::TRUE,
+        }
+    }
+}
+
+type PfnCmdBeginRenderingKHR =
+    unsafe extern "system" fn(vk::CommandBuffer, *const RenderingInfoKHR);
+type PfnCmdEndRenderingKHR = unsafe extern "system" fn(vk::CommandBuffer);
+
+/// Specifies a single attachment rendered to with dynamic rendering.
+#[derive(Clone, Copy)]
+pub struct RenderingAttachment {
+    pub image_view: vk::ImageView,
+    /// The layout the image is in during rendering.
+    pub layout: vk::ImageLayout,
+    pub load: LoadOp,
+    pub store: StoreOp,
+    pub clear_value: vk::ClearValue,
+    /// Single sampled image view and layout to resolve multisampled contents into.
+    pub resolve: Option<(vk::ImageView, vk::ImageLayout)>,
+}
+
+impl Into<RenderingAttachmentInfoKHR> for &RenderingAttachment {
+    fn into(self) -> RenderingAttachmentInfoKHR {
+        let (resolve_mode, resolve_image_view, resolve_image_layout) = match self.resolve {
+            Some((view, layout)) => (vk::ResolveModeFlags::AVERAGE, view, layout),
+            None => (
+                vk::ResolveModeFlags::NONE,
+                vk::ImageView::null(),
+                vk::ImageLayout::UNDEFINED,
+            ),
+        };
+
+        RenderingAttachmentInfoKHR {
+            s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_RENDERING_ATTACHMENT_INFO_KHR),
+            p_next: ptr::null(),
+            image_view: self.image_view,
+            image_layout: self.layout,
+            resolve_mode,
+            resolve_image_view,
+            resolve_image_layout,
+            load_op: self.load,
+            store_op: self.store,
+            clear_value: self.clear_value,
+        }
+    }
+}
+
+/// An image read for the fragment shading rate of each `texel_size` block of pixels, see
+/// `shading_rate`. The image needs to be in `shading_rate::ATTACHMENT_LAYOUT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShadingRateAttachment {
+    pub image_view: vk::ImageView,
+    pub texel_size: Extent,
+}
+
+/// The attachments of a dynamic rendering
+#[derive(Default, Clone, Copy)]
+pub struct RenderingAttachments<'a> {
+    pub color: &'a [RenderingAttachment],
+    pub depth: Option<&'a RenderingAttachment>,
+    /// Usually the same as the depth attachment when using a combined depth stencil format
+    pub stencil: Option<&'a RenderingAttachment>,
+    /// Requires pipelines created with `RenderingFormats::shading_rate_attachment`
+    pub shading_rate: Option<ShadingRateAttachment>,
+    /// Renders to the layers of the attachments whose bits are set through multiview. 0
+    /// renders to the first layer only. Requires pipelines created with the same
+    /// `RenderingFormats::view_mask`.
+    pub view_mask: u32,
+}
+
+/// The attachment formats a dynamic rendering pipeline renders to.
+/// Replaces the renderpass for pipeline creation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderingFormats {
+    pub color_formats: ArrayVec<[vk::Format; MAX_ATTACHMENTS]>,
+    /// `vk::Format::UNDEFINED` if no depth attachment is used. The depth attachment is also
+    /// used as stencil attachment if the format has a stencil component.
+    pub depth_format: vk::Format,
+    /// The pipeline is used while rendering with a `ShadingRateAttachment`
+    pub shading_rate_attachment: bool,
+    /// The views rendered through multiview, see `RenderingAttachments::view_mask`
+    pub view_mask: u32,
+}
+
+impl RenderingFormats {
+    /// Returns the stencil attachment format, which is the depth format if it has a stencil
+    /// component.
+    pub fn stencil_format(&self) -> vk::Format {
+        if has_stencil(self.depth_format) {
+            self.depth_format
+        } else {
+            vk::Format::UNDEFINED
+        }
+    }
+
+    /// Returns a pipeline rendering create info pointing into self.
+    pub fn create_info(&self) -> PipelineRenderingCreateInfoKHR {
+        PipelineRenderingCreateInfoKHR {
+            s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_PIPELINE_RENDERING_CREATE_INFO_KHR),
+            p_next: ptr::null(),
+            view_mask: self.view_mask,
+            color_attachment_count: self.color_formats.len() as u32,
+            p_color_attachment_formats: self.color_formats.as_ptr(),
+            depth_attachment_format: self.depth_format,
+            stencil_attachment_format: self.stencil_format(),
+        }
+    }
+
+    /// Returns the inheritance info of secondary command buffers executed within dynamic
+    /// rendering to attachments of these formats. Points into self.
+    pub fn inheritance_info(
+        &self,
+        samples: vk::SampleCountFlags,
+    ) -> CommandBufferInheritanceRenderingInfoKHR {
+        CommandBufferInheritanceRenderingInfoKHR {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_COMMAND_BUFFER_INHERITANCE_RENDERING_INFO_KHR,
+            ),
+            p_next: ptr::null(),
+            flags: 0,
+            view_mask: self.view_mask,
+            color_attachment_count: self.color_formats.len() as u32,
+            p_color_attachment_formats: self.color_formats.as_ptr(),
+            depth_attachment_format: self.depth_format,
+            stencil_attachment_format: self.stencil_format(),
+            rasterization_samples: samples,
+        }
+    }
+}
+
+/// Loaded `VK_KHR_dynamic_rendering` device commands.
+pub struct DynamicRendering {
+    cmd_begin_rendering: PfnCmdBeginRenderingKHR,
+    cmd_end_rendering: PfnCmdEndRenderingKHR,
+}
+
+impl DynamicRendering {
+    /// Loads the dynamic rendering commands from device. The extension must have been enabled.
+    pub fn new(instance: &Instance, device: &Device) -> Option<Self> {
+        unsafe {
+            let begin = instance.get_device_proc_addr(
+                device.handle(),
+                CStr::from_bytes_with_nul_unchecked(b"vkCmdBeginRenderingKHR\0").as_ptr(),
+            )?;
+
+            let end = instance.get_device_proc_addr(
+                device.handle(),
+                CStr::from_bytes_with_nul_unchecked(b"vkCmdEndRenderingKHR\0").as_ptr(),
+            )?;
+
+            Some(Self {
+                cmd_begin_rendering: mem::transmute::<
+                    unsafe extern "system" fn(),
+                    PfnCmdBeginRenderingKHR,
+                >(begin),
+                cmd_end_rendering: mem::transmute::<
+                    unsafe extern "system" fn(),
+                    PfnCmdEndRenderingKHR,
+                >(end),
+            })
+        }
+    }
+
+    /// Begins dynamic rendering into the provided attachments. The rendering commands are
+    /// either recorded inline or executed from secondary command buffers depending on
+    /// `contents`.
+    pub fn begin_rendering(
+        &self,
+        commandbuffer: vk::CommandBuffer,
+        extent: Extent,
+        attachments: &RenderingAttachments,
+        contents: vk::SubpassContents,
+    ) {
+        let color_attachments = attachments
+            .color
+            .iter()
+            .map(|attachment| attachment.into())
+            .collect::<ArrayVec<[RenderingAttachmentInfoKHR; MAX_ATTACHMENTS]>>();
+
+        let depth_attachment: Option<RenderingAttachmentInfoKHR> =
+            attachments.depth.map(|attachment| attachment.into());
+
+        let stencil_attachment: Option<RenderingAttachmentInfoKHR> =
+            attachments.stencil.map(|attachment| attachment.into());
+
+        let shading_rate_attachment = attachments.shading_rate.map(|attachment| {
+            RenderingFragmentShadingRateAttachmentInfoKHR::new(
+                attachment.image_view,
+                attachment.texel_size.into(),
+            )
+        });
+
+        let rendering_info = RenderingInfoKHR {
+            s_type: vk::StructureType::from_raw(STRUCTURE_TYPE_RENDERING_INFO_KHR),
+            p_next: match &shading_rate_attachment {
+                Some(attachment) => attachment as *const _ as *const c_void,
+                None => ptr::null(),
+            },
+            flags: if contents == vk::SubpassContents::SECONDARY_COMMAND_BUFFERS {
+                RENDERING_CONTENTS_SECONDARY_COMMAND_BUFFERS
+            } else {
+                0
+            },
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: extent.into(),
+            },
+            // Ignored with multiview, which renders to the layers of the view mask instead
+            layer_count: 1,
+            view_mask: attachments.view_mask,
+            color_attachment_count: color_attachments.len() as u32,
+            p_color_attachments: color_attachments.as_ptr(),
+            p_depth_attachment: match &depth_attachment {
+                Some(attachment) => attachment,
+                None => ptr::null(),
+            },
+            p_stencil_attachment: match &stencil_attachment {
+                Some(attachment) => attachment,
+                None => ptr::null(),
+            },
+        };
+
+        unsafe { (self.cmd_begin_rendering)(commandbuffer, &rendering_info) };
+    }
+
+    /// Ends the current dynamic rendering.
+    pub fn end_rendering(&self, commandbuffer: vk::CommandBuffer) {
+        unsafe { (self.cmd_end_rendering)(commandbuffer) };
+    }
+}
@@ -3,6 +3,10 @@ use std::{ffi::CString, path::PathBuf};
 use ash::vk;
 use thiserror::Error;
 
+use super::texture::ColorSpace;
+use super::validation::AttachmentFormats;
+use super::{BufferType, BufferUsage};
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Failed to load vulkan library")]
@@ -31,12 +35,107 @@ pub enum Error {
         size: vk::DeviceSize,
         max_size: vk::DeviceSize,
     },
+    #[error("Buffers with {0:?} usage can not be read from the host")]
+    UnreadableBuffer(BufferUsage),
     #[error("Failed to load image file {0}")]
     ImageError(PathBuf),
+    #[error("Failed to load OpenEXR image {path:?}: {source}")]
+    ExrError {
+        path: PathBuf,
+        source: exr::error::Error,
+    },
+    #[error("Mip levels of {0:?} textures can not be generated in a compute shader")]
+    UnsupportedMipFormat(vk::Format),
+    #[error("Aliased textures have no compatible memory type")]
+    IncompatibleAliasing,
+    #[error("No memory type of the buffer has the properties {0:?}")]
+    NoMemoryType(vk::MemoryPropertyFlags),
+    #[error("Invalid sampler anisotropy {0}. Anisotropy needs to be at least 1.0")]
+    InvalidAnisotropy(f32),
+    #[error("Expected a texture in the {expected:?} color space but found a {found:?} texture")]
+    ColorSpaceMismatch {
+        expected: ColorSpace,
+        found: ColorSpace,
+    },
+
+    #[error("No shader binding named {0:?} in the material set")]
+    MissingBinding(String),
+    #[error("The effect samples {0:?} but the material provides no texture for it")]
+    MissingTexture(String),
+    #[error("Shader binding {name:?} expects a {expected:?} descriptor but a {found:?} buffer was bound")]
+    BindingTypeMismatch {
+        name: String,
+        expected: vk::DescriptorType,
+        found: BufferType,
+    },
+    #[error("Shader binding {name:?} expects a {expected:?} descriptor but a texture was bound")]
+    TextureBindingMismatch {
+        name: String,
+        expected: vk::DescriptorType,
+    },
+    #[error("Buffer of {size} bytes bound to {name:?} is smaller than the shader block of {required} bytes")]
+    BindingTooSmall {
+        name: String,
+        size: vk::DeviceSize,
+        required: vk::DeviceSize,
+    },
 
-    #[error("Unsupported layout transition from {0:?} to {1:?}")]
-    UnsupportedLayoutTransition(vk::ImageLayout, vk::ImageLayout),
+    #[error("Failed to resolve shader include {0:?}")]
+    MissingInclude(PathBuf),
+    #[error("Recursive shader include of {0:?}")]
+    RecursiveInclude(PathBuf),
+    #[error("Failed to compile shader {path:?}:\n{message}")]
+    ShaderCompilation { path: PathBuf, message: String },
+    #[error("Shader stage {0:?} can not be compiled")]
+    UnsupportedShaderStage(vk::ShaderStageFlags),
+
+    #[error("Descriptor binding {binding} is declared as {found:?} but was {existing:?} in another stage")]
+    BindingConflict {
+        binding: u32,
+        existing: vk::DescriptorType,
+        found: vk::DescriptorType,
+    },
 
     #[error("SPIR-V reflection error: {0}")]
     SPVReflectError(&'static str),
+
+    #[error("The {0} device feature is required but not enabled")]
+    MissingFeature(&'static str),
+    #[error("Patches of {count} control points exceed the maximum patch size of {max}")]
+    InvalidPatchSize { count: u32, max: u32 },
+    #[error("Mesh shading pipelines can not have tessellation or geometry shaders")]
+    MeshShadingStages,
+    #[error("Shader binding {name:?} uses descriptor set {set} which is never bound")]
+    UnknownSet { set: u32, name: String },
+    #[error("Vertex shader input {name:?} at location {location} has no vertex attribute")]
+    MissingVertexAttribute { location: u32, name: String },
+    #[error(
+        "Vertex shader input {name:?} at location {location} reads {input} from {attribute:?}"
+    )]
+    VertexAttributeMismatch {
+        location: u32,
+        name: String,
+        input: &'static str,
+        attribute: vk::Format,
+    },
+
+    #[error(
+        "Pipeline created for attachments {expected:?} was bound while rendering to {found:?}"
+    )]
+    IncompatiblePipeline {
+        expected: AttachmentFormats,
+        found: AttachmentFormats,
+    },
+    #[error("Descriptor set bound to set {set} does not match the layout of the pipeline")]
+    IncompatibleDescriptorSet { set: u32 },
+    #[error("Descriptor set bound to set {set} but the pipeline layout only has {count} sets")]
+    DescriptorSetOutOfRange { set: u32, count: u32 },
+    #[error("Framebuffer has {found} attachments but the renderpass has {expected}")]
+    AttachmentCountMismatch { expected: usize, found: usize },
+    #[error("Framebuffer attachment {index} is {found:?} but the renderpass expects {expected:?}")]
+    AttachmentMismatch {
+        index: usize,
+        expected: (vk::Format, vk::SampleCountFlags),
+        found: (vk::Format, vk::SampleCountFlags),
+    },
 }
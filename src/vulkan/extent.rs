@@ -1,9 +1,10 @@
 use std::{
     fmt::Display,
-    ops::{Add, Mul},
+    ops::{Add, Div, Mul},
 };
 
 use ash::vk;
+use ultraviolet::Vec2;
 
 /// Represents a width and height.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -16,6 +17,42 @@ impl Extent {
     pub fn new(width: u32, height: u32) -> Extent {
         Self { width, height }
     }
+
+    /// Returns the width divided by the height. A zero height is treated as one.
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height.max(1) as f32
+    }
+
+    /// Returns the extent scaled by `factor` and rounded, e.g; for rendering at a fraction of
+    /// the window size. Never smaller than 1x1.
+    pub fn scaled(&self, factor: f32) -> Extent {
+        let scale = |value: u32| ((value as f32 * factor).round() as u32).max(1);
+        Self::new(scale(self.width), scale(self.height))
+    }
+
+    /// Returns the extent of mip level `mip_level` of an image of this extent
+    pub fn mip(&self, mip_level: u32) -> Extent {
+        Self::new(
+            (self.width >> mip_level).max(1),
+            (self.height >> mip_level).max(1),
+        )
+    }
+
+    /// Returns the smallest width and height of both extents
+    pub fn min(&self, other: Extent) -> Extent {
+        Self::new(self.width.min(other.width), self.height.min(other.height))
+    }
+
+    /// Returns the largest width and height of both extents
+    pub fn max(&self, other: Extent) -> Extent {
+        Self::new(self.width.max(other.width), self.height.max(other.height))
+    }
+
+    /// Clamps the width and height between `min` and `max`, e.g; to the image extents
+    /// supported by a surface
+    pub fn clamp(&self, min: Extent, max: Extent) -> Extent {
+        self.min(max).max(min)
+    }
 }
 
 impl Display for Extent {
@@ -45,6 +82,17 @@ impl Add<u32> for Extent {
     }
 }
 
+impl Div<u32> for Extent {
+    type Output = Self;
+
+    fn div(self, rhs: u32) -> Self::Output {
+        Self {
+            width: self.width / rhs,
+            height: self.height / rhs,
+        }
+    }
+}
+
 // Conversions
 
 impl Into<vk::Extent2D> for Extent {
@@ -130,3 +178,19 @@ impl From<(usize, usize)> for Extent {
         }
     }
 }
+
+impl From<Extent> for Vec2 {
+    fn from(v: Extent) -> Self {
+        Vec2::new(v.width as f32, v.height as f32)
+    }
+}
+
+/// Rounds each component to the nearest integer, negative components are clamped to zero
+impl From<Vec2> for Extent {
+    fn from(v: Vec2) -> Self {
+        Self {
+            width: v.x.round().max(0.0) as u32,
+            height: v.y.round().max(0.0) as u32,
+        }
+    }
+}
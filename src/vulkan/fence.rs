@@ -23,6 +23,12 @@ pub fn wait(device: &Device, fences: &[vk::Fence], wait_all: bool) -> Result<(),
     Ok(())
 }
 
+/// Returns true if the fence is signaled without waiting
+pub fn is_signaled(device: &Device, fence: vk::Fence) -> Result<bool, Error> {
+    let signaled = unsafe { device.get_fence_status(fence)? };
+    Ok(signaled)
+}
+
 pub fn reset(device: &Device, fences: &[vk::Fence]) -> Result<(), Error> {
     unsafe { device.reset_fences(fences)? }
     Ok(())
@@ -1,6 +1,7 @@
 use std::rc::Rc;
 
-use super::{renderpass::MAX_ATTACHMENTS, Error, Extent, RenderPass};
+use super::{renderpass::MAX_ATTACHMENTS, validation, Error, Extent, RenderPass};
+use super::{Texture, TextureView};
 use arrayvec::ArrayVec;
 use ash::version::DeviceV1_0;
 use ash::vk;
@@ -10,6 +11,42 @@ use ash::Device;
 /// The framebuffer does not own the Textures and as such the user must ensure the referenced
 /// textures are kept alive. This is because a texture can be used in several framebuffers
 /// simultaneously.
+/// An image view which can be attached to a framebuffer
+pub trait Attachment: AsRef<vk::ImageView> {
+    fn format(&self) -> vk::Format;
+    fn samples(&self) -> vk::SampleCountFlags;
+}
+
+impl Attachment for Texture {
+    fn format(&self) -> vk::Format {
+        self.format()
+    }
+
+    fn samples(&self) -> vk::SampleCountFlags {
+        self.samples()
+    }
+}
+
+impl Attachment for TextureView {
+    fn format(&self) -> vk::Format {
+        self.format()
+    }
+
+    fn samples(&self) -> vk::SampleCountFlags {
+        self.samples()
+    }
+}
+
+impl<T: Attachment> Attachment for &T {
+    fn format(&self) -> vk::Format {
+        (*self).format()
+    }
+
+    fn samples(&self) -> vk::SampleCountFlags {
+        (*self).samples()
+    }
+}
+
 pub struct Framebuffer {
     device: Rc<Device>,
     framebuffer: vk::Framebuffer,
@@ -17,12 +54,23 @@ pub struct Framebuffer {
 }
 
 impl Framebuffer {
-    pub fn new<T: AsRef<vk::ImageView>>(
+    /// Creates a framebuffer for `renderpass`. In debug builds, fails if the formats or samples
+    /// of the attachments differ from the attachments of the renderpass.
+    pub fn new<T: Attachment>(
         device: Rc<Device>,
         renderpass: &RenderPass,
         attachments: &[T],
         extent: Extent,
     ) -> Result<Self, Error> {
+        if validation::ENABLED {
+            let found = attachments
+                .iter()
+                .map(|attachment| (attachment.format(), attachment.samples()))
+                .collect::<ArrayVec<[_; MAX_ATTACHMENTS]>>();
+
+            validation::check_framebuffer(renderpass.attachments(), &found)?;
+        }
+
         let attachment_views = attachments
             .iter()
             .map(|attachment| *attachment.as_ref())
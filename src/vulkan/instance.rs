@@ -12,6 +12,19 @@ pub const ENABLE_VALIDATION_LAYERS: bool = false;
 
 pub const INSTANCE_EXTENSIONS: &'static [&str] = &["VK_EXT_debug_utils"];
 
+/// The Vulkan version requested for the instance. Older loaders fall back to the highest
+/// version they support.
+pub const REQUESTED_API_VERSION: u32 = vk::make_version(1, 2, 0);
+
+/// Instance extensions which are enabled only if available
+pub const OPTIONAL_INSTANCE_EXTENSIONS: &'static [&str] = &[
+    "VK_KHR_get_physical_device_properties2",
+    // Exposes wide gamut and HDR surface color spaces
+    "VK_EXT_swapchain_colorspace",
+    // Required by exclusive fullscreen
+    "VK_KHR_get_surface_capabilities2",
+];
+
 // Returns the currently enabled instance layers
 pub fn get_layers() -> &'static [&'static str] {
     if ENABLE_VALIDATION_LAYERS {
@@ -21,25 +34,46 @@ pub fn get_layers() -> &'static [&'static str] {
     }
 }
 
-/// Creates a vulkan instance with the appropriate extensions and layers
+/// Returns the highest instance version supported by the loader up to
+/// `REQUESTED_API_VERSION`, without the patch version. Loaders without
+/// `vkEnumerateInstanceVersion` only support Vulkan 1.0.
+pub fn negotiate_api_version(entry: &Entry) -> Result<u32, Error> {
+    let supported = entry
+        .try_enumerate_instance_version()?
+        .unwrap_or(vk::make_version(1, 0, 0));
+
+    Ok(major_minor(supported).min(REQUESTED_API_VERSION))
+}
+
+/// Strips the patch version
+pub fn major_minor(version: u32) -> u32 {
+    vk::make_version(vk::version_major(version), vk::version_minor(version), 0)
+}
+
+/// Creates a vulkan instance with the appropriate extensions and layers for `api_version`
 pub fn create(
     entry: &Entry,
     glfw: &Glfw,
     name: &str,
     engine_name: &str,
+    api_version: u32,
+    required: &[String],
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
 ) -> Result<Instance, Error> {
     let name = CString::new(name).unwrap();
     let engine_name = CString::new(engine_name).unwrap();
 
     let app_info = vk::ApplicationInfo::builder()
         .application_name(&name)
-        .engine_name(&engine_name);
+        .engine_name(&engine_name)
+        .api_version(api_version);
 
-    let extensions: Vec<CString> = glfw
+    let mut extensions: Vec<CString> = glfw
         .get_required_instance_extensions()
         .ok_or(Error::VulkanUnsupported)?
         .into_iter()
         .chain(INSTANCE_EXTENSIONS.iter().map(|s| s.to_string()))
+        .chain(required.iter().cloned())
         .map(CString::new)
         .collect::<Result<Vec<_>, _>>()
         .unwrap();
@@ -51,6 +85,19 @@ pub fn create(
         return Err(Error::MissingExtensions(missing));
     }
 
+    let optional = OPTIONAL_INSTANCE_EXTENSIONS
+        .iter()
+        .map(|s| CString::new(*s))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    let missing = get_missing_extensions(entry, &optional)?;
+    extensions.extend(optional.into_iter().filter(|ext| !missing.contains(ext)));
+
+    // Required extensions may repeat those enabled by default
+    extensions.sort();
+    extensions.dedup();
+
     let extension_names_raw = extensions
         .iter()
         .map(|ext| ext.as_ptr() as *const i8)
@@ -81,12 +128,23 @@ pub fn create(
         .enabled_extension_names(&extension_names_raw)
         .enabled_layer_names(&layer_names_raw);
 
-    let instance = unsafe { entry.create_instance(&create_info, None)? };
+    let instance = unsafe { entry.create_instance(&create_info, allocation_callbacks)? };
     Ok(instance)
 }
 
-pub fn destroy(instance: &Instance) {
-    unsafe { instance.destroy_instance(None) };
+/// Returns true if the optional instance extension `name` is available and thus enabled by
+/// `create`.
+pub fn optional_extension_enabled(entry: &Entry, name: &str) -> bool {
+    let extension = CString::new(name).unwrap();
+
+    OPTIONAL_INSTANCE_EXTENSIONS.contains(&name)
+        && get_missing_extensions(entry, &[extension])
+            .map(|missing| missing.is_empty())
+            .unwrap_or(false)
+}
+
+pub fn destroy(instance: &Instance, allocation_callbacks: Option<&vk::AllocationCallbacks>) {
+    unsafe { instance.destroy_instance(allocation_callbacks) };
 }
 
 /// Returns a vector of missing extensions
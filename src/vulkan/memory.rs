@@ -0,0 +1,323 @@
+//! Memory heap inspection, budget tracking through `VK_EXT_memory_budget`, and allocation
+//! policies depending on the available heaps.
+use std::fmt;
+
+use ash::version::{EntryV1_0, InstanceV1_0};
+use ash::vk;
+use ash::{Entry, Instance};
+
+use std::rc::Rc;
+
+use super::{Error, VulkanContext};
+
+/// The device extension providing heap budgets
+pub const MEMORY_BUDGET_EXTENSION: &str = "VK_EXT_memory_budget";
+
+/// Heaps that are both device local and host visible larger than this are considered resizable
+/// BAR heaps. Without resizable BAR only a 256 MiB window is host visible.
+pub const RESIZABLE_BAR_THRESHOLD: vk::DeviceSize = 256 * 1024 * 1024;
+
+/// Fraction of the heap size assumed to be available when no budget extension is present.
+/// Matches the heuristic of VMA.
+const ESTIMATED_BUDGET_FRACTION: f64 = 0.8;
+
+/// Returns true if a memory type that is both DEVICE_LOCAL and HOST_VISIBLE exists in a heap
+/// larger than the legacy 256 MiB BAR window.
+pub fn has_resizable_bar(properties: &vk::PhysicalDeviceMemoryProperties) -> bool {
+    properties.memory_types[..properties.memory_type_count as usize]
+        .iter()
+        .filter(|ty| {
+            ty.property_flags.contains(
+                vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE,
+            )
+        })
+        .any(|ty| properties.memory_heaps[ty.heap_index as usize].size > RESIZABLE_BAR_THRESHOLD)
+}
+
+/// Returns the allocation info for host visible memory which is frequently written by the
+/// host and read by the device.
+/// When resizable BAR is available device local memory is explicitly preferred, since
+/// the device can then read the data without crossing the bus.
+pub fn host_visible_allocation_info(
+    resizable_bar: bool,
+    flags: vk_mem::AllocationCreateFlags,
+) -> vk_mem::AllocationCreateInfo {
+    if resizable_bar {
+        vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::Unknown,
+            flags,
+            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE,
+            preferred_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL
+                | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ..Default::default()
+        }
+    } else {
+        vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::CpuToGpu,
+            flags,
+            ..Default::default()
+        }
+    }
+}
+
+/// Expands the range of `size` bytes at `offset` to multiples of `atom_size`, as required when
+/// flushing or invalidating non coherent memory. The range is clamped to `allocation_size`.
+/// Offsets are relative to the allocation, which is aligned to `atom_size` by the allocator.
+pub fn align_to_atom(
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    atom_size: vk::DeviceSize,
+    allocation_size: vk::DeviceSize,
+) -> (vk::DeviceSize, vk::DeviceSize) {
+    let mask = atom_size.max(1) - 1;
+    let start = offset & !mask;
+    let end = ((offset + size + mask) & !mask).min(allocation_size);
+
+    (start, end.saturating_sub(start))
+}
+
+/// A single allocation shared by several resources that are never in use at the same time.
+/// Freed when dropped.
+pub struct AliasedMemory {
+    context: Rc<VulkanContext>,
+    allocation: vk_mem::Allocation,
+    size: vk::DeviceSize,
+}
+
+impl AliasedMemory {
+    /// Allocates memory fulfilling `requirements`, which should be the combined requirements
+    /// of all aliasing resources. Lazily allocated memory is preferred if `transient`.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        requirements: &vk::MemoryRequirements,
+        transient: bool,
+    ) -> Result<Self, Error> {
+        let preferred_flags = if transient {
+            vk::MemoryPropertyFlags::LAZILY_ALLOCATED
+        } else {
+            vk::MemoryPropertyFlags::default()
+        };
+
+        let (allocation, _allocation_info) = context.allocator().allocate_memory(
+            requirements,
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                preferred_flags,
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            context,
+            allocation,
+            size: requirements.size,
+        })
+    }
+
+    pub fn allocation(&self) -> &vk_mem::Allocation {
+        &self.allocation
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+}
+
+impl Drop for AliasedMemory {
+    fn drop(&mut self) {
+        if let Err(e) = self.context.allocator().free_memory(&self.allocation) {
+            log::error!("Failed to free aliased memory: {}", e);
+        }
+    }
+}
+
+/// Queries heap budgets and usage through `VK_EXT_memory_budget`.
+pub struct MemoryBudget {
+    properties2: vk::KhrGetPhysicalDeviceProperties2Fn,
+}
+
+impl MemoryBudget {
+    /// Loads the required instance commands. `VK_KHR_get_physical_device_properties2` must be
+    /// enabled on the instance and `VK_EXT_memory_budget` on the device.
+    pub fn new(entry: &Entry, instance: &Instance) -> Self {
+        let properties2 = vk::KhrGetPhysicalDeviceProperties2Fn::load(|name| unsafe {
+            std::mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+        });
+
+        Self { properties2 }
+    }
+
+    /// Returns the current budget and usage of each heap in bytes.
+    /// The values are only updated by the driver between frames.
+    pub fn query(
+        &self,
+        physical_device: vk::PhysicalDevice,
+    ) -> (
+        [vk::DeviceSize; vk::MAX_MEMORY_HEAPS],
+        [vk::DeviceSize; vk::MAX_MEMORY_HEAPS],
+    ) {
+        let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties = vk::PhysicalDeviceMemoryProperties2 {
+            p_next: &mut budget as *mut _ as *mut std::ffi::c_void,
+            ..Default::default()
+        };
+
+        unsafe {
+            self.properties2
+                .get_physical_device_memory_properties2_khr(physical_device, &mut properties);
+        }
+
+        (budget.heap_budget, budget.heap_usage)
+    }
+}
+
+/// Reports allocation statistics and the budget of a single memory heap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeapReport {
+    pub flags: vk::MemoryHeapFlags,
+    pub size: vk::DeviceSize,
+    /// The amount of memory the application can allocate from the heap without degrading
+    /// performance. Estimated from the heap size if `VK_EXT_memory_budget` is unavailable.
+    pub budget: vk::DeviceSize,
+    /// Memory used from the heap by the process. Only includes the memory allocated by this
+    /// allocator if `VK_EXT_memory_budget` is unavailable.
+    pub usage: vk::DeviceSize,
+    /// Bytes allocated in device memory blocks by the allocator
+    pub block_bytes: vk::DeviceSize,
+    /// Bytes occupied by live allocations
+    pub allocated_bytes: vk::DeviceSize,
+    pub allocation_count: u32,
+}
+
+/// Allocator statistics and heap budgets of the device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryReport {
+    pub heaps: Vec<HeapReport>,
+    pub allocated_bytes: vk::DeviceSize,
+    pub block_bytes: vk::DeviceSize,
+    pub allocation_count: u32,
+    /// The budgets and usages are reported by the driver rather than estimated
+    pub exact_budget: bool,
+    /// Frequently updated buffers are placed in device local host visible memory
+    pub resizable_bar: bool,
+}
+
+impl MemoryReport {
+    /// Collects the allocator statistics and queries the heap budgets if `budget` is
+    /// available.
+    pub fn new(
+        allocator: &vk_mem::Allocator,
+        physical_device: vk::PhysicalDevice,
+        properties: &vk::PhysicalDeviceMemoryProperties,
+        budget: Option<&MemoryBudget>,
+        resizable_bar: bool,
+    ) -> Result<Self, Error> {
+        let stats = allocator.calculate_stats()?;
+
+        let budgets = budget.map(|budget| budget.query(physical_device));
+
+        let heaps = properties.memory_heaps[..properties.memory_heap_count as usize]
+            .iter()
+            .enumerate()
+            .map(|(i, heap)| {
+                let stat = &stats.memoryHeap[i];
+                let block_bytes = stat.usedBytes + stat.unusedBytes;
+
+                let (budget, usage) = match &budgets {
+                    Some((budget, usage)) => (budget[i], usage[i]),
+                    None => (
+                        (heap.size as f64 * ESTIMATED_BUDGET_FRACTION) as vk::DeviceSize,
+                        block_bytes,
+                    ),
+                };
+
+                HeapReport {
+                    flags: heap.flags,
+                    size: heap.size,
+                    budget,
+                    usage,
+                    block_bytes,
+                    allocated_bytes: stat.usedBytes,
+                    allocation_count: stat.allocationCount,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            heaps,
+            allocated_bytes: stats.total.usedBytes,
+            block_bytes: stats.total.usedBytes + stats.total.unusedBytes,
+            allocation_count: stats.total.allocationCount,
+            exact_budget: budgets.is_some(),
+            resizable_bar,
+        })
+    }
+
+    /// Returns true if any heap uses more than its budget
+    pub fn over_budget(&self) -> bool {
+        self.heaps.iter().any(|heap| heap.usage > heap.budget)
+    }
+}
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} allocations, {:.2} MiB allocated in {:.2} MiB blocks, resizable BAR: {}",
+            self.allocation_count,
+            self.allocated_bytes as f64 / MIB,
+            self.block_bytes as f64 / MIB,
+            self.resizable_bar,
+        )?;
+
+        for (i, heap) in self.heaps.iter().enumerate() {
+            writeln!(
+                f,
+                "Heap {} {:?}: {:.2} / {:.2} MiB budget{}, {:.2} MiB size, {} allocations",
+                i,
+                heap.flags,
+                heap.usage as f64 / MIB,
+                heap.budget as f64 / MIB,
+                if self.exact_budget {
+                    ""
+                } else {
+                    " (estimated)"
+                },
+                heap.size as f64 / MIB,
+                heap.allocation_count,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::align_to_atom;
+
+    #[test]
+    fn align_unaligned_offset() {
+        assert_eq!(align_to_atom(70, 10, 64, 1024), (64, 64));
+    }
+
+    #[test]
+    fn align_unaligned_size() {
+        assert_eq!(align_to_atom(0, 100, 64, 1024), (0, 128));
+        assert_eq!(align_to_atom(128, 64, 64, 1024), (128, 64));
+    }
+
+    #[test]
+    fn align_clamps_to_allocation() {
+        assert_eq!(align_to_atom(960, 30, 64, 1000), (960, 40));
+        assert_eq!(align_to_atom(0, 1000, 256, 1000), (0, 1000));
+    }
+
+    #[test]
+    fn align_without_atom_size() {
+        assert_eq!(align_to_atom(13, 7, 0, 100), (13, 7));
+        assert_eq!(align_to_atom(13, 7, 1, 100), (13, 7));
+    }
+}
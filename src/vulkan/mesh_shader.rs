@@ -0,0 +1,189 @@
+//! Experimental support for drawing with task and mesh shaders rather than the vertex input
+//! stages through `VK_EXT_mesh_shader`. The extension is newer than the bundled vulkan headers,
+//! so the required structures are declared here and the commands are loaded manually.
+//!
+//! The extension depends on `VK_KHR_spirv_1_4`, which is only assumed from Vulkan 1.2 on.
+use std::ffi::{c_void, CStr};
+use std::{mem, ptr};
+
+use ash::version::{InstanceV1_0, InstanceV1_1};
+use ash::vk;
+use ash::{Device, Instance};
+
+/// The device extension providing mesh shaders
+pub const MESH_SHADER_EXTENSION: &str = "VK_EXT_mesh_shader";
+
+/// `VK_SHADER_STAGE_TASK_BIT_EXT`
+pub const TASK_STAGE: vk::ShaderStageFlags = vk::ShaderStageFlags::from_raw(0x40);
+/// `VK_SHADER_STAGE_MESH_BIT_EXT`
+pub const MESH_STAGE: vk::ShaderStageFlags = vk::ShaderStageFlags::from_raw(0x80);
+
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_MESH_SHADER_FEATURES_EXT: i32 = 1000328000;
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_MESH_SHADER_PROPERTIES_EXT: i32 = 1000328001;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PhysicalDeviceMeshShaderFeaturesEXT {
+    pub s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    pub task_shader: vk::Bool32,
+    pub mesh_shader: vk::Bool32,
+    pub multiview_mesh_shader: vk::Bool32,
+    pub primitive_fragment_shading_rate_mesh_shader: vk::Bool32,
+    pub mesh_shader_queries: vk::Bool32,
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceMeshShaderFeaturesEXT {}
+
+impl Default for PhysicalDeviceMeshShaderFeaturesEXT {
+    fn default() -> Self {
+        Self {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_PHYSICAL_DEVICE_MESH_SHADER_FEATURES_EXT,
+            ),
+            p_next: ptr::null_mut(),
+            task_shader: vk::FALSE,
+            mesh_shader: vk::FALSE,
+            multiview_mesh_shader: vk::FALSE,
+            primitive_fragment_shading_rate_mesh_shader: vk::FALSE,
+            mesh_shader_queries: vk::FALSE,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PhysicalDeviceMeshShaderPropertiesEXT {
+    pub s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    pub max_task_work_group_total_count: u32,
+    pub max_task_work_group_count: [u32; 3],
+    pub max_task_work_group_invocations: u32,
+    pub max_task_work_group_size: [u32; 3],
+    pub max_task_payload_size: u32,
+    pub max_task_shared_memory_size: u32,
+    pub max_task_payload_and_shared_memory_size: u32,
+    pub max_mesh_work_group_total_count: u32,
+    pub max_mesh_work_group_count: [u32; 3],
+    pub max_mesh_work_group_invocations: u32,
+    pub max_mesh_work_group_size: [u32; 3],
+    pub max_mesh_shared_memory_size: u32,
+    pub max_mesh_payload_and_shared_memory_size: u32,
+    pub max_mesh_output_memory_size: u32,
+    pub max_mesh_payload_and_output_memory_size: u32,
+    pub max_mesh_output_components: u32,
+    pub max_mesh_output_vertices: u32,
+    pub max_mesh_output_primitives: u32,
+    pub max_mesh_output_layers: u32,
+    pub max_mesh_multiview_view_count: u32,
+    pub mesh_output_per_vertex_granularity: u32,
+    pub mesh_output_per_primitive_granularity: u32,
+    pub max_preferred_task_work_group_invocations: u32,
+    pub max_preferred_mesh_work_group_invocations: u32,
+    pub prefers_local_invocation_vertex_output: vk::Bool32,
+    pub prefers_local_invocation_primitive_output: vk::Bool32,
+    pub prefers_compact_vertex_output: vk::Bool32,
+    pub prefers_compact_primitive_output: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceMeshShaderPropertiesEXT {
+    fn default() -> Self {
+        Self {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_PHYSICAL_DEVICE_MESH_SHADER_PROPERTIES_EXT,
+            ),
+            p_next: ptr::null_mut(),
+            max_task_work_group_total_count: 0,
+            max_task_work_group_count: [0; 3],
+            max_task_work_group_invocations: 0,
+            max_task_work_group_size: [0; 3],
+            max_task_payload_size: 0,
+            max_task_shared_memory_size: 0,
+            max_task_payload_and_shared_memory_size: 0,
+            max_mesh_work_group_total_count: 0,
+            max_mesh_work_group_count: [0; 3],
+            max_mesh_work_group_invocations: 0,
+            max_mesh_work_group_size: [0; 3],
+            max_mesh_shared_memory_size: 0,
+            max_mesh_payload_and_shared_memory_size: 0,
+            max_mesh_output_memory_size: 0,
+            max_mesh_payload_and_output_memory_size: 0,
+            max_mesh_output_components: 0,
+            max_mesh_output_vertices: 0,
+            max_mesh_output_primitives: 0,
+            max_mesh_output_layers: 0,
+            max_mesh_multiview_view_count: 0,
+            mesh_output_per_vertex_granularity: 0,
+            mesh_output_per_primitive_granularity: 0,
+            max_preferred_task_work_group_invocations: 0,
+            max_preferred_mesh_work_group_invocations: 0,
+            prefers_local_invocation_vertex_output: vk::FALSE,
+            prefers_local_invocation_primitive_output: vk::FALSE,
+            prefers_compact_vertex_output: vk::FALSE,
+            prefers_compact_primitive_output: vk::FALSE,
+        }
+    }
+}
+
+type PfnCmdDrawMeshTasksEXT = unsafe extern "system" fn(vk::CommandBuffer, u32, u32, u32);
+
+/// Queries the mesh shader features and properties of a Vulkan 1.2 device which supports
+/// `MESH_SHADER_EXTENSION`.
+pub fn query(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> (
+    PhysicalDeviceMeshShaderFeaturesEXT,
+    PhysicalDeviceMeshShaderPropertiesEXT,
+) {
+    let mut features = PhysicalDeviceMeshShaderFeaturesEXT::default();
+    let mut features2 = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+
+    let mut properties = PhysicalDeviceMeshShaderPropertiesEXT::default();
+    let mut properties2 = vk::PhysicalDeviceProperties2 {
+        p_next: &mut properties as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+
+    unsafe {
+        instance.get_physical_device_features2(physical_device, &mut features2);
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+    }
+
+    features.p_next = ptr::null_mut();
+    properties.p_next = ptr::null_mut();
+
+    (features, properties)
+}
+
+/// Loaded `VK_EXT_mesh_shader` device commands.
+pub struct MeshShader {
+    cmd_draw_mesh_tasks: PfnCmdDrawMeshTasksEXT,
+}
+
+impl MeshShader {
+    /// Loads the mesh shader commands from device. The extension must have been enabled.
+    pub fn new(instance: &Instance, device: &Device) -> Option<Self> {
+        unsafe {
+            let draw = instance.get_device_proc_addr(
+                device.handle(),
+                CStr::from_bytes_with_nul_unchecked(b"vkCmdDrawMeshTasksEXT\0").as_ptr(),
+            )?;
+
+            let draw = mem::transmute::<unsafe extern "system" fn(), PfnCmdDrawMeshTasksEXT>(draw);
+
+            Some(Self {
+                cmd_draw_mesh_tasks: draw,
+            })
+        }
+    }
+
+    /// Draws the given number of workgroups of the task shader, or of the mesh shader if the
+    /// bound pipeline has no task shader.
+    pub fn draw_mesh_tasks(&self, commandbuffer: vk::CommandBuffer, x: u32, y: u32, z: u32) {
+        unsafe { (self.cmd_draw_mesh_tasks)(commandbuffer, x, y, z) };
+    }
+}
@@ -0,0 +1,188 @@
+//! Generation of the mip levels of sampled textures from the first level after upload.
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ash::{vk, Device};
+
+use super::barrier::ImageBarrier;
+use super::commands::CommandBuffer;
+use super::descriptors::{DescriptorAllocator, DescriptorBuilder, DescriptorLayoutCache};
+use super::pipeline::{ComputePipeline, ComputePipelineInfo};
+use super::texture::Texture;
+use super::{Error, Extent, VulkanContext};
+
+/// The width and height of the downsampling workgroups
+const GROUP_SIZE: u32 = 8;
+
+/// How the mip levels of a texture are generated from the first level when written
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MipGeneration {
+    /// Blits each level from the previous one with linear filtering. The format needs to
+    /// support linear blits.
+    #[default]
+    Blit,
+    /// Downsamples up to two levels per dispatch in a compute shader, averaging sRGB textures
+    /// in linear space. Falls back to `Blit` for formats which are not supported, see
+    /// `is_supported`.
+    Compute,
+}
+
+/// Returns the format of the storage views of `format` along with the shader variant which
+/// downsamples it, or None if compute generation does not support the format. sRGB formats are
+/// stored through unorm views and converted by the shader.
+pub fn storage_format(format: vk::Format) -> Option<(vk::Format, &'static str)> {
+    match format {
+        vk::Format::R8G8B8A8_SRGB => Some((vk::Format::R8G8B8A8_UNORM, "downsample.srgb")),
+        vk::Format::R8G8B8A8_UNORM => Some((vk::Format::R8G8B8A8_UNORM, "downsample")),
+        vk::Format::R16G16B16A16_SFLOAT => {
+            Some((vk::Format::R16G16B16A16_SFLOAT, "downsample.rgba16f"))
+        }
+        vk::Format::R32G32B32A32_SFLOAT => {
+            Some((vk::Format::R32G32B32A32_SFLOAT, "downsample.rgba32f"))
+        }
+        _ => None,
+    }
+}
+
+/// Returns true if the mip levels of `format` textures can be generated in a compute shader.
+/// Views of another format than the image require Vulkan 1.1 for the extended usage.
+pub fn is_supported(context: &VulkanContext, format: vk::Format) -> bool {
+    storage_format(format).is_some_and(|(storage, _)| {
+        context.supports_format(storage, vk::FormatFeatureFlags::STORAGE_IMAGE)
+            && (storage == format || context.api_version() >= vk::make_version(1, 1, 0))
+    })
+}
+
+/// Downsamples the mip levels of textures using `MipGeneration::Compute`. Owned by the context
+/// and created on first use, so the pipelines are shared by all textures.
+pub struct MipGenerator {
+    device: Rc<Device>,
+    layout_cache: DescriptorLayoutCache,
+    descriptor_allocator: DescriptorAllocator,
+    /// Created on first use of each shader variant
+    pipelines: HashMap<&'static str, ComputePipeline>,
+}
+
+impl MipGenerator {
+    pub fn new(device: Rc<Device>) -> Self {
+        Self {
+            layout_cache: DescriptorLayoutCache::new(device.clone()),
+            descriptor_allocator: DescriptorAllocator::new(device.clone(), 16),
+            pipelines: HashMap::new(),
+            device,
+        }
+    }
+
+    /// Records and waits for the downsampling of all mip levels of `texture` from the first
+    /// level, which needs to be in `TRANSFER_DST_OPTIMAL`. Leaves all levels in
+    /// `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn generate(&mut self, context: &VulkanContext, texture: &Texture) -> Result<(), Error> {
+        let (format, variant) = storage_format(texture.format())
+            .ok_or_else(|| Error::UnsupportedMipFormat(texture.format()))?;
+
+        let device = &self.device;
+        let layout_cache = &mut self.layout_cache;
+
+        if !self.pipelines.contains_key(variant) {
+            let pipeline = ComputePipeline::new(
+                device.clone(),
+                layout_cache,
+                ComputePipelineInfo {
+                    shader: format!("./data/shaders/{}.comp.spv", variant).into(),
+                    ..Default::default()
+                },
+            )?;
+
+            self.pipelines.insert(variant, pipeline);
+        }
+
+        let pipeline = &self.pipelines[variant];
+
+        let views = (0..texture.mip_levels())
+            .map(|mip_level| texture.format_view(format, mip_level))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Each dispatch writes one level, or two if the first has even dimensions
+        let mut dispatches = Vec::new();
+        let mut source = 0;
+
+        while source + 1 < views.len() {
+            let first = texture.extent().mip(source as u32 + 1);
+            let even = first.width.is_multiple_of(2) && first.height.is_multiple_of(2);
+            let levels = if source + 2 < views.len() && even {
+                2
+            } else {
+                1
+            };
+
+            let mut set = Default::default();
+            DescriptorBuilder::new()
+                .bind_storage_image_view(0, vk::ShaderStageFlags::COMPUTE, &views[source])
+                .bind_storage_image_view(1, vk::ShaderStageFlags::COMPUTE, &views[source + 1])
+                .bind_storage_image_view(2, vk::ShaderStageFlags::COMPUTE, &views[source + levels])
+                .build(
+                    device,
+                    layout_cache,
+                    &mut self.descriptor_allocator,
+                    &mut set,
+                )?;
+
+            dispatches.push((set, first, levels as u32));
+            source += levels;
+        }
+
+        let result = context
+            .transfer_pool()
+            .single_time_command(context.graphics_queue(), |commandbuffer| {
+                record(commandbuffer, pipeline, texture, &dispatches)
+            });
+
+        self.descriptor_allocator.reset()?;
+        result
+    }
+}
+
+fn record(
+    commandbuffer: &CommandBuffer,
+    pipeline: &ComputePipeline,
+    texture: &Texture,
+    dispatches: &[(vk::DescriptorSet, Extent, u32)],
+) {
+    let barrier = |old_layout, new_layout| {
+        ImageBarrier::new(
+            texture.image(),
+            vk::ImageAspectFlags::COLOR,
+            texture.mip_levels(),
+            old_layout,
+            new_layout,
+        )
+    };
+
+    commandbuffer.image_barriers(&[barrier(
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::GENERAL,
+    )]);
+
+    commandbuffer.bind_compute_pipeline(pipeline);
+
+    for (i, (set, extent, levels)) in dispatches.iter().enumerate() {
+        // Waits for the previous dispatch to write the source level
+        if i > 0 {
+            commandbuffer
+                .image_barriers(&[barrier(vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL)]);
+        }
+
+        commandbuffer.bind_compute_descriptor_sets(pipeline, 0, &[*set], &[]);
+        commandbuffer.push_constants(pipeline, vk::ShaderStageFlags::COMPUTE, 0, levels);
+        commandbuffer.dispatch(
+            extent.width.div_ceil(GROUP_SIZE),
+            extent.height.div_ceil(GROUP_SIZE),
+            1,
+        );
+    }
+
+    commandbuffer.image_barriers(&[barrier(
+        vk::ImageLayout::GENERAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    )]);
+}
@@ -1,3 +1,4 @@
+pub mod barrier;
 pub mod buffer;
 pub mod commands;
 pub mod common_vertex;
@@ -5,29 +6,48 @@ pub mod context;
 pub mod debug_utils;
 pub mod descriptors;
 pub mod device;
+pub mod dynamic_rendering;
 pub mod entry;
 mod error;
 pub mod extent;
 pub mod fence;
 pub mod framebuffer;
 pub mod instance;
+pub mod memory;
+pub mod mesh_shader;
+pub mod mipmap;
+pub mod per_frame_buffer;
 pub mod pipeline;
+pub mod ray_tracing;
 pub mod renderpass;
 pub mod sampler;
 pub mod semaphore;
+pub mod shading_rate;
 pub mod surface;
 pub mod swapchain;
 pub mod texture;
+pub mod tracking;
+pub mod typed_buffer;
+pub mod validation;
 pub mod vertex;
 
-pub use buffer::{Buffer, BufferType, BufferUsage};
-pub use context::VulkanContext;
+pub use barrier::ImageBarrier;
+pub use buffer::{Buffer, BufferType, BufferUsage, MappedWriter, QueueSharing};
+pub use context::{ContextInfo, VulkanContext};
+pub use debug_utils::DebugName;
+pub use device::{DeviceCapabilities, DeviceFeatures};
+pub use dynamic_rendering::{
+    RenderingAttachment, RenderingAttachments, RenderingFormats, ShadingRateAttachment,
+};
 pub use error::Error;
 pub use extent::Extent;
 pub use framebuffer::Framebuffer;
+pub use memory::MemoryReport;
+pub use per_frame_buffer::PerFrameBuffer;
 pub use pipeline::Pipeline;
 pub use renderpass::{AttachmentInfo, AttachmentReference, LoadOp, RenderPass, StoreOp};
 pub use sampler::{Sampler, SamplerInfo};
 pub use swapchain::Swapchain;
-pub use texture::{Texture, TextureInfo, TextureUsage};
-pub use vertex::VertexDesc;
+pub use texture::{Texture, TextureInfo, TextureLoadInfo, TextureUsage, TextureView};
+pub use typed_buffer::TypedBuffer;
+pub use vertex::{VertexAttribute, VertexDesc};
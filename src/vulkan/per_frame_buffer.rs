@@ -0,0 +1,131 @@
+use std::rc::Rc;
+
+use arrayvec::ArrayVec;
+
+use std::ops::Range;
+
+use super::{swapchain::MAX_FRAMES, BufferType, BufferUsage, Error, TypedBuffer, VulkanContext};
+
+/// A typed buffer duplicated for each frame in flight. Each frame writes and binds its own copy,
+/// so the host never writes a copy which the device may still be reading as long as the fence
+/// of the frame has been waited on.
+pub struct PerFrameBuffer<T> {
+    context: Rc<VulkanContext>,
+    ty: BufferType,
+    usage: BufferUsage,
+    frames: ArrayVec<[TypedBuffer<T>; MAX_FRAMES]>,
+}
+
+/// Unchanged runs of at most this many elements between two changed elements are written as
+/// well, which merges the ranges into a single write and flush
+const MAX_GAP: usize = 16;
+
+impl<T: Copy> PerFrameBuffer<T> {
+    /// Creates `frames_in_flight` zeroed copies of `len` elements.
+    /// Panics if `frames_in_flight` is larger than `MAX_FRAMES`.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        ty: BufferType,
+        usage: BufferUsage,
+        frames_in_flight: usize,
+        len: usize,
+    ) -> Result<Self, Error> {
+        let frames = (0..frames_in_flight)
+            .map(|_| TypedBuffer::new(context.clone(), ty, usage, len))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            context,
+            ty,
+            usage,
+            frames,
+        })
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns the copy of the frame in flight `frame_index`
+    pub fn current(&self, frame_index: usize) -> &TypedBuffer<T> {
+        &self.frames[frame_index]
+    }
+
+    /// Returns the copy of the frame in flight `frame_index` for writing. The previous
+    /// submission of the frame must have completed.
+    pub fn current_mut(&mut self, frame_index: usize) -> &mut TypedBuffer<T> {
+        &mut self.frames[frame_index]
+    }
+
+    /// Replaces the copy of the frame in flight `frame_index` with `len` zeroed elements. The
+    /// other frames keep their length until resized themselves, since they may still be in use
+    /// by the device. Descriptor sets referring to the old copy need to be updated.
+    pub fn resize(&mut self, frame_index: usize, len: usize) -> Result<(), Error> {
+        self.frames[frame_index] =
+            TypedBuffer::new(self.context.clone(), self.ty, self.usage, len)?;
+        Ok(())
+    }
+
+    /// Writes the elements at the ascending `indices` of the copy of the frame in flight
+    /// `frame_index`, with the value of each returned by `element`. Nearby indices are
+    /// coalesced into as few writes as possible, which writes the elements between them as
+    /// well. Returns the number of elements written.
+    pub fn write_indices<I, F>(
+        &mut self,
+        frame_index: usize,
+        indices: I,
+        element: F,
+    ) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = usize>,
+        F: Fn(usize) -> T,
+    {
+        let buffer = &mut self.frames[frame_index];
+
+        let mut written = 0;
+
+        for range in coalesce(indices) {
+            written += range.len();
+            buffer.write_iter(range.start, range.map(&element))?;
+        }
+
+        Ok(written)
+    }
+
+    /// Returns the copies of each frame in flight in order
+    pub fn iter(&self) -> impl Iterator<Item = &TypedBuffer<T>> {
+        self.frames.iter()
+    }
+}
+
+// Merges ascending indices into ranges. Indices separated by at most `MAX_GAP` other indices
+// are placed in the same range.
+fn coalesce(indices: impl IntoIterator<Item = usize>) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+
+    for i in indices {
+        match ranges.last_mut() {
+            Some(last) if i - last.end <= MAX_GAP => last.end = i + 1,
+            _ => ranges.push(i..i + 1),
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce_nearby() {
+        assert_eq!(coalesce(vec![]), vec![]);
+        assert_eq!(coalesce(vec![3]), vec![3..4]);
+        assert_eq!(coalesce(vec![0, 1, 2]), vec![0..3]);
+        assert_eq!(coalesce(vec![0, 1 + MAX_GAP]), vec![0..2 + MAX_GAP]);
+        assert_eq!(
+            coalesce(vec![0, 2 + MAX_GAP, 3 + MAX_GAP]),
+            vec![0..1, 2 + MAX_GAP..4 + MAX_GAP]
+        );
+    }
+}
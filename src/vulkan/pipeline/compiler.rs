@@ -0,0 +1,176 @@
+//! Runtime compilation of GLSL shaders with `#include` resolution and per-effect defines.
+//! Uses the same compiler as the build time compilation in the Makefile.
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use ash::vk;
+
+use crate::vulkan::mesh_shader::{MESH_STAGE, TASK_STAGE};
+use crate::vulkan::Error;
+
+/// The GLSL to SPIR-V compiler executable. Needs to be in PATH.
+pub const SHADERC: &str = "glslc";
+
+/// Returns true if the shader at `path` is GLSL source rather than precompiled SPIR-V.
+pub fn is_source(path: &Path) -> bool {
+    path.extension().map(|ext| ext != "spv").unwrap_or(true)
+}
+
+/// Reads the GLSL source at `path` and expands all `#include "file"` directives relative to
+/// the including file. Each file is included at most once.
+/// `defines` are inserted as `#define` directives after the `#version` directive.
+pub fn preprocess(path: &Path, defines: &[(String, String)]) -> Result<String, Error> {
+    let mut output = String::new();
+    let mut included = HashSet::new();
+    let mut stack = Vec::new();
+
+    expand(path, Some(defines), &mut included, &mut stack, &mut output)?;
+
+    Ok(output)
+}
+
+/// Compiles preprocessed GLSL source for the given stage. `path` is only used for error
+/// messages.
+pub fn compile(source: &str, stage: vk::ShaderStageFlags, path: &Path) -> Result<Vec<u32>, Error> {
+    let mut command = Command::new(SHADERC);
+    command.arg(format!("-fshader-stage={}", stage_name(stage)?));
+
+    // Mesh shaders require SPIR-V 1.4
+    if stage == TASK_STAGE || stage == MESH_STAGE {
+        command.arg("--target-spv=spv1.4");
+    }
+
+    // Ray queries are only available in Vulkan 1.2 environments
+    if source.contains("GL_EXT_ray_query") {
+        command.arg("--target-env=vulkan1.2");
+    }
+
+    let mut child = command
+        .args(&["-o", "-", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Close stdin after writing to signal the end of the source
+    child.stdin.take().unwrap().write_all(source.as_bytes())?;
+
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(Error::ShaderCompilation {
+            path: path.to_owned(),
+            message: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+
+    Ok(ash::util::read_spv(&mut Cursor::new(output.stdout))?)
+}
+
+/// Preprocesses and compiles the GLSL source file at `path`.
+pub fn compile_file(
+    path: &Path,
+    stage: vk::ShaderStageFlags,
+    defines: &[(String, String)],
+) -> Result<Vec<u32>, Error> {
+    let source = preprocess(path, defines)?;
+    compile(&source, stage, path)
+}
+
+fn expand(
+    path: &Path,
+    defines: Option<&[(String, String)]>,
+    included: &mut HashSet<PathBuf>,
+    stack: &mut Vec<PathBuf>,
+    output: &mut String,
+) -> Result<(), Error> {
+    let path = fs::canonicalize(path).map_err(|_| Error::MissingInclude(path.to_owned()))?;
+
+    if stack.contains(&path) {
+        return Err(Error::RecursiveInclude(path));
+    }
+
+    if !included.insert(path.clone()) {
+        return Ok(());
+    }
+
+    let source = fs::read_to_string(&path)?;
+    let has_version = source
+        .lines()
+        .any(|line| line.trim_start().starts_with("#version"));
+
+    match defines {
+        Some(defines) if !has_version => write_defines(defines, 1, output),
+        // Line numbers restart for included files
+        None => output.push_str("#line 1\n"),
+        _ => {}
+    }
+
+    stack.push(path.clone());
+
+    for (i, line) in source.lines().enumerate() {
+        let trimmed = line.trim_start();
+
+        if let Some(include) = parse_include(trimmed) {
+            let include = path.parent().unwrap().join(include);
+            expand(&include, None, included, stack, output)?;
+
+            // Restore the line numbers of the including file
+            output.push_str(&format!("#line {}\n", i + 2));
+            continue;
+        }
+
+        output.push_str(line);
+        output.push('\n');
+
+        if let Some(defines) = defines {
+            if trimmed.starts_with("#version") {
+                write_defines(defines, i + 2, output);
+            }
+        }
+    }
+
+    stack.pop();
+
+    Ok(())
+}
+
+/// Writes the defines followed by a line directive to continue at `next_line`
+fn write_defines(defines: &[(String, String)], next_line: usize, output: &mut String) {
+    for (name, value) in defines {
+        output.push_str(&format!("#define {} {}\n", name, value));
+    }
+
+    output.push_str(&format!("#line {}\n", next_line));
+}
+
+/// Returns the path of an `#include "file"` directive
+fn parse_include(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("#include")?.trim();
+
+    if rest.len() >= 2 && rest.starts_with('"') && rest.ends_with('"') {
+        Some(&rest[1..rest.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// Returns the name of a single shader stage as understood by the compiler
+fn stage_name(stage: vk::ShaderStageFlags) -> Result<&'static str, Error> {
+    let name = match stage {
+        vk::ShaderStageFlags::VERTEX => "vert",
+        vk::ShaderStageFlags::FRAGMENT => "frag",
+        vk::ShaderStageFlags::GEOMETRY => "geom",
+        vk::ShaderStageFlags::TESSELLATION_CONTROL => "tesc",
+        vk::ShaderStageFlags::TESSELLATION_EVALUATION => "tese",
+        vk::ShaderStageFlags::COMPUTE => "comp",
+        TASK_STAGE => "task",
+        MESH_STAGE => "mesh",
+        _ => return Err(Error::UnsupportedShaderStage(stage)),
+    };
+
+    Ok(name)
+}
@@ -0,0 +1,103 @@
+use ash::version::DeviceV1_0;
+use ash::{vk, Device};
+use std::{ffi::CString, path::PathBuf, rc::Rc};
+
+use super::shader::{self, ShaderBinding, ShaderModule};
+use crate::vulkan::{descriptors::DescriptorLayoutCache, Error};
+
+#[derive(Debug, Clone, Default)]
+pub struct ComputePipelineInfo {
+    /// Path to a GLSL source or precompiled SPIR-V compute shader
+    pub shader: PathBuf,
+    /// Preprocessor defines used when compiling GLSL sources
+    pub defines: Vec<(String, String)>,
+}
+
+/// A pipeline dispatching a single compute shader. The layout is reflected from the shader.
+pub struct ComputePipeline {
+    device: Rc<Device>,
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    bindings: Vec<ShaderBinding>,
+}
+
+impl ComputePipeline {
+    pub fn new(
+        device: Rc<Device>,
+        layout_cache: &mut DescriptorLayoutCache,
+        info: ComputePipelineInfo,
+    ) -> Result<Self, Error> {
+        let shader = ShaderModule::load(
+            &device,
+            &info.shader,
+            vk::ShaderStageFlags::COMPUTE,
+            &info.defines,
+        )?;
+
+        let (layout, bindings) = shader::reflect(&device, &[&shader], layout_cache)?;
+
+        let entrypoint = CString::new("main").unwrap();
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .module(shader.module)
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .name(&entrypoint)
+            .build();
+
+        let create_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(layout)
+            .build();
+
+        let pipeline = unsafe {
+            device
+                .create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
+                .map_err(|(_, e)| e)
+        };
+
+        shader.destroy(&device);
+
+        let pipeline = match pipeline {
+            Ok(pipelines) => pipelines[0],
+            Err(e) => {
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                return Err(e.into());
+            }
+        };
+
+        Ok(Self {
+            device,
+            pipeline,
+            layout,
+            bindings,
+        })
+    }
+
+    /// Returns the raw vulkan pipeline handle.
+    pub fn pipeline(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+
+    // Returns the pipeline layout.
+    pub fn layout(&self) -> vk::PipelineLayout {
+        self.layout
+    }
+
+    /// Returns the reflected descriptor bindings of the shader.
+    pub fn bindings(&self) -> &[ShaderBinding] {
+        &self.bindings
+    }
+}
+
+impl AsRef<vk::PipelineLayout> for ComputePipeline {
+    fn as_ref(&self) -> &vk::PipelineLayout {
+        &self.layout
+    }
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_pipeline(self.pipeline, None) }
+        unsafe { self.device.destroy_pipeline_layout(self.layout, None) }
+    }
+}
@@ -1,26 +1,223 @@
-use super::{descriptors::DescriptorLayoutCache, Error};
-use super::{renderpass::*, Extent};
+use super::mesh_shader::{MESH_STAGE, TASK_STAGE};
+use super::renderpass::*;
+use super::shading_rate;
+use super::validation::AttachmentFormats;
+use super::{descriptors::DescriptorLayoutCache, dynamic_rendering::RenderingFormats, Error};
+use super::{DebugName, DeviceCapabilities, Extent, VulkanContext};
+use arrayvec::ArrayVec;
 use ash::version::DeviceV1_0;
 use ash::Device;
+use std::path::{Path, PathBuf};
 use std::{ffi::CString, rc::Rc};
-use std::{fs::File, path::PathBuf};
 
 use ash::vk;
 
+pub mod compiler;
+mod compute;
 mod shader;
+pub use compute::{ComputePipeline, ComputePipelineInfo};
+pub use shader::ShaderBinding;
 use shader::*;
 
+pub use vk::BlendFactor;
+pub use vk::BlendOp;
+pub use vk::ColorComponentFlags as ColorWriteMask;
+pub use vk::StencilOp;
+
+/// The specialization constant id of the point size in vertex shaders, declared as
+/// `layout(constant_id = 0) const float POINT_SIZE = 1.0;` and written to `gl_PointSize`.
+pub const POINT_SIZE_CONSTANT_ID: u32 = 0;
+
+/// Specifies how fragment outputs are written and blended into the color attachment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlendState {
+    /// The color channels written to. Empty for passes that only write depth or stencil
+    pub write_mask: ColorWriteMask,
+    /// Blend with the existing attachment contents instead of overwriting them
+    pub enable: bool,
+    pub src_color: BlendFactor,
+    pub dst_color: BlendFactor,
+    pub color_op: BlendOp,
+    pub src_alpha: BlendFactor,
+    pub dst_alpha: BlendFactor,
+    pub alpha_op: BlendOp,
+}
+
+impl Default for BlendState {
+    fn default() -> Self {
+        Self {
+            write_mask: ColorWriteMask::all(),
+            enable: false,
+            src_color: BlendFactor::ONE,
+            dst_color: BlendFactor::ZERO,
+            color_op: BlendOp::ADD,
+            src_alpha: BlendFactor::ONE,
+            dst_alpha: BlendFactor::ZERO,
+            alpha_op: BlendOp::ADD,
+        }
+    }
+}
+
+impl BlendState {
+    /// Overwrites the color attachment with the fragment output
+    pub fn opaque() -> Self {
+        Self::default()
+    }
+
+    /// Blends the fragment output over the color attachment by the output alpha
+    pub fn alpha() -> Self {
+        Self {
+            enable: true,
+            src_color: BlendFactor::SRC_ALPHA,
+            dst_color: BlendFactor::ONE_MINUS_SRC_ALPHA,
+            src_alpha: BlendFactor::ONE,
+            dst_alpha: BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the fragment output to the color attachment
+    pub fn additive() -> Self {
+        Self {
+            enable: true,
+            src_color: BlendFactor::ONE,
+            dst_color: BlendFactor::ONE,
+            src_alpha: BlendFactor::ONE,
+            dst_alpha: BlendFactor::ONE,
+            ..Default::default()
+        }
+    }
+
+    /// Writes no color at all, e.g; for depth only or stencil marking passes
+    pub fn no_color() -> Self {
+        Self {
+            write_mask: ColorWriteMask::empty(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Into<vk::PipelineColorBlendAttachmentState> for &BlendState {
+    fn into(self) -> vk::PipelineColorBlendAttachmentState {
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable: self.enable as vk::Bool32,
+            src_color_blend_factor: self.src_color,
+            dst_color_blend_factor: self.dst_color,
+            color_blend_op: self.color_op,
+            src_alpha_blend_factor: self.src_alpha,
+            dst_alpha_blend_factor: self.dst_alpha,
+            alpha_blend_op: self.alpha_op,
+            color_write_mask: self.write_mask,
+        }
+    }
+}
+
+/// The tessellation stages of a pipeline. The vertices are assembled into patches rather than
+/// the primitives of the topology.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TessellationInfo {
+    /// Path to a GLSL source or precompiled SPIR-V tessellation control shader
+    pub control_shader: PathBuf,
+    /// Path to a GLSL source or precompiled SPIR-V tessellation evaluation shader
+    pub evaluation_shader: PathBuf,
+    /// The number of vertices of each patch, e.g; 3 for the triangles of a mesh. At most
+    /// `DeviceCapabilities::max_tessellation_patch_size`.
+    pub patch_control_points: u32,
+}
+
+/// The task and mesh stages of a pipeline, which replace the vertex input, vertex,
+/// tessellation and geometry stages. The mesh shader outputs the primitives directly, e.g; the
+/// triangles of a meshlet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshShadingInfo {
+    /// Path to a GLSL source or precompiled SPIR-V task shader, which launches the mesh shader
+    /// workgroups, e.g; after culling meshlets. The mesh shader workgroups are drawn directly
+    /// if None.
+    pub task_shader: Option<PathBuf>,
+    /// Path to a GLSL source or precompiled SPIR-V mesh shader
+    pub mesh_shader: PathBuf,
+}
+
+/// The variable rate shading of a pipeline, which shades a single fragment for each block of
+/// `fragment_size` pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShadingRateState {
+    /// The width and height in pixels of each fragment, e.g; 2x2 for effects with little high
+    /// frequency detail. Both need to be powers of two, and are clamped to
+    /// `DeviceCapabilities::max_fragment_size`.
+    pub fragment_size: Extent,
+    /// Use the rate of the shading rate attachment instead of `fragment_size` when rendering
+    /// with one. See `RenderingAttachments::shading_rate`.
+    pub attachment: bool,
+    /// Set the fragment size with `CommandBuffer::set_fragment_shading_rate` when drawing
+    /// rather than using `fragment_size`
+    pub dynamic: bool,
+}
+
+impl Default for ShadingRateState {
+    fn default() -> Self {
+        Self {
+            fragment_size: Extent::new(1, 1),
+            attachment: true,
+            dynamic: false,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct PipelineInfo {
+    /// Path to a GLSL source or precompiled SPIR-V vertex shader
     pub vertexshader: PathBuf,
+    /// Path to a GLSL source or precompiled SPIR-V fragment shader
     pub fragmentshader: PathBuf,
+    /// Tessellates the patches output by the vertex shader if Some, e.g; for displacement
+    /// mapping. Requires `DeviceFeatures::tessellation_shader`.
+    pub tessellation: Option<TessellationInfo>,
+    /// Path to a GLSL source or precompiled SPIR-V geometry shader run on each primitive after
+    /// the vertex and tessellation stages if Some, e.g; to emit the lines of a normal
+    /// visualization. Requires `DeviceFeatures::geometry_shader`.
+    pub geometryshader: Option<PathBuf>,
+    /// Draws with task and mesh shaders rather than the vertex shader if Some. Requires
+    /// `DeviceFeatures::mesh_shader`, and falls back to the vertex shader without it. See
+    /// `supported`.
+    pub mesh_shading: Option<MeshShadingInfo>,
+    /// Preprocessor defines used when compiling GLSL sources
+    pub defines: Vec<(String, String)>,
     pub vertex_binding: vk::VertexInputBindingDescription,
     pub vertex_attributes: &'static [vk::VertexInputAttributeDescription],
+    /// Reads per instance attributes from a second binding if Some, e.g; described by
+    /// `vertex_desc!` with the `INSTANCE` input rate
+    pub instance_binding: Option<vk::VertexInputBindingDescription>,
+    pub instance_attributes: &'static [vk::VertexInputAttributeDescription],
     pub samples: vk::SampleCountFlags,
-    pub extent: Extent,
     pub subpass: u32,
+    /// How the vertices are assembled into primitives
+    pub topology: vk::PrimitiveTopology,
     pub polygon_mode: vk::PolygonMode,
+    /// The width in pixels of line primitives and of polygons drawn with a line polygon mode.
+    /// Widths other than 1.0 require `DeviceFeatures::wide_lines`. See `supported`.
+    pub line_width: f32,
+    /// Set the line width with `CommandBuffer::set_line_width` when drawing rather than using
+    /// `line_width`
+    pub dynamic_line_width: bool,
+    /// The size in pixels of points, provided to the vertex shader through the specialization
+    /// constant `POINT_SIZE_CONSTANT_ID`. Sizes other than 1.0 require
+    /// `DeviceFeatures::large_points`. See `supported`.
+    pub point_size: f32,
     pub cull_mode: vk::CullModeFlags,
     pub front_face: vk::FrontFace,
+    /// How the fragment output is written to the color attachment
+    pub blend: BlendState,
+    /// Compares the fragment depth against the depth attachment
+    pub depth_compare: vk::CompareOp,
+    /// Write the fragment depth to the depth attachment when passing the depth test
+    pub depth_write: bool,
+    /// Enables the stencil test if Some
+    pub stencil: Option<StencilState>,
+    /// Shades fragments at a variable rate if Some. Requires
+    /// `DeviceFeatures::fragment_shading_rate`, and shades every pixel without it. See
+    /// `supported`.
+    pub shading_rate: Option<ShadingRateState>,
 }
 
 impl Default for PipelineInfo {
@@ -28,22 +225,268 @@ impl Default for PipelineInfo {
         Self {
             vertexshader: "".into(),
             fragmentshader: "".into(),
+            tessellation: None,
+            geometryshader: None,
+            mesh_shading: None,
+            defines: Vec::new(),
             vertex_binding: vk::VertexInputBindingDescription::default(),
             vertex_attributes: &[],
+            instance_binding: None,
+            instance_attributes: &[],
             samples: vk::SampleCountFlags::TYPE_1,
-            extent: (0, 0).into(),
             subpass: 0,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             polygon_mode: vk::PolygonMode::FILL,
+            line_width: 1.0,
+            dynamic_line_width: false,
+            point_size: 1.0,
             cull_mode: vk::CullModeFlags::BACK,
             front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            blend: BlendState::default(),
+            depth_compare: vk::CompareOp::LESS,
+            depth_write: true,
+            stencil: None,
+            shading_rate: None,
         }
     }
 }
 
+impl PipelineInfo {
+    /// Returns the info with the line width and point size clamped to the ranges supported by
+    /// the device, which are 1.0 without the wide lines and large points features. Fails if the
+    /// device does not support the shader stages of the info. The mesh shading stages are
+    /// dropped without mesh shader support, which leaves the vertex shader. Likewise, the
+    /// shading rate is dropped without fragment shading rate support and its fragment size
+    /// is clamped to the largest supported fragment.
+    pub fn supported(mut self, capabilities: &DeviceCapabilities) -> Result<Self, Error> {
+        if self.mesh_shading.is_some() && !capabilities.features.mesh_shader {
+            log::debug!("Mesh shaders are not enabled, using the vertex shader");
+            self.mesh_shading = None;
+        }
+
+        if self.shading_rate.is_some() && !capabilities.features.fragment_shading_rate {
+            log::debug!("Fragment shading rate is not enabled, shading every pixel");
+            self.shading_rate = None;
+        }
+
+        let shading_rate = self.shading_rate.map(|state| ShadingRateState {
+            fragment_size: Extent::new(
+                state
+                    .fragment_size
+                    .width
+                    .min(capabilities.max_fragment_size.width)
+                    .max(1),
+                state
+                    .fragment_size
+                    .height
+                    .min(capabilities.max_fragment_size.height)
+                    .max(1),
+            ),
+            attachment: state.attachment && capabilities.shading_rate_texel_size_range.is_some(),
+            ..state
+        });
+
+        if self.mesh_shading.is_some()
+            && (self.tessellation.is_some() || self.geometryshader.is_some())
+        {
+            return Err(Error::MeshShadingStages);
+        }
+
+        if self.geometryshader.is_some() && !capabilities.features.geometry_shader {
+            return Err(Error::MissingFeature("geometryShader"));
+        }
+
+        if let Some(tessellation) = &self.tessellation {
+            if !capabilities.features.tessellation_shader {
+                return Err(Error::MissingFeature("tessellationShader"));
+            }
+
+            if tessellation.patch_control_points == 0
+                || tessellation.patch_control_points > capabilities.max_tessellation_patch_size
+            {
+                return Err(Error::InvalidPatchSize {
+                    count: tessellation.patch_control_points,
+                    max: capabilities.max_tessellation_patch_size,
+                });
+            }
+        }
+
+        let [min_width, max_width] = capabilities.line_width_range;
+        let [min_size, max_size] = capabilities.point_size_range;
+
+        let line_width = self.line_width.max(min_width).min(max_width);
+        let point_size = self.point_size.max(min_size).min(max_size);
+
+        if (line_width - self.line_width).abs() > f32::EPSILON {
+            log::warn!(
+                "Line width {} is not supported, using {}",
+                self.line_width,
+                line_width
+            );
+        }
+
+        if (point_size - self.point_size).abs() > f32::EPSILON {
+            log::warn!(
+                "Point size {} is not supported, using {}",
+                self.point_size,
+                point_size
+            );
+        }
+
+        Ok(Self {
+            line_width,
+            point_size,
+            shading_rate,
+            ..self
+        })
+    }
+
+    /// Returns the path and stage of each shader in the order of the pipeline stages
+    fn stages(&self) -> Vec<(&Path, vk::ShaderStageFlags)> {
+        if let Some(mesh_shading) = &self.mesh_shading {
+            let mut stages = Vec::new();
+
+            if let Some(task_shader) = &mesh_shading.task_shader {
+                stages.push((task_shader.as_path(), TASK_STAGE));
+            }
+
+            stages.push((&mesh_shading.mesh_shader, MESH_STAGE));
+            stages.push((&self.fragmentshader, vk::ShaderStageFlags::FRAGMENT));
+            return stages;
+        }
+
+        let mut stages = vec![(self.vertexshader.as_path(), vk::ShaderStageFlags::VERTEX)];
+
+        if let Some(tessellation) = &self.tessellation {
+            stages.push((
+                &tessellation.control_shader,
+                vk::ShaderStageFlags::TESSELLATION_CONTROL,
+            ));
+            stages.push((
+                &tessellation.evaluation_shader,
+                vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+            ));
+        }
+
+        if let Some(geometryshader) = &self.geometryshader {
+            stages.push((geometryshader, vk::ShaderStageFlags::GEOMETRY));
+        }
+
+        stages.push((&self.fragmentshader, vk::ShaderStageFlags::FRAGMENT));
+        stages
+    }
+}
+
+/// The stencil test and operations of a single face
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StencilFace {
+    /// Performed when the stencil test fails
+    pub fail_op: StencilOp,
+    /// Performed when both the stencil and depth tests pass
+    pub pass_op: StencilOp,
+    /// Performed when the stencil test passes but the depth test fails
+    pub depth_fail_op: StencilOp,
+    /// Compares the masked reference against the masked stencil value
+    pub compare_op: vk::CompareOp,
+    /// The bits of the reference and stencil value that are compared
+    pub compare_mask: u32,
+    /// The bits of the stencil value that are written
+    pub write_mask: u32,
+}
+
+impl Default for StencilFace {
+    fn default() -> Self {
+        Self {
+            fail_op: StencilOp::KEEP,
+            pass_op: StencilOp::KEEP,
+            depth_fail_op: StencilOp::KEEP,
+            compare_op: vk::CompareOp::ALWAYS,
+            compare_mask: !0,
+            write_mask: !0,
+        }
+    }
+}
+
+impl StencilFace {
+    /// Writes the reference where the geometry passes the depth test, e.g; for marking the area
+    /// of a portal or the silhouette of an outlined object
+    pub fn mark() -> Self {
+        Self {
+            pass_op: StencilOp::REPLACE,
+            ..Default::default()
+        }
+    }
+
+    /// Passes where the stencil value equals the reference, e.g; for drawing inside a portal
+    pub fn equal() -> Self {
+        Self {
+            compare_op: vk::CompareOp::EQUAL,
+            ..Default::default()
+        }
+    }
+
+    /// Passes where the stencil value differs from the reference, e.g; for drawing an outline
+    /// around a marked silhouette
+    pub fn not_equal() -> Self {
+        Self {
+            compare_op: vk::CompareOp::NOT_EQUAL,
+            ..Default::default()
+        }
+    }
+
+    fn op_state(&self, reference: u32) -> vk::StencilOpState {
+        vk::StencilOpState {
+            fail_op: self.fail_op,
+            pass_op: self.pass_op,
+            depth_fail_op: self.depth_fail_op,
+            compare_op: self.compare_op,
+            compare_mask: self.compare_mask,
+            write_mask: self.write_mask,
+            reference,
+        }
+    }
+}
+
+/// Stencil test configuration of a pipeline. Requires a depth attachment format with a
+/// stencil component.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StencilState {
+    pub front: StencilFace,
+    pub back: StencilFace,
+    /// The value compared against and written by `StencilOp::REPLACE`.
+    /// Ignored if `dynamic_reference` is set.
+    pub reference: u32,
+    /// Set the reference with `CommandBuffer::set_stencil_reference` when drawing rather than
+    /// using the reference of the pipeline
+    pub dynamic_reference: bool,
+}
+
+impl StencilState {
+    /// Uses the same test and operations for front and back faces
+    pub fn new(face: StencilFace, reference: u32) -> Self {
+        Self {
+            front: face,
+            back: face,
+            reference,
+            dynamic_reference: false,
+        }
+    }
+}
+
+/// Describes what a pipeline will be rendering into
+enum RenderTarget<'a> {
+    RenderPass(&'a RenderPass),
+    Dynamic(&'a RenderingFormats),
+}
+
 pub struct Pipeline {
     device: Rc<Device>,
     pipeline: vk::Pipeline,
     layout: vk::PipelineLayout,
+    bindings: Vec<ShaderBinding>,
+    /// The task and mesh stages, empty for pipelines using the vertex shader
+    mesh_stages: vk::ShaderStageFlags,
+    attachment_formats: AttachmentFormats,
 }
 
 impl Pipeline {
@@ -53,57 +496,139 @@ impl Pipeline {
         renderpass: &RenderPass,
         info: PipelineInfo,
     ) -> Result<Self, Error> {
-        let mut vertexshader = File::open(info.vertexshader)?;
-        let mut fragmentshader = File::open(info.fragmentshader)?;
+        Self::create(
+            device,
+            layout_cache,
+            RenderTarget::RenderPass(renderpass),
+            info,
+        )
+    }
 
-        let vertexshader = ShaderModule::new(&device, &mut vertexshader)?;
-        let fragmentshader = ShaderModule::new(&device, &mut fragmentshader)?;
+    /// Creates a pipeline for use with dynamic rendering rather than a renderpass.
+    /// `info.subpass` is ignored.
+    pub fn new_dynamic(
+        device: Rc<Device>,
+        layout_cache: &mut DescriptorLayoutCache,
+        formats: &RenderingFormats,
+        info: PipelineInfo,
+    ) -> Result<Self, Error> {
+        Self::create(device, layout_cache, RenderTarget::Dynamic(formats), info)
+    }
 
-        let layout = shader::reflect(&device, &[&vertexshader, &fragmentshader], layout_cache)?;
+    fn create(
+        device: Rc<Device>,
+        layout_cache: &mut DescriptorLayoutCache,
+        target: RenderTarget,
+        info: PipelineInfo,
+    ) -> Result<Self, Error> {
+        let modules = info
+            .stages()
+            .into_iter()
+            .map(|(path, stage)| ShaderModule::load(&device, path, stage, &info.defines))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let vertex_attributes = info
+            .vertex_attributes
+            .iter()
+            .chain(info.instance_attributes)
+            .copied()
+            .collect::<Vec<_>>();
+
+        // Mesh shading pipelines have no vertex stage
+        if let Some(vertex) = modules
+            .iter()
+            .find(|module| module.stage == vk::ShaderStageFlags::VERTEX)
+        {
+            shader::check_vertex_input(vertex, &vertex_attributes)?;
+        }
+
+        let (layout, bindings) =
+            shader::reflect(&device, &modules.iter().collect::<Vec<_>>(), layout_cache)?;
 
         let entrypoint = CString::new("main").unwrap();
 
-        let shader_stages = [
-            vk::PipelineShaderStageCreateInfo::builder()
-                .module(vertexshader.module)
-                .stage(vk::ShaderStageFlags::VERTEX)
-                .name(&entrypoint)
-                .build(),
-            vk::PipelineShaderStageCreateInfo::builder()
-                .module(fragmentshader.module)
-                .stage(vk::ShaderStageFlags::FRAGMENT)
-                .name(&entrypoint)
-                .build(),
-        ];
-
-        let vertex_binding_descriptions = [info.vertex_binding];
-
-        // No vertices for now
+        // Entries of constants not declared by the shader are ignored
+        let point_size = info.point_size.to_ne_bytes();
+        let specialization_entries = [vk::SpecializationMapEntry {
+            constant_id: POINT_SIZE_CONSTANT_ID,
+            offset: 0,
+            size: point_size.len(),
+        }];
+
+        let vertex_specialization = vk::SpecializationInfo::builder()
+            .map_entries(&specialization_entries)
+            .data(&point_size);
+
+        let shader_stages = modules
+            .iter()
+            .map(|module| {
+                let create_info = vk::PipelineShaderStageCreateInfo::builder()
+                    .module(module.module)
+                    .stage(module.stage)
+                    .name(&entrypoint);
+
+                if module.stage == vk::ShaderStageFlags::VERTEX {
+                    create_info
+                        .specialization_info(&vertex_specialization)
+                        .build()
+                } else {
+                    create_info.build()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let vertex_binding_descriptions = std::iter::once(info.vertex_binding)
+            .chain(info.instance_binding)
+            .collect::<ArrayVec<[_; 2]>>();
+
         let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
             .vertex_binding_descriptions(&vertex_binding_descriptions)
-            .vertex_attribute_descriptions(&info.vertex_attributes);
+            .vertex_attribute_descriptions(&vertex_attributes);
+
+        let topology = match info.tessellation {
+            Some(_) => vk::PrimitiveTopology::PATCH_LIST,
+            None => info.topology,
+        };
 
         let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .topology(topology)
             .primitive_restart_enable(false);
 
-        let viewports = [vk::Viewport {
-            x: 0.0f32,
-            y: 0.0f32,
-            width: info.extent.width as _,
-            height: info.extent.height as _,
-            min_depth: 0.0f32,
-            max_depth: 1.0f32,
-        }];
-
-        let scissors = [vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: info.extent.into(),
-        }];
+        let tessellation_state = info.tessellation.as_ref().map(|tessellation| {
+            vk::PipelineTessellationStateCreateInfo::builder()
+                .patch_control_points(tessellation.patch_control_points)
+                .build()
+        });
 
+        // The viewport is set when drawing so that the pipeline can be used for any extent
         let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-            .viewports(&viewports)
-            .scissors(&scissors);
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+
+        if let Some(StencilState {
+            dynamic_reference: true,
+            ..
+        }) = info.stencil
+        {
+            dynamic_states.push(vk::DynamicState::STENCIL_REFERENCE);
+        }
+
+        if info.dynamic_line_width {
+            dynamic_states.push(vk::DynamicState::LINE_WIDTH);
+        }
+
+        if let Some(ShadingRateState { dynamic: true, .. }) = info.shading_rate {
+            dynamic_states.push(vk::DynamicState::FRAGMENT_SHADING_RATE_KHR);
+        }
+
+        let mut shading_rate_state = info.shading_rate.map(|state| {
+            shading_rate::pipeline_state(state.fragment_size.into(), state.attachment)
+        });
+
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
 
         let rasterizer = vk::PipelineRasterizationStateCreateInfo::builder()
             // Clamp pixels outside far and near
@@ -111,7 +636,7 @@ impl Pipeline {
             // If true: Discard all pixels
             .rasterizer_discard_enable(false)
             .polygon_mode(info.polygon_mode)
-            .line_width(1.0)
+            .line_width(info.line_width)
             .cull_mode(info.cull_mode)
             .front_face(info.front_face)
             .depth_bias_enable(false)
@@ -126,52 +651,96 @@ impl Pipeline {
             .alpha_to_coverage_enable(false)
             .alpha_to_one_enable(false);
 
-        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
-            .color_write_mask(
-                vk::ColorComponentFlags::R
-                    | vk::ColorComponentFlags::G
-                    | vk::ColorComponentFlags::B
-                    | vk::ColorComponentFlags::A,
-            )
-            .blend_enable(false)
-            .src_color_blend_factor(vk::BlendFactor::ONE)
-            .dst_color_blend_factor(vk::BlendFactor::ZERO)
-            .color_blend_op(vk::BlendOp::ADD)
-            .src_alpha_blend_factor(vk::BlendFactor::ONE)
-            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-            .alpha_blend_op(vk::BlendOp::ADD)
-            .build()];
+        // Depth only passes have no color attachments to blend into
+        let color_attachment_count = match &target {
+            RenderTarget::RenderPass(renderpass) => renderpass.color_attachment_count(info.subpass),
+            RenderTarget::Dynamic(formats) => formats.color_formats.len(),
+        };
+
+        let color_blend_attachments: Vec<vk::PipelineColorBlendAttachmentState> =
+            vec![(&info.blend).into(); color_attachment_count];
 
         let color_blending = vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op_enable(false)
             .attachments(&color_blend_attachments)
             .logic_op(vk::LogicOp::COPY);
 
+        let stencil = info
+            .stencil
+            .unwrap_or_else(|| StencilState::new(StencilFace::default(), 0));
+
         let depth_stencil = vk::PipelineDepthStencilStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
             depth_test_enable: vk::TRUE,
-            depth_write_enable: vk::TRUE,
-            depth_compare_op: vk::CompareOp::LESS,
+            depth_write_enable: info.depth_write as vk::Bool32,
+            depth_compare_op: info.depth_compare,
             depth_bounds_test_enable: vk::FALSE,
-            stencil_test_enable: vk::FALSE,
+            stencil_test_enable: info.stencil.is_some() as vk::Bool32,
+            front: stencil.front.op_state(stencil.reference),
+            back: stencil.back.op_state(stencil.reference),
             min_depth_bounds: 0.0,
             max_depth_bounds: 1.0,
             ..Default::default()
         };
 
-        let create_info = vk::GraphicsPipelineCreateInfo::builder()
+        let attachment_formats = match target {
+            RenderTarget::RenderPass(renderpass) => AttachmentFormats {
+                samples: info.samples,
+                ..renderpass.subpass_formats(info.subpass).clone()
+            },
+            RenderTarget::Dynamic(formats) => AttachmentFormats::dynamic(formats, info.samples),
+        };
+
+        let mut rendering_info = match target {
+            RenderTarget::Dynamic(formats) => Some(formats.create_info()),
+            RenderTarget::RenderPass(_) => None,
+        };
+
+        let mut create_info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&shader_stages)
-            .vertex_input_state(&vertex_input_info)
-            .input_assembly_state(&input_assembly)
             .viewport_state(&viewport_state)
             .rasterization_state(&rasterizer)
             .multisample_state(&multisampling)
             .color_blend_state(&color_blending)
             .depth_stencil_state(&depth_stencil)
-            .layout(layout)
-            .render_pass(renderpass.renderpass())
-            .subpass(info.subpass)
-            .build();
+            .dynamic_state(&dynamic_state)
+            .layout(layout);
+
+        // Mesh shading pipelines have no vertex input or input assembly
+        if info.mesh_shading.is_none() {
+            create_info = create_info
+                .vertex_input_state(&vertex_input_info)
+                .input_assembly_state(&input_assembly);
+        }
+
+        if let Some(tessellation_state) = &tessellation_state {
+            create_info = create_info.tessellation_state(tessellation_state);
+        }
+
+        if let RenderTarget::RenderPass(renderpass) = target {
+            create_info = create_info
+                .render_pass(renderpass.renderpass())
+                .subpass(info.subpass);
+        }
+
+        // Dynamic rendering pipelines specify the attachment formats instead of a renderpass
+        if let Some(rendering_info) = &mut rendering_info {
+            create_info = create_info.push_next(rendering_info);
+        }
+
+        if let Some(shading_rate_state) = &mut shading_rate_state {
+            create_info = create_info.push_next(shading_rate_state);
+        }
+
+        if let RenderTarget::Dynamic(RenderingFormats {
+            shading_rate_attachment: true,
+            ..
+        }) = target
+        {
+            create_info = create_info.flags(shading_rate::RENDERING_ATTACHMENT_PIPELINE_FLAG);
+        }
+
+        let create_info = create_info.build();
 
         let pipeline = unsafe {
             device
@@ -180,13 +749,24 @@ impl Pipeline {
         }[0];
 
         // Destroy shader modules
-        vertexshader.destroy(&device);
-        fragmentshader.destroy(&device);
+        for module in modules {
+            module.destroy(&device);
+        }
 
         Ok(Pipeline {
             device,
             pipeline,
             layout,
+            bindings,
+            mesh_stages: info
+                .stages()
+                .iter()
+                .map(|(_, stage)| *stage)
+                .filter(|stage| *stage == TASK_STAGE || *stage == MESH_STAGE)
+                .fold(vk::ShaderStageFlags::empty(), |stages, stage| {
+                    stages | stage
+                }),
+            attachment_formats,
         })
     }
 
@@ -199,6 +779,37 @@ impl Pipeline {
     pub fn layout(&self) -> vk::PipelineLayout {
         self.layout
     }
+
+    /// Returns the reflected descriptor bindings of all shader stages.
+    pub fn bindings(&self) -> &[ShaderBinding] {
+        &self.bindings
+    }
+
+    /// Returns the descriptor binding with the given variable or block name.
+    pub fn binding(&self, name: &str) -> Option<&ShaderBinding> {
+        self.bindings.iter().find(|binding| binding.name == name)
+    }
+
+    /// Returns true if the pipeline draws with task and mesh shaders, which are drawn with
+    /// `CommandBuffer::draw_mesh_tasks` rather than from vertex buffers.
+    pub fn uses_mesh_shading(&self) -> bool {
+        !self.mesh_stages.is_empty()
+    }
+
+    /// Returns the task and mesh stages of a mesh shading pipeline
+    pub fn mesh_stages(&self) -> vk::ShaderStageFlags {
+        self.mesh_stages
+    }
+
+    /// Returns the attachments of the subpass or dynamic rendering the pipeline was created for
+    pub fn attachment_formats(&self) -> &AttachmentFormats {
+        &self.attachment_formats
+    }
+
+    /// Returns true if any shader stage accesses descriptor set `set`.
+    pub fn uses_set(&self, set: u32) -> bool {
+        self.bindings.iter().any(|binding| binding.set == set)
+    }
 }
 
 impl AsRef<vk::Pipeline> for Pipeline {
@@ -225,6 +836,13 @@ impl Into<vk::PipelineLayout> for &Pipeline {
     }
 }
 
+impl DebugName for Pipeline {
+    fn set_debug_name(&self, context: &VulkanContext, name: &str) {
+        context.set_object_name(self.pipeline, name);
+        context.set_object_name(self.layout, name);
+    }
+}
+
 impl Drop for Pipeline {
     fn drop(&mut self) {
         unsafe { self.device.destroy_pipeline(self.pipeline, None) }
@@ -1,35 +1,83 @@
 use arrayvec::ArrayVec;
+use std::fs::File;
 use std::io::{Read, Seek};
+use std::path::Path;
 
 use crate::vulkan::descriptors;
 use ash::version::DeviceV1_0;
 use ash::{vk, Device};
 use descriptors::*;
 
-use crate::vulkan::Error;
+use crate::vulkan::{validation, Error};
 
-pub const MAX_SETS: usize = 4;
+use spirv_reflect::types::{ReflectDecorationFlags, ReflectFormat};
+
+use super::compiler;
+
+/// The maximum number of descriptor sets of a pipeline layout. Devices are only required to
+/// support binding 4, see `DeviceCapabilities::max_bound_descriptor_sets`.
+pub const MAX_SETS: usize = 10;
 pub const MAX_PUSH_CONSTANTS: usize = 4;
 
+/// A named descriptor binding of a shader, retained from reflection to validate resources
+/// bound by name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderBinding {
+    /// The name of the variable, or block type if the variable is unnamed
+    pub name: String,
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub stage_flags: vk::ShaderStageFlags,
+    /// The size in bytes of the uniform or storage block. Zero for other descriptor types.
+    pub size: vk::DeviceSize,
+}
+
 pub struct ShaderModule {
     pub reflect_module: spirv_reflect::ShaderModule,
-    // pub stage: vk::ShaderStageFlags,
+    /// The stage the module is used in, which the descriptor bindings are visible to
+    pub stage: vk::ShaderStageFlags,
     pub module: vk::ShaderModule,
 }
 
 impl ShaderModule {
     pub fn new<R: Read + Seek>(device: &Device, code: &mut R) -> Result<Self, Error> {
         let code = ash::util::read_spv(code)?;
+        Self::from_code(device, &code)
+    }
+
+    /// Loads a shader from disk. GLSL sources are compiled with `defines`, while the defines
+    /// are ignored for precompiled SPIR-V. `stage` takes precedence over the reflected stage,
+    /// which is unknown for stages newer than the reflection, e.g; mesh shaders.
+    pub fn load(
+        device: &Device,
+        path: &Path,
+        stage: vk::ShaderStageFlags,
+        defines: &[(String, String)],
+    ) -> Result<Self, Error> {
+        let module = if compiler::is_source(path) {
+            let code = compiler::compile_file(path, stage, defines)?;
+            Self::from_code(device, &code)?
+        } else {
+            Self::new(device, &mut File::open(path)?)?
+        };
+
+        Ok(Self { stage, ..module })
+    }
 
-        let create_info = vk::ShaderModuleCreateInfo::builder().code(&code);
+    pub fn from_code(device: &Device, code: &[u32]) -> Result<Self, Error> {
+        let create_info = vk::ShaderModuleCreateInfo::builder().code(code);
         let module = unsafe { device.create_shader_module(&create_info, None)? };
         let reflect_module = spirv_reflect::create_shader_module(unsafe {
             std::slice::from_raw_parts(create_info.p_code as *const u8, create_info.code_size)
         })
         .map_err(|msg| Error::SPVReflectError(msg))?;
 
+        let stage = vk::ShaderStageFlags::from_raw(reflect_module.get_shader_stage().bits());
+
         Ok(Self {
             module,
+            stage,
             reflect_module,
         })
     }
@@ -57,36 +105,56 @@ impl Into<vk::ShaderModule> for &ShaderModule {
     }
 }
 
-/// Creates a pipeline layout from shader reflection.
-pub fn reflect<S: AsRef<spirv_reflect::ShaderModule>>(
+/// Creates a pipeline layout from shader reflection. Returns the layout along with the
+/// descriptor bindings of all modules.
+pub fn reflect(
     device: &Device,
-    modules: &[S],
+    modules: &[&ShaderModule],
     layout_cache: &mut DescriptorLayoutCache,
-) -> Result<vk::PipelineLayout, Error> {
+) -> Result<(vk::PipelineLayout, Vec<ShaderBinding>), Error> {
     let mut sets: [DescriptorLayoutInfo; MAX_SETS] = Default::default();
+    let mut shader_bindings: Vec<ShaderBinding> = Vec::new();
 
     let mut push_constant_ranges: ArrayVec<[vk::PushConstantRange; MAX_PUSH_CONSTANTS]> =
         ArrayVec::new();
 
     for module in modules {
-        let module = module.as_ref();
-
-        let stage_flags = vk::ShaderStageFlags::from_raw(module.get_shader_stage().bits());
+        let stage_flags = module.stage;
         let bindings = module
+            .reflect_module
             .enumerate_descriptor_bindings(None)
             .map_err(|msg| Error::SPVReflectError(msg))?;
 
         for binding in bindings {
+            let descriptor_type = map_descriptortype(binding.descriptor_type);
+
             sets[binding.set as usize].add(descriptors::DescriptorSetBinding {
                 binding: binding.binding,
-                descriptor_type: map_descriptortype(binding.descriptor_type),
+                descriptor_type,
                 descriptor_count: binding.count,
                 stage_flags,
                 p_immutable_samplers: std::ptr::null(),
-            })
+            })?;
+
+            // Merge bindings used by several stages
+            match shader_bindings
+                .iter_mut()
+                .find(|b| b.set == binding.set && b.binding == binding.binding)
+            {
+                Some(existing) => existing.stage_flags |= stage_flags,
+                None => shader_bindings.push(ShaderBinding {
+                    name: binding_name(&binding),
+                    set: binding.set,
+                    binding: binding.binding,
+                    descriptor_type,
+                    stage_flags,
+                    size: binding.block.size as vk::DeviceSize,
+                }),
+            }
         }
 
         let push_constants = module
+            .reflect_module
             .enumerate_push_constant_blocks(None)
             .map_err(|msg| Error::SPVReflectError(msg))?;
 
@@ -99,9 +167,15 @@ pub fn reflect<S: AsRef<spirv_reflect::ShaderModule>>(
         }
     }
 
-    let set_layouts = sets
+    // Sets below the last used set which are not used by any stage get an empty layout, so
+    // that shaders can skip sets
+    let set_count = sets
+        .iter()
+        .rposition(|set| !set.bindings().is_empty())
+        .map_or(0, |last| last + 1);
+
+    let set_layouts = sets[..set_count]
         .iter_mut()
-        .take_while(|set| set.bindings().len() > 0)
         .map(|set| layout_cache.get(set))
         .collect::<Result<ArrayVec<[_; MAX_SETS]>, _>>()?;
 
@@ -114,8 +188,121 @@ pub fn reflect<S: AsRef<spirv_reflect::ShaderModule>>(
     };
 
     let pipeline_layout = unsafe { device.create_pipeline_layout(&create_info, None)? };
+    validation::register_pipeline_layout(pipeline_layout, &set_layouts);
+
+    Ok((pipeline_layout, shader_bindings))
+}
+
+/// Returns an error if an input of the vertex shader `module` has no attribute in `attributes`,
+/// or is read as another numeric type than the format of its attribute, which would otherwise
+/// read garbage. Inputs with fewer components than their attribute are allowed, and matrix
+/// inputs are only checked for an attribute at their first location.
+pub fn check_vertex_input(
+    module: &ShaderModule,
+    attributes: &[vk::VertexInputAttributeDescription],
+) -> Result<(), Error> {
+    let inputs = module
+        .reflect_module
+        .enumerate_input_variables(None)
+        .map_err(Error::SPVReflectError)?;
+
+    for input in inputs.iter().filter(|input| {
+        !input
+            .decoration_flags
+            .contains(ReflectDecorationFlags::BUILT_IN)
+    }) {
+        let attribute = attributes
+            .iter()
+            .find(|attribute| attribute.location == input.location)
+            .ok_or_else(|| Error::MissingVertexAttribute {
+                location: input.location,
+                name: input.name.clone(),
+            })?;
+
+        let expected = match reflect_numeric_type(input.format) {
+            Some(ty) => ty,
+            None => continue,
+        };
+
+        if numeric_type(attribute.format) != expected {
+            return Err(Error::VertexAttributeMismatch {
+                location: input.location,
+                name: input.name.clone(),
+                input: expected,
+                attribute: attribute.format,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the numeric type a vertex input of `format` is read as. None for matrices and other
+/// inputs without a single format.
+fn reflect_numeric_type(format: ReflectFormat) -> Option<&'static str> {
+    match format {
+        ReflectFormat::Undefined => None,
+        ReflectFormat::R32_UINT
+        | ReflectFormat::R32G32_UINT
+        | ReflectFormat::R32G32B32_UINT
+        | ReflectFormat::R32G32B32A32_UINT => Some("uint"),
+        ReflectFormat::R32_SINT
+        | ReflectFormat::R32G32_SINT
+        | ReflectFormat::R32G32B32_SINT
+        | ReflectFormat::R32G32B32A32_SINT => Some("int"),
+        ReflectFormat::R32_SFLOAT
+        | ReflectFormat::R32G32_SFLOAT
+        | ReflectFormat::R32G32B32_SFLOAT
+        | ReflectFormat::R32G32B32A32_SFLOAT => Some("float"),
+    }
+}
+
+/// Returns the numeric type of vertex attributes of `format`. Normalized and scaled formats are
+/// converted to floats.
+fn numeric_type(format: vk::Format) -> &'static str {
+    match format {
+        vk::Format::R8_UINT
+        | vk::Format::R8G8_UINT
+        | vk::Format::R8G8B8_UINT
+        | vk::Format::R8G8B8A8_UINT
+        | vk::Format::R16_UINT
+        | vk::Format::R16G16_UINT
+        | vk::Format::R16G16B16_UINT
+        | vk::Format::R16G16B16A16_UINT
+        | vk::Format::R32_UINT
+        | vk::Format::R32G32_UINT
+        | vk::Format::R32G32B32_UINT
+        | vk::Format::R32G32B32A32_UINT
+        | vk::Format::A2B10G10R10_UINT_PACK32 => "uint",
+        vk::Format::R8_SINT
+        | vk::Format::R8G8_SINT
+        | vk::Format::R8G8B8_SINT
+        | vk::Format::R8G8B8A8_SINT
+        | vk::Format::R16_SINT
+        | vk::Format::R16G16_SINT
+        | vk::Format::R16G16B16_SINT
+        | vk::Format::R16G16B16A16_SINT
+        | vk::Format::R32_SINT
+        | vk::Format::R32G32_SINT
+        | vk::Format::R32G32B32_SINT
+        | vk::Format::R32G32B32A32_SINT
+        | vk::Format::A2B10G10R10_SINT_PACK32 => "int",
+        _ => "float",
+    }
+}
+
+/// Returns the variable name of the binding. Falls back to the block type name for uniform and
+/// storage blocks declared without an instance name.
+fn binding_name(binding: &spirv_reflect::types::ReflectDescriptorBinding) -> String {
+    if !binding.name.is_empty() {
+        return binding.name.clone();
+    }
 
-    Ok(pipeline_layout)
+    binding
+        .type_description
+        .as_ref()
+        .map(|ty| ty.type_name.clone())
+        .unwrap_or_default()
 }
 
 // Maps descriptor type from spir-v reflect to ash::vk types
@@ -155,8 +342,9 @@ fn map_descriptortype(
         spirv_reflect::types::ReflectDescriptorType::InputAttachment => {
             vk::DescriptorType::INPUT_ATTACHMENT
         }
+        // Shares the SPIR-V type with the final KHR acceleration structures used by ray queries
         spirv_reflect::types::ReflectDescriptorType::AccelerationStructureNV => {
-            vk::DescriptorType::ACCELERATION_STRUCTURE_NV
+            vk::DescriptorType::ACCELERATION_STRUCTURE_KHR
         }
     }
 }
@@ -0,0 +1,611 @@
+//! Acceleration structures for ray queries through `VK_KHR_acceleration_structure` and
+//! `VK_KHR_ray_query`.
+//!
+//! Acceleration structures and their build inputs are referenced by device address. The
+//! allocator is not created with device address support, so these buffers are allocated
+//! directly from the device, see `AddressBuffer`.
+use std::ffi::c_void;
+use std::{mem, ptr, rc::Rc, slice};
+
+use ash::extensions::khr;
+use ash::version::{DeviceV1_0, DeviceV1_2, InstanceV1_1};
+use ash::vk;
+use ash::{Device, Instance};
+
+use super::commands::CommandBuffer;
+use super::{Error, VulkanContext};
+
+/// The device extensions required for ray queries on a Vulkan 1.2 device
+pub const RAY_QUERY_EXTENSIONS: &[&str] = &[
+    "VK_KHR_acceleration_structure",
+    "VK_KHR_ray_query",
+    "VK_KHR_deferred_host_operations",
+];
+
+/// An instance of a bottom level structure in a top level structure, as read by the device
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeometryInstance {
+    /// The first three rows of the row major transform from object to world space
+    pub transform: [f32; 12],
+    /// The custom index in the lower 24 bits and the visibility mask in the upper 8 bits
+    pub custom_index_and_mask: u32,
+    /// The hit group offset in the lower 24 bits and the instance flags in the upper 8 bits
+    pub sbt_offset_and_flags: u32,
+    /// The device address of the bottom level structure
+    pub acceleration_structure: vk::DeviceAddress,
+}
+
+impl GeometryInstance {
+    /// Places the bottom level structure with the column major object to world `model` matrix.
+    /// Triangles are hit from both sides regardless of their winding.
+    pub fn new(model: &[f32; 16], custom_index: u32, bottom_level: &AccelerationStructure) -> Self {
+        let mut transform = [0.0; 12];
+
+        for row in 0..3 {
+            for column in 0..4 {
+                transform[row * 4 + column] = model[column * 4 + row];
+            }
+        }
+
+        Self {
+            transform,
+            custom_index_and_mask: (custom_index & 0xFFFFFF) | 0xFF << 24,
+            sbt_offset_and_flags: vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE
+                .as_raw()
+                << 24,
+            acceleration_structure: bottom_level.address(),
+        }
+    }
+}
+
+/// Queries the acceleration structure and ray query features, and the acceleration structure
+/// properties of a Vulkan 1.2 device which supports `RAY_QUERY_EXTENSIONS`.
+pub fn query(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> (
+    vk::PhysicalDeviceAccelerationStructureFeaturesKHR,
+    vk::PhysicalDeviceRayQueryFeaturesKHR,
+    vk::PhysicalDeviceBufferDeviceAddressFeatures,
+    vk::PhysicalDeviceAccelerationStructurePropertiesKHR,
+) {
+    let mut address_features = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+
+    let mut ray_query_features = vk::PhysicalDeviceRayQueryFeaturesKHR {
+        p_next: &mut address_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+
+    let mut structure_features = vk::PhysicalDeviceAccelerationStructureFeaturesKHR {
+        p_next: &mut ray_query_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+
+    let mut features2 = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut structure_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+
+    let mut properties = vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
+    let mut properties2 = vk::PhysicalDeviceProperties2 {
+        p_next: &mut properties as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+
+    unsafe {
+        instance.get_physical_device_features2(physical_device, &mut features2);
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+    }
+
+    structure_features.p_next = ptr::null_mut();
+    ray_query_features.p_next = ptr::null_mut();
+    address_features.p_next = ptr::null_mut();
+    properties.p_next = ptr::null_mut();
+
+    (
+        structure_features,
+        ray_query_features,
+        address_features,
+        properties,
+    )
+}
+
+/// Loaded `VK_KHR_acceleration_structure` device commands.
+pub struct RayTracing {
+    loader: khr::AccelerationStructure,
+}
+
+impl RayTracing {
+    /// Loads the acceleration structure commands from device. The extensions must have been
+    /// enabled.
+    pub fn new(instance: &Instance, device: &Device) -> Self {
+        Self {
+            loader: khr::AccelerationStructure::new(instance, device),
+        }
+    }
+
+    // Returns the sizes of the structure and of the scratch buffer for building `info` with
+    // `max_primitive_count` primitives
+    fn build_sizes(
+        &self,
+        info: &vk::AccelerationStructureBuildGeometryInfoKHR,
+        max_primitive_count: u32,
+    ) -> vk::AccelerationStructureBuildSizesInfoKHR {
+        unsafe {
+            self.loader.get_acceleration_structure_build_sizes(
+                vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                info,
+                &[max_primitive_count],
+            )
+        }
+    }
+
+    // Records the build of `info` with a single geometry of `primitive_count` primitives
+    fn cmd_build(
+        &self,
+        commandbuffer: &CommandBuffer,
+        info: &vk::AccelerationStructureBuildGeometryInfoKHR,
+        primitive_count: u32,
+    ) {
+        let range = vk::AccelerationStructureBuildRangeInfoKHR {
+            primitive_count,
+            ..Default::default()
+        };
+
+        unsafe {
+            self.loader.cmd_build_acceleration_structures(
+                *commandbuffer.as_ref(),
+                slice::from_ref(info),
+                &[&[range]],
+            )
+        };
+    }
+}
+
+/// A buffer referenced by its device address. The memory is allocated directly from the device
+/// with the device address flag, and is host visible and coherent if created with data.
+pub struct AddressBuffer {
+    context: Rc<VulkanContext>,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    address: vk::DeviceAddress,
+}
+
+impl AddressBuffer {
+    /// Creates a device local buffer of `size` bytes
+    pub fn new(
+        context: Rc<VulkanContext>,
+        usage: vk::BufferUsageFlags,
+        size: vk::DeviceSize,
+    ) -> Result<Self, Error> {
+        Self::with_properties(context, usage, size, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+    }
+
+    /// Creates a host visible buffer holding `data`, e.g; the input of a single build
+    pub fn with_data<T: Copy>(
+        context: Rc<VulkanContext>,
+        usage: vk::BufferUsageFlags,
+        data: &[T],
+    ) -> Result<Self, Error> {
+        let size = mem::size_of_val(data) as vk::DeviceSize;
+
+        let buffer = Self::with_properties(
+            context,
+            usage,
+            size,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let device = buffer.context.device();
+
+        unsafe {
+            let mapped = device.map_memory(buffer.memory, 0, size, vk::MemoryMapFlags::empty())?;
+            let bytes = slice::from_raw_parts(data.as_ptr() as *const u8, size as usize);
+            ptr::copy_nonoverlapping(bytes.as_ptr(), mapped as *mut u8, bytes.len());
+            device.unmap_memory(buffer.memory);
+        }
+
+        Ok(buffer)
+    }
+
+    fn with_properties(
+        context: Rc<VulkanContext>,
+        usage: vk::BufferUsageFlags,
+        size: vk::DeviceSize,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<Self, Error> {
+        let device = context.device();
+        let allocation_callbacks = context.allocation_callbacks();
+
+        let buffer_info = vk::BufferCreateInfo {
+            size: size.max(1),
+            usage: usage | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+
+        let buffer = unsafe { device.create_buffer(&buffer_info, allocation_callbacks)? };
+        context.objects().created(vk::ObjectType::BUFFER);
+
+        let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+
+        let memory_properties = context.memory_properties();
+        let memory_type = (0..memory_properties.memory_type_count).find(|index| {
+            requirements.memory_type_bits & (1 << index) != 0
+                && memory_properties.memory_types[*index as usize]
+                    .property_flags
+                    .contains(properties)
+        });
+
+        let memory_type = match memory_type {
+            Some(memory_type) => memory_type,
+            None => {
+                unsafe { device.destroy_buffer(buffer, allocation_callbacks) };
+                context.objects().destroyed(vk::ObjectType::BUFFER);
+                return Err(Error::NoMemoryType(properties));
+            }
+        };
+
+        let mut flags_info = vk::MemoryAllocateFlagsInfo {
+            flags: vk::MemoryAllocateFlags::DEVICE_ADDRESS,
+            ..Default::default()
+        };
+
+        let allocate_info = vk::MemoryAllocateInfo {
+            p_next: &mut flags_info as *mut _ as *const c_void,
+            allocation_size: requirements.size,
+            memory_type_index: memory_type,
+            ..Default::default()
+        };
+
+        let memory = unsafe {
+            device
+                .allocate_memory(&allocate_info, allocation_callbacks)
+                .and_then(
+                    |memory| match device.bind_buffer_memory(buffer, memory, 0) {
+                        Ok(()) => Ok(memory),
+                        Err(e) => {
+                            device.free_memory(memory, allocation_callbacks);
+                            Err(e)
+                        }
+                    },
+                )
+        };
+
+        let memory = match memory {
+            Ok(memory) => memory,
+            Err(e) => {
+                unsafe { device.destroy_buffer(buffer, allocation_callbacks) };
+                context.objects().destroyed(vk::ObjectType::BUFFER);
+                return Err(e.into());
+            }
+        };
+
+        context.objects().created(vk::ObjectType::DEVICE_MEMORY);
+
+        let address = unsafe {
+            device.get_buffer_device_address(&vk::BufferDeviceAddressInfo {
+                buffer,
+                ..Default::default()
+            })
+        };
+
+        Ok(Self {
+            context,
+            buffer,
+            memory,
+            size,
+            address,
+        })
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    pub fn address(&self) -> vk::DeviceAddress {
+        self.address
+    }
+}
+
+impl Drop for AddressBuffer {
+    fn drop(&mut self) {
+        let device = self.context.device();
+        let allocation_callbacks = self.context.allocation_callbacks();
+
+        unsafe {
+            device.destroy_buffer(self.buffer, allocation_callbacks);
+            device.free_memory(self.memory, allocation_callbacks);
+        }
+
+        self.context.objects().destroyed(vk::ObjectType::BUFFER);
+        self.context
+            .objects()
+            .destroyed(vk::ObjectType::DEVICE_MEMORY);
+    }
+}
+
+/// A bottom level structure holding the triangles of a mesh, or a top level structure holding
+/// instances of bottom level structures. Destroyed when dropped.
+pub struct AccelerationStructure {
+    context: Rc<VulkanContext>,
+    acceleration_structure: vk::AccelerationStructureKHR,
+    address: vk::DeviceAddress,
+    /// The scratch size of a build of the top level structure, which is rebuilt in place
+    scratch_size: vk::DeviceSize,
+    _buffer: AddressBuffer,
+}
+
+impl AccelerationStructure {
+    /// Builds a bottom level structure of the opaque triangles of `indices` into `vertices`,
+    /// whose positions are three floats at the start of each vertex. Waits for the build to
+    /// complete.
+    pub fn bottom_level<V: Copy>(
+        context: Rc<VulkanContext>,
+        vertices: &[V],
+        indices: &[u32],
+    ) -> Result<Self, Error> {
+        let ray_tracing = context
+            .ray_tracing()
+            .ok_or(Error::MissingFeature("ray_query"))?;
+
+        let input_usage = vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR;
+        let vertex_buffer = AddressBuffer::with_data(context.clone(), input_usage, vertices)?;
+        let index_buffer = AddressBuffer::with_data(context.clone(), input_usage, indices)?;
+
+        let geometry = vk::AccelerationStructureGeometryKHR {
+            geometry_type: vk::GeometryTypeKHR::TRIANGLES,
+            geometry: vk::AccelerationStructureGeometryDataKHR {
+                triangles: vk::AccelerationStructureGeometryTrianglesDataKHR {
+                    vertex_format: vk::Format::R32G32B32_SFLOAT,
+                    vertex_data: vk::DeviceOrHostAddressConstKHR {
+                        device_address: vertex_buffer.address(),
+                    },
+                    vertex_stride: mem::size_of::<V>() as vk::DeviceSize,
+                    max_vertex: (vertices.len() as u32).saturating_sub(1),
+                    index_type: vk::IndexType::UINT32,
+                    index_data: vk::DeviceOrHostAddressConstKHR {
+                        device_address: index_buffer.address(),
+                    },
+                    ..Default::default()
+                },
+            },
+            flags: vk::GeometryFlagsKHR::OPAQUE,
+            ..Default::default()
+        };
+
+        let primitive_count = indices.len() as u32 / 3;
+
+        let (mut structure, mut info) = Self::create(
+            context.clone(),
+            ray_tracing,
+            vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL,
+            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE,
+            &geometry,
+            primitive_count,
+        )?;
+
+        let scratch = Self::scratch_buffer(context.clone(), structure.scratch_size)?;
+        info.scratch_data = vk::DeviceOrHostAddressKHR {
+            device_address: aligned_scratch_address(&context, &scratch),
+        };
+
+        context
+            .transfer_pool()
+            .single_time_command(context.graphics_queue(), |commandbuffer| {
+                ray_tracing.cmd_build(commandbuffer, &info, primitive_count)
+            })?;
+
+        // Only top level structures are rebuilt
+        structure.scratch_size = 0;
+
+        Ok(structure)
+    }
+
+    /// Creates a top level structure of up to `max_instances` instances, which is built by
+    /// `record_build`.
+    pub fn top_level(context: Rc<VulkanContext>, max_instances: u32) -> Result<Self, Error> {
+        let ray_tracing = context
+            .ray_tracing()
+            .ok_or(Error::MissingFeature("ray_query"))?;
+
+        let (structure, _) = Self::create(
+            context.clone(),
+            ray_tracing,
+            vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD,
+            &instances_geometry(0),
+            max_instances,
+        )?;
+
+        Ok(structure)
+    }
+
+    // Creates the structure with the size required to build `geometry`, and returns it along
+    // with the build info of the structure lacking the scratch address. The returned info
+    // points to `geometry`.
+    fn create(
+        context: Rc<VulkanContext>,
+        ray_tracing: &RayTracing,
+        ty: vk::AccelerationStructureTypeKHR,
+        flags: vk::BuildAccelerationStructureFlagsKHR,
+        geometry: &vk::AccelerationStructureGeometryKHR,
+        max_primitive_count: u32,
+    ) -> Result<(Self, vk::AccelerationStructureBuildGeometryInfoKHR), Error> {
+        let mut info = vk::AccelerationStructureBuildGeometryInfoKHR {
+            ty,
+            flags,
+            mode: vk::BuildAccelerationStructureModeKHR::BUILD,
+            geometry_count: 1,
+            p_geometries: geometry,
+            ..Default::default()
+        };
+
+        let sizes = ray_tracing.build_sizes(&info, max_primitive_count);
+
+        let buffer = AddressBuffer::new(
+            context.clone(),
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR,
+            sizes.acceleration_structure_size,
+        )?;
+
+        let create_info = vk::AccelerationStructureCreateInfoKHR {
+            buffer: buffer.buffer(),
+            size: sizes.acceleration_structure_size,
+            ty,
+            ..Default::default()
+        };
+
+        let acceleration_structure = unsafe {
+            ray_tracing
+                .loader
+                .create_acceleration_structure(&create_info, context.allocation_callbacks())?
+        };
+
+        context
+            .objects()
+            .created(vk::ObjectType::ACCELERATION_STRUCTURE_KHR);
+
+        let address_info = vk::AccelerationStructureDeviceAddressInfoKHR {
+            acceleration_structure,
+            ..Default::default()
+        };
+
+        let address = unsafe {
+            ray_tracing
+                .loader
+                .get_acceleration_structure_device_address(&address_info)
+        };
+
+        info.dst_acceleration_structure = acceleration_structure;
+
+        Ok((
+            Self {
+                context,
+                acceleration_structure,
+                address,
+                scratch_size: sizes.build_scratch_size,
+                _buffer: buffer,
+            },
+            info,
+        ))
+    }
+
+    /// Creates a scratch buffer large enough to build a structure needing `size` bytes of
+    /// scratch memory, including the alignment of the scratch address
+    pub fn scratch_buffer(
+        context: Rc<VulkanContext>,
+        size: vk::DeviceSize,
+    ) -> Result<AddressBuffer, Error> {
+        let alignment = context
+            .capabilities()
+            .acceleration_structure_scratch_alignment as vk::DeviceSize;
+
+        AddressBuffer::new(
+            context,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            size + alignment,
+        )
+    }
+
+    /// Records a rebuild of this top level structure from the `instance_count` instances at
+    /// `instances`, using `scratch` from `scratch_buffer` with the `scratch_size` of this
+    /// structure. The instances and scratch buffer need to be synchronized with the build
+    /// stage.
+    pub fn record_build(
+        &self,
+        commandbuffer: &CommandBuffer,
+        instances: vk::DeviceAddress,
+        instance_count: u32,
+        scratch: &AddressBuffer,
+    ) {
+        let ray_tracing = match self.context.ray_tracing() {
+            Some(ray_tracing) => ray_tracing,
+            None => return,
+        };
+
+        let geometry = instances_geometry(instances);
+
+        let info = vk::AccelerationStructureBuildGeometryInfoKHR {
+            ty: vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+            flags: vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD,
+            mode: vk::BuildAccelerationStructureModeKHR::BUILD,
+            dst_acceleration_structure: self.acceleration_structure,
+            geometry_count: 1,
+            p_geometries: &geometry,
+            scratch_data: vk::DeviceOrHostAddressKHR {
+                device_address: aligned_scratch_address(&self.context, scratch),
+            },
+            ..Default::default()
+        };
+
+        ray_tracing.cmd_build(commandbuffer, &info, instance_count);
+    }
+
+    pub fn handle(&self) -> vk::AccelerationStructureKHR {
+        self.acceleration_structure
+    }
+
+    /// Returns the device address referenced by instances of this structure
+    pub fn address(&self) -> vk::DeviceAddress {
+        self.address
+    }
+
+    /// Returns the scratch size of rebuilding this top level structure. Zero for bottom level
+    /// structures.
+    pub fn scratch_size(&self) -> vk::DeviceSize {
+        self.scratch_size
+    }
+}
+
+impl Drop for AccelerationStructure {
+    fn drop(&mut self) {
+        if let Some(ray_tracing) = self.context.ray_tracing() {
+            unsafe {
+                ray_tracing.loader.destroy_acceleration_structure(
+                    self.acceleration_structure,
+                    self.context.allocation_callbacks(),
+                )
+            };
+        }
+
+        self.context
+            .objects()
+            .destroyed(vk::ObjectType::ACCELERATION_STRUCTURE_KHR);
+    }
+}
+
+// Returns the geometry of tightly packed `GeometryInstance`s at `instances`
+fn instances_geometry(instances: vk::DeviceAddress) -> vk::AccelerationStructureGeometryKHR {
+    vk::AccelerationStructureGeometryKHR {
+        geometry_type: vk::GeometryTypeKHR::INSTANCES,
+        geometry: vk::AccelerationStructureGeometryDataKHR {
+            instances: vk::AccelerationStructureGeometryInstancesDataKHR {
+                array_of_pointers: vk::FALSE,
+                data: vk::DeviceOrHostAddressConstKHR {
+                    device_address: instances,
+                },
+                ..Default::default()
+            },
+        },
+        flags: vk::GeometryFlagsKHR::OPAQUE,
+        ..Default::default()
+    }
+}
+
+// Rounds the address of `scratch` up to the scratch alignment of the device, which is a power
+// of two
+fn aligned_scratch_address(context: &VulkanContext, scratch: &AddressBuffer) -> vk::DeviceAddress {
+    let alignment = (context
+        .capabilities()
+        .acceleration_structure_scratch_alignment as vk::DeviceAddress)
+        .max(1);
+
+    (scratch.address() + alignment - 1) & !(alignment - 1)
+}
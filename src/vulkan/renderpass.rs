@@ -1,5 +1,7 @@
 use std::rc::Rc;
 
+use super::texture::has_stencil;
+use super::validation::{self, AttachmentFormats};
 use super::{Error, Texture, TextureUsage};
 use arrayvec::ArrayVec;
 use ash::Device;
@@ -28,6 +30,7 @@ pub struct AttachmentInfo {
     /// What to do with existing attachment content.
     pub store: StoreOp,
     /// What to do with new attachment content.
+    /// The stencil component of depth stencil formats uses the same load and store operations.
     pub load: LoadOp,
     /// The expected image layout of loaded subpass contents
     /// Usually UNDEFINED unless using output of previous renderpass/subpass.
@@ -74,14 +77,20 @@ impl AttachmentInfo {
 
 impl Into<vk::AttachmentDescription> for &AttachmentInfo {
     fn into(self) -> vk::AttachmentDescription {
+        let (stencil_load_op, stencil_store_op) = if has_stencil(self.format) {
+            (self.load, self.store)
+        } else {
+            (LoadOp::DONT_CARE, StoreOp::DONT_CARE)
+        };
+
         vk::AttachmentDescription {
             flags: vk::AttachmentDescriptionFlags::default(),
             format: self.format,
             samples: self.samples,
             load_op: self.load,
             store_op: self.store,
-            stencil_load_op: LoadOp::DONT_CARE,
-            stencil_store_op: StoreOp::DONT_CARE,
+            stencil_load_op,
+            stencil_store_op,
             initial_layout: self.initial_layout,
             final_layout: self.final_layout,
         }
@@ -126,11 +135,19 @@ impl<'a, 'b> Into<vk::SubpassDescription> for &SubpassInfo<'a, 'b> {
 pub struct RenderPassInfo<'a, 'b, 'c, 'd> {
     pub attachments: &'a [AttachmentInfo],
     pub subpasses: &'b [SubpassInfo<'c, 'd>],
+    /// Renders each subpass to the layers of the attachments whose bits are set through
+    /// multiview, e.g; 0b11 for the two eyes of a stereo view. The attachments need as many
+    /// layers and the framebuffer a single layer. 0 disables multiview.
+    pub view_mask: u32,
 }
 
 pub struct RenderPass {
     device: Rc<Device>,
     renderpass: vk::RenderPass,
+    /// The format and samples of each attachment
+    attachments: ArrayVec<[(Format, SampleCountFlags); MAX_ATTACHMENTS]>,
+    /// The attachments of each subpass
+    subpasses: ArrayVec<[AttachmentFormats; MAX_SUBPASSES]>,
 }
 
 impl RenderPass {
@@ -148,11 +165,13 @@ impl RenderPass {
             .map(|subpass| subpass.into())
             .collect::<ArrayVec<[vk::SubpassDescription; MAX_SUBPASSES]>>();
 
+        // Waits for the attachment writes of earlier passes, which may use aliased memory
         let dependencies = [vk::SubpassDependency {
             src_subpass: vk::SUBPASS_EXTERNAL,
             dst_subpass: 0,
             src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
-                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
             src_access_mask: vk::AccessFlags::default(),
             dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
                 | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
@@ -161,19 +180,100 @@ impl RenderPass {
             dependency_flags: vk::DependencyFlags::default(),
         }];
 
-        let create_info = vk::RenderPassCreateInfo::builder()
+        let view_masks = info
+            .subpasses
+            .iter()
+            .map(|_| info.view_mask)
+            .collect::<ArrayVec<[u32; MAX_SUBPASSES]>>();
+
+        // The views are assumed to see mostly the same geometry, which lets the implementation
+        // render them concurrently
+        let correlation_masks = [info.view_mask];
+
+        let mut multiview_info = vk::RenderPassMultiviewCreateInfo::builder()
+            .view_masks(&view_masks)
+            .correlation_masks(&correlation_masks);
+
+        let mut create_info = vk::RenderPassCreateInfo::builder()
             .attachments(&vk_attachments)
             .subpasses(&vk_subpasses)
             .dependencies(&dependencies);
 
+        if info.view_mask != 0 {
+            create_info = create_info.push_next(&mut multiview_info);
+        }
+
         let renderpass = unsafe { device.create_render_pass(&create_info, None)? };
 
-        Ok(RenderPass { device, renderpass })
+        let attachments = info
+            .attachments
+            .iter()
+            .map(|attachment| (attachment.format, attachment.samples))
+            .collect::<ArrayVec<[_; MAX_ATTACHMENTS]>>();
+
+        let subpasses = info
+            .subpasses
+            .iter()
+            .map(|subpass| subpass_formats(info.attachments, subpass))
+            .collect::<ArrayVec<[_; MAX_SUBPASSES]>>();
+
+        validation::register_renderpass(renderpass, subpasses.to_vec());
+
+        Ok(RenderPass {
+            device,
+            renderpass,
+            attachments,
+            subpasses,
+        })
     }
 
     pub fn renderpass(&self) -> vk::RenderPass {
         self.renderpass
     }
+
+    /// Returns the number of color attachments written by `subpass`. Zero for depth only
+    /// passes.
+    pub fn color_attachment_count(&self, subpass: u32) -> usize {
+        self.subpasses[subpass as usize].color.len()
+    }
+
+    /// Returns the format and samples of each attachment, which the framebuffer attachments
+    /// need to match
+    pub fn attachments(&self) -> &[(Format, SampleCountFlags)] {
+        &self.attachments
+    }
+
+    /// Returns the attachments rendered to by `subpass`, which pipelines used in the subpass
+    /// need to be created for
+    pub fn subpass_formats(&self, subpass: u32) -> &AttachmentFormats {
+        &self.subpasses[subpass as usize]
+    }
+}
+
+fn subpass_formats(attachments: &[AttachmentInfo], subpass: &SubpassInfo) -> AttachmentFormats {
+    let color = subpass
+        .color_attachments
+        .iter()
+        .map(|reference| attachments[reference.attachment as usize].format)
+        .collect();
+
+    let depth = subpass
+        .depth_attachment
+        .map(|reference| &attachments[reference.attachment as usize]);
+
+    // All attachments of a subpass have the same number of samples
+    let samples = subpass
+        .color_attachments
+        .first()
+        .map(|reference| &attachments[reference.attachment as usize])
+        .or(depth)
+        .map_or(SampleCountFlags::TYPE_1, |attachment| attachment.samples);
+
+    AttachmentFormats {
+        color,
+        depth: depth.map_or(Format::UNDEFINED, |attachment| attachment.format),
+        samples,
+    }
 }
 
 impl Drop for RenderPass {
@@ -1,12 +1,14 @@
 use std::rc::Rc;
 
-use super::{Error, VulkanContext};
+use super::{DebugName, Error, VulkanContext};
 use ash::version::DeviceV1_0;
 use ash::vk;
 
 // Re-export enums
+pub use vk::CompareOp;
 pub use vk::Filter as FilterMode;
 pub use vk::SamplerAddressMode as AddressMode;
+pub use vk::SamplerMipmapMode as MipmapMode;
 
 /// Specification dictating how a sampler is created
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -20,56 +22,118 @@ pub struct SamplerInfo {
     pub min_filter: vk::Filter,
     /// Set to true to map from 0..size instead of 0..1
     pub unnormalized_coordinates: bool,
-    /// From 1.0 to 16.0
-    /// Anisotropy is automatically disabled if value is set to 1.0
-    pub anisotropy: f32,
-    /// Number of mipmapping levels to use
-    pub mip_levels: u32,
+    /// From 1.0 to 16.0, clamped to the device limit.
+    /// Anisotropy is automatically disabled if value is set to 1.0 or the device does not
+    /// support anisotropic filtering.
+    /// Uses the default anisotropy of the context if None
+    pub anisotropy: Option<f32>,
+    /// Filter mode used between mip levels
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// The most detailed mip level that can be sampled
+    pub min_lod: f32,
+    /// The least detailed mip level that can be sampled.
+    /// Defaults to `vk::LOD_CLAMP_NONE` which makes all mip levels of the image available
+    pub max_lod: f32,
+    /// Bias added to the computed level of detail. Negative values sharpen, positive values
+    /// blur. Clamped to the device limit
+    pub mip_lod_bias: f32,
+    /// Enables depth comparison against a reference value, e.g; for shadow samplers
+    pub compare_op: Option<vk::CompareOp>,
+}
+
+impl Default for SamplerInfo {
+    fn default() -> Self {
+        Self {
+            address_mode: AddressMode::REPEAT,
+            mag_filter: FilterMode::LINEAR,
+            min_filter: FilterMode::LINEAR,
+            unnormalized_coordinates: false,
+            anisotropy: None,
+            mipmap_mode: MipmapMode::LINEAR,
+            min_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+            mip_lod_bias: 0.0,
+            compare_op: None,
+        }
+    }
+}
+
+/// Validates and clamps `anisotropy` to the supported range of the device.
+/// Anisotropy is clamped to 1.0 if the feature is not enabled.
+pub fn clamp_anisotropy(context: &VulkanContext, anisotropy: f32) -> Result<f32, Error> {
+    if anisotropy.is_nan() || anisotropy < 1.0 {
+        return Err(Error::InvalidAnisotropy(anisotropy));
+    }
+
+    Ok(anisotropy.min(context.capabilities().max_sampler_anisotropy))
 }
 
 pub struct Sampler {
     context: Rc<VulkanContext>,
     sampler: vk::Sampler,
+    info: SamplerInfo,
 }
 
 impl Sampler {
     // Creates a new sampler from the specified sampling options
     pub fn new(context: Rc<VulkanContext>, info: SamplerInfo) -> Result<Self, Error> {
-        let max_anisotropy = info.anisotropy.max(context.limits().max_sampler_anisotropy);
+        let max_anisotropy = match info.anisotropy {
+            Some(anisotropy) => clamp_anisotropy(&context, anisotropy)?,
+            None => context.default_anisotropy(),
+        };
+
         let anisotropy_enable = if max_anisotropy > 1.0 {
             vk::TRUE
         } else {
             vk::FALSE
         };
 
+        let max_lod_bias = context.capabilities().max_sampler_lod_bias;
+        let mip_lod_bias = info.mip_lod_bias.max(-max_lod_bias).min(max_lod_bias);
+
         let create_info = vk::SamplerCreateInfo {
             s_type: vk::StructureType::SAMPLER_CREATE_INFO,
             p_next: std::ptr::null(),
             flags: vk::SamplerCreateFlags::default(),
             mag_filter: info.mag_filter,
             min_filter: info.min_filter,
-            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
-            min_lod: 0.0,
-            max_lod: info.mip_levels as f32,
+            mipmap_mode: info.mipmap_mode,
+            min_lod: info.min_lod,
+            max_lod: info.max_lod.max(info.min_lod),
             address_mode_u: info.address_mode,
             address_mode_v: info.address_mode,
             address_mode_w: info.address_mode,
-            mip_lod_bias: 0.0,
+            mip_lod_bias,
             anisotropy_enable,
             max_anisotropy,
-            compare_enable: vk::FALSE,
-            compare_op: vk::CompareOp::ALWAYS,
+            compare_enable: info.compare_op.is_some() as u32,
+            compare_op: info.compare_op.unwrap_or(vk::CompareOp::ALWAYS),
             border_color: vk::BorderColor::INT_OPAQUE_BLACK,
             unnormalized_coordinates: info.unnormalized_coordinates as u32,
         };
 
-        let sampler = unsafe { context.device().create_sampler(&create_info, None)? };
-        Ok(Self { context, sampler })
+        let sampler = unsafe {
+            context
+                .device()
+                .create_sampler(&create_info, context.allocation_callbacks())?
+        };
+        context.objects().created(vk::ObjectType::SAMPLER);
+
+        Ok(Self {
+            context,
+            sampler,
+            info,
+        })
     }
 
     pub fn sampler(&self) -> vk::Sampler {
         self.sampler
     }
+
+    /// Returns the sampling options the sampler was created with
+    pub fn info(&self) -> &SamplerInfo {
+        &self.info
+    }
 }
 
 impl AsRef<vk::Sampler> for Sampler {
@@ -84,10 +148,66 @@ impl Into<vk::Sampler> for &Sampler {
     }
 }
 
+/// Shares samplers created with identical options, e.g; between materials, which in turn allows
+/// materials sampling the same textures to share descriptor sets.
+pub struct SamplerCache {
+    context: Rc<VulkanContext>,
+    samplers: Vec<Rc<Sampler>>,
+}
+
+impl SamplerCache {
+    pub fn new(context: Rc<VulkanContext>) -> Self {
+        Self {
+            context,
+            samplers: Vec::new(),
+        }
+    }
+
+    /// Returns the sampler created from `info`, or creates it if it does not already exist.
+    /// Options without an anisotropy use the default anisotropy of the context at the time of
+    /// the call.
+    pub fn get(&mut self, info: SamplerInfo) -> Result<Rc<Sampler>, Error> {
+        let info = SamplerInfo {
+            anisotropy: Some(
+                info.anisotropy
+                    .unwrap_or_else(|| self.context.default_anisotropy()),
+            ),
+            ..info
+        };
+
+        if let Some(sampler) = self.samplers.iter().find(|sampler| sampler.info == info) {
+            return Ok(sampler.clone());
+        }
+
+        let sampler = Rc::new(Sampler::new(self.context.clone(), info)?);
+        self.samplers.push(sampler.clone());
+
+        Ok(sampler)
+    }
+
+    /// Returns the number of distinct samplers created
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+}
+
+impl DebugName for Sampler {
+    fn set_debug_name(&self, context: &VulkanContext, name: &str) {
+        context.set_object_name(self.sampler, name);
+    }
+}
+
 impl Drop for Sampler {
     fn drop(&mut self) {
         unsafe {
-            self.context.device().destroy_sampler(self.sampler, None);
+            self.context
+                .device()
+                .destroy_sampler(self.sampler, self.context.allocation_callbacks());
         }
+        self.context.objects().destroyed(vk::ObjectType::SAMPLER);
     }
 }
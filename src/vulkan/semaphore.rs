@@ -14,6 +14,19 @@ pub fn create(device: &Device) -> Result<vk::Semaphore, Error> {
     Ok(semaphore)
 }
 
+/// Creates a timeline semaphore starting at `initial_value`. Requires the
+/// `timeline_semaphores` device feature.
+pub fn create_timeline(device: &Device, initial_value: u64) -> Result<vk::Semaphore, Error> {
+    let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+        .semaphore_type(vk::SemaphoreType::TIMELINE)
+        .initial_value(initial_value);
+
+    let create_info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_info);
+
+    let semaphore = unsafe { device.create_semaphore(&create_info, None)? };
+    Ok(semaphore)
+}
+
 pub fn destroy(device: &Device, semaphore: vk::Semaphore) {
     unsafe { device.destroy_semaphore(semaphore, None) }
 }
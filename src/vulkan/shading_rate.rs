@@ -0,0 +1,166 @@
+//! Variable rate shading through `VK_KHR_fragment_shading_rate`, which shades a single fragment
+//! for a block of pixels. The rate is set per pipeline, per draw through dynamic state or per
+//! region of the framebuffer through a shading rate attachment. The attachment info of dynamic
+//! rendering is newer than the bundled vulkan headers, so it is declared here and the commands
+//! are loaded manually.
+//!
+//! Only dynamic rendering can use shading rate attachments, as renderpasses are created without
+//! `VK_KHR_create_renderpass2`.
+use std::ffi::{c_void, CStr};
+use std::{mem, ptr};
+
+use ash::version::{InstanceV1_0, InstanceV1_1};
+use ash::vk;
+use ash::{Device, Instance};
+
+/// The device extension providing variable rate shading
+pub const FRAGMENT_SHADING_RATE_EXTENSION: &str = "VK_KHR_fragment_shading_rate";
+
+/// The usage, layout, access and stage of images read as shading rate attachments
+pub const ATTACHMENT_USAGE: vk::ImageUsageFlags =
+    vk::ImageUsageFlags::FRAGMENT_SHADING_RATE_ATTACHMENT_KHR;
+pub const ATTACHMENT_LAYOUT: vk::ImageLayout =
+    vk::ImageLayout::FRAGMENT_SHADING_RATE_ATTACHMENT_OPTIMAL_KHR;
+pub const ATTACHMENT_READ: vk::AccessFlags =
+    vk::AccessFlags::FRAGMENT_SHADING_RATE_ATTACHMENT_READ_KHR;
+pub const ATTACHMENT_STAGE: vk::PipelineStageFlags =
+    vk::PipelineStageFlags::FRAGMENT_SHADING_RATE_ATTACHMENT_KHR;
+/// `VK_PIPELINE_CREATE_RENDERING_FRAGMENT_SHADING_RATE_ATTACHMENT_BIT_KHR`, required for
+/// dynamic rendering pipelines used with a shading rate attachment
+pub const RENDERING_ATTACHMENT_PIPELINE_FLAG: vk::PipelineCreateFlags =
+    vk::PipelineCreateFlags::from_raw(0x0020_0000);
+
+/// The format of shading rate attachments. Each texel holds the log2 of the fragment width in
+/// bits 2..3 and the log2 of the fragment height in bits 0..1.
+pub const ATTACHMENT_FORMAT: vk::Format = vk::Format::R8_UINT;
+
+const STRUCTURE_TYPE_RENDERING_FRAGMENT_SHADING_RATE_ATTACHMENT_INFO_KHR: i32 = 1000044006;
+
+/// Shades fragments of `fragment_size` pixels. The pipeline rate is replaced by the rate of the
+/// shading rate attachment if `attachment`.
+pub fn pipeline_state(
+    fragment_size: vk::Extent2D,
+    attachment: bool,
+) -> vk::PipelineFragmentShadingRateStateCreateInfoKHR {
+    vk::PipelineFragmentShadingRateStateCreateInfoKHR {
+        fragment_size,
+        combiner_ops: combiner_ops(attachment),
+        ..Default::default()
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct RenderingFragmentShadingRateAttachmentInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub image_view: vk::ImageView,
+    pub image_layout: vk::ImageLayout,
+    pub shading_rate_attachment_texel_size: vk::Extent2D,
+}
+
+impl RenderingFragmentShadingRateAttachmentInfoKHR {
+    /// Reads the rate of each `texel_size` block of pixels from `image_view`, which needs to be
+    /// in `ATTACHMENT_LAYOUT`.
+    pub fn new(image_view: vk::ImageView, texel_size: vk::Extent2D) -> Self {
+        Self {
+            s_type: vk::StructureType::from_raw(
+                STRUCTURE_TYPE_RENDERING_FRAGMENT_SHADING_RATE_ATTACHMENT_INFO_KHR,
+            ),
+            p_next: ptr::null(),
+            image_view,
+            image_layout: ATTACHMENT_LAYOUT,
+            shading_rate_attachment_texel_size: texel_size,
+        }
+    }
+}
+
+/// Returns the combiner ops of the primitive and attachment rates. The primitive rate is never
+/// written by the shaders and is ignored.
+fn combiner_ops(attachment: bool) -> [vk::FragmentShadingRateCombinerOpKHR; 2] {
+    if attachment {
+        [
+            vk::FragmentShadingRateCombinerOpKHR::KEEP,
+            vk::FragmentShadingRateCombinerOpKHR::REPLACE,
+        ]
+    } else {
+        [vk::FragmentShadingRateCombinerOpKHR::KEEP; 2]
+    }
+}
+
+type PfnCmdSetFragmentShadingRateKHR = unsafe extern "system" fn(
+    vk::CommandBuffer,
+    *const vk::Extent2D,
+    *const [vk::FragmentShadingRateCombinerOpKHR; 2],
+);
+
+/// Queries the fragment shading rate features and properties of a Vulkan 1.2 device which
+/// supports `FRAGMENT_SHADING_RATE_EXTENSION`.
+pub fn query(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> (
+    vk::PhysicalDeviceFragmentShadingRateFeaturesKHR,
+    vk::PhysicalDeviceFragmentShadingRatePropertiesKHR,
+) {
+    let mut features = vk::PhysicalDeviceFragmentShadingRateFeaturesKHR::default();
+    let mut features2 = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+
+    let mut properties = vk::PhysicalDeviceFragmentShadingRatePropertiesKHR::default();
+    let mut properties2 = vk::PhysicalDeviceProperties2 {
+        p_next: &mut properties as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+
+    unsafe {
+        instance.get_physical_device_features2(physical_device, &mut features2);
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+    }
+
+    features.p_next = ptr::null_mut();
+    properties.p_next = ptr::null_mut();
+
+    (features, properties)
+}
+
+/// Loaded `VK_KHR_fragment_shading_rate` device commands.
+pub struct FragmentShadingRate {
+    cmd_set_fragment_shading_rate: PfnCmdSetFragmentShadingRateKHR,
+}
+
+impl FragmentShadingRate {
+    /// Loads the fragment shading rate commands from device. The extension must have been
+    /// enabled.
+    pub fn new(instance: &Instance, device: &Device) -> Option<Self> {
+        unsafe {
+            let set = instance.get_device_proc_addr(
+                device.handle(),
+                CStr::from_bytes_with_nul_unchecked(b"vkCmdSetFragmentShadingRateKHR\0").as_ptr(),
+            )?;
+
+            Some(Self {
+                cmd_set_fragment_shading_rate: mem::transmute::<
+                    unsafe extern "system" fn(),
+                    PfnCmdSetFragmentShadingRateKHR,
+                >(set),
+            })
+        }
+    }
+
+    /// Sets the pipeline shading rate of subsequent draws with pipelines created with
+    /// `ShadingRateState::dynamic`. See `pipeline_state`.
+    pub fn set_fragment_shading_rate(
+        &self,
+        commandbuffer: vk::CommandBuffer,
+        fragment_size: vk::Extent2D,
+        attachment: bool,
+    ) {
+        let combiner_ops = combiner_ops(attachment);
+        unsafe {
+            (self.cmd_set_fragment_shading_rate)(commandbuffer, &fragment_size, &combiner_ops)
+        };
+    }
+}
@@ -14,11 +14,19 @@ pub fn create_loader(entry: &Entry, instance: &Instance) -> Surface {
 }
 
 /// Creates a vulkan surface from window
-pub fn create(instance: &Instance, window: &Window) -> Result<SurfaceKHR, Error> {
+pub fn create(
+    instance: &Instance,
+    window: &Window,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
+) -> Result<SurfaceKHR, Error> {
+    let allocation_callbacks = allocation_callbacks
+        .map(|callbacks| callbacks as *const vk::AllocationCallbacks as *const _)
+        .unwrap_or(std::ptr::null());
+
     let mut surface: u64 = 0_u64;
     let result = window.create_window_surface(
         instance.handle().as_raw() as _,
-        std::ptr::null(),
+        allocation_callbacks,
         &mut surface,
     );
 
@@ -29,6 +37,10 @@ pub fn create(instance: &Instance, window: &Window) -> Result<SurfaceKHR, Error>
     Ok(SurfaceKHR::from_raw(surface))
 }
 
-pub fn destroy(surface_loader: &Surface, surface: SurfaceKHR) {
-    unsafe { surface_loader.destroy_surface(surface, None) };
+pub fn destroy(
+    surface_loader: &Surface,
+    surface: SurfaceKHR,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
+) {
+    unsafe { surface_loader.destroy_surface(surface, allocation_callbacks) };
 }
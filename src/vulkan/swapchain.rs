@@ -11,6 +11,83 @@ use super::{Error, Extent, Texture, TextureInfo, VulkanContext};
 /// This is to allow inline allocation of per swapchain image resources through `ArrayVec`.
 pub const MAX_FRAMES: usize = 5;
 
+/// 8 bit SRGB formats supported by nearly all displays
+pub const SDR_SURFACE_FORMATS: &[vk::SurfaceFormatKHR] = &[
+    vk::SurfaceFormatKHR {
+        format: vk::Format::B8G8R8A8_SRGB,
+        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    },
+    vk::SurfaceFormatKHR {
+        format: vk::Format::R8G8B8A8_SRGB,
+        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    },
+];
+
+/// 10 bit formats using the HDR10 ST2084 (PQ) transfer function.
+/// Requires `VK_EXT_swapchain_colorspace`.
+pub const HDR10_SURFACE_FORMATS: &[vk::SurfaceFormatKHR] = &[
+    vk::SurfaceFormatKHR {
+        format: vk::Format::A2B10G10R10_UNORM_PACK32,
+        color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    },
+    vk::SurfaceFormatKHR {
+        format: vk::Format::A2R10G10B10_UNORM_PACK32,
+        color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    },
+];
+
+/// 16 bit floating point scRGB format with linear encoding.
+/// Requires `VK_EXT_swapchain_colorspace`.
+pub const SCRGB_SURFACE_FORMATS: &[vk::SurfaceFormatKHR] = &[vk::SurfaceFormatKHR {
+    format: vk::Format::R16G16B16A16_SFLOAT,
+    color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+}];
+
+/// Device extension allowing swapchains to control exclusive fullscreen. Only available on
+/// Windows.
+pub const FULL_SCREEN_EXCLUSIVE_EXTENSION: &str = "VK_EXT_full_screen_exclusive";
+
+/// Specifies swapchain creation preferences.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapchainInfo {
+    /// Surface formats and color spaces in order of preference. The first supported one is
+    /// used, otherwise the first format reported by the surface.
+    /// Note: shaders are responsible for outputting values encoded for the chosen color space.
+    pub preferred_formats: Vec<vk::SurfaceFormatKHR>,
+    /// Recreate the swapchain when it no longer matches the surface exactly, e.g; after the
+    /// display was rotated or the window moved to a monitor with a different scale. Otherwise
+    /// a suboptimal swapchain is used until it is out of date.
+    pub recreate_on_suboptimal: bool,
+    /// The preferred present mode, which falls back to FIFO if not supported by the surface.
+    /// Reselected whenever the swapchain is recreated, e.g; after moving to another monitor.
+    pub present_mode: vk::PresentModeKHR,
+    /// Allow the swapchain to acquire exclusive fullscreen when `VK_EXT_full_screen_exclusive`
+    /// is enabled. Otherwise exclusive fullscreen is disallowed, which keeps borderless windows
+    /// from being promoted by the driver.
+    pub full_screen_exclusive: bool,
+    /// Allow the images to be copied from, e.g; for capturing the presented frames. Ignored if
+    /// the surface does not support transfer source images.
+    pub transfer_src: bool,
+    /// The preferred minimum number of images, e.g; 2 for double buffering with lower latency or
+    /// 3 for triple buffering with higher throughput. Clamped to the counts supported by the
+    /// surface and `MAX_FRAMES`. One more than the minimum supported by the surface if None.
+    /// The surface may create more images, see `Swapchain::image_count`.
+    pub image_count: Option<u32>,
+}
+
+impl Default for SwapchainInfo {
+    fn default() -> Self {
+        Self {
+            preferred_formats: SDR_SURFACE_FORMATS.to_vec(),
+            recreate_on_suboptimal: true,
+            present_mode: vk::PresentModeKHR::IMMEDIATE,
+            full_screen_exclusive: false,
+            transfer_src: false,
+            image_count: None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SwapchainSupport {
     pub capabilities: vk::SurfaceCapabilitiesKHR,
@@ -41,13 +118,15 @@ pub fn query_support(
     })
 }
 
-fn pick_format(formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
-    for surface_format in formats {
-        // Preferred surface_format
-        if surface_format.format == vk::Format::B8G8R8A8_SRGB
-            && surface_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-        {
-            return *surface_format;
+/// Picks the first of `preferred` that is available
+/// Otherwise, the first available format is returned
+fn pick_format(
+    formats: &[vk::SurfaceFormatKHR],
+    preferred: &[vk::SurfaceFormatKHR],
+) -> vk::SurfaceFormatKHR {
+    for preferred in preferred {
+        if formats.contains(preferred) {
+            return *preferred;
         }
     }
 
@@ -71,6 +150,32 @@ fn pick_present_mode(
     return vk::PresentModeKHR::FIFO;
 }
 
+/// Picks the minimum number of images
+/// If `preferred` is None, one more than the minimum supported is used
+/// The count is clamped to the supported counts and `MAX_FRAMES`
+fn pick_image_count(capabilities: &vk::SurfaceCapabilitiesKHR, preferred: Option<u32>) -> u32 {
+    let count = preferred.unwrap_or(capabilities.min_image_count + 1);
+
+    let mut max = MAX_FRAMES as u32;
+
+    // Zero is no limit
+    if capabilities.max_image_count != 0 {
+        max = cmp::min(max, capabilities.max_image_count);
+    }
+
+    let image_count = count.max(capabilities.min_image_count).min(max);
+
+    if preferred.is_some() && image_count != count {
+        log::warn!(
+            "{} swapchain images are not supported, using {}",
+            count,
+            image_count
+        );
+    }
+
+    image_count
+}
+
 fn pick_extent(window: &glfw::Window, capabilities: &vk::SurfaceCapabilitiesKHR) -> Extent {
     // The extent of the surface needs to match exactly
     if capabilities.current_extent.width != std::u32::MAX {
@@ -78,19 +183,10 @@ fn pick_extent(window: &glfw::Window, capabilities: &vk::SurfaceCapabilitiesKHR)
     }
 
     // Freely choose extent based on window and min-max capabilities
-    let (width, height) = window.get_framebuffer_size();
-
-    let width = cmp::max(
-        capabilities.min_image_extent.width,
-        cmp::min(capabilities.max_image_extent.width, width as u32),
-    );
-
-    let height = cmp::max(
-        capabilities.min_image_extent.height,
-        cmp::min(capabilities.max_image_extent.height, height as u32),
-    );
-
-    (width, height).into()
+    Extent::from(window.get_framebuffer_size()).clamp(
+        capabilities.min_image_extent.into(),
+        capabilities.max_image_extent.into(),
+    )
 }
 
 pub fn create_loader(instance: &Instance, device: &Device) -> SwapchainLoader {
@@ -100,11 +196,13 @@ pub fn create_loader(instance: &Instance, device: &Device) -> SwapchainLoader {
 /// High level swapchain representation
 /// Implements Drop
 pub struct Swapchain {
+    context: Rc<VulkanContext>,
     swapchain_loader: Rc<SwapchainLoader>,
     swapchain_khr: vk::SwapchainKHR,
     images: Vec<Texture>,
     extent: Extent,
     surface_format: vk::SurfaceFormatKHR,
+    usage: vk::ImageUsageFlags,
 }
 
 impl Swapchain {
@@ -112,6 +210,7 @@ impl Swapchain {
         context: Rc<VulkanContext>,
         swapchain_loader: Rc<SwapchainLoader>,
         window: &glfw::Window,
+        info: &SwapchainInfo,
     ) -> Result<Self, Error> {
         let support = query_support(
             context.surface_loader(),
@@ -119,13 +218,7 @@ impl Swapchain {
             context.physical_device(),
         )?;
 
-        // Use one more image than the minumum supported
-        let mut image_count = (support.capabilities.min_image_count + 1).min(MAX_FRAMES as u32);
-
-        // Make sure max image count isn't exceeded
-        if support.capabilities.max_image_count != 0 {
-            image_count = cmp::min(image_count, support.capabilities.max_image_count);
-        }
+        let image_count = pick_image_count(&support.capabilities, info.image_count);
 
         // The full set
         let queue_family_indices = [
@@ -141,21 +234,44 @@ impl Swapchain {
                 (vk::SharingMode::CONCURRENT, &queue_family_indices)
             };
 
-        let surface_format = pick_format(&support.formats);
+        let surface_format = pick_format(&support.formats, &info.preferred_formats);
+        log::debug!(
+            "Surface format: {:?}, color space: {:?}",
+            surface_format.format,
+            surface_format.color_space
+        );
 
-        let present_mode = pick_present_mode(&support.present_modes, vk::PresentModeKHR::IMMEDIATE);
+        let present_mode = pick_present_mode(&support.present_modes, info.present_mode);
+        log::debug!("Present mode: {:?}", present_mode);
 
         let extent = pick_extent(window, &support.capabilities);
 
-        let create_info = vk::SwapchainCreateInfoKHR::builder()
+        // For now, render directly to the images
+        let mut usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        if info.transfer_src
+            && support
+                .capabilities
+                .supported_usage_flags
+                .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+
+        let mut full_screen_exclusive = vk::SurfaceFullScreenExclusiveInfoEXT::builder()
+            .full_screen_exclusive(if info.full_screen_exclusive {
+                vk::FullScreenExclusiveEXT::ALLOWED
+            } else {
+                vk::FullScreenExclusiveEXT::DISALLOWED
+            });
+
+        let mut create_info = vk::SwapchainCreateInfoKHR::builder()
             .surface(context.surface())
             .min_image_count(image_count)
             .image_format(surface_format.format)
             .image_color_space(surface_format.color_space)
             .image_extent(extent.into())
             .image_array_layers(1)
-            // For now, render directly to the images
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(usage)
             .image_sharing_mode(sharing_mode)
             .queue_family_indices(queue_family_indices)
             .pre_transform(support.capabilities.current_transform)
@@ -164,9 +280,17 @@ impl Swapchain {
             .clipped(true)
             .old_swapchain(vk::SwapchainKHR::null());
 
-        let swapchain_khr = unsafe { swapchain_loader.create_swapchain(&create_info, None)? };
+        if context.capabilities().full_screen_exclusive {
+            create_info = create_info.push_next(&mut full_screen_exclusive);
+        }
+
+        let swapchain_khr = unsafe {
+            swapchain_loader.create_swapchain(&create_info, context.allocation_callbacks())?
+        };
+        context.objects().created(vk::ObjectType::SWAPCHAIN_KHR);
 
         let images = unsafe { swapchain_loader.get_swapchain_images(swapchain_khr)? };
+        log::debug!("Image count: {}, requested: {}", images.len(), image_count);
 
         let image_info = TextureInfo {
             extent,
@@ -174,6 +298,7 @@ impl Swapchain {
             usage: super::TextureUsage::ColorAttachment,
             format: surface_format.format,
             samples: vk::SampleCountFlags::TYPE_1,
+            ..Default::default()
         };
 
         let images = images
@@ -182,16 +307,20 @@ impl Swapchain {
             .collect::<Result<_, _>>()?;
 
         Ok(Swapchain {
+            context,
             swapchain_khr,
             images,
             surface_format,
             swapchain_loader,
             extent,
+            usage,
         })
     }
 
-    pub fn next_image(&self, semaphore: vk::Semaphore) -> Result<u32, vk::Result> {
-        let (image_index, _) = unsafe {
+    /// Acquires the next image and returns its index along with whether the swapchain is
+    /// suboptimal for the surface. The image is acquired even if suboptimal.
+    pub fn next_image(&self, semaphore: vk::Semaphore) -> Result<(u32, bool), vk::Result> {
+        let (image_index, suboptimal) = unsafe {
             self.swapchain_loader.acquire_next_image(
                 self.swapchain_khr,
                 std::u64::MAX,
@@ -200,7 +329,7 @@ impl Swapchain {
             )?
         };
 
-        Ok(image_index)
+        Ok((image_index, suboptimal))
     }
 
     pub fn present(
@@ -229,6 +358,11 @@ impl Swapchain {
         self.images.len() as u32
     }
 
+    /// Returns how the images may be used
+    pub fn usage(&self) -> vk::ImageUsageFlags {
+        self.usage
+    }
+
     pub fn image_format(&self) -> vk::Format {
         self.surface_format.format
     }
@@ -237,6 +371,23 @@ impl Swapchain {
         self.surface_format
     }
 
+    /// Returns the color space the presentation engine interprets the images in
+    pub fn color_space(&self) -> vk::ColorSpaceKHR {
+        self.surface_format.color_space
+    }
+
+    /// Returns true if the chosen color space is a HDR color space
+    pub fn is_hdr(&self) -> bool {
+        match self.surface_format.color_space {
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT
+            | vk::ColorSpaceKHR::HDR10_HLG_EXT
+            | vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT
+            | vk::ColorSpaceKHR::BT2020_LINEAR_EXT
+            | vk::ColorSpaceKHR::DOLBYVISION_EXT => true,
+            _ => false,
+        }
+    }
+
     pub fn extent(&self) -> Extent {
         self.extent
     }
@@ -256,7 +407,10 @@ impl Drop for Swapchain {
         // Destroy the swapchain
         unsafe {
             self.swapchain_loader
-                .destroy_swapchain(self.swapchain_khr, None);
+                .destroy_swapchain(self.swapchain_khr, self.context.allocation_callbacks());
         };
+        self.context
+            .objects()
+            .destroyed(vk::ObjectType::SWAPCHAIN_KHR);
     }
 }
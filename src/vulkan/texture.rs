@@ -1,11 +1,17 @@
-use std::{path::Path, rc::Rc};
+use std::{cell::Cell, path::Path, rc::Rc};
 
 use ash::version::DeviceV1_0;
 use ash::vk;
 
-use super::{buffer, commands::*, context::VulkanContext, extent::Extent, Error};
+use super::{
+    barrier::ImageBarrier, buffer, commands::*, context::VulkanContext, extent::Extent,
+    memory::AliasedMemory, mipmap, shading_rate, Buffer, BufferType, BufferUsage, DebugName, Error,
+    QueueSharing,
+};
 
+pub use super::mipmap::MipGeneration;
 pub use vk::Format;
+pub use vk::ImageViewType;
 pub use vk::SampleCountFlags;
 
 /// Specifies texture creation info.
@@ -21,6 +27,15 @@ pub struct TextureInfo {
     /// The pixel format.
     pub format: Format,
     pub samples: SampleCountFlags,
+    /// How the texture is shared between the graphics and transfer queue families.
+    pub sharing: QueueSharing,
+    /// The number of array layers, e.g; one for each cascade of a shadow map.
+    pub array_layers: u32,
+    /// How the layers are viewed when sampled. Textures with several layers need an array
+    /// view type.
+    pub view_type: ImageViewType,
+    /// How the mip levels are generated when the texture is written
+    pub mip_generation: MipGeneration,
 }
 
 impl Default for TextureInfo {
@@ -31,18 +46,182 @@ impl Default for TextureInfo {
             usage: TextureUsage::Sampled,
             format: Format::R8G8B8A8_SRGB,
             samples: SampleCountFlags::TYPE_1,
+            sharing: QueueSharing::Exclusive,
+            array_layers: 1,
+            view_type: ImageViewType::TYPE_2D,
+            mip_generation: MipGeneration::Blit,
         }
     }
 }
 
+/// How the 8 bit color values of a loaded texture are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Values are sRGB encoded and converted to linear when sampled. Used for color textures,
+    /// e.g; albedo.
+    Srgb,
+    /// Values are sampled as is. Used for non-color data, e.g; normal maps, roughness and
+    /// height maps.
+    Linear,
+}
+
+impl ColorSpace {
+    /// Returns the 8 bit RGBA format of the color space
+    pub fn format(&self) -> Format {
+        match self {
+            ColorSpace::Srgb => Format::R8G8B8A8_SRGB,
+            ColorSpace::Linear => Format::R8G8B8A8_UNORM,
+        }
+    }
+
+    /// Returns the color space of an 8 bit color format, or None for other formats
+    pub fn of(format: Format) -> Option<Self> {
+        match format {
+            Format::R8G8B8A8_SRGB | Format::B8G8R8A8_SRGB | Format::R8G8B8_SRGB => {
+                Some(ColorSpace::Srgb)
+            }
+            Format::R8G8B8A8_UNORM | Format::B8G8R8A8_UNORM | Format::R8G8B8_UNORM => {
+                Some(ColorSpace::Linear)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for ColorSpace {
+    fn default() -> Self {
+        ColorSpace::Srgb
+    }
+}
+
+/// How the float pixels of Radiance HDR and OpenEXR images are stored when loaded. 8 bit images,
+/// e.g; PNG, JPEG and TGA, are always stored as 8 bit RGBA in the color space of the load info.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelConversion {
+    /// Stored as 16 bit floats, which all devices can filter and blit for mipmapping
+    HalfFloat,
+    /// Stored as 32 bit floats if the device can filter them, and as 16 bit floats otherwise
+    FullFloat,
+    /// Clamped to [0, 1] and stored as 8 bit RGBA in the color space, e.g; for HDR images used
+    /// as albedo
+    Clamp,
+}
+
+impl Default for PixelConversion {
+    fn default() -> Self {
+        PixelConversion::HalfFloat
+    }
+}
+
+/// Specifies how an image file is loaded into a texture
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TextureLoadInfo {
+    /// How 8 bit values are interpreted. Float values are always linear.
+    pub color_space: ColorSpace,
+    pub conversion: PixelConversion,
+    /// Compute generation averages sRGB textures in linear space rather than blitting
+    pub mip_generation: MipGeneration,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextureUsage {
     /// The most common usage. Texture is sampled in shader and transferred from CPU rarely.
     Sampled,
-    /// Texture is used as a color attachment. Lazily allocates image when possible.
+    /// Texture is used as a transient color attachment. Contents can not be read outside the
+    /// renderpass. Lazily allocates image when possible.
     ColorAttachment,
-    /// Texture is used as a depth attachment. Lazily allocates image when possible.
+    /// Texture is used as a transient depth attachment. Contents can not be read outside the
+    /// renderpass. Lazily allocates image when possible.
     DepthAttachment,
+    /// Texture is used as a color attachment whose contents can be copied back to the CPU.
+    ReadbackAttachment,
+    /// Texture is rendered to as a color attachment and sampled in shaders or copied afterwards,
+    /// e.g; by materials.
+    RenderTarget,
+    /// Texture is read and written to in shaders through image load/store, e.g; by a compute
+    /// shader. Can also be sampled. Should be in GENERAL layout when accessed as storage.
+    /// Note: SRGB formats are usually not supported for storage images.
+    Storage,
+    /// Texture is rendered to as a depth attachment and sampled in shaders afterwards, e.g; as
+    /// a shadow map sampled with a comparison sampler.
+    ShadowMap,
+    /// Texture is read as the shading rate attachment of dynamic rendering and written from the
+    /// CPU, see `shading_rate`.
+    ShadingRate,
+}
+
+impl TextureUsage {
+    /// Returns the image aspects of a texture with this usage and `format`. Depth attachments
+    /// include the stencil aspect if the format has a stencil component.
+    pub fn aspect_mask(&self, format: vk::Format) -> vk::ImageAspectFlags {
+        match self {
+            TextureUsage::Sampled => vk::ImageAspectFlags::COLOR,
+            TextureUsage::ColorAttachment => vk::ImageAspectFlags::COLOR,
+            TextureUsage::DepthAttachment if has_stencil(format) => {
+                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+            }
+            TextureUsage::DepthAttachment => vk::ImageAspectFlags::DEPTH,
+            TextureUsage::ReadbackAttachment => vk::ImageAspectFlags::COLOR,
+            TextureUsage::RenderTarget => vk::ImageAspectFlags::COLOR,
+            TextureUsage::Storage => vk::ImageAspectFlags::COLOR,
+            // Only the depth aspect can be sampled
+            TextureUsage::ShadowMap => vk::ImageAspectFlags::DEPTH,
+            TextureUsage::ShadingRate => vk::ImageAspectFlags::COLOR,
+        }
+    }
+
+    /// Returns true if the texture is only accessed as an attachment within a renderpass and
+    /// thus can be backed by lazily allocated memory
+    pub fn is_transient(&self) -> bool {
+        match self {
+            TextureUsage::ColorAttachment | TextureUsage::DepthAttachment => true,
+            _ => false,
+        }
+    }
+
+    fn image_usage(&self) -> vk::ImageUsageFlags {
+        match self {
+            TextureUsage::Sampled => {
+                vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED
+            }
+            TextureUsage::ColorAttachment => {
+                vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT
+            }
+            TextureUsage::DepthAttachment => {
+                vk::ImageUsageFlags::TRANSIENT_ATTACHMENT
+                    | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+            }
+            TextureUsage::ReadbackAttachment => {
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC
+            }
+            TextureUsage::RenderTarget => {
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+            }
+            TextureUsage::Storage => {
+                vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_DST
+            }
+            TextureUsage::ShadowMap => {
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+            }
+            TextureUsage::ShadingRate => {
+                shading_rate::ATTACHMENT_USAGE | vk::ImageUsageFlags::TRANSFER_DST
+            }
+        }
+    }
+}
+
+/// Describes how the memory of a texture's image is owned
+enum TextureMemory {
+    /// The image is owned elsewhere, e.g; by the swapchain
+    External,
+    /// The image and its allocation is owned by the texture
+    Owned(vk_mem::Allocation),
+    /// The image is owned by the texture, but bound to memory shared with other textures
+    Aliased(Rc<AliasedMemory>),
 }
 
 // Represents a texture combining an image and image view. A texture also stores its own width,
@@ -53,12 +232,16 @@ pub struct Texture {
     image: vk::Image,
     image_view: vk::ImageView,
     format: vk::Format,
-    // May not necessarily own the allocation
-    allocation: Option<vk_mem::Allocation>,
+    memory: TextureMemory,
     extent: Extent,
     mip_levels: u32,
+    array_layers: u32,
     samples: vk::SampleCountFlags,
     usage: TextureUsage,
+    sharing: QueueSharing,
+    mip_generation: MipGeneration,
+    // The last known layout of all mip levels
+    layout: Cell<vk::ImageLayout>,
 }
 
 impl Texture {
@@ -66,20 +249,166 @@ impl Texture {
     /// Uses the width and height of the loaded image, no resizing.
     /// Uses mipmapping.
     pub fn load<P: AsRef<Path>>(context: Rc<VulkanContext>, path: P) -> Result<Self, Error> {
-        let image =
-            stb::Image::load(&path, 4).ok_or(Error::ImageError(path.as_ref().to_owned()))?;
+        Self::load_with_color_space(context, path, ColorSpace::Srgb)
+    }
+
+    /// Loads a texture from an image file whose values are interpreted in `color_space`.
+    /// Non-color data such as normal maps need to be loaded with `ColorSpace::Linear`.
+    pub fn load_with_color_space<P: AsRef<Path>>(
+        context: Rc<VulkanContext>,
+        path: P,
+        color_space: ColorSpace,
+    ) -> Result<Self, Error> {
+        Self::load_with_info(
+            context,
+            path,
+            TextureLoadInfo {
+                color_space,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Loads a mipmapped texture from an image file. OpenEXR (.exr) and Radiance HDR images are
+    /// loaded as float textures according to `info.conversion`, and all other formats supported
+    /// by stb, e.g; PNG, JPEG and TGA, as 8 bit RGBA.
+    pub fn load_with_info<P: AsRef<Path>>(
+        context: Rc<VulkanContext>,
+        path: P,
+        info: TextureLoadInfo,
+    ) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let image_error = || Error::ImageError(path.to_owned());
+
+        let is_exr = matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some(extension) if extension.eq_ignore_ascii_case("exr")
+        );
+
+        let (extent, pixels) = if is_exr {
+            load_exr(path)?
+        } else if stb::is_hdr(path) {
+            let image = stb::HdrImage::load(path, 4).ok_or_else(image_error)?;
+            let extent = (image.width(), image.height()).into();
+            (extent, image.pixels().to_vec())
+        } else {
+            let image = stb::Image::load(path, 4).ok_or_else(image_error)?;
+
+            return Self::upload(
+                context,
+                (image.width(), image.height()).into(),
+                info.color_space.format(),
+                image.pixels(),
+                info.mip_generation,
+            );
+        };
+
+        let full_float = info.conversion == PixelConversion::FullFloat
+            && context.supports_format(
+                Format::R32G32B32A32_SFLOAT,
+                vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
+                    | vk::FormatFeatureFlags::BLIT_SRC
+                    | vk::FormatFeatureFlags::BLIT_DST,
+            );
+
+        match info.conversion {
+            PixelConversion::Clamp => {
+                let pixels = pixels
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| {
+                        let value = value.clamp(0.0, 1.0);
+                        // Alpha is never sRGB encoded
+                        let value = if info.color_space == ColorSpace::Srgb && i % 4 != 3 {
+                            linear_to_srgb(value)
+                        } else {
+                            value
+                        };
+
+                        (value * 255.0).round() as u8
+                    })
+                    .collect::<Vec<_>>();
+
+                Self::upload(
+                    context,
+                    extent,
+                    info.color_space.format(),
+                    &pixels,
+                    info.mip_generation,
+                )
+            }
+            _ if full_float => {
+                let bytes = pixels
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes().to_vec())
+                    .collect::<Vec<_>>();
+
+                Self::upload(
+                    context,
+                    extent,
+                    Format::R32G32B32A32_SFLOAT,
+                    &bytes,
+                    info.mip_generation,
+                )
+            }
+            _ => {
+                let bytes = pixels
+                    .iter()
+                    .flat_map(|value| half::f16::from_f32(*value).to_le_bytes().to_vec())
+                    .collect::<Vec<_>>();
+
+                Self::upload(
+                    context,
+                    extent,
+                    Format::R16G16B16A16_SFLOAT,
+                    &bytes,
+                    info.mip_generation,
+                )
+            }
+        }
+    }
 
+    /// Creates a mipmapped texture from tightly packed 8 bit RGBA pixels, e.g; the decoded
+    /// images of a glTF document.
+    pub fn from_pixels(
+        context: Rc<VulkanContext>,
+        extent: Extent,
+        pixels: &[u8],
+        color_space: ColorSpace,
+    ) -> Result<Self, Error> {
+        Self::from_data(context, extent, color_space.format(), pixels)
+    }
+
+    /// Creates a mipmapped texture from tightly packed pixels of `format`. The format needs to
+    /// support linear blits for generating the mip levels.
+    pub fn from_data(
+        context: Rc<VulkanContext>,
+        extent: Extent,
+        format: Format,
+        data: &[u8],
+    ) -> Result<Self, Error> {
+        Self::upload(context, extent, format, data, MipGeneration::Blit)
+    }
+
+    fn upload(
+        context: Rc<VulkanContext>,
+        extent: Extent,
+        format: Format,
+        data: &[u8],
+        mip_generation: MipGeneration,
+    ) -> Result<Self, Error> {
         let texture = Self::new(
             context,
             TextureInfo {
-                extent: (image.width(), image.height()).into(),
+                extent,
                 mip_levels: 0,
+                format,
+                mip_generation,
                 ..Default::default()
             },
         )?;
 
-        let size = image.width() as u64 * image.height() as u64 * 4;
-        texture.write(size, image.pixels())?;
+        texture.write(data.len() as u64, data)?;
         Ok(texture)
     }
 
@@ -88,68 +417,115 @@ impl Texture {
     pub fn new(context: Rc<VulkanContext>, info: TextureInfo) -> Result<Self, Error> {
         // Re-alias as mutable
         let mut info = info;
-        let mut mip_levels = calculate_mip_levels(info.extent);
 
-        // Multisampled images cannot use more than one miplevel
-        if info.samples != vk::SampleCountFlags::TYPE_1 {
-            info.mip_levels = 1;
-        }
-
-        // Don't use more mip_levels than info
-        if info.mip_levels != 0 {
-            mip_levels = mip_levels.min(info.mip_levels)
-        }
+        let queue_family_indices = context.sharing_families(info.sharing);
+        let image_info = image_create_info(&context, &mut info, &queue_family_indices);
 
-        // Override mip levels
-        info.mip_levels = mip_levels;
-
-        let vk_usage = match info.usage {
-            TextureUsage::Sampled => {
-                vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED
-            }
-            TextureUsage::ColorAttachment => {
-                vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT
-            }
-            TextureUsage::DepthAttachment => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-        } | if mip_levels > 1 {
-            vk::ImageUsageFlags::TRANSFER_SRC
+        // Transient attachments prefer lazily allocated memory, which may never be backed by
+        // physical memory on tiled architectures
+        let preferred_flags = if info.usage.is_transient() {
+            vk::MemoryPropertyFlags::LAZILY_ALLOCATED
         } else {
-            vk::ImageUsageFlags::default()
+            vk::MemoryPropertyFlags::default()
         };
 
-        let memory_usage = vk_mem::MemoryUsage::GpuOnly;
-        let flags = vk_mem::AllocationCreateFlags::NONE;
-
-        let image_info = vk::ImageCreateInfo::builder()
-            .image_type(vk::ImageType::TYPE_2D)
-            .extent(vk::Extent3D {
-                width: info.extent.width,
-                height: info.extent.height,
-                depth: 1,
-            })
-            .mip_levels(mip_levels)
-            .array_layers(1)
-            .format(info.format)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .usage(vk_usage)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .samples(info.samples);
-
         let allocator = context.allocator();
 
         let (image, allocation, _allocation_info) = allocator.create_image(
             &image_info,
             &vk_mem::AllocationCreateInfo {
-                usage: memory_usage,
-                flags,
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                flags: vk_mem::AllocationCreateFlags::NONE,
+                preferred_flags,
                 ..Default::default()
             },
         )?;
+        context.objects().created(vk::ObjectType::IMAGE);
 
         Self::from_image(context, info, image, Some(allocation))
     }
 
+    /// Creates textures sharing a single allocation large enough for the largest of them.
+    /// Use for offscreen targets which are never alive at the same time, e.g; the transient
+    /// attachments of passes drawn one after another. Writing to one of the textures leaves the
+    /// contents of the others undefined.
+    pub fn new_aliased(
+        context: Rc<VulkanContext>,
+        infos: &[TextureInfo],
+    ) -> Result<Vec<Self>, Error> {
+        let device = context.device();
+
+        let mut images = Vec::with_capacity(infos.len());
+        let mut requirements = vk::MemoryRequirements {
+            size: 0,
+            alignment: 1,
+            memory_type_bits: !0,
+        };
+
+        for info in infos {
+            let mut info = *info;
+            let queue_family_indices = context.sharing_families(info.sharing);
+            let image_info = image_create_info(&context, &mut info, &queue_family_indices);
+
+            let image =
+                match unsafe { device.create_image(&image_info, context.allocation_callbacks()) } {
+                    Ok(image) => image,
+                    Err(e) => {
+                        destroy_images(&context, &images);
+                        return Err(e.into());
+                    }
+                };
+            context.objects().created(vk::ObjectType::IMAGE);
+
+            images.push((image, info));
+
+            let image_requirements = unsafe { device.get_image_memory_requirements(image) };
+            requirements.size = requirements.size.max(image_requirements.size);
+            requirements.alignment = requirements.alignment.max(image_requirements.alignment);
+            requirements.memory_type_bits &= image_requirements.memory_type_bits;
+        }
+
+        if requirements.memory_type_bits == 0 {
+            destroy_images(&context, &images);
+            return Err(Error::IncompatibleAliasing);
+        }
+
+        // Only lazily allocate if all textures are transient
+        let transient = infos.iter().all(|info| info.usage.is_transient());
+
+        let memory = match AliasedMemory::new(context.clone(), &requirements, transient) {
+            Ok(memory) => Rc::new(memory),
+            Err(e) => {
+                destroy_images(&context, &images);
+                return Err(e);
+            }
+        };
+
+        // Textures already created destroy their images when dropped
+        let mut textures = Vec::with_capacity(images.len());
+
+        for (i, (image, info)) in images.iter().enumerate() {
+            let texture = context
+                .allocator()
+                .bind_image_memory(*image, memory.allocation())
+                .map_err(Error::from)
+                .and_then(|_| Self::from_image(context.clone(), *info, *image, None));
+
+            match texture {
+                Ok(mut texture) => {
+                    texture.memory = TextureMemory::Aliased(Rc::clone(&memory));
+                    textures.push(texture);
+                }
+                Err(e) => {
+                    destroy_images(&context, &images[i..]);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(textures)
+    }
+
     /// Creates a texture from an already existing VkImage
     /// If allocation is provided, the image will be destroyed along with self
     pub fn from_image(
@@ -158,25 +534,26 @@ impl Texture {
         image: vk::Image,
         allocation: Option<vk_mem::Allocation>,
     ) -> Result<Self, Error> {
-        let aspect_mask = match info.usage {
-            TextureUsage::Sampled => vk::ImageAspectFlags::COLOR,
-            TextureUsage::ColorAttachment => vk::ImageAspectFlags::COLOR,
-            TextureUsage::DepthAttachment => vk::ImageAspectFlags::DEPTH,
-        };
+        let aspect_mask = info.usage.aspect_mask(info.format);
 
         let create_info = vk::ImageViewCreateInfo::builder()
             .image(image)
-            .view_type(vk::ImageViewType::TYPE_2D)
+            .view_type(info.view_type)
             .format(info.format)
             .subresource_range(vk::ImageSubresourceRange {
                 aspect_mask,
                 base_mip_level: 0,
                 level_count: info.mip_levels,
                 base_array_layer: 0,
-                layer_count: 1,
+                layer_count: info.array_layers,
             });
 
-        let image_view = unsafe { context.device().create_image_view(&create_info, None) }?;
+        let image_view = unsafe {
+            context
+                .device()
+                .create_image_view(&create_info, context.allocation_callbacks())
+        }?;
+        context.objects().created(vk::ObjectType::IMAGE_VIEW);
 
         Ok(Self {
             context,
@@ -184,10 +561,17 @@ impl Texture {
             image_view,
             extent: info.extent,
             mip_levels: info.mip_levels,
+            array_layers: info.array_layers,
             format: info.format,
             samples: info.samples,
             usage: info.usage,
-            allocation,
+            sharing: info.sharing,
+            mip_generation: info.mip_generation,
+            layout: Cell::new(vk::ImageLayout::UNDEFINED),
+            memory: match allocation {
+                Some(allocation) => TextureMemory::Owned(allocation),
+                None => TextureMemory::External,
+            },
         })
     }
 
@@ -205,43 +589,295 @@ impl Texture {
         let transfer_pool = self.context.transfer_pool();
         let graphics_queue = self.context.graphics_queue();
 
-        // Prepare the image layout
-        transition_layout(
-            transfer_pool,
-            graphics_queue,
+        let dedicated_transfer = self.sharing != QueueSharing::Exclusive
+            && self.context.queue_families().has_dedicated_transfer();
+
+        // Upload on the transfer queue if allowed by the sharing policy
+        let (upload_pool, upload_queue) = if dedicated_transfer {
+            (
+                self.context.dedicated_transfer_pool(),
+                self.context.transfer_queue(),
+            )
+        } else {
+            (transfer_pool, graphics_queue)
+        };
+
+        // Prepare the image layout, previous contents are discarded
+        let barrier = ImageBarrier::new(
             self.image,
+            vk::ImageAspectFlags::COLOR,
             self.mip_levels,
             vk::ImageLayout::UNDEFINED,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        )?;
+        );
+
+        upload_pool.single_time_command(upload_queue, |commandbuffer| {
+            commandbuffer.image_barriers(&[barrier])
+        })?;
 
         buffer::copy_to_image(
-            transfer_pool,
-            graphics_queue,
+            upload_pool,
+            upload_queue,
             staging_buffer,
             self.image,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             self.extent,
         )?;
 
-        // Generate Mipmaps
-        generate_mipmaps(
-            transfer_pool,
-            graphics_queue,
-            self.image,
-            self.extent,
-            self.mip_levels,
-        )?;
+        // Hand the image over to the graphics queue for mipmapping and rendering
+        if dedicated_transfer && self.sharing == QueueSharing::OwnershipTransfer {
+            self.transfer_ownership(vk::ImageLayout::TRANSFER_DST_OPTIMAL)?;
+        }
+
+        match self.mip_generation {
+            MipGeneration::Compute => self.context.mip_generator().generate(&self.context, self)?,
+            MipGeneration::Blit => generate_mipmaps(
+                transfer_pool,
+                graphics_queue,
+                self.image,
+                self.extent,
+                self.mip_levels,
+            )?,
+        }
+
+        self.layout.set(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
 
         // Destroy the staging buffer
         allocator.destroy_buffer(staging_buffer, &staging_allocation)?;
         Ok(())
     }
 
+    /// Copies the first mip level of the first layer into host memory and returns its first
+    /// `size` bytes. The texture needs to be copyable, e.g; `TextureUsage::RenderTarget`, and
+    /// is returned to its current layout. Waits for the copy to complete.
+    pub fn read(&self, size: vk::DeviceSize) -> Result<Vec<u8>, Error> {
+        let readback = Buffer::new_uninit(
+            self.context.clone(),
+            BufferType::Readback,
+            BufferUsage::Mapped,
+            size,
+        )?;
+
+        // Images with undefined contents are left readable
+        let layout = match self.layout() {
+            vk::ImageLayout::UNDEFINED => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            layout => layout,
+        };
+
+        self.context.transfer_pool().single_time_command(
+            self.context.graphics_queue(),
+            |commandbuffer| {
+                self.transition(commandbuffer, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+                commandbuffer.copy_image_buffer(
+                    self.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    readback.buffer(),
+                    &[vk::BufferImageCopy {
+                        buffer_offset: 0,
+                        buffer_row_length: 0,
+                        buffer_image_height: 0,
+                        image_subresource: vk::ImageSubresourceLayers {
+                            aspect_mask: self.usage.aspect_mask(self.format),
+                            mip_level: 0,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        },
+                        image_offset: vk::Offset3D::default(),
+                        image_extent: vk::Extent3D {
+                            width: self.extent.width,
+                            height: self.extent.height,
+                            depth: 1,
+                        },
+                    }],
+                );
+
+                commandbuffer.buffer_barrier(
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::HOST,
+                    &[vk::BufferMemoryBarrier {
+                        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                        dst_access_mask: vk::AccessFlags::HOST_READ,
+                        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        buffer: readback.buffer(),
+                        offset: 0,
+                        size,
+                        ..Default::default()
+                    }],
+                );
+
+                self.transition(commandbuffer, layout);
+            },
+        )?;
+
+        readback.read_slice(size, 0, |data: &[u8]| data.to_vec())
+    }
+
+    /// Transitions all mip levels of the texture from the last known layout to `new_layout`.
+    /// Waits for the transition to complete.
+    pub fn transition_layout(&self, new_layout: vk::ImageLayout) -> Result<(), Error> {
+        self.context
+            .transfer_pool()
+            .single_time_command(self.context.graphics_queue(), |commandbuffer| {
+                self.transition(commandbuffer, new_layout)
+            })
+    }
+
+    /// Records a transition of all mip levels from the last known layout to `new_layout` into
+    /// `commandbuffer`. The layout is considered changed immediately.
+    pub fn transition(&self, commandbuffer: &CommandBuffer, new_layout: vk::ImageLayout) {
+        commandbuffer.image_barriers(&[self.barrier(new_layout)]);
+    }
+
+    /// Returns a barrier transitioning all mip levels and layers from the last known layout to
+    /// `new_layout` and updates the tracked layout. The barrier must be recorded by the caller.
+    pub fn barrier(&self, new_layout: vk::ImageLayout) -> ImageBarrier {
+        let old_layout = self.layout.replace(new_layout);
+
+        ImageBarrier {
+            layer_count: self.array_layers,
+            ..ImageBarrier::new(
+                self.image,
+                self.usage.aspect_mask(self.format),
+                self.mip_levels,
+                old_layout,
+                new_layout,
+            )
+        }
+    }
+
+    /// Creates a view of the first mip level of a single array layer, e.g; for rendering into
+    /// one cascade of a shadow map or one face of a cube map.
+    pub fn layer_view(&self, layer: u32) -> Result<TextureView, Error> {
+        self.create_view(vk::ImageViewType::TYPE_2D, self.format, layer, 1, 0)
+    }
+
+    /// Creates a view of all array layers of a single mip level, e.g; for writing the faces of
+    /// one mip level of a cube map as a storage image. Cube maps are usually viewed as
+    /// `TYPE_2D_ARRAY` for storage.
+    pub fn mip_view(
+        &self,
+        view_type: vk::ImageViewType,
+        mip_level: u32,
+    ) -> Result<TextureView, Error> {
+        self.create_view(view_type, self.format, 0, self.array_layers, mip_level)
+    }
+
+    /// Creates a view of the first layer of a single mip level reinterpreted as `format`, e.g;
+    /// for writing an sRGB texture as a unorm storage image. The texture needs to be created
+    /// with a mutable format, see `MipGeneration::Compute`.
+    pub fn format_view(&self, format: vk::Format, mip_level: u32) -> Result<TextureView, Error> {
+        self.create_view(vk::ImageViewType::TYPE_2D, format, 0, 1, mip_level)
+    }
+
+    fn create_view(
+        &self,
+        view_type: vk::ImageViewType,
+        format: vk::Format,
+        base_array_layer: u32,
+        layer_count: u32,
+        mip_level: u32,
+    ) -> Result<TextureView, Error> {
+        let create_info = vk::ImageViewCreateInfo::builder()
+            .image(self.image)
+            .view_type(view_type)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: self.usage.aspect_mask(self.format),
+                base_mip_level: mip_level,
+                level_count: 1,
+                base_array_layer,
+                layer_count,
+            });
+
+        let image_view = unsafe {
+            self.context
+                .device()
+                .create_image_view(&create_info, self.context.allocation_callbacks())
+        }?;
+        self.context.objects().created(vk::ObjectType::IMAGE_VIEW);
+
+        Ok(TextureView {
+            context: self.context.clone(),
+            image_view,
+            format,
+            samples: self.samples,
+        })
+    }
+
+    /// Returns the last known layout of the texture.
+    pub fn layout(&self) -> vk::ImageLayout {
+        self.layout.get()
+    }
+
+    /// Informs the texture of a layout change not done through `transition`, e.g; by the final
+    /// layout of a renderpass.
+    pub fn set_layout(&self, layout: vk::ImageLayout) {
+        self.layout.set(layout)
+    }
+
+    // Releases ownership from the transfer queue family and acquires it on the graphics queue
+    // family. The layout is kept.
+    fn transfer_ownership(&self, layout: vk::ImageLayout) -> Result<(), Error> {
+        let queue_families = self.context.queue_families();
+
+        let barrier = vk::ImageMemoryBarrier {
+            old_layout: layout,
+            new_layout: layout,
+            src_queue_family_index: queue_families.transfer().unwrap(),
+            dst_queue_family_index: queue_families.graphics().unwrap(),
+            image: self.image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: self.mip_levels,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        };
+
+        self.context.dedicated_transfer_pool().single_time_command(
+            self.context.transfer_queue(),
+            |commandbuffer| {
+                commandbuffer.pipeline_barrier(
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    &[vk::ImageMemoryBarrier {
+                        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                        ..barrier
+                    }],
+                )
+            },
+        )?;
+
+        self.context.transfer_pool().single_time_command(
+            self.context.graphics_queue(),
+            |commandbuffer| {
+                commandbuffer.pipeline_barrier(
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::TRANSFER,
+                    &[vk::ImageMemoryBarrier {
+                        dst_access_mask: vk::AccessFlags::TRANSFER_READ
+                            | vk::AccessFlags::TRANSFER_WRITE,
+                        ..barrier
+                    }],
+                )
+            },
+        )
+    }
+
     pub fn format(&self) -> vk::Format {
         self.format
     }
 
+    /// Returns the color space of 8 bit color textures. None for other formats, e.g; floating
+    /// point formats which always store linear values.
+    pub fn color_space(&self) -> Option<ColorSpace> {
+        ColorSpace::of(self.format)
+    }
+
     pub fn image(&self) -> vk::Image {
         self.image
     }
@@ -254,6 +890,10 @@ impl Texture {
         self.mip_levels
     }
 
+    pub fn array_layers(&self) -> u32 {
+        self.array_layers
+    }
+
     /// Return a reference to the texture's samples.
     pub fn samples(&self) -> vk::SampleCountFlags {
         self.samples
@@ -264,6 +904,19 @@ impl Texture {
         self.usage
     }
 
+    /// Returns the queue sharing policy of the texture
+    pub fn sharing(&self) -> QueueSharing {
+        self.sharing
+    }
+
+    /// Returns the memory shared with other textures if created with `new_aliased`
+    pub fn aliased_memory(&self) -> Option<&Rc<AliasedMemory>> {
+        match &self.memory {
+            TextureMemory::Aliased(memory) => Some(memory),
+            _ => None,
+        }
+    }
+
     // Returns the textures width and height
     pub fn extent(&self) -> Extent {
         self.extent
@@ -294,21 +947,222 @@ impl Into<vk::Image> for &Texture {
     }
 }
 
+impl DebugName for Texture {
+    fn set_debug_name(&self, context: &VulkanContext, name: &str) {
+        context.set_object_name(self.image, name);
+        context.set_object_name(self.image_view, name);
+    }
+}
+
 impl Drop for Texture {
     fn drop(&mut self) {
         let allocator = self.context.allocator();
-
-        // Destroy allocation if texture owns image
-        if let Some(allocation) = self.allocation.take() {
-            allocator.destroy_image(self.image, &allocation).unwrap();
+        let callbacks = self.context.allocation_callbacks();
+
+        match &self.memory {
+            // Destroy allocation if texture owns image
+            TextureMemory::Owned(allocation) => {
+                if let Err(e) = allocator.destroy_image(self.image, allocation) {
+                    log::error!("Failed to destroy image: {}", e);
+                }
+                self.context.objects().destroyed(vk::ObjectType::IMAGE);
+            }
+            // The shared memory is freed when the last texture is dropped
+            TextureMemory::Aliased(_) => {
+                unsafe { self.context.device().destroy_image(self.image, callbacks) };
+                self.context.objects().destroyed(vk::ObjectType::IMAGE);
+            }
+            TextureMemory::External => {}
         }
 
         // Destroy image view
         unsafe {
             self.context
                 .device()
-                .destroy_image_view(self.image_view, None);
+                .destroy_image_view(self.image_view, callbacks);
         }
+        self.context.objects().destroyed(vk::ObjectType::IMAGE_VIEW);
+    }
+}
+
+/// An additional view into a subresource of a texture. Destroyed on drop, and must not outlive
+/// the texture.
+pub struct TextureView {
+    context: Rc<VulkanContext>,
+    image_view: vk::ImageView,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+}
+
+impl TextureView {
+    pub fn image_view(&self) -> vk::ImageView {
+        self.image_view
+    }
+
+    /// Returns the format of the view, which may differ from the texture's
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn samples(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+}
+
+impl AsRef<vk::ImageView> for TextureView {
+    fn as_ref(&self) -> &vk::ImageView {
+        &self.image_view
+    }
+}
+
+impl Drop for TextureView {
+    fn drop(&mut self) {
+        unsafe {
+            self.context
+                .device()
+                .destroy_image_view(self.image_view, self.context.allocation_callbacks());
+        }
+        self.context.objects().destroyed(vk::ObjectType::IMAGE_VIEW);
+    }
+}
+
+/// Returns true if `format` has a stencil component
+pub fn has_stencil(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::S8_UINT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+/// Clamps the mip levels of `info`, falls back to blitting the mip levels of unsupported formats
+/// and returns the create info of its image.
+/// `queue_family_indices` needs to outlive the returned create info.
+fn image_create_info(
+    context: &VulkanContext,
+    info: &mut TextureInfo,
+    queue_family_indices: &[u32],
+) -> vk::ImageCreateInfo {
+    let mut mip_levels = calculate_mip_levels(info.extent);
+
+    // Multisampled images cannot use more than one miplevel
+    if info.samples != vk::SampleCountFlags::TYPE_1 {
+        info.mip_levels = 1;
+    }
+
+    // Don't use more mip_levels than info
+    if info.mip_levels != 0 {
+        mip_levels = mip_levels.min(info.mip_levels)
+    }
+
+    // Override mip levels
+    info.mip_levels = mip_levels;
+
+    if info.mip_generation == MipGeneration::Compute && !mipmap::is_supported(context, info.format)
+    {
+        info.mip_generation = MipGeneration::Blit;
+    }
+
+    let compute_mips = mip_levels > 1 && info.mip_generation == MipGeneration::Compute;
+
+    let usage = info.usage.image_usage()
+        | if mip_levels > 1 {
+            vk::ImageUsageFlags::TRANSFER_SRC
+        } else {
+            vk::ImageUsageFlags::default()
+        }
+        | if compute_mips {
+            vk::ImageUsageFlags::STORAGE
+        } else {
+            vk::ImageUsageFlags::default()
+        };
+
+    let sharing_mode = if queue_family_indices.is_empty() {
+        vk::SharingMode::EXCLUSIVE
+    } else {
+        vk::SharingMode::CONCURRENT
+    };
+
+    // Cube views require the image to be created for them
+    let mut flags = match info.view_type {
+        vk::ImageViewType::CUBE | vk::ImageViewType::CUBE_ARRAY => {
+            vk::ImageCreateFlags::CUBE_COMPATIBLE
+        }
+        _ => vk::ImageCreateFlags::empty(),
+    };
+
+    // sRGB formats are written through storage views of another format, which the image format
+    // itself does not need to support storage for
+    let storage_format = mipmap::storage_format(info.format).map(|(format, _)| format);
+    if compute_mips && storage_format != Some(info.format) {
+        flags |= vk::ImageCreateFlags::MUTABLE_FORMAT | vk::ImageCreateFlags::EXTENDED_USAGE;
+    }
+
+    vk::ImageCreateInfo::builder()
+        .flags(flags)
+        .image_type(vk::ImageType::TYPE_2D)
+        .extent(vk::Extent3D {
+            width: info.extent.width,
+            height: info.extent.height,
+            depth: 1,
+        })
+        .mip_levels(mip_levels)
+        .array_layers(info.array_layers)
+        .format(info.format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(usage)
+        .sharing_mode(sharing_mode)
+        .queue_family_indices(queue_family_indices)
+        .samples(info.samples)
+        .build()
+}
+
+fn destroy_images(context: &VulkanContext, images: &[(vk::Image, TextureInfo)]) {
+    for (image, _) in images {
+        unsafe {
+            context
+                .device()
+                .destroy_image(*image, context.allocation_callbacks())
+        };
+        context.objects().destroyed(vk::ObjectType::IMAGE);
+    }
+}
+
+/// Loads the first RGBA layer of an OpenEXR image as 32 bit float pixels. Images without an
+/// alpha channel are opaque.
+fn load_exr(path: &Path) -> Result<(Extent, Vec<f32>), Error> {
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| {
+            (
+                resolution.width(),
+                vec![0.0; resolution.width() * resolution.height() * 4],
+            )
+        },
+        |(width, pixels): &mut (usize, Vec<f32>), position, (r, g, b, a): (f32, f32, f32, f32)| {
+            let i = (position.y() * *width + position.x()) * 4;
+            pixels[i..i + 4].copy_from_slice(&[r, g, b, a]);
+        },
+    )
+    .map_err(|source| Error::ExrError {
+        path: path.to_owned(),
+        source,
+    })?;
+
+    let size = image.layer_data.size;
+    let (_, pixels) = image.layer_data.channel_data.pixels;
+
+    Ok(((size.width() as u32, size.height() as u32).into(), pixels))
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
     }
 }
 
@@ -439,54 +1293,3 @@ fn generate_mipmaps(
         );
     })
 }
-
-// Transitions image layout from one layout to another using a pipeline barrier
-fn transition_layout(
-    commandpool: &CommandPool,
-    queue: vk::Queue,
-    image: vk::Image,
-    mip_levels: u32,
-    old_layout: vk::ImageLayout,
-    new_layout: vk::ImageLayout,
-) -> Result<(), Error> {
-    let (src_access_mask, dst_access_mask, src_stage_mask, dst_stage_mask) =
-        match (old_layout, new_layout) {
-            (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
-                vk::AccessFlags::default(),
-                vk::AccessFlags::TRANSFER_WRITE,
-                vk::PipelineStageFlags::TOP_OF_PIPE,
-                vk::PipelineStageFlags::TRANSFER,
-            ),
-
-            (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
-                vk::AccessFlags::TRANSFER_WRITE,
-                vk::AccessFlags::SHADER_READ,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::FRAGMENT_SHADER,
-            ),
-            _ => return Err(Error::UnsupportedLayoutTransition(old_layout, new_layout)),
-        };
-
-    let barrier = vk::ImageMemoryBarrier {
-        s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-        p_next: std::ptr::null(),
-        src_access_mask,
-        dst_access_mask,
-        old_layout,
-        new_layout,
-        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-        image,
-        subresource_range: vk::ImageSubresourceRange {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: mip_levels,
-            base_array_layer: 0,
-            layer_count: 1,
-        },
-    };
-
-    commandpool.single_time_command(queue, |commandbuffer| {
-        commandbuffer.pipeline_barrier(src_stage_mask, dst_stage_mask, &[barrier])
-    })
-}
@@ -0,0 +1,232 @@
+//! Tracking of host allocations and live Vulkan objects.
+//! Objects created through the context are counted by type, and objects still alive when the
+//! context is dropped are reported as leaks. Objects created directly from a device, e.g;
+//! pipelines and renderpasses, are not tracked.
+use std::alloc::{self, Layout};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ash::vk;
+
+/// The number of `vk::SystemAllocationScope` values
+const SCOPE_COUNT: usize = 5;
+
+/// Stored in front of each host allocation to free it without the original layout
+#[repr(C)]
+struct Header {
+    size: usize,
+    align: usize,
+    scope: usize,
+}
+
+/// The live host allocations of one allocation scope
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HostAllocationStats {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+/// Host allocation callbacks which forward to the global allocator while counting the live
+/// allocations of each scope. Drivers may call the callbacks from any thread.
+pub struct HostAllocator {
+    counts: [AtomicUsize; SCOPE_COUNT],
+    bytes: [AtomicUsize; SCOPE_COUNT],
+}
+
+impl HostAllocator {
+    /// Creates the allocator boxed, since the callbacks refer to it by address
+    pub fn new() -> Box<Self> {
+        Box::new(Self {
+            counts: Default::default(),
+            bytes: Default::default(),
+        })
+    }
+
+    /// Returns the callbacks to pass when creating and destroying objects. Must not outlive
+    /// self.
+    pub fn callbacks(&self) -> vk::AllocationCallbacks {
+        // The free function is declared as returning c_void, which can not be constructed
+        let free: vk::PFN_vkFreeFunction =
+            unsafe { mem::transmute(free as unsafe extern "system" fn(*mut c_void, *mut c_void)) };
+
+        vk::AllocationCallbacks {
+            p_user_data: self as *const Self as *mut c_void,
+            pfn_allocation: Some(allocation),
+            pfn_reallocation: Some(reallocation),
+            pfn_free: free,
+            pfn_internal_allocation: None,
+            pfn_internal_free: None,
+        }
+    }
+
+    /// Returns the live allocations of each scope with any allocations
+    pub fn stats(&self) -> Vec<(vk::SystemAllocationScope, HostAllocationStats)> {
+        (0..SCOPE_COUNT)
+            .map(|scope| {
+                let stats = HostAllocationStats {
+                    count: self.counts[scope].load(Ordering::Relaxed),
+                    bytes: self.bytes[scope].load(Ordering::Relaxed),
+                };
+
+                (vk::SystemAllocationScope::from_raw(scope as i32), stats)
+            })
+            .filter(|(_, stats)| stats.count > 0)
+            .collect()
+    }
+
+    fn allocate(&self, size: usize, alignment: usize, scope: usize) -> *mut c_void {
+        if size == 0 {
+            return ptr::null_mut();
+        }
+
+        let align = alignment.max(mem::align_of::<Header>());
+        let offset = round_up(mem::size_of::<Header>(), align);
+
+        let layout = match Layout::from_size_align(offset + size, align) {
+            Ok(layout) => layout,
+            Err(_) => return ptr::null_mut(),
+        };
+
+        unsafe {
+            let base = alloc::alloc(layout);
+            if base.is_null() {
+                return ptr::null_mut();
+            }
+
+            let memory = base.add(offset);
+            let scope = scope.min(SCOPE_COUNT - 1);
+            header(memory).write(Header { size, align, scope });
+
+            self.counts[scope].fetch_add(1, Ordering::Relaxed);
+            self.bytes[scope].fetch_add(size, Ordering::Relaxed);
+
+            memory as *mut c_void
+        }
+    }
+
+    unsafe fn free(&self, memory: *mut c_void) {
+        if memory.is_null() {
+            return;
+        }
+
+        let memory = memory as *mut u8;
+        let Header { size, align, scope } = header(memory).read();
+        let offset = round_up(mem::size_of::<Header>(), align);
+
+        self.counts[scope].fetch_sub(1, Ordering::Relaxed);
+        self.bytes[scope].fetch_sub(size, Ordering::Relaxed);
+
+        alloc::dealloc(
+            memory.sub(offset),
+            Layout::from_size_align_unchecked(offset + size, align),
+        );
+    }
+}
+
+impl fmt::Display for HostAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (scope, stats) in self.stats() {
+            writeln!(
+                f,
+                "{:?}: {} allocations, {} bytes",
+                scope, stats.count, stats.bytes
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts the live Vulkan objects of each type
+#[derive(Debug, Default)]
+pub struct ObjectTracker {
+    live: RefCell<BTreeMap<vk::ObjectType, usize>>,
+}
+
+impl ObjectTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn created(&self, object_type: vk::ObjectType) {
+        *self.live.borrow_mut().entry(object_type).or_default() += 1;
+    }
+
+    /// Logs an error if more objects of the type were destroyed than created
+    pub fn destroyed(&self, object_type: vk::ObjectType) {
+        let mut live = self.live.borrow_mut();
+        match live.get_mut(&object_type) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => log::error!("Destroyed untracked {:?} object", object_type),
+        }
+    }
+
+    /// Returns the number of live objects of each type with any live objects
+    pub fn live(&self) -> Vec<(vk::ObjectType, usize)> {
+        self.live
+            .borrow()
+            .iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(object_type, count)| (*object_type, *count))
+            .collect()
+    }
+}
+
+/// Rounds `value` up to a multiple of `align`, which is a power of two
+fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+/// Returns the header in front of an allocation
+unsafe fn header(memory: *mut u8) -> *mut Header {
+    memory.sub(mem::size_of::<Header>()) as *mut Header
+}
+
+unsafe extern "system" fn allocation(
+    user_data: *mut c_void,
+    size: usize,
+    alignment: usize,
+    scope: vk::SystemAllocationScope,
+) -> *mut c_void {
+    let allocator = &*(user_data as *const HostAllocator);
+    allocator.allocate(size, alignment, scope.as_raw() as usize)
+}
+
+unsafe extern "system" fn reallocation(
+    user_data: *mut c_void,
+    original: *mut c_void,
+    size: usize,
+    alignment: usize,
+    scope: vk::SystemAllocationScope,
+) -> *mut c_void {
+    let allocator = &*(user_data as *const HostAllocator);
+
+    if original.is_null() {
+        return allocator.allocate(size, alignment, scope.as_raw() as usize);
+    }
+
+    if size == 0 {
+        allocator.free(original);
+        return ptr::null_mut();
+    }
+
+    let old_size = header(original as *mut u8).read().size;
+
+    let memory = allocator.allocate(size, alignment, scope.as_raw() as usize);
+    if !memory.is_null() {
+        ptr::copy_nonoverlapping(original as *const u8, memory as *mut u8, old_size.min(size));
+        allocator.free(original);
+    }
+
+    memory
+}
+
+unsafe extern "system" fn free(user_data: *mut c_void, memory: *mut c_void) {
+    let allocator = &*(user_data as *const HostAllocator);
+    allocator.free(memory)
+}
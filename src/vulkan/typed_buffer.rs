@@ -0,0 +1,134 @@
+use std::{marker::PhantomData, mem, rc::Rc};
+
+use ash::vk;
+use vk::DeviceSize;
+
+use super::{Buffer, BufferType, BufferUsage, DebugName, Error, VulkanContext};
+
+/// A buffer of `len` elements of T, written by element instead of by byte.
+/// T is uploaded as is and should be plain data with the layout expected by the shaders,
+/// e.g; `#[repr(C)]`.
+pub struct TypedBuffer<T> {
+    buffer: Buffer,
+    len: usize,
+    marker: PhantomData<T>,
+}
+
+impl<T: Copy> TypedBuffer<T> {
+    /// Creates a buffer of `len` zeroed elements.
+    pub fn new(
+        context: Rc<VulkanContext>,
+        ty: BufferType,
+        usage: BufferUsage,
+        len: usize,
+    ) -> Result<Self, Error> {
+        let buffer = Buffer::new_zeroed(context, ty, usage, stride::<T>() * len as DeviceSize)?;
+
+        Ok(Self {
+            buffer,
+            len,
+            marker: PhantomData,
+        })
+    }
+
+    /// Creates a buffer containing `data`.
+    pub fn from_slice(
+        context: Rc<VulkanContext>,
+        ty: BufferType,
+        usage: BufferUsage,
+        data: &[T],
+    ) -> Result<Self, Error> {
+        let buffer = Buffer::new(context, ty, usage, data)?;
+
+        Ok(Self {
+            buffer,
+            len: data.len(),
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the number of elements the buffer fits
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Writes a single element at `index`.
+    pub fn write_at(&mut self, index: usize, value: &T) -> Result<(), Error> {
+        self.write_slice(index, std::slice::from_ref(value))
+    }
+
+    /// Writes `data` starting at element `offset`.
+    pub fn write_slice(&mut self, offset: usize, data: &[T]) -> Result<(), Error> {
+        self.write_iter(offset, data.iter().copied())
+    }
+
+    /// Writes each element produced by `iter` starting at element `offset`, e.g; by mapping
+    /// scene objects directly into the buffer without collecting them first.
+    pub fn write_iter<I>(&mut self, offset: usize, iter: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let iter = iter.into_iter();
+        let count = iter.len();
+
+        self.check_range(offset, count)?;
+
+        if count == 0 {
+            return Ok(());
+        }
+
+        self.buffer
+            .write_slice(count as DeviceSize, offset as DeviceSize, |mut writer| {
+                writer.write_iter(0, iter);
+            })
+    }
+
+    /// Reads `len` elements starting at element `offset`. Only supported for host visible
+    /// usages. See `Buffer::read`.
+    pub fn read<F, R>(&self, offset: usize, len: usize, read_func: F) -> Result<R, Error>
+    where
+        F: FnOnce(&[T]) -> R,
+    {
+        self.check_range(offset, len)?;
+        self.buffer
+            .read_slice(len as DeviceSize, offset as DeviceSize, read_func)
+    }
+
+    /// Returns the underlying buffer, e.g; to bind it
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    fn check_range(&self, offset: usize, count: usize) -> Result<(), Error> {
+        if offset + count > self.len {
+            return Err(Error::BufferOverflow {
+                size: stride::<T>() * (offset + count) as DeviceSize,
+                max_size: self.buffer.size(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> AsRef<Buffer> for TypedBuffer<T> {
+    fn as_ref(&self) -> &Buffer {
+        &self.buffer
+    }
+}
+
+impl<T> DebugName for TypedBuffer<T> {
+    fn set_debug_name(&self, context: &VulkanContext, name: &str) {
+        self.buffer.set_debug_name(context, name)
+    }
+}
+
+/// Returns the size of an element
+pub fn stride<T>() -> DeviceSize {
+    mem::size_of::<T>() as DeviceSize
+}
@@ -0,0 +1,355 @@
+//! Checks of the compatibility of pipelines, descriptor sets and framebuffers with what they are
+//! used with, which report mismatches as errors instead of leaving them to the validation layers.
+//! Only performed in debug builds.
+//!
+//! Command buffers remember the first mismatch recorded into them and return it from
+//! `CommandBuffer::end`. Descriptor sets are only checked if allocated through a
+//! `DescriptorAllocator`, and pipelines are not checked within dynamic rendering begun by the
+//! primary command buffer, since the formats of the attachments are not known.
+//!
+//! Command buffers also track whether they are recording, rendering and have a pipeline bound,
+//! and fail debug assertions when drawing, dispatching or submitting in the wrong state.
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use arrayvec::ArrayVec;
+use ash::vk;
+
+use super::descriptors::DescriptorSetBinding;
+use super::renderpass::MAX_ATTACHMENTS;
+use super::{Error, RenderingFormats};
+
+/// True if the checks are performed
+pub const ENABLED: bool = cfg!(debug_assertions);
+
+/// The formats and samples of the attachments of a subpass or dynamic rendering. Pipelines need
+/// to be created for the same formats as the subpass they are bound in.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AttachmentFormats {
+    pub color: ArrayVec<[vk::Format; MAX_ATTACHMENTS]>,
+    /// `vk::Format::UNDEFINED` without a depth attachment
+    pub depth: vk::Format,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl AttachmentFormats {
+    /// Returns the attachments of dynamic rendering to `formats`
+    pub fn dynamic(formats: &RenderingFormats, samples: vk::SampleCountFlags) -> Self {
+        Self {
+            color: formats.color_formats.clone(),
+            depth: formats.depth_format,
+            samples,
+        }
+    }
+}
+
+/// The part of a descriptor set layout binding compared between the layout of a set and the
+/// pipeline layout it is bound to. Stage flags are not compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BindingSignature {
+    binding: u32,
+    descriptor_type: vk::DescriptorType,
+    descriptor_count: u32,
+}
+
+/// The objects created while the checks are enabled, looked up by handle when used
+#[derive(Default)]
+struct Registry {
+    set_layouts: HashMap<vk::DescriptorSetLayout, Vec<BindingSignature>>,
+    sets: HashMap<vk::DescriptorSet, vk::DescriptorSetLayout>,
+    pipeline_layouts: HashMap<vk::PipelineLayout, Vec<vk::DescriptorSetLayout>>,
+    renderpasses: HashMap<vk::RenderPass, Vec<AttachmentFormats>>,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::new(Registry::default());
+}
+
+fn with_registry<F: FnOnce(&mut Registry) -> R, R>(func: F) -> R {
+    REGISTRY.with(|registry| func(&mut registry.borrow_mut()))
+}
+
+/// Remembers the bindings of a created descriptor set layout
+pub fn register_set_layout(layout: vk::DescriptorSetLayout, bindings: &[DescriptorSetBinding]) {
+    if !ENABLED {
+        return;
+    }
+
+    let mut signature = bindings
+        .iter()
+        .map(|binding| BindingSignature {
+            binding: binding.binding,
+            descriptor_type: binding.descriptor_type,
+            descriptor_count: binding.descriptor_count,
+        })
+        .collect::<Vec<_>>();
+
+    signature.sort_by_key(|binding| binding.binding);
+
+    with_registry(|registry| registry.set_layouts.insert(layout, signature));
+}
+
+/// Remembers the layout of allocated descriptor sets
+pub fn register_sets(layout: vk::DescriptorSetLayout, sets: &[vk::DescriptorSet]) {
+    if !ENABLED {
+        return;
+    }
+
+    with_registry(|registry| registry.sets.extend(sets.iter().map(|set| (*set, layout))));
+}
+
+/// Remembers the set layouts of a created pipeline layout, in set order
+pub fn register_pipeline_layout(
+    layout: vk::PipelineLayout,
+    set_layouts: &[vk::DescriptorSetLayout],
+) {
+    if !ENABLED {
+        return;
+    }
+
+    with_registry(|registry| {
+        registry
+            .pipeline_layouts
+            .insert(layout, set_layouts.to_vec())
+    });
+}
+
+/// Remembers the attachments of each subpass of a created renderpass
+pub fn register_renderpass(renderpass: vk::RenderPass, subpasses: Vec<AttachmentFormats>) {
+    if !ENABLED {
+        return;
+    }
+
+    with_registry(|registry| registry.renderpasses.insert(renderpass, subpasses));
+}
+
+/// Returns the attachments of `subpass` of a registered renderpass
+pub fn subpass_formats(renderpass: vk::RenderPass, subpass: u32) -> Option<AttachmentFormats> {
+    with_registry(|registry| {
+        registry
+            .renderpasses
+            .get(&renderpass)
+            .and_then(|subpasses| subpasses.get(subpass as usize))
+            .cloned()
+    })
+}
+
+/// Returns an error if the pipeline created for `expected` is bound within `found`
+pub fn check_pipeline(
+    expected: &AttachmentFormats,
+    found: &AttachmentFormats,
+) -> Result<(), Error> {
+    if expected != found {
+        return Err(Error::IncompatiblePipeline {
+            expected: expected.clone(),
+            found: found.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns an error if any of `sets` bound from `first_set` has a layout which is not compatible
+/// with the corresponding set of `pipeline_layout`. Sets and layouts which were not registered
+/// are not checked.
+pub fn check_descriptor_sets(
+    pipeline_layout: vk::PipelineLayout,
+    first_set: u32,
+    sets: &[vk::DescriptorSet],
+) -> Result<(), Error> {
+    with_registry(|registry| {
+        let set_layouts = match registry.pipeline_layouts.get(&pipeline_layout) {
+            Some(set_layouts) => set_layouts,
+            None => return Ok(()),
+        };
+
+        for (set, descriptor_set) in (first_set..).zip(sets) {
+            let expected = match set_layouts.get(set as usize) {
+                Some(layout) => *layout,
+                None => {
+                    return Err(Error::DescriptorSetOutOfRange {
+                        set,
+                        count: set_layouts.len() as u32,
+                    })
+                }
+            };
+
+            let found = match registry.sets.get(descriptor_set) {
+                Some(layout) => *layout,
+                None => continue,
+            };
+
+            let compatible = match (
+                registry.set_layouts.get(&found),
+                registry.set_layouts.get(&expected),
+            ) {
+                (Some(found), Some(expected)) => found == expected,
+                _ => true,
+            };
+
+            if !compatible {
+                return Err(Error::IncompatibleDescriptorSet { set });
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Returns an error if the framebuffer attachments described by `found` do not match the
+/// attachments of the renderpass
+pub fn check_framebuffer(
+    expected: &[(vk::Format, vk::SampleCountFlags)],
+    found: &[(vk::Format, vk::SampleCountFlags)],
+) -> Result<(), Error> {
+    if expected.len() != found.len() {
+        return Err(Error::AttachmentCountMismatch {
+            expected: expected.len(),
+            found: found.len(),
+        });
+    }
+
+    match expected.iter().zip(found).position(|(a, b)| a != b) {
+        Some(index) => Err(Error::AttachmentMismatch {
+            index,
+            expected: expected[index],
+            found: found[index],
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The state of a command buffer which is checked against while recording. Misuse which would
+/// crash the driver, e.g; drawing outside of a renderpass, fails debug assertions.
+#[derive(Default)]
+pub struct CommandState {
+    /// Between `begin` and `end`
+    recording: bool,
+    /// Ended and not begun again since, which is required to submit or execute the commands
+    executable: bool,
+    /// Within a renderpass or dynamic rendering, or a secondary command buffer continuing one
+    rendering: bool,
+    /// A secondary command buffer continuing rendering begun by the primary command buffer
+    continuing: bool,
+    graphics_pipeline: bool,
+    compute_pipeline: bool,
+    /// The attachments of the current subpass or dynamic rendering, None if unknown or outside of
+    /// any
+    target: Option<AttachmentFormats>,
+    /// The first mismatch recorded since the command buffer was begun
+    error: Option<Error>,
+}
+
+impl CommandState {
+    /// Clears the state when the command buffer is begun. Secondary command buffers are begun
+    /// within the `rendering` they continue.
+    pub fn begin(&mut self, continuing: bool, target: Option<AttachmentFormats>) {
+        *self = Self {
+            recording: true,
+            rendering: continuing,
+            continuing,
+            target,
+            ..Default::default()
+        }
+    }
+
+    pub fn end(&mut self) {
+        debug_assert!(self.recording, "Command buffer ended without being begun");
+        debug_assert!(
+            !self.rendering || self.continuing,
+            "Command buffer ended within a renderpass or dynamic rendering"
+        );
+
+        self.recording = false;
+        self.executable = true;
+    }
+
+    pub fn begin_rendering(&mut self, target: Option<AttachmentFormats>) {
+        debug_assert!(self.recording, "Rendering begun outside of recording");
+        debug_assert!(!self.rendering, "Rendering begun within another renderpass");
+
+        self.rendering = true;
+        self.target = target;
+    }
+
+    pub fn end_rendering(&mut self) {
+        debug_assert!(self.rendering, "Rendering ended without being begun");
+
+        self.rendering = false;
+        self.target = None;
+    }
+
+    pub fn bind_graphics_pipeline(&mut self, expected: &AttachmentFormats) {
+        debug_assert!(self.recording, "Pipeline bound outside of recording");
+
+        self.graphics_pipeline = true;
+
+        if ENABLED {
+            self.check_pipeline(expected);
+        }
+    }
+
+    pub fn bind_compute_pipeline(&mut self) {
+        debug_assert!(self.recording, "Pipeline bound outside of recording");
+
+        self.compute_pipeline = true;
+    }
+
+    /// Asserts that a draw is valid, which requires a graphics pipeline within rendering
+    pub fn draw(&mut self) {
+        debug_assert!(
+            self.rendering,
+            "Draw recorded outside of a renderpass or dynamic rendering"
+        );
+        debug_assert!(
+            self.graphics_pipeline,
+            "Draw recorded without a bound graphics pipeline"
+        );
+    }
+
+    /// Asserts that a dispatch is valid, which requires a compute pipeline outside of rendering
+    pub fn dispatch(&mut self) {
+        debug_assert!(
+            self.recording && !self.rendering,
+            "Dispatch recorded within a renderpass or dynamic rendering"
+        );
+        debug_assert!(
+            self.compute_pipeline,
+            "Dispatch recorded without a bound compute pipeline"
+        );
+    }
+
+    /// Returns true if the command buffer was recorded and can be submitted or executed
+    pub fn is_executable(&self) -> bool {
+        self.executable
+    }
+
+    fn check_pipeline(&mut self, expected: &AttachmentFormats) {
+        if let Some(found) = &self.target {
+            let result = check_pipeline(expected, found);
+            self.record(result);
+        }
+    }
+
+    pub fn check_descriptor_sets(
+        &mut self,
+        pipeline_layout: vk::PipelineLayout,
+        first_set: u32,
+        sets: &[vk::DescriptorSet],
+    ) {
+        let result = check_descriptor_sets(pipeline_layout, first_set, sets);
+        self.record(result);
+    }
+
+    /// Returns the first recorded mismatch
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    fn record(&mut self, result: Result<(), Error>) {
+        if let (Err(e), None) = (result, &self.error) {
+            log::error!("{}", e);
+            self.error = Some(e);
+        }
+    }
+}
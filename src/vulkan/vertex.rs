@@ -1,6 +1,98 @@
+//! Descriptions of the vertex and instance streams read by vertex shaders.
 use ash::vk;
+use ultraviolet::{IVec2, IVec3, IVec4, UVec2, UVec3, UVec4, Vec2, Vec3, Vec4};
 
 pub trait VertexDesc {
     fn binding_description() -> vk::VertexInputBindingDescription;
     fn attribute_descriptions() -> &'static [vk::VertexInputAttributeDescription];
 }
+
+/// A type of vertex struct field which can be read by shaders as a vertex attribute
+pub trait VertexAttribute {
+    /// The format of the attribute. Integer formats are read as uint or int, all others as
+    /// float.
+    const FORMAT: vk::Format;
+}
+
+macro_rules! impl_vertex_attribute {
+    ($($ty:ty => $format:ident),* $(,)?) => {
+        $(impl VertexAttribute for $ty {
+            const FORMAT: vk::Format = vk::Format::$format;
+        })*
+    };
+}
+
+impl_vertex_attribute!(
+    f32 => R32_SFLOAT,
+    Vec2 => R32G32_SFLOAT,
+    Vec3 => R32G32B32_SFLOAT,
+    Vec4 => R32G32B32A32_SFLOAT,
+    [f32; 2] => R32G32_SFLOAT,
+    [f32; 3] => R32G32B32_SFLOAT,
+    [f32; 4] => R32G32B32A32_SFLOAT,
+    u32 => R32_UINT,
+    UVec2 => R32G32_UINT,
+    UVec3 => R32G32B32_UINT,
+    UVec4 => R32G32B32A32_UINT,
+    [u16; 4] => R16G16B16A16_UINT,
+    i32 => R32_SINT,
+    IVec2 => R32G32_SINT,
+    IVec3 => R32G32B32_SINT,
+    IVec4 => R32G32B32A32_SINT,
+    // Normalized, e.g; for colors
+    [u8; 4] => R8G8B8A8_UNORM,
+);
+
+/// Implements `VertexDesc` for a struct from the locations of its fields. The offsets and
+/// formats of the attributes are taken from the fields, see `VertexAttribute`, and fields which
+/// are not listed are not read. The stride is the size of the struct.
+///
+/// Describes a vertex stream at binding 0 by default, or an instance stream:
+///
+/// ```ignore
+/// vertex_desc!(Vertex {
+///     0 => position: Vec3,
+///     1 => normal: Vec3,
+/// });
+///
+/// vertex_desc!(Instance, binding = 1, INSTANCE {
+///     6 => offset: Vec3,
+///     7 => color: [u8; 4],
+/// });
+/// ```
+///
+/// Fails to compile if a field does not exist or is not of the listed type.
+#[macro_export]
+macro_rules! vertex_desc {
+    ($ty:ident { $($fields:tt)* }) => {
+        $crate::vertex_desc!($ty, binding = 0, VERTEX { $($fields)* });
+    };
+    ($ty:ident, binding = $binding:expr, $rate:ident {
+        $($location:expr => $field:ident: $field_ty:ty),* $(,)?
+    }) => {
+        impl $crate::vulkan::VertexDesc for $ty {
+            fn binding_description() -> ::ash::vk::VertexInputBindingDescription {
+                ::ash::vk::VertexInputBindingDescription {
+                    binding: $binding,
+                    stride: ::std::mem::size_of::<$ty>() as u32,
+                    input_rate: ::ash::vk::VertexInputRate::$rate,
+                }
+            }
+
+            fn attribute_descriptions() -> &'static [::ash::vk::VertexInputAttributeDescription] {
+                $(let _: fn(&$ty) -> &$field_ty = |vertex| &vertex.$field;)*
+
+                const ATTRIBUTES: &[::ash::vk::VertexInputAttributeDescription] = &[
+                    $(::ash::vk::VertexInputAttributeDescription {
+                        binding: $binding,
+                        location: $location,
+                        format: <$field_ty as $crate::vulkan::VertexAttribute>::FORMAT,
+                        offset: ::std::mem::offset_of!($ty, $field) as u32,
+                    }),*
+                ];
+
+                ATTRIBUTES
+            }
+        }
+    };
+}
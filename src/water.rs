@@ -0,0 +1,352 @@
+//! Water surfaces drawn as large planes of the water effect, see `data/effects/water.toml`. The
+//! surface is perturbed by two normal maps scrolling in different directions, and blends from the
+//! water color to a reflection by a Fresnel term.
+//!
+//! The reflection is a render target drawing the scene from the camera mirrored below the
+//! surface, which is sampled in screen space. Objects below the surface are not clipped from the
+//! reflection, and should be excluded through `WaterInfo::reflection_layers`.
+//!
+//! The appearance of all surfaces is given by the `WaterSettings` of the scene, which the master
+//! renderer uploads each frame along with the time animating the normals. See
+//! `Scene::set_water`.
+use std::{rc::Rc, slice};
+
+use ultraviolet::{Rotor3, Vec2, Vec3, Vec4};
+
+use crate::color::Color;
+use crate::document::Transform;
+use crate::ecs::Entity;
+use crate::layers::Layers;
+use crate::light_culling::update_buffer;
+use crate::master_renderer::MasterRenderer;
+use crate::object::SCALE;
+use crate::render_target::{RenderTarget, RenderTargetInfo};
+use crate::resources::{Handle, ResourceManager};
+use crate::vulkan;
+use crate::{Camera, Error, Material, MaterialInfo, Mesh, MeshData, PassTag, Scene, Vertex};
+use vulkan::commands::CommandBuffer;
+use vulkan::texture::{ColorSpace, Texture};
+use vulkan::{Buffer, BufferType, BufferUsage, Extent, VulkanContext};
+
+/// The name of the normal map binding of the water effect
+pub const NORMAL_MAP_BINDING: &str = "normalMap";
+
+/// The name of the uniform block of the water effect holding the `WaterSettings`
+pub const WATER_BINDING: &str = "water";
+
+/// The name of the uniform buffer resource shared by all water materials. Created along with
+/// the first water surface.
+pub const WATER_BUFFER: &str = "water";
+
+/// Specifies the appearance of the water surfaces of a scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WaterSettings {
+    /// The color of the water, and the opacity when seen from straight above
+    pub color: Color,
+    /// The velocity of each normal map layer in world units per second
+    pub scroll: [Vec2; 2],
+    /// Normal map repeats per world unit
+    pub tiling: f32,
+    /// Scales the slope of the normal maps. Zero gives a flat surface.
+    pub normal_strength: f32,
+    /// Higher powers confine the reflection to grazing angles
+    pub fresnel_power: f32,
+    /// The reflectance at grazing angles. Zero shows only the water color.
+    pub reflectivity: f32,
+    /// How far the normals offset the reflection, as a fraction of the screen
+    pub distortion: f32,
+    /// The specular exponent of the sun highlight
+    pub shininess: f32,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            color: Color::rgba(20, 60, 80, 200),
+            scroll: [Vec2::new(0.03, 0.02), Vec2::new(-0.02, 0.035)],
+            tiling: 0.25,
+            normal_strength: 0.5,
+            fresnel_power: 5.0,
+            reflectivity: 1.0,
+            distortion: 0.02,
+            shininess: 128.0,
+        }
+    }
+}
+
+/// The water uniform, matching the std140 `WaterData` block of the water effect
+#[derive(Default)]
+#[repr(C)]
+struct WaterData {
+    color: Vec4,
+    scroll: Vec4,
+    time: f32,
+    tiling: f32,
+    normal_strength: f32,
+    fresnel_power: f32,
+    reflectivity: f32,
+    distortion: f32,
+    shininess: f32,
+    _padding: f32,
+}
+
+impl WaterData {
+    fn new(settings: &WaterSettings, time: f32) -> Self {
+        let [first, second] = settings.scroll;
+
+        Self {
+            color: settings.color.to_linear_vec4(),
+            scroll: Vec4::new(first.x, first.y, second.x, second.y),
+            time,
+            tiling: settings.tiling,
+            normal_strength: settings.normal_strength,
+            fresnel_power: settings.fresnel_power,
+            reflectivity: settings.reflectivity,
+            distortion: settings.distortion,
+            shininess: settings.shininess,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Records the upload of `settings` and the `time` in seconds to the water uniform. Does
+/// nothing if no water surface was created. Needs to be recorded outside of a renderpass before
+/// the views are drawn.
+pub fn update_uniform(
+    commandbuffer: &CommandBuffer,
+    resources: &ResourceManager,
+    settings: &WaterSettings,
+    time: f32,
+) {
+    let buffer = resources
+        .buffer(WATER_BUFFER)
+        .and_then(|buffer| resources.buffers().raw(buffer));
+
+    if let Ok(buffer) = buffer {
+        let data = WaterData::new(settings, time);
+        update_buffer(commandbuffer, buffer, slice::from_ref(&data));
+    }
+}
+
+/// Specifies the placement and reflection of a water surface
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaterInfo {
+    /// The name of the loaded water effect
+    pub effect: String,
+    /// The name of the normal map texture, which needs to be loaded as linear
+    pub normal_map: String,
+    /// The world position of the center of the surface
+    pub center: Vec3,
+    /// The extent along x and z in world units
+    pub size: Vec2,
+    /// The resolution of the planar reflection, or None to reflect `sky_color` only
+    pub reflection: Option<Extent>,
+    /// The layers drawn into the reflection
+    pub reflection_layers: Layers,
+    /// The color the reflection is cleared to, since the sky is not drawn into it
+    pub sky_color: Color,
+    /// The layers the surface is drawn in
+    pub layers: Layers,
+}
+
+impl Default for WaterInfo {
+    fn default() -> Self {
+        Self {
+            effect: "water".into(),
+            normal_map: "water_normals".into(),
+            center: Vec3::zero(),
+            size: Vec2::new(256.0, 256.0),
+            reflection: Some((512, 512).into()),
+            reflection_layers: Layers::ALL,
+            sky_color: Color::rgba(170, 200, 230, 255),
+            layers: Layers::ALL,
+        }
+    }
+}
+
+/// A water surface drawn as an entity of the scene world
+pub struct Water {
+    entity: Entity,
+    mesh: Handle<Mesh>,
+    material: Handle<Material>,
+    reflection: Option<Handle<RenderTarget>>,
+    reflection_layers: Layers,
+    height: f32,
+}
+
+impl Water {
+    /// Creates a water surface with the material '<name>' and the plane mesh '<name>::plane'.
+    /// The reflection is drawn into the render target '<name>::reflection', and needs to follow
+    /// the camera through `update_reflection`.
+    pub fn new<S: AsRef<str>>(
+        name: S,
+        context: Rc<VulkanContext>,
+        master_renderer: &mut MasterRenderer,
+        resources: &mut ResourceManager,
+        scene: &mut Scene,
+        info: WaterInfo,
+    ) -> Result<Self, Error> {
+        let name = name.as_ref();
+
+        if resources.buffer(WATER_BUFFER).is_err() {
+            let buffer = Buffer::new(
+                context.clone(),
+                BufferType::Uniform,
+                BufferUsage::Staged,
+                slice::from_ref(&WaterData::default()),
+            )?;
+
+            resources.insert_buffer(WATER_BUFFER, buffer);
+        }
+
+        let (reflection, albedo) = match info.reflection {
+            Some(extent) => {
+                let albedo = format!("{}::reflection", name);
+
+                let reflection = master_renderer.create_render_target(
+                    resources,
+                    albedo.clone(),
+                    RenderTargetInfo {
+                        extent,
+                        passes: vec![PassTag::Opaque],
+                        clear_color: info.sky_color.to_linear(),
+                        ..Default::default()
+                    },
+                )?;
+
+                (Some(reflection), albedo)
+            }
+            None => {
+                let albedo = format!("{}::sky", name);
+
+                let sky = Texture::from_pixels(
+                    context,
+                    (1, 1).into(),
+                    &info.sky_color.to_array(),
+                    ColorSpace::Srgb,
+                )?;
+
+                resources.insert_texture(albedo.clone(), sky);
+                (None, albedo)
+            }
+        };
+
+        let material = resources.load_material(
+            name,
+            MaterialInfo {
+                effect: info.effect,
+                albedo,
+                buffers: vec![(WATER_BINDING.to_owned(), WATER_BUFFER.to_owned())],
+                textures: vec![(NORMAL_MAP_BINDING.to_owned(), info.normal_map)],
+                ..Default::default()
+            },
+        )?;
+
+        let mesh = resources.load_mesh_data(
+            format!("{}::plane", name),
+            &plane_data(info.center, info.size),
+        )?;
+
+        let world = scene.world_mut();
+        let entity = world.spawn();
+
+        // The vertices are in world units relative to the center
+        world.insert(
+            entity,
+            Transform {
+                position: info.center,
+                rotation: Rotor3::identity(),
+                scale: Vec3::broadcast(1.0 / SCALE),
+            },
+        );
+        world.insert(entity, mesh);
+        world.insert(entity, material);
+        world.insert(entity, info.layers);
+
+        Ok(Self {
+            entity,
+            mesh,
+            material,
+            reflection,
+            reflection_layers: info.reflection_layers,
+            height: info.center.y,
+        })
+    }
+
+    /// Moves the reflection camera to mirror `camera` below the surface. Should be called once
+    /// per frame before drawing with the camera of the first view as drawn into the window,
+    /// e.g; from `Camera::with_viewport_aspect`, since the reflection is sampled in the screen
+    /// space of it.
+    pub fn update_reflection(
+        &self,
+        camera: &Camera,
+        master_renderer: &mut MasterRenderer,
+    ) -> Result<(), Error> {
+        if let Some(reflection) = self.reflection {
+            let target = master_renderer.render_target_mut(reflection)?;
+            target.camera = self.reflect(camera);
+        }
+
+        Ok(())
+    }
+
+    /// Returns `camera` mirrored below the surface. The mirrored camera is upright, so that its
+    /// image is the reflection flipped vertically in screen space.
+    pub fn reflect(&self, camera: &Camera) -> Camera {
+        let mirror = |v: Vec3| Vec3::new(v.x, -v.y, v.z);
+
+        let mut reflected = *camera;
+        reflected.position.y = 2.0 * self.height - camera.position.y;
+        reflected.look_in(mirror(camera.forward()), -mirror(camera.up()));
+
+        // The reflection keeps the proportions of the view rather than of the texture
+        reflected.auto_aspect = false;
+        reflected.layers = self.reflection_layers;
+
+        reflected
+    }
+
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    pub fn mesh(&self) -> Handle<Mesh> {
+        self.mesh
+    }
+
+    pub fn material(&self) -> Handle<Material> {
+        self.material
+    }
+
+    /// Returns the render target drawing the reflection, or None without a reflection
+    pub fn reflection(&self) -> Option<Handle<RenderTarget>> {
+        self.reflection
+    }
+
+    /// Returns the world height of the surface
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+}
+
+// Returns a plane of `size` facing upwards centered on the origin. The texture coordinates are
+// the world position along x and z at `center`, which keeps the normals continuous between
+// adjacent surfaces.
+fn plane_data(center: Vec3, size: Vec2) -> MeshData {
+    let half = size / 2.0;
+
+    let vertices = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+        .iter()
+        .map(|&(x, z)| {
+            let local = Vec2::new(x * half.x, z * half.y);
+
+            Vertex::new(
+                Vec3::new(local.x, 0.0, local.y),
+                Vec3::unit_y(),
+                Vec2::new(center.x, center.z) + local,
+            )
+        })
+        .collect();
+
+    MeshData::new(vertices, vec![0, 2, 1, 1, 2, 3])
+}
@@ -0,0 +1,686 @@
+//! Head tracked rendering on OpenXR headsets, enabled by the `openxr` feature.
+//!
+//! The runtime is loaded with `XrSystem` before the context is created, since it decides the
+//! extensions the instance and device need, see `ContextInfo::instance_extensions`. The
+//! `XrSession` then shares the device of the context. Each frame the eyes are drawn by the
+//! stereo renderer of the master renderer, see `MasterRenderer::set_stereo`, from the views of
+//! `XrFrame::stereo_views` and copied into the swapchain image of the runtime. The window keeps
+//! showing the eyes side by side.
+//!
+//! The tracking space is placed at the camera of the first view, so moving the camera moves
+//! the play area through the world.
+//!
+//! ```ignore
+//! xr.poll_events()?;
+//! let frame = xr.begin_frame()?;
+//!
+//! if let Some(stereo) = master_renderer.stereo_mut() {
+//!     stereo.set_views(frame.map(|frame| frame.stereo_views(&camera)));
+//! }
+//!
+//! master_renderer.draw(&window, dt, &views, &mut scene, &resources)?;
+//! xr.end_frame()?;
+//! ```
+use ash::vk::{self, Handle};
+use openxr as xr;
+use std::rc::Rc;
+use thiserror::Error;
+use ultraviolet::{Mat4, Rotor3, Vec3, Vec4};
+
+use crate::stereo::{self, StereoDestination, StereoViews, EYE_COUNT};
+use crate::{Camera, Projection};
+
+use super::vulkan;
+use vulkan::{Extent, VulkanContext};
+
+const VIEW_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
+
+/// Swapchain formats in order of preference. The eyes are scaled into the swapchain images,
+/// which converts between formats.
+const PREFERRED_FORMATS: &[vk::Format] = &[
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::B8G8R8A8_UNORM,
+];
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to load the OpenXR loader: {0}")]
+    Load(String),
+    #[error("OpenXR error: {0}")]
+    Xr(#[from] xr::sys::Result),
+    #[error("The OpenXR runtime does not support Vulkan")]
+    VulkanUnsupported,
+    #[error("The OpenXR runtime requires Vulkan {major}.{minor} or newer")]
+    VersionUnsupported { major: u16, minor: u16 },
+    #[error("The OpenXR runtime requires a different physical device than the one in use")]
+    DeviceMismatch,
+    #[error("The OpenXR runtime supports none of the swapchain formats")]
+    UnsupportedFormat,
+    #[error("The headset has {0} views instead of one for each eye")]
+    UnsupportedViews(usize),
+}
+
+/// Specifies how the OpenXR runtime is connected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XrInfo {
+    pub application_name: &'static str,
+    /// Tracks poses relative to the floor of the play area if supported by the runtime, rather
+    /// than relative to the position of the head when the session started
+    pub stage: bool,
+}
+
+impl Default for XrInfo {
+    fn default() -> Self {
+        Self {
+            application_name: "Vulkan Application",
+            stage: true,
+        }
+    }
+}
+
+/// A tracked position and orientation. Poses from the session are in tracking space, see
+/// `in_world`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pose {
+    pub position: Vec3,
+    /// Rotates from the local space of the tracked device, looking down -Z with Y up
+    pub rotation: Rotor3,
+}
+
+impl Pose {
+    /// Returns the pose in world space, where the tracking space is placed at `origin`.
+    pub fn in_world(&self, origin: &Camera) -> Self {
+        Self {
+            position: origin.position + origin.rotation * self.position,
+            rotation: origin.rotation * self.rotation,
+        }
+    }
+
+    /// Returns the view matrix of a camera at the pose
+    pub fn view(&self) -> Mat4 {
+        self.rotation.reversed().into_matrix().into_homogeneous()
+            * Mat4::from_translation(-self.position)
+    }
+}
+
+impl From<xr::Posef> for Pose {
+    fn from(pose: xr::Posef) -> Self {
+        let xr::Vector3f { x, y, z } = pose.position;
+        let xr::Quaternionf {
+            x: qx,
+            y: qy,
+            z: qz,
+            w: qw,
+        } = pose.orientation;
+
+        Self {
+            position: Vec3::new(x, y, z),
+            rotation: Rotor3::from_quaternion_array([qx, qy, qz, qw]).normalized(),
+        }
+    }
+}
+
+/// A tracked hand controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hand {
+    Left,
+    Right,
+}
+
+/// A connection to the OpenXR runtime and the headset, created before the context
+pub struct XrSystem {
+    instance: xr::Instance,
+    system: xr::SystemId,
+    info: XrInfo,
+}
+
+impl XrSystem {
+    /// Loads the OpenXR runtime and finds a head mounted display. Fails if no runtime is
+    /// installed or no headset is connected.
+    pub fn new(info: XrInfo) -> Result<Self, Error> {
+        let entry = unsafe { xr::Entry::load() }.map_err(|e| Error::Load(e.to_string()))?;
+
+        if !entry.enumerate_extensions()?.khr_vulkan_enable {
+            return Err(Error::VulkanUnsupported);
+        }
+
+        let mut extensions = xr::ExtensionSet::default();
+        extensions.khr_vulkan_enable = true;
+
+        let instance = entry.create_instance(
+            &xr::ApplicationInfo {
+                application_name: info.application_name,
+                application_version: 0,
+                engine_name: "vulkan-sandbox",
+                engine_version: 0,
+            },
+            &extensions,
+            &[],
+        )?;
+
+        let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+
+        let properties = instance.properties()?;
+        log::info!(
+            "Using OpenXR runtime {} {}",
+            properties.runtime_name,
+            properties.runtime_version
+        );
+
+        Ok(Self {
+            instance,
+            system,
+            info,
+        })
+    }
+
+    /// Returns the instance extensions the runtime requires, see
+    /// `ContextInfo::instance_extensions`.
+    pub fn instance_extensions(&self) -> Result<Vec<String>, Error> {
+        let extensions = self
+            .instance
+            .vulkan_legacy_instance_extensions(self.system)?;
+
+        Ok(split_extensions(&extensions))
+    }
+
+    /// Returns the device extensions the runtime requires, see
+    /// `ContextInfo::device_extensions`.
+    pub fn device_extensions(&self) -> Result<Vec<String>, Error> {
+        let extensions = self.instance.vulkan_legacy_device_extensions(self.system)?;
+        Ok(split_extensions(&extensions))
+    }
+
+    /// Returns the recommended resolution of each eye.
+    pub fn eye_extent(&self) -> Result<Extent, Error> {
+        let views = self
+            .instance
+            .enumerate_view_configuration_views(self.system, VIEW_TYPE)?;
+
+        match views.first() {
+            Some(view) => Ok(Extent::new(
+                view.recommended_image_rect_width,
+                view.recommended_image_rect_height,
+            )),
+            None => Err(Error::UnsupportedViews(0)),
+        }
+    }
+}
+
+/// The views of a frame begun with `XrSession::begin_frame`
+#[derive(Debug, Clone, Copy)]
+pub struct XrFrame {
+    /// The poses in tracking space and the fields of view of the left and right eye
+    pub views: [xr::View; EYE_COUNT],
+    display_time: xr::Time,
+    destination: StereoDestination,
+}
+
+impl XrFrame {
+    /// Returns the eyes for `StereoRenderer::set_views`, which copies them into the acquired
+    /// swapchain image. The tracking space is placed at `camera`, whose clip planes and layers
+    /// are used.
+    pub fn stereo_views(&self, camera: &Camera) -> StereoViews {
+        let (near, far) = match camera.projection_kind() {
+            Projection::Perspective { near, far, .. } => (near, far),
+            Projection::Orthographic { near, far, .. } => (near, far),
+        };
+
+        let eyes = [
+            Pose::from(self.views[0].pose).in_world(camera),
+            Pose::from(self.views[1].pose).in_world(camera),
+        ];
+
+        StereoViews {
+            view_projection: [
+                projection(self.views[0].fov, near, far) * eyes[0].view(),
+                projection(self.views[1].fov, near, far) * eyes[1].view(),
+            ],
+            culling_camera: self.culling_camera(&eyes, camera, near, far),
+            destination: Some(self.destination),
+        }
+    }
+
+    /// Returns the predicted time the frame is displayed at
+    pub fn display_time(&self) -> xr::Time {
+        self.display_time
+    }
+
+    // Returns a symmetric camera between the eyes covering the fields of view of both
+    fn culling_camera(
+        &self,
+        eyes: &[Pose; EYE_COUNT],
+        camera: &Camera,
+        near: f32,
+        far: f32,
+    ) -> Camera {
+        let (horizontal, vertical) = self.views.iter().fold((0.0f32, 0.0f32), |(h, v), view| {
+            (
+                h.max(-view.fov.angle_left.tan())
+                    .max(view.fov.angle_right.tan()),
+                v.max(view.fov.angle_up.tan())
+                    .max(-view.fov.angle_down.tan()),
+            )
+        });
+
+        let mut center = Camera::perspective(
+            (eyes[0].position + eyes[1].position) * 0.5,
+            2.0 * vertical.atan(),
+            horizontal / vertical,
+            near,
+            far,
+        );
+
+        center.rotation = eyes[0].rotation;
+        center.layers = camera.layers;
+
+        stereo::culling_camera(&center, (eyes[1].position - eyes[0].position).mag())
+    }
+}
+
+/// A running OpenXR session sharing the device of the context. Drives the frame loop of the
+/// headset and tracks the head and hand controllers.
+pub struct XrSession {
+    instance: xr::Instance,
+    session: xr::Session<xr::Vulkan>,
+    frame_waiter: xr::FrameWaiter,
+    frame_stream: xr::FrameStream<xr::Vulkan>,
+    blend_mode: xr::EnvironmentBlendMode,
+    /// The tracking space all poses are relative to
+    space: xr::Space,
+    head_space: xr::Space,
+    swapchain: xr::Swapchain<xr::Vulkan>,
+    images: Vec<vk::Image>,
+    extent: Extent,
+
+    hand_actions: HandActions,
+
+    events: xr::EventDataBuffer,
+    /// True between the runtime signaling ready and stopping
+    running: bool,
+    /// The frame begun but not yet ended
+    frame: Option<XrFrame>,
+
+    head: Option<Pose>,
+    hands: [Option<Pose>; 2],
+
+    /// Dropped after the session, which uses its device
+    _context: Rc<VulkanContext>,
+}
+
+impl XrSession {
+    /// Creates a session on the device of `context`, which needs to have been created with
+    /// the extensions of `system` and on the physical device used by the runtime. Nothing is
+    /// rendered until the runtime signals the session to start, see `poll_events`.
+    pub fn new(context: Rc<VulkanContext>, system: XrSystem) -> Result<Self, Error> {
+        let XrSystem {
+            instance,
+            system,
+            info,
+        } = system;
+
+        // Needs to be queried before the session is created
+        let requirements = instance.graphics_requirements::<xr::Vulkan>(system)?;
+        let min_version = requirements.min_api_version_supported;
+
+        if vk::make_version(min_version.major() as u32, min_version.minor() as u32, 0)
+            > context.api_version()
+        {
+            return Err(Error::VersionUnsupported {
+                major: min_version.major(),
+                minor: min_version.minor(),
+            });
+        }
+
+        let physical_device = unsafe {
+            instance.vulkan_graphics_device(system, context.instance().handle().as_raw() as _)?
+        };
+
+        if vk::PhysicalDevice::from_raw(physical_device as _) != context.physical_device() {
+            return Err(Error::DeviceMismatch);
+        }
+
+        let views = instance.enumerate_view_configuration_views(system, VIEW_TYPE)?;
+        if views.len() != EYE_COUNT {
+            return Err(Error::UnsupportedViews(views.len()));
+        }
+
+        let extent = Extent::new(
+            views[0].recommended_image_rect_width,
+            views[0].recommended_image_rect_height,
+        );
+
+        let blend_mode = instance.enumerate_environment_blend_modes(system, VIEW_TYPE)?[0];
+
+        let (session, frame_waiter, frame_stream) = unsafe {
+            instance.create_session::<xr::Vulkan>(
+                system,
+                &xr::vulkan::SessionCreateInfo {
+                    instance: context.instance().handle().as_raw() as _,
+                    physical_device: context.physical_device().as_raw() as _,
+                    device: context.device().handle().as_raw() as _,
+                    queue_family_index: context.queue_families().graphics().unwrap(),
+                    queue_index: 0,
+                },
+            )?
+        };
+
+        let space_type = if info.stage
+            && session
+                .enumerate_reference_spaces()?
+                .contains(&xr::ReferenceSpaceType::STAGE)
+        {
+            xr::ReferenceSpaceType::STAGE
+        } else {
+            xr::ReferenceSpaceType::LOCAL
+        };
+
+        let space = session.create_reference_space(space_type, xr::Posef::IDENTITY)?;
+        let head_space =
+            session.create_reference_space(xr::ReferenceSpaceType::VIEW, xr::Posef::IDENTITY)?;
+
+        let formats = session.enumerate_swapchain_formats()?;
+        let format = PREFERRED_FORMATS
+            .iter()
+            .find(|format| formats.contains(&(format.as_raw() as u32)))
+            .ok_or(Error::UnsupportedFormat)?;
+
+        let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+            create_flags: xr::SwapchainCreateFlags::EMPTY,
+            usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+                | xr::SwapchainUsageFlags::TRANSFER_DST,
+            format: format.as_raw() as u32,
+            sample_count: 1,
+            width: extent.width,
+            height: extent.height,
+            face_count: 1,
+            array_size: EYE_COUNT as u32,
+            mip_count: 1,
+        })?;
+
+        let images = swapchain
+            .enumerate_images()?
+            .into_iter()
+            .map(vk::Image::from_raw)
+            .collect();
+
+        let hand_actions = HandActions::new(&instance, &session)?;
+
+        Ok(Self {
+            instance,
+            session,
+            frame_waiter,
+            frame_stream,
+            blend_mode,
+            space,
+            head_space,
+            swapchain,
+            images,
+            extent,
+            hand_actions,
+            events: xr::EventDataBuffer::new(),
+            running: false,
+            frame: None,
+            head: None,
+            hands: [None; 2],
+            _context: context,
+        })
+    }
+
+    /// Handles the events of the runtime, which starts and stops the session. Returns false
+    /// if the session has ended and the application should exit.
+    pub fn poll_events(&mut self) -> Result<bool, Error> {
+        while let Some(event) = self.instance.poll_event(&mut self.events)? {
+            match event {
+                xr::Event::SessionStateChanged(event) => match event.state() {
+                    xr::SessionState::READY => {
+                        self.session.begin(VIEW_TYPE)?;
+                        self.running = true;
+                        log::info!("OpenXR session started");
+                    }
+                    xr::SessionState::STOPPING => {
+                        self.session.end()?;
+                        self.running = false;
+                        log::info!("OpenXR session stopped");
+                    }
+                    xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => return Ok(false),
+                    _ => {}
+                },
+                xr::Event::InstanceLossPending(_) => return Ok(false),
+                _ => {}
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Waits for the runtime to be ready for the next frame and updates the tracked poses.
+    /// Returns the frame if it should be drawn, in which case the swapchain image is acquired
+    /// until `end_frame`. Returns None while the session is not running or the headset is not
+    /// displaying the application.
+    pub fn begin_frame(&mut self) -> Result<Option<XrFrame>, Error> {
+        if !self.running {
+            return Ok(None);
+        }
+
+        let state = self.frame_waiter.wait()?;
+        self.frame_stream.begin()?;
+
+        let display_time = state.predicted_display_time;
+        self.update_poses(display_time)?;
+
+        if !state.should_render {
+            self.frame_stream.end(display_time, self.blend_mode, &[])?;
+            return Ok(None);
+        }
+
+        let (_, views) = self
+            .session
+            .locate_views(VIEW_TYPE, display_time, &self.space)?;
+
+        let index = self.swapchain.acquire_image()?;
+        self.swapchain.wait_image(xr::Duration::INFINITE)?;
+
+        let frame = XrFrame {
+            views: [views[0], views[1]],
+            display_time,
+            destination: StereoDestination {
+                image: self.images[index as usize],
+                extent: self.extent,
+            },
+        };
+
+        self.frame = Some(frame);
+        Ok(Some(frame))
+    }
+
+    /// Releases the swapchain image and submits the eyes to the headset. Needs to be called
+    /// after the commands drawing into the image have been submitted, i.e; after the master
+    /// renderer has drawn the frame. Does nothing if no frame was begun.
+    pub fn end_frame(&mut self) -> Result<(), Error> {
+        let frame = match self.frame.take() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        self.swapchain.release_image()?;
+
+        let rect = xr::Rect2Di {
+            offset: xr::Offset2Di { x: 0, y: 0 },
+            extent: xr::Extent2Di {
+                width: self.extent.width as i32,
+                height: self.extent.height as i32,
+            },
+        };
+
+        let views = [
+            projection_view(&self.swapchain, &frame.views[0], 0, rect),
+            projection_view(&self.swapchain, &frame.views[1], 1, rect),
+        ];
+
+        self.frame_stream.end(
+            frame.display_time,
+            self.blend_mode,
+            &[&xr::CompositionLayerProjection::new()
+                .space(&self.space)
+                .views(&views)],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns true if the runtime has started the session
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Returns the resolution of each eye, see `StereoInfo::extent`.
+    pub fn eye_extent(&self) -> Extent {
+        self.extent
+    }
+
+    /// Returns the pose of the head in tracking space at the last begun frame, or None if it
+    /// is not tracked.
+    pub fn head_pose(&self) -> Option<Pose> {
+        self.head
+    }
+
+    /// Returns the grip pose of a hand controller in tracking space at the last begun frame,
+    /// or None if the controller is not tracked.
+    pub fn hand_pose(&self, hand: Hand) -> Option<Pose> {
+        self.hands[hand as usize]
+    }
+
+    // Locates the head and hands at `time`
+    fn update_poses(&mut self, time: xr::Time) -> Result<(), Error> {
+        let hand_actions = &self.hand_actions;
+
+        self.session
+            .sync_actions(&[xr::ActiveActionSet::new(&hand_actions.set)])?;
+
+        self.head = locate(&self.head_space, &self.space, time)?;
+
+        for (i, (action, space)) in hand_actions
+            .actions
+            .iter()
+            .zip(&hand_actions.spaces)
+            .enumerate()
+        {
+            self.hands[i] = if action.is_active(&self.session, xr::Path::NULL)? {
+                locate(space, &self.space, time)?
+            } else {
+                None
+            };
+        }
+
+        Ok(())
+    }
+}
+
+/// The actions tracking the grip pose of the left and right hand
+struct HandActions {
+    set: xr::ActionSet,
+    actions: [xr::Action<xr::Posef>; 2],
+    spaces: [xr::Space; 2],
+}
+
+impl HandActions {
+    // Creates the actions bound for the simple controller profile, which runtimes remap to
+    // the connected controllers
+    fn new(instance: &xr::Instance, session: &xr::Session<xr::Vulkan>) -> Result<Self, Error> {
+        let set = instance.create_action_set("poses", "Poses", 0)?;
+
+        let left = set.create_action::<xr::Posef>("left_hand", "Left hand", &[])?;
+        let right = set.create_action::<xr::Posef>("right_hand", "Right hand", &[])?;
+
+        instance.suggest_interaction_profile_bindings(
+            instance.string_to_path("/interaction_profiles/khr/simple_controller")?,
+            &[
+                xr::Binding::new(
+                    &left,
+                    instance.string_to_path("/user/hand/left/input/grip/pose")?,
+                ),
+                xr::Binding::new(
+                    &right,
+                    instance.string_to_path("/user/hand/right/input/grip/pose")?,
+                ),
+            ],
+        )?;
+
+        session.attach_action_sets(&[&set])?;
+
+        let spaces = [
+            left.create_space(session.clone(), xr::Path::NULL, xr::Posef::IDENTITY)?,
+            right.create_space(session.clone(), xr::Path::NULL, xr::Posef::IDENTITY)?,
+        ];
+
+        Ok(Self {
+            set,
+            actions: [left, right],
+            spaces,
+        })
+    }
+}
+
+// Returns the pose of `space` in `base` at `time` if both its position and orientation are
+// tracked
+fn locate(space: &xr::Space, base: &xr::Space, time: xr::Time) -> Result<Option<Pose>, Error> {
+    let location = space.locate(base, time)?;
+
+    let valid = xr::SpaceLocationFlags::POSITION_VALID | xr::SpaceLocationFlags::ORIENTATION_VALID;
+
+    if location.location_flags.contains(valid) {
+        Ok(Some(location.pose.into()))
+    } else {
+        Ok(None)
+    }
+}
+
+fn projection_view<'a>(
+    swapchain: &'a xr::Swapchain<xr::Vulkan>,
+    view: &xr::View,
+    layer: u32,
+    rect: xr::Rect2Di,
+) -> xr::CompositionLayerProjectionView<'a, xr::Vulkan> {
+    xr::CompositionLayerProjectionView::new()
+        .pose(view.pose)
+        .fov(view.fov)
+        .sub_image(
+            xr::SwapchainSubImage::new()
+                .swapchain(swapchain)
+                .image_array_index(layer)
+                .image_rect(rect),
+        )
+}
+
+// Returns the projection matrix of an asymmetric field of view, with the clip space
+// conventions of `Camera`
+fn projection(fov: xr::Fovf, near: f32, far: f32) -> Mat4 {
+    let left = fov.angle_left.tan();
+    let right = fov.angle_right.tan();
+    let up = fov.angle_up.tan();
+    let down = fov.angle_down.tan();
+
+    let width = right - left;
+    let height = up - down;
+
+    // Y points down in Vulkan clip space
+    Mat4::new(
+        Vec4::new(2.0 / width, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, -2.0 / height, 0.0, 0.0),
+        Vec4::new(
+            (right + left) / width,
+            -(up + down) / height,
+            far / (near - far),
+            -1.0,
+        ),
+        Vec4::new(0.0, 0.0, near * far / (near - far), 0.0),
+    )
+}
+
+// The runtime lists the required extensions separated by spaces
+fn split_extensions(extensions: &str) -> Vec<String> {
+    extensions.split_whitespace().map(str::to_owned).collect()
+}
@@ -19,6 +19,32 @@ extern "C" {
         channels: *mut c_int,
         desired_channels: c_int,
     ) -> *mut c_uchar;
+
+    fn stbi_loadf(
+        filename: *const c_char,
+        x: *mut c_int,
+        y: *mut c_int,
+        channels: *mut c_int,
+        desired_channels: c_int,
+    ) -> *mut c_float;
+
+    fn stbi_is_hdr(filename: *const c_char) -> c_int;
+}
+
+/// Returns true if the image at path is a Radiance HDR image, which should be loaded with
+/// `HdrImage::load` to retain the values above 1.0
+pub fn is_hdr<P: AsRef<Path>>(path: P) -> bool {
+    let filename = match path
+        .as_ref()
+        .as_os_str()
+        .to_str()
+        .and_then(|path| std::ffi::CString::new(path).ok())
+    {
+        Some(filename) => filename,
+        None => return false,
+    };
+
+    unsafe { stbi_is_hdr(filename.as_ptr()) != 0 }
 }
 
 pub struct Image {
@@ -126,3 +152,66 @@ impl Image {
         &mut self.pixels
     }
 }
+
+/// An image of linear 32 bit float pixels
+pub struct HdrImage {
+    width: u32,
+    height: u32,
+    channels: u32,
+    pixels: Box<[f32]>,
+}
+
+impl HdrImage {
+    /// Loads an image from a path as floats. 8 bit images are converted to linear.
+    pub fn load<P: AsRef<Path>>(path: P, desired_channels: i32) -> Option<Self> {
+        let filename = std::ffi::CString::new(path.as_ref().as_os_str().to_str()?).ok()?;
+        let mut width: c_int = 0;
+        let mut height: c_int = 0;
+        let mut channels: c_int = desired_channels;
+
+        let pixels_raw = unsafe {
+            stbi_loadf(
+                filename.as_ptr(),
+                &mut width,
+                &mut height,
+                &mut channels,
+                desired_channels,
+            )
+        };
+
+        if pixels_raw.is_null() {
+            return None;
+        }
+
+        // Desired channels override channels
+        if desired_channels != 0 {
+            channels = desired_channels;
+        }
+
+        let image_size = width as usize * height as usize * channels as usize;
+        let pixels = unsafe { Vec::from_raw_parts(pixels_raw, image_size, image_size) };
+
+        Some(Self {
+            width: width as _,
+            height: height as _,
+            channels: channels as _,
+            pixels: pixels.into_boxed_slice(),
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    pub fn pixels(&self) -> &[f32] {
+        &self.pixels
+    }
+}
@@ -1,2 +1,2 @@
 mod image;
-pub use image::Image;
+pub use image::{is_hdr, HdrImage, Image};
@@ -0,0 +1,21 @@
+//! Golden image tests, see `vulkan_sandbox::golden`. Set `UPDATE_GOLDEN` to rewrite the
+//! references in `tests/golden` after intended changes to the output.
+#![cfg(feature = "gpu-tests")]
+use ultraviolet::Vec3;
+
+use vulkan_sandbox::golden::{self, GoldenInfo};
+use vulkan_sandbox::Camera;
+
+/// An empty scene is only the clear color. Channels of 0.0 and 1.0 are stored exactly in both
+/// unorm and sRGB formats, so the reference does not depend on the format of the target.
+#[test]
+fn clear() {
+    let info = GoldenInfo {
+        clear_color: [1.0, 0.0, 1.0, 1.0],
+        ..Default::default()
+    };
+
+    golden::assert_matches("clear", &info, |_, _, _| {
+        Ok(Camera::perspective(Vec3::zero(), 1.0, 1.0, 0.1, 100.0))
+    });
+}
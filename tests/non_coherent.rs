@@ -0,0 +1,54 @@
+//! Writes and reads mapped buffers with all memory treated as non coherent, which flushes and
+//! invalidates the atom aligned ranges around each access.
+#![cfg(feature = "gpu-tests")]
+use std::rc::Rc;
+
+use vulkan_sandbox::vulkan::{Buffer, BufferType, BufferUsage, ContextInfo, VulkanContext};
+
+fn context() -> (glfw::Glfw, glfw::Window, Rc<VulkanContext>) {
+    let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
+
+    glfw.window_hint(glfw::WindowHint::ClientApi(glfw::ClientApiHint::NoApi));
+    glfw.window_hint(glfw::WindowHint::Visible(false));
+
+    let (window, _events) = glfw
+        .create_window(64, 64, "Non coherent", glfw::WindowMode::Windowed)
+        .expect("Failed to create window");
+
+    let context = VulkanContext::new_with_info(
+        &glfw,
+        &window,
+        ContextInfo {
+            force_non_coherent: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    (glfw, window, Rc::new(context))
+}
+
+#[test]
+fn forced_non_coherent() {
+    let (_glfw, _window, context) = context();
+
+    let memory_types = context.memory_properties().memory_type_count;
+    assert!((0..memory_types).all(|memory_type| !context.is_coherent(memory_type)));
+
+    for usage in [BufferUsage::Mapped, BufferUsage::MappedPersistent] {
+        // Neither the size nor the offsets are multiples of the atom size
+        let mut buffer =
+            Buffer::new_zeroed(context.clone(), BufferType::Storage, usage, 1000).unwrap();
+
+        let data = (0..100).map(|i| i as u8).collect::<Vec<_>>();
+        buffer.fill(13, &data).unwrap();
+
+        let read = buffer
+            .read_slice(110, 10, |mapped: &[u8]| mapped.to_vec())
+            .unwrap();
+
+        assert_eq!(&read[..3], &[0; 3]);
+        assert_eq!(&read[3..103], &data[..]);
+        assert_eq!(&read[103..], &[0; 7]);
+    }
+}